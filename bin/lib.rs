@@ -0,0 +1,642 @@
+//! The textecca command-line interface. Use `run_with_registry` to run it with
+//! extra output formats.
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use std::{convert::TryInto, rc::Rc};
+
+use structopt::StructOpt;
+use thiserror::Error;
+
+use textecca::{
+    cmd::{
+        CommandError, FsResolver, Profile, ResourceError, ResourceResolver, Strictness, Thunk,
+        Warning, WarningKind, World,
+    },
+    diagnostic::{Diagnostic, ErrorCode, Severity},
+    doc::{self, Doc, DocBuilder, DocBuilderError},
+    env::Environment,
+    parse::{self, parse_source, Source, SyntaxError},
+    ser::{
+        self, AssetCollector, HtmlOptions, Registry, SerializerError, SerializerOptions,
+        TextOptions,
+    },
+};
+use textecca_stdlib as builtins;
+
+mod serve;
+
+#[derive(StructOpt)]
+struct Opt {
+    /// Input file.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The project directory; paths in the document (e.g. of images and
+    /// included files) may not refer to files outside it. Defaults to the
+    /// input file's directory.
+    #[structopt(long, parse(from_os_str))]
+    resource_root: Option<PathBuf>,
+
+    /// Copy local images and stylesheets the output refers to into this
+    /// directory, naming them after their content, and refer to them there.
+    #[structopt(long, parse(from_os_str))]
+    copy_assets: Option<PathBuf>,
+
+    /// Embed local images and stylesheets in the output, so it's a single
+    /// self-contained file.
+    #[structopt(long, conflicts_with = "copy-assets")]
+    embed_assets: bool,
+
+    /// Link to a stylesheet; a URL, or a path relative to the resource root.
+    #[structopt(long = "stylesheet", number_of_values = 1)]
+    stylesheets: Vec<String>,
+
+    /// The output format, e.g. `html`, `epub`, `json`, `latex`, `markdown`,
+    /// `pandoc`, or `text`.
+    #[structopt(short, long, default_value = "html")]
+    format: String,
+
+    /// Set a document metadata value, e.g. `-D draft=true`. A key with no
+    /// value is set to `true`. Overrides values set in the document.
+    #[structopt(short = "D", number_of_values = 1, parse(from_str = parse_define))]
+    define: Vec<(String, String)>,
+
+    /// Only parse command arguments when they're evaluated, rather than
+    /// checking the whole document for syntax errors first. Errors in arguments
+    /// which are never evaluated aren't reported.
+    #[structopt(long)]
+    lazy: bool,
+
+    /// Treat recoverable problems, like unknown commands or math which fails
+    /// to render, as errors. Otherwise, arguments which can't be parsed are
+    /// warnings, and only errors if they're evaluated.
+    #[structopt(long, conflicts_with = "lenient")]
+    strict: bool,
+
+    /// Silently work around recoverable problems, rather than warning about
+    /// them.
+    #[structopt(long)]
+    lenient: bool,
+
+    /// If a command fails, still write the output: the document up to the
+    /// failure, followed by the error. The exit status is still unsuccessful.
+    /// With `--serve`, the partial document is shown rather than just the
+    /// error.
+    #[structopt(long)]
+    partial: bool,
+
+    /// How errors and warnings are printed: `human`, or `json` for one JSON
+    /// object per line, for editors and other tools. See `doc/error-codes.md`
+    /// for the codes the JSON objects include.
+    #[structopt(long, default_value = "human", possible_values = &["human", "json"])]
+    error_format: ErrorFormat,
+
+    /// Print a tree of the commands called while rendering the input to
+    /// stderr, with how long each took and how much it wrote.
+    #[structopt(long)]
+    trace: bool,
+
+    /// Print a table of the commands called while rendering the input to
+    /// stderr, with how many times each was called and how long they took.
+    #[structopt(long)]
+    profile: bool,
+
+    /// Format the input and print it, rather than rendering it.
+    #[structopt(long)]
+    fmt: bool,
+
+    /// Check the input without rendering it, reporting unknown commands,
+    /// invalid arguments, and missing included files, and exit unsuccessfully
+    /// if there are any. If there aren't, the document is evaluated, and
+    /// warnings from evaluating it and breaks of style rules, like images
+    /// needing alt text, are reported the same way; turn a rule off with e.g.
+    /// `-D lint:missing-alt=false`. With `--fmt`, instead
+    /// print nothing and exit unsuccessfully if formatting would change the
+    /// input.
+    #[structopt(long)]
+    check: bool,
+
+    /// The width to wrap prose to, with `--fmt` or `--format=text`.
+    #[structopt(long, default_value = "80")]
+    width: usize,
+
+    /// Serve the rendered document over HTTP, along with the files in the
+    /// resource root, at the address given as `--serve=ADDR` (by default,
+    /// 127.0.0.1:8000). The document is rendered again whenever the input
+    /// file changes, and open pages reload themselves.
+    #[structopt(long, require_equals = true, value_name = "ADDR")]
+    serve: Option<Option<String>>,
+
+    /// Write the HTML output as a file per section, starting a new file at
+    /// each heading of at most this level (e.g. 1 for a file per chapter),
+    /// along with an index page with a table of contents.
+    #[structopt(long, requires = "out-dir", conflicts_with = "serve")]
+    split_level: Option<i32>,
+
+    /// With `--split-level`, the directory to write the files to.
+    #[structopt(long, parse(from_os_str), requires = "split-level")]
+    out_dir: Option<PathBuf>,
+}
+
+impl Opt {
+    fn strictness(&self) -> Strictness {
+        if self.strict {
+            Strictness::Strict
+        } else if self.lenient {
+            Strictness::Lenient
+        } else {
+            Strictness::Warn
+        }
+    }
+
+    /// Print a problem to stderr, as `human` or as `diagnostic` depending on
+    /// `error_format`.
+    fn report(&self, human: &str, diagnostic: &Diagnostic) {
+        match self.error_format {
+            ErrorFormat::Human => eprintln!("{}", human),
+            ErrorFormat::Json => eprintln!(
+                "{}",
+                serde_json::to_string(diagnostic).expect("Diagnostics are always serializable")
+            ),
+        }
+    }
+
+    /// Print a warning to stderr.
+    fn warn(&self, src: &Source, warning: &Warning) {
+        self.report(&format!("Warning: {}", warning), &warning.diagnostic(src));
+    }
+
+    /// Print an error to stderr; `src` is the input, if it's been read.
+    fn error(&self, src: Option<&Source>, err: &MainError) {
+        self.report(&format!("Error: {}", err.render()), &err.diagnostic(src));
+    }
+}
+
+/// How errors and warnings are printed; see `Opt::error_format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown error format {:?}", s)),
+        }
+    }
+}
+
+fn parse_define(define: &str) -> (String, String) {
+    let mut parts = define.splitn(2, '=');
+    let key = parts.next().unwrap_or_default().to_owned();
+    let value = parts.next().unwrap_or("true").to_owned();
+    (key, value)
+}
+
+#[derive(Error, Debug)]
+enum MainError<'i> {
+    #[error("IO Error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("{0}")]
+    Command(CommandError<'i>),
+
+    #[error("{0}")]
+    Serializer(#[from] SerializerError),
+
+    #[error("{0}")]
+    Doc(#[from] DocBuilderError),
+
+    #[error("{0}")]
+    Footnote(#[from] doc::FootnoteError),
+
+    #[error("{0}")]
+    Lint(#[from] doc::InvalidLintConfig),
+
+    #[error("Couldn't copy assets: {0}")]
+    Resource(#[from] ResourceError),
+
+    #[error("{}: {0}", .0.position)]
+    Parse(#[from] SyntaxError),
+
+    #[error("Found {0} syntax error(s)")]
+    Syntax(usize),
+
+    #[error("Unknown output format {0:?}; expected one of {1}")]
+    Format(String, String),
+
+    #[error("{0} is not inside the resource root {1}")]
+    OutsideRoot(String, String),
+
+    #[error("Only HTML output can be split, not {0:?}")]
+    SplitFormat(String),
+}
+
+impl MainError<'_> {
+    /// Did writing the output fail because its reader went away, e.g. when
+    /// piping to `head`?
+    fn is_broken_pipe(&self) -> bool {
+        match self {
+            Self::Io(err) | Self::Serializer(SerializerError::Io(err)) => {
+                err.kind() == io::ErrorKind::BrokenPipe
+            }
+            _ => false,
+        }
+    }
+
+    /// Render this error, including the causes of command errors.
+    fn render(&self) -> String {
+        match self {
+            Self::Command(err) => err.render_causes(),
+            err => err.to_string(),
+        }
+    }
+
+    /// This error as a `Diagnostic`; `src` is the input, if it's been read.
+    fn diagnostic(&self, src: Option<&Source>) -> Diagnostic {
+        let code = match (self, src) {
+            (Self::Command(err), Some(src)) => return err.diagnostic(src),
+            (Self::Parse(err), Some(src)) => return err.diagnostic(src),
+            (Self::Parse(err), None) => err.code(),
+            (Self::Command(err), None) => err.code(),
+            (Self::Io(_), _) => ErrorCode::Io,
+            (Self::Serializer(err), _) => err.code(),
+            (Self::Doc(err), _) => err.code(),
+            (Self::Footnote(err), _) => err.code(),
+            (Self::Lint(err), _) => err.code(),
+            (Self::Resource(err), _) => err.code(),
+            (Self::Syntax(_), _) => ErrorCode::Syntax,
+            (Self::Format(..), _) => ErrorCode::UnknownFormat,
+            (Self::OutsideRoot(..), _) => ErrorCode::OutsideRoot,
+            (Self::SplitFormat(_), _) => ErrorCode::Unsplittable,
+        };
+        let ret = Diagnostic::new(Severity::Error, code, self.to_string());
+        match src {
+            Some(src) => ret.in_file(src),
+            None => ret,
+        }
+    }
+}
+
+impl<'i> From<CommandError<'i>> for MainError<'i> {
+    fn from(err: CommandError<'i>) -> Self {
+        Self::Command(err)
+    }
+}
+
+fn main_inner<'i>(
+    src: &'i Source,
+    opt: &Opt,
+    registry: &Registry,
+    resolver: Rc<dyn ResourceResolver>,
+    out: Box<dyn Write>,
+) -> Result<(), MainError<'i>> {
+    if registry.get(&opt.format).is_none() {
+        let names: Vec<_> = registry.names().collect();
+        return Err(MainError::Format(opt.format.clone(), names.join(", ")));
+    }
+    if opt.split_level.is_some() && opt.format != "html" {
+        return Err(MainError::SplitFormat(opt.format.clone()));
+    }
+    let profile = if opt.profile {
+        Some(Rc::new(RefCell::new(Profile::new())))
+    } else {
+        None
+    };
+    let world = new_world(src, opt, resolver.clone(), profile.clone());
+    for warning in parse::lint_source(src) {
+        opt.warn(src, &warning);
+    }
+    let mut toks = parse_source(src)?;
+    if !opt.lazy {
+        // Unless problems are errors, arguments which can't be parsed are
+        // only warned about here; see `Opt::strict`.
+        let errors = world.validate_or_warn(&mut toks)?;
+        for error in &errors {
+            opt.report(&format!("Error: {}", error), &error.diagnostic(src));
+        }
+        if !errors.is_empty() {
+            return Err(MainError::Syntax(errors.len()));
+        }
+    }
+    let mut doc = DocBuilder::new();
+    let mut failure = None;
+    if let Err(err) = Thunk::from(toks).force(&world, &mut doc) {
+        if !opt.partial {
+            return Err(err.into());
+        }
+        doc.push_error(&err.render_causes());
+        failure = Some(err);
+    }
+    if let Some(profile) = profile {
+        eprint!("{}", profile.borrow());
+    }
+    let mut doc: Doc = doc.try_into()?;
+    doc.meta.extend(opt.define.iter().cloned());
+    doc::number_figures(&mut doc);
+    for label in doc::undefined_labels(&mut doc) {
+        world.report(Warning::new(
+            WarningKind::UndefinedLabel,
+            format!("Undefined label {:?}", label),
+        ))?;
+    }
+    let draft = doc::is_draft(&doc.meta);
+    // An argument which couldn't be parsed was warned about, but it's
+    // reported as the error.
+    let failed_argument = match failure.as_ref().map(CommandError::innermost) {
+        Some(CommandError::InvalidArgument(argument)) => Some(argument.position),
+        _ => None,
+    };
+    for warning in world.take_warnings() {
+        let reported = warning.kind == WarningKind::InvalidArgument
+            && failed_argument.is_some()
+            && warning.position == failed_argument;
+        let todo_in_draft = draft && warning.kind == WarningKind::Todo;
+        if !todo_in_draft && !reported {
+            opt.warn(src, &warning);
+        }
+    }
+    doc::hoist_footnotes(&mut doc, doc::FootnotePolicy::Hoist)?;
+    let assets = opt.copy_assets.as_ref().map(|dir| {
+        Rc::new(RefCell::new(AssetCollector::new(
+            resolver.clone(),
+            dir.to_string_lossy(),
+        )))
+    });
+    let options = SerializerOptions {
+        strictness: opt.strictness(),
+        html: HtmlOptions {
+            stylesheets: opt.stylesheets.clone(),
+            assets: assets.clone(),
+            embed_assets: opt.embed_assets,
+            resolver: Some(resolver),
+            ..Default::default()
+        },
+        text: TextOptions {
+            width: Some(opt.width),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    if let (Some(level), Some(dir)) = (opt.split_level, &opt.out_dir) {
+        fs::create_dir_all(dir)?;
+        for warning in ser::write_split(doc, level, dir, options.html_options())? {
+            opt.warn(src, &warning);
+        }
+    } else {
+        let mut ser = registry.create(&opt.format, out, &options)?;
+        let result = ser.write_doc(&doc);
+        for warning in ser.take_warnings() {
+            opt.warn(src, &warning);
+        }
+        result?;
+    }
+    if let (Some(assets), Some(dir)) = (assets, &opt.copy_assets) {
+        assets.borrow().copy_to(dir)?;
+    }
+    match failure {
+        Some(err) => Err(err.into()),
+        None => Ok(()),
+    }
+}
+
+/// Render the input file, giving the output or the rendered error. The files
+/// read are recorded in `resolver`.
+fn render_to_string(
+    opt: &Opt,
+    registry: &Registry,
+    resolver: Rc<serve::WatchingResolver>,
+) -> Result<String, String> {
+    let input = fs::read_to_string(&opt.input).map_err(|err| format!("IO Error: {}", err))?;
+    let path = input_path(opt).map_err(|err| err.render())?;
+    let src = Source::new(input).with_path(path);
+    let out = serve::SharedBuffer::default();
+    let result = main_inner(&src, opt, registry, resolver, Box::new(out.clone()));
+    let html = String::from_utf8_lossy(&out.0.borrow()).into_owned();
+    match result {
+        Ok(()) => Ok(html),
+        // With `--partial`, a failed command still gives a page.
+        Err(err @ MainError::Command(_)) if opt.partial && !html.is_empty() => {
+            eprintln!("Error: {}", err.render());
+            Ok(html)
+        }
+        Err(err) => Err(err.render()),
+    }
+}
+
+/// Serve the rendered input at the given address, rendering it again when it
+/// or a file it reads (e.g. with `\include`) changes; see `Opt::serve`.
+fn serve_main(opt: &Opt, registry: &Registry, addr: &str) -> io::Result<()> {
+    let mut server = serve::Server::bind(addr, resource_root(opt))?;
+    eprintln!(
+        "Serving {} at http://{}/",
+        opt.input.display(),
+        server.local_addr()?
+    );
+    // The files the last render read, starting with the input, and their
+    // states then.
+    let mut files = Vec::new();
+    let mut rendered = None;
+    loop {
+        let states = serve::file_states(&files);
+        if rendered.as_ref() != Some(&states) {
+            let resolver = Rc::new(serve::WatchingResolver::new(FsResolver::new(
+                resource_root(opt),
+            )));
+            // The input's state is taken before it's read, so an edit made
+            // while rendering isn't missed.
+            let mut states = serve::file_states(std::slice::from_ref(&opt.input));
+            match render_to_string(opt, registry, resolver.clone()) {
+                Ok(html) => server.set_page(&html),
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    server.set_error(&err);
+                }
+            }
+            files = vec![opt.input.clone()];
+            files.extend(resolver.files());
+            states.extend(serve::file_states(&files[1..]));
+            rendered = Some(states);
+        }
+        server.poll()?;
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Create a world with the builtins bound, recording the commands called in
+/// `profile` if it's given.
+fn new_world<'i>(
+    src: &'i Source,
+    opt: &Opt,
+    resolver: Rc<dyn ResourceResolver>,
+    profile: Option<Rc<RefCell<Profile>>>,
+) -> World<'i> {
+    let mut env = Environment::new();
+    builtins::import(Rc::get_mut(&mut env).unwrap());
+    let mut world = World::new(env, src)
+        .with_strictness(opt.strictness())
+        .with_resolver(resolver);
+    let trace = opt.trace;
+    if trace || profile.is_some() {
+        world.set_tracer(Box::new(move |event| {
+            if let Some(profile) = &profile {
+                profile.borrow_mut().record(&event);
+            }
+            if trace {
+                eprintln!("{}", event);
+            }
+        }));
+    }
+    world
+}
+
+/// Check the input without evaluating it (see `World::check`), and if that
+/// finds no problems, evaluate it, reporting any warnings, and lint it (see
+/// `doc::lint`). Returns whether no problems were found.
+fn check_main<'i>(src: &'i Source, opt: &Opt) -> Result<bool, MainError<'i>> {
+    let world = new_world(src, opt, Rc::new(FsResolver::new(resource_root(opt))), None);
+    for warning in parse::lint_source(src) {
+        opt.warn(src, &warning);
+    }
+    let toks = parse_source(src)?;
+    let diagnostics = world.check(&toks);
+    for diagnostic in &diagnostics {
+        opt.report(
+            &format!("Error: {}", diagnostic),
+            &diagnostic.diagnostic(src),
+        );
+    }
+    if !diagnostics.is_empty() {
+        return Ok(false);
+    }
+    let mut builder = DocBuilder::new();
+    Thunk::from(toks).force(&world, &mut builder)?;
+    let (mut doc, offsets) = builder.try_into_located()?;
+    doc.meta.extend(opt.define.iter().cloned());
+    let draft = doc::is_draft(&doc.meta);
+    let warnings: Vec<_> = world
+        .take_warnings()
+        .into_iter()
+        .filter(|warning| !(draft && warning.kind == WarningKind::Todo))
+        .collect();
+    for warning in &warnings {
+        opt.warn(src, warning);
+    }
+    let lints = doc::lint(&doc, &doc::LintConfig::from_meta(&doc.meta)?);
+    for lint in &lints {
+        let position = offsets
+            .get(&lint.block)
+            .map(|&offset| src.offset_position(offset));
+        opt.warn(
+            src,
+            &Warning::new(WarningKind::Lint, lint.to_string()).at(position),
+        );
+    }
+    Ok(warnings.is_empty() && lints.is_empty())
+}
+
+/// The project directory; see `Opt::resource_root`.
+fn resource_root(opt: &Opt) -> PathBuf {
+    match &opt.resource_root {
+        Some(root) => root.clone(),
+        None => opt.input.parent().map(Path::to_owned).unwrap_or_default(),
+    }
+}
+
+/// The input file's path relative to the resource root.
+fn input_path(opt: &Opt) -> Result<PathBuf, MainError<'static>> {
+    let root = resource_root(opt);
+    let outside_root =
+        || MainError::OutsideRoot(opt.input.display().to_string(), root.display().to_string());
+    let canonical_root = Path::new(".").join(&root).canonicalize()?;
+    opt.input
+        .canonicalize()?
+        .strip_prefix(canonical_root)
+        .map(Path::to_owned)
+        .map_err(|_| outside_root())
+}
+
+/// Format the input; returns whether it was already formatted.
+fn fmt_main<'i>(src: &'i Source, opt: &Opt) -> Result<bool, MainError<'i>> {
+    let mut env = Environment::new();
+    builtins::import(Rc::get_mut(&mut env).unwrap());
+    let style = parse::FormatStyle {
+        width: opt.width,
+        ..parse::FormatStyle::for_env(&env)
+    };
+    let formatted = parse::format(src, &style)?;
+    if !opt.check {
+        io::stdout().write_all(formatted.as_bytes())?;
+    }
+    Ok(formatted == **src)
+}
+
+/// Run the command-line interface with the given serializers available as
+/// output formats.
+pub fn run_with_registry(registry: &Registry) -> io::Result<()> {
+    let opt = Opt::from_args();
+    let mut input = String::new();
+    let mut fh = File::open(&opt.input)?;
+    fh.read_to_string(&mut input)?;
+    if opt.fmt {
+        let src = Source::new(input);
+        match fmt_main(&src, &opt) {
+            Ok(true) => {}
+            Ok(false) if opt.check => {
+                eprintln!("{} is not formatted", opt.input.display());
+                std::process::exit(1);
+            }
+            Ok(false) => {}
+            Err(err) if err.is_broken_pipe() => {}
+            Err(err) => {
+                opt.error(Some(&src), &err);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    if let Some(addr) = &opt.serve {
+        let addr = addr.as_deref().unwrap_or("127.0.0.1:8000");
+        return serve_main(&opt, registry, addr);
+    }
+    let src = match input_path(&opt) {
+        Ok(path) => Source::new(input).with_path(path),
+        Err(err) => {
+            opt.error(None, &err);
+            std::process::exit(1);
+        }
+    };
+    if opt.check {
+        match check_main(&src, &opt) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(1),
+            Err(err) => {
+                opt.error(Some(&src), &err);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    let resolver = Rc::new(FsResolver::new(resource_root(&opt)));
+    match main_inner(&src, &opt, registry, resolver, Box::new(io::stdout())) {
+        // Like other command-line tools, stop quietly when the output's
+        // reader has all it wants.
+        Err(err) if err.is_broken_pipe() => {}
+        Err(err) => {
+            opt.error(Some(&src), &err);
+            std::process::exit(1);
+        }
+        Ok(()) => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,463 @@
+//! A tiny HTTP server for previewing a document while editing it; see
+//! `--serve`.
+//!
+//! The rendered page is served at `/`, and other paths are served from the
+//! resource root. A script injected into the page long-polls `/__textecca/wait`
+//! and reloads the page when a new version is rendered.
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+use textecca::cmd::{FsResolver, ResourceError, ResourceResolver};
+use textecca::ser::escape_with;
+
+/// The path the reload script long-polls for new versions.
+const WAIT_PATH: &str = "/__textecca/wait";
+
+/// How long a long-poll request is held before it's answered anyway.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a client has to send its request before it's dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest request read; longer requests are dropped.
+const MAX_REQUEST_LEN: usize = 16 * 1024;
+
+/// A connection whose request hasn't been read in full yet.
+struct Connection {
+    stream: TcpStream,
+    request: Vec<u8>,
+    since: Instant,
+}
+
+impl Connection {
+    /// Read what's been received without blocking, returning whether the
+    /// request line and headers are complete.
+    fn read(&mut self) -> io::Result<bool> {
+        let mut buf = [0; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(len) => {
+                    self.request.extend_from_slice(&buf[..len]);
+                    if self.request.len() > MAX_REQUEST_LEN {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Request too long",
+                        ));
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(self.request.windows(4).any(|w| w == b"\r\n\r\n")
+            || self.request.windows(2).any(|w| w == b"\n\n"))
+    }
+}
+
+/// A preview server; `poll` handles requests without blocking.
+pub(crate) struct Server {
+    listener: TcpListener,
+    /// The directory other files are served from.
+    root: PathBuf,
+    /// The page served at `/`, with the reload script.
+    page: String,
+    /// Incremented each time the page changes.
+    version: u64,
+    /// Connections still sending their requests.
+    reading: Vec<Connection>,
+    /// Long-poll requests waiting for a new version.
+    waiting: Vec<(TcpStream, Instant)>,
+}
+
+impl Server {
+    /// Start listening on the given address, serving files from `root`.
+    pub(crate) fn bind(addr: impl ToSocketAddrs, root: PathBuf) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            root,
+            page: String::new(),
+            version: 0,
+            reading: Vec::new(),
+            waiting: Vec::new(),
+        })
+    }
+
+    /// The address the server is listening on.
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serve a newly rendered page, reloading the pages already open.
+    pub(crate) fn set_page(&mut self, html: &str) {
+        self.version += 1;
+        let script = reload_script(self.version);
+        self.page = match html.rfind("</body>") {
+            Some(i) => format!("{}{}\n{}", &html[..i], script, &html[i..]),
+            None => format!("{}{}\n", html, script),
+        };
+        for (stream, _) in std::mem::take(&mut self.waiting) {
+            let _ = respond(stream, "200 OK", "text/plain", self.version.to_string());
+        }
+    }
+
+    /// Serve an error page showing the given message, e.g. when the document
+    /// fails to render.
+    pub(crate) fn set_error(&mut self, message: &str) {
+        let escaped = escape_with(message, |c| match c {
+            '&' => Some("&amp;"),
+            '<' => Some("&lt;"),
+            '>' => Some("&gt;"),
+            _ => None,
+        });
+        self.set_page(&format!(
+            "<!DOCTYPE html>\n<html>\n<head><title>Error</title></head>\n\
+             <body>\n<h1>The document failed to render</h1>\n<pre>{}</pre>\n</body>\n</html>\n",
+            escaped
+        ));
+    }
+
+    /// Handle the requests received since the last call, and answer
+    /// long-poll requests which have waited too long.
+    ///
+    /// Requests are read without blocking, so a slow or idle client doesn't
+    /// hold up the others.
+    pub(crate) fn poll(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    self.reading.push(Connection {
+                        stream,
+                        request: Vec::new(),
+                        since: now,
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        for mut conn in std::mem::take(&mut self.reading) {
+            match conn.read() {
+                Ok(true) => {
+                    if let Err(err) = self.handle(conn.stream, &conn.request) {
+                        eprintln!("Warning: Failed to handle request: {}", err);
+                    }
+                }
+                Ok(false) if now.duration_since(conn.since) < READ_TIMEOUT => {
+                    self.reading.push(conn);
+                }
+                // The client gave up, or took too long.
+                Ok(false) | Err(_) => {}
+            }
+        }
+        let (expired, waiting) = std::mem::take(&mut self.waiting)
+            .into_iter()
+            .partition(|(_, since)| now.duration_since(*since) >= WAIT_TIMEOUT);
+        self.waiting = waiting;
+        for (stream, _) in expired {
+            let _ = respond(stream, "200 OK", "text/plain", self.version.to_string());
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, stream: TcpStream, request: &[u8]) -> io::Result<()> {
+        let request = String::from_utf8_lossy(request);
+        let request_line = request.lines().next().unwrap_or_default();
+        let mut parts = request_line.split_whitespace();
+        let (method, target) = match (parts.next(), parts.next()) {
+            (Some(method), Some(target)) => (method, target),
+            _ => return respond(stream, "400 Bad Request", "text/plain", "Bad request"),
+        };
+        if method != "GET" {
+            return respond(
+                stream,
+                "405 Method Not Allowed",
+                "text/plain",
+                "Method not allowed",
+            );
+        }
+        let (path, query) = match target.find('?') {
+            Some(i) => (&target[..i], &target[i + 1..]),
+            None => (target, ""),
+        };
+        match path {
+            "/" | "/index.html" => respond(stream, "200 OK", "text/html", self.page.clone()),
+            WAIT_PATH => {
+                let seen = query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("v="))
+                    .and_then(|v| v.parse().ok());
+                if seen == Some(self.version) {
+                    self.waiting.push((stream, Instant::now()));
+                    Ok(())
+                } else {
+                    respond(stream, "200 OK", "text/plain", self.version.to_string())
+                }
+            }
+            _ => match self.file(path) {
+                Some((content, content_type)) => respond(stream, "200 OK", content_type, content),
+                None => respond(stream, "404 Not Found", "text/plain", "Not found"),
+            },
+        }
+    }
+
+    /// Read a file under the root, given its (percent-encoded) URL path;
+    /// paths leaving the root aren't served.
+    fn file(&self, path: &str) -> Option<(Vec<u8>, &'static str)> {
+        let path = percent_decode(path)?;
+        let rel = Path::new(path.trim_start_matches('/'));
+        if !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+            return None;
+        }
+        let content = fs::read(self.root.join(rel)).ok()?;
+        let content_type = match rel.extension().and_then(|ext| ext.to_str()) {
+            Some("html") => "text/html",
+            Some("css") => "text/css",
+            Some("js") => "text/javascript",
+            Some("svg") => "image/svg+xml",
+            Some("png") => "image/png",
+            Some("jpg") | Some("jpeg") => "image/jpeg",
+            Some("gif") => "image/gif",
+            Some("webp") => "image/webp",
+            _ => "application/octet-stream",
+        };
+        Some((content, content_type))
+    }
+}
+
+/// Decode the `%XX` escapes in a URL path, e.g. `my%20pic.png` to `my
+/// pic.png`; malformed escapes and paths which aren't UTF-8 give `None`.
+fn percent_decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The script reloading the page when a version newer than `version` is
+/// rendered.
+fn reload_script(version: u64) -> String {
+    format!(
+        concat!(
+            "<script>(function poll(v) {{ fetch(\"{}?v=\" + v)",
+            ".then(r => r.text())",
+            ".then(n => n === String(v) ? poll(v) : location.reload())",
+            ".catch(() => setTimeout(() => poll(v), 1000)); }})({});</script>"
+        ),
+        WAIT_PATH, version
+    )
+}
+
+fn respond(
+    mut stream: TcpStream,
+    status: &str,
+    content_type: &str,
+    body: impl AsRef<[u8]>,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(READ_TIMEOUT))?;
+    let body = body.as_ref();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// A writer collecting output in memory, shared with its creator, for
+/// rendering into the served page.
+#[derive(Clone, Default)]
+pub(crate) struct SharedBuffer(pub(crate) Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A resolver recording the files read, so the document can be rendered
+/// again when one of them changes.
+pub(crate) struct WatchingResolver {
+    inner: FsResolver,
+    /// The files read, relative to the project root.
+    read: RefCell<BTreeSet<PathBuf>>,
+}
+
+impl WatchingResolver {
+    pub(crate) fn new(inner: FsResolver) -> Self {
+        Self {
+            inner,
+            read: Default::default(),
+        }
+    }
+
+    /// The files read (or attempted) so far, on the filesystem.
+    pub(crate) fn files(&self) -> Vec<PathBuf> {
+        let root = self.inner.root();
+        self.read
+            .borrow()
+            .iter()
+            .map(|path| root.join(path))
+            .collect()
+    }
+}
+
+impl ResourceResolver for WatchingResolver {
+    fn resolve(&self, base: &Path, rel: &str) -> Result<PathBuf, ResourceError> {
+        self.inner.resolve(base, rel)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, ResourceError> {
+        // Missing files are watched too, so creating one renders the
+        // document again.
+        self.read.borrow_mut().insert(path.to_owned());
+        self.inner.read(path)
+    }
+}
+
+/// The state of the given files, which changes when they're written, created,
+/// or removed.
+pub(crate) fn file_states(files: &[PathBuf]) -> Vec<Option<(SystemTime, u64)>> {
+    files
+        .iter()
+        .map(|file| {
+            let meta = fs::metadata(file).ok()?;
+            Some((meta.modified().ok()?, meta.len()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use std::io::Read;
+    use std::thread;
+
+    use super::*;
+
+    /// Make a GET request, returning the response's status line and body.
+    fn get(addr: SocketAddr, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response.lines().next().unwrap().to_owned();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, body.to_owned())
+    }
+
+    /// Poll `server` until `client` finishes, returning its result.
+    fn run<T: Send + 'static>(
+        server: &mut Server,
+        client: impl FnOnce(SocketAddr) -> T + Send + 'static,
+        mut between: impl FnMut(&mut Server),
+    ) -> T {
+        let addr = server.local_addr().unwrap();
+        let client = thread::spawn(move || client(addr));
+        while !client.is_finished() {
+            server.poll().unwrap();
+            between(server);
+            thread::sleep(Duration::from_millis(5));
+        }
+        client.join().unwrap()
+    }
+
+    #[test]
+    fn test_serve() {
+        let root = std::env::temp_dir().join(format!("textecca-serve-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("style.css"), "p {}").unwrap();
+        fs::write(root.join("my pic.svg"), "<svg/>").unwrap();
+        let mut server = Server::bind("127.0.0.1:0", root.clone()).unwrap();
+        server.set_page("<html><body><p>One.</p></body></html>");
+
+        let (status, body) = run(&mut server, |addr| get(addr, "/"), |_| {});
+        assert_eq!("HTTP/1.1 200 OK", status);
+        assert!(body.starts_with("<html><body><p>One.</p><script>"));
+        assert!(body.contains("})(1);</script>\n</body></html>"));
+
+        let (status, body) = run(&mut server, |addr| get(addr, "/style.css"), |_| {});
+        assert_eq!(
+            ("HTTP/1.1 200 OK", "p {}"),
+            (status.as_str(), body.as_str())
+        );
+        // E.g. `\img{my pic.svg}` is requested as `/my%20pic.svg`.
+        let (status, body) = run(&mut server, |addr| get(addr, "/my%20pic.svg"), |_| {});
+        assert_eq!(
+            ("HTTP/1.1 200 OK", "<svg/>"),
+            (status.as_str(), body.as_str())
+        );
+        let (status, _) = run(&mut server, |addr| get(addr, "/../etc/passwd"), |_| {});
+        assert_eq!("HTTP/1.1 404 Not Found", status);
+        // Escapes are decoded before the path is checked.
+        let (status, _) = run(&mut server, |addr| get(addr, "/%2E%2E/etc/passwd"), |_| {});
+        assert_eq!("HTTP/1.1 404 Not Found", status);
+
+        // A long-poll for the current version is answered after a rebuild.
+        let mut rebuilt = false;
+        let (_, version) = run(
+            &mut server,
+            |addr| get(addr, "/__textecca/wait?v=1"),
+            |server| {
+                if !rebuilt && !server.waiting.is_empty() {
+                    server.set_error("Error: <oops>");
+                    rebuilt = true;
+                }
+            },
+        );
+        assert_eq!("2", version);
+        let (_, body) = run(&mut server, |addr| get(addr, "/"), |_| {});
+        assert!(body.contains("<pre>Error: &lt;oops&gt;</pre>"));
+
+        // A stale version is answered immediately.
+        let (_, version) = run(
+            &mut server,
+            |addr| get(addr, "/__textecca/wait?v=1"),
+            |_| {},
+        );
+        assert_eq!("2", version);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_idle_connection() {
+        let mut server = Server::bind("127.0.0.1:0", std::env::temp_dir()).unwrap();
+        server.set_page("<p>One.</p>");
+        // A client which connects but never sends its request.
+        let idle = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        let (status, _) = run(&mut server, |addr| get(addr, "/"), |_| {});
+        assert_eq!("HTTP/1.1 200 OK", status);
+        assert_eq!(1, server.reading.len());
+        drop(idle);
+    }
+}
@@ -0,0 +1,33 @@
+//! Piping the output to a reader which stops early, like `head`.
+use std::fs;
+use std::io::Read;
+use std::process::Stdio;
+
+use pretty_assertions::assert_eq;
+
+mod common;
+use common::{textecca, TempDir};
+
+#[test]
+fn test_broken_pipe() {
+    let dir = TempDir::new("pipe");
+    let input = dir.join("long.tc");
+    // Much more output than fits in a pipe's buffer.
+    fs::write(&input, "Some text.\n\n".repeat(8_000)).unwrap();
+
+    let mut child = textecca()
+        .arg(&input)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut start = [0; 16];
+    stdout.read_exact(&mut start).unwrap();
+    drop(stdout);
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!("<!DOCTYPE html>\n", String::from_utf8_lossy(&start));
+    assert_eq!("", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success(), "{}", output.status);
+}
@@ -0,0 +1,9 @@
+//! Fixtures shared by the integration tests.
+use std::process::Command;
+
+pub use friendly_html::test_util::TempDir;
+
+/// The `textecca` binary, ready to be given arguments.
+pub fn textecca() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_textecca-bin"))
+}
@@ -0,0 +1,95 @@
+//! Printing diagnostics as JSON with `--error-format json`.
+use std::fs;
+use std::process::Output;
+
+use pretty_assertions::assert_eq;
+use serde_json::{json, Value};
+
+mod common;
+use common::{textecca, TempDir};
+
+fn render(name: &str, src: &str, args: &[&str]) -> Output {
+    let dir = TempDir::new(name);
+    let input = dir.join("doc.tc");
+    fs::write(&input, src).unwrap();
+    textecca()
+        .args(["--error-format", "json"])
+        .args(args)
+        .arg(&input)
+        .output()
+        .unwrap()
+}
+
+/// The diagnostics printed to stderr, one per line.
+fn diagnostics(output: &Output) -> Vec<Value> {
+    String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_json_syntax_error() {
+    let output = render("json-syntax", "Hello \\emph{x\n", &[]);
+    assert!(!output.status.success());
+    assert_eq!(
+        vec![json!({
+            "version": 1,
+            "severity": "error",
+            "code": "E0001",
+            "message": "expected '}' in the command at 1:7",
+            "file": "doc.tc",
+            "span": {"start": 14, "end": 14, "line": 2, "column": 1},
+            "related": [{
+                "span": {"start": 6, "end": 14, "line": 1, "column": 7},
+                "message": "In this command",
+            }],
+            "help": null,
+        })],
+        diagnostics(&output)
+    );
+}
+
+#[test]
+fn test_json_strict_warning() {
+    let src = "Text \\nonexistent{x}.\n";
+    let warning = json!({
+        "version": 1,
+        "severity": "warning",
+        "code": "W0006",
+        "message": "Command nonexistent not defined in current environment",
+        "file": "doc.tc",
+        "span": {"start": 6, "end": 6, "line": 1, "column": 7},
+        "related": [],
+        "help": "Check the command's spelling",
+    });
+    let output = render("json-warning", src, &[]);
+    assert!(output.status.success());
+    assert_eq!(vec![warning.clone()], diagnostics(&output));
+
+    // The same diagnostic, as an error.
+    let output = render("json-strict", src, &["--strict"]);
+    assert!(!output.status.success());
+    let mut error = warning;
+    error["severity"] = json!("error");
+    assert_eq!(vec![error], diagnostics(&output));
+}
+
+#[test]
+fn test_json_invalid_argument() {
+    // The argument is warned about when it's parsed, and is an error when
+    // it's evaluated; only the error is printed.
+    let output = render(
+        "json-invalid-argument",
+        "Text \\emph{Bad \\ escape.}\n",
+        &["--partial"],
+    );
+    assert!(!output.status.success());
+    let diagnostics = diagnostics(&output);
+    assert_eq!(1, diagnostics.len(), "{:?}", diagnostics);
+    assert_eq!(json!("error"), diagnostics[0]["severity"]);
+    assert_eq!(
+        json!({"start": 11, "end": 24, "line": 1, "column": 12}),
+        diagnostics[0]["span"]
+    );
+}
@@ -0,0 +1,73 @@
+//! Linting the document with `--check`.
+use std::fs;
+use std::process::Output;
+
+use pretty_assertions::assert_eq;
+
+mod common;
+use common::{textecca, TempDir};
+
+fn check_source(src: &str, args: &[&str]) -> Output {
+    let dir = TempDir::new("lint");
+    let input = dir.join("doc.tc");
+    fs::write(&input, src).unwrap();
+    textecca()
+        .arg("--check")
+        .args(args)
+        .arg(&input)
+        .output()
+        .unwrap()
+}
+
+fn check(args: &[&str]) -> Output {
+    check_source("\\strong{Very \\strong{important}}.\n", args)
+}
+
+#[test]
+fn test_check_lints() {
+    let output = check(&[]);
+    assert_eq!(
+        "Warning: 1:2: Strong text is inside strong text (block 2; lint:nested-strong)\n",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!output.status.success());
+
+    let output = check(&["-D", "lint:nested-strong=false"]);
+    assert_eq!("", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success(), "{}", output.status);
+
+    let output = check(&["-D", "lint:nested=false"]);
+    assert_eq!(
+        "Error: Unknown lint rule \"nested\"\n",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_check_lint_position() {
+    let output = check_source(
+        "First paragraph.\n\nSecond \\emph{paragraph}.\n\nThird \\strong{c \\strong{d}}.\n",
+        &[],
+    );
+    assert_eq!(
+        "Warning: 5:1: Strong text is inside strong text (block 3; lint:nested-strong)\n",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_check_evaluation_warnings() {
+    let output = check_source("Text.\\todo{Cite this.}\n", &[]);
+    assert_eq!(
+        "Warning: 1:12: TODO: Cite this.\n",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!output.status.success());
+
+    // Todos are expected in drafts.
+    let output = check_source("Text.\\todo{Cite this.}\n", &["-D", "draft=true"]);
+    assert_eq!("", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success(), "{}", output.status);
+}
@@ -0,0 +1,26 @@
+//! Printing where evaluation spent its time with `--profile`.
+use std::fs;
+
+mod common;
+use common::{textecca, TempDir};
+
+#[test]
+fn test_profile() {
+    let dir = TempDir::new("profile");
+    let input = dir.join("doc.tc");
+    fs::write(&input, "\\strong{\\emph{a} \\emph{b}} \\emph{c}\n").unwrap();
+    let output = textecca().arg("--profile").arg(&input).output().unwrap();
+    assert!(output.status.success());
+
+    // Rows are sorted by self time, which varies, so only the names and call
+    // counts are checked.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut lines = stderr.lines();
+    let header: Vec<&str> = lines.next().unwrap().split_whitespace().collect();
+    assert_eq!(vec!["command", "calls", "total", "self"], header);
+    let mut rows: Vec<Vec<&str>> = lines
+        .map(|line| line.split_whitespace().take(2).collect())
+        .collect();
+    rows.sort();
+    assert_eq!(vec![vec!["\\emph", "3"], vec!["\\strong", "1"]], rows);
+}
@@ -0,0 +1,58 @@
+//! Previewing a document with `--serve`.
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::Stdio;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+use common::{textecca, TempDir};
+
+/// Get the page served at `/`.
+fn get_page(addr: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_serve_included_file() {
+    let dir = TempDir::new("serve-include");
+    let input = dir.join("doc.tc");
+    fs::write(&input, "Before.\n\n\\include{part.tc}\n").unwrap();
+    fs::write(dir.join("part.tc"), "First version.\n").unwrap();
+
+    let mut child = textecca()
+        .arg("--serve=127.0.0.1:0")
+        .arg(&input)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let addr = line
+        .trim_end()
+        .rsplit("http://")
+        .next()
+        .unwrap()
+        .trim_end_matches('/')
+        .to_owned();
+
+    assert!(get_page(&addr).contains("First version."));
+
+    // Editing the included file renders the document again.
+    fs::write(dir.join("part.tc"), "The second version.\n").unwrap();
+    let start = Instant::now();
+    let mut page = get_page(&addr);
+    while !page.contains("The second version.") && start.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(50));
+        page = get_page(&addr);
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(page.contains("The second version."), "{}", page);
+}
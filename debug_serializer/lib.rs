@@ -0,0 +1,127 @@
+#![warn(missing_docs)]
+
+//! A trivial textecca serializer which writes the `Debug` representation of a
+//! document. This crate shows how other crates add output formats; see
+//! `textecca::ser::Registry`.
+
+use std::io::Write;
+
+use textecca::{
+    doc::Doc,
+    ser::{Registry, Serializer, SerializerError, SerializerOptions},
+};
+
+/// Writes documents with `{:#?}`.
+pub struct DebugSerializer {
+    writer: Box<dyn Write>,
+}
+
+impl DebugSerializer {
+    /// Create a new serializer; this is a `textecca::ser::SerializerFactory`.
+    pub fn create(
+        writer: Box<dyn Write>,
+        _options: &SerializerOptions,
+    ) -> Result<Box<dyn Serializer>, SerializerError> {
+        Ok(Box::new(Self { writer }))
+    }
+}
+
+impl Serializer for DebugSerializer {
+    fn format_name(&self) -> &'static str {
+        "debug"
+    }
+
+    fn write_doc(&mut self, doc: &Doc) -> Result<(), SerializerError> {
+        writeln!(self.writer, "{:#?}", doc)?;
+        Ok(())
+    }
+}
+
+/// Register the `"debug"` format.
+pub fn register(registry: &mut Registry) {
+    registry.register("debug", DebugSerializer::create);
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    use pretty_assertions::assert_eq;
+    use textecca::doc::{Block, BlockInner, Blocks, Id, Inline};
+
+    use super::*;
+
+    /// A writer whose output can be read after the serializer is dropped.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::with_builtins();
+        register(&mut registry);
+        assert_eq!(
+            vec!["debug", "epub", "html", "json", "latex", "markdown", "pandoc", "text"],
+            registry.names().collect::<Vec<_>>()
+        );
+
+        let doc = Doc::from_content(
+            Block::new(
+                Id::from(0),
+                BlockInner::Par(vec![Inline::Text("Hello!".into())]),
+            )
+            .into(),
+        );
+        let out = SharedBuf::default();
+        let mut ser = registry
+            .create("debug", Box::new(out.clone()), &Default::default())
+            .unwrap();
+        assert_eq!("debug", ser.format_name());
+        ser.write_doc(&doc).unwrap();
+        assert_eq!(
+            format!("{:#?}\n", doc),
+            String::from_utf8(out.0.borrow().clone()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_two_formats() {
+        let mut registry = Registry::with_builtins();
+        register(&mut registry);
+        let par = |text: &str| {
+            Blocks::from(Block::new(
+                Id::from(0),
+                BlockInner::Par(vec![Inline::Text(text.into())]),
+            ))
+        };
+        let mut doc = Doc::from_content(par("Hello!"));
+        // A channel only the debug output knows.
+        doc.collections
+            .insert("custom".into(), vec![par("Collected.")]);
+        // The same document, borrowed by both serializers.
+        let mut outputs = Vec::new();
+        for format in &["debug", "html"] {
+            let out = SharedBuf::default();
+            let mut ser = registry
+                .create(format, Box::new(out.clone()), &Default::default())
+                .unwrap();
+            ser.write_doc(&doc).unwrap();
+            outputs.push(String::from_utf8(out.0.borrow().clone()).unwrap());
+        }
+        assert!(outputs[0].contains("Hello!"));
+        assert!(outputs[0].contains("Collected."));
+        assert!(outputs[1].contains("<p>Hello!</p>"));
+        assert!(!outputs[1].contains("Collected."));
+    }
+}
@@ -7,20 +7,31 @@ use syn::{
     AttrStyle, Attribute, Expr, ExprLit, ExprPath, Ident, Lit, LitStr, Path,
 };
 
+/// Either a `name = expr` pair or a bare `name` flag.
 struct MetaNameExpr {
     path: Path,
-    #[allow(dead_code)]
-    eq_token: syn::token::Eq,
-    expr: syn::Expr,
+    expr: Option<syn::Expr>,
 }
 
 impl Parse for MetaNameExpr {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        Ok(MetaNameExpr {
-            path: input.parse()?,
-            eq_token: input.parse()?,
-            expr: input.parse()?,
-        })
+        let path = input.parse()?;
+        let expr = if input.peek(syn::token::Eq) {
+            let _eq_token: syn::token::Eq = input.parse()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        Ok(MetaNameExpr { path, expr })
+    }
+}
+
+impl MetaNameExpr {
+    fn expr(self) -> Expr {
+        match self.expr {
+            Some(expr) => expr,
+            None => panic!("Attribute {:?} requires a value", self.path),
+        }
     }
 }
 
@@ -63,6 +74,8 @@ fn expr_to_litstr(expr: Expr) -> LitStr {
 
 pub struct FieldAttr {
     pub name: Option<LitStr>,
+    /// Whether this field collects all remaining keyword arguments.
+    pub kwargs: bool,
 }
 
 impl FieldAttr {
@@ -73,21 +86,29 @@ impl FieldAttr {
     // TODO: Unify this with StructAttr init. boilerplate?
     pub fn from_attrs(attrs: Vec<Attribute>) -> Option<Self> {
         let name_path: Path = syn::parse_str("name").unwrap();
+        let kwargs_path: Path = syn::parse_str("kwargs").unwrap();
         let mut name = None;
+        let mut kwargs = false;
         for meta in name_expr_attrs(attrs) {
             if meta.path == name_path {
-                name = Some(Self::field_name_expr(meta.expr));
+                name = Some(Self::field_name_expr(meta.expr()));
+            } else if meta.path == kwargs_path {
+                kwargs = true;
             } else {
                 panic!("Unsupported attribute name {:?}", meta.path);
             }
         }
-        Some(Self { name })
+        Some(Self { name, kwargs })
     }
 }
 
 pub struct StructAttr {
     pub name: Option<LitStr>,
     pub parser: Option<Expr>,
+    /// The parser to install for commands evaluated in the command's body.
+    pub parser_override: Option<Expr>,
+    /// Why the command is deprecated and what to use instead, if it is.
+    pub deprecated: Option<LitStr>,
 }
 
 impl StructAttr {
@@ -98,19 +119,32 @@ impl StructAttr {
     pub fn from_attrs(attrs: Vec<Attribute>) -> Self {
         let name_path: Path = syn::parse_str("name").unwrap();
         let parser_path: Path = syn::parse_str("parser").unwrap();
+        let parser_override_path: Path = syn::parse_str("parser_override").unwrap();
+        let deprecated_path: Path = syn::parse_str("deprecated").unwrap();
 
         let mut name = None;
         let mut parser = None;
+        let mut parser_override = None;
+        let mut deprecated = None;
         for meta in name_expr_attrs(attrs) {
             if meta.path == name_path {
-                name = Some(Self::cmd_name_attr(meta.expr));
+                name = Some(Self::cmd_name_attr(meta.expr()));
             } else if meta.path == parser_path {
-                parser = Some(meta.expr);
+                parser = Some(meta.expr());
+            } else if meta.path == parser_override_path {
+                parser_override = Some(meta.expr());
+            } else if meta.path == deprecated_path {
+                deprecated = Some(expr_to_litstr(meta.expr()));
             } else {
                 panic!("Unsupported attribute name {:?}", meta.path);
             }
         }
-        Self { name, parser }
+        Self {
+            name,
+            parser,
+            parser_override,
+            deprecated,
+        }
     }
 
     pub fn cmd_name(&self, default: &Ident) -> LitStr {
@@ -5,7 +5,7 @@ use syn::{DeriveInput, Ident, Path};
 mod attrs;
 mod param;
 use attrs::{FieldAttr, StructAttr};
-use param::Param;
+use param::{Param, ParamKind};
 
 #[proc_macro_derive(CommandInfo, attributes(textecca))]
 pub fn command_macro_derive(input: TokenStream) -> TokenStream {
@@ -23,13 +23,40 @@ fn impl_command_macro(ast: syn::DeriveInput) -> TokenStream {
     } = ast;
     let parsed_args_ident: Ident = syn::parse_str("parsed__").unwrap();
     let params = struct_to_params(data);
-    let params_code = params.iter().map(|p| p.to_tokens(&parsed_args_ident));
+    // Keyword arguments are collected last, after any named parameters are popped.
+    let params_code = params
+        .iter()
+        .filter(|p| p.kind != ParamKind::Kwargs)
+        .chain(params.iter().filter(|p| p.kind == ParamKind::Kwargs))
+        .map(|p| p.to_tokens(&parsed_args_ident));
 
     let struct_attrs = StructAttr::from_attrs(attrs);
     let fields = params.iter().map(|p| &p.field_ident);
     let cmd_name_lit = struct_attrs.cmd_name(&ident);
     let default_parser: Path = syn::parse_str("::textecca::parse::default_parser").unwrap();
     let parser_expr = struct_attrs.parser(&default_parser);
+    // Commands with their own parser don't inherit one.
+    let inherits_parser_fn = struct_attrs.parser.as_ref().map(|_| {
+        quote! {
+            fn inherits_parser() -> bool {
+                false
+            }
+        }
+    });
+    let parser_override_fn = struct_attrs.parser_override.as_ref().map(|parser| {
+        quote! {
+            fn parser_override() -> ::std::option::Option<::textecca::parse::Parser> {
+                ::std::option::Option::Some(#parser)
+            }
+        }
+    });
+    let deprecated_fn = struct_attrs.deprecated.as_ref().map(|message| {
+        quote! {
+            fn deprecated() -> ::std::option::Option<String> {
+                ::std::option::Option::Some(String::from(#message))
+            }
+        }
+    });
 
     let gen = quote! {
         impl#generics #ident#generics {
@@ -59,6 +86,10 @@ fn impl_command_macro(ast: syn::DeriveInput) -> TokenStream {
             fn parser_fn() -> ::textecca::parse::Parser {
                 #parser_expr
             }
+
+            #inherits_parser_fn
+            #parser_override_fn
+            #deprecated_fn
         }
     };
     gen.into()
@@ -75,9 +106,14 @@ fn struct_to_params(data: syn::Data) -> Vec<Param> {
                 // Named fields always have identifiers.
                 let ident = field.ident.unwrap();
                 let attrs = FieldAttr::from_attrs(field.attrs);
+                let kind = match &attrs {
+                    Some(FieldAttr { kwargs: true, .. }) => ParamKind::Kwargs,
+                    _ => ParamKind::from_type(&field.ty),
+                };
                 ret.push(Param {
-                    name: attrs.map(|a| a.name).flatten(),
+                    name: attrs.and_then(|a| a.name),
                     field_ident: ident,
+                    kind,
                 });
             }
             ret
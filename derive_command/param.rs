@@ -1,10 +1,41 @@
 use proc_macro2::{Span, TokenStream};
 use quote::quote;
-use syn::{Ident, LitStr};
+use syn::{Ident, LitStr, Type};
+
+/// How a `Param` is populated from a `ParsedArgs`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ParamKind {
+    /// A mandatory argument, given by keyword or positionally.
+    Mandatory,
+    /// An optional keyword argument; the field is an `Option`.
+    Optional,
+    /// All remaining keyword arguments.
+    Kwargs,
+}
+
+impl ParamKind {
+    /// The kind of a field with the given type, unless it's a kwargs field.
+    pub fn from_type(ty: &Type) -> Self {
+        match ty {
+            Type::Path(path)
+                if path
+                    .path
+                    .segments
+                    .last()
+                    .map(|seg| seg.ident == "Option")
+                    .unwrap_or(false) =>
+            {
+                ParamKind::Optional
+            }
+            _ => ParamKind::Mandatory,
+        }
+    }
+}
 
 pub struct Param {
     pub name: Option<LitStr>,
     pub field_ident: Ident,
+    pub kind: ParamKind,
 }
 
 impl Param {
@@ -20,8 +51,16 @@ impl Param {
     pub fn to_tokens(&self, parsed_args_ident: &Ident) -> TokenStream {
         let Self { field_ident, .. } = self;
         let name = self.name();
-        quote! {
-            let #field_ident = #parsed_args_ident.pop_mandatory(#name)?;
+        match self.kind {
+            ParamKind::Mandatory => quote! {
+                let #field_ident = #parsed_args_ident.pop_mandatory(#name)?;
+            },
+            ParamKind::Optional => quote! {
+                let #field_ident = #parsed_args_ident.pop_optional(#name);
+            },
+            ParamKind::Kwargs => quote! {
+                let #field_ident = #parsed_args_ident.take_kwargs();
+            },
         }
     }
 }
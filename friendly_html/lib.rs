@@ -22,10 +22,65 @@ use html5ever::{namespace_url, ns};
 mod tendril_ext;
 use tendril_ext::AsStrLossy;
 
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
 /// An HTML serializer.
+///
+/// After the writer fails, the serializer is *poisoned*: the failed call
+/// gives the writer's error, and every later call which would write gives a
+/// `SerializeError::Poisoned` without writing anything. Otherwise, the
+/// serializer and the output could disagree about which elements are open,
+/// and e.g. closing them could write garbage or panic.
 pub struct HtmlSerializer<W: Write> {
     ser: h5::HtmlSerializer<W>,
     elems: Vec<h5::QualName>,
+    options: Options,
+    /// Whether a write has failed; see `is_poisoned`.
+    poisoned: bool,
+    /// Whether the last thing written was a block-level tag; see
+    /// `Options::indent`.
+    after_block_tag: bool,
+    /// Whether the output is at the start of a line.
+    line_start: bool,
+}
+
+/// Options for an `HtmlSerializer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// Whether the output is for a browser with scripting enabled; on by
+    /// default.
+    ///
+    /// Such browsers parse the content of a `<noscript>` element as raw text
+    /// rather than as markup, so text written directly in a `<noscript>`
+    /// element isn't escaped; with this off, it's escaped like any other
+    /// text. Text in elements inside a `<noscript>` element is always
+    /// escaped.
+    pub scripting_enabled: bool,
+
+    /// Whether an end tag without a matching start tag in the markup given to
+    /// `write_html` is written anyway, rather than giving a
+    /// `SerializeError::EndEmpty`; off by default.
+    pub create_missing_parent: bool,
+
+    /// If given, block-level elements (see `is_block`) are indented by this
+    /// many spaces per open element: each block-level start tag starts a new
+    /// line, as does each block-level end tag after another block-level tag.
+    /// Text, inline elements, and the markup given to `write_html` are
+    /// written as they are, and nothing is indented inside elements where
+    /// whitespace is significant, like `<pre>`. By default, `None`, and
+    /// nothing is indented.
+    pub indent: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            scripting_enabled: true,
+            create_missing_parent: false,
+            indent: None,
+        }
+    }
 }
 
 impl<W: Write + fmt::Debug> fmt::Debug for HtmlSerializer<W> {
@@ -33,6 +88,7 @@ impl<W: Write + fmt::Debug> fmt::Debug for HtmlSerializer<W> {
         f.debug_struct("HtmlSerializer")
             .field("writer", &self.ser.writer)
             .field("elems", &self.elems)
+            .field("poisoned", &self.poisoned)
             .finish()
     }
 }
@@ -40,69 +96,163 @@ impl<W: Write + fmt::Debug> fmt::Debug for HtmlSerializer<W> {
 impl<W: Write> HtmlSerializer<W> {
     /// Create a new serializer from a given `Write` object.
     pub fn new(writer: W) -> Self {
+        Self::with_options(writer, Default::default())
+    }
+
+    /// Create a new serializer from a given `Write` object with the given
+    /// options.
+    pub fn with_options(writer: W, options: Options) -> Self {
         Self {
             ser: h5::HtmlSerializer::new(
                 writer,
                 h5::SerializeOpts {
-                    // Documentation is unclear, but the only effect this has is to
-                    // not escape text when we use `write_text` within a
-                    // `<noscript>` element.
-                    scripting_enabled: true,
+                    scripting_enabled: options.scripting_enabled,
                     // Don't serialize the "root node".
                     traversal_scope: h5::TraversalScope::ChildrenOnly(None),
-                    // Just panic if we attempt to serialize an invalid tree --
-                    // though our bindings prevent us from doing that.
-                    create_missing_parent: false,
+                    // Unmatched end tags are handled by `SerializerSink`, so
+                    // html5ever's serializer never sees them unless they're
+                    // wanted.
+                    create_missing_parent: options.create_missing_parent,
                 },
             ),
             // Will *likely* not need to reallocate.
             elems: Vec::with_capacity(256),
+            options,
+            poisoned: false,
+            after_block_tag: false,
+            line_start: true,
         }
     }
 
     /// Create a new serializer and write `<!DOCTYPE html>` before returning it.
     pub fn with_doctype(writer: W) -> Result<Self, SerializeError> {
-        let mut ret = Self::new(writer);
+        Self::with_doctype_options(writer, Default::default())
+    }
+
+    /// Create a new serializer with the given options and write `<!DOCTYPE
+    /// html>` before returning it.
+    pub fn with_doctype_options(writer: W, options: Options) -> Result<Self, SerializeError> {
+        let mut ret = Self::with_options(writer, options);
         ret.write_doctype("html")?;
         ret.write_text("\n")?;
         Ok(ret)
     }
 
+    /// The options this serializer was created with.
+    pub fn options(&self) -> Options {
+        self.options
+    }
+
+    /// Has the writer failed? If so, nothing more is written; see
+    /// `HtmlSerializer`.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Call `f` with the underlying serializer unless the writer has failed,
+    /// and poison this serializer if it fails now.
+    fn write_with<T>(
+        &mut self,
+        f: impl FnOnce(&mut h5::HtmlSerializer<W>) -> io::Result<T>,
+    ) -> Result<T, SerializeError> {
+        if self.poisoned {
+            return Err(SerializeError::Poisoned);
+        }
+        f(&mut self.ser).map_err(|err| {
+            self.poisoned = true;
+            err.into()
+        })
+    }
+
+    /// Start a new line indented for an element with `depth` open elements
+    /// around it, if it's a block-level element and indenting is on; see
+    /// `Options::indent`. `name` is open if it's being closed.
+    fn indent(&mut self, name: &h5::QualName, depth: usize) -> Result<(), SerializeError> {
+        let width = match self.options.indent {
+            Some(width) if is_block(&&*name.local) => width,
+            _ => return Ok(()),
+        };
+        if self
+            .elems
+            .iter()
+            .any(|elem| PREFORMATTED_ELEMENTS.contains(&&*elem.local))
+        {
+            return Ok(());
+        }
+        let mut indent = if self.line_start {
+            String::new()
+        } else {
+            "\n".to_owned()
+        };
+        indent.push_str(&" ".repeat(width * depth));
+        self.write_with(|ser| ser.writer.write_all(indent.as_bytes()))
+    }
+
+    /// Update the state used for indenting after writing a tag.
+    fn wrote_tag(&mut self, name: &h5::QualName) {
+        self.after_block_tag = is_block(&&*name.local);
+        self.line_start = false;
+    }
+
     /// Serialize a comment.
-    #[must_use]
     pub fn write_comment(&mut self, text: &str) -> Result<(), SerializeError> {
-        Ok(self.ser.write_comment(text)?)
+        self.write_with(|ser| ser.write_comment(text))?;
+        self.after_block_tag = false;
+        self.line_start = false;
+        Ok(())
     }
 
     /// Serialize text, escaping it if necessary.
-    #[must_use]
     pub fn write_text(&mut self, text: impl AsRef<str>) -> Result<(), SerializeError> {
-        Ok(self.ser.write_text(text.as_ref())?)
+        let text = text.as_ref();
+        self.write_with(|ser| ser.write_text(text))?;
+        // Whitespace between blocks doesn't make them inline.
+        if !text.trim().is_empty() {
+            self.after_block_tag = false;
+        }
+        if !text.is_empty() {
+            self.line_start = text.ends_with('\n');
+        }
+        Ok(())
     }
 
     /// Serialize a doctype.
-    #[must_use]
     pub fn write_doctype(&mut self, name: &str) -> Result<(), SerializeError> {
-        Ok(self.ser.write_doctype(name)?)
+        self.write_with(|ser| ser.write_doctype(name))?;
+        self.line_start = false;
+        Ok(())
+    }
+
+    /// Flush the writer, e.g. so a buffered writer's errors are reported
+    /// rather than lost when it's dropped.
+    pub fn flush(&mut self) -> Result<(), SerializeError> {
+        self.write_with(|ser| ser.writer.flush())
     }
 
     /// Serialize the start of an element.
-    #[must_use]
     pub fn elem(&mut self, name: impl AsRef<str>) -> Result<(), SerializeError> {
         let tag_name = html_name(&name);
         // We'll need to close a non-void tag.
         let elem_is_void = is_void(&name);
-        let ret = Ok(self.ser.start_elem(tag_name.clone(), iter::empty())?);
+        self.indent(&tag_name, self.elems.len())?;
+        self.write_with(|ser| ser.start_elem(tag_name.clone(), iter::empty()))?;
+        self.wrote_tag(&tag_name);
         if elem_is_void {
-            self.ser.end_elem(tag_name)?;
+            self.write_with(|ser| ser.end_elem(tag_name))?;
         } else {
             self.elems.push(tag_name);
         }
-        ret
+        Ok(())
     }
 
     /// Serialize the start of an element with attributes.
-    #[must_use]
+    ///
+    /// Attribute values may contain arbitrary text; they're always quoted and
+    /// escaped, so a value can't end its attribute or element early. Attribute
+    /// names, however, are written verbatim, so this errors with
+    /// `SerializeError::InvalidAttrName` if a name isn't valid (see
+    /// `is_valid_attr_name`). The element name is also written verbatim and
+    /// should be a known element.
     pub fn elem_attrs(
         &mut self,
         name: impl AsRef<str>,
@@ -110,43 +260,112 @@ impl<W: Write> HtmlSerializer<W> {
     ) -> Result<(), SerializeError> {
         // This isn't a big deal, especially if the iterator has a size hint.
         let attrs: Vec<_> = attrs
-            .into_iter()
-            .map(|(name, value)| (attr_name(name), value))
-            .collect();
+            .iter()
+            .map(|(name, value)| {
+                if is_valid_attr_name(name.as_ref()) {
+                    Ok((attr_name(name), value))
+                } else {
+                    Err(SerializeError::InvalidAttrName(name.as_ref().to_owned()))
+                }
+            })
+            .collect::<Result<_, _>>()?;
         let tag_name = html_name(&name);
         let elem_is_void = is_void(&name);
-        let ret = Ok(self.ser.start_elem(
-            tag_name.clone(),
-            attrs.iter().map(|(name, value)| (name, value.as_ref())),
-        )?);
+        self.indent(&tag_name, self.elems.len())?;
+        self.write_with(|ser| {
+            ser.start_elem(
+                tag_name.clone(),
+                attrs.iter().map(|(name, value)| (name, value.as_ref())),
+            )
+        })?;
+        self.wrote_tag(&tag_name);
         if elem_is_void {
-            self.ser.end_elem(tag_name)?;
+            self.write_with(|ser| ser.end_elem(tag_name))?;
         } else {
             self.elems.push(tag_name);
         }
-        ret
+        Ok(())
     }
 
-    /// Close the last-opened element.
-    #[must_use]
+    /// Close the last-opened element, or give a `SerializeError::EndEmpty` if
+    /// there isn't one.
     pub fn end_elem(&mut self) -> Result<(), SerializeError> {
-        Ok(self.ser.end_elem(self.elems.pop().unwrap())?)
+        if self.poisoned {
+            return Err(SerializeError::Poisoned);
+        }
+        let name = self.elems.last().ok_or(SerializeError::EndEmpty)?.clone();
+        if self.after_block_tag {
+            self.indent(&name, self.elems.len() - 1)?;
+        }
+        self.elems.pop();
+        self.write_with(|ser| ser.end_elem(name.clone()))?;
+        self.wrote_tag(&name);
+        Ok(())
+    }
+
+    /// The number of elements which are open, i.e. haven't been closed with
+    /// `end_elem`.
+    pub fn open_element_count(&self) -> usize {
+        self.elems.len()
+    }
+
+    /// The name of the innermost open element, if any.
+    pub fn current_element(&self) -> Option<&str> {
+        self.elems.last().map(|name| &*name.local)
+    }
+
+    /// Stop serializing early, e.g. after an error: write a comment marking the
+    /// truncation point (`TRUNCATION_COMMENT`) and close all open elements, so
+    /// that the partial output is at least well-formed.
+    ///
+    /// If the writer has failed, nothing can be written, so this does nothing;
+    /// the writer's error is the one to report.
+    pub fn abort(&mut self) -> Result<(), SerializeError> {
+        if self.poisoned {
+            return Ok(());
+        }
+        self.write_comment(TRUNCATION_COMMENT)?;
+        while !self.elems.is_empty() {
+            self.end_elem()?;
+        }
+        Ok(())
     }
 
     /// Write the HTML *string* to the writer.
+    ///
+    /// End tags close the elements opened earlier in the same string; see
+    /// `Options::create_missing_parent` for those which don't.
     pub fn write_html(&mut self, html: &str) -> Result<(), SerializeError> {
+        if self.poisoned {
+            return Err(SerializeError::Poisoned);
+        }
         let sink = SerializerSink {
             ser: Ok(&mut self.ser),
+            open: 0,
+            create_missing_parent: self.options.create_missing_parent,
         };
         let mut queue = h5::BufferQueue::new();
         queue.push_back(html.into());
         let mut tokenizer = h5::Tokenizer::new(sink, Default::default());
         let _ = tokenizer.feed(&mut queue);
-        tokenizer.sink.ser?;
-        Ok(())
+        self.after_block_tag = false;
+        if !html.is_empty() {
+            self.line_start = html.ends_with('\n');
+        }
+        match tokenizer.sink.ser {
+            Ok(_) => Ok(()),
+            Err(err @ SerializeError::Io(_)) => {
+                self.poisoned = true;
+                Err(err)
+            }
+            Err(err) => Err(err),
+        }
     }
 }
 
+/// The text of the comment written by `HtmlSerializer::abort`.
+pub const TRUNCATION_COMMENT: &str = " Output truncated due to an error. ";
+
 /// [Void elements][void] have no children or ending tag.
 ///
 /// [void]: https://html.spec.whatwg.org/multipage/syntax.html#void-elements
@@ -155,10 +374,90 @@ const VOID_ELEMENTS: [&str; 14] = [
     "track", "wbr",
 ];
 
-fn is_void(name: &impl AsRef<str>) -> bool {
+/// Is `name` a void element, which has no children or end tag?
+pub fn is_void(name: &impl AsRef<str>) -> bool {
     VOID_ELEMENTS.contains(&name.as_ref())
 }
 
+/// The elements `is_block` is true for.
+const BLOCK_ELEMENTS: [&str; 50] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "caption",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "li",
+    "link",
+    "main",
+    "meta",
+    "nav",
+    "noscript",
+    "ol",
+    "p",
+    "pre",
+    "script",
+    "section",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+/// Elements where whitespace is significant, so nothing inside them is
+/// indented; see `Options::indent`.
+const PREFORMATTED_ELEMENTS: [&str; 4] = ["pre", "script", "style", "textarea"];
+
+/// Is `name` a block-level element, which starts on its own line when
+/// indenting (see `Options::indent`)? This includes the elements of the
+/// document's structure, like `<head>`, and of tables and lists, like `<tr>`
+/// and `<li>`, along with elements in `<head>` like `<link>`. Unknown
+/// elements are inline.
+pub fn is_block(name: &impl AsRef<str>) -> bool {
+    BLOCK_ELEMENTS.contains(&name.as_ref())
+}
+
+/// Can `name` be written as an attribute name without changing the structure of
+/// the surrounding markup?
+///
+/// Valid names are non-empty and don't contain whitespace, control characters,
+/// or any of `"'<>/=`.
+pub fn is_valid_attr_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.chars().any(|c| {
+            c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '<' | '>' | '/' | '=')
+        })
+}
+
 fn attr_name(name: impl AsRef<str>) -> h5::QualName {
     h5::QualName::new(None, ns!(), h5::LocalName::from(name.as_ref()))
 }
@@ -169,6 +468,10 @@ fn html_name(name: impl AsRef<str>) -> h5::QualName {
 
 struct SerializerSink<'s, W: Write> {
     ser: Result<&'s mut h5::HtmlSerializer<W>, SerializeError>,
+    /// The number of non-void elements opened and not yet closed.
+    open: usize,
+    /// See `Options::create_missing_parent`.
+    create_missing_parent: bool,
 }
 
 impl<'s, W: Write> SerializerSink<'s, W> {
@@ -183,12 +486,20 @@ impl<'s, W: Write> SerializerSink<'s, W> {
         AttrIter: Iterator<Item = (&'a h5::QualName, &'a str)>,
     {
         if let Ok(ser) = self.ser.as_mut() {
+            if !is_void(&&*name.local) {
+                self.open += 1;
+            }
             let err = ser.start_elem(name, attrs);
             self.err(err);
         }
     }
 
     fn end_elem(&mut self, name: h5::QualName) {
+        if self.open > 0 {
+            self.open -= 1;
+        } else if !self.create_missing_parent {
+            self.ser = Err(SerializeError::EndEmpty);
+        }
         if let Ok(ser) = self.ser.as_mut() {
             let err = ser.end_elem(name);
             self.err(err);
@@ -267,6 +578,106 @@ impl<'s, W: Write> h5::TokenSink for SerializerSink<'s, W> {
     }
 }
 
+/// An HTML token, as produced by `tokenize`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HtmlToken {
+    /// A doctype, e.g. `<!DOCTYPE html>`.
+    Doctype(String),
+    /// The start of an element.
+    StartTag {
+        /// The element's name.
+        name: String,
+        /// The element's attributes, in source order, with character
+        /// references in their values decoded.
+        attrs: Vec<(String, String)>,
+    },
+    /// The end of an element.
+    EndTag(String),
+    /// Text, with character references decoded.
+    Text(String),
+    /// A comment.
+    Comment(String),
+}
+
+/// Tokenize an HTML string, e.g. to check the structure of serialized output.
+///
+/// Errors if the input contains any HTML parse errors.
+pub fn tokenize(html: &str) -> Result<Vec<HtmlToken>, SerializeError> {
+    let sink = TokenizeSink {
+        tokens: Vec::new(),
+        err: None,
+    };
+    let mut queue = h5::BufferQueue::new();
+    queue.push_back(html.into());
+    let mut tokenizer = h5::Tokenizer::new(sink, Default::default());
+    let _ = tokenizer.feed(&mut queue);
+    tokenizer.end();
+    match tokenizer.sink.err {
+        Some(err) => Err(err),
+        None => Ok(tokenizer.sink.tokens),
+    }
+}
+
+struct TokenizeSink {
+    tokens: Vec<HtmlToken>,
+    err: Option<SerializeError>,
+}
+
+impl TokenizeSink {
+    fn push_text(&mut self, text: &str) {
+        match self.tokens.last_mut() {
+            Some(HtmlToken::Text(prev)) => prev.push_str(text),
+            _ => self.tokens.push(HtmlToken::Text(text.to_owned())),
+        }
+    }
+}
+
+impl h5::TokenSink for TokenizeSink {
+    type Handle = ();
+
+    fn process_token(
+        &mut self,
+        token: h5::Token,
+        _line_number: u64,
+    ) -> h5::TokenSinkResult<Self::Handle> {
+        match token {
+            h5::Token::DoctypeToken(doctype) => self.tokens.push(HtmlToken::Doctype(
+                doctype
+                    .name
+                    .map(|name| name.as_str_lossy().into_owned())
+                    .unwrap_or_default(),
+            )),
+            h5::Token::TagToken(tag) => self.tokens.push(match tag.kind {
+                h5::TagKind::StartTag => HtmlToken::StartTag {
+                    name: tag.name.to_string(),
+                    attrs: tag
+                        .attrs
+                        .iter()
+                        .map(|attr| {
+                            (
+                                attr.name.local.to_string(),
+                                attr.value.as_str_lossy().into_owned(),
+                            )
+                        })
+                        .collect(),
+                },
+                h5::TagKind::EndTag => HtmlToken::EndTag(tag.name.to_string()),
+            }),
+            h5::Token::CommentToken(s) => {
+                self.tokens
+                    .push(HtmlToken::Comment(s.as_str_lossy().into_owned()));
+            }
+            h5::Token::CharacterTokens(s) => self.push_text(&s.as_str_lossy()),
+            h5::Token::NullCharacterToken => {}
+            h5::Token::EOFToken => {}
+            h5::Token::ParseError(e) => {
+                self.err.get_or_insert(SerializeError::ParseError(e));
+            }
+        }
+        h5::TokenSinkResult::Continue
+    }
+}
+
 /// Errors caused when serializing HTML
 #[derive(Debug, Error)]
 pub enum SerializeError {
@@ -281,4 +692,244 @@ pub enum SerializeError {
     /// Error when parsing HTML.
     #[error("Parse error: {0}")]
     ParseError(Cow<'static, str>),
+
+    /// An attribute name which can't be written without changing the
+    /// structure of the output; see `is_valid_attr_name`.
+    #[error("Invalid attribute name {0:?}")]
+    InvalidAttrName(String),
+
+    /// A write was attempted after the writer failed; see `HtmlSerializer`.
+    #[error("Can't write after an earlier write failed")]
+    Poisoned,
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::test_util::FailAfter;
+    use super::*;
+
+    fn serialize(f: impl FnOnce(&mut HtmlSerializer<&mut Vec<u8>>)) -> String {
+        serialize_with(Default::default(), f)
+    }
+
+    fn serialize_with(
+        options: Options,
+        f: impl FnOnce(&mut HtmlSerializer<&mut Vec<u8>>),
+    ) -> String {
+        let mut out = Vec::new();
+        f(&mut HtmlSerializer::with_options(&mut out, options));
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_noscript() {
+        fn write(ser: &mut HtmlSerializer<&mut Vec<u8>>) {
+            ser.elem("noscript").unwrap();
+            ser.write_text("<p>a & b</p>").unwrap();
+            ser.elem("p").unwrap();
+            ser.write_text("<c>").unwrap();
+            ser.end_elem().unwrap();
+            ser.end_elem().unwrap();
+        }
+        assert_eq!(
+            "<noscript><p>a & b</p><p>&lt;c&gt;</p></noscript>",
+            serialize(write)
+        );
+        assert_eq!(
+            "<noscript>&lt;p&gt;a &amp; b&lt;/p&gt;<p>&lt;c&gt;</p></noscript>",
+            serialize_with(
+                Options {
+                    scripting_enabled: false,
+                    ..Default::default()
+                },
+                write
+            )
+        );
+    }
+
+    #[test]
+    fn test_indent() {
+        let html = serialize_with(
+            Options {
+                indent: Some(2),
+                ..Default::default()
+            },
+            |ser| {
+                ser.elem("body").unwrap();
+                ser.elem("ul").unwrap();
+                ser.elem("li").unwrap();
+                ser.write_text("a ").unwrap();
+                ser.elem("em").unwrap();
+                ser.write_text("b").unwrap();
+                ser.end_elem().unwrap();
+                ser.end_elem().unwrap();
+                ser.elem("li").unwrap();
+                ser.elem("p").unwrap();
+                ser.write_text("c").unwrap();
+                ser.end_elem().unwrap();
+                ser.elem("hr").unwrap();
+                ser.end_elem().unwrap();
+                ser.end_elem().unwrap();
+                ser.write_text("\n").unwrap();
+                ser.elem("pre").unwrap();
+                ser.elem("code").unwrap();
+                ser.write_text(" x\n").unwrap();
+                ser.elem("div").unwrap();
+                ser.end_elem().unwrap();
+                ser.end_elem().unwrap();
+                ser.end_elem().unwrap();
+                ser.end_elem().unwrap();
+            },
+        );
+        assert_eq!(
+            indoc::indoc!(
+                "
+                <body>
+                  <ul>
+                    <li>a <em>b</em></li>
+                    <li>
+                      <p>c</p>
+                      <hr>
+                    </li>
+                  </ul>
+                  <pre><code> x
+                <div></div></code></pre>
+                </body>"
+            )
+            .trim_start(),
+            html
+        );
+    }
+
+    #[test]
+    fn test_unmatched_end_tags() {
+        let mut ser = HtmlSerializer::new(Vec::new());
+        match ser.end_elem() {
+            Err(SerializeError::EndEmpty) => {}
+            res => panic!("Expected an unmatched end tag error, got {:?}", res),
+        }
+        ser.elem("div").unwrap();
+        match ser.write_html("<p>a</p></div>") {
+            Err(SerializeError::EndEmpty) => {}
+            res => panic!("Expected an unmatched end tag error, got {:?}", res),
+        }
+
+        let html = serialize_with(
+            Options {
+                create_missing_parent: true,
+                ..Default::default()
+            },
+            |ser| ser.write_html("<p>a</p></div></div>").unwrap(),
+        );
+        assert_eq!("<p>a</p></div></div>", html);
+    }
+
+    #[test]
+    fn test_attr_values_escaped() {
+        let html = serialize(|ser| {
+            ser.elem_attrs("a", &[("href", r#"x"><script>&amp;"#)])
+                .unwrap();
+            ser.write_text("</a><script>").unwrap();
+            ser.end_elem().unwrap();
+        });
+        assert_eq!(
+            vec![
+                HtmlToken::StartTag {
+                    name: "a".into(),
+                    attrs: vec![("href".into(), r#"x"><script>&amp;"#.into())],
+                },
+                HtmlToken::Text("</a><script>".into()),
+                HtmlToken::EndTag("a".into()),
+            ],
+            tokenize(&html).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_abort() {
+        let html = serialize(|ser| {
+            ser.elem("body").unwrap();
+            ser.elem_attrs("p", &[("class", "x")]).unwrap();
+            ser.write_text("Partial").unwrap();
+            assert_eq!(2, ser.open_element_count());
+            assert_eq!(Some("p"), ser.current_element());
+            ser.abort().unwrap();
+            assert_eq!(0, ser.open_element_count());
+            assert_eq!(None, ser.current_element());
+        });
+        assert_eq!(
+            vec![
+                HtmlToken::StartTag {
+                    name: "body".into(),
+                    attrs: vec![],
+                },
+                HtmlToken::StartTag {
+                    name: "p".into(),
+                    attrs: vec![("class".into(), "x".into())],
+                },
+                HtmlToken::Text("Partial".into()),
+                HtmlToken::Comment(TRUNCATION_COMMENT.into()),
+                HtmlToken::EndTag("p".into()),
+                HtmlToken::EndTag("body".into()),
+            ],
+            tokenize(&html).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_poisoned() {
+        fn write(ser: &mut HtmlSerializer<FailAfter>) -> Result<(), SerializeError> {
+            ser.elem("div")?;
+            ser.elem_attrs("p", &[("class", "x")])?;
+            ser.write_text("Some text")?;
+            ser.elem("br")?;
+            ser.write_html("<b>!</b>")?;
+            ser.end_elem()?;
+            ser.end_elem()
+        }
+        let html = r#"<div><p class="x">Some text<br><b>!</b></p></div>"#;
+        for limit in 0..html.len() {
+            let mut ser = HtmlSerializer::new(FailAfter::new(limit));
+            match write(&mut ser) {
+                Err(SerializeError::Io(_)) => {}
+                res => panic!("Expected an IO error, got {:?}", res),
+            }
+            assert!(ser.is_poisoned());
+            for res in [
+                ser.write_text("More"),
+                ser.elem("p"),
+                ser.end_elem(),
+                ser.write_html("<p>More</p>"),
+                ser.flush(),
+            ] {
+                match res {
+                    Err(SerializeError::Poisoned) => {}
+                    res => panic!("Expected a poisoned error, got {:?}", res),
+                }
+            }
+            ser.abort().unwrap();
+            assert_eq!(&html.as_bytes()[..limit], &ser.ser.writer.out[..]);
+        }
+
+        let mut ser = HtmlSerializer::new(FailAfter::new(html.len()));
+        write(&mut ser).unwrap();
+        assert!(!ser.is_poisoned());
+        assert_eq!(html.as_bytes(), &ser.ser.writer.out[..]);
+    }
+
+    #[test]
+    fn test_invalid_attr_names() {
+        for name in &["", "a b", "x\"", "x=y", "/", "x>", "a\u{0}"] {
+            assert!(!is_valid_attr_name(name), "{:?}", name);
+            let mut ser = HtmlSerializer::new(Vec::new());
+            match ser.elem_attrs("p", &[(name, "")]) {
+                Err(SerializeError::InvalidAttrName(_)) => {}
+                res => panic!("Expected an invalid attribute name error, got {:?}", res),
+            }
+        }
+        assert!(is_valid_attr_name("data-x"));
+        assert!(is_valid_attr_name("aria-label"));
+    }
 }
@@ -0,0 +1,82 @@
+//! Helpers for testing code which writes HTML, shared with textecca's tests.
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A writer which fails once it's been given `limit` bytes, like a full
+/// disk.
+#[derive(Debug, Default)]
+pub struct FailAfter {
+    /// The bytes written so far.
+    pub out: Vec<u8>,
+    /// The number of bytes to accept before failing.
+    pub limit: usize,
+}
+
+impl FailAfter {
+    /// A writer which fails after `limit` bytes.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            out: Vec::new(),
+            limit,
+        }
+    }
+}
+
+impl Write for FailAfter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.limit - self.out.len());
+        if len == 0 && !buf.is_empty() {
+            return Err(io::Error::other("Disk full"));
+        }
+        self.out.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A temporary directory, removed when it's dropped.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create an empty directory named after `name`. Directories are never
+    /// shared, even between tests running at the same time.
+    pub fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "textecca-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // Don't panic while a failed test is already panicking.
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
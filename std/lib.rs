@@ -1,18 +1,64 @@
 #![allow(missing_docs)] // TODO: Remove this?
 use std::error;
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
 
 use derive_command::CommandInfo;
+use nom::Slice as _;
 
 use textecca::{
-    cmd::{Command, CommandError, CommandInfo, Thunk, World},
-    doc::{self, BlockInner, DocBuilder, DocBuilderPush as _, Heading, Inline},
+    cmd::{
+        kwargs_into_meta, Command, CommandError, CommandInfo, Kwargs, Thunk, Warning, WarningKind,
+        World,
+    },
+    doc::{
+        self, BlockInner, ContainerKind, DocBuilder, DocBuilderPush as _, Heading, Inline, RefId,
+    },
     env::Environment,
-    parse::{Source, Span, Token, Tokens},
+    parse::{default_parser, Source, Span, Token, Tokens},
 };
 
 /// Adds the builtins bindings to the given `Environment`.
+///
+/// This is how to evaluate documents with the standard commands, e.g. `\sec`
+/// and `\emph`; textecca itself binds none.
+///
+/// ```
+/// use std::convert::TryInto;
+/// use std::rc::Rc;
+///
+/// use textecca::cmd::{Thunk, World};
+/// use textecca::doc::{BlockInner, Doc, DocBuilder, Inline, Style};
+/// use textecca::env::Environment;
+/// use textecca::parse::{default_parser, Source};
+///
+/// let mut env = Environment::new();
+/// textecca_stdlib::import(Rc::get_mut(&mut env).unwrap());
+/// let src = Source::new("Some \\emph{text}.".into());
+/// let world = World::new(env, &src);
+/// let toks = default_parser(&src, (&src).into()).unwrap();
+/// let mut doc = DocBuilder::new();
+/// Thunk::from(toks).force(&world, &mut doc).unwrap();
+/// let doc: Doc = doc.try_into().unwrap();
+/// assert_eq!(
+///     BlockInner::Par(vec![
+///         Inline::Text("Some ".into()),
+///         Inline::Styled {
+///             style: Style::Emph,
+///             content: vec![Inline::Text("text".into())],
+///         },
+///         Inline::Text(".".into()),
+///     ]),
+///     doc.content[0].inner
+/// );
+/// ```
+///
+/// Every command bound here must be used in the end-to-end test corpus; see
+/// `textecca/tests/corpus/README.md`.
 pub fn import(env: &mut Environment) {
     env.add_binding::<Par>();
+    env.add_binding::<Br>();
     env.add_binding::<Sec>();
     env.add_binding::<Footnote>();
     env.add_binding::<Code>();
@@ -20,15 +66,103 @@ pub fn import(env: &mut Environment) {
     env.add_binding::<Strong>();
     env.add_binding::<Math>();
     env.add_binding::<Equation>();
+    env.add_binding::<Link>();
+    env.add_binding::<Ref>();
+    env.add_binding::<Figure>();
+    env.add_binding::<Caption>();
+    env.add_binding::<Codeblock>();
+    env.add_binding::<Table>();
+    env.add_binding::<Only>();
+    env.add_binding::<Raw>();
+    env.add_binding::<Html>();
+    env.add_binding::<Tex>();
+    env.add_binding::<Todo>();
+    env.add_binding::<Abstract>();
+    env.add_binding::<DraftOnly>();
+    env.add_binding::<Set>();
+    env.add_binding::<Lang>();
+    env.add_binding::<Rtl>();
+    env.add_binding::<Ltr>();
+    env.add_binding::<Img>();
+    env.add_binding::<Include>();
 }
 
+/// The parser for arguments which are taken literally, like `\code`'s.
+///
+/// An argument's text is kept verbatim, including newlines and backslashes,
+/// except that escaped braces (`\{` and `\}`) are unescaped. Unescaped braces
+/// delimit the argument, so they must be balanced. Other escapes, including
+/// `\\`, are kept as written; `\code{\\}` is two backslashes, and the
+/// braces in `\code{\\{}}` aren't escaped, so they must be balanced.
 fn literal_parser<'i>(
     _arena: &'i Source,
     input: Span<'i>,
+) -> Result<Tokens<'i>, Box<dyn error::Error + 'i>> {
+    let mut ret = Vec::new();
+    let mut start = 0;
+    let mut chars = input.fragment().char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            continue;
+        }
+        if let Some((j, '{')) | Some((j, '}')) = chars.next() {
+            if start < i {
+                ret.push(Token::Text(input.slice(start..i)));
+            }
+            start = j;
+        }
+    }
+    if start < input.fragment().len() || ret.is_empty() {
+        ret.push(Token::Text(input.slice(start..)));
+    }
+    Ok(ret)
+}
+
+/// The parser for arguments which are kept exactly as written, including
+/// escaped braces; unlike `literal_parser`, `\{` is two characters. This is
+/// for TeX, where escaped braces mean the same thing (`\math{\{x\}}` is a
+/// set), and for arguments which are parsed further by their command.
+fn verbatim_parser<'i>(
+    _arena: &'i Source,
+    input: Span<'i>,
 ) -> Result<Tokens<'i>, Box<dyn error::Error + 'i>> {
     Ok(vec![Token::Text(input)])
 }
 
+/// Evaluate `content` and push it tagged with `meta`. Content which evaluates
+/// to a single paragraph is tagged as inlines, and anything else as blocks.
+fn push_tagged<'i>(
+    doc: &mut DocBuilder,
+    world: &World<'i>,
+    content: Thunk<'i>,
+    meta: doc::Meta,
+) -> Result<(), CommandError<'i>> {
+    let blocks = content.into_blocks(world, doc)?;
+    push_tagged_blocks(doc, blocks, meta)
+}
+
+/// Like `push_tagged`, for content which is already evaluated.
+fn push_tagged_blocks<'i>(
+    doc: &mut DocBuilder,
+    mut blocks: doc::Blocks,
+    meta: doc::Meta,
+) -> Result<(), CommandError<'i>> {
+    if blocks.len() == 1 {
+        if let BlockInner::Par(inlines) | BlockInner::Plain(inlines) = &mut blocks[0].inner {
+            doc.push(Inline::Tagged(doc::TaggedInlines {
+                content: mem::take(inlines),
+                meta,
+            }))?;
+            return Ok(());
+        }
+    }
+    doc.push(BlockInner::Tagged(doc::TaggedBlocks {
+        content: blocks,
+        meta,
+    }))?;
+    Ok(())
+}
+
 #[derive(Debug, CommandInfo)]
 pub struct Par {}
 impl<'i> Command<'i> for Par {
@@ -42,9 +176,25 @@ impl<'i> Command<'i> for Par {
     }
 }
 
+/// A hard line break.
+#[derive(Debug, CommandInfo)]
+pub struct Br {}
+impl<'i> Command<'i> for Br {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        _world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        doc.push(Inline::LineBreak)?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, CommandInfo)]
 pub struct Sec<'i> {
     title: Thunk<'i>,
+    #[textecca(kwargs)]
+    attrs: Kwargs<'i>,
 }
 impl<'i> Command<'i> for Sec<'i> {
     fn call(
@@ -52,11 +202,16 @@ impl<'i> Command<'i> for Sec<'i> {
         doc: &mut DocBuilder,
         world: &World<'i>,
     ) -> Result<(), CommandError<'i>> {
+        let attrs = kwargs_into_meta(self.attrs)?;
+        if let Some(id) = attrs.get(doc::ID_KEY) {
+            RefId::new(id.as_str())?;
+        }
+        let text = self.title.into_inlines(world, doc)?;
         doc.push(BlockInner::Heading(Heading {
             level: 1,
-            text: Default::default(),
+            text,
+            attrs,
         }))?;
-        self.title.force(world, doc)?;
         Ok(())
     }
 }
@@ -71,9 +226,9 @@ impl<'i> Command<'i> for Footnote<'i> {
         doc: &mut DocBuilder,
         world: &World<'i>,
     ) -> Result<(), CommandError<'i>> {
-        doc.push(Inline::Footnote(doc::Footnote {
-            content: self.content.into_blocks(world)?,
-        }))?;
+        doc.begin(ContainerKind::Footnote)?;
+        self.content.force(world, doc)?;
+        doc.end(ContainerKind::Footnote)?;
         Ok(())
     }
 }
@@ -82,6 +237,9 @@ impl<'i> Command<'i> for Footnote<'i> {
 #[textecca(parser = literal_parser)]
 pub struct Code<'i> {
     content: Thunk<'i>,
+    lang: Option<Thunk<'i>>,
+    #[textecca(kwargs)]
+    attrs: Kwargs<'i>,
 }
 impl<'i> Command<'i> for Code<'i> {
     fn call(
@@ -90,8 +248,9 @@ impl<'i> Command<'i> for Code<'i> {
         _world: &World<'i>,
     ) -> Result<(), CommandError<'i>> {
         doc.push(Inline::Code(doc::InlineCode {
-            language: None,
+            language: self.lang.map(|lang| lang.into_string()).transpose()?,
             content: self.content.into_string()?,
+            attrs: kwargs_into_meta(self.attrs)?,
         }))?;
         Ok(())
     }
@@ -107,9 +266,10 @@ impl<'i> Command<'i> for Emph<'i> {
         doc: &mut DocBuilder,
         world: &World<'i>,
     ) -> Result<(), CommandError<'i>> {
+        let content = self.content.into_inlines(world, doc)?;
         doc.push(Inline::Styled {
             style: doc::Style::Emph,
-            content: self.content.into_inlines(world)?,
+            content,
         })?;
         Ok(())
     }
@@ -125,18 +285,29 @@ impl<'i> Command<'i> for Strong<'i> {
         doc: &mut DocBuilder,
         world: &World<'i>,
     ) -> Result<(), CommandError<'i>> {
+        let content = self.content.into_inlines(world, doc)?;
         doc.push(Inline::Styled {
             style: doc::Style::Strong,
-            content: self.content.into_inlines(world)?,
+            content,
         })?;
         Ok(())
     }
 }
 
+/// Get math's alt text from the `alt` argument if it's given, or read the TeX
+/// aloud if possible.
+fn math_alt<'i>(tex: &str, alt: Option<Thunk<'i>>) -> Result<Option<String>, CommandError<'i>> {
+    Ok(match alt {
+        Some(alt) => Some(alt.into_string()?),
+        None => doc::tex_to_speech(tex),
+    })
+}
+
 #[derive(Debug, CommandInfo)]
-#[textecca(parser = literal_parser)]
+#[textecca(parser = verbatim_parser)]
 pub struct Math<'i> {
     content: Thunk<'i>,
+    alt: Option<Thunk<'i>>,
 }
 impl<'i> Command<'i> for Math<'i> {
     fn call(
@@ -144,17 +315,20 @@ impl<'i> Command<'i> for Math<'i> {
         doc: &mut DocBuilder,
         _world: &World<'i>,
     ) -> Result<(), CommandError<'i>> {
+        let tex = self.content.into_string()?;
         doc.push(Inline::Math(doc::InlineMath {
-            tex: self.content.into_string()?,
+            alt: math_alt(&tex, self.alt)?,
+            tex,
         }))?;
         Ok(())
     }
 }
 
 #[derive(Debug, CommandInfo)]
-#[textecca(parser = literal_parser)]
+#[textecca(parser = verbatim_parser)]
 pub struct Equation<'i> {
     content: Thunk<'i>,
+    alt: Option<Thunk<'i>>,
 }
 impl<'i> Command<'i> for Equation<'i> {
     fn call(
@@ -162,9 +336,2002 @@ impl<'i> Command<'i> for Equation<'i> {
         doc: &mut DocBuilder,
         _world: &World<'i>,
     ) -> Result<(), CommandError<'i>> {
+        let tex = self.content.into_string()?;
         doc.push(BlockInner::Math(doc::Math {
-            tex: self.content.into_string()?,
+            alt: math_alt(&tex, self.alt)?,
+            tex,
+        }))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, CommandInfo)]
+pub struct Link<'i> {
+    target: Thunk<'i>,
+    content: Thunk<'i>,
+    #[textecca(kwargs)]
+    attrs: Kwargs<'i>,
+}
+impl<'i> Command<'i> for Link<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let content = self.content.into_inlines(world, doc)?;
+        doc.push(Inline::Link(doc::Link {
+            content: Some(content),
+            label: None,
+            target: doc::LinkTarget::URL(self.target.into_string()?),
+            attrs: kwargs_into_meta(self.attrs)?,
+        }))?;
+        Ok(())
+    }
+}
+
+/// An image, e.g. `\img[alt=A diagram]{diagram.svg}`. The path is relative
+/// to the file the command is in; URLs are kept as they are.
+#[derive(Debug, CommandInfo)]
+#[textecca(parser = literal_parser)]
+pub struct Img<'i> {
+    src: Thunk<'i>,
+    alt: Option<Thunk<'i>>,
+    #[textecca(kwargs)]
+    attrs: Kwargs<'i>,
+}
+impl<'i> Command<'i> for Img<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let src = self.src.into_string()?;
+        let src = if doc::is_url(&src) {
+            src
+        } else {
+            path_to_src(&world.resolve(&src)?)
+        };
+        doc.push(Inline::Image(doc::Image {
+            src,
+            alt: self.alt.map(|alt| alt.into_string()).transpose()?,
+            attrs: kwargs_into_meta(self.attrs)?,
+        }))?;
+        Ok(())
+    }
+}
+
+/// Write a path with `/` separators, for `doc::Image::src`.
+fn path_to_src(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Evaluate another source file in place, e.g. `\include{ch1.tc}`. The path
+/// is relative to the file the command is in, and paths in the included file
+/// are relative to the included file.
+///
+/// Source positions in included files aren't tracked yet, so warnings and
+/// errors in them are reported at the wrong position.
+#[derive(Debug, CommandInfo)]
+#[textecca(parser = literal_parser)]
+pub struct Include<'i> {
+    path: Thunk<'i>,
+}
+impl<'i> Command<'i> for Include<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let path = world.resolve(&self.path.into_string()?)?;
+        let src: &'i str = world.arena.alloc(world.read_to_string(&path)?);
+        let mut toks =
+            default_parser(world.arena, Span::new(src)).map_err(CommandError::ParseError)?;
+        world.in_file(path, || {
+            if let Some(error) = world.validate_or_warn(&mut toks)?.into_iter().next() {
+                return Err(CommandError::ParseError(Box::new(error)));
+            }
+            Thunk::from(toks).force(world, doc)
+        })
+    }
+
+    fn check(&self, world: &World<'i>) -> Result<(), CommandError<'i>> {
+        let path = world.resolve(&self.path.into_string()?)?;
+        world.read_to_string(&path)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, CommandInfo)]
+pub struct Ref<'i> {
+    label: Thunk<'i>,
+}
+impl<'i> Command<'i> for Ref<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        _world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        doc.push(Inline::Link(doc::Link {
+            content: None,
+            label: None,
+            target: doc::LinkTarget::Label(RefId::new(self.label.into_string()?)?),
+            attrs: Default::default(),
+        }))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, CommandInfo)]
+pub struct Figure<'i> {
+    content: Thunk<'i>,
+    label: Option<Thunk<'i>>,
+    kind: Option<Thunk<'i>>,
+}
+impl<'i> Command<'i> for Figure<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let kind = match self.kind {
+            Some(kind) => doc::FigureKind::from_name(&kind.into_string()?),
+            None => doc::FigureKind::Figure,
+        };
+        let label = match self.label {
+            Some(label) => Some(RefId::new(label.into_string()?)?),
+            None => None,
+        };
+        doc.begin_figure(kind, label)?;
+        self.content.force(world, doc)?;
+        doc.end(ContainerKind::Figure)?;
+        Ok(())
+    }
+}
+
+/// Sets the caption of the enclosing `\figure`.
+#[derive(Debug, CommandInfo)]
+pub struct Caption<'i> {
+    content: Thunk<'i>,
+}
+impl<'i> Command<'i> for Caption<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let caption = self.content.into_inlines(world, doc)?;
+        doc.set_caption(caption)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, CommandInfo)]
+#[textecca(parser = literal_parser)]
+pub struct Codeblock<'i> {
+    content: Thunk<'i>,
+    lang: Option<Thunk<'i>>,
+    linenos: Option<Thunk<'i>>,
+    hl: Option<Thunk<'i>>,
+    #[textecca(kwargs)]
+    attrs: Kwargs<'i>,
+}
+impl<'i> Command<'i> for Codeblock<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        _world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let language = match self.lang {
+            Some(lang) => lang.into_string()?,
+            None => "plain".to_owned(),
+        };
+        let line_numbers = match self.linenos {
+            Some(linenos) => {
+                let start = linenos.into_string()?;
+                Some(doc::LineNumbers {
+                    start: if start.is_empty() {
+                        1
+                    } else {
+                        start.trim().parse().map_err(|_| {
+                            CommandError::Type(format!("Invalid starting line number {:?}", start))
+                        })?
+                    },
+                })
+            }
+            None => None,
+        };
+        let highlight_lines = match self.hl {
+            Some(hl) => parse_line_ranges(&hl.into_string()?)?,
+            None => Vec::new(),
+        };
+        doc.push(BlockInner::Code(doc::Code {
+            language,
+            line_numbers,
+            lines: split_code_lines(&self.content.into_string()?),
+            highlight_lines,
+            attrs: kwargs_into_meta(self.attrs)?,
+        }))?;
+        Ok(())
+    }
+}
+
+/// A table, e.g. `\table[cols=l:30% c r]{a & b & c}`. Rows are separated by
+/// line breaks and cells by `&`, except inside commands' arguments; `\&` is a
+/// literal `&`.
+///
+/// Each column in `cols` is an alignment (`l`, `c`, `r`, or `j`), optionally
+/// followed by a colon and a width, e.g. `5em` or `30%`.
+#[derive(Debug, CommandInfo)]
+#[textecca(parser = verbatim_parser)]
+pub struct Table<'i> {
+    content: Thunk<'i>,
+    cols: Option<Thunk<'i>>,
+}
+impl<'i> Command<'i> for Table<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let columns = match self.cols {
+            Some(cols) => parse_table_columns(&cols.into_string()?)?,
+            None => Vec::new(),
+        };
+        let mut cells = Vec::new();
+        if let Some(content) = self.content.span() {
+            for row in split_top_level(content, '\n') {
+                if row.fragment().trim().is_empty() {
+                    continue;
+                }
+                let row_cells = split_top_level(row, '&')
+                    .into_iter()
+                    .map(|cell| table_cell(world, doc, cell))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if !columns.is_empty() && row_cells.len() != columns.len() {
+                    world.report(
+                        Warning::new(
+                            WarningKind::TableColumns,
+                            format!(
+                                "Table row has {} cells, but the table has {} columns",
+                                row_cells.len(),
+                                columns.len()
+                            ),
+                        )
+                        .at(Some(world.arena.position(&row))),
+                    )?;
+                }
+                cells.push(row_cells);
+            }
+        }
+        doc.push(BlockInner::Table(doc::Table { columns, cells }))?;
+        Ok(())
+    }
+}
+
+/// Includes content only in the given output format, e.g. `\only{html}{...}`.
+#[derive(Debug, CommandInfo)]
+pub struct Only<'i> {
+    format: Thunk<'i>,
+    content: Thunk<'i>,
+}
+impl<'i> Command<'i> for Only<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let mut meta = doc::Meta::new();
+        meta.insert(doc::ONLY_FORMAT_KEY.to_owned(), self.format.into_string()?);
+        push_tagged(doc, world, self.content, meta)
+    }
+}
+
+/// Content written verbatim in the given output format, and skipped by
+/// others, e.g. `\raw{html}{<kbd>Ctrl</kbd>}`.
+#[derive(Debug, CommandInfo)]
+#[textecca(parser = literal_parser)]
+pub struct Raw<'i> {
+    format: Thunk<'i>,
+    content: Thunk<'i>,
+}
+impl<'i> Command<'i> for Raw<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        _world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        doc.push(Inline::Raw(doc::RawInline {
+            format: self.format.into_string()?,
+            content: self.content.into_string()?,
         }))?;
         Ok(())
     }
 }
+
+/// Raw HTML; short for `\raw{html}{...}`.
+#[derive(Debug, CommandInfo)]
+#[textecca(parser = literal_parser)]
+pub struct Html<'i> {
+    content: Thunk<'i>,
+}
+impl<'i> Command<'i> for Html<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        _world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        doc.push(Inline::Raw(doc::RawInline {
+            format: "html".into(),
+            content: self.content.into_string()?,
+        }))?;
+        Ok(())
+    }
+}
+
+/// Raw LaTeX; short for `\raw{latex}{...}`.
+#[derive(Debug, CommandInfo)]
+#[textecca(parser = literal_parser)]
+pub struct Tex<'i> {
+    content: Thunk<'i>,
+}
+impl<'i> Command<'i> for Tex<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        _world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        doc.push(Inline::Raw(doc::RawInline {
+            format: "latex".into(),
+            content: self.content.into_string()?,
+        }))?;
+        Ok(())
+    }
+}
+
+/// A note to the author, shown highlighted in draft builds and reported as a
+/// warning otherwise. Notes are also collected in `doc::TODO_CHANNEL`.
+#[derive(Debug, CommandInfo)]
+pub struct Todo<'i> {
+    content: Thunk<'i>,
+}
+impl<'i> Command<'i> for Todo<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let message = match self.content.into_string() {
+            Ok(text) => format!("TODO: {}", text.trim()),
+            Err(_) => "TODO".to_owned(),
+        };
+        let position = self.content.span().map(|span| world.arena.position(&span));
+        world.warn(Warning::new(WarningKind::Todo, message).at(position));
+
+        let blocks = self.content.into_blocks(world, doc)?;
+        doc.collect(doc::TODO_CHANNEL, blocks.clone());
+        let mut meta = doc::Meta::new();
+        meta.insert(doc::TODO_KEY.to_owned(), String::new());
+        push_tagged_blocks(doc, blocks, meta)
+    }
+}
+
+/// The document's abstract, which is collected in `doc::ABSTRACT_CHANNEL`
+/// rather than written where it's called.
+#[derive(Debug, CommandInfo)]
+pub struct Abstract<'i> {
+    content: Thunk<'i>,
+}
+impl<'i> Command<'i> for Abstract<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let blocks = self.content.into_blocks(world, doc)?;
+        doc.collect(doc::ABSTRACT_CHANNEL, blocks);
+        Ok(())
+    }
+}
+
+/// Includes content only in draft builds; see `doc::is_draft`.
+#[derive(Debug, CommandInfo)]
+#[textecca(name = "draftonly")]
+pub struct DraftOnly<'i> {
+    content: Thunk<'i>,
+}
+impl<'i> Command<'i> for DraftOnly<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let mut meta = doc::Meta::new();
+        meta.insert(doc::DRAFT_ONLY_KEY.to_owned(), String::new());
+        push_tagged(doc, world, self.content, meta)
+    }
+}
+
+/// Marks content as being in another language, e.g. `\\lang{de}{...}`.
+#[derive(Debug, CommandInfo)]
+pub struct Lang<'i> {
+    lang: Thunk<'i>,
+    content: Thunk<'i>,
+}
+impl<'i> Command<'i> for Lang<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let mut meta = doc::Meta::new();
+        meta.insert(
+            doc::LANG_KEY.to_owned(),
+            self.lang.into_string()?.trim().to_owned(),
+        );
+        push_tagged(doc, world, self.content, meta)
+    }
+}
+
+/// Marks content as written right-to-left, e.g. in Arabic or Hebrew.
+#[derive(Debug, CommandInfo)]
+pub struct Rtl<'i> {
+    content: Thunk<'i>,
+}
+impl<'i> Command<'i> for Rtl<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let mut meta = doc::Meta::new();
+        meta.insert(doc::DIR_KEY.to_owned(), "rtl".to_owned());
+        push_tagged(doc, world, self.content, meta)
+    }
+}
+
+/// Marks content as written left-to-right, e.g. English inside `\rtl`.
+#[derive(Debug, CommandInfo)]
+pub struct Ltr<'i> {
+    content: Thunk<'i>,
+}
+impl<'i> Command<'i> for Ltr<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let mut meta = doc::Meta::new();
+        meta.insert(doc::DIR_KEY.to_owned(), "ltr".to_owned());
+        push_tagged(doc, world, self.content, meta)
+    }
+}
+
+/// Sets a document metadata value, e.g. `\set{draft}{true}`. The value may
+/// contain commands, and is flattened to plain text.
+#[derive(Debug, CommandInfo)]
+pub struct Set<'i> {
+    key: Thunk<'i>,
+    value: Thunk<'i>,
+}
+impl<'i> Command<'i> for Set<'i> {
+    fn call(
+        self: Box<Self>,
+        doc: &mut DocBuilder,
+        world: &World<'i>,
+    ) -> Result<(), CommandError<'i>> {
+        let value = self.value.force_to_string(world, doc)?;
+        doc.set_meta(
+            self.key.into_string()?.trim().to_owned(),
+            value.trim().to_owned(),
+        );
+        Ok(())
+    }
+}
+
+/// Split a code listing into lines, dropping the line break directly after the
+/// opening brace and any whitespace-only last line before the closing brace.
+fn split_code_lines(code: &str) -> Vec<doc::Inlines> {
+    let code = code
+        .strip_prefix("\r\n")
+        .or_else(|| code.strip_prefix('\n'))
+        .unwrap_or(code);
+    let mut lines: Vec<&str> = code
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+        .collect();
+    if lines.len() > 1 && lines.last().map(|l| l.trim().is_empty()) == Some(true) {
+        lines.pop();
+    }
+    lines
+        .into_iter()
+        .map(|line| {
+            if line.is_empty() {
+                Vec::new()
+            } else {
+                vec![Inline::Text(line.to_owned())]
+            }
+        })
+        .collect()
+}
+
+/// Parse a comma-separated list of 1-indexed line numbers and inclusive ranges,
+/// e.g. `3-4, 7`.
+fn parse_line_ranges<'i>(ranges: &str) -> Result<Vec<Range<u32>>, CommandError<'i>> {
+    let bad_range = |range: &str| CommandError::Type(format!("Invalid line range {:?}", range));
+    ranges
+        .split(',')
+        .map(str::trim)
+        .filter(|range| !range.is_empty())
+        .map(|range| {
+            let mut bounds = range.splitn(2, '-').map(|n| n.trim().parse::<u32>());
+            let start = bounds
+                .next()
+                .and_then(Result::ok)
+                .ok_or_else(|| bad_range(range))?;
+            let end = match bounds.next() {
+                Some(end) => end.map_err(|_| bad_range(range))?,
+                None => start,
+            };
+            if end < start {
+                return Err(bad_range(range));
+            }
+            Ok(start..end + 1)
+        })
+        .collect()
+}
+
+/// Parse and evaluate a single table cell.
+fn table_cell<'i>(
+    world: &World<'i>,
+    doc: &mut DocBuilder,
+    cell: Span<'i>,
+) -> Result<doc::TableCell, CommandError<'i>> {
+    let text = cell.fragment();
+    let start = text.len() - text.trim_start().len();
+    let mut cell = cell.slice(start..start + text.trim().len());
+    let unescaped = unescape_top_level(cell.fragment(), '&');
+    if unescaped != *cell.fragment() {
+        cell = world.arena.alloc_span(unescaped, cell);
+    }
+    let tokens = default_parser(world.arena, cell).map_err(CommandError::ParseError)?;
+    let mut content = Thunk::Lazy(tokens).into_blocks(world, doc)?;
+    if content.len() == 1 {
+        if let BlockInner::Par(inlines) = &mut content[0].inner {
+            content[0].inner = BlockInner::Plain(mem::take(inlines));
+        }
+    }
+    Ok(doc::TableCell {
+        content,
+        ..Default::default()
+    })
+}
+
+/// Split `span` on each `sep` which isn't inside braces or escaped with a
+/// backslash. Escaped braces (`\{` and `\}`) aren't counted.
+fn split_top_level(span: Span, sep: char) -> Vec<Span> {
+    let mut ret = Vec::new();
+    let mut depth = 0_usize;
+    let mut start = 0;
+    let mut chars = span.fragment().char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            c if c == sep && depth == 0 => {
+                ret.push(span.slice(start..i));
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    ret.push(span.slice(start..));
+    ret
+}
+
+/// Remove the backslash from each `\` followed by `c` which isn't inside
+/// braces; see `split_top_level`.
+fn unescape_top_level(text: &str, c: char) -> String {
+    let mut ret = String::with_capacity(text.len());
+    let mut depth = 0_usize;
+    let mut chars = text.chars();
+    while let Some(next) = chars.next() {
+        match next {
+            '\\' => {
+                match chars.next() {
+                    Some(escaped) if escaped == c && depth == 0 => ret.push(escaped),
+                    Some(escaped) => ret.extend(&['\\', escaped]),
+                    None => ret.push('\\'),
+                }
+                continue;
+            }
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        ret.push(next);
+    }
+    ret
+}
+
+/// Parse a table's column specification, e.g. `l:30% c r:5em`.
+fn parse_table_columns<'i>(spec: &str) -> Result<Vec<doc::TableColumn>, CommandError<'i>> {
+    spec.split_whitespace()
+        .map(|column| {
+            let mut parts = column.splitn(2, ':');
+            let alignment = match parts.next() {
+                Some("l") => doc::Alignment::Left,
+                Some("c") => doc::Alignment::Center,
+                Some("r") => doc::Alignment::Right,
+                Some("j") => doc::Alignment::Justify,
+                _ => {
+                    return Err(CommandError::Type(format!(
+                        "Invalid table column {:?}; expected one of l, c, r, or j",
+                        column
+                    )))
+                }
+            };
+            let width = parts
+                .next()
+                .map(|width| width.parse())
+                .transpose()
+                .map_err(|e| CommandError::Type(format!("{}", e)))?;
+            Ok(doc::TableColumn { alignment, width })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use std::cell::RefCell;
+    use std::convert::TryInto;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use textecca::{
+        cmd::{MemoryResolver, ResourceResolver as _, Strictness, TraceEvent},
+        diagnostic::ErrorCode,
+        doc::Doc,
+        parse::{default_parser, FormatStyle, SourcePosition},
+        ser::{HtmlOptions, HtmlSerializer, InitSerializer as _, Serializer as _},
+    };
+
+    use super::*;
+
+    /// Evaluate the given source with the builtins bound.
+    fn eval(src: &Source) -> (Doc, Vec<Warning>) {
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        let world = World::new(env, src);
+        let toks = default_parser(src, src.into()).unwrap();
+        let mut doc = DocBuilder::new();
+        Thunk::from(toks).force(&world, &mut doc).unwrap();
+        (doc.try_into().unwrap(), world.take_warnings())
+    }
+
+    fn to_html(doc: Doc) -> String {
+        let mut out = Vec::new();
+        HtmlSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&doc)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn lines(lines: &[&str]) -> Vec<doc::Inlines> {
+        lines
+            .iter()
+            .map(|line| {
+                if line.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![Inline::Text((*line).to_owned())]
+                }
+            })
+            .collect()
+    }
+
+    /// Evaluate source containing a single inline, e.g. `\\code{...}`, or
+    /// give the error.
+    fn eval_inline(src: &str) -> Result<Inline, String> {
+        let src = Source::new(src.into());
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        let world = World::new(env, &src);
+        let toks = default_parser(&src, (&src).into()).map_err(|err| err.to_string())?;
+        let mut inlines = Thunk::from(toks)
+            .into_inlines(&world, &mut DocBuilder::new())
+            .map_err(|err| err.to_string())?;
+        assert_eq!(1, inlines.len());
+        Ok(inlines.remove(0))
+    }
+
+    #[test]
+    fn test_literal_args() {
+        let code = |src: &str| match eval_inline(src) {
+            Ok(Inline::Code(code)) => Ok(code.content),
+            Ok(inline) => panic!("Expected code, got {:?}", inline),
+            Err(err) => Err(err),
+        };
+        let cases = [
+            (r"\code{x}", r"x"),
+            (r"\code{}", r""),
+            (r"\code{\par}", r"\par"),
+            (r"\code{\emph{x}}", r"\emph{x}"),
+            (r"\code{{x}}", r"{x}"),
+            (r"\code{a\{b}", r"a{b"),
+            (r"\code{a\}b}", r"a}b"),
+            (r"\code{\{\}}", r"{}"),
+            (r"\code{\}\{}", r"}{"),
+            (r"\code{\\}", r"\\"),
+            (r"\code{a\\}", r"a\\"),
+            (r"\code{\\{x}}", r"\\{x}"),
+            (r"\code{\n\t}", r"\n\t"),
+            (r"\code{$x$ & % #}", r"$x$ & % #"),
+            ("\\code{a\n\n  b\n}", "a\n\n  b\n"),
+        ];
+        for (src, expected) in cases.iter() {
+            assert_eq!(Ok((*expected).to_owned()), code(src), "{}", src);
+        }
+        for src in [r"\code{a{b}", r"\code{a\}", r"\code{\{"].iter() {
+            assert!(code(src).is_err(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_raw() {
+        let raw = |format: &str, content: &str| {
+            Inline::Raw(doc::RawInline {
+                format: format.into(),
+                content: content.into(),
+            })
+        };
+        for (src, expected) in [
+            (r"\html{<kbd>Ctrl</kbd>}", raw("html", "<kbd>Ctrl</kbd>")),
+            (r"\tex{\LaTeX{}\ \&}", raw("latex", r"\LaTeX{}\ \&")),
+            (r"\raw{html}{a  \{b\}}", raw("html", "a  {b}")),
+            (r"\raw{epub}{<br/>}", raw("epub", "<br/>")),
+        ] {
+            assert_eq!(Ok(expected), eval_inline(src), "{}", src);
+        }
+
+        let (doc, _) = eval(&Source::new(
+            "Press \\html{<kbd>Ctrl</kbd>} to copy.\\tex{\\clearpage}".into(),
+        ));
+        let mut out = Vec::new();
+        let mut ser = HtmlSerializer::new(&mut out).unwrap();
+        ser.write_doc(&doc).unwrap();
+        assert_eq!(Vec::<Warning>::new(), ser.take_warnings());
+        drop(ser);
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains("<p>Press <kbd>Ctrl</kbd> to copy.</p>"));
+        assert!(!html.contains("clearpage"));
+    }
+
+    #[test]
+    fn test_tex_args() {
+        let tex = |src: &str| match eval_inline(src) {
+            Ok(Inline::Math(math)) => Ok(math.tex),
+            Ok(inline) => panic!("Expected math, got {:?}", inline),
+            Err(err) => Err(err),
+        };
+        let cases = [
+            (r"\math{x^2}", r"x^2"),
+            (r"\math{\frac{1}{2}}", r"\frac{1}{2}"),
+            (r"\math{\{x\}}", r"\{x\}"),
+            (r"\math{\{ x \mid x > 0 \}}", r"\{ x \mid x > 0 \}"),
+            (r"\math{a \\ b}", r"a \\ b"),
+            (r"\math{\left\{ x \right.}", r"\left\{ x \right."),
+            ("\\math{a\n+ b}", "a\n+ b"),
+        ];
+        for (src, expected) in cases.iter() {
+            assert_eq!(Ok((*expected).to_owned()), tex(src), "{}", src);
+        }
+        assert!(tex(r"\math{\frac{1}{2}").is_err());
+    }
+
+    /// Evaluate `main.tc` from a project with the given files.
+    fn eval_project(files: &[(&str, &str)]) -> Result<Doc, String> {
+        let mut resolver = MemoryResolver::new();
+        for (path, content) in files {
+            resolver.insert(*path, *content);
+        }
+        let src = Source::new(resolver.read_to_string(Path::new("main.tc")).unwrap())
+            .with_path("main.tc");
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        let world = World::new(env, &src).with_resolver(Rc::new(resolver));
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        let mut doc = DocBuilder::new();
+        Thunk::from(toks)
+            .force(&world, &mut doc)
+            .map_err(|err| err.innermost().to_string())?;
+        assert_eq!(PathBuf::from("main.tc"), world.current_file());
+        Ok(doc.try_into().unwrap())
+    }
+
+    #[test]
+    fn test_include() {
+        let files = [
+            (
+                "main.tc",
+                "\\img{cover.png} \\include{ch/1.tc} \\img{https://example.com/x.png}",
+            ),
+            (
+                "ch/1.tc",
+                "\\img[alt=A figure]{fig.svg} \\include{../shared/logo.tc}",
+            ),
+            ("shared/logo.tc", "\\img{/img/logo.png}"),
+        ];
+        let doc = eval_project(&files).unwrap();
+        let mut images = Vec::new();
+        doc::walk_mut(&mut doc.content.clone(), &mut ImageCollector(&mut images));
+        assert_eq!(
+            vec![
+                "cover.png",
+                "ch/fig.svg",
+                "img/logo.png",
+                "https://example.com/x.png"
+            ],
+            images
+        );
+
+        let mut out = Vec::new();
+        HtmlSerializer::with_options(
+            &mut out,
+            HtmlOptions {
+                output_dir: Some("out/html".into()),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .write_doc(&doc)
+        .unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains(r#"<img src="../../ch/fig.svg" alt="A figure">"#));
+        assert!(html.contains(r#"<img src="https://example.com/x.png">"#));
+    }
+
+    #[test]
+    fn test_include_errors() {
+        assert_eq!(
+            Err(r#""../../secret.png" is outside the project root"#.to_owned()),
+            eval_project(&[
+                ("main.tc", "\\include{ch/1.tc}"),
+                ("ch/1.tc", "\\img{../../secret.png}"),
+            ])
+            .map(|_| ())
+        );
+        assert_eq!(
+            Err("ch/1.tc includes itself".to_owned()),
+            eval_project(&[
+                ("main.tc", "\\include{ch/1.tc}"),
+                ("ch/1.tc", "\\include{2.tc}"),
+                ("ch/2.tc", "\\include{1.tc}"),
+            ])
+            .map(|_| ())
+        );
+        assert_eq!(
+            Err("missing.tc not found".to_owned()),
+            eval_project(&[("main.tc", "\\include{missing.tc}")]).map(|_| ())
+        );
+    }
+
+    struct ImageCollector<'a>(&'a mut Vec<String>);
+
+    impl doc::VisitMut for ImageCollector<'_> {
+        fn visit_inline(&mut self, inline: &mut Inline) {
+            if let Inline::Image(image) = inline {
+                self.0.push(image.src.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_code_lines() {
+        assert_eq!(
+            lines(&["fn main() {", "", "    x();", "}"]),
+            split_code_lines("\nfn main() {\n\n    x();\n}\n")
+        );
+        assert_eq!(
+            lines(&["a", "", "b"]),
+            split_code_lines("\r\na\r\n\r\nb\r\n")
+        );
+        assert_eq!(lines(&["inline"]), split_code_lines("inline"));
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let src = Source::new("One\r\rtwo\u{2029}three\r\n\r\nfour\u{2028}five.".into());
+        let html = to_html(eval(&src).0);
+        assert!(html.contains("<p>One</p>\n<p>two</p>\n<p>three</p>\n<p>four five.</p>"));
+    }
+
+    #[test]
+    fn test_trailing_newline() {
+        // The newline ending a paragraph's last line isn't a space in it.
+        let src = Source::new("a\nb\n\nc d e\n".into());
+        let (doc, _) = eval(&src);
+        let pars: Vec<_> = doc.content.iter().map(|block| &block.inner).collect();
+        assert_eq!(
+            vec![
+                &BlockInner::Par(vec![
+                    Inline::Text("a".into()),
+                    Inline::Text(" ".into()),
+                    Inline::Text("b".into()),
+                ]),
+                &BlockInner::Par(vec![Inline::Text("c d e".into())]),
+            ],
+            pars
+        );
+        assert!(to_html(doc).contains("<p>a b</p>\n<p>c d e</p>"));
+    }
+
+    #[test]
+    fn test_text_after_figure() {
+        // Text after a figure starts a new paragraph; it isn't caption text.
+        let src = Source::new(
+            "Intro.\n\\figure{\\img[alt=x]{a.png}\\caption{A plot.}}\nAs the figure shows, things.\n"
+                .into(),
+        );
+        let (doc, _) = eval(&src);
+        match &doc.content[1].inner {
+            BlockInner::Figure(figure) => {
+                assert_eq!(vec![Inline::Text("A plot.".into())], figure.caption)
+            }
+            other => panic!("Expected a figure, got {:?}", other),
+        }
+        let html = to_html(doc);
+        assert!(html.contains("<figcaption>A plot.</figcaption></figure>"));
+        assert!(html.contains("As the figure shows, things.</p>"));
+    }
+
+    #[test]
+    fn test_parse_line_ranges() {
+        assert_eq!(vec![3..5], parse_line_ranges("3-4").unwrap());
+        assert_eq!(vec![1..2, 7..10], parse_line_ranges("1, 7-9").unwrap());
+        assert!(parse_line_ranges("4-3").is_err());
+        assert!(parse_line_ranges("x").is_err());
+    }
+
+    #[test]
+    fn test_draft_mode() {
+        let src = Source::new("Some text.\\todo{Cite this.}\n\n\\draftonly{Draft notes.}".into());
+        let (doc, warnings) = eval(&src);
+        assert_eq!(
+            vec![Warning {
+                kind: WarningKind::Todo,
+                message: "TODO: Cite this.".into(),
+                position: Some(SourcePosition {
+                    line: 1,
+                    column: 17
+                }),
+            }],
+            warnings
+        );
+
+        let html = to_html(doc.clone());
+        assert!(!html.contains("Cite this."));
+        assert!(!html.contains("Draft notes."));
+
+        let mut draft = doc;
+        draft.meta.insert(doc::DRAFT_KEY.into(), "true".into());
+        let html = to_html(draft);
+        assert!(html.contains(r#"Some text.<span class="todo">Cite this.</span>"#));
+        assert!(html.contains("Draft notes."));
+    }
+
+    #[test]
+    fn test_collections() {
+        let src =
+            Source::new("\\abstract{A summary.}Text.\\todo{First.} More.\\todo{Second.}".into());
+        let (doc, _) = eval(&src);
+        let texts = |channel: &str| -> Vec<String> {
+            doc.collections[channel]
+                .iter()
+                .map(|blocks| match &blocks[..] {
+                    [doc::Block {
+                        inner: BlockInner::Par(inlines) | BlockInner::Plain(inlines),
+                        ..
+                    }] => doc::inlines_to_plain_text(inlines).unwrap(),
+                    blocks => panic!("Expected a paragraph, got {:?}", blocks),
+                })
+                .collect()
+        };
+        assert_eq!(vec!["A summary."], texts(doc::ABSTRACT_CHANNEL));
+        assert_eq!(vec!["First.", "Second."], texts(doc::TODO_CHANNEL));
+        let html = to_html(doc);
+        assert!(html.contains("<section class=\"abstract\">\n<p>A summary.</p></section>"));
+        assert!(html.contains("<p>Text. More.</p>"));
+    }
+
+    #[test]
+    fn test_nested_collections() {
+        // Content collected by arguments evaluated on their own, like these,
+        // still belongs to the document.
+        let src =
+            Source::new("Text \\emph{in \\abstract{A summary.}}.\\sec{\\todo{Title.}}".into());
+        let (doc, _) = eval(&src);
+        assert_eq!(1, doc.collections[doc::ABSTRACT_CHANNEL].len());
+        assert_eq!(1, doc.collections[doc::TODO_CHANNEL].len());
+        let html = to_html(doc);
+        assert!(html.contains("<section class=\"abstract\">\n<p>A summary.</p></section>"));
+        assert!(html.contains("<p>Text <em>in </em>.</p>"));
+    }
+
+    /// `\ifelse{cond}{then}{else}` evaluates `then` if `cond` is `true`.
+    #[derive(Debug, CommandInfo)]
+    #[textecca(name = "ifelse")]
+    struct IfElse<'i> {
+        cond: Thunk<'i>,
+        then: Thunk<'i>,
+        else_: Thunk<'i>,
+    }
+    impl<'i> Command<'i> for IfElse<'i> {
+        fn call(
+            self: Box<Self>,
+            doc: &mut DocBuilder,
+            world: &World<'i>,
+        ) -> Result<(), CommandError<'i>> {
+            if self.cond.into_string()? == "true" {
+                self.then.force(world, doc)
+            } else {
+                self.else_.force(world, doc)
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let src = Source::new("\\ifelse{true}{Fine.}{\\emph{Bad \\ escape.}}".into());
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        Rc::get_mut(&mut env).unwrap().add_binding::<IfElse>();
+
+        // Lazily, the unused branch is never parsed.
+        let world = World::new(env.clone(), &src);
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        let mut doc = DocBuilder::new();
+        Thunk::from(toks).force(&world, &mut doc).unwrap();
+        let doc: doc::Doc = doc.try_into().unwrap();
+        assert!(to_html(doc).contains("Fine."));
+
+        // Validation parses it anyways.
+        let world = World::new(env, &src);
+        let mut toks = default_parser(&src, (&src).into()).unwrap();
+        let errors = world.validate(&mut toks);
+        assert_eq!(
+            vec![SourcePosition {
+                line: 1,
+                column: 28
+            }],
+            errors.iter().map(|e| e.position).collect::<Vec<_>>()
+        );
+    }
+
+    /// `\\noted[note=...]{content}` evaluates `content`, and discards `note`.
+    #[derive(Debug, CommandInfo)]
+    struct Noted<'i> {
+        content: Thunk<'i>,
+        note: Option<Thunk<'i>>,
+    }
+    impl<'i> Command<'i> for Noted<'i> {
+        fn call(
+            self: Box<Self>,
+            doc: &mut DocBuilder,
+            world: &World<'i>,
+        ) -> Result<(), CommandError<'i>> {
+            drop(self.note);
+            self.content.force(world, doc)
+        }
+    }
+
+    #[test]
+    fn test_invalid_argument() {
+        let src = Source::new(r#"\noted[note="Bad \ escape."]{Fine.}"#.into());
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        Rc::get_mut(&mut env).unwrap().add_binding::<Noted>();
+        let toks = default_parser(&src, (&src).into()).unwrap();
+
+        // The unused argument is only a warning.
+        let world = World::new(env.clone(), &src);
+        let mut doc = DocBuilder::new();
+        Thunk::from(toks.clone()).force(&world, &mut doc).unwrap();
+        assert!(to_html(doc.try_into().unwrap()).contains("Fine."));
+        let warnings = world.take_warnings();
+        assert_eq!(1, warnings.len());
+        assert_eq!(WarningKind::InvalidArgument, warnings[0].kind);
+        assert_eq!(
+            Some(SourcePosition {
+                line: 1,
+                column: 14
+            }),
+            warnings[0].position
+        );
+        assert_eq!(
+            ErrorCode::InvalidArgument,
+            warnings[0].diagnostic(&src).code
+        );
+
+        // Validating the document first warns about it once, not twice.
+        let world = World::new(env.clone(), &src);
+        let mut validated = toks.clone();
+        assert!(world.validate_or_warn(&mut validated).unwrap().is_empty());
+        Thunk::from(validated)
+            .force(&world, &mut DocBuilder::new())
+            .unwrap();
+        assert_eq!(warnings, world.take_warnings());
+
+        // Strictly, it's an error like before.
+        let world = World::new(env.clone(), &src).with_strictness(Strictness::Strict);
+        let mut validated = toks.clone();
+        assert_eq!(1, world.validate_or_warn(&mut validated).unwrap().len());
+        let res = Thunk::from(toks).force(&world, &mut DocBuilder::new());
+        match res.as_ref().map_err(CommandError::innermost) {
+            Err(CommandError::ParseError(_)) => {}
+            _ => panic!("Expected a parse error, got {:?}", res),
+        }
+
+        // Using the argument gives the parse error, pointing at the argument.
+        let src = Source::new("\\noted{Bad \\ escape.}".into());
+        let world = World::new(env, &src);
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        let err = Thunk::from(toks)
+            .force(&world, &mut DocBuilder::new())
+            .unwrap_err();
+        match err.innermost() {
+            CommandError::InvalidArgument(argument) => {
+                assert_eq!(7..20, argument.span);
+            }
+            err => panic!("Expected an invalid argument error, got {:?}", err),
+        }
+        let diagnostic = err.diagnostic(&src);
+        assert_eq!(ErrorCode::Syntax, diagnostic.code);
+        assert_eq!(
+            vec!["In \\noted"],
+            diagnostic
+                .related
+                .iter()
+                .map(|related| related.message.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, world.take_warnings().len());
+    }
+
+    /// `\\literally{content}` evaluates `content` with the arguments of the
+    /// commands in it taken literally.
+    #[derive(Debug, CommandInfo)]
+    #[textecca(parser_override = literal_parser)]
+    struct Literally<'i> {
+        content: Thunk<'i>,
+    }
+    impl<'i> Command<'i> for Literally<'i> {
+        fn call(
+            self: Box<Self>,
+            doc: &mut DocBuilder,
+            world: &World<'i>,
+        ) -> Result<(), CommandError<'i>> {
+            assert!(world.active_parser().is_some());
+            self.content.force(world, doc)
+        }
+    }
+
+    #[test]
+    fn test_parser_override() {
+        let src =
+            Source::new("\\literally{\\emph{\\strong{x}} \\code{\\{}} \\emph{\\strong{y}}".into());
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        Rc::get_mut(&mut env).unwrap().add_binding::<Literally>();
+        let emph = |content| Inline::Styled {
+            style: doc::Style::Emph,
+            content: vec![content],
+        };
+        let expected = vec![
+            emph(Inline::Text("\\strong{x}".into())),
+            Inline::Text(" ".into()),
+            Inline::Code(doc::InlineCode {
+                content: "{".into(),
+                ..Default::default()
+            }),
+            Inline::Text(" ".into()),
+            emph(Inline::Styled {
+                style: doc::Style::Strong,
+                content: vec![Inline::Text("y".into())],
+            }),
+        ];
+
+        for validate in [false, true].iter() {
+            let world = World::new(env.clone(), &src);
+            let mut toks = default_parser(&src, (&src).into()).unwrap();
+            if *validate {
+                assert!(world.validate(&mut toks).is_empty());
+            }
+            let inlines = Thunk::from(toks)
+                .into_inlines(&world, &mut DocBuilder::new())
+                .unwrap();
+            assert_eq!(expected, inlines, "validate = {}", validate);
+            assert!(world.active_parser().is_none());
+        }
+    }
+
+    /// `\expensive{content}` panics if it's called.
+    #[derive(Debug, CommandInfo)]
+    struct Expensive<'i> {
+        content: Thunk<'i>,
+    }
+    impl<'i> Command<'i> for Expensive<'i> {
+        fn call(
+            self: Box<Self>,
+            _doc: &mut DocBuilder,
+            _world: &World<'i>,
+        ) -> Result<(), CommandError<'i>> {
+            panic!("\\expensive was called with {:?}", self.content);
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let src = Source::new(
+            concat!(
+                "\\nope{a} \\emph{x}{y}\n",
+                "\\emph[bogus=1]{x} \\expensive{\\emph{\\nope{b}}}\n",
+                "\\include{missing.tc} \\include{ch.tc} \\code{\\nope{c}}",
+            )
+            .into(),
+        )
+        .with_path("main.tc");
+        let mut resolver = MemoryResolver::new();
+        resolver.insert("ch.tc", "Fine.");
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        Rc::get_mut(&mut env).unwrap().add_binding::<Expensive>();
+        let world = World::new(env, &src).with_resolver(Rc::new(resolver));
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        assert_eq!(
+            vec![
+                "1:2: Command nope not defined in current environment",
+                "1:11: Invalid arguments: Too many args",
+                r#"2:2: Invalid arguments: Unknown kwarg(s) "bogus""#,
+                "2:37: Command nope not defined in current environment",
+                "3:2: missing.tc not found",
+            ],
+            world
+                .check(&toks)
+                .iter()
+                .map(|diagnostic| diagnostic.to_string())
+                .collect::<Vec<_>>()
+        );
+        assert!(world.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_math_alt() {
+        let src = Source::new("\\math[alt=the square of x]{x^2} \\math{\\int x}".into());
+        let html = to_html(eval(&src).0);
+        assert!(html.contains(r#"aria-label="the square of x""#));
+        assert_eq!(1, html.matches("aria-label").count());
+
+        let src = Source::new("\\equation{x^2}".into());
+        let html = to_html(eval(&src).0);
+        assert!(html.contains(r#"<div class="math" role="img" aria-label="x squared">"#));
+    }
+
+    /// An old name for `\\strong`.
+    #[derive(Debug, CommandInfo)]
+    #[textecca(deprecated = "use \\strong instead")]
+    struct Bold<'i> {
+        content: Thunk<'i>,
+    }
+    impl<'i> Command<'i> for Bold<'i> {
+        fn call(
+            self: Box<Self>,
+            doc: &mut DocBuilder,
+            world: &World<'i>,
+        ) -> Result<(), CommandError<'i>> {
+            Box::new(Strong {
+                content: self.content,
+            })
+            .call(doc, world)
+        }
+    }
+
+    #[test]
+    fn test_deprecated() {
+        let src = Source::new("\\bold{a} \\bold{b}".into());
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        Rc::get_mut(&mut env).unwrap().add_binding::<Bold>();
+
+        let world = World::new(env.clone(), &src);
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        let mut doc = DocBuilder::new();
+        Thunk::from(toks.clone()).force(&world, &mut doc).unwrap();
+        assert_eq!(
+            vec![Warning {
+                kind: WarningKind::Deprecated,
+                message: "\\bold is deprecated; use \\strong instead".into(),
+                position: Some(SourcePosition { line: 1, column: 2 }),
+            }],
+            world.take_warnings()
+        );
+
+        let world = World::new(env, &src).with_strictness(Strictness::Strict);
+        let mut doc = DocBuilder::new();
+        let res = Thunk::from(toks).force(&world, &mut doc);
+        match res.as_ref().map_err(CommandError::innermost) {
+            Err(CommandError::Strict(warning)) => assert_eq!(WarningKind::Deprecated, warning.kind),
+            _ => panic!("Expected a deprecation error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_redefine() {
+        let src = Source::new("\\x{a} \\x{b}".into());
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        Rc::get_mut(&mut env)
+            .unwrap()
+            .add_binding_name::<Bold>("x".into());
+        let mut world = World::new(env, &src);
+        let mut toks = default_parser(&src, (&src).into()).unwrap();
+        let second = toks.split_off(1);
+        let mut doc = DocBuilder::new();
+
+        let before = world.env.generation();
+        let bold = world.env.cmd_info("x").unwrap().generation;
+        Thunk::from(toks).force(&world, &mut doc).unwrap();
+        Rc::make_mut(&mut world.env).add_binding_name::<Emph>("x".into());
+        Thunk::from(second).force(&world, &mut doc).unwrap();
+        assert_eq!(
+            "<p><strong>a</strong> <em>b</em></p>",
+            to_html(doc.try_into().unwrap())
+                .lines()
+                .find(|line| line.starts_with("<p>"))
+                .unwrap()
+        );
+        assert_eq!(1, world.take_warnings().len());
+
+        let emph = world.env.cmd_info("x").unwrap().generation;
+        assert_ne!(bold, emph);
+        assert_ne!(before, world.env.generation());
+        // Child environments see the current binding.
+        let child = Rc::clone(&world.env).new_inheriting();
+        assert_eq!(emph, child.cmd_info("x").unwrap().generation);
+        assert_eq!(world.env.generation(), child.generation());
+
+        // A command bound again is a different binding, and a deprecated one
+        // is warned about again.
+        Rc::make_mut(&mut world.env).add_binding_name::<Bold>("x".into());
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        Thunk::from(toks)
+            .force(&world, &mut DocBuilder::new())
+            .unwrap();
+        assert_eq!(1, world.take_warnings().len());
+    }
+
+    #[test]
+    fn test_format_style() {
+        let mut root = Environment::new();
+        import(Rc::get_mut(&mut root).unwrap());
+        let mut env = root.new_inheriting();
+        let env_mut = Rc::get_mut(&mut env).unwrap();
+        env_mut.add_binding_name::<Codeblock>("listing".into());
+        env_mut.add_binding_name::<Emph>("code".into());
+        let verbatim = FormatStyle::for_env(&env).verbatim_commands;
+        for name in &["codeblock", "listing", "math", "table"] {
+            assert!(verbatim.iter().any(|v| v == name), "{}", name);
+        }
+        for name in &["code", "emph"] {
+            assert!(!verbatim.iter().any(|v| v == name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let mut root = Environment::new();
+        Rc::get_mut(&mut root)
+            .unwrap()
+            .add_binding_name::<Bold>("x".into());
+        let bold = root.cmd_info("x").unwrap().generation;
+        let mut mid = Rc::clone(&root).new_inheriting();
+        Rc::get_mut(&mut mid)
+            .unwrap()
+            .add_binding_name::<Emph>("x".into());
+        let emph = mid.cmd_info("x").unwrap().generation;
+        let mut leaf = Rc::clone(&mid).new_inheriting();
+        for _ in 0..4 {
+            leaf = leaf.new_inheriting();
+        }
+
+        // Looked up twice, to check the remembered resolution too.
+        for _ in 0..2 {
+            assert_eq!(emph, leaf.cmd_info("x").unwrap().generation);
+            assert_eq!(bold, root.cmd_info("x").unwrap().generation);
+            assert!(leaf.lookup("y").is_none());
+            match leaf.cmd_info("y") {
+                Err(CommandError::Name(name)) => assert_eq!("y", name),
+                result => panic!("Expected a name error, got {:?}", result),
+            }
+        }
+
+        // Bindings in the innermost environment shadow the others, until
+        // they're undone.
+        let leaf = Rc::make_mut(&mut leaf);
+        let snapshot = leaf.snapshot();
+        leaf.add_binding_name::<Bold>("x".into());
+        let inner = leaf.cmd_info("x").unwrap().generation;
+        assert_ne!(emph, inner);
+        assert_ne!(bold, inner);
+        leaf.restore(snapshot);
+        assert_eq!(emph, leaf.cmd_info("x").unwrap().generation);
+
+        // A resolution remembered for other bindings isn't used.
+        let mut other = (*Rc::clone(&root).new_inheriting()).clone();
+        assert_eq!(bold, other.cmd_info("x").unwrap().generation);
+        other.restore(leaf.snapshot());
+        assert_eq!(emph, other.cmd_info("x").unwrap().generation);
+        let mut other = (*leaf).clone();
+        other.restore(Rc::clone(&root).new_inheriting().snapshot());
+        assert_eq!(bold, other.cmd_info("x").unwrap().generation);
+
+        // Including after restoring the bindings of an environment with the
+        // same generation but a different parent.
+        let mut root = (*root).clone();
+        root.add_binding_name::<Bold>("z".into());
+        let root = Rc::new(root);
+        let mid = Rc::clone(&root).new_inheriting();
+        let mut child = (*Rc::clone(&mid).new_inheriting()).clone();
+        let z = child.cmd_info("z").unwrap().generation;
+        assert_eq!(mid.generation(), child.generation());
+        child.restore(mid.snapshot());
+        assert_eq!(z, child.cmd_info("z").unwrap().generation);
+    }
+
+    /// Writes its keyword arguments as `name=value;` text.
+    #[derive(Debug, CommandInfo)]
+    struct Pairs<'i> {
+        #[textecca(kwargs)]
+        pairs: Kwargs<'i>,
+    }
+    impl<'i> Command<'i> for Pairs<'i> {
+        fn call(
+            self: Box<Self>,
+            doc: &mut DocBuilder,
+            world: &World<'i>,
+        ) -> Result<(), CommandError<'i>> {
+            for (name, value) in self.pairs {
+                let value = value.force_to_string(world, doc)?;
+                doc.push(Inline::Text(format!("{}={};", name, value)))?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_kwargs_order() {
+        let src = Source::new("\\pairs[b=1, a=2, c=3]".into());
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        Rc::get_mut(&mut env).unwrap().add_binding::<Pairs>();
+        let world = World::new(env, &src);
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        let inlines = Thunk::from(toks)
+            .into_inlines(&world, &mut DocBuilder::new())
+            .unwrap();
+        assert_eq!(
+            Ok("b=1;a=2;c=3;".to_owned()),
+            doc::inlines_to_plain_text(&inlines)
+        );
+    }
+
+    #[test]
+    fn test_speculate() {
+        let src = Source::new("\\bold{a}\\set{draft}{yes}\\nope{x}".into());
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        let mut world = World::new(env, &src);
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        let doc = DocBuilder::new();
+
+        let speculative = world.speculate(|world| {
+            Rc::make_mut(&mut world.env).add_binding::<Bold>();
+            let mut scratch = doc.clone();
+            Thunk::from(toks.clone())
+                .force(world, &mut scratch)
+                .unwrap();
+            let scratch: Doc = scratch.try_into().unwrap();
+            scratch
+        });
+        assert_eq!(
+            Some("yes"),
+            speculative.meta.get("draft").map(String::as_str)
+        );
+
+        assert!(world.env.cmd_info("bold").is_err());
+        assert_eq!(Vec::<Warning>::new(), world.take_warnings());
+        let doc: Doc = doc.try_into().unwrap();
+        assert_eq!(None, doc.meta.get("draft"));
+
+        // The environment's own snapshots work the same way.
+        let mut env = (*world.env).clone();
+        let generation = env.generation();
+        let snapshot = env.snapshot();
+        env.add_binding::<Bold>();
+        assert!(env.cmd_info("bold").is_ok());
+        assert_ne!(generation, env.generation());
+        env.restore(snapshot);
+        assert!(env.cmd_info("bold").is_err());
+        assert_eq!(generation, env.generation());
+    }
+
+    #[test]
+    fn test_nested_error() {
+        let code = "let x = 1;\n".repeat(100);
+        let src = Source::new(format!(
+            "Text.\n\n\\strong{{a \\emph{{\\codeblock{{{}}}}}}}",
+            code
+        ));
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        let world = World::new(env, &src);
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        let err = Thunk::from(toks)
+            .force(&world, &mut DocBuilder::new())
+            .unwrap_err();
+        assert!(matches!(
+            err.innermost(),
+            CommandError::DocBuilder(doc::DocBuilderError::UnexpectedBlocks(_))
+        ));
+        assert_eq!(
+            [
+                "In \\strong at 3:2",
+                "  In \\emph at 3:12",
+                "    Invalid document structure",
+                "      Expected inline content, but got a code block",
+            ]
+            .join("\n"),
+            err.render_causes()
+        );
+    }
+
+    /// Evaluate and write a document with several recoverable problems with
+    /// the given strictness, returning the HTML and the kinds of warnings
+    /// emitted, or the first error.
+    fn eval_problems(strictness: Strictness) -> Result<(String, Vec<WarningKind>), String> {
+        let src = Source::new(
+            "\\nope{x} \\ref{missing} \\math{\\frac}\n\n\\table[cols=l l]{a & b\nc}".into(),
+        );
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        let world = World::new(env, &src).with_strictness(strictness);
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        let mut doc = DocBuilder::new();
+        Thunk::from(toks)
+            .force(&world, &mut doc)
+            .map_err(|err| err.to_string())?;
+        let mut doc: Doc = doc.try_into().unwrap();
+        for label in doc::undefined_labels(&mut doc) {
+            world
+                .report(Warning::new(
+                    WarningKind::UndefinedLabel,
+                    label.into_string(),
+                ))
+                .map_err(|err| err.to_string())?;
+        }
+
+        let mut out = Vec::new();
+        let mut ser = HtmlSerializer::with_options(
+            &mut out,
+            HtmlOptions {
+                strictness,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        ser.write_doc(&doc).map_err(|err| err.to_string())?;
+        let warnings = world
+            .take_warnings()
+            .into_iter()
+            .chain(ser.take_warnings())
+            .map(|warning| warning.kind)
+            .collect();
+        drop(ser);
+        Ok((String::from_utf8(out).unwrap(), warnings))
+    }
+
+    #[test]
+    fn test_strictness_strict() {
+        assert_eq!(
+            Err("1:2: Command nope not defined in current environment".to_owned()),
+            eval_problems(Strictness::Strict)
+        );
+    }
+
+    #[test]
+    fn test_strictness_warn() {
+        let (html, warnings) = eval_problems(Strictness::Warn).unwrap();
+        assert_eq!(
+            vec![
+                WarningKind::UnknownCommand,
+                WarningKind::TableColumns,
+                WarningKind::UndefinedLabel,
+                WarningKind::Math,
+            ],
+            warnings
+        );
+        assert!(html.contains(
+            r##"<p>\nope{x} <a href="#missing">missing</a> <code class="math-error">\frac</code></p>"##
+        ));
+    }
+
+    #[test]
+    fn test_strictness_lenient() {
+        let (html, warnings) = eval_problems(Strictness::Lenient).unwrap();
+        assert_eq!(Vec::<WarningKind>::new(), warnings);
+        assert_eq!(html, eval_problems(Strictness::Warn).unwrap().0);
+    }
+
+    #[test]
+    fn test_lang() {
+        let src = Source::new(
+            "\\set{lang}{en}Hi \\lang{de}{Hallo \\lang{fr}{Bonjour}}\n\n\\lang{fr}{Salut\n\n\\sec{Oui}}"
+                .into(),
+        );
+        let html = to_html(eval(&src).0);
+        assert!(html.contains(r#"<html lang="en">"#));
+        assert!(html
+            .contains(r#"<p>Hi <span lang="de">Hallo <span lang="fr">Bonjour</span></span></p>"#));
+        assert!(html.contains(r#"<div lang="fr">"#));
+    }
+
+    #[test]
+    fn test_table() {
+        let src =
+            Source::new("\\table[cols=l:30% c r]{\n  a & \\emph{b & c} & d\n  e & f\n}".into());
+        let (doc, warnings) = eval(&src);
+        assert_eq!(
+            vec![Warning {
+                kind: WarningKind::TableColumns,
+                message: "Table row has 2 cells, but the table has 3 columns".into(),
+                position: Some(SourcePosition { line: 3, column: 1 }),
+            }],
+            warnings
+        );
+        let table = match &doc.content[0].inner {
+            BlockInner::Table(table) => table,
+            block => panic!("Expected a table, got {:?}", block),
+        };
+        assert_eq!(
+            vec![
+                doc::TableColumn {
+                    alignment: doc::Alignment::Left,
+                    width: Some("30%".parse().unwrap()),
+                },
+                doc::TableColumn {
+                    alignment: doc::Alignment::Center,
+                    width: None,
+                },
+                doc::TableColumn {
+                    alignment: doc::Alignment::Right,
+                    width: None,
+                },
+            ],
+            table.columns
+        );
+        assert_eq!(
+            vec![3, 2],
+            table.cells.iter().map(Vec::len).collect::<Vec<_>>()
+        );
+
+        let html = to_html(doc);
+        assert!(html.contains(r#"<td class="align-center"><em>b &amp; c</em></td>"#));
+    }
+
+    #[test]
+    fn test_table_escapes() {
+        let src = Source::new("\\table{\n  \\code{\\{} & a \\& b\n  \\code{\\}} & c\n}".into());
+        let (doc, warnings) = eval(&src);
+        assert_eq!(Vec::<Warning>::new(), warnings);
+        let table = match &doc.content[0].inner {
+            BlockInner::Table(table) => table,
+            block => panic!("Expected a table, got {:?}", block),
+        };
+        assert_eq!(
+            vec![2, 2],
+            table.cells.iter().map(Vec::len).collect::<Vec<_>>()
+        );
+
+        let html = to_html(doc);
+        assert!(html.contains("<td><code>{</code></td>"), "{}", html);
+        assert!(html.contains("<td>a &amp; b</td>"), "{}", html);
+        assert!(html.contains("<td><code>}</code></td>"), "{}", html);
+    }
+
+    #[test]
+    fn test_block_body_paragraphs() {
+        fn par(text: &str) -> BlockInner {
+            BlockInner::Par(vec![Inline::Text(text.into())])
+        }
+        let emph = BlockInner::Par(vec![Inline::Styled {
+            style: doc::Style::Emph,
+            content: vec![Inline::Text("x".into())],
+        }]);
+        let cases = vec![
+            ("\\figure{\n\na}", vec![par("a")]),
+            ("\\figure{a\n\n}", vec![par("a")]),
+            (
+                "\\figure{\n\na\n\n\\caption{A caption.}\n\n}",
+                vec![par("a")],
+            ),
+            ("\\figure{\\emph{x}}", vec![emph]),
+            ("\\figure{a\n\nb}", vec![par("a"), par("b")]),
+        ];
+        for (src, expected) in cases {
+            let (doc, _) = eval(&Source::new(src.into()));
+            let content: Vec<_> = match &doc.content[..] {
+                [block] => match &block.inner {
+                    BlockInner::Figure(figure) => figure
+                        .content
+                        .iter()
+                        .map(|block| block.inner.clone())
+                        .collect(),
+                    other => panic!("Expected a figure, got {:?}", other),
+                },
+                blocks => panic!("Expected one block, got {:?}", blocks),
+            };
+            assert_eq!(expected, content, "{:?}", src);
+        }
+
+        let (doc, _) = eval(&Source::new("a\n\n\\sec{b}\n\nc\n\n".into()));
+        let kinds: Vec<_> = doc.content.iter().map(|block| block.kind_name()).collect();
+        assert_eq!(vec!["paragraph", "heading", "paragraph"], kinds);
+    }
+
+    #[test]
+    fn test_parse_table_columns() {
+        assert!(parse_table_columns("l c:1in").is_ok());
+        assert!(parse_table_columns("x").is_err());
+        assert!(parse_table_columns("l:30").is_err());
+    }
+
+    #[test]
+    fn test_dir() {
+        let src =
+            Source::new("\\set{dir}{rtl}\\rtl{مرحبا \\ltr{Hello}}\n\n\\rtl{שלום\n\nעולם}".into());
+        let html = to_html(eval(&src).0);
+        assert!(html.contains(r#"<html dir="rtl">"#));
+        assert!(
+            html.contains(r#"<p><span dir="rtl">مرحبا <span dir="ltr">Hello</span></span></p>"#)
+        );
+        assert!(html.contains(r#"<div dir="rtl">"#));
+    }
+
+    #[test]
+    fn test_sec_paragraph_break() {
+        let src = Source::new("\\sec{A\n\nheading}\n\nText.".into());
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        let world = World::new(env, &src);
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        let err = Thunk::from(toks)
+            .force(&world, &mut DocBuilder::new())
+            .unwrap_err();
+        assert_eq!(
+            vec![
+                "In \\sec at 1:2",
+                "Invalid document structure",
+                "Expected inline content, but got 2 blocks, starting with a paragraph"
+            ],
+            err.causes()
+        );
+    }
+
+    #[test]
+    fn test_partial_doc() {
+        let src = Source::new("Before \\strong{this}.\n\n\\sec{A\n\nheading}\n\nAfter.".into());
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        let world = World::new(env, &src);
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        let mut builder = DocBuilder::new();
+        let err = Thunk::from(toks).force(&world, &mut builder).unwrap_err();
+        builder.push_error(&err.render_causes());
+        let html = to_html(builder.try_into().unwrap());
+        assert!(
+            html.contains("<p>Before <strong>this</strong>.</p>"),
+            "{}",
+            html
+        );
+        assert!(html.contains("class=\"error\""), "{}", html);
+        assert!(html.contains("In \\sec at 3:2"), "{}", html);
+        assert!(!html.contains("After."), "{}", html);
+    }
+
+    #[test]
+    fn test_sec_id() {
+        let src = Source::new(
+            "\\sec[id=stable-intro]{Introduction, revised}\n\nSee \\ref{stable-intro}.".into(),
+        );
+        let (doc, _) = eval(&src);
+        let toc: Vec<_> = doc::toc(&doc).iter().map(doc::TocEntry::anchor).collect();
+        assert_eq!(vec![RefId::new("stable-intro").unwrap()], toc);
+        let html = to_html(doc);
+        assert!(html.contains(r#"<h1 id="stable-intro">"#));
+        assert!(html.contains(r##"<a href="#stable-intro">stable-intro</a>"##));
+    }
+
+    #[test]
+    fn test_invalid_labels() {
+        for (src, command) in &[
+            ("\\ref{fig 1}", "ref"),
+            ("\\figure[label={fig 1}]{a}", "figure"),
+            ("\\sec[id={an id}]{A}", "sec"),
+        ] {
+            let src = Source::new((*src).to_owned());
+            let mut env = Environment::new();
+            import(Rc::get_mut(&mut env).unwrap());
+            let world = World::new(env, &src);
+            let toks = default_parser(&src, (&src).into()).unwrap();
+            let err = Thunk::from(toks)
+                .force(&world, &mut DocBuilder::new())
+                .unwrap_err();
+            assert_eq!(format!("In \\{} at 1:2", command), err.causes()[0]);
+            assert!(
+                matches!(err.innermost(), CommandError::InvalidRefId(_)),
+                "{:?}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let src = Source::new("\\nope{a}\n\\emph{\\ref{a b}} \\emph{\\ escape}".into())
+            .with_path("main.tc");
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        let world = World::new(env, &src);
+        let json = |diagnostic| serde_json::to_string(&diagnostic).unwrap();
+
+        // Parse errors.
+        let mut toks = default_parser(&src, (&src).into()).unwrap();
+        let errors = world.validate(&mut toks);
+        assert_eq!(
+            vec![
+                r#"{"version":1,"severity":"error","code":"E0001","message":"unexpected '\\\\'","file":"main.tc","span":{"start":32,"end":40,"line":2,"column":24},"related":[],"help":null}"#
+            ],
+            errors
+                .iter()
+                .map(|error| json(error.diagnostic(&src)))
+                .collect::<Vec<_>>()
+        );
+
+        // Problems found without evaluating.
+        let diagnostics = world.check(&toks);
+        assert_eq!(
+            vec![ErrorCode::UnknownCommand, ErrorCode::Syntax],
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.error.code())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            r#"{"version":1,"severity":"error","code":"E0101","message":"Command nope not defined in current environment","file":"main.tc","span":{"start":1,"end":5,"line":1,"column":2},"related":[],"help":"Check the command's spelling"}"#,
+            json(diagnostics[0].diagnostic(&src))
+        );
+
+        // Errors while evaluating, in the innermost command.
+        let err = Thunk::from(toks)
+            .force(&world, &mut DocBuilder::new())
+            .unwrap_err();
+        assert_eq!(
+            r#"{"version":1,"severity":"error","code":"E0110","message":"Labels can't contain whitespace or control characters, but \"a b\" contains ' '","file":"main.tc","span":{"start":16,"end":19,"line":2,"column":8},"related":[{"span":{"start":10,"end":14,"line":2,"column":2},"message":"In \\emph"}],"help":"Use a label without spaces, like `fig:results`"}"#,
+            json(err.diagnostic(&src))
+        );
+
+        // Warnings.
+        assert_eq!(
+            r#"{"version":1,"severity":"warning","code":"W0006","message":"Command nope not defined in current environment","file":"main.tc","span":{"start":1,"end":1,"line":1,"column":2},"related":[],"help":"Check the command's spelling"}"#,
+            json(world.take_warnings()[0].diagnostic(&src))
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let src = Source::new("\\strong{a \\emph{b}}\n\n\\emph{c}".into());
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        let mut world = World::new(env, &src);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let events_ = Rc::clone(&events);
+        world.set_tracer(Box::new(move |event| events_.borrow_mut().push(event)));
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        Thunk::from(toks)
+            .force(&world, &mut DocBuilder::new())
+            .unwrap();
+
+        let events = events.borrow();
+        let tree: Vec<_> = events
+            .iter()
+            .map(|event| match event {
+                TraceEvent::Start { name, args, .. } => {
+                    format!("{}{} {:?}", "  ".repeat(event.depth()), name, args)
+                }
+                TraceEvent::End {
+                    name,
+                    blocks,
+                    inlines,
+                    failed,
+                    ..
+                } => format!(
+                    "{}/{} {} {} {}",
+                    "  ".repeat(event.depth()),
+                    name,
+                    blocks,
+                    inlines,
+                    failed
+                ),
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                r#"strong ["a \\emph{b}"]"#,
+                r#"  emph ["b"]"#,
+                "  /emph 0 1 false",
+                "/strong 0 1 false",
+                "par []",
+                "/par 1 0 false",
+                r#"emph ["c"]"#,
+                "/emph 0 1 false",
+            ],
+            tree
+        );
+
+        let elapsed = |i: usize| match &events[i] {
+            TraceEvent::End { elapsed, .. } => *elapsed,
+            event => panic!("Expected an end event, got {:?}", event),
+        };
+        assert!(elapsed(3) >= elapsed(2));
+        assert!(elapsed(3) > Duration::from_secs(0));
+    }
+
+    #[test]
+    fn test_set_draft() {
+        let src = Source::new("\\set{draft}{yes}".into());
+        let (doc, _) = eval(&src);
+        assert!(doc::is_draft(&doc.meta));
+    }
+
+    #[test]
+    fn test_set_flattens() {
+        let src = Source::new("\\set{title}{A \\emph{fine} \\strong{day}}".into());
+        let (doc, _) = eval(&src);
+        assert_eq!(
+            Some("A fine day"),
+            doc.meta.get("title").map(String::as_str)
+        );
+
+        let src = Source::new("\\set{title}{A day\\footnote{Not really.}}".into());
+        let mut env = Environment::new();
+        import(Rc::get_mut(&mut env).unwrap());
+        let world = World::new(env, &src);
+        let toks = default_parser(&src, (&src).into()).unwrap();
+        let err = Thunk::from(toks)
+            .force(&world, &mut DocBuilder::new())
+            .unwrap_err();
+        assert_eq!(
+            vec!["In \\set at 1:2", "Expected plain text, but got a footnote"],
+            err.causes()
+        );
+    }
+}
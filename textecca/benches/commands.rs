@@ -0,0 +1,137 @@
+//! Timing benchmarks for each standard library command in isolation.
+//!
+//! Each command is evaluated in a document calling it `CALLS` times. Run with
+//! `cargo bench --bench commands`; after benchmarking, each command's time
+//! per call is checked against its budget in `COMMANDS`, and the run fails if
+//! any command is over budget. Budgets are generous, so they only catch
+//! order-of-magnitude regressions rather than noise; CI checks them with short
+//! measurement times. `\include` isn't benchmarked, since it's dominated by
+//! reading files.
+use std::time::{Duration, Instant};
+
+use criterion::{BatchSize, Criterion, Throughput};
+
+use textecca::parse::Source;
+
+mod fixtures;
+use fixtures::*;
+
+/// The number of calls in each command's document.
+const CALLS: usize = 200;
+
+/// A benchmarked command.
+struct Bench {
+    name: &'static str,
+    /// A call to the command.
+    call: &'static str,
+    /// The most time a call may take, on average.
+    budget: Duration,
+}
+
+const fn bench(name: &'static str, call: &'static str, budget_micros: u64) -> Bench {
+    Bench {
+        name,
+        call,
+        budget: Duration::from_micros(budget_micros),
+    }
+}
+
+const COMMANDS: &[Bench] = &[
+    bench("par", "a\\par b", 250),
+    bench("br", "a\\br b", 250),
+    bench("sec", "\\sec{A heading}", 500),
+    bench("footnote", "a\\footnote{A note.}", 500),
+    bench("code", "\\code{let x = 1;}", 250),
+    bench("emph", "\\emph{a}", 250),
+    bench("strong", "\\strong{a}", 250),
+    bench("math", "\\math{x^2 + y^2}", 250),
+    bench("equation", "\\equation{\\sum_{i=0}^n i}", 250),
+    bench("link", "\\link{https://example.com}{a link}", 250),
+    bench("ref", "\\ref{fig:a}", 250),
+    bench("figure", "\\figure{a\\caption{A caption.}}", 500),
+    bench("codeblock", "\\codeblock[lang=rust]{\nfn main() {}\n}", 500),
+    bench("table", "\\table{\n  a & b\n  c & d\n}", 1000),
+    bench("only", "\\only{html}{a}", 250),
+    bench("todo", "\\todo{a}", 250),
+    bench("abstract", "\\abstract{a}", 250),
+    bench("draftonly", "\\draftonly{a}", 250),
+    bench("set", "\\set{title}{A title}", 250),
+    bench("lang", "\\lang{de}{a}", 250),
+    bench("rtl", "\\rtl{a}", 250),
+    bench("ltr", "\\ltr{a}", 250),
+    bench("img", "\\img{https://example.com/a.png}", 250),
+];
+
+/// A document calling a command `CALLS` times, in separate paragraphs.
+fn calls(bench: &Bench) -> String {
+    let mut ret = String::new();
+    for _ in 0..CALLS {
+        ret.push_str(bench.call);
+        ret.push_str("\n\n");
+    }
+    ret
+}
+
+fn commands(c: &mut Criterion) {
+    for bench in COMMANDS {
+        let src = Source::new(calls(bench));
+        let toks = parse(&src);
+        let mut group = c.benchmark_group(format!("commands/{}", bench.name));
+        group.throughput(Throughput::Elements(CALLS as u64));
+        group.bench_function("evaluate", |b| {
+            b.iter_batched(
+                || toks.clone(),
+                |toks| evaluate(&src, toks),
+                BatchSize::SmallInput,
+            )
+        });
+        group.finish();
+    }
+}
+
+/// Check each command's time per call against its budget, returning the
+/// commands which are over budget.
+fn check_budgets() -> Vec<String> {
+    let mut ret = Vec::new();
+    for bench in COMMANDS {
+        let src = Source::new(calls(bench));
+        let toks = parse(&src);
+        // The fastest of a few runs, to discount noise.
+        let elapsed = (0..5)
+            .map(|_| {
+                let toks = toks.clone();
+                let start = Instant::now();
+                evaluate(&src, toks);
+                start.elapsed()
+            })
+            .min()
+            .unwrap();
+        let per_call = elapsed / CALLS as u32;
+        if per_call > bench.budget {
+            ret.push(format!(
+                "\\{} took {:?} per call, over its budget of {:?}",
+                bench.name, per_call, bench.budget
+            ));
+        }
+    }
+    ret
+}
+
+fn main() {
+    let mut criterion = Criterion::default().configure_from_args();
+    commands(&mut criterion);
+    criterion.final_summary();
+    // Budgets only apply to optimized builds; `cargo test` runs each
+    // benchmark once, without `--bench`, to check that it works.
+    if !std::env::args().any(|arg| arg == "--bench") {
+        return;
+    }
+
+    let over_budget = check_budgets();
+    for message in &over_budget {
+        eprintln!("{}", message);
+    }
+    if !over_budget.is_empty() {
+        std::process::exit(1);
+    }
+}
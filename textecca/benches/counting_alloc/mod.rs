@@ -0,0 +1,43 @@
+//! A counting global allocator, shared by the memory benchmark and the
+//! `clones` test. Including this module installs it as the binary's global
+//! allocator.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A global allocator which tracks the current and peak number of bytes
+/// allocated.
+struct CountingAlloc {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ret = System.alloc(layout);
+        if !ret.is_null() {
+            let current = self.current.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            self.peak.fetch_max(current, Ordering::SeqCst);
+        }
+        ret
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        self.current.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc {
+    current: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+};
+
+/// Run `f`, returning its result and the peak number of bytes allocated while
+/// it ran, above what was allocated beforehand.
+pub fn peak_bytes<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let baseline = ALLOC.current.load(Ordering::SeqCst);
+    ALLOC.peak.store(baseline, Ordering::SeqCst);
+    let ret = f();
+    (ret, ALLOC.peak.load(Ordering::SeqCst) - baseline)
+}
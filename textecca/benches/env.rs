@@ -0,0 +1,65 @@
+//! Timing benchmarks for looking up commands in nested environments.
+//!
+//! The same document is evaluated with the standard library bound in the
+//! root environment, and in an environment `DEPTH` levels below it, like a
+//! command's content in a deeply nested scope.
+use std::rc::Rc;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use textecca::env::Environment;
+use textecca::parse::Source;
+
+mod fixtures;
+use fixtures::*;
+
+/// The number of command invocations in the document.
+const CALLS: usize = 10_000;
+
+/// How many environments the nested environment is below the root.
+const DEPTH: usize = 8;
+
+/// Paragraphs of short commands, `CALLS` invocations in total.
+fn calls() -> String {
+    let mut ret = String::new();
+    for i in 0..CALLS / 4 {
+        ret.push_str("\\emph{a} \\strong{b} \\code{c} \\math{d} ");
+        if i % 10 == 9 {
+            ret.push_str("\n\n");
+        }
+    }
+    ret
+}
+
+/// An environment `depth` levels below one with the standard library bound.
+fn nested(depth: usize) -> Rc<Environment> {
+    let mut env = stdlib();
+    for _ in 0..depth {
+        env = env.new_inheriting();
+    }
+    env
+}
+
+fn env(c: &mut Criterion) {
+    let src = Source::new(calls());
+    let toks = parse(&src);
+    let mut group = c.benchmark_group("env");
+    group.throughput(Throughput::Elements(CALLS as u64));
+    for &(name, depth) in &[("flat", 0), ("nested", DEPTH)] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || (nested(depth), toks.clone()),
+                |(env, toks)| evaluate_in(env, &src, toks),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = env
+}
+criterion_main!(benches);
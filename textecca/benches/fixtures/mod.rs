@@ -0,0 +1,186 @@
+//! Synthetic documents and pipeline stages shared by the benchmarks.
+#![allow(dead_code)] // Not every benchmark uses every fixture.
+
+use std::convert::TryInto;
+use std::rc::Rc;
+
+use textecca::{
+    cmd::{Thunk, World},
+    doc::{Doc, DocBuilder},
+    env::Environment,
+    parse::{default_parser, Source, Tokens},
+    ser::{HtmlSerializer, InitSerializer as _, Serializer as _},
+};
+
+/// A named synthetic document.
+pub struct Fixture {
+    pub name: &'static str,
+    pub src: String,
+}
+
+/// All the fixtures, at roughly comparable sizes.
+pub fn fixtures() -> Vec<Fixture> {
+    vec![
+        Fixture {
+            name: "plain_prose",
+            src: plain_prose(400),
+        },
+        Fixture {
+            name: "command_heavy",
+            src: command_heavy(400),
+        },
+        Fixture {
+            name: "nested_braces",
+            src: nested_braces(100, 32),
+        },
+        Fixture {
+            name: "math_heavy",
+            src: math_heavy(100),
+        },
+        Fixture {
+            name: "repeated_math",
+            src: repeated_math(500, 20),
+        },
+        Fixture {
+            name: "blank_lines",
+            src: blank_lines(400, 64),
+        },
+        Fixture {
+            name: "long_blank_run",
+            src: blank_lines(2, 100_000),
+        },
+    ]
+}
+
+const SENTENCE: &str = "The quick brown fox jumps over the lazy dog, again and again.";
+
+/// Paragraphs of plain text with no commands.
+pub fn plain_prose(paragraphs: usize) -> String {
+    let mut ret = String::new();
+    for _ in 0..paragraphs {
+        for _ in 0..5 {
+            ret.push_str(SENTENCE);
+            ret.push(' ');
+        }
+        ret.push_str("\n\n");
+    }
+    ret
+}
+
+/// Paragraphs where every sentence has emphasis and a footnote.
+pub fn command_heavy(paragraphs: usize) -> String {
+    let mut ret = String::new();
+    for _ in 0..paragraphs {
+        for i in 0..5 {
+            ret.push_str(&format!(
+                "The \\emph{{quick}} brown fox \\strong{{jumps}} over the lazy dog.\\footnote{{Note {}.}} ",
+                i
+            ));
+        }
+        ret.push_str("\n\n");
+    }
+    ret
+}
+
+/// Paragraphs each containing commands and brace groups nested `depth` deep.
+pub fn nested_braces(paragraphs: usize, depth: usize) -> String {
+    let mut ret = String::new();
+    for _ in 0..paragraphs {
+        for i in 0..depth {
+            if i % 2 == 0 {
+                ret.push_str("\\emph{a ");
+            } else {
+                ret.push_str("{b ");
+            }
+        }
+        ret.push_str(SENTENCE);
+        for _ in 0..depth {
+            ret.push('}');
+        }
+        ret.push_str("\n\n");
+    }
+    ret
+}
+
+/// Paragraphs with inline math in every sentence and a display equation.
+pub fn math_heavy(paragraphs: usize) -> String {
+    let mut ret = String::new();
+    for _ in 0..paragraphs {
+        for i in 0..5 {
+            ret.push_str(&format!(
+                "Let \\math{{x_{} = \\frac{{a^2 + b^2}}{{\\sqrt{{c}}}}}} hold. ",
+                i
+            ));
+        }
+        ret.push_str("\n\n\\equation{\\sum_{i=0}^n i = \\frac{n(n+1)}{2}}\n\n");
+    }
+    ret
+}
+
+/// Paragraphs with `occurrences` inline formulas in total, cycling through
+/// `distinct` different formulas.
+pub fn repeated_math(occurrences: usize, distinct: usize) -> String {
+    let mut ret = String::new();
+    for i in 0..occurrences {
+        ret.push_str(&format!(
+            "Then \\math{{\\alpha_{{{}}} = \\frac{{\\pi}}{{2}}}} holds. ",
+            i % distinct
+        ));
+        if i % 10 == 9 {
+            ret.push_str("\n\n");
+        }
+    }
+    ret
+}
+
+/// Short paragraphs separated by long runs of blank lines.
+pub fn blank_lines(paragraphs: usize, blank: usize) -> String {
+    let mut ret = String::new();
+    for _ in 0..paragraphs {
+        ret.push_str(SENTENCE);
+        for _ in 0..blank {
+            ret.push('\n');
+        }
+    }
+    ret
+}
+
+/// Parse a source with the default parser.
+pub fn parse(src: &Source) -> Tokens<'_> {
+    default_parser(src, src.into()).unwrap()
+}
+
+/// An environment with the standard library bound.
+pub fn stdlib() -> Rc<Environment> {
+    let mut env = Environment::new();
+    textecca_stdlib::import(Rc::get_mut(&mut env).unwrap());
+    env
+}
+
+/// Evaluate parsed tokens with the standard library bound.
+pub fn evaluate<'i>(src: &'i Source, toks: Tokens<'i>) -> Doc {
+    evaluate_in(stdlib(), src, toks)
+}
+
+/// Evaluate parsed tokens in the given environment.
+pub fn evaluate_in<'i>(env: Rc<Environment>, src: &'i Source, toks: Tokens<'i>) -> Doc {
+    let world = World::new(env, src);
+    let mut doc = DocBuilder::new();
+    Thunk::from(toks).force(&world, &mut doc).unwrap();
+    doc.try_into().unwrap()
+}
+
+/// Serialize a document to HTML in memory.
+pub fn serialize(doc: Doc) -> Vec<u8> {
+    let mut out = Vec::new();
+    HtmlSerializer::new(&mut out)
+        .unwrap()
+        .write_doc(&doc)
+        .unwrap();
+    out
+}
+
+/// Run the whole pipeline on a source.
+pub fn end_to_end(src: &Source) -> Vec<u8> {
+    serialize(evaluate(src, parse(src)))
+}
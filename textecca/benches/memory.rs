@@ -0,0 +1,35 @@
+//! Peak heap usage of each stage of the pipeline on synthetic documents,
+//! measured with a counting allocator.
+//!
+//! Run with `cargo bench --bench memory`; prints a table of peak bytes
+//! allocated above the baseline at the start of each stage.
+use textecca::parse::Source;
+
+mod counting_alloc;
+use counting_alloc::peak_bytes;
+
+mod fixtures;
+use fixtures::*;
+
+fn main() {
+    println!(
+        "{:<16} {:>12} {:>12} {:>12} {:>12} {:>12}",
+        "fixture", "source", "parse", "evaluate", "serialize", "end_to_end"
+    );
+    for fixture in fixtures() {
+        let src = Source::new(fixture.src.clone());
+        let (toks, parse_peak) = peak_bytes(|| parse(&src));
+        let (doc, evaluate_peak) = peak_bytes(|| evaluate(&src, toks));
+        let (_, serialize_peak) = peak_bytes(|| serialize(doc));
+        let (_, end_to_end_peak) = peak_bytes(|| end_to_end(&src));
+        println!(
+            "{:<16} {:>12} {:>12} {:>12} {:>12} {:>12}",
+            fixture.name,
+            fixture.src.len(),
+            parse_peak,
+            evaluate_peak,
+            serialize_peak,
+            end_to_end_peak
+        );
+    }
+}
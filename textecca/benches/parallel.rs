@@ -0,0 +1,72 @@
+//! Serializing the chapters of one document on several threads, compared to
+//! serializing them one after another. See "Threads" in the crate docs.
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use textecca::{
+    doc::{Blocks, Doc},
+    parse::Source,
+};
+
+mod fixtures;
+use fixtures::*;
+
+/// Split a document into `n` chapters of roughly equal numbers of blocks.
+fn chapters(doc: &Doc, n: usize) -> Vec<Doc> {
+    let size = doc.content.len().div_ceil(n);
+    doc.content
+        .chunks(size.max(1))
+        .map(|blocks| Doc {
+            meta: doc.meta.clone(),
+            content: Blocks(blocks.to_vec()),
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn parallel(c: &mut Criterion) {
+    for fixture in fixtures() {
+        let src = Source::new(fixture.src.clone());
+        let doc = evaluate(&src, parse(&src));
+
+        let mut group = c.benchmark_group(format!("{}/chapters", fixture.name));
+        for &n in &[1, 2, 4, 8] {
+            let chapters = chapters(&doc, n);
+            group.bench_with_input(
+                BenchmarkId::new("sequential", n),
+                &chapters,
+                |b, chapters| {
+                    b.iter(|| {
+                        chapters
+                            .iter()
+                            .map(|chapter| serialize(chapter.clone()))
+                            .collect::<Vec<_>>()
+                    })
+                },
+            );
+            group.bench_with_input(BenchmarkId::new("threads", n), &chapters, |b, chapters| {
+                b.iter(|| {
+                    thread::scope(|scope| {
+                        let handles: Vec<_> = chapters
+                            .iter()
+                            .map(|chapter| scope.spawn(move || serialize(chapter.clone())))
+                            .collect();
+                        handles
+                            .into_iter()
+                            .map(|handle| handle.join().unwrap())
+                            .collect::<Vec<_>>()
+                    })
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = parallel
+}
+criterion_main!(benches);
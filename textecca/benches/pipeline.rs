@@ -0,0 +1,40 @@
+//! Timing benchmarks for each stage of the pipeline on synthetic documents.
+//!
+//! Lexing isn't a separate stage; it's included in `parse`.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use textecca::parse::Source;
+
+mod fixtures;
+use fixtures::*;
+
+fn pipeline(c: &mut Criterion) {
+    for fixture in fixtures() {
+        let src = Source::new(fixture.src.clone());
+        let toks = parse(&src);
+        let doc = evaluate(&src, toks.clone());
+
+        let mut group = c.benchmark_group(fixture.name);
+        group.throughput(Throughput::Bytes(fixture.src.len() as u64));
+        group.bench_function("parse", |b| b.iter(|| parse(&src)));
+        group.bench_function("evaluate", |b| {
+            b.iter_batched(
+                || toks.clone(),
+                |toks| evaluate(&src, toks),
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function("serialize", |b| {
+            b.iter_batched(|| doc.clone(), serialize, BatchSize::SmallInput)
+        });
+        group.bench_function("end_to_end", |b| b.iter(|| end_to_end(&src)));
+        group.finish();
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = pipeline
+}
+criterion_main!(benches);
@@ -1,33 +1,100 @@
-use std::collections::{HashMap, VecDeque};
-use std::{borrow::Borrow, error};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::{borrow::Borrow, error, mem};
 
+use indexmap::IndexMap;
 use thiserror::Error;
 
-use super::{Command, Thunk, World};
+use super::{span_range, Command, CommandError, Diagnostic, Thunk, World};
+use crate::diagnostic::ErrorCode;
+use crate::doc::Meta;
 use crate::parse::{Argument, Parser};
 
+/// Keyword arguments to a command, as collected by `ParsedArgs::take_kwargs`,
+/// in the order they're written.
+pub type Kwargs<'i> = IndexMap<String, Thunk<'i>>;
+
+/// Render each of the given keyword arguments as a string with
+/// `Thunk::into_string`, e.g. for use as a block's attributes.
+pub fn kwargs_into_meta(kwargs: Kwargs<'_>) -> Result<Meta, CommandError<'_>> {
+    kwargs
+        .into_iter()
+        .map(|(name, value)| Ok((name, value.into_string()?)))
+        .collect()
+}
+
 /// Arguments to a command.
 #[derive(Debug, Clone, PartialEq)]
 pub struct ParsedArgs<'i> {
     /// Positional arguments.
     pub args: VecDeque<Thunk<'i>>,
-    /// Keyword arguments.
-    pub kwargs: HashMap<String, Thunk<'i>>,
+    /// Keyword arguments, in the order they're written. A keyword given more
+    /// than once keeps its first position and its last value.
+    pub kwargs: Kwargs<'i>,
 }
 
 impl<'i> ParsedArgs<'i> {
     /// Parse a number of raw arguments (i.e. `Span`s) into a `ParsedArgs`
     /// instance with the given `Parser`, evaluating in the given `World`.
+    ///
+    /// Arguments already parsed by `World::validate` aren't parsed again.
     pub fn from_unparsed(
         args: &[Argument<'i>],
         parser: Parser,
         world: &World<'i>,
     ) -> Result<Self, Box<dyn error::Error + 'i>> {
+        Self::parse_each(args, |arg| {
+            Ok(match &arg.tokens {
+                Some(tokens) => tokens.clone(),
+                None => parser(world.arena, arg.value)?,
+            }
+            .into())
+        })
+    }
+
+    /// Like `from_unparsed`, but an argument which can't be parsed doesn't
+    /// stop the command from being constructed: the parse error is reported
+    /// as a `WarningKind::InvalidArgument` warning right away (unless
+    /// `World::validate_or_warn` already reported it), and the
+    /// argument is a `Thunk::Invalid`, which only gives the error if the
+    /// command uses it.
+    ///
+    /// Errors only if the warning is an error; see `World::report`.
+    pub fn from_unparsed_lenient(
+        args: &[Argument<'i>],
+        parser: Parser,
+        world: &World<'i>,
+    ) -> Result<Self, CommandError<'i>> {
+        Self::parse_each(args, |arg| {
+            let tokens = match &arg.tokens {
+                Some(tokens) => tokens.clone(),
+                None => match parser(world.arena, arg.value) {
+                    Ok(tokens) => tokens,
+                    Err(error) => {
+                        let position = world.arena.position(&arg.value);
+                        world.report_invalid_argument(position, span_range(&arg.value), &*error)?;
+                        return Ok(Thunk::Invalid(Rc::new(Diagnostic {
+                            position,
+                            span: span_range(&arg.value),
+                            error: CommandError::ParseError(error),
+                        })));
+                    }
+                },
+            };
+            Ok(tokens.into())
+        })
+    }
+
+    /// Collect the given arguments, making each one's value with `parse`.
+    fn parse_each<E>(
+        args: &[Argument<'i>],
+        mut parse: impl FnMut(&Argument<'i>) -> Result<Thunk<'i>, E>,
+    ) -> Result<Self, E> {
         let mut posargs = VecDeque::new();
-        let mut kwargs = HashMap::new();
+        let mut kwargs = IndexMap::new();
         for arg in args {
             // TODO: Handle various errors relating to kwargs in incorrect places.
-            let value = parser(world.arena, arg.value)?.into();
+            let value = parse(arg)?;
             match arg.name {
                 Some(kw) => {
                     kwargs.insert(kw.fragment().to_string(), value);
@@ -47,20 +114,37 @@ impl<'i> ParsedArgs<'i> {
     /// given as a keyword argument, from the last positional argument.
     pub fn pop_mandatory(&mut self, name: impl AsRef<str>) -> Result<Thunk<'i>, FromArgsError> {
         self.kwargs
-            .remove(name.as_ref())
+            .shift_remove(name.as_ref())
             .or_else(|| self.args.pop_back())
             .ok_or_else(|| FromArgsError::Missing(name.as_ref().into()))
     }
 
+    /// Removes and returns an optional argument, which may only be given as a
+    /// keyword argument.
+    pub fn pop_optional(&mut self, name: impl AsRef<str>) -> Option<Thunk<'i>> {
+        self.kwargs.shift_remove(name.as_ref())
+    }
+
+    /// The remaining keyword arguments, in the order they're written.
+    pub fn iter_kwargs_in_order(&self) -> impl Iterator<Item = (&str, &Thunk<'i>)> {
+        self.kwargs
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Removes and returns all remaining keyword arguments, in the order
+    /// they're written.
+    pub fn take_kwargs(&mut self) -> Kwargs<'i> {
+        mem::take(&mut self.kwargs)
+    }
+
     /// Returns Err if there are positional or keyword arguments remaining.
-    #[must_use]
     pub fn check_no_args(&self) -> Result<(), FromArgsError> {
         self.check_no_posargs()
             .and_then(|()| self.check_no_kwargs())
     }
 
     /// Returns Err if there are positional arguments remaining.
-    #[must_use]
     pub fn check_no_posargs(&self) -> Result<(), FromArgsError> {
         if self.args.is_empty() {
             Ok(())
@@ -70,7 +154,6 @@ impl<'i> ParsedArgs<'i> {
     }
 
     /// Returns Err if there are keyword arguments remaining.
-    #[must_use]
     pub fn check_no_kwargs(&self) -> Result<(), FromArgsError> {
         if self.kwargs.is_empty() {
             Ok(())
@@ -116,7 +199,20 @@ pub enum FromArgsError {
 }
 
 impl FromArgsError {
-    /// Create an `UnexpectedKeyword` error from the remaining kwargs in `ParsedArgs`.
+    /// The stable code for this error; see `diagnostic::ErrorCode`.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::TooFew => ErrorCode::TooFewArgs,
+            Self::TooMany => ErrorCode::TooManyArgs,
+            Self::Missing(_) => ErrorCode::MissingArg,
+            Self::MissingPositional(_) => ErrorCode::MissingPositionalArg,
+            Self::MissingKeyword(_) => ErrorCode::MissingKeyword,
+            Self::UnexpectedKeyword(_) => ErrorCode::UnknownKeyword,
+        }
+    }
+
+    /// Create an `UnexpectedKeyword` error from the remaining kwargs in
+    /// `ParsedArgs`, listed in the order they're written.
     pub fn from_extra_kwargs(parsed: &ParsedArgs<'_>) -> Self {
         FromArgsError::UnexpectedKeyword(itertools::join(
             parsed.kwargs.keys().map(|k| format!("{:?}", k)),
@@ -124,3 +220,44 @@ impl FromArgsError {
         ))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::env::Environment;
+    use crate::parse::{default_parser, Source, Token};
+
+    /// Parse the arguments of the single command in `src`.
+    fn parse_args<'i>(src: &'i Source) -> ParsedArgs<'i> {
+        let world = World::new(Environment::new(), src);
+        let mut toks = default_parser(src, src.into()).unwrap();
+        match toks.remove(0) {
+            Token::Command(cmd) => {
+                ParsedArgs::from_unparsed(&cmd.args, default_parser, &world).unwrap()
+            }
+            Token::Text(_) => panic!("Expected a command"),
+        }
+    }
+
+    #[test]
+    fn test_kwargs_order() {
+        let src = Source::new("\\x[zeta=1, alpha=2, mu=3, beta=4]{y}".into());
+        let mut args = parse_args(&src);
+        let names: Vec<_> = args.iter_kwargs_in_order().map(|(name, _)| name).collect();
+        assert_eq!(vec!["zeta", "alpha", "mu", "beta"], names);
+
+        args.pop_optional("alpha").unwrap();
+        assert_eq!(
+            FromArgsError::UnexpectedKeyword(r#""zeta","mu","beta""#.into()),
+            args.check_no_kwargs().unwrap_err()
+        );
+        let names: Vec<_> = args
+            .take_kwargs()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(vec!["zeta", "mu", "beta"], names);
+    }
+}
@@ -2,26 +2,43 @@
 //!
 //! Commands provide a parser function, which determines how commands and blocks
 //! in the command's input are detected.
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
 use std::error;
+use std::fmt::{self, Display};
 use std::io::{self, Write};
+use std::ops::{Deref, DerefMut, Range};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::Instant;
 
 use derivative::Derivative;
 use thiserror::Error;
 
-use crate::doc::{BlockInner, Blocks, DocBuilder, DocBuilderError};
+use crate::diagnostic::{self, DiagnosticSpan, ErrorCode, RelatedSpan, Severity};
+use crate::doc::{
+    BlockInner, Blocks, DocBuilder, DocBuilderError, DocBuilderPush, Inline, InvalidRefId,
+    NotPlainText,
+};
 use crate::env::Environment;
-use crate::parse::{self, Argument, Parser, Source, Token, Tokens};
+use crate::parse::{self, Argument, Parser, Source, SourcePosition, Token, Tokens};
 
 mod args;
 mod default_cmd;
+mod profile;
+mod resource;
 mod thunk;
+mod trace;
+mod warning;
 
 pub use args::*;
 pub use default_cmd::*;
+pub use profile::*;
+pub use resource::*;
 pub use thunk::*;
+pub use trace::*;
+pub use warning::*;
 
 /// Memoized information about a particular command; its name, its parser, and
 /// how to construct it.
@@ -41,6 +58,18 @@ pub struct CommandInfoMemo {
     /// *within* the arguments refer to other commands and their arguments.
     #[derivative(Debug = "ignore")]
     pub parser_fn: Parser,
+    /// Whether the command's arguments are parsed with the parser in scope
+    /// (see `World::active_parser`), if any, rather than `parser_fn`.
+    pub inherits_parser: bool,
+    /// The parser in scope while the command is called; see
+    /// `CommandInfo::parser_override`.
+    #[derivative(Debug = "ignore")]
+    pub parser_override: Option<Parser>,
+    /// If the command is deprecated, why and what to use instead.
+    pub deprecated: Option<String>,
+    /// The `Environment::generation` the command was bound in, or 0 if it
+    /// hasn't been bound.
+    pub generation: u64,
 }
 
 impl CommandInfoMemo {
@@ -50,6 +79,10 @@ impl CommandInfoMemo {
             name: C::name(),
             from_args_fn: C::from_args_fn(),
             parser_fn: C::parser_fn(),
+            inherits_parser: C::inherits_parser(),
+            parser_override: C::parser_override(),
+            deprecated: C::deprecated(),
+            generation: 0,
         }
     }
 }
@@ -62,11 +95,32 @@ pub trait CommandInfo {
     fn from_args_fn() -> FromArgs;
     /// The command's embedded parser for interpreting arguments.
     ///
-    /// Currently defaults to `parse::default_parser` but should be inherited
-    /// from the surrounding command.
+    /// Defaults to `parse::default_parser`; see also `inherits_parser`.
     fn parser_fn() -> Parser {
         parse::default_parser
     }
+
+    /// Whether the command's arguments are parsed with the parser installed by
+    /// a surrounding command (see `parser_override`), if any, rather than
+    /// `parser_fn`. Commands with their own parser (set with
+    /// `#[textecca(parser = ...)]`) don't inherit one.
+    fn inherits_parser() -> bool {
+        true
+    }
+
+    /// A parser to install for the commands evaluated while this command is
+    /// called, e.g. in its body, which inherit their parser. This command's own
+    /// arguments are still parsed with `parser_fn`. Set with
+    /// `#[textecca(parser_override = ...)]`.
+    fn parser_override() -> Option<Parser> {
+        None
+    }
+
+    /// If the command is deprecated, why and what to use instead, e.g. `"use
+    /// \\strong instead"`. Set with `#[textecca(deprecated = "...")]`.
+    fn deprecated() -> Option<String> {
+        None
+    }
 }
 
 /// A command, which can be called to render itself as blocks to a particular
@@ -84,21 +138,362 @@ pub trait Command<'i>: std::fmt::Debug {
     /// For example, if this command's `Parser` transformed a `-` at the
     /// beginning of a line into `\item`, the returned environment should have
     /// `\item` bound.
-    fn environment(&self, parent: Rc<Environment>) -> Result<Rc<Environment>, CommandError> {
+    fn environment(&self, parent: Rc<Environment>) -> Result<Rc<Environment>, CommandError<'_>> {
         Ok(parent)
     }
+
+    /// Check this command without calling it, e.g. that the files it refers
+    /// to exist; see `World::check`. This shouldn't force the command's
+    /// arguments.
+    fn check(&self, _world: &World<'i>) -> Result<(), CommandError<'i>> {
+        Ok(())
+    }
 }
 
 /// An evaluation context for `Command`s.
-#[derive(Debug, Clone)]
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
 pub struct World<'i> {
     /// The environment of bindings.
     pub env: Rc<Environment>,
     /// The arena, for generating new tokens.
     pub arena: &'i Source,
+    /// Warnings emitted so far.
+    warnings: RefCell<Vec<Warning>>,
+    /// How recoverable problems are handled.
+    strictness: Strictness,
+    /// Finds the files the document refers to.
+    #[derivative(Debug = "ignore")]
+    resolver: Rc<dyn ResourceResolver>,
+    /// The files being evaluated, relative to the project root; the innermost
+    /// included file is last.
+    files: RefCell<Vec<PathBuf>>,
+    /// Names and generations (see `CommandInfoMemo::generation`) of the
+    /// deprecated commands which have been warned about.
+    deprecations_warned: RefCell<HashSet<(String, u64)>>,
+    /// The files and byte ranges of the arguments which couldn't be parsed
+    /// and have been warned about; see `report_invalid_argument`.
+    invalid_arguments_warned: RefCell<HashSet<(PathBuf, Range<usize>)>>,
+    /// The parsers installed by the commands being called, innermost last;
+    /// see `CommandInfo::parser_override`.
+    #[derivative(Debug = "ignore")]
+    parsers: RefCell<Vec<Parser>>,
+    /// Receives events as commands are called; see `set_tracer`.
+    #[derivative(Debug = "ignore")]
+    tracer: Option<Rc<RefCell<Tracer>>>,
+    /// The number of commands being called, while tracing.
+    trace_depth: Cell<usize>,
 }
 
 impl<'i> World<'i> {
+    /// Create a new evaluation context.
+    pub fn new(env: Rc<Environment>, arena: &'i Source) -> Self {
+        Self {
+            env,
+            arena,
+            warnings: Default::default(),
+            strictness: Default::default(),
+            resolver: Rc::new(FsResolver::new(".")),
+            files: RefCell::new(arena.path().map(Path::to_owned).into_iter().collect()),
+            deprecations_warned: Default::default(),
+            invalid_arguments_warned: Default::default(),
+            parsers: Default::default(),
+            tracer: None,
+            trace_depth: Default::default(),
+        }
+    }
+
+    /// Set how the files the document refers to are found; by default,
+    /// they're relative to the current directory.
+    pub fn with_resolver(self, resolver: Rc<dyn ResourceResolver>) -> Self {
+        Self { resolver, ..self }
+    }
+
+    /// The file being evaluated, relative to the project root; the source's
+    /// path (see `Source::with_path`), or the innermost file being included
+    /// (see `in_file`).
+    pub fn current_file(&self) -> PathBuf {
+        self.files.borrow().last().cloned().unwrap_or_default()
+    }
+
+    /// Whether the content being evaluated is from the source this world was
+    /// created with, rather than from an included file (see `in_file`).
+    pub fn in_source(&self) -> bool {
+        self.files.borrow().len() <= usize::from(self.arena.path().is_some())
+    }
+
+    /// Resolve a path written in the current file to a path relative to the
+    /// project root; see `ResourceResolver::resolve`.
+    pub fn resolve(&self, rel: &str) -> Result<PathBuf, CommandError<'i>> {
+        Ok(self.resolver.resolve(&self.current_file(), rel)?)
+    }
+
+    /// Read a file, given as a path relative to the project root.
+    pub fn read_to_string(&self, path: &Path) -> Result<String, CommandError<'i>> {
+        Ok(self.resolver.read_to_string(path)?)
+    }
+
+    /// Call `f` with `path` (relative to the project root) as the current
+    /// file, e.g. to evaluate an included file. It's an error if `path` is
+    /// already being evaluated.
+    pub fn in_file<T>(
+        &self,
+        path: PathBuf,
+        f: impl FnOnce() -> Result<T, CommandError<'i>>,
+    ) -> Result<T, CommandError<'i>> {
+        if self.files.borrow().contains(&path) {
+            return Err(ResourceError::Cycle(path).into());
+        }
+        self.files.borrow_mut().push(path);
+        let ret = f();
+        self.files.borrow_mut().pop();
+        ret
+    }
+
+    /// Set how recoverable problems are handled; see `report`.
+    pub fn with_strictness(self, strictness: Strictness) -> Self {
+        Self { strictness, ..self }
+    }
+
+    /// How recoverable problems are handled.
+    pub fn strictness(&self) -> Strictness {
+        self.strictness
+    }
+
+    /// Record a warning, to be reported once evaluation is finished.
+    pub fn warn(&self, warning: Warning) {
+        self.warnings.borrow_mut().push(warning);
+    }
+
+    /// Report a recoverable problem in the document; an error, a warning, or
+    /// ignored, depending on this world's `Strictness`.
+    pub fn report(&self, warning: Warning) -> Result<(), CommandError<'i>> {
+        match self.strictness.handle(warning) {
+            Ok(Some(warning)) => self.warn(warning),
+            Ok(None) => {}
+            Err(warning) => return Err(CommandError::Strict(warning)),
+        }
+        Ok(())
+    }
+
+    /// Call `tracer` with a `TraceEvent` as each command is called and
+    /// returns, e.g. to print an expansion tree. Without a tracer, calling
+    /// commands has no tracing overhead. Clones of this world share the
+    /// tracer.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(Rc::new(RefCell::new(tracer)));
+    }
+
+    /// Send an event to the tracer, if there is one.
+    fn trace(&self, event: TraceEvent) {
+        if let Some(tracer) = &self.tracer {
+            (tracer.borrow_mut())(event);
+        }
+    }
+
+    /// The parser installed by the innermost command being called, if any;
+    /// see `CommandInfo::parser_override`.
+    pub fn active_parser(&self) -> Option<Parser> {
+        self.parsers.borrow().last().copied()
+    }
+
+    /// The parser for the arguments of the command described by `info`, given
+    /// the parser in scope.
+    fn parser_for(info: &CommandInfoMemo, active: Option<Parser>) -> Parser {
+        match active {
+            Some(parser) if info.inherits_parser => parser,
+            _ => info.parser_fn,
+        }
+    }
+
+    /// Call `f` with this world, then undo its effects on the world, e.g. to
+    /// try evaluating something and fall back to something else if it fails.
+    ///
+    /// Bindings added to `env`, warnings, and which deprecated commands have
+    /// been warned about are all restored, even if `f` panics. Output isn't;
+    /// evaluate into a scratch `DocBuilder` (or a clone of the current one) to
+    /// discard it along with the metadata it sets.
+    pub fn speculate<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let mut speculation = Speculation::new(self);
+        f(&mut speculation)
+    }
+
+    /// Remove and return the warnings emitted so far.
+    pub fn take_warnings(&self) -> Vec<Warning> {
+        self.warnings.replace(Default::default())
+    }
+
+    /// Parse the arguments of every command in `tokens` up front, recursively,
+    /// with each command's parser, and return all the parse errors found.
+    ///
+    /// Otherwise, a command's arguments are only parsed when the command is
+    /// called, so syntax errors in arguments which are never evaluated (e.g.
+    /// the unused branch of a conditional) go unreported. The parsed arguments
+    /// are kept in `tokens` and reused when the commands are called.
+    ///
+    /// Commands not bound in this world's environment are skipped, as their
+    /// parser isn't known.
+    pub fn validate(&self, tokens: &mut Tokens<'i>) -> Vec<ValidationError<'i>> {
+        let mut errors = Vec::new();
+        self.validate_into(tokens, self.active_parser(), &mut errors);
+        errors
+    }
+
+    /// Like `validate`, but unless this world is strict, the arguments which
+    /// can't be parsed are reported as `WarningKind::InvalidArgument`
+    /// warnings rather than returned, and are only errors if they're used;
+    /// see `ParsedArgs::from_unparsed_lenient`.
+    pub fn validate_or_warn(
+        &self,
+        tokens: &mut Tokens<'i>,
+    ) -> Result<Vec<ValidationError<'i>>, CommandError<'i>> {
+        let errors = self.validate(tokens);
+        if self.strictness == Strictness::Strict {
+            return Ok(errors);
+        }
+        for error in errors {
+            self.report_invalid_argument(error.position, error.span, &*error.error)?;
+        }
+        Ok(Vec::new())
+    }
+
+    /// Report an argument in the current file which couldn't be parsed, the
+    /// first time it's reported.
+    pub(crate) fn report_invalid_argument(
+        &self,
+        position: SourcePosition,
+        span: Range<usize>,
+        error: &dyn error::Error,
+    ) -> Result<(), CommandError<'i>> {
+        let first = self
+            .invalid_arguments_warned
+            .borrow_mut()
+            .insert((self.current_file(), span));
+        if first {
+            self.report(
+                Warning::new(
+                    WarningKind::InvalidArgument,
+                    format!("Couldn't parse argument: {}", error),
+                )
+                .at(Some(position)),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn validate_into(
+        &self,
+        tokens: &mut Tokens<'i>,
+        active: Option<Parser>,
+        errors: &mut Vec<ValidationError<'i>>,
+    ) {
+        for tok in tokens {
+            let cmd = match tok {
+                Token::Command(cmd) => cmd,
+                Token::Text(_) => continue,
+            };
+            let (parser, inner) = match self.env.lookup(cmd.name.fragment()) {
+                Some(info) => (
+                    Self::parser_for(&info, active),
+                    info.parser_override.or(active),
+                ),
+                None => continue,
+            };
+            for arg in &mut cmd.args {
+                match parser(self.arena, arg.value) {
+                    Ok(mut arg_tokens) => {
+                        self.validate_into(&mut arg_tokens, inner, errors);
+                        arg.tokens = Some(arg_tokens);
+                    }
+                    Err(error) => errors.push(ValidationError {
+                        position: self.arena.position(&arg.value),
+                        span: span_range(&arg.value),
+                        error,
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Check every command in `tokens` without evaluating them, recursively,
+    /// and return all the problems found: commands which aren't bound,
+    /// arguments which don't parse or don't fit the command's parameters, and
+    /// problems found by `Command::check`.
+    ///
+    /// Commands are constructed but never called, and arguments are never
+    /// forced, so this is much faster than evaluating the document; problems
+    /// found while evaluating (e.g. math which fails to render) aren't
+    /// reported.
+    pub fn check(&self, tokens: &Tokens<'i>) -> Vec<Diagnostic<'i>> {
+        let mut diagnostics = Vec::new();
+        self.check_into(tokens, self.active_parser(), &mut diagnostics);
+        diagnostics
+    }
+
+    fn check_into(
+        &self,
+        tokens: &Tokens<'i>,
+        active: Option<Parser>,
+        diagnostics: &mut Vec<Diagnostic<'i>>,
+    ) {
+        for tok in tokens {
+            let cmd = match tok {
+                Token::Command(cmd) => cmd,
+                Token::Text(_) => continue,
+            };
+            let span = span_range(&cmd.name);
+            let info = match self.env.cmd_info(cmd.name.fragment()) {
+                Ok(info) => info,
+                Err(error) => {
+                    diagnostics.push(Diagnostic {
+                        position: self.arena.position(&cmd.name),
+                        span,
+                        error,
+                    });
+                    continue;
+                }
+            };
+            let parser = Self::parser_for(&info, active);
+            let mut args = Vec::with_capacity(cmd.args.len());
+            for arg in &cmd.args {
+                let arg_tokens = match &arg.tokens {
+                    Some(tokens) => tokens.clone(),
+                    None => match parser(self.arena, arg.value) {
+                        Ok(tokens) => tokens,
+                        Err(error) => {
+                            diagnostics.push(Diagnostic {
+                                position: self.arena.position(&arg.value),
+                                span: span_range(&arg.value),
+                                error: CommandError::ParseError(error),
+                            });
+                            continue;
+                        }
+                    },
+                };
+                self.check_into(&arg_tokens, info.parser_override.or(active), diagnostics);
+                args.push(Argument {
+                    tokens: Some(arg_tokens),
+                    ..arg.clone()
+                });
+            }
+            if args.len() < cmd.args.len() {
+                // The command can't be constructed without all its arguments.
+                continue;
+            }
+            let checked = ParsedArgs::from_unparsed(&args, parser, self)
+                .map_err(CommandError::ParseError)
+                .and_then(|mut args| Ok((info.from_args_fn)(&mut args)?))
+                .and_then(|cmd| cmd.check(self));
+            if let Err(error) = checked {
+                diagnostics.push(Diagnostic {
+                    position: self.arena.position(&cmd.name),
+                    span,
+                    error,
+                });
+            }
+        }
+    }
+
     /// Construct the given `Command` and parse its arguments.
     pub fn get_cmd(
         &self,
@@ -106,18 +501,253 @@ impl<'i> World<'i> {
     ) -> Result<Box<dyn Command<'i> + 'i>, CommandError<'i>> {
         let name = *cmd.name.fragment();
         let info = self.env.cmd_info(name)?;
-        let mut args = ParsedArgs::from_unparsed(&cmd.args, info.parser_fn, self)
-            .map_err(CommandError::ParseError)?;
+        if let Some(message) = &info.deprecated {
+            self.deprecated(&cmd, info.generation, message)?;
+        }
+        let parser = Self::parser_for(&info, self.active_parser());
+        // Unless problems are errors, arguments which can't be parsed are
+        // only errors if they're used.
+        let mut args = if self.strictness == Strictness::Strict {
+            ParsedArgs::from_unparsed(&cmd.args, parser, self).map_err(CommandError::ParseError)?
+        } else {
+            ParsedArgs::from_unparsed_lenient(&cmd.args, parser, self)?
+        };
         Ok((info.from_args_fn)(&mut args)?)
     }
 
+    /// Report a use of a deprecated command, the first time each command is
+    /// used; a name bound to another deprecated command is reported again.
+    fn deprecated(
+        &self,
+        cmd: &parse::Command<'i>,
+        generation: u64,
+        message: &str,
+    ) -> Result<(), CommandError<'i>> {
+        let name = *cmd.name.fragment();
+        if self
+            .deprecations_warned
+            .borrow_mut()
+            .insert((name.to_owned(), generation))
+        {
+            self.report(
+                Warning::new(
+                    WarningKind::Deprecated,
+                    format!("\\{} is deprecated; {}", name, message),
+                )
+                .at(Some(self.arena.position(&cmd.name))),
+            )?;
+        }
+        Ok(())
+    }
+
     /// Construct and call the given `Command`.
+    ///
+    /// A command which isn't bound in the environment is reported (see
+    /// `report`), and written as literal text. A command's
+    /// `CommandInfo::parser_override` is in scope while it's called.
     pub fn call_cmd(
         &self,
         cmd: parse::Command<'i>,
         doc: &mut DocBuilder,
     ) -> Result<(), CommandError<'i>> {
-        self.get_cmd(cmd)?.call(doc, self)
+        let name = *cmd.name.fragment();
+        let parser_override = match self.env.lookup(name) {
+            Some(info) => info.parser_override,
+            None => {
+                self.report(
+                    Warning::new(
+                        WarningKind::UnknownCommand,
+                        format!("Command {} not defined in current environment", name),
+                    )
+                    .at(Some(self.arena.position(&cmd.name))),
+                )?;
+                doc.push(Inline::Text(command_source(&cmd)))?;
+                return Ok(());
+            }
+        };
+        // Looking up the position isn't free, so it's only done when it's
+        // needed.
+        let name_span = cmd.name;
+        let trace_start = if self.tracer.is_some() {
+            let depth = self.trace_depth.get();
+            self.trace(TraceEvent::Start {
+                name: name.to_owned(),
+                position: self.arena.position(&name_span),
+                args: cmd.args.iter().map(summarize_arg).collect(),
+                depth,
+            });
+            self.trace_depth.set(depth + 1);
+            Some((Instant::now(), doc.pushed()))
+        } else {
+            None
+        };
+        let ret = self.get_cmd(cmd).and_then(|cmd| match parser_override {
+            Some(parser) => {
+                self.parsers.borrow_mut().push(parser);
+                let ret = cmd.call(doc, self);
+                self.parsers.borrow_mut().pop();
+                ret
+            }
+            None => cmd.call(doc, self),
+        });
+        if let Some((start, (blocks, inlines))) = trace_start {
+            let depth = self.trace_depth.get() - 1;
+            self.trace_depth.set(depth);
+            let (blocks_after, inlines_after) = doc.pushed();
+            self.trace(TraceEvent::End {
+                name: name.to_owned(),
+                depth,
+                elapsed: start.elapsed(),
+                blocks: blocks_after - blocks,
+                inlines: inlines_after - inlines,
+                failed: ret.is_err(),
+            });
+        }
+        ret.map_err(|error| CommandError::InCommand {
+            name: name.to_owned(),
+            position: self.arena.position(&name_span),
+            error: Box::new(error),
+        })
+    }
+}
+
+/// A world being used speculatively, which is restored when this is dropped;
+/// see `World::speculate`.
+struct Speculation<'w, 'i> {
+    world: &'w mut World<'i>,
+    /// Changing a binding copies the environment (see `Rc::make_mut`), so
+    /// this is left as it was.
+    env: Rc<Environment>,
+    warnings: usize,
+    deprecations_warned: HashSet<(String, u64)>,
+    files: usize,
+    parsers: usize,
+    trace_depth: usize,
+}
+
+impl<'w, 'i> Speculation<'w, 'i> {
+    fn new(world: &'w mut World<'i>) -> Self {
+        let warnings = world.warnings.borrow().len();
+        let deprecations_warned = world.deprecations_warned.borrow().clone();
+        let files = world.files.borrow().len();
+        let parsers = world.parsers.borrow().len();
+        Self {
+            env: Rc::clone(&world.env),
+            warnings,
+            deprecations_warned,
+            files,
+            parsers,
+            trace_depth: world.trace_depth.get(),
+            world,
+        }
+    }
+}
+
+impl<'i> Deref for Speculation<'_, 'i> {
+    type Target = World<'i>;
+
+    fn deref(&self) -> &Self::Target {
+        self.world
+    }
+}
+
+impl DerefMut for Speculation<'_, '_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.world
+    }
+}
+
+impl Drop for Speculation<'_, '_> {
+    fn drop(&mut self) {
+        self.world.env = Rc::clone(&self.env);
+        self.world.warnings.borrow_mut().truncate(self.warnings);
+        self.world
+            .deprecations_warned
+            .replace(std::mem::take(&mut self.deprecations_warned));
+        self.world.files.borrow_mut().truncate(self.files);
+        self.world.parsers.borrow_mut().truncate(self.parsers);
+        self.world.trace_depth.set(self.trace_depth);
+    }
+}
+
+/// Reconstruct a command's source, e.g. `\link[to=x]{y}`.
+fn command_source(cmd: &parse::Command) -> String {
+    let mut ret = format!("\\{}", cmd.name.fragment());
+    let kwargs: Vec<_> = cmd
+        .args
+        .iter()
+        .filter_map(|arg| {
+            arg.name
+                .map(|name| format!("{}={}", name.fragment(), arg.value.fragment()))
+        })
+        .collect();
+    if !kwargs.is_empty() {
+        ret.push_str(&format!("[{}]", kwargs.join(", ")));
+    }
+    for arg in cmd.args.iter().filter(|arg| arg.name.is_none()) {
+        ret.push_str(&format!("{{{}}}", arg.value.fragment()));
+    }
+    ret
+}
+
+/// The byte range of a span of the source.
+fn span_range(span: &parse::Span) -> Range<usize> {
+    span.location_offset()..span.location_offset() + span.fragment().len()
+}
+
+/// A syntax error in a command's argument, found by `World::validate`.
+#[derive(Debug)]
+pub struct ValidationError<'i> {
+    /// The start of the argument which failed to parse.
+    pub position: SourcePosition,
+    /// The byte range of the argument.
+    pub span: Range<usize>,
+    /// The parser's error.
+    pub error: Box<dyn error::Error + 'i>,
+}
+
+impl ValidationError<'_> {
+    /// This error as a `diagnostic::Diagnostic`, in the given source.
+    pub fn diagnostic(&self, src: &Source) -> diagnostic::Diagnostic {
+        diagnostic::Diagnostic::new(Severity::Error, ErrorCode::Syntax, self.error.to_string())
+            .in_file(src)
+            .with_span(Some(DiagnosticSpan::new(src, self.span.clone())))
+    }
+}
+
+impl Display for ValidationError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.error)
+    }
+}
+
+impl error::Error for ValidationError<'_> {}
+
+/// A problem found by `World::check`.
+#[derive(Debug)]
+pub struct Diagnostic<'i> {
+    /// The position of the command or argument with the problem.
+    pub position: SourcePosition,
+    /// The byte range of the command's name or the argument.
+    pub span: Range<usize>,
+    /// The problem.
+    pub error: CommandError<'i>,
+}
+
+impl Diagnostic<'_> {
+    /// This problem as a `diagnostic::Diagnostic`, in the given source.
+    pub fn diagnostic(&self, src: &Source) -> diagnostic::Diagnostic {
+        let mut ret = self.error.diagnostic(src);
+        if ret.span.is_none() {
+            ret.span = Some(DiagnosticSpan::new(src, self.span.clone()));
+        }
+        ret
+    }
+}
+
+impl Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.error.causes().join(": "))
     }
 }
 
@@ -129,21 +759,40 @@ pub enum CommandError<'i> {
     Type(String),
 
     /// An error while initializing the `Command` from a `ParsedArgs` instance.
-    #[error("Args error: {0}")]
+    #[error("Invalid arguments")]
     FromArgs(#[from] FromArgsError),
 
     /// An unbound command.
     #[error("Command {0} not defined in current environment")]
     Name(String),
 
+    /// A recoverable problem was reported with `Strictness::Strict`; see
+    /// `World::report`.
+    #[error("{0}")]
+    Strict(Warning),
+
     /// An error while parsing the `Command`'s arguments.
     #[error("Parse error: {0}")]
     ParseError(Box<dyn error::Error + 'i>),
 
+    /// An argument which couldn't be parsed was evaluated; see
+    /// `ParsedArgs::from_unparsed_lenient`.
+    #[error("Invalid argument at {}: {}", .0.position, .0.error)]
+    InvalidArgument(Rc<Diagnostic<'i>>),
+
     /// Error while creating the output document.
-    #[error("{0}")]
+    #[error("Invalid document structure")]
     DocBuilder(#[from] DocBuilderError),
 
+    /// Content with no plain text representation was given where text was
+    /// expected; see `Thunk::force_to_string`.
+    #[error(transparent)]
+    NotPlainText(#[from] NotPlainText),
+
+    /// An invalid label was given, e.g. to `\\ref`; see `doc::RefId`.
+    #[error(transparent)]
+    InvalidRefId(#[from] InvalidRefId),
+
     /// A `Thunk` was `Forced` where it was expected to be `Lazy`.
     #[error("Expected thunk to be unevaluated")]
     ForcedThunk,
@@ -151,4 +800,130 @@ pub enum CommandError<'i> {
     /// A `Token` wasn't expected in the input.
     #[error("Unexpected token {0:?}")]
     BadToken(Token<'i>),
+
+    /// An error finding or reading a file; see `World::resolve`.
+    #[error(transparent)]
+    Resource(#[from] ResourceError),
+
+    /// An error while calling a command; see `World::call_cmd`.
+    ///
+    /// The inner error isn't the `source` of this error, as `source` requires
+    /// a `'static` error; use `CommandError::causes` to include it.
+    #[error("In \\{name} at {position}")]
+    InCommand {
+        /// The command's name.
+        name: String,
+        /// The position of the command's name.
+        position: SourcePosition,
+        /// The error.
+        error: Box<CommandError<'i>>,
+    },
+}
+
+impl<'i> CommandError<'i> {
+    /// The stable code for this error; see `diagnostic::ErrorCode`. An `InCommand`
+    /// error has the code of the innermost error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Type(_) => ErrorCode::Type,
+            Self::FromArgs(err) => err.code(),
+            Self::Name(_) => ErrorCode::UnknownCommand,
+            Self::Strict(warning) => warning.kind.code(),
+            Self::ParseError(_) => ErrorCode::Syntax,
+            Self::InvalidArgument(diagnostic) => diagnostic.error.code(),
+            Self::DocBuilder(err) => err.code(),
+            Self::NotPlainText(err) => err.code(),
+            Self::InvalidRefId(err) => err.code(),
+            Self::ForcedThunk => ErrorCode::ForcedThunk,
+            Self::BadToken(_) => ErrorCode::UnexpectedToken,
+            Self::Resource(err) => err.code(),
+            Self::InCommand { error, .. } => error.code(),
+        }
+    }
+
+    /// This error as a `diagnostic::Diagnostic`, in the given source.
+    ///
+    /// The diagnostic's message is the innermost error and its causes, and
+    /// its span is the name of the innermost command the error happened in
+    /// (or the argument, for an `InvalidArgument`); the commands outside that
+    /// one are given as related spans, outermost first.
+    pub fn diagnostic(&self, src: &Source) -> diagnostic::Diagnostic {
+        let mut commands = Vec::new();
+        let mut err = self;
+        while let CommandError::InCommand {
+            name,
+            position,
+            error,
+        } = err
+        {
+            let start = src.position_offset(*position);
+            commands.push(RelatedSpan {
+                span: DiagnosticSpan::new(src, start..start + name.len()),
+                message: format!("In \\{}", name),
+            });
+            err = error;
+        }
+        let (message, span) = match err {
+            CommandError::InvalidArgument(argument) => (
+                err.causes().join(": "),
+                Some(DiagnosticSpan::new(src, argument.span.clone())),
+            ),
+            // The warning's position is given as the span rather than in the
+            // message.
+            CommandError::Strict(warning) => {
+                let command = commands.pop().map(|command| command.span);
+                let span = warning
+                    .position
+                    .map(|position| DiagnosticSpan::at(src, position));
+                (warning.message.clone(), span.or(command))
+            }
+            _ => (
+                err.causes().join(": "),
+                commands.pop().map(|command| command.span),
+            ),
+        };
+        let mut ret = diagnostic::Diagnostic::new(Severity::Error, err.code(), message)
+            .in_file(src)
+            .with_span(span);
+        ret.related = commands;
+        ret
+    }
+
+    /// The innermost error, inside any `InCommand` errors.
+    pub fn innermost(&self) -> &Self {
+        match self {
+            CommandError::InCommand { error, .. } => error.innermost(),
+            err => err,
+        }
+    }
+
+    /// The messages of this error and its causes, outermost first; the
+    /// commands the error happened in, the innermost error, and then the
+    /// innermost error's `source` chain.
+    pub fn causes(&self) -> Vec<String> {
+        let mut ret = Vec::new();
+        let mut err = self;
+        while let CommandError::InCommand { error, .. } = err {
+            ret.push(err.to_string());
+            err = error;
+        }
+        ret.push(err.to_string());
+        let mut source = error::Error::source(err);
+        while let Some(cause) = source {
+            ret.push(cause.to_string());
+            source = cause.source();
+        }
+        ret
+    }
+
+    /// Render `causes` as a list, each cause on its own line and indented
+    /// further than the last.
+    pub fn render_causes(&self) -> String {
+        self.causes()
+            .iter()
+            .enumerate()
+            .map(|(depth, cause)| format!("{}{}", "  ".repeat(depth), cause))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 }
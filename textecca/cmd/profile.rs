@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+use super::TraceEvent;
+
+/// Time spent in each command, accumulated from the `TraceEvent`s sent to a
+/// `Tracer` (see `World::set_tracer`), e.g. to find which commands a
+/// document spends its time in.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// Entries by command name.
+    entries: HashMap<String, ProfileEntry>,
+    /// For each command being called, innermost last, the time spent in the
+    /// commands it called so far.
+    children: Vec<Duration>,
+    /// The number of calls to each command in progress, so time spent in
+    /// recursive calls isn't counted twice in `ProfileEntry::total`.
+    active: HashMap<String, usize>,
+}
+
+/// The time spent in one command; see `Profile`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProfileEntry {
+    /// The command's name.
+    pub name: String,
+    /// The number of times the command was called.
+    pub calls: usize,
+    /// The time spent in the command, including the commands it called.
+    pub total: Duration,
+    /// The time spent in the command itself, excluding the commands it
+    /// called.
+    pub self_time: Duration,
+}
+
+impl Profile {
+    /// Create an empty profile.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Record a command being called or returning.
+    pub fn record(&mut self, event: &TraceEvent) {
+        match event {
+            TraceEvent::Start { name, .. } => {
+                self.children.push(Duration::default());
+                *self.active.entry(name.clone()).or_insert(0) += 1;
+            }
+            TraceEvent::End { name, elapsed, .. } => {
+                let children = self.children.pop().unwrap_or_default();
+                if let Some(parent) = self.children.last_mut() {
+                    *parent += *elapsed;
+                }
+                let active = self.active.entry(name.clone()).or_insert(1);
+                *active -= 1;
+                let outermost = *active == 0;
+
+                let entry = self
+                    .entries
+                    .entry(name.clone())
+                    .or_insert_with(|| ProfileEntry {
+                        name: name.clone(),
+                        ..Default::default()
+                    });
+                entry.calls += 1;
+                entry.self_time += elapsed.checked_sub(children).unwrap_or_default();
+                if outermost {
+                    entry.total += *elapsed;
+                }
+            }
+        }
+    }
+
+    /// The commands called so far, the slowest (by `ProfileEntry::self_time`)
+    /// first.
+    pub fn entries(&self) -> Vec<&ProfileEntry> {
+        let mut ret: Vec<_> = self.entries.values().collect();
+        ret.sort_by(|a, b| b.self_time.cmp(&a.self_time).then(a.name.cmp(&b.name)));
+        ret
+    }
+}
+
+/// Writes the entries as a table, the slowest command first.
+impl Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = self.entries();
+        let width = entries
+            .iter()
+            .map(|entry| entry.name.chars().count() + 1)
+            .chain(Some("command".len()))
+            .max()
+            .unwrap_or_default();
+        writeln!(
+            f,
+            "{:<width$} {:>8} {:>12} {:>12}",
+            "command",
+            "calls",
+            "total",
+            "self",
+            width = width
+        )?;
+        for entry in entries {
+            writeln!(
+                f,
+                "{:<width$} {:>8} {:>12} {:>12}",
+                format!("\\{}", entry.name),
+                entry.calls,
+                format!("{:.3?}", entry.total),
+                format!("{:.3?}", entry.self_time),
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::parse::SourcePosition;
+
+    fn start(name: &str) -> TraceEvent {
+        TraceEvent::Start {
+            name: name.into(),
+            position: SourcePosition { line: 1, column: 1 },
+            args: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    fn end(name: &str, millis: u64) -> TraceEvent {
+        TraceEvent::End {
+            name: name.into(),
+            depth: 0,
+            elapsed: Duration::from_millis(millis),
+            blocks: 0,
+            inlines: 0,
+            failed: false,
+        }
+    }
+
+    fn entry(name: &str, calls: usize, total: u64, self_time: u64) -> ProfileEntry {
+        ProfileEntry {
+            name: name.into(),
+            calls,
+            total: Duration::from_millis(total),
+            self_time: Duration::from_millis(self_time),
+        }
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut profile = Profile::new();
+        // \strong{\emph{\emph{a}} \math{b}} \emph{c}
+        for event in &[
+            start("strong"),
+            start("emph"),
+            start("emph"),
+            end("emph", 2),
+            end("emph", 5),
+            start("math"),
+            end("math", 4),
+            end("strong", 10),
+            start("emph"),
+            end("emph", 1),
+        ] {
+            profile.record(event);
+        }
+        assert_eq!(
+            vec![
+                &entry("emph", 3, 6, 6),
+                &entry("math", 1, 4, 4),
+                &entry("strong", 1, 10, 1),
+            ],
+            profile.entries()
+        );
+        assert_eq!(
+            "command    calls        total         self\n\
+             \\emph          3      6.000ms      6.000ms\n\
+             \\math          1      4.000ms      4.000ms\n\
+             \\strong        1     10.000ms      1.000ms\n",
+            profile.to_string()
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::diagnostic::ErrorCode;
+
+/// Finds and reads the files a document refers to, like images and included
+/// source files.
+///
+/// Paths are relative to a project root: a document's files are given as
+/// paths relative to the root, and paths in a document are resolved relative
+/// to the file they're written in. See `World::resolve`.
+pub trait ResourceResolver {
+    /// Resolve the path `rel`, written in the file `base`, to a path relative
+    /// to the project root. An absolute `rel` is relative to the root.
+    ///
+    /// By default, paths are resolved lexically (without following symlinks),
+    /// and paths outside the project root are an error.
+    fn resolve(&self, base: &Path, rel: &str) -> Result<PathBuf, ResourceError> {
+        resolve_path(base, rel, false)
+    }
+
+    /// Read the file at `path`, relative to the project root.
+    fn read(&self, path: &Path) -> Result<Vec<u8>, ResourceError>;
+
+    /// Read the UTF-8 file at `path`, relative to the project root.
+    fn read_to_string(&self, path: &Path) -> Result<String, ResourceError> {
+        String::from_utf8(self.read(path)?).map_err(|_| ResourceError::Utf8(path.to_owned()))
+    }
+}
+
+/// Resolve `rel`, written in the file `base`, lexically; see
+/// `ResourceResolver::resolve`. If `allow_outside_root` is set, the result
+/// may start with `..` components.
+pub fn resolve_path(
+    base: &Path,
+    rel: &str,
+    allow_outside_root: bool,
+) -> Result<PathBuf, ResourceError> {
+    let rel_path = Path::new(rel);
+    let mut components: Vec<Component> = Vec::new();
+    let dir = if rel_path.has_root() {
+        Path::new("")
+    } else {
+        base.parent().unwrap_or_else(|| Path::new(""))
+    };
+    for component in dir.components().chain(rel_path.components()) {
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => match components.last() {
+                Some(Component::Normal(_)) => {
+                    components.pop();
+                }
+                _ if allow_outside_root => components.push(component),
+                _ => return Err(ResourceError::OutsideRoot(rel.to_owned())),
+            },
+            Component::Normal(_) => components.push(component),
+        }
+    }
+    Ok(components.iter().collect())
+}
+
+/// Resolves paths relative to a directory on the filesystem.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FsResolver {
+    root: PathBuf,
+    allow_outside_root: bool,
+}
+
+impl FsResolver {
+    /// Create a resolver for the project in the given directory.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            allow_outside_root: false,
+        }
+    }
+
+    /// Allow paths outside the project root.
+    pub fn allow_outside_root(self) -> Self {
+        Self {
+            allow_outside_root: true,
+            ..self
+        }
+    }
+
+    /// The project root.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl ResourceResolver for FsResolver {
+    fn resolve(&self, base: &Path, rel: &str) -> Result<PathBuf, ResourceError> {
+        resolve_path(base, rel, self.allow_outside_root)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, ResourceError> {
+        fs::read(self.root.join(path)).map_err(|error| ResourceError::Io {
+            path: path.to_owned(),
+            error,
+        })
+    }
+}
+
+/// Resolves paths to files held in memory, for tests or environments without
+/// a filesystem.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryResolver {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryResolver {
+    /// Create a resolver with no files.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a file at the given path, relative to the project root.
+    pub fn insert(&mut self, path: impl Into<PathBuf>, content: impl Into<Vec<u8>>) {
+        self.files.insert(path.into(), content.into());
+    }
+}
+
+impl ResourceResolver for MemoryResolver {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, ResourceError> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| ResourceError::NotFound(path.to_owned()))
+    }
+}
+
+/// An error finding or reading a resource.
+#[derive(Debug, Error)]
+pub enum ResourceError {
+    /// A path refers to a file outside the project root.
+    #[error("{0:?} is outside the project root")]
+    OutsideRoot(String),
+
+    /// A file doesn't exist.
+    #[error("{} not found", .0.display())]
+    NotFound(PathBuf),
+
+    /// A file couldn't be read.
+    #[error("Couldn't read {}", .path.display())]
+    Io {
+        /// The file's path, relative to the project root.
+        path: PathBuf,
+        /// The error reading it.
+        #[source]
+        error: io::Error,
+    },
+
+    /// A file which should be text isn't valid UTF-8.
+    #[error("{} isn't valid UTF-8", .0.display())]
+    Utf8(PathBuf),
+
+    /// A file includes itself, directly or indirectly.
+    #[error("{} includes itself", .0.display())]
+    Cycle(PathBuf),
+}
+
+impl ResourceError {
+    /// The stable code for this error; see `diagnostic::ErrorCode`.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::OutsideRoot(_) => ErrorCode::OutsideRoot,
+            Self::NotFound(_) => ErrorCode::FileNotFound,
+            Self::Io { .. } => ErrorCode::FileUnreadable,
+            Self::Utf8(_) => ErrorCode::InvalidUtf8,
+            Self::Cycle(_) => ErrorCode::IncludeCycle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve_path() {
+        let resolve = |base: &str, rel: &str| resolve_path(Path::new(base), rel, false).ok();
+        assert_eq!(Some("a.svg".into()), resolve("doc.tc", "a.svg"));
+        assert_eq!(Some("ch/img/a.svg".into()), resolve("ch/1.tc", "img/a.svg"));
+        assert_eq!(
+            Some("img/a.svg".into()),
+            resolve("ch/1.tc", "../img/./a.svg")
+        );
+        assert_eq!(Some("img/a.svg".into()), resolve("ch/1.tc", "/img/a.svg"));
+        assert_eq!(None, resolve("ch/1.tc", "../../a.svg"));
+        assert_eq!(None, resolve("doc.tc", "/../a.svg"));
+        assert_eq!(
+            Some("../a.svg".into()),
+            resolve_path(Path::new("doc.tc"), "../a.svg", true).ok()
+        );
+    }
+}
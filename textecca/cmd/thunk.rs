@@ -1,17 +1,34 @@
 use std::{convert::TryInto, rc::Rc};
 
-use super::{CommandError, ParsedArgs, World};
-use crate::doc::{BlockInner, Blocks, DocBuilder, DocBuilderPush, Inline, Inlines};
+use super::{CommandError, Diagnostic, ParsedArgs, World};
+use crate::doc::{
+    inlines_to_plain_text, BlockInner, Blocks, DocBuilder, DocBuilderPush, Inline, Inlines,
+};
 use crate::env::Environment;
-use crate::parse::{Source, Token, Tokens};
+use crate::parse::{Source, Span, Token, Tokens};
 
 /// A lazily-evaluated `Command` argument.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Thunk<'i> {
     /// An unevaluated sequence of `Tokens`.
     Lazy(Tokens<'i>),
     /// An evaluated sequence of `Blocks`.
     Forced(Blocks),
+    /// An argument which couldn't be parsed; evaluating it gives a
+    /// `CommandError::InvalidArgument`. See `ParsedArgs::from_unparsed_lenient`.
+    Invalid(Rc<Diagnostic<'i>>),
+}
+
+/// `Invalid` thunks are only equal to themselves (or their clones).
+impl PartialEq for Thunk<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Lazy(a), Self::Lazy(b)) => a == b,
+            (Self::Forced(a), Self::Forced(b)) => a == b,
+            (Self::Invalid(a), Self::Invalid(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
 }
 
 impl<'i> From<Tokens<'i>> for Thunk<'i> {
@@ -31,12 +48,21 @@ impl<'i> Thunk<'i> {
     pub fn force(self, world: &World<'i>, doc: &mut DocBuilder) -> Result<(), CommandError<'i>> {
         match self {
             Self::Lazy(tokens) => {
+                // Content from an included file is located at the command
+                // including it.
+                let in_source = world.in_source();
                 for tok in tokens {
                     match tok {
                         Token::Text(sp) => {
+                            if in_source {
+                                doc.set_source_offset(sp.location_offset());
+                            }
                             doc.push(sp)?;
                         }
                         Token::Command(cmd) => {
+                            if in_source {
+                                doc.set_source_offset(cmd.name.location_offset());
+                            }
                             world.call_cmd(cmd, doc)?;
                         }
                     }
@@ -47,22 +73,67 @@ impl<'i> Thunk<'i> {
                 doc.push(blocks)?;
                 Ok(())
             }
+            Self::Invalid(diagnostic) => Err(CommandError::InvalidArgument(diagnostic)),
         }
     }
 
     /// Evaluate the given `Thunk` and return its blocks directly; avoids
     /// manually creating a temporary `DocBuilder`.
-    pub fn into_blocks(self, world: &World<'i>) -> Result<Blocks, CommandError<'i>> {
-        let mut doc = DocBuilder::new();
-        self.force(world, &mut doc)?;
-        Ok(doc.try_into()?)
+    ///
+    /// Paragraph breaks separate paragraphs, but never create empty ones:
+    /// breaks at the start or end of the thunk, or before another block, are
+    /// dropped, and inline content with no breaks (e.g. just `\emph{x}`) is
+    /// a single paragraph. See `DocBuilder`.
+    ///
+    /// Content the thunk collects outside the document flow (see
+    /// `DocBuilder::collect`) is added to `doc`, the builder of the document
+    /// the blocks are for.
+    pub fn into_blocks(
+        self,
+        world: &World<'i>,
+        doc: &mut DocBuilder,
+    ) -> Result<Blocks, CommandError<'i>> {
+        let mut inner = DocBuilder::new_inheriting(doc);
+        self.force(world, &mut inner)?;
+        doc.collect_from(&mut inner);
+        Ok(inner.try_into()?)
     }
 
     /// Evaluate the given `Thunk` and extract its inlines; errors if the `Thunk` renders to `Blocks`.
-    pub fn into_inlines(self, world: &World<'i>) -> Result<Inlines, CommandError<'i>> {
-        let mut doc = DocBuilder::new();
-        self.force(world, &mut doc)?;
-        Ok(doc.try_into()?)
+    /// Collected content is added to `doc`, like with `into_blocks`.
+    pub fn into_inlines(
+        self,
+        world: &World<'i>,
+        doc: &mut DocBuilder,
+    ) -> Result<Inlines, CommandError<'i>> {
+        let mut inner = DocBuilder::new_inheriting(doc);
+        self.force(world, &mut inner)?;
+        doc.collect_from(&mut inner);
+        Ok(inner.try_into()?)
+    }
+
+    /// Evaluate the given `Thunk` and flatten its inlines to plain text with
+    /// `inlines_to_plain_text`, e.g. for use as an attribute value.
+    ///
+    /// Unlike `into_string`, commands are allowed; errors if the `Thunk`
+    /// renders to `Blocks` or to inlines with no plain text, like footnotes.
+    pub fn force_to_string(
+        self,
+        world: &World<'i>,
+        doc: &mut DocBuilder,
+    ) -> Result<String, CommandError<'i>> {
+        Ok(inlines_to_plain_text(&self.into_inlines(world, doc)?)?)
+    }
+
+    /// The span of this thunk's first token, if it's `Lazy` and non-empty.
+    pub fn span(&self) -> Option<Span<'i>> {
+        match self {
+            Thunk::Lazy(toks) => toks.first().map(|tok| match tok {
+                Token::Text(span) => *span,
+                Token::Command(cmd) => cmd.name,
+            }),
+            Thunk::Forced(_) | Thunk::Invalid(_) => None,
+        }
     }
 
     /// Render this `Thunk` as a string if it's `Lazy`, and give an error if it's
@@ -82,6 +153,7 @@ impl<'i> Thunk<'i> {
                 Ok(ret)
             }
             Thunk::Forced(_) => Err(CommandError::ForcedThunk),
+            Thunk::Invalid(diagnostic) => Err(CommandError::InvalidArgument(Rc::clone(diagnostic))),
         }
     }
 }
@@ -0,0 +1,107 @@
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+use crate::parse::{self, SourcePosition};
+
+/// A callback receiving a `TraceEvent` as each command is called and returns;
+/// see `World::set_tracer`.
+pub type Tracer = Box<dyn FnMut(TraceEvent)>;
+
+/// An event in the evaluation of a document, for debugging command expansion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEvent {
+    /// A command is being called.
+    Start {
+        /// The command's name.
+        name: String,
+        /// The position of the command's name.
+        position: SourcePosition,
+        /// A short summary of each argument, e.g. `x` or `alt=A dia…`.
+        args: Vec<String>,
+        /// The number of commands being called around this one.
+        depth: usize,
+    },
+    /// A command returned.
+    End {
+        /// The command's name.
+        name: String,
+        /// The number of commands being called around this one.
+        depth: usize,
+        /// How long the command took, including the commands it called.
+        elapsed: Duration,
+        /// The number of blocks pushed to the output by the command, including
+        /// the commands it called.
+        blocks: usize,
+        /// The number of inlines pushed to the output by the command,
+        /// including the commands it called.
+        inlines: usize,
+        /// Whether the command returned an error.
+        failed: bool,
+    },
+}
+
+impl TraceEvent {
+    /// The number of commands being called around the one this event is for.
+    pub fn depth(&self) -> usize {
+        match self {
+            TraceEvent::Start { depth, .. } | TraceEvent::End { depth, .. } => *depth,
+        }
+    }
+}
+
+/// Writes the event as a line of an expansion tree, indented by its depth.
+impl Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", "  ".repeat(self.depth()))?;
+        match self {
+            TraceEvent::Start {
+                name,
+                position,
+                args,
+                ..
+            } => {
+                write!(f, "\\{}", name)?;
+                for arg in args {
+                    write!(f, "{{{}}}", arg)?;
+                }
+                write!(f, " at {}", position)
+            }
+            TraceEvent::End {
+                name,
+                elapsed,
+                blocks,
+                inlines,
+                failed,
+                ..
+            } => write!(
+                f,
+                "\\{} {} after {:?}: {} blocks, {} inlines",
+                name,
+                if *failed { "failed" } else { "done" },
+                elapsed,
+                blocks,
+                inlines
+            ),
+        }
+    }
+}
+
+/// The longest argument summary written in a `TraceEvent::Start`, in
+/// characters.
+const ARG_SUMMARY_LEN: usize = 24;
+
+/// Summarize a command's argument for a `TraceEvent::Start`.
+pub(super) fn summarize_arg(arg: &parse::Argument) -> String {
+    let text = match arg.name {
+        Some(name) => format!("{}={}", name.fragment().trim(), arg.value.fragment()),
+        None => (*arg.value.fragment()).to_owned(),
+    };
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() > ARG_SUMMARY_LEN {
+        let mut ret: String = text.chars().take(ARG_SUMMARY_LEN - 1).collect();
+        ret.push('…');
+        ret
+    } else {
+        text
+    }
+}
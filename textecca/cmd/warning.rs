@@ -0,0 +1,151 @@
+use std::fmt::{self, Display};
+
+use crate::diagnostic::{Diagnostic, DiagnosticSpan, ErrorCode, Severity};
+use crate::parse::{Source, SourcePosition};
+
+/// A non-fatal problem noticed while evaluating a document; see `World::warn`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// What sort of warning this is.
+    pub kind: WarningKind,
+    /// A human-readable description of the warning.
+    pub message: String,
+    /// Where in the source the warning originated, if known.
+    pub position: Option<SourcePosition>,
+}
+
+/// The kind of a `Warning`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    /// A `\todo` note; these are only reported in non-draft builds.
+    Todo,
+    /// A bidirectional formatting character in the source; see
+    /// `parse::lint_source`.
+    BidiControl,
+    /// A control character with no meaning in the source; see
+    /// `parse::lint_source`.
+    ControlCharacter,
+    /// A deprecated command was used; see `CommandInfo::deprecated`.
+    Deprecated,
+    /// A table row has a different number of cells than the table has
+    /// columns.
+    TableColumns,
+    /// A command which isn't bound in the environment was used; it's written
+    /// as literal text.
+    UnknownCommand,
+    /// A link refers to a label which no figure defines; see
+    /// `doc::undefined_labels`.
+    UndefinedLabel,
+    /// Math which couldn't be rendered; it's written as code.
+    Math,
+    /// Content which the serializer can't write, and skips.
+    Unsupported,
+    /// A local file the output refers to couldn't be copied or embedded; see
+    /// `ser::AssetCollector` and `ser::AssetEmbedder`.
+    MissingAsset,
+    /// A command's argument couldn't be parsed; it's an error if the command
+    /// uses it. See `ParsedArgs::from_unparsed_lenient`.
+    InvalidArgument,
+    /// The document breaks a style rule; see `doc::lint`.
+    Lint,
+    /// A heading's explicit id (see `doc::ID_KEY`) isn't a valid `RefId`; it's
+    /// written as a slug of the id instead.
+    InvalidId,
+}
+
+impl WarningKind {
+    /// The stable code for warnings of this kind; see `diagnostic::ErrorCode`.
+    pub fn code(self) -> ErrorCode {
+        match self {
+            Self::Todo => ErrorCode::Todo,
+            Self::BidiControl => ErrorCode::BidiControl,
+            Self::ControlCharacter => ErrorCode::ControlCharacter,
+            Self::Deprecated => ErrorCode::Deprecated,
+            Self::TableColumns => ErrorCode::TableColumns,
+            Self::UnknownCommand => ErrorCode::UnknownCommandText,
+            Self::UndefinedLabel => ErrorCode::UndefinedLabel,
+            Self::Math => ErrorCode::Math,
+            Self::Unsupported => ErrorCode::Unsupported,
+            Self::MissingAsset => ErrorCode::MissingAsset,
+            Self::InvalidArgument => ErrorCode::InvalidArgument,
+            Self::Lint => ErrorCode::Lint,
+            Self::InvalidId => ErrorCode::InvalidId,
+        }
+    }
+}
+
+/// How recoverable problems (a `Warning` reported with `World::report`, or by
+/// a serializer) are handled. The same level should be given to the `World`
+/// and the serializer (see `ser::SerializerOptions`).
+///
+/// | Problem                        | `Strict` | `Warn`                     | `Lenient`          |
+/// |--------------------------------|----------|----------------------------|--------------------|
+/// | Unknown command                | Error    | Warning; written literally | Written literally  |
+/// | Deprecated command             | Error    | Warning, once per command  | Ignored            |
+/// | Table row/column mismatch      | Error    | Warning                    | Ignored            |
+/// | Undefined `\ref` label         | Error    | Warning                    | Ignored            |
+/// | Math which fails to render     | Error    | Warning; written as code   | Written as code    |
+/// | Content unsupported by output  | Error    | Warning; skipped           | Skipped            |
+/// | Asset which can't be copied    | Error    | Warning; not rewritten     | Not rewritten      |
+/// | Invalid explicit heading id    | Error    | Warning; slugified         | Slugified          |
+///
+/// Warnings which aren't problems with the document, like `\todo` notes and
+/// `parse::lint_source`'s, are always reported with `World::warn`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strictness {
+    /// Recoverable problems are errors.
+    Strict,
+    /// Recoverable problems are warnings.
+    #[default]
+    Warn,
+    /// Recoverable problems are silently worked around.
+    Lenient,
+}
+
+impl Strictness {
+    /// Decide what to do about a recoverable problem: `Err` if it's an error,
+    /// `Ok(Some)` if it should be reported as a warning, and `Ok(None)` if it
+    /// should be ignored.
+    pub fn handle(self, warning: Warning) -> Result<Option<Warning>, Warning> {
+        match self {
+            Self::Strict => Err(warning),
+            Self::Warn => Ok(Some(warning)),
+            Self::Lenient => Ok(None),
+        }
+    }
+}
+
+impl Warning {
+    /// Create a new warning with no source position.
+    pub fn new(kind: WarningKind, message: String) -> Self {
+        Self {
+            kind,
+            message,
+            position: None,
+        }
+    }
+
+    /// Set the source position of this warning.
+    pub fn at(self, position: Option<SourcePosition>) -> Self {
+        Self { position, ..self }
+    }
+
+    /// This warning as a `Diagnostic`, in the given source.
+    pub fn diagnostic(&self, src: &Source) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, self.kind.code(), self.message.clone())
+            .in_file(src)
+            .with_span(
+                self.position
+                    .map(|position| DiagnosticSpan::at(src, position)),
+            )
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "{}: {}", position, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
@@ -0,0 +1,482 @@
+//! Machine-readable diagnostics, e.g. for editor integrations.
+//!
+//! Every error and warning textecca reports has an `ErrorCode`, a short identifier
+//! like `E0101` which stays the same between versions, even if the message
+//! changes. A `Diagnostic` bundles an error or warning with its code and
+//! where it happened in the source, and serializes (with serde) to a stable
+//! format; the command-line interface writes diagnostics as JSON, one per
+//! line, with `--error-format json`:
+//!
+//! ```json
+//! {"version":1,"severity":"error","code":"E0101","message":"Command foo not defined in current environment","file":"doc.tex","span":{"start":1,"end":4,"line":1,"column":2},"related":[],"help":null}
+//! ```
+//!
+//! Fields may be added in future versions of the format, but existing fields
+//! only change when `FORMAT_VERSION` does. The codes are listed in
+//! `doc/error-codes.md`, which is generated from `ErrorCode::ALL`; see
+//! `codes_table`.
+use std::fmt::{self, Display};
+use std::ops::Range;
+
+#[cfg(feature = "cli-support")]
+use serde::{Serialize, Serializer};
+
+use crate::parse::{Source, SourcePosition};
+
+/// The version of the `Diagnostic` format, included in each diagnostic as
+/// `version`.
+pub const FORMAT_VERSION: u32 = 1;
+
+macro_rules! codes {
+    ($($(#[doc = $doc:literal])+ $name:ident = $code:literal,)+) => {
+        /// A stable identifier for a kind of error or warning; see the
+        /// module docs.
+        ///
+        /// Codes starting with `E` are errors, and codes starting with `W` are
+        /// warnings (which are still reported as errors with
+        /// `Strictness::Strict`).
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub enum ErrorCode {
+            $($(#[doc = $doc])+ $name,)+
+        }
+
+        impl ErrorCode {
+            /// Every code, in order.
+            pub const ALL: &'static [ErrorCode] = &[$(ErrorCode::$name,)+];
+
+            /// The code as it's written, e.g. `E0101`.
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $(ErrorCode::$name => $code,)+
+                }
+            }
+
+            /// A description of the errors with this code.
+            pub fn description(self) -> &'static str {
+                match self {
+                    $(ErrorCode::$name => concat!($($doc),+).trim(),)+
+                }
+            }
+        }
+    };
+}
+
+codes! {
+    /// A syntax error in the input or in a command's argument, e.g. an
+    /// unclosed brace.
+    Syntax = "E0001",
+    /// A file couldn't be read or written.
+    Io = "E0002",
+    /// A document metadata value is invalid, e.g. a `lint:` setting; see
+    /// `doc::LintConfig`.
+    InvalidMeta = "E0003",
+
+    /// A command isn't defined.
+    UnknownCommand = "E0101",
+    /// A command was given too few arguments.
+    TooFewArgs = "E0102",
+    /// A command was given too many arguments.
+    TooManyArgs = "E0103",
+    /// A command's mandatory argument is missing.
+    MissingArg = "E0104",
+    /// A command's mandatory positional argument is missing.
+    MissingPositionalArg = "E0105",
+    /// A keyword-only argument was given positionally.
+    MissingKeyword = "E0106",
+    /// A command was given a keyword argument it doesn't take.
+    UnknownKeyword = "E0107",
+    /// An argument has the wrong type, e.g. a word where a number is
+    /// expected.
+    Type = "E0108",
+    /// Content with no plain text representation, e.g. a footnote, was given
+    /// where text is expected.
+    NotPlainText = "E0109",
+    /// A label is empty or contains whitespace; see `doc::RefId`.
+    InvalidLabel = "E0110",
+    /// A command got input it doesn't expect.
+    UnexpectedToken = "E0111",
+    /// An argument was evaluated more than once.
+    ForcedThunk = "E0112",
+
+    /// Inline content was added to an empty term list.
+    EmptyTermList = "E0201",
+    /// Blocks were given where inline content is expected.
+    UnexpectedBlocks = "E0202",
+    /// A caption is outside a figure.
+    CaptionOutsideFigure = "E0203",
+    /// A container was ended without being started.
+    UnopenedContainer = "E0204",
+    /// A container was ended while a container inside it was still open.
+    MismatchedEnd = "E0205",
+    /// A container was started but never ended.
+    UnclosedContainer = "E0206",
+    /// A container is outside the container it must be in, e.g. a list item
+    /// outside a list.
+    OutsideContainer = "E0207",
+    /// A container is directly inside a container which can't hold it.
+    ContainerNotAllowed = "E0208",
+    /// Content is directly inside a container which only holds other
+    /// containers.
+    ContentNotAllowed = "E0209",
+    /// A footnote with several paragraphs is inside inline content.
+    BlocksInInlineFootnote = "E0210",
+
+    /// A path refers to a file outside the project root.
+    OutsideRoot = "E0301",
+    /// A file doesn't exist.
+    FileNotFound = "E0302",
+    /// A file couldn't be read.
+    FileUnreadable = "E0303",
+    /// A text file isn't valid UTF-8.
+    InvalidUtf8 = "E0304",
+    /// A file includes itself.
+    IncludeCycle = "E0305",
+
+    /// There's no serializer for the output format.
+    UnknownFormat = "E0401",
+    /// The document is nested too deeply to write.
+    TooDeep = "E0402",
+    /// A heading has a level the output format doesn't support.
+    BadHeading = "E0403",
+    /// Two headings have the same id, or a heading has an id used for
+    /// another element.
+    DuplicateId = "E0404",
+    /// The output format can't be split into several files.
+    Unsplittable = "E0405",
+    /// Some other error while writing the output.
+    Output = "E0499",
+
+    /// A serialized document is malformed; see `doc::migrate`.
+    InvalidSerializedDoc = "E0501",
+    /// A serialized document is from a newer version of textecca.
+    DocFormatVersion = "E0502",
+
+    /// A `\todo` note.
+    Todo = "W0001",
+    /// A bidirectional formatting character in the source.
+    BidiControl = "W0002",
+    /// A control character with no meaning in the source.
+    ControlCharacter = "W0003",
+    /// A deprecated command was used.
+    Deprecated = "W0004",
+    /// A table row has a different number of cells than the table has
+    /// columns.
+    TableColumns = "W0005",
+    /// A command isn't defined, and is written as literal text.
+    UnknownCommandText = "W0006",
+    /// A link refers to a label which isn't defined.
+    UndefinedLabel = "W0007",
+    /// Math couldn't be rendered, and is written as code.
+    Math = "W0008",
+    /// Content the output format doesn't support was skipped.
+    Unsupported = "W0009",
+    /// A file the output refers to couldn't be copied or embedded.
+    MissingAsset = "W0010",
+    /// A command's argument couldn't be parsed, and is an error if it's used.
+    InvalidArgument = "W0011",
+    /// The document breaks a style rule; see `doc::lint`.
+    Lint = "W0012",
+    /// A heading's id isn't valid, and is replaced with a slug of it.
+    InvalidId = "W0013",
+}
+
+impl ErrorCode {
+    /// Advice for fixing errors with this code, if there's any which applies
+    /// to all of them.
+    pub fn help(self) -> Option<&'static str> {
+        match self {
+            ErrorCode::UnknownCommand | ErrorCode::UnknownCommandText => {
+                Some("Check the command's spelling")
+            }
+            ErrorCode::InvalidLabel => Some("Use a label without spaces, like `fig:results`"),
+            ErrorCode::InvalidId => Some("Use an id without spaces, like `intro`"),
+            ErrorCode::IncludeCycle => Some("Remove the `\\include` which includes the file again"),
+            ErrorCode::BlocksInInlineFootnote => {
+                Some("Move the footnote outside the emphasis or link it's in")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "cli-support")]
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// A Markdown table of every code and its description, as in
+/// `doc/error-codes.md`.
+pub fn codes_table() -> String {
+    let mut ret = String::from("| Code | Description |\n|------|-------------|\n");
+    for code in ErrorCode::ALL {
+        ret.push_str(&format!("| `{}` | {} |\n", code, code.description()));
+    }
+    ret
+}
+
+/// How serious a `Diagnostic` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize))]
+#[cfg_attr(feature = "cli-support", serde(rename_all = "lowercase"))]
+pub enum Severity {
+    /// The document couldn't be written.
+    Error,
+    /// A problem which was worked around.
+    Warning,
+}
+
+/// A range of the source a `Diagnostic` refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize))]
+pub struct DiagnosticSpan {
+    /// The byte offset of the start of the range.
+    pub start: usize,
+    /// The byte offset of the end of the range, exclusive.
+    pub end: usize,
+    /// The 1-indexed line of the start of the range.
+    pub line: u32,
+    /// The 1-indexed column of the start of the range, in characters.
+    pub column: usize,
+}
+
+impl DiagnosticSpan {
+    /// The span of a byte range of the source.
+    pub fn new(src: &Source, range: Range<usize>) -> Self {
+        let SourcePosition { line, column } = src.offset_position(range.start);
+        Self {
+            start: range.start,
+            end: range.end,
+            line,
+            column,
+        }
+    }
+
+    /// The empty span at a position in the source.
+    pub fn at(src: &Source, position: SourcePosition) -> Self {
+        let offset = src.position_offset(position);
+        Self::new(src, offset..offset)
+    }
+}
+
+/// Another part of the source relevant to a `Diagnostic`, e.g. the command an
+/// error happened in.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize))]
+pub struct RelatedSpan {
+    /// Where the related part of the source is.
+    pub span: DiagnosticSpan,
+    /// How it's related.
+    pub message: String,
+}
+
+/// An error or warning, with a stable `ErrorCode` and where it happened; see the
+/// module docs.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize))]
+pub struct Diagnostic {
+    /// The version of the format; `FORMAT_VERSION`.
+    pub version: u32,
+    /// Whether this is an error or a warning.
+    pub severity: Severity,
+    /// What sort of error or warning this is.
+    pub code: ErrorCode,
+    /// A human-readable description of the problem.
+    pub message: String,
+    /// The file the problem is in, relative to the project root, if known.
+    pub file: Option<String>,
+    /// Where the problem is, if known.
+    pub span: Option<DiagnosticSpan>,
+    /// Other parts of the source relevant to the problem.
+    pub related: Vec<RelatedSpan>,
+    /// Advice for fixing the problem.
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    /// Create a diagnostic with no location, and with the code's help.
+    pub fn new(severity: Severity, code: ErrorCode, message: String) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            severity,
+            code,
+            message,
+            file: None,
+            span: None,
+            related: Vec::new(),
+            help: code.help().map(str::to_owned),
+        }
+    }
+
+    /// Set the file this diagnostic is in to the source's path.
+    pub fn in_file(self, src: &Source) -> Self {
+        Self {
+            file: src.path().map(|path| path.display().to_string()),
+            ..self
+        }
+    }
+
+    /// Set where this diagnostic is, if it's known.
+    pub fn with_span(self, span: Option<DiagnosticSpan>) -> Self {
+        Self { span, ..self }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::cmd::{ResourceError, Warning, WarningKind};
+    use crate::ser::SerializerError;
+
+    #[cfg(feature = "cli-support")]
+    fn json(diagnostic: &Diagnostic) -> String {
+        serde_json::to_string(diagnostic).unwrap()
+    }
+
+    #[test]
+    fn test_codes() {
+        let mut seen = HashSet::new();
+        for code in ErrorCode::ALL {
+            let s = code.as_str();
+            assert!(seen.insert(s), "{} is used twice", s);
+            assert_eq!(5, s.len());
+            assert!(s.starts_with('E') || s.starts_with('W'));
+            assert!(s[1..].bytes().all(|b| b.is_ascii_digit()));
+            assert!(!code.description().is_empty());
+        }
+        let mut sorted = ErrorCode::ALL.to_vec();
+        sorted.sort_by_key(|code| code.as_str());
+        assert_eq!(ErrorCode::ALL, &sorted[..]);
+    }
+
+    /// `doc/error-codes.md` lists every code; run with
+    /// `TEXTECCA_UPDATE_CODES=1` to regenerate it.
+    #[test]
+    fn test_codes_table() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../doc/error-codes.md");
+        let expected = format!(
+            "# Error codes\n\n\
+             Every error and warning has a code, which stays the same between \
+             versions;\nsee `--error-format json`. This file is generated by \
+             `cargo test`.\n\n{}",
+            codes_table()
+        );
+        if env::var_os("TEXTECCA_UPDATE_CODES").is_some() {
+            fs::write(&path, &expected).unwrap();
+        }
+        let actual = fs::read_to_string(&path).unwrap_or_default();
+        assert_eq!(
+            expected, actual,
+            "doc/error-codes.md is out of date; run the tests with TEXTECCA_UPDATE_CODES=1"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cli-support")]
+    fn test_warning() {
+        let src = Source::new("one\ntwo \\ref{x}".into()).with_path("doc.tex");
+        let warning = Warning::new(WarningKind::UndefinedLabel, "Undefined label \"x\"".into())
+            .at(Some(SourcePosition { line: 2, column: 6 }));
+        assert_eq!(
+            r#"{"version":1,"severity":"warning","code":"W0007","message":"Undefined label \"x\"","file":"doc.tex","span":{"start":9,"end":9,"line":2,"column":6},"related":[],"help":null}"#,
+            json(&warning.diagnostic(&src))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cli-support")]
+    fn test_resource_error() {
+        let src = Source::new(String::new());
+        let err = ResourceError::Cycle("a.tex".into());
+        let diagnostic = Diagnostic::new(Severity::Error, err.code(), err.to_string());
+        assert_eq!(
+            r#"{"version":1,"severity":"error","code":"E0305","message":"a.tex includes itself","file":null,"span":null,"related":[],"help":"Remove the `\\include` which includes the file again"}"#,
+            json(&diagnostic.in_file(&src))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cli-support")]
+    fn test_serializer_error() {
+        let err = SerializerError::TooDeep { depth: 3 };
+        assert_eq!(
+            r#"{"version":1,"severity":"error","code":"E0402","message":"Document is nested more than 3 levels deep","file":null,"span":null,"related":[],"help":null}"#,
+            json(&Diagnostic::new(
+                Severity::Error,
+                err.code(),
+                err.to_string()
+            ))
+        );
+        let err = SerializerError::Strict(Warning::new(WarningKind::Math, "Bad math".into()));
+        assert_eq!(ErrorCode::Math, err.code());
+    }
+
+    #[test]
+    fn test_offset_position_line_endings() {
+        // Unix, Windows, and old Mac OS line endings, mixed.
+        let src = Source::new("a\nb\r\nc\rd".into());
+        let positions: Vec<_> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|c| src.offset_position(src.find(c).unwrap()))
+            .map(|position| (position.line, position.column))
+            .collect();
+        assert_eq!(vec![(1, 1), (2, 1), (3, 1), (4, 1)], positions);
+        assert_eq!(
+            src.find('d').unwrap(),
+            src.position_offset(SourcePosition { line: 4, column: 1 })
+        );
+        // A `\r` before a `\n` is part of its line.
+        assert_eq!(
+            SourcePosition { line: 2, column: 2 },
+            src.offset_position(src.find('\r').unwrap())
+        );
+    }
+
+    #[test]
+    fn test_offset_position_many_lines() {
+        // Looking up every line's position stays fast, rather than scanning
+        // the source from the start each time.
+        let lines = 200_000;
+        let src = Source::new("line\n".repeat(lines));
+        for line in 0..lines {
+            let offset = line * 5 + 2;
+            let position = src.offset_position(offset);
+            assert_eq!(
+                SourcePosition {
+                    line: line as u32 + 1,
+                    column: 3,
+                },
+                position
+            );
+            assert_eq!(offset, src.position_offset(position));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_position_offset_round_trip(s in "(.|\r|\n)*", n in any::<usize>()) {
+            let src = Source::new(s);
+            let mut offset = n % (src.len() + 1);
+            while !src.is_char_boundary(offset) {
+                offset -= 1;
+            }
+            prop_assert_eq!(offset, src.position_offset(src.offset_position(offset)));
+        }
+    }
+}
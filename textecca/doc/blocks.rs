@@ -1,18 +1,28 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ops::Range;
 
-use super::structure::{Blocks, Inlines, Meta};
+#[cfg(feature = "cli-support")]
+use serde::{Deserialize, Serialize};
+
+use super::structure::{Blocks, Inlines, Meta, ID_KEY};
+use super::{Inline, Length, RefId};
 
 /// A group of blocks tagged with some metadata; metadata is currently
 /// unstructured and its representation will almost certainly change in the
 /// future.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct TaggedBlocks {
-    content: Blocks,
-    meta: Meta,
+    /// The contained blocks.
+    pub content: Blocks,
+    /// The tagged metadata.
+    pub meta: Meta,
 }
 
 /// A table.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct Table {
     /// The table's column specifications; this field holds no data, but
     /// determines the table's display.
@@ -28,6 +38,7 @@ pub type TableRows = Vec<Vec<TableCell>>;
 
 /// A cell in a `Table`.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct TableCell {
     /// The cell's alignment.
     pub alignment: Option<Alignment>,
@@ -55,15 +66,17 @@ impl Default for TableCell {
 /// A column-specification in a `Table`; note that this does *not* include the
 /// column's *contents.*
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct TableColumn {
     /// The column's alignment.
     pub alignment: Alignment,
-    /// Relative width.
-    pub width: f64,
+    /// The column's width, if given.
+    pub width: Option<Length>,
 }
 
 /// A `Table` column's alignment.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub enum Alignment {
     /// Left-aligned.
     Left,
@@ -75,13 +88,51 @@ pub enum Alignment {
     Justify,
 }
 
+impl Alignment {
+    /// The alignment's name, e.g. `"left"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Alignment::Left => "left",
+            Alignment::Right => "right",
+            Alignment::Center => "center",
+            Alignment::Justify => "justify",
+        }
+    }
+}
+
 /// A document heading.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct Heading {
     /// The heading's level in the document hierarchy.
     pub level: i32,
     /// The heading's text.
     pub text: Inlines,
+    /// Extra attributes, e.g. `toc = "false"` to omit the heading from the
+    /// table of contents.
+    pub attrs: Meta,
+}
+
+impl Heading {
+    /// Should this heading be listed in the table of contents?
+    pub fn in_toc(&self) -> bool {
+        self.attrs
+            .get("toc")
+            .map(|toc| toc != "false")
+            .unwrap_or(true)
+    }
+
+    /// The heading's id, for linking to it: the id given in its attributes
+    /// (see `ID_KEY`), or else a slug of its text. An id which isn't a valid
+    /// `RefId` is slugified.
+    pub fn anchor(&self) -> RefId {
+        match self.attrs.get(ID_KEY) {
+            Some(id) => {
+                RefId::new(id.as_str()).unwrap_or_else(|_| RefId::slug(&[Inline::Text(id.clone())]))
+            }
+            None => RefId::slug(&self.text),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -109,6 +160,7 @@ enum HeadingLevel {
 
 /// A list, ordered, unordered, or of defined terms.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct List {
     /// The list's kind.
     pub kind: ListKind,
@@ -118,6 +170,7 @@ pub struct List {
 
 /// A `List`'s type.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub enum ListKind {
     /// An unordered, i.e. bulleted list.
     ///
@@ -137,6 +190,7 @@ pub enum ListKind {
 
 /// An item in a `List`.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct ListItem {
     /// This item's content.
     pub content: Blocks,
@@ -149,6 +203,7 @@ pub struct ListItem {
 ///
 /// [dl]: https://developer.mozilla.org/en-US/docs/Web/HTML/Element/dl
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct TermListItem {
     /// This item's label.
     pub term: Inlines,
@@ -158,6 +213,7 @@ pub struct TermListItem {
 
 /// A figure, i.e. a captioned diagram, image, or similar.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct Figure {
     /// The kind of figure.
     pub kind: FigureKind,
@@ -165,10 +221,27 @@ pub struct Figure {
     pub caption: Inlines,
     /// The figure's content, i.e. the image/diagram/table/etc.
     pub content: Blocks,
+    /// The figure's label, used to refer to it elsewhere in the document.
+    pub label: Option<RefId>,
+    /// The figure's numbered name, e.g. "Figure 3", if `number_figures` has
+    /// numbered it. The caption then starts with the name, e.g. "Figure 3: ".
+    pub number: Option<String>,
+}
+
+impl Figure {
+    /// The caption without the name `number_figures` added to it, for formats
+    /// which number figures themselves, like LaTeX.
+    pub fn unnumbered_caption(&self) -> &[Inline] {
+        match self.number {
+            Some(_) => self.caption.get(2..).unwrap_or_default(),
+            None => &self.caption,
+        }
+    }
 }
 
 /// The kind of figure, used for labelling.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub enum FigureKind {
     /// A figure, diagram, etc.
     Figure,
@@ -180,8 +253,43 @@ pub enum FigureKind {
     Other(String),
 }
 
+impl FigureKind {
+    /// The kind of figure with the given name, e.g. `"table"`; unknown names
+    /// give `FigureKind::Other`.
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "figure" => FigureKind::Figure,
+            "table" => FigureKind::Table,
+            "listing" => FigureKind::Listing,
+            _ => FigureKind::Other(name.to_owned()),
+        }
+    }
+
+    /// A short name for this kind of figure, e.g. `"figure"` or `"table"`.
+    pub fn name(&self) -> &str {
+        match self {
+            FigureKind::Figure => "figure",
+            FigureKind::Table => "table",
+            FigureKind::Listing => "listing",
+            FigureKind::Other(name) => name,
+        }
+    }
+
+    /// The word used to label this kind of figure in English, e.g. `"Figure"`
+    /// in "Figure 3".
+    pub fn label_prefix(&self) -> &str {
+        match self {
+            FigureKind::Figure => "Figure",
+            FigureKind::Table => "Table",
+            FigureKind::Listing => "Listing",
+            FigureKind::Other(name) => name,
+        }
+    }
+}
+
 /// A defined object; a definition of a term, a theorem, an article, etc.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct Defn {
     /// The defined object's name. For a term definition, this would be the term.
     /// For a Wikipedia article, it would be the article title.
@@ -196,6 +304,7 @@ pub struct Defn {
 
 /// A code listing.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct Code {
     /// The code's language, for highlighting. `"plain"` indicates no highlighting.
     pub language: String,
@@ -203,10 +312,47 @@ pub struct Code {
     pub line_numbers: Option<LineNumbers>,
     /// The lines of code themselves.
     pub lines: Vec<Inlines>,
+    /// Ranges of lines to highlight, by their displayed numbers: numbered from
+    /// `line_numbers`' start, or from 1 without line numbers.
+    pub highlight_lines: Vec<Range<u32>>,
+    /// Extra attributes, e.g. `linenos`.
+    pub attrs: Meta,
+}
+
+impl Code {
+    /// The displayed number of the first line; see `LineNumbers::start`.
+    pub fn first_line_number(&self) -> i32 {
+        self.line_numbers.as_ref().map_or(1, |n| n.start)
+    }
+
+    /// Is the line with the given displayed number highlighted? See
+    /// `highlight_lines`.
+    pub fn is_highlighted(&self, line: i32) -> bool {
+        let line = match u32::try_from(line) {
+            Ok(line) => line,
+            Err(_) => return false,
+        };
+        self.highlight_lines
+            .iter()
+            .any(|range| range.contains(&line))
+    }
+}
+
+impl Default for Code {
+    fn default() -> Self {
+        Self {
+            language: "plain".into(),
+            line_numbers: None,
+            lines: Default::default(),
+            highlight_lines: Default::default(),
+            attrs: Default::default(),
+        }
+    }
 }
 
 /// A `Code` listing's line numbers, if any.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct LineNumbers {
     /// The starting line number.
     pub start: i32,
@@ -214,7 +360,25 @@ pub struct LineNumbers {
 
 /// A display math block.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct Math {
     /// The LaTeX math code.
     pub tex: String,
+    /// A textual description of the math for screen readers, if any; see
+    /// `tex_to_speech`.
+    pub alt: Option<String>,
+}
+
+/// A block of content in a particular output format, like an HTML `<div>`,
+/// which that format's serializer writes verbatim; the block form of
+/// `RawInline`. Serializers for other formats leave it out, as they do
+/// content tagged for another format.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
+pub struct RawBlock {
+    /// The format the content is in, as named by `Serializer::format_name`,
+    /// e.g. `html` or `latex`.
+    pub format: String,
+    /// The content, which isn't escaped or reflowed.
+    pub content: String,
 }
@@ -1,30 +1,181 @@
-use std::{convert::TryInto, mem};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::{convert::TryInto, fmt, mem};
 
 use thiserror::Error;
 
 use super::{
-    Block, BlockInner, Blocks, Code, Defn, Doc, Figure, Heading, Id, Inline, Inlines, List,
-    ListItem, Table, TableCell, TermListItem,
+    Block, BlockInner, Blocks, Code, Defn, Doc, Figure, FigureKind, Footnote, Heading, Id, Inline,
+    Inlines, List, ListItem, ListKind, Meta, RefId, Table, TableCell, TaggedBlocks, TermListItem,
+    ERROR_KEY,
 };
+use crate::diagnostic::ErrorCode;
 use crate::parse::Span;
 
 /// A builder for `Doc` instances; `Command`s use a `DocBuilder` to add blocks to an output stream.
+///
+/// Inlines are collected into the current paragraph until a block is pushed.
+/// A paragraph break (an empty `BlockInner::Par`) starts a new paragraph,
+/// which following inlines are added to; a paragraph left with no content
+/// (or only whitespace) when the next block is pushed, or when the enclosing
+/// container or document ends, is dropped, and whitespace at the end of any
+/// other paragraph is removed.
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct DocBuilder {
     doc: Doc,
     inner: DocBuilderInner,
+    /// Containers under construction, innermost last; content is added to the
+    /// innermost container.
+    containers: Vec<Container>,
+    /// The source offset each container in `containers` was begun at; see
+    /// `set_source_offset`.
+    container_offsets: Vec<Option<usize>>,
+    /// The number of blocks pushed so far; see `pushed`.
+    pushed_blocks: usize,
+    /// The number of inlines pushed so far; see `pushed`.
+    pushed_inlines: usize,
 }
 #[derive(Debug, Default, Clone, PartialEq)]
 struct DocBuilderInner {
     current: Inlines,
-    id: Id,
+    /// The source offset of the first of the `current` inlines.
+    current_offset: Option<usize>,
+    /// The source offset of the content being added; see `set_source_offset`.
+    offset: Option<usize>,
+    ids: Rc<RefCell<BlockIds>>,
+}
+
+/// The ids given to a document's blocks, shared by the builders for its
+/// content (see `DocBuilder::new_inheriting`), so that ids are unique in the
+/// whole document.
+#[derive(Debug, Default, PartialEq)]
+struct BlockIds {
+    next: Id,
+    /// The source offset each block starts at, where it's known.
+    offsets: HashMap<Id, usize>,
+}
+
+/// A kind of container which can be built with `DocBuilder::begin`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerKind {
+    /// A `List`, containing only list items.
+    List,
+    /// A `ListItem`; only allowed directly inside a list.
+    ListItem,
+    /// A `Table`, containing only rows.
+    Table,
+    /// A row of a `Table`, containing only cells; only allowed directly inside
+    /// a table.
+    Row,
+    /// A `TableCell`; only allowed directly inside a row.
+    Cell,
+    /// A `Figure`; see also `DocBuilder::begin_figure`.
+    Figure,
+    /// A block quotation.
+    Quote,
+    /// A `Footnote`. Unlike other containers, this is added to the enclosing
+    /// content as an inline.
+    Footnote,
+}
+
+impl ContainerKind {
+    /// The container this kind must be directly inside, if any.
+    fn parent(self) -> Option<ContainerKind> {
+        match self {
+            ContainerKind::ListItem => Some(ContainerKind::List),
+            ContainerKind::Row => Some(ContainerKind::Table),
+            ContainerKind::Cell => Some(ContainerKind::Row),
+            _ => None,
+        }
+    }
+
+    /// The only container allowed directly inside this kind, if it's
+    /// restricted.
+    fn child(self) -> Option<ContainerKind> {
+        match self {
+            ContainerKind::List => Some(ContainerKind::ListItem),
+            ContainerKind::Table => Some(ContainerKind::Row),
+            ContainerKind::Row => Some(ContainerKind::Cell),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ContainerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ContainerKind::List => "list",
+            ContainerKind::ListItem => "list item",
+            ContainerKind::Table => "table",
+            ContainerKind::Row => "table row",
+            ContainerKind::Cell => "table cell",
+            ContainerKind::Figure => "figure",
+            ContainerKind::Quote => "quote",
+            ContainerKind::Footnote => "footnote",
+        })
+    }
+}
+
+/// A container under construction.
+#[derive(Debug, Clone, PartialEq)]
+enum Container {
+    List(List),
+    ListItem(Blocks),
+    Table(Table),
+    Row(Vec<TableCell>),
+    Cell(Blocks),
+    Figure(Figure),
+    Quote(Blocks),
+    Footnote {
+        content: Blocks,
+        /// The enclosing inlines, restored when the footnote ends.
+        outer: Inlines,
+        /// The source offset of the first of the enclosing inlines.
+        outer_offset: Option<usize>,
+    },
+}
+
+impl Container {
+    fn kind(&self) -> ContainerKind {
+        match self {
+            Container::List(_) => ContainerKind::List,
+            Container::ListItem(_) => ContainerKind::ListItem,
+            Container::Table(_) => ContainerKind::Table,
+            Container::Row(_) => ContainerKind::Row,
+            Container::Cell(_) => ContainerKind::Cell,
+            Container::Figure(_) => ContainerKind::Figure,
+            Container::Quote(_) => ContainerKind::Quote,
+            Container::Footnote { .. } => ContainerKind::Footnote,
+        }
+    }
+
+    /// The blocks content is added to, if this container holds any.
+    fn blocks_mut(&mut self) -> Option<&mut Blocks> {
+        match self {
+            Container::ListItem(blocks)
+            | Container::Cell(blocks)
+            | Container::Figure(Figure {
+                content: blocks, ..
+            })
+            | Container::Quote(blocks)
+            | Container::Footnote {
+                content: blocks, ..
+            } => Some(blocks),
+            Container::List(_) | Container::Table(_) | Container::Row(_) => None,
+        }
+    }
 }
 
 impl TryInto<Doc> for DocBuilder {
     type Error = DocBuilderError;
     fn try_into(self) -> Result<Doc, Self::Error> {
         let mut self_ = self;
+        if let Some(container) = self_.containers.last() {
+            return Err(DocBuilderError::Unclosed(container.kind()));
+        }
         self_.drain_current()?;
+        finish_par(&mut self_.doc.content);
         Ok(self_.doc)
     }
 }
@@ -43,16 +194,20 @@ impl TryInto<Inlines> for DocBuilder {
         let doc: Doc = self.try_into()?;
         let mut blocks = doc.content;
 
-        if blocks.is_empty() {
-            Ok(Default::default())
-        } else {
-            let block = blocks
-                .pop()
-                .ok_or_else(|| DocBuilderError::UnexpectedBlocks(blocks))?;
-            match block.inner {
-                BlockInner::Plain(inlines) | BlockInner::Par(inlines) => Ok(inlines),
-                _ => Err(DocBuilderError::UnexpectedBlocks(block.into())),
+        match blocks.len() {
+            0 => Ok(Default::default()),
+            1 => {
+                let block = blocks.pop().expect("Checked length");
+                match block.inner {
+                    BlockInner::Plain(inlines) | BlockInner::Par(inlines) => Ok(inlines),
+                    _ => Err(DocBuilderError::UnexpectedBlocks(BlocksSummary::of(&[
+                        block,
+                    ]))),
+                }
             }
+            _ => Err(DocBuilderError::UnexpectedBlocks(BlocksSummary::of(
+                &blocks,
+            ))),
         }
     }
 }
@@ -63,16 +218,322 @@ impl DocBuilder {
         Default::default()
     }
 
-    /// Create a new builder inheriting from the given parent.
-    pub fn new_inheriting(_parent: &Self) -> Self {
-        Default::default()
+    /// Create a new builder for content evaluated on its own which belongs to
+    /// the document `parent` builds, e.g. a command's argument. Its blocks'
+    /// ids continue from the parent's, and the parent's ids continue from
+    /// its, so ids are unique in the whole document.
+    pub fn new_inheriting(parent: &Self) -> Self {
+        let mut ret = Self::new();
+        ret.inner.ids = Rc::clone(&parent.inner.ids);
+        ret.inner.offset = parent.inner.offset;
+        ret
+    }
+
+    /// Set the byte offset in the source of the content about to be added,
+    /// e.g. of the token being evaluated; `Thunk::force` sets this for each
+    /// token. Blocks are recorded as starting where their first content was
+    /// added; see `try_into_located`.
+    pub fn set_source_offset(&mut self, offset: usize) {
+        self.inner.offset = Some(offset);
+    }
+
+    /// Finish the document, along with the byte offset in the source each of
+    /// its blocks starts at, by id, for blocks built after `set_source_offset`
+    /// was called. This includes blocks built by builders inheriting from
+    /// this one, e.g. in footnotes.
+    pub fn try_into_located(self) -> Result<(Doc, HashMap<Id, usize>), DocBuilderError> {
+        let ids = Rc::clone(&self.inner.ids);
+        let doc = self.try_into()?;
+        let offsets = mem::take(&mut ids.borrow_mut().offsets);
+        Ok((doc, offsets))
+    }
+
+    /// The number of blocks and inlines pushed to this builder so far, e.g.
+    /// to see how much a command wrote; a pushed `Blocks` or `Inlines` counts
+    /// each element.
+    pub fn pushed(&self) -> (usize, usize) {
+        (self.pushed_blocks, self.pushed_inlines)
+    }
+
+    /// Set a document metadata value, e.g. `draft`.
+    pub fn set_meta(&mut self, key: String, value: String) {
+        self.doc.meta.insert(key, value);
+    }
+
+    /// Add some blocks to the end of a channel of `Doc::collections`, rather
+    /// than to the document's content.
+    ///
+    /// Collected blocks belong to the document this builder builds; content
+    /// evaluated on its own, e.g. with `Thunk::into_blocks`, is moved to the
+    /// document it's for with `collect_from`.
+    pub fn collect(&mut self, channel: &str, blocks: Blocks) {
+        self.doc
+            .collections
+            .entry(channel.to_owned())
+            .or_default()
+            .push(blocks);
+    }
+
+    /// Move the blocks collected by `inner`, a builder for content evaluated
+    /// on its own, to the end of this builder's channels.
+    pub fn collect_from(&mut self, inner: &mut Self) {
+        for (channel, blocks) in mem::take(&mut inner.doc.collections) {
+            self.doc
+                .collections
+                .entry(channel)
+                .or_default()
+                .extend(blocks);
+        }
+    }
+
+    /// Start building a container; until the matching `end`, content is added
+    /// to it.
+    ///
+    /// Errors if the container isn't allowed in the innermost open container,
+    /// e.g. a list item outside a list.
+    pub fn begin(&mut self, kind: ContainerKind) -> Result<(), DocBuilderError> {
+        let container = match kind {
+            ContainerKind::List => Container::List(List {
+                kind: ListKind::Unordered,
+                items: Vec::new(),
+            }),
+            ContainerKind::ListItem => Container::ListItem(Default::default()),
+            ContainerKind::Table => Container::Table(Table {
+                columns: Vec::new(),
+                cells: Vec::new(),
+            }),
+            ContainerKind::Row => Container::Row(Vec::new()),
+            ContainerKind::Cell => Container::Cell(Default::default()),
+            ContainerKind::Figure => Container::Figure(Figure {
+                kind: FigureKind::Figure,
+                caption: Default::default(),
+                content: Default::default(),
+                label: None,
+                number: None,
+            }),
+            ContainerKind::Quote => Container::Quote(Default::default()),
+            ContainerKind::Footnote => Container::Footnote {
+                content: Default::default(),
+                outer: Default::default(),
+                outer_offset: None,
+            },
+        };
+        self.begin_container(container)
+    }
+
+    /// Start building a figure; until the matching `end`, blocks are added to
+    /// the figure's content.
+    pub fn begin_figure(
+        &mut self,
+        kind: FigureKind,
+        label: Option<RefId>,
+    ) -> Result<(), DocBuilderError> {
+        self.begin_container(Container::Figure(Figure {
+            kind,
+            caption: Default::default(),
+            content: Default::default(),
+            label,
+            number: None,
+        }))
+    }
+
+    fn begin_container(&mut self, mut container: Container) -> Result<(), DocBuilderError> {
+        let kind = container.kind();
+        let parent = self.containers.last().map(Container::kind);
+        match (kind.parent(), parent) {
+            (Some(required), parent) if parent != Some(required) => {
+                return Err(DocBuilderError::OutsideContainer {
+                    kind,
+                    parent: required,
+                });
+            }
+            (None, Some(parent)) if parent.child().is_some() => {
+                return Err(DocBuilderError::ContainerNotAllowed { kind, parent });
+            }
+            _ => {}
+        }
+        if let Container::Footnote {
+            outer,
+            outer_offset,
+            ..
+        } = &mut container
+        {
+            *outer_offset = self.inner.current_offset.take();
+            *outer = self.inner.take_current();
+        } else {
+            self.drain_current()?;
+        }
+        self.containers.push(container);
+        self.container_offsets.push(self.inner.offset);
+        Ok(())
+    }
+
+    /// Finish the innermost container, which must be of the given kind, and add
+    /// it to the enclosing content.
+    pub fn end(&mut self, kind: ContainerKind) -> Result<(), DocBuilderError> {
+        match self.containers.last().map(Container::kind) {
+            Some(open) if open == kind => {}
+            Some(open) if self.containers.iter().any(|c| c.kind() == kind) => {
+                return Err(DocBuilderError::MismatchedEnd { open, end: kind });
+            }
+            _ => return Err(DocBuilderError::Unopened(kind)),
+        }
+        self.drain_current()?;
+        let mut container = self.containers.pop().expect("Checked above");
+        if let Some(blocks) = container.blocks_mut() {
+            finish_par(blocks);
+        }
+        // The container's block starts where the container was begun.
+        let begun_at = self.container_offsets.pop().flatten();
+        let offset = mem::replace(&mut self.inner.offset, begun_at);
+        let result = self.end_container(container);
+        self.inner.offset = offset;
+        result
+    }
+
+    /// Add a finished container to the enclosing content.
+    fn end_container(&mut self, container: Container) -> Result<(), DocBuilderError> {
+        match container {
+            Container::List(list) => self.push(BlockInner::List(list))?,
+            Container::Table(table) => self.push(BlockInner::Table(table))?,
+            Container::Figure(figure) => self.push(BlockInner::Figure(figure))?,
+            Container::Quote(blocks) => self.push(BlockInner::Quote(blocks))?,
+            Container::ListItem(content) => match self.containers.last_mut() {
+                Some(Container::List(list)) => list.items.push(ListItem { content }),
+                _ => unreachable!("List items are only begun inside lists"),
+            },
+            Container::Row(row) => match self.containers.last_mut() {
+                Some(Container::Table(table)) => table.cells.push(row),
+                _ => unreachable!("Rows are only begun inside tables"),
+            },
+            Container::Cell(content) => match self.containers.last_mut() {
+                Some(Container::Row(row)) => row.push(TableCell {
+                    content,
+                    ..Default::default()
+                }),
+                _ => unreachable!("Cells are only begun inside rows"),
+            },
+            Container::Footnote {
+                content,
+                outer,
+                outer_offset,
+            } => {
+                self.inner.current = outer;
+                self.inner.current_offset = outer_offset;
+                self.inner
+                    .current
+                    .push(Inline::Footnote(Footnote { content }));
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the caption of the innermost figure under construction.
+    pub fn set_caption(&mut self, caption: Inlines) -> Result<(), DocBuilderError> {
+        let figure = self
+            .containers
+            .iter_mut()
+            .rev()
+            .find_map(|container| match container {
+                Container::Figure(figure) => Some(figure),
+                _ => None,
+            });
+        match figure {
+            Some(figure) => {
+                figure.caption = caption;
+                Ok(())
+            }
+            None => Err(DocBuilderError::CaptionOutsideFigure),
+        }
+    }
+
+    /// End all open containers, innermost first, e.g. after a command failed
+    /// partway through building one. Content which can't be added where it
+    /// was pushed (e.g. text directly inside a list) is dropped, so this
+    /// always leaves the builder ready to convert into a `Doc`.
+    pub fn close_all(&mut self) {
+        while let Some(container) = self.containers.last() {
+            let kind = container.kind();
+            if self.drain_current().is_err() {
+                self.inner.current.clear();
+            }
+            if self.end(kind).is_err() {
+                self.containers.pop();
+            }
+        }
+        if self.drain_current().is_err() {
+            self.inner.current.clear();
+        }
+    }
+
+    /// Close all open containers (see `close_all`) and add a placeholder for
+    /// an error, tagged with `ERROR_KEY`, showing the given message as plain
+    /// code. Used to write a document which failed to evaluate up to the
+    /// point of failure.
+    pub fn push_error(&mut self, message: &str) {
+        self.close_all();
+        finish_par(&mut self.doc.content);
+        let code = Code {
+            lines: message
+                .lines()
+                .map(|line| vec![Inline::Text(line.to_owned())])
+                .collect(),
+            ..Default::default()
+        };
+        let mut meta = Meta::new();
+        meta.insert(ERROR_KEY.to_owned(), "true".to_owned());
+        let code = self.inner.block_from_inner(BlockInner::Code(code));
+        let tagged = self
+            .inner
+            .block_from_inner(BlockInner::Tagged(TaggedBlocks {
+                content: vec![code].into(),
+                meta,
+            }));
+        self.pushed_blocks += 1;
+        self.doc.content.push(tagged);
+    }
+
+    /// The blocks currently being added to; either the innermost container's
+    /// or the document's.
+    fn blocks_mut(&mut self) -> Result<&mut Blocks, DocBuilderError> {
+        match self.containers.last_mut() {
+            Some(container) => {
+                let kind = container.kind();
+                container
+                    .blocks_mut()
+                    .ok_or(DocBuilderError::ContentNotAllowed(kind))
+            }
+            None => Ok(&mut self.doc.content),
+        }
+    }
+
+    /// Whether the innermost container only holds other containers, e.g. a
+    /// list, which only holds list items.
+    fn in_restricted_container(&self) -> bool {
+        self.containers
+            .last()
+            .and_then(|container| container.kind().child())
+            .is_some()
     }
 
     fn drain_current(&mut self) -> Result<(), DocBuilderError> {
         if self.inner.is_empty() {
             Ok(())
+        } else if self.inner.is_blank() && self.in_restricted_container() {
+            // Whitespace between e.g. list items.
+            self.inner.current.clear();
+            Ok(())
         } else {
-            self.inner.add_to_blocks(&mut self.doc.content)
+            let blocks = match self.containers.last_mut() {
+                Some(container) => {
+                    let kind = container.kind();
+                    container
+                        .blocks_mut()
+                        .ok_or(DocBuilderError::ContentNotAllowed(kind))?
+                }
+                None => &mut self.doc.content,
+            };
+            self.inner.add_to_blocks(blocks)
         }
     }
 }
@@ -82,33 +543,56 @@ impl DocBuilderInner {
         self.current.is_empty()
     }
 
-    fn inc_id(&mut self) -> Id {
-        let id = self.id;
-        self.id = self.id.next().unwrap();
-        id
+    /// Whether the current inlines are all whitespace.
+    fn is_blank(&self) -> bool {
+        is_blank(&self.current)
+    }
+
+    /// Add inlines to the current inlines.
+    fn push_current(&mut self, inlines: impl IntoIterator<Item = Inline>) {
+        if self.current.is_empty() {
+            self.current_offset = self.offset;
+        }
+        self.current.extend(inlines);
     }
 
     fn take_current(&mut self) -> Inlines {
         mem::take(&mut self.current)
     }
 
-    fn block_from_inner(&mut self, inner: BlockInner) -> Block {
-        Block {
-            id: self.inc_id(),
-            inner,
+    /// Record that a block starts at the given source offset.
+    fn set_block_offset(&self, id: Id, offset: Option<usize>) {
+        if let Some(offset) = offset {
+            self.ids.borrow_mut().offsets.insert(id, offset);
         }
     }
 
-    fn to_block(&mut self) -> Block {
+    fn block_from_inner(&mut self, inner: BlockInner) -> Block {
+        self.block_at(inner, self.offset)
+    }
+
+    fn block_at(&mut self, inner: BlockInner, offset: Option<usize>) -> Block {
+        let id = {
+            let mut ids = self.ids.borrow_mut();
+            let id = ids.next;
+            ids.next = ids.next.next().unwrap();
+            id
+        };
+        self.set_block_offset(id, offset);
+        Block { id, inner }
+    }
+
+    fn take_block(&mut self) -> Block {
         let inner = BlockInner::Par(self.take_current());
-        self.block_from_inner(inner)
+        let offset = self.current_offset.take().or(self.offset);
+        self.block_at(inner, offset)
     }
 
     fn add_to_list(&mut self, list: &mut List) -> Result<(), DocBuilderError> {
         match list.items.last_mut() {
             None => {
                 list.items.push(ListItem {
-                    content: self.to_block().into(),
+                    content: self.take_block().into(),
                 });
                 Ok(())
             }
@@ -116,7 +600,7 @@ impl DocBuilderInner {
         }
     }
 
-    fn add_to_termlist(&mut self, list: &mut Vec<TermListItem>) -> Result<(), DocBuilderError> {
+    fn add_to_termlist(&mut self, list: &mut [TermListItem]) -> Result<(), DocBuilderError> {
         match list.last_mut() {
             None => Err(DocBuilderError::EmptyTermList),
             Some(item) => {
@@ -126,21 +610,6 @@ impl DocBuilderInner {
         }
     }
 
-    fn add_to_table(&mut self, table: &mut Table) {
-        match table.cells.last_mut().and_then(|row| row.last_mut()) {
-            None => {
-                let mut row = Vec::with_capacity(table.columns.len());
-                let inner = BlockInner::Plain(self.take_current());
-                row.push(TableCell {
-                    content: self.block_from_inner(inner).into(),
-                    ..Default::default()
-                });
-                table.cells.push(row);
-            }
-            Some(_) => {}
-        }
-    }
-
     fn add_to_code(&mut self, code: &mut Code) {
         match code.lines.last_mut() {
             None => {
@@ -152,15 +621,9 @@ impl DocBuilderInner {
         }
     }
 
-    #[must_use]
     fn add_to_block(&mut self, block: &mut BlockInner) -> Result<Option<Block>, DocBuilderError> {
         match block {
-            BlockInner::Plain(inlines)
-            | BlockInner::Par(inlines)
-            | BlockInner::Heading(Heading { text: inlines, .. })
-            | BlockInner::Figure(Figure {
-                caption: inlines, ..
-            }) => {
+            BlockInner::Plain(inlines) | BlockInner::Par(inlines) => {
                 inlines.append(&mut self.current);
             }
 
@@ -171,11 +634,20 @@ impl DocBuilderInner {
                 self.add_to_blocks(blocks)?;
             }
 
-            BlockInner::Rule | BlockInner::Math(_) => return Ok(Some(self.to_block())),
+            // Headings are pushed with their text; whitespace after one is
+            // dropped, and anything else starts a new paragraph.
+            BlockInner::Heading(_) if self.is_blank() => self.current.clear(),
+
+            BlockInner::Heading(_)
+            | BlockInner::Rule
+            | BlockInner::Math(_)
+            | BlockInner::Raw(_)
+            | BlockInner::Table(_)
+            | BlockInner::Figure(_)
+            | BlockInner::Tagged(_) => return Ok(Some(self.take_block())),
 
             BlockInner::Code(code) => self.add_to_code(code),
             BlockInner::List(list) => self.add_to_list(list)?,
-            BlockInner::Table(table) => self.add_to_table(table),
             BlockInner::TermList(list) => self.add_to_termlist(list)?,
         }
         Ok(None)
@@ -184,9 +656,14 @@ impl DocBuilderInner {
     fn add_to_blocks(&mut self, blocks: &mut Blocks) -> Result<(), DocBuilderError> {
         match blocks.last_mut() {
             None => {
-                blocks.push(self.to_block());
+                blocks.push(self.take_block());
             }
             Some(block) => {
+                // A paragraph started by a paragraph break starts at its
+                // first content, not at the break.
+                if matches!(&block.inner, BlockInner::Par(inlines) if is_blank(inlines)) {
+                    self.set_block_offset(block.id, self.current_offset);
+                }
                 if let Some(new_block) = self.add_to_block(block)? {
                     blocks.push(new_block);
                 }
@@ -202,47 +679,121 @@ pub trait DocBuilderPush<T> {
     fn push(&mut self, elem: T) -> Result<(), DocBuilderError>;
 }
 
+/// Finish the last block if it's a paragraph, before the next block or the
+/// end of the enclosing content. A paragraph with no content, e.g. from a
+/// paragraph break with no text after it, is removed; otherwise, whitespace
+/// at its end, e.g. from the newline ending its last line, is.
+fn finish_par(blocks: &mut Blocks) {
+    if let Some(BlockInner::Par(inlines)) = blocks.last_mut().map(|block| &mut block.inner) {
+        if is_blank(inlines) {
+            blocks.pop();
+        } else {
+            while inlines.last().is_some_and(is_whitespace) {
+                inlines.pop();
+            }
+        }
+    }
+}
+
+/// Whether the inlines are all whitespace.
+fn is_blank(inlines: &[Inline]) -> bool {
+    inlines.iter().all(is_whitespace)
+}
+
+/// Whether the inline is whitespace, like a space or text with only spaces.
+fn is_whitespace(inline: &Inline) -> bool {
+    match inline {
+        Inline::Text(text) => text.trim().is_empty(),
+        Inline::Space => true,
+        _ => false,
+    }
+}
+
 impl DocBuilderPush<BlockInner> for DocBuilder {
     fn push(&mut self, elem: BlockInner) -> Result<(), DocBuilderError> {
+        self.pushed_blocks += 1;
         self.drain_current()?;
-        self.doc.content.push(self.inner.block_from_inner(elem));
+        if let BlockInner::Par(inlines) | BlockInner::Plain(inlines) = &elem {
+            if is_blank(inlines) && self.in_restricted_container() {
+                // Paragraph breaks between e.g. list items.
+                return Ok(());
+            }
+        }
+        let block = self.inner.block_from_inner(elem);
+        let blocks = self.blocks_mut()?;
+        finish_par(blocks);
+        blocks.push(block);
         Ok(())
     }
 }
 
 impl DocBuilderPush<Blocks> for DocBuilder {
     fn push(&mut self, elem: Blocks) -> Result<(), DocBuilderError> {
+        self.pushed_blocks += elem.len();
         self.drain_current()?;
         let mut elem = elem;
-        self.doc.content.append(&mut elem);
+        let blocks = self.blocks_mut()?;
+        finish_par(blocks);
+        blocks.append(&mut elem);
         Ok(())
     }
 }
 
 impl DocBuilderPush<Inline> for DocBuilder {
     fn push(&mut self, elem: Inline) -> Result<(), DocBuilderError> {
-        self.inner.current.push(elem);
+        self.pushed_inlines += 1;
+        self.inner.push_current(Some(elem));
         Ok(())
     }
 }
 
 impl DocBuilderPush<Inlines> for DocBuilder {
     fn push(&mut self, elem: Inlines) -> Result<(), DocBuilderError> {
-        let mut elem = elem;
-        self.inner.current.append(&mut elem);
+        self.pushed_inlines += elem.len();
+        self.inner.push_current(elem);
         Ok(())
     }
 }
 
 impl<'i> DocBuilderPush<Span<'i>> for DocBuilder {
     fn push(&mut self, elem: Span<'i>) -> Result<(), DocBuilderError> {
+        self.pushed_inlines += 1;
         self.inner
-            .current
-            .push(Inline::Text(elem.fragment().to_string()));
+            .push_current(Some(Inline::Text(elem.fragment().to_string())));
         Ok(())
     }
 }
 
+/// A description of some `Blocks` for error messages, which is readable no
+/// matter how much content there is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlocksSummary {
+    /// The number of blocks.
+    pub count: usize,
+    /// The kind of the first block (see `BlockInner::kind_name`), if any.
+    pub first_kind: Option<&'static str>,
+}
+
+impl BlocksSummary {
+    /// Summarize the given blocks.
+    pub fn of(blocks: &[Block]) -> Self {
+        Self {
+            count: blocks.len(),
+            first_kind: blocks.first().map(|block| block.kind_name()),
+        }
+    }
+}
+
+impl fmt::Display for BlocksSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.count, self.first_kind) {
+            (1, Some(kind)) => write!(f, "a {}", kind),
+            (count, Some(kind)) => write!(f, "{} blocks, starting with a {}", count, kind),
+            (_, None) => write!(f, "no blocks"),
+        }
+    }
+}
+
 /// An error while building a document.
 #[derive(Error, Debug)]
 pub enum DocBuilderError {
@@ -251,6 +802,384 @@ pub enum DocBuilderError {
     EmptyTermList,
 
     /// Inlines were expected.
-    #[error("Unexpected blocks {0:?}")]
-    UnexpectedBlocks(Blocks),
+    #[error("Expected inline content, but got {0}")]
+    UnexpectedBlocks(BlocksSummary),
+
+    /// A caption was given outside of a figure.
+    #[error("\\caption used outside a figure")]
+    CaptionOutsideFigure,
+
+    /// A container was ended without being started.
+    #[error("Attempted to end a {0} that was never started")]
+    Unopened(ContainerKind),
+
+    /// A container was ended while a container inside it was still open.
+    #[error("Attempted to end a {end} before the {open} inside it")]
+    MismatchedEnd {
+        /// The innermost open container.
+        open: ContainerKind,
+        /// The container which was ended.
+        end: ContainerKind,
+    },
+
+    /// A container was started but never ended.
+    #[error("A {0} was never ended")]
+    Unclosed(ContainerKind),
+
+    /// A container was started outside the container it must be in, e.g. a
+    /// list item outside a list.
+    #[error("A {kind} was used outside a {parent}")]
+    OutsideContainer {
+        /// The container which was started.
+        kind: ContainerKind,
+        /// The container it must be in.
+        parent: ContainerKind,
+    },
+
+    /// A container was started directly inside a container which can't hold
+    /// it, e.g. a figure directly inside a table (rather than a table cell).
+    #[error("A {kind} can't be directly inside a {parent}")]
+    ContainerNotAllowed {
+        /// The container which was started.
+        kind: ContainerKind,
+        /// The innermost open container.
+        parent: ContainerKind,
+    },
+
+    /// Content was added directly to a container which only holds other
+    /// containers, e.g. a list rather than a list item.
+    #[error("Content can't be directly inside a {0}")]
+    ContentNotAllowed(ContainerKind),
+}
+
+impl DocBuilderError {
+    /// The stable code for this error; see `diagnostic::ErrorCode`.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::EmptyTermList => ErrorCode::EmptyTermList,
+            Self::UnexpectedBlocks(_) => ErrorCode::UnexpectedBlocks,
+            Self::CaptionOutsideFigure => ErrorCode::CaptionOutsideFigure,
+            Self::Unopened(_) => ErrorCode::UnopenedContainer,
+            Self::MismatchedEnd { .. } => ErrorCode::MismatchedEnd,
+            Self::Unclosed(_) => ErrorCode::UnclosedContainer,
+            Self::OutsideContainer { .. } => ErrorCode::OutsideContainer,
+            Self::ContainerNotAllowed { .. } => ErrorCode::ContainerNotAllowed,
+            Self::ContentNotAllowed(_) => ErrorCode::ContentNotAllowed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use claim::*;
+
+    fn text(s: &str) -> Inlines {
+        vec![Inline::Text(s.into())]
+    }
+
+    fn figure_content(doc: Doc) -> Figure {
+        match doc.content.into_iter().next().map(|block| block.inner) {
+            Some(BlockInner::Figure(figure)) => figure,
+            other => panic!("Expected a figure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_caption_before_content() {
+        let mut builder = DocBuilder::new();
+        builder.begin_figure(FigureKind::Figure, None).unwrap();
+        builder.set_caption(text("A caption.")).unwrap();
+        builder.push(text("Content.")).unwrap();
+        builder.end(ContainerKind::Figure).unwrap();
+        let figure = figure_content(builder.try_into().unwrap());
+        assert_eq!(text("A caption."), figure.caption);
+        assert_eq!(1, figure.content.len());
+    }
+
+    #[test]
+    fn test_caption_after_content() {
+        let mut builder = DocBuilder::new();
+        builder.push(text("Before.")).unwrap();
+        builder.begin_figure(FigureKind::Table, None).unwrap();
+        builder.push(text("Content.")).unwrap();
+        builder.set_caption(text("A caption.")).unwrap();
+        builder.end(ContainerKind::Figure).unwrap();
+        let doc: Doc = builder.try_into().unwrap();
+        assert_eq!(2, doc.content.len());
+        let figure = figure_content(Doc::from_content(doc.content.0[1].clone().into()));
+        assert_eq!(text("A caption."), figure.caption);
+        assert_eq!(FigureKind::Table, figure.kind);
+        assert_eq!(BlockInner::Par(text("Content.")), figure.content[0].inner);
+    }
+
+    #[test]
+    fn test_nested_containers() {
+        let mut builder = DocBuilder::new();
+        builder.begin(ContainerKind::Quote).unwrap();
+        builder.begin(ContainerKind::List).unwrap();
+        builder.push(text(" ")).unwrap();
+        for item in &["a", "b"] {
+            builder.begin(ContainerKind::ListItem).unwrap();
+            builder.push(text(item)).unwrap();
+            builder.begin(ContainerKind::Table).unwrap();
+            builder.begin(ContainerKind::Row).unwrap();
+            builder.begin(ContainerKind::Cell).unwrap();
+            builder.push(text("cell")).unwrap();
+            builder.end(ContainerKind::Cell).unwrap();
+            builder.end(ContainerKind::Row).unwrap();
+            builder.end(ContainerKind::Table).unwrap();
+            builder.end(ContainerKind::ListItem).unwrap();
+            builder.push(BlockInner::Par(Default::default())).unwrap();
+        }
+        builder.end(ContainerKind::List).unwrap();
+        builder.end(ContainerKind::Quote).unwrap();
+        let doc: Doc = builder.try_into().unwrap();
+
+        let list = match &doc.content[0].inner {
+            BlockInner::Quote(blocks) => match &blocks[0].inner {
+                BlockInner::List(list) => list,
+                other => panic!("Expected a list, got {:?}", other),
+            },
+            other => panic!("Expected a quote, got {:?}", other),
+        };
+        assert_eq!(2, list.items.len());
+        assert_eq!(BlockInner::Par(text("b")), list.items[1].content[0].inner);
+        match &list.items[1].content[1].inner {
+            BlockInner::Table(table) => assert_eq!(
+                BlockInner::Par(text("cell")),
+                table.cells[0][0].content[0].inner
+            ),
+            other => panic!("Expected a table, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_footnote_container() {
+        let mut builder = DocBuilder::new();
+        builder.push(text("Before")).unwrap();
+        builder.begin(ContainerKind::Footnote).unwrap();
+        builder.push(text("Note.")).unwrap();
+        builder.end(ContainerKind::Footnote).unwrap();
+        builder.push(text(" after.")).unwrap();
+        let doc: Doc = builder.try_into().unwrap();
+        assert_eq!(1, doc.content.len());
+        match &doc.content[0].inner {
+            BlockInner::Par(inlines) => {
+                assert_eq!(3, inlines.len());
+                assert_matches!(&inlines[1], Inline::Footnote(_));
+            }
+            other => panic!("Expected a paragraph, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_push_error() {
+        let mut builder = DocBuilder::new();
+        builder.push(text("Before.")).unwrap();
+        builder.begin(ContainerKind::List).unwrap();
+        builder.begin(ContainerKind::ListItem).unwrap();
+        builder.push(text("Item")).unwrap();
+        builder.begin(ContainerKind::Footnote).unwrap();
+        builder.push(text("Note.")).unwrap();
+        builder.push_error("Error: Oops\n  caused by: Something");
+        let doc: Doc = builder.try_into().unwrap();
+
+        assert_eq!(BlockInner::Par(text("Before.")), doc.content[0].inner);
+        match &doc.content[1].inner {
+            BlockInner::List(list) => match &list.items[0].content[0].inner {
+                BlockInner::Par(inlines) => {
+                    assert_eq!(2, inlines.len());
+                    assert_matches!(&inlines[1], Inline::Footnote(_));
+                }
+                other => panic!("Expected a paragraph, got {:?}", other),
+            },
+            other => panic!("Expected a list, got {:?}", other),
+        }
+        match &doc.content[2].inner {
+            BlockInner::Tagged(TaggedBlocks { content, meta }) => {
+                assert_some!(meta.get(ERROR_KEY));
+                match &content[0].inner {
+                    BlockInner::Code(code) => assert_eq!(
+                        vec![text("Error: Oops"), text("  caused by: Something")],
+                        code.lines
+                    ),
+                    other => panic!("Expected code, got {:?}", other),
+                }
+            }
+            other => panic!("Expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_close_all_drops_stray_content() {
+        let mut builder = DocBuilder::new();
+        builder.begin(ContainerKind::List).unwrap();
+        builder.push(text("Not in an item.")).unwrap();
+        builder.close_all();
+        let doc: Doc = builder.try_into().unwrap();
+        match &doc.content[0].inner {
+            BlockInner::List(list) => assert_eq!(0, list.items.len()),
+            other => panic!("Expected a list, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_after_heading() {
+        let mut builder = DocBuilder::new();
+        builder
+            .push(BlockInner::Heading(Heading {
+                level: 1,
+                text: text("Title"),
+                attrs: Default::default(),
+            }))
+            .unwrap();
+        builder.push(text(" ")).unwrap();
+        builder.push(BlockInner::Par(Default::default())).unwrap();
+        builder.push(text("Text.")).unwrap();
+        let doc: Doc = builder.try_into().unwrap();
+        assert_eq!(2, doc.content.len());
+        assert_matches!(
+            &doc.content[0].inner,
+            BlockInner::Heading(Heading { text: title, .. }) if *title == text("Title")
+        );
+        assert_eq!(BlockInner::Par(text("Text.")), doc.content[1].inner);
+
+        let mut builder = DocBuilder::new();
+        builder.push(text("One.")).unwrap();
+        builder.push(BlockInner::Par(Default::default())).unwrap();
+        builder.push(text("Two.")).unwrap();
+        let inlines: Result<Inlines, _> = builder.try_into();
+        assert_matches!(
+            inlines,
+            Err(DocBuilderError::UnexpectedBlocks(BlocksSummary {
+                count: 2,
+                ..
+            }))
+        );
+    }
+
+    #[test]
+    fn test_mismatched_containers() {
+        let mut builder = DocBuilder::new();
+        assert_matches!(
+            builder.end(ContainerKind::List),
+            Err(DocBuilderError::Unopened(ContainerKind::List))
+        );
+        assert_matches!(
+            builder.begin(ContainerKind::ListItem),
+            Err(DocBuilderError::OutsideContainer {
+                kind: ContainerKind::ListItem,
+                parent: ContainerKind::List,
+            })
+        );
+
+        builder.begin(ContainerKind::Table).unwrap();
+        assert_matches!(
+            builder.begin(ContainerKind::Figure),
+            Err(DocBuilderError::ContainerNotAllowed {
+                kind: ContainerKind::Figure,
+                parent: ContainerKind::Table,
+            })
+        );
+        assert_matches!(
+            builder.push(BlockInner::Rule),
+            Err(DocBuilderError::ContentNotAllowed(ContainerKind::Table))
+        );
+        builder.begin(ContainerKind::Row).unwrap();
+        assert_matches!(
+            builder.end(ContainerKind::Table),
+            Err(DocBuilderError::MismatchedEnd {
+                open: ContainerKind::Row,
+                end: ContainerKind::Table,
+            })
+        );
+        assert_matches!(
+            builder.end(ContainerKind::Quote),
+            Err(DocBuilderError::Unopened(ContainerKind::Quote))
+        );
+        let doc: Result<Doc, _> = builder.try_into();
+        assert_matches!(doc, Err(DocBuilderError::Unclosed(ContainerKind::Row)));
+    }
+
+    #[test]
+    fn test_collect() {
+        let mut builder = DocBuilder::new();
+        builder.push(text("Content.")).unwrap();
+        let par = |s| Blocks::from(vec![Block::new(Id::from(0), BlockInner::Par(text(s)))]);
+        builder.collect("notes", par("First."));
+        builder.collect("other", par("Elsewhere."));
+        builder.collect("notes", par("Second."));
+        let doc: Doc = builder.try_into().unwrap();
+        assert_eq!(1, doc.content.len());
+        assert_eq!(
+            vec![par("First."), par("Second.")],
+            doc.collections["notes"]
+        );
+        assert_eq!(vec![par("Elsewhere.")], doc.collections["other"]);
+    }
+
+    #[test]
+    fn test_caption_outside_figure() {
+        let mut builder = DocBuilder::new();
+        assert_matches!(
+            builder.set_caption(text("A caption.")),
+            Err(DocBuilderError::CaptionOutsideFigure)
+        );
+    }
+
+    #[test]
+    fn test_unexpected_blocks() {
+        let mut builder = DocBuilder::new();
+        builder.push(BlockInner::Rule).unwrap();
+        let res: Result<Inlines, _> = builder.try_into();
+        assert_eq!(
+            "Expected inline content, but got a rule",
+            res.unwrap_err().to_string()
+        );
+        assert_eq!(
+            "3 blocks, starting with a heading",
+            BlocksSummary {
+                count: 3,
+                first_kind: Some("heading"),
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_source_offsets() {
+        let mut builder = DocBuilder::new();
+        builder.set_source_offset(0);
+        builder.push(text("One.")).unwrap();
+        builder.set_source_offset(4);
+        builder.push(BlockInner::Par(Default::default())).unwrap();
+        builder.set_source_offset(6);
+        builder.push(text("Two ")).unwrap();
+        builder.set_source_offset(10);
+        let mut note = DocBuilder::new_inheriting(&builder);
+        note.push(text("Note.")).unwrap();
+        let note: Blocks = note.try_into().unwrap();
+        builder
+            .push(Inline::Footnote(Footnote { content: note }))
+            .unwrap();
+        let (doc, offsets) = builder.try_into_located().unwrap();
+
+        let ids: Vec<_> = doc.content.iter().map(|block| block.id).collect();
+        let note_id = match &doc.content[1].inner {
+            BlockInner::Par(inlines) => match &inlines[1] {
+                Inline::Footnote(note) => note.content[0].id,
+                other => panic!("Expected a footnote, got {:?}", other),
+            },
+            other => panic!("Expected a paragraph, got {:?}", other),
+        };
+        // Ids are unique across builders.
+        assert_ne!(note_id, ids[0]);
+        assert_ne!(note_id, ids[1]);
+        assert_eq!(Some(&0), offsets.get(&ids[0]));
+        assert_eq!(Some(&6), offsets.get(&ids[1]));
+        assert_eq!(Some(&10), offsets.get(&note_id));
+    }
 }
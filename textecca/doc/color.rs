@@ -0,0 +1,113 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+#[cfg(feature = "cli-support")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// A color, for colored text.
+///
+/// Generally compatible with [CSS colors]; only the hexadecimal and named
+/// forms are supported.
+///
+/// [CSS colors]: https://developer.mozilla.org/en-US/docs/Web/CSS/color_value
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
+pub enum Color {
+    /// A color given by its red, green, and blue components.
+    Rgb(u8, u8, u8),
+    /// A named color, like `rebeccapurple`. Names which aren't entirely ASCII
+    /// letters aren't valid colors, and serializers may skip them.
+    Named(String),
+}
+
+impl Color {
+    /// Is this color valid to write out, i.e. not a name with characters
+    /// other than ASCII letters? Names aren't checked against any list of
+    /// known colors.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Color::Rgb(..) => true,
+            Color::Named(name) => !name.is_empty() && name.chars().all(|c| c.is_ascii_alphabetic()),
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = ColorError;
+
+    /// Parse a color written as in CSS, e.g. `#639`, `#663399`, or
+    /// `rebeccapurple`. Names are lowercased.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || ColorError::Invalid(s.to_owned());
+        if let Some(hex) = s.strip_prefix('#') {
+            if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            let digits: Vec<u8> = match hex.len() {
+                3 => hex
+                    .chars()
+                    .map(|c| c.to_digit(16).unwrap() as u8 * 0x11)
+                    .collect(),
+                6 => (0..6)
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                    .collect(),
+                _ => return Err(invalid()),
+            };
+            Ok(Color::Rgb(digits[0], digits[1], digits[2]))
+        } else {
+            let color = Color::Named(s.to_ascii_lowercase());
+            if color.is_valid() {
+                Ok(color)
+            } else {
+                Err(invalid())
+            }
+        }
+    }
+}
+
+impl Display for Color {
+    /// Write the color as CSS, e.g. `#663399`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Color::Rgb(r, g, b) => write!(f, "#{:02x}{:02x}{:02x}", r, g, b),
+            Color::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// An error when parsing a `Color`.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ColorError {
+    /// The color is neither a hexadecimal color nor a name.
+    #[error("Invalid color {0:?}")]
+    Invalid(String),
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(Ok(Color::Rgb(0x66, 0x33, 0x99)), "#663399".parse());
+        assert_eq!(Ok(Color::Rgb(0x66, 0x33, 0x99)), " #639".parse());
+        assert_eq!(Ok(Color::Named("red".into())), "Red".parse());
+        for invalid in &["#12345", "#ggg", "red;", "", "#"] {
+            assert_eq!(
+                Err(ColorError::Invalid((*invalid).to_owned())),
+                invalid.parse::<Color>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_display_color() {
+        assert_eq!("#0a0b0c", Color::Rgb(10, 11, 12).to_string());
+        assert_eq!("teal", Color::Named("teal".into()).to_string());
+    }
+}
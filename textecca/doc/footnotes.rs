@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::mem;
+
+use thiserror::Error;
+
+use super::{
+    walk_mut, Block, BlockInner, Defn, Doc, Figure, Footnote, Heading, Id, Inline, Inlines, Link,
+    Quote, TaggedInlines, TermListItem, VisitMut,
+};
+use crate::diagnostic::{Diagnostic, DiagnosticSpan, ErrorCode, Severity};
+use crate::parse::Source;
+
+/// What to do with a footnote containing blocks (e.g. several paragraphs or a
+/// list) which is nested inside other inline content, like emphasis or a link.
+///
+/// Serializers which write footnotes where they're referenced (e.g. as
+/// sidenotes) may not be able to put blocks there; see `hoist_footnotes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FootnotePolicy {
+    /// Move the footnote out of the inline content containing it, splitting
+    /// that content around the footnote, so that it's directly in its block.
+    Hoist,
+    /// Report an error.
+    Error,
+}
+
+/// Find footnotes containing blocks which are nested inside other inline
+/// content, and handle them according to `policy`.
+///
+/// For example, with `FootnotePolicy::Hoist`, a multi-paragraph footnote in
+/// the middle of some emphasized text splits the emphasis in two, with the
+/// footnote between the halves.
+pub fn hoist_footnotes(doc: &mut Doc, policy: FootnotePolicy) -> Result<(), FootnoteError> {
+    let mut hoister = FootnoteHoister { policy, err: None };
+    walk_mut(&mut doc.content, &mut hoister);
+    match hoister.err {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+struct FootnoteHoister {
+    policy: FootnotePolicy,
+    err: Option<FootnoteError>,
+}
+
+impl VisitMut for FootnoteHoister {
+    fn visit_block(&mut self, block: &mut Block) {
+        if self.err.is_some() {
+            return;
+        }
+        let id = block.id;
+        let result = match &mut block.inner {
+            BlockInner::Plain(inlines)
+            | BlockInner::Par(inlines)
+            | BlockInner::Heading(Heading { text: inlines, .. })
+            | BlockInner::Figure(Figure {
+                caption: inlines, ..
+            })
+            | BlockInner::Defn(Defn { name: inlines, .. }) => self.hoist_inlines(id, inlines),
+            BlockInner::TermList(items) => items
+                .iter_mut()
+                .try_for_each(|TermListItem { term, .. }| self.hoist_inlines(id, term)),
+            _ => Ok(()),
+        };
+        self.err = result.err();
+    }
+}
+
+impl FootnoteHoister {
+    /// Hoist the footnotes in `inlines`, which are in the block `block`.
+    fn hoist_inlines(&self, block: Id, inlines: &mut Inlines) -> Result<(), FootnoteError> {
+        let mut ret = Vec::with_capacity(inlines.len());
+        for inline in mem::take(inlines) {
+            ret.extend(self.split_inline(block, inline)?);
+        }
+        *inlines = ret;
+        Ok(())
+    }
+
+    /// Split `inline` around any footnotes with blocks nested within it.
+    fn split_inline(&self, block: Id, inline: Inline) -> Result<Inlines, FootnoteError> {
+        let mut wrapper = inline;
+        let content = match inline_content_mut(&mut wrapper) {
+            Some(content) => mem::take(content),
+            None => return Ok(vec![wrapper]),
+        };
+
+        let mut ret = Vec::new();
+        let mut current = Vec::new();
+        for child in content {
+            for piece in self.split_inline(block, child)? {
+                match piece {
+                    Inline::Footnote(footnote) if has_blocks(&footnote) => {
+                        if self.policy == FootnotePolicy::Error {
+                            return Err(FootnoteError::BlocksInInline {
+                                block,
+                                excerpt: excerpt(&footnote),
+                            });
+                        }
+                        if !current.is_empty() {
+                            ret.push(with_content(&wrapper, mem::take(&mut current)));
+                        }
+                        ret.push(Inline::Footnote(footnote));
+                    }
+                    piece => current.push(piece),
+                }
+            }
+        }
+        if !current.is_empty() || ret.is_empty() {
+            ret.push(with_content(&wrapper, current));
+        }
+        Ok(ret)
+    }
+}
+
+/// The content of an inline which contains other inlines.
+fn inline_content_mut(inline: &mut Inline) -> Option<&mut Inlines> {
+    match inline {
+        Inline::Styled { content, .. }
+        | Inline::Quote(Quote { content, .. })
+        | Inline::Link(Link {
+            content: Some(content),
+            ..
+        })
+        | Inline::Tagged(TaggedInlines { content, .. }) => Some(content),
+        _ => None,
+    }
+}
+
+/// A copy of `wrapper` containing `content` instead.
+fn with_content(wrapper: &Inline, content: Inlines) -> Inline {
+    let mut ret = wrapper.clone();
+    if let Some(old) = inline_content_mut(&mut ret) {
+        *old = content;
+    }
+    ret
+}
+
+/// Does this footnote contain anything besides a single paragraph?
+fn has_blocks(footnote: &Footnote) -> bool {
+    match &footnote.content[..] {
+        [] => false,
+        [block] => !matches!(block.inner, BlockInner::Par(_) | BlockInner::Plain(_)),
+        _ => true,
+    }
+}
+
+/// The start of a footnote's text, to identify it in errors.
+fn excerpt(footnote: &Footnote) -> String {
+    const MAX_LEN: usize = 30;
+    let mut ret = String::new();
+    if let Some(BlockInner::Par(inlines)) | Some(BlockInner::Plain(inlines)) =
+        footnote.content.first().map(|block| &block.inner)
+    {
+        for inline in inlines {
+            match inline {
+                Inline::Text(text) => ret.push_str(text),
+                Inline::Space => ret.push(' '),
+                _ => {}
+            }
+        }
+    }
+    if ret.chars().count() > MAX_LEN {
+        ret = ret.chars().take(MAX_LEN).collect();
+        ret.push('…');
+    }
+    ret
+}
+
+/// An error from `hoist_footnotes`.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum FootnoteError {
+    /// A footnote containing blocks is nested inside other inline content.
+    #[error("Footnote {excerpt:?} contains multiple paragraphs or other blocks, but is inside inline content like emphasis or a link")]
+    BlocksInInline {
+        /// The block the footnote is in.
+        block: Id,
+        /// The start of the footnote's text.
+        excerpt: String,
+    },
+}
+
+impl FootnoteError {
+    /// The stable code for this error; see `diagnostic::ErrorCode`.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::BlocksInInline { .. } => ErrorCode::BlocksInInlineFootnote,
+        }
+    }
+
+    /// The block the error is in.
+    pub fn block(&self) -> Id {
+        match self {
+            Self::BlocksInInline { block, .. } => *block,
+        }
+    }
+
+    /// This error as a `Diagnostic`, pointing at the start of its block in
+    /// `src` if it's in `offsets`, as given by `DocBuilder::try_into_located`.
+    pub fn diagnostic(&self, src: &Source, offsets: &HashMap<Id, usize>) -> Diagnostic {
+        let span = offsets
+            .get(&self.block())
+            .map(|&offset| DiagnosticSpan::new(src, offset..offset));
+        Diagnostic::new(Severity::Error, self.code(), self.to_string())
+            .in_file(src)
+            .with_span(span)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::doc::{Id, Style};
+
+    fn text(s: &str) -> Inline {
+        Inline::Text(s.into())
+    }
+
+    fn emph(content: Inlines) -> Inline {
+        Inline::Styled {
+            style: Style::Emph,
+            content,
+        }
+    }
+
+    fn par(content: Inlines) -> Block {
+        Block {
+            id: Id::from(0),
+            inner: BlockInner::Par(content),
+        }
+    }
+
+    fn footnote(pars: &[&str]) -> Inline {
+        Inline::Footnote(Footnote {
+            content: pars
+                .iter()
+                .map(|s| par(vec![text(s)]))
+                .collect::<Vec<_>>()
+                .into(),
+        })
+    }
+
+    fn test_doc() -> Doc {
+        Doc::from_content(
+            par(vec![
+                text("a "),
+                emph(vec![
+                    text("b"),
+                    footnote(&["First paragraph.", "Second paragraph."]),
+                    text("c"),
+                    footnote(&["Short."]),
+                ]),
+                text(" d"),
+            ])
+            .into(),
+        )
+    }
+
+    #[test]
+    fn test_hoist_footnotes() {
+        let mut doc = test_doc();
+        hoist_footnotes(&mut doc, FootnotePolicy::Hoist).unwrap();
+        assert_eq!(
+            BlockInner::Par(vec![
+                text("a "),
+                emph(vec![text("b")]),
+                footnote(&["First paragraph.", "Second paragraph."]),
+                emph(vec![text("c"), footnote(&["Short."])]),
+                text(" d"),
+            ]),
+            doc.content[0].inner
+        );
+    }
+
+    #[test]
+    fn test_footnote_policy_error() {
+        let mut doc = test_doc();
+        assert_eq!(
+            Err(FootnoteError::BlocksInInline {
+                block: Id::from(0),
+                excerpt: "First paragraph.".into()
+            }),
+            hoist_footnotes(&mut doc, FootnotePolicy::Error)
+        );
+
+        // Single-paragraph footnotes are fine anywhere.
+        let mut doc = Doc::from_content(par(vec![emph(vec![footnote(&["Short."])])]).into());
+        let expected = doc.clone();
+        hoist_footnotes(&mut doc, FootnotePolicy::Error).unwrap();
+        assert_eq!(expected, doc);
+    }
+
+    #[test]
+    fn test_footnote_error_diagnostic() {
+        let src = Source::new("Intro.\n\n\\emph{Text.\\footnote{A.\n\nB.}}\n".into());
+        let err = FootnoteError::BlocksInInline {
+            block: Id::from(1),
+            excerpt: "A.".into(),
+        };
+        let offsets = vec![(Id::from(0), 0), (Id::from(1), 8)]
+            .into_iter()
+            .collect();
+        let diagnostic = err.diagnostic(&src, &offsets);
+        assert_eq!(ErrorCode::BlocksInInlineFootnote, diagnostic.code);
+        assert_eq!(Some(DiagnosticSpan::new(&src, 8..8)), diagnostic.span);
+        assert_eq!(3, diagnostic.span.unwrap().line);
+
+        // Blocks without a known position give no span.
+        assert_eq!(None, err.diagnostic(&src, &HashMap::new()).span);
+    }
+}
@@ -1,9 +1,12 @@
-use super::Length;
-use super::{Blocks, Inline, Inlines, Meta};
+use super::{Blocks, Color, Inline, Inlines, Length, Meta, RefId};
 use std::borrow::Cow;
 
+#[cfg(feature = "cli-support")]
+use serde::{Deserialize, Serialize};
+
 /// A group of inlines tagged with some metadata.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct TaggedInlines {
     /// The contained text.
     pub content: Inlines,
@@ -13,6 +16,7 @@ pub struct TaggedInlines {
 
 /// A link, either to something within this document or to an external URL.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct Link {
     /// The link text, if any; if no text is given, the serializer may compute
     /// its own representation. This is likely appropriate for intra-document links.
@@ -21,11 +25,13 @@ pub struct Link {
     pub label: Option<String>,
     /// The link's target.
     pub target: LinkTarget,
+    /// Extra attributes, e.g. `rel = "nofollow"`.
+    pub attrs: Meta,
 }
 
 impl Link {
     /// Get the link's text, from `text`, `label`, or `target` (in that order).
-    pub fn text(&self) -> Cow<[Inline]> {
+    pub fn text(&self) -> Cow<'_, [Inline]> {
         if let Some(inlines) = &self.content {
             Cow::Borrowed(inlines)
         } else if let Some(text) = &self.label {
@@ -38,10 +44,10 @@ impl Link {
 
 /// A `Link`'s destination, either within the document or external.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub enum LinkTarget {
-    /// A label defined elsewhere in the document. If the label is never defined,
-    /// an error is raised.
-    Label(String),
+    /// A label defined elsewhere in the document; see `undefined_labels`.
+    Label(RefId),
     /// A URL.
     URL(String),
 }
@@ -50,7 +56,8 @@ impl LinkTarget {
     /// Get the target of this link as a string slice.
     pub fn as_str(&self) -> &str {
         match self {
-            LinkTarget::Label(s) | LinkTarget::URL(s) => &s,
+            LinkTarget::Label(label) => label.as_str(),
+            LinkTarget::URL(url) => url,
         }
     }
 }
@@ -59,6 +66,7 @@ impl LinkTarget {
 
 /// An inline quotation.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct Quote {
     /// The quotation markers.
     pub kind: QuoteKind,
@@ -68,6 +76,7 @@ pub struct Quote {
 
 /// Quotation markers; see [Wikipedia](https://en.wikipedia.org/wiki/Quotation_mark).
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub enum QuoteKind {
     /// Primary quotes, locale-defined.
     ///
@@ -83,24 +92,51 @@ pub enum QuoteKind {
 }
 
 impl QuoteKind {
-    /// Gives a pair of the open and close quote markers as `Inlines`.
-    pub fn to_inlines(&self) -> (Cow<[Inline]>, Cow<[Inline]>) {
-        match self {
-            QuoteKind::Primary => (
-                Cow::Owned(vec![Inline::Text("“".into())]),
-                Cow::Owned(vec![Inline::Text("”".into())]),
-            ),
-            QuoteKind::Secondary => (
-                Cow::Owned(vec![Inline::Text("‘".into())]),
-                Cow::Owned(vec![Inline::Text("’".into())]),
-            ),
-            QuoteKind::Other(l, r) => (Cow::Borrowed(&l), Cow::Borrowed(&r)),
-        }
+    /// Gives a pair of the open and close quote markers as `Inlines`, in US
+    /// English.
+    pub fn to_inlines(&self) -> (Cow<'_, [Inline]>, Cow<'_, [Inline]>) {
+        self.to_inlines_in(None)
+    }
+
+    /// Gives a pair of the open and close quote markers as `Inlines` for the
+    /// given language (a BCP 47 tag like `de` or `fr-CA`; see `LANG_KEY`),
+    /// falling back to US English for unknown languages.
+    pub fn to_inlines_in(&self, lang: Option<&str>) -> (Cow<'_, [Inline]>, Cow<'_, [Inline]>) {
+        let (primary, secondary) = quote_marks(lang.unwrap_or("en"));
+        let (l, r) = match self {
+            QuoteKind::Primary => primary,
+            QuoteKind::Secondary => secondary,
+            QuoteKind::Other(l, r) => return (Cow::Borrowed(l), Cow::Borrowed(r)),
+        };
+        (
+            Cow::Owned(vec![Inline::Text(l.into())]),
+            Cow::Owned(vec![Inline::Text(r.into())]),
+        )
+    }
+}
+
+type QuoteMarks = (&'static str, &'static str);
+
+/// The primary and secondary quotation markers for a language.
+fn quote_marks(lang: &str) -> (QuoteMarks, QuoteMarks) {
+    let primary_subtag = lang.split(['-', '_']).next().unwrap_or(lang);
+    match primary_subtag.to_ascii_lowercase().as_str() {
+        "de" => (("„", "“"), ("‚", "‘")),
+        "fr" => (("«\u{a0}", "\u{a0}»"), ("‹\u{a0}", "\u{a0}›")),
+        "es" | "it" | "pt" => (("«", "»"), ("“", "”")),
+        "ru" => (("«", "»"), ("„", "“")),
+        "ja" | "zh" => (("「", "」"), ("『", "』")),
+        _ => (("“", "”"), ("‘", "’")),
     }
 }
 
 /// Styled text.
+///
+/// New styles may be added in future versions; see `ser::Serializer` for how
+/// serializers handle them.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum Style {
     /// Emphasized text, typically displayed with italics.
     Emph,
@@ -126,37 +162,90 @@ pub enum Style {
     FontFeatures(FontFeatures),
 }
 
-/// Colored text.
-#[derive(Debug, Clone, PartialEq)]
-pub struct Color {}
-
 /// Text in a custom font.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct Font {}
 
 /// Text with particular font features activated.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct FontFeatures {}
 
 /// An inline code snippet.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct InlineCode {
     /// The code's language, for highlighting. `"plain"` indicates no highlighting.
     pub language: Option<String>,
     /// The code.
     pub content: String,
+    /// Extra attributes.
+    pub attrs: Meta,
 }
 
 /// A footnote.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct Footnote {
     /// The footnote text.
     pub content: Blocks,
 }
 
+/// An image.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
+pub struct Image {
+    /// The image's location; a URL, or a path relative to the project root
+    /// (see `cmd::ResourceResolver`).
+    pub src: String,
+    /// A description of the image for screen readers, or for when the image
+    /// can't be shown.
+    pub alt: Option<String>,
+    /// Extra attributes.
+    pub attrs: Meta,
+}
+
+impl Image {
+    /// Is this image's `src` a URL, rather than a path?
+    pub fn is_url(&self) -> bool {
+        is_url(&self.src)
+    }
+}
+
+/// Does `s` look like a URL (with a scheme, e.g. `https:` or `data:`), rather
+/// than a path?
+pub fn is_url(s: &str) -> bool {
+    match s.find(':') {
+        // Not `C:\...`.
+        Some(colon) if colon > 1 => s[..colon]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)),
+        _ => false,
+    }
+}
+
 /// Inline mathematical text.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct InlineMath {
     /// The math to render, as `LaTeX`.
     pub tex: String,
+    /// A textual description of the math for screen readers, if any; see
+    /// `tex_to_speech`.
+    pub alt: Option<String>,
+}
+
+/// Content in a particular output format, like a snippet of HTML, which
+/// that format's serializer writes verbatim. Serializers for other formats
+/// leave it out, as they do content tagged for another format
+/// (see `ONLY_FORMAT_KEY`).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
+pub struct RawInline {
+    /// The format the content is in, as named by `Serializer::format_name`,
+    /// e.g. `html` or `latex`.
+    pub format: String,
+    /// The content, which isn't escaped or reflowed.
+    pub content: String,
 }
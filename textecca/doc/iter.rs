@@ -0,0 +1,385 @@
+use super::{
+    Block, BlockInner, Defn, Figure, Footnote, Heading, Inline, Link, ListItem, Quote,
+    TaggedBlocks, TaggedInlines, TermListItem,
+};
+
+/// A visitor for mutably traversing a document's blocks and inlines; see
+/// `walk_mut`.
+///
+/// Visitors don't need to traverse the tree of blocks they visit themselves.
+pub trait VisitMut {
+    /// Visit a block, before its children are visited.
+    fn visit_block(&mut self, _block: &mut Block) {}
+
+    /// Visit an inline, before its children are visited.
+    fn visit_inline(&mut self, _inline: &mut Inline) {}
+}
+
+/// A block or inline waiting to be visited by `walk_mut`.
+enum Node<'a> {
+    Block(&'a mut Block),
+    Inline(&'a mut Inline),
+}
+
+/// Visit each `Block` and `Inline` in `blocks` recursively, in document order.
+///
+/// Parents are visited before their children, so a visitor may modify a block
+/// or inline before its children are traversed. Footnote content is visited
+/// where the footnote is referenced.
+///
+/// The traversal keeps its own stack rather than recursing, so arbitrarily
+/// deep documents can be walked.
+pub fn walk_mut(blocks: &mut [Block], visitor: &mut impl VisitMut) {
+    let mut stack: Vec<Node> = blocks.iter_mut().rev().map(Node::Block).collect();
+    walk_stack(&mut stack, visitor);
+}
+
+/// Visit each `Inline` in `inlines` recursively, in document order.
+pub fn walk_inlines_mut(inlines: &mut [Inline], visitor: &mut impl VisitMut) {
+    let mut stack: Vec<Node> = inlines.iter_mut().rev().map(Node::Inline).collect();
+    walk_stack(&mut stack, visitor);
+}
+
+/// Visit the nodes on `stack`, last first, and their children.
+fn walk_stack<'a>(stack: &mut Vec<Node<'a>>, visitor: &mut impl VisitMut) {
+    let mut children = Vec::new();
+    while let Some(node) = stack.pop() {
+        match node {
+            Node::Block(block) => {
+                visitor.visit_block(block);
+                block_children(&mut block.inner, &mut children);
+            }
+            Node::Inline(inline) => {
+                visitor.visit_inline(inline);
+                inline_children(inline, &mut children);
+            }
+        }
+        // Children are pushed in reverse, so they're visited in order.
+        stack.extend(children.drain(..).rev());
+    }
+}
+
+fn blocks<'a>(blocks: &'a mut [Block]) -> impl Iterator<Item = Node<'a>> {
+    blocks.iter_mut().map(Node::Block)
+}
+
+fn inlines<'a>(inlines: &'a mut [Inline]) -> impl Iterator<Item = Node<'a>> {
+    inlines.iter_mut().map(Node::Inline)
+}
+
+fn inline_children<'a>(inline: &'a mut Inline, children: &mut Vec<Node<'a>>) {
+    match inline {
+        Inline::Styled { content, .. }
+        | Inline::Quote(Quote { content, .. })
+        | Inline::Link(Link {
+            content: Some(content),
+            ..
+        })
+        | Inline::Tagged(TaggedInlines { content, .. }) => children.extend(inlines(content)),
+        Inline::Footnote(Footnote { content }) => children.extend(blocks(content)),
+        Inline::Text(_)
+        | Inline::Code(_)
+        | Inline::Space
+        | Inline::LineBreak
+        | Inline::Link(_)
+        | Inline::Math(_)
+        | Inline::Image(_)
+        | Inline::Raw(_) => {}
+    }
+}
+
+fn block_children<'a>(inner: &'a mut BlockInner, children: &mut Vec<Node<'a>>) {
+    match inner {
+        BlockInner::Plain(text)
+        | BlockInner::Par(text)
+        | BlockInner::Heading(Heading { text, .. }) => children.extend(inlines(text)),
+        BlockInner::Code(code) => {
+            for line in &mut code.lines {
+                children.extend(inlines(line));
+            }
+        }
+        BlockInner::Quote(content) | BlockInner::Tagged(TaggedBlocks { content, .. }) => {
+            children.extend(blocks(content))
+        }
+        BlockInner::List(list) => {
+            for ListItem { content } in &mut list.items {
+                children.extend(blocks(content));
+            }
+        }
+        BlockInner::TermList(items) => {
+            for TermListItem { term, content } in items {
+                children.extend(inlines(term));
+                children.extend(blocks(content));
+            }
+        }
+        BlockInner::Table(table) => {
+            for cell in table.cells.iter_mut().flatten() {
+                children.extend(blocks(&mut cell.content));
+            }
+        }
+        BlockInner::Figure(Figure {
+            caption, content, ..
+        }) => {
+            children.extend(inlines(caption));
+            children.extend(blocks(content));
+        }
+        BlockInner::Defn(Defn {
+            name,
+            summary,
+            content,
+        }) => {
+            children.extend(inlines(name));
+            children.extend(blocks(summary));
+            children.extend(blocks(content));
+        }
+        BlockInner::Rule | BlockInner::Math(_) | BlockInner::Raw(_) => {}
+    }
+}
+
+/// A block, a sequence of inlines, or an inline in a document; see `nodes`.
+#[derive(Debug, Clone, Copy)]
+pub enum DocNode<'a> {
+    /// A block.
+    Block(&'a Block),
+    /// The inlines directly inside a block or inline, e.g. a paragraph's text.
+    Inlines(&'a [Inline]),
+    /// An inline.
+    Inline(&'a Inline),
+}
+
+impl<'a> DocNode<'a> {
+    /// Add the nodes directly inside this one to `children`, in document
+    /// order. A block's or inline's inline children are added as one
+    /// `DocNode::Inlines`, and its block children one by one.
+    pub fn children(self, children: &mut Vec<DocNode<'a>>) {
+        match self {
+            Self::Block(block) => block_nodes(&block.inner, children),
+            Self::Inlines(inlines) => children.extend(inlines.iter().map(Self::Inline)),
+            Self::Inline(inline) => inline_nodes(inline, children),
+        }
+    }
+}
+
+/// An iterator over the nodes in some blocks, recursively, in document order;
+/// see `nodes`.
+pub struct Nodes<'a> {
+    stack: Vec<DocNode<'a>>,
+    children: Vec<DocNode<'a>>,
+}
+
+/// Iterate over each block, sequence of inlines, and inline in `blocks`
+/// recursively, in document order.
+///
+/// Like `walk_mut`, parents come before their children, footnote content comes
+/// where the footnote is referenced, and the traversal keeps its own stack
+/// rather than recursing.
+pub fn nodes(blocks: &[Block]) -> Nodes<'_> {
+    Nodes {
+        stack: blocks.iter().rev().map(DocNode::Block).collect(),
+        children: Vec::new(),
+    }
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = DocNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        node.children(&mut self.children);
+        self.stack.extend(self.children.drain(..).rev());
+        Some(node)
+    }
+}
+
+fn inline_nodes<'a>(inline: &'a Inline, children: &mut Vec<DocNode<'a>>) {
+    match inline {
+        Inline::Styled { content, .. }
+        | Inline::Quote(Quote { content, .. })
+        | Inline::Link(Link {
+            content: Some(content),
+            ..
+        })
+        | Inline::Tagged(TaggedInlines { content, .. }) => children.push(DocNode::Inlines(content)),
+        Inline::Footnote(Footnote { content }) => {
+            children.extend(content.iter().map(DocNode::Block))
+        }
+        Inline::Text(_)
+        | Inline::Code(_)
+        | Inline::Space
+        | Inline::LineBreak
+        | Inline::Link(_)
+        | Inline::Math(_)
+        | Inline::Image(_)
+        | Inline::Raw(_) => {}
+    }
+}
+
+fn block_nodes<'a>(inner: &'a BlockInner, children: &mut Vec<DocNode<'a>>) {
+    let blocks = |blocks: &'a [Block]| blocks.iter().map(DocNode::Block);
+    match inner {
+        BlockInner::Plain(text)
+        | BlockInner::Par(text)
+        | BlockInner::Heading(Heading { text, .. }) => children.push(DocNode::Inlines(text)),
+        BlockInner::Code(code) => {
+            children.extend(code.lines.iter().map(|line| DocNode::Inlines(line)))
+        }
+        BlockInner::Quote(content) | BlockInner::Tagged(TaggedBlocks { content, .. }) => {
+            children.extend(blocks(content))
+        }
+        BlockInner::List(list) => {
+            for ListItem { content } in &list.items {
+                children.extend(blocks(content));
+            }
+        }
+        BlockInner::TermList(items) => {
+            for TermListItem { term, content } in items {
+                children.push(DocNode::Inlines(term));
+                children.extend(blocks(content));
+            }
+        }
+        BlockInner::Table(table) => {
+            for cell in table.cells.iter().flatten() {
+                children.extend(blocks(&cell.content));
+            }
+        }
+        BlockInner::Figure(Figure {
+            caption, content, ..
+        }) => {
+            children.push(DocNode::Inlines(caption));
+            children.extend(blocks(content));
+        }
+        BlockInner::Defn(Defn {
+            name,
+            summary,
+            content,
+        }) => {
+            children.push(DocNode::Inlines(name));
+            children.extend(blocks(summary));
+            children.extend(blocks(content));
+        }
+        BlockInner::Rule | BlockInner::Math(_) | BlockInner::Raw(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::doc::{Id, QuoteKind};
+
+    struct Counter {
+        blocks: usize,
+        inlines: usize,
+    }
+
+    impl VisitMut for Counter {
+        fn visit_block(&mut self, _block: &mut Block) {
+            self.blocks += 1;
+        }
+
+        fn visit_inline(&mut self, _inline: &mut Inline) {
+            self.inlines += 1;
+        }
+    }
+
+    #[test]
+    fn test_walk_deep() {
+        let mut block = Block {
+            id: Id::from(0),
+            inner: BlockInner::Par(vec![Inline::Text("Deep.".into())]),
+        };
+        for i in 1..=100_000 {
+            block = Block {
+                id: Id::from(i),
+                inner: BlockInner::Quote(block.into()),
+            };
+        }
+        let mut counter = Counter {
+            blocks: 0,
+            inlines: 0,
+        };
+        walk_mut(&mut [block], &mut counter);
+        assert_eq!((100_001, 1), (counter.blocks, counter.inlines));
+    }
+
+    #[test]
+    fn test_walk_order() {
+        struct Texts(Vec<String>);
+        impl VisitMut for Texts {
+            fn visit_inline(&mut self, inline: &mut Inline) {
+                if let Inline::Text(text) = inline {
+                    self.0.push(text.clone());
+                }
+            }
+        }
+
+        let text = |s: &str| Inline::Text(s.into());
+        let mut blocks = vec![
+            Block {
+                id: Id::from(0),
+                inner: BlockInner::Par(vec![
+                    text("a"),
+                    Inline::Quote(Quote {
+                        kind: QuoteKind::Primary,
+                        content: vec![text("b"), text("c")],
+                    }),
+                ]),
+            },
+            Block {
+                id: Id::from(1),
+                inner: BlockInner::Plain(vec![text("d")]),
+            },
+        ];
+        let mut texts = Texts(Vec::new());
+        walk_mut(&mut blocks, &mut texts);
+        assert_eq!(vec!["a", "b", "c", "d"], texts.0);
+    }
+
+    #[test]
+    fn test_nodes() {
+        let text = |s: &str| Inline::Text(s.into());
+        let blocks = vec![Block {
+            id: Id::from(0),
+            inner: BlockInner::Quote(
+                Block {
+                    id: Id::from(1),
+                    inner: BlockInner::Par(vec![
+                        text("a"),
+                        Inline::Footnote(Footnote {
+                            content: Block {
+                                id: Id::from(2),
+                                inner: BlockInner::Plain(vec![text("b")]),
+                            }
+                            .into(),
+                        }),
+                        text("c"),
+                    ]),
+                }
+                .into(),
+            ),
+        }];
+        let kinds: Vec<_> = nodes(&blocks)
+            .map(|node| match node {
+                DocNode::Block(block) => format!("block {}", block.id),
+                DocNode::Inlines(inlines) => format!("{} inlines", inlines.len()),
+                DocNode::Inline(Inline::Text(text)) => text.clone(),
+                DocNode::Inline(_) => "inline".into(),
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                "block 0",
+                "block 1",
+                "3 inlines",
+                "a",
+                "inline",
+                "block 2",
+                "1 inlines",
+                "b",
+                "c"
+            ],
+            kinds
+        );
+    }
+}
@@ -1,3 +1,10 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+#[cfg(feature = "cli-support")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
 /// A length, either relative or absolute.
 ///
 /// Generally compatible with [CSS lengths].
@@ -8,6 +15,7 @@
 /// [CSS lengths]: https://developer.mozilla.org/en-US/docs/Web/CSS/length
 /// [TeX units]: https://en.wikibooks.org/wiki/LaTeX/Lengths#Units
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub enum Length {
     /// An absolute length, convertible to final dimensions, e.g. pixels, inches, etc.
     Absolute(AbsLength),
@@ -17,6 +25,7 @@ pub enum Length {
 
 /// An absolute length, i.e. resolvable immediately to points.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub enum AbsLength {
     /// Pixels.
     ///
@@ -60,6 +69,7 @@ impl From<AbsLength> for Point {
 /// A length, computed relatively to the current font, base font-size, viewport,
 /// or elsewhere.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub enum RelLength {
     /// Relative to font size.
     Em(f64),
@@ -77,4 +87,128 @@ pub enum RelLength {
     Vmin(f64),
     /// Larger of Vw and Vh.
     Vmax(f64),
+    /// A percentage of the containing element's size.
+    Percent(f64),
+}
+
+/// A unit a `Length` can be written in, and how to construct a length in it.
+type Unit = (&'static str, fn(f64) -> Length);
+
+/// The units a `Length` can be written in.
+const UNITS: [Unit; 15] = [
+    ("px", |l| Length::Absolute(AbsLength::Px(l))),
+    ("pt", |l| Length::Absolute(AbsLength::Pt(l))),
+    ("pc", |l| Length::Absolute(AbsLength::Pc(l))),
+    ("in", |l| Length::Absolute(AbsLength::In(l))),
+    ("cm", |l| Length::Absolute(AbsLength::Cm(l))),
+    ("mm", |l| Length::Absolute(AbsLength::Mm(l))),
+    ("em", |l| Length::Relative(RelLength::Em(l))),
+    ("ch", |l| Length::Relative(RelLength::Ch(l))),
+    ("ex", |l| Length::Relative(RelLength::Ex(l))),
+    ("rem", |l| Length::Relative(RelLength::Rem(l))),
+    ("vh", |l| Length::Relative(RelLength::Vh(l))),
+    ("vw", |l| Length::Relative(RelLength::Vw(l))),
+    ("vmin", |l| Length::Relative(RelLength::Vmin(l))),
+    ("vmax", |l| Length::Relative(RelLength::Vmax(l))),
+    ("%", |l| Length::Relative(RelLength::Percent(l))),
+];
+
+impl FromStr for Length {
+    type Err = LengthError;
+
+    /// Parse a length written as a number followed by a unit, as in CSS, e.g.
+    /// `5em`, `2.5in`, or `30%`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let unit_start = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+            .ok_or_else(|| LengthError::MissingUnit(s.to_owned()))?;
+        let (number, unit) = s.split_at(unit_start);
+        let number: f64 = number
+            .parse()
+            .map_err(|_| LengthError::Invalid(s.to_owned()))?;
+        UNITS
+            .iter()
+            .find(|(name, _)| unit.eq_ignore_ascii_case(name))
+            .map(|(_, length)| length(number))
+            .ok_or_else(|| LengthError::UnknownUnit(unit.to_owned()))
+    }
+}
+
+impl Display for Length {
+    /// Write the length as CSS, e.g. `5em`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (number, unit) = match *self {
+            Length::Absolute(AbsLength::Px(l)) => (l, "px"),
+            Length::Absolute(AbsLength::Pt(l)) => (l, "pt"),
+            Length::Absolute(AbsLength::Pc(l)) => (l, "pc"),
+            Length::Absolute(AbsLength::In(l)) => (l, "in"),
+            Length::Absolute(AbsLength::Cm(l)) => (l, "cm"),
+            Length::Absolute(AbsLength::Mm(l)) => (l, "mm"),
+            Length::Relative(RelLength::Em(l)) => (l, "em"),
+            Length::Relative(RelLength::Ch(l)) => (l, "ch"),
+            Length::Relative(RelLength::Ex(l)) => (l, "ex"),
+            Length::Relative(RelLength::Rem(l)) => (l, "rem"),
+            Length::Relative(RelLength::Vh(l)) => (l, "vh"),
+            Length::Relative(RelLength::Vw(l)) => (l, "vw"),
+            Length::Relative(RelLength::Vmin(l)) => (l, "vmin"),
+            Length::Relative(RelLength::Vmax(l)) => (l, "vmax"),
+            Length::Relative(RelLength::Percent(l)) => (l, "%"),
+        };
+        write!(f, "{}{}", number, unit)
+    }
+}
+
+/// An error when parsing a `Length`.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum LengthError {
+    /// The length's number couldn't be parsed.
+    #[error("Invalid length {0:?}")]
+    Invalid(String),
+
+    /// The length has no unit.
+    #[error("Length {0:?} is missing a unit")]
+    MissingUnit(String),
+
+    /// The length's unit isn't known.
+    #[error("Unknown length unit {0:?}")]
+    UnknownUnit(String),
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_length() {
+        assert_eq!(
+            Ok(Length::Relative(RelLength::Percent(30.0))),
+            "30%".parse()
+        );
+        assert_eq!(Ok(Length::Relative(RelLength::Em(1.5))), " 1.5em".parse());
+        assert_eq!(Ok(Length::Absolute(AbsLength::In(2.0))), "2IN".parse());
+        assert_eq!(
+            Err(LengthError::MissingUnit("12".into())),
+            "12".parse::<Length>()
+        );
+        assert_eq!(
+            Err(LengthError::UnknownUnit("furlongs".into())),
+            "3furlongs".parse::<Length>()
+        );
+        assert_eq!(
+            Err(LengthError::Invalid("em".into())),
+            "em".parse::<Length>()
+        );
+    }
+
+    #[test]
+    fn test_display_length() {
+        assert_eq!(
+            "30%",
+            Length::Relative(RelLength::Percent(30.0)).to_string()
+        );
+        assert_eq!("0.5em", Length::Relative(RelLength::Em(0.5)).to_string());
+    }
 }
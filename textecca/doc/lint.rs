@@ -0,0 +1,689 @@
+//! Style checks over an evaluated document, like headings without text or
+//! images without alt text; see `lint`.
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use super::{
+    meta_hints, Block, BlockInner, Doc, DocNode, Footnote, Id, Image, Inline, InlineCode,
+    InlineMath, Link, LinkTarget, Meta, Style,
+};
+use crate::diagnostic::ErrorCode;
+
+/// The prefix of `Meta` keys configuring `lint`, e.g. `lint:long-footnote`;
+/// see `LintConfig::from_meta`.
+pub const LINT_HINT_PREFIX: &str = "lint";
+
+/// A style check made by `lint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LintRule {
+    /// A heading has no text.
+    EmptyHeading,
+    /// A footnote is longer than `LintConfig::max_footnote_words`.
+    LongFootnote,
+    /// A paragraph has more sentences than
+    /// `LintConfig::max_paragraph_sentences`.
+    LongParagraph,
+    /// Strong text is inside strong text, e.g. `\strong{a \strong{b}}`, so it
+    /// looks the same as the text around it.
+    NestedStrong,
+    /// An image has no alt text.
+    MissingAlt,
+    /// A link goes to the same place as the link before it, with only space
+    /// between them.
+    RepeatedLink,
+}
+
+impl LintRule {
+    /// Every rule, in order.
+    pub const ALL: &'static [LintRule] = &[
+        LintRule::EmptyHeading,
+        LintRule::LongFootnote,
+        LintRule::LongParagraph,
+        LintRule::NestedStrong,
+        LintRule::MissingAlt,
+        LintRule::RepeatedLink,
+    ];
+
+    /// The rule's name, as used in `Meta` keys, e.g. `empty-heading`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::EmptyHeading => "empty-heading",
+            Self::LongFootnote => "long-footnote",
+            Self::LongParagraph => "long-paragraph",
+            Self::NestedStrong => "nested-strong",
+            Self::MissingAlt => "missing-alt",
+            Self::RepeatedLink => "repeated-link",
+        }
+    }
+}
+
+impl Display for LintRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for LintRule {
+    type Err = InvalidLintConfig;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|rule| rule.name() == s)
+            .ok_or_else(|| InvalidLintConfig::UnknownRule(s.to_owned()))
+    }
+}
+
+/// Which rules `lint` checks, and their limits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintConfig {
+    /// The rules which aren't checked.
+    pub disabled: HashSet<LintRule>,
+    /// The most words a footnote may have; see `LintRule::LongFootnote`.
+    pub max_footnote_words: usize,
+    /// The most sentences a paragraph may have; see
+    /// `LintRule::LongParagraph`.
+    pub max_paragraph_sentences: usize,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            disabled: HashSet::new(),
+            max_footnote_words: 100,
+            max_paragraph_sentences: 12,
+        }
+    }
+}
+
+impl LintConfig {
+    /// The default configuration, changed by the document's metadata.
+    ///
+    /// Each rule is configured by the key `lint:` followed by its name (see
+    /// `LintRule::name`), e.g. `\set{lint:nested-strong}{false}` in the
+    /// document or `-D lint:nested-strong=false` on the command line. The
+    /// value is `true` or `false` to check the rule or not, or, for
+    /// `long-footnote` and `long-paragraph`, a number to check the rule with
+    /// that limit.
+    ///
+    /// ```
+    /// use textecca::doc::{LintConfig, LintRule, Meta};
+    ///
+    /// let mut meta = Meta::new();
+    /// meta.insert("lint:missing-alt".into(), "false".into());
+    /// meta.insert("lint:long-footnote".into(), "50".into());
+    /// let config = LintConfig::from_meta(&meta).unwrap();
+    /// assert!(!config.is_enabled(LintRule::MissingAlt));
+    /// assert_eq!(50, config.max_footnote_words);
+    /// ```
+    pub fn from_meta(meta: &Meta) -> Result<Self, InvalidLintConfig> {
+        let mut ret = Self::default();
+        for (name, value) in meta_hints(meta, LINT_HINT_PREFIX) {
+            let rule = name.parse()?;
+            let invalid = || InvalidLintConfig::Value {
+                rule,
+                value: value.to_owned(),
+            };
+            match (rule, value) {
+                (_, "true") => {
+                    ret.disabled.remove(&rule);
+                }
+                (_, "false") => {
+                    ret.disabled.insert(rule);
+                }
+                (LintRule::LongFootnote, limit) => {
+                    ret.max_footnote_words = limit.parse().map_err(|_| invalid())?;
+                    ret.disabled.remove(&rule);
+                }
+                (LintRule::LongParagraph, limit) => {
+                    ret.max_paragraph_sentences = limit.parse().map_err(|_| invalid())?;
+                    ret.disabled.remove(&rule);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Is the rule checked?
+    pub fn is_enabled(&self, rule: LintRule) -> bool {
+        !self.disabled.contains(&rule)
+    }
+}
+
+/// Invalid `lint:` metadata; see `LintConfig::from_meta`.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum InvalidLintConfig {
+    /// There's no rule with the given name.
+    #[error("Unknown lint rule {0:?}")]
+    UnknownRule(String),
+
+    /// The rule's value isn't `true`, `false`, or a limit it takes.
+    #[error("Invalid value {value:?} for lint:{rule}")]
+    Value {
+        /// The rule being configured.
+        rule: LintRule,
+        /// The invalid value.
+        value: String,
+    },
+}
+
+impl InvalidLintConfig {
+    /// The stable code for this error; see `diagnostic::ErrorCode`.
+    pub fn code(&self) -> ErrorCode {
+        ErrorCode::InvalidMeta
+    }
+}
+
+/// A problem found by `lint`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintWarning {
+    /// The rule the document breaks.
+    pub rule: LintRule,
+    /// The innermost block containing the problem.
+    pub block: Id,
+    /// A human-readable description of the problem.
+    pub message: String,
+}
+
+/// Writes the message followed by the rule's `Meta` key, so it's clear how to
+/// turn the rule off.
+impl Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (block {}; {}:{})",
+            self.message, self.block, LINT_HINT_PREFIX, self.rule
+        )
+    }
+}
+
+/// Check the document's content against the rules enabled in `config`,
+/// returning the problems found in document order.
+///
+/// Footnotes are checked where they're referenced, and problems in them are
+/// reported in their own blocks. Problems are located by block id; a
+/// `DocBuilder` records where in the source each block starts (see
+/// `DocBuilder::try_into_located`).
+pub fn lint(doc: &Doc, config: &LintConfig) -> Vec<LintWarning> {
+    let mut linter = Linter {
+        config,
+        warnings: Vec::new(),
+    };
+    // Like `nodes`, the traversal keeps its own stack rather than recursing,
+    // along with each node's context.
+    let mut stack: Vec<(DocNode, Context)> = doc
+        .content
+        .iter()
+        .rev()
+        .map(|block| (DocNode::Block(block), Context::in_block(block)))
+        .collect();
+    let mut children = Vec::new();
+    while let Some((node, context)) = stack.pop() {
+        let context = match node {
+            DocNode::Block(block) => {
+                linter.block(block);
+                Context::in_block(block)
+            }
+            DocNode::Inlines(inlines) => {
+                linter.inlines(inlines, context);
+                context
+            }
+            DocNode::Inline(inline) => {
+                linter.inline(inline, context);
+                Context {
+                    strong: context.strong || is_strong(inline),
+                    ..context
+                }
+            }
+        };
+        node.children(&mut children);
+        stack.extend(children.drain(..).rev().map(|child| (child, context)));
+    }
+    linter.warnings
+}
+
+/// Where a `DocNode` is in the document.
+#[derive(Clone, Copy)]
+struct Context {
+    /// The innermost block containing the node.
+    block: Id,
+    /// Whether the node is inside strong text.
+    strong: bool,
+}
+
+impl Context {
+    fn in_block(block: &Block) -> Self {
+        Self {
+            block: block.id,
+            strong: false,
+        }
+    }
+}
+
+struct Linter<'c> {
+    config: &'c LintConfig,
+    warnings: Vec<LintWarning>,
+}
+
+impl Linter<'_> {
+    fn warn(&mut self, rule: LintRule, block: Id, message: String) {
+        if self.config.is_enabled(rule) {
+            self.warnings.push(LintWarning {
+                rule,
+                block,
+                message,
+            });
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        match &block.inner {
+            BlockInner::Heading(heading)
+                if text(&[DocNode::Inlines(&heading.text)]).trim().is_empty() =>
+            {
+                self.warn(
+                    LintRule::EmptyHeading,
+                    block.id,
+                    "Heading has no text".into(),
+                );
+            }
+            BlockInner::Par(inlines) => {
+                let sentences = sentences(&text(&[DocNode::Inlines(inlines)]));
+                if sentences > self.config.max_paragraph_sentences {
+                    self.warn(
+                        LintRule::LongParagraph,
+                        block.id,
+                        format!(
+                            "Paragraph has {} sentences, more than {}",
+                            sentences, self.config.max_paragraph_sentences
+                        ),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Check a sequence of inlines, before each inline in it is checked.
+    fn inlines(&mut self, inlines: &[Inline], context: Context) {
+        let mut previous: Option<&LinkTarget> = None;
+        for inline in inlines {
+            match inline {
+                Inline::Link(Link { target, .. }) => {
+                    if previous == Some(target) {
+                        self.warn(
+                            LintRule::RepeatedLink,
+                            context.block,
+                            format!("Link to {} repeats the link before it", target.as_str()),
+                        );
+                    }
+                    previous = Some(target);
+                }
+                Inline::Space | Inline::LineBreak => {}
+                Inline::Text(text) if text.trim().is_empty() => {}
+                _ => previous = None,
+            }
+        }
+    }
+
+    fn inline(&mut self, inline: &Inline, context: Context) {
+        match inline {
+            Inline::Styled {
+                style: Style::Strong,
+                ..
+            } if context.strong => {
+                self.warn(
+                    LintRule::NestedStrong,
+                    context.block,
+                    "Strong text is inside strong text".into(),
+                );
+            }
+            Inline::Image(Image { src, alt, .. }) if !matches!(alt, Some(alt) if !alt.trim().is_empty()) =>
+            {
+                self.warn(
+                    LintRule::MissingAlt,
+                    context.block,
+                    format!("Image {} has no alt text", src),
+                );
+            }
+            Inline::Footnote(Footnote { content }) => {
+                let nodes: Vec<_> = content.iter().map(DocNode::Block).collect();
+                let words = text(&nodes).split_whitespace().count();
+                if words > self.config.max_footnote_words {
+                    self.warn(
+                        LintRule::LongFootnote,
+                        context.block,
+                        format!(
+                            "Footnote has {} words, more than {}",
+                            words, self.config.max_footnote_words
+                        ),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_strong(inline: &Inline) -> bool {
+    matches!(
+        inline,
+        Inline::Styled {
+            style: Style::Strong,
+            ..
+        }
+    )
+}
+
+/// The text of some nodes, e.g. to count its words. Blocks are separated by
+/// spaces, and footnotes are left out, since they aren't read as part of the
+/// text around them.
+fn text(nodes: &[DocNode]) -> String {
+    let mut ret = String::new();
+    let mut stack: Vec<DocNode> = nodes.iter().rev().copied().collect();
+    let mut children = Vec::new();
+    while let Some(node) = stack.pop() {
+        match node {
+            DocNode::Block(_) => ret.push(' '),
+            DocNode::Inlines(_) => {}
+            DocNode::Inline(inline) => match inline {
+                Inline::Text(text)
+                | Inline::Code(InlineCode { content: text, .. })
+                | Inline::Math(InlineMath { tex: text, .. }) => ret.push_str(text),
+                Inline::Image(Image { alt: Some(alt), .. }) => ret.push_str(alt),
+                Inline::Space | Inline::LineBreak => ret.push(' '),
+                Inline::Footnote(_) => continue,
+                _ => {}
+            },
+        }
+        node.children(&mut children);
+        stack.extend(children.drain(..).rev());
+    }
+    ret
+}
+
+/// The number of sentences in some text: runs of text ending in `.`, `!`, or
+/// `?` followed by whitespace or the end of the text, and any text after the
+/// last of them. Abbreviations like "e.g." end sentences too.
+fn sentences(text: &str) -> usize {
+    let mut ret = 0;
+    let mut in_sentence = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if matches!(c, '.' | '!' | '?') {
+            let at_end = !matches!(chars.peek(), Some(next) if !next.is_whitespace());
+            if in_sentence && at_end {
+                ret += 1;
+                in_sentence = false;
+            }
+        } else if !c.is_whitespace() {
+            in_sentence = true;
+        }
+    }
+    ret + in_sentence as usize
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::doc::{Blocks, Heading, Inlines};
+
+    fn plain(s: &str) -> Inlines {
+        vec![Inline::Text(s.into())]
+    }
+
+    fn par(id: usize, inlines: Inlines) -> Block {
+        Block::new(Id::from(id), BlockInner::Par(inlines))
+    }
+
+    fn strong(content: Inlines) -> Inline {
+        Inline::Styled {
+            style: Style::Strong,
+            content,
+        }
+    }
+
+    fn link(url: &str) -> Inline {
+        Inline::Link(Link {
+            content: Some(plain("a link")),
+            label: None,
+            target: LinkTarget::URL(url.into()),
+            attrs: Meta::new(),
+        })
+    }
+
+    fn image(alt: Option<&str>) -> Inline {
+        Inline::Image(Image {
+            src: "cat.png".into(),
+            alt: alt.map(Into::into),
+            attrs: Meta::new(),
+        })
+    }
+
+    fn footnote(id: usize, words: usize) -> Inline {
+        Inline::Footnote(Footnote {
+            content: par(id, plain(&vec!["word"; words].join(" "))).into(),
+        })
+    }
+
+    fn heading(id: usize, text: Inlines) -> Block {
+        Block::new(
+            Id::from(id),
+            BlockInner::Heading(Heading {
+                level: 1,
+                text,
+                attrs: Meta::new(),
+            }),
+        )
+    }
+
+    fn lint_with(blocks: Vec<Block>, config: &LintConfig) -> Vec<(LintRule, Id)> {
+        lint(&Doc::from_content(Blocks::from(blocks)), config)
+            .into_iter()
+            .map(|warning| (warning.rule, warning.block))
+            .collect()
+    }
+
+    fn check(blocks: Vec<Block>) -> Vec<(LintRule, Id)> {
+        lint_with(blocks, &LintConfig::default())
+    }
+
+    /// A document breaking every rule once, in order.
+    fn bad_doc() -> Vec<Block> {
+        vec![
+            heading(0, vec![Inline::Space]),
+            par(1, vec![footnote(2, 101)]),
+            par(3, plain(&"A sentence. ".repeat(13))),
+            par(4, vec![strong(vec![strong(plain("Very"))])]),
+            par(5, vec![image(None)]),
+            par(6, vec![link("a"), Inline::Space, link("a")]),
+        ]
+    }
+
+    #[test]
+    fn test_empty_heading() {
+        assert_eq!(
+            vec![
+                (LintRule::EmptyHeading, Id::from(0)),
+                (LintRule::EmptyHeading, Id::from(1)),
+            ],
+            check(vec![
+                heading(0, Vec::new()),
+                heading(1, vec![strong(vec![Inline::Space])]),
+                heading(2, plain("Introduction")),
+                heading(
+                    3,
+                    vec![Inline::Math(InlineMath {
+                        tex: "x".into(),
+                        alt: None,
+                    })]
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_long_footnote() {
+        assert_eq!(
+            vec![(LintRule::LongFootnote, Id::from(0))],
+            check(vec![
+                par(0, vec![footnote(1, 101)]),
+                par(2, vec![footnote(3, 100)])
+            ])
+        );
+        let config = LintConfig {
+            max_footnote_words: 5,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![(LintRule::LongFootnote, Id::from(2))],
+            lint_with(
+                vec![par(0, vec![footnote(1, 5)]), par(2, vec![footnote(3, 6)])],
+                &config
+            )
+        );
+    }
+
+    #[test]
+    fn test_long_paragraph() {
+        assert_eq!(
+            vec![(LintRule::LongParagraph, Id::from(1))],
+            check(vec![
+                par(0, plain(&"A sentence. ".repeat(12))),
+                par(1, plain(&format!("{}Maybe", "Yes! No? ".repeat(6)))),
+            ])
+        );
+        assert_eq!(0, sentences(" "));
+        assert_eq!(1, sentences("No ending"));
+        assert_eq!(2, sentences("What?! Really..."));
+        assert_eq!(2, sentences("Version 1.5 is out. Try it"));
+        assert_eq!(3, sentences("Fruit, e.g. apples. Yum."));
+    }
+
+    #[test]
+    fn test_nested_strong() {
+        assert_eq!(
+            vec![(LintRule::NestedStrong, Id::from(1))],
+            check(vec![
+                par(0, vec![strong(plain("a")), strong(plain("b"))]),
+                par(
+                    1,
+                    vec![strong(vec![Inline::Styled {
+                        style: Style::Emph,
+                        content: vec![strong(plain("c"))],
+                    }])]
+                ),
+            ])
+        );
+        // Footnotes aren't part of the strong text they're in.
+        assert_eq!(
+            Vec::<(LintRule, Id)>::new(),
+            check(vec![par(
+                0,
+                vec![strong(vec![Inline::Footnote(Footnote {
+                    content: par(1, vec![strong(plain("d"))]).into(),
+                })])]
+            )])
+        );
+    }
+
+    #[test]
+    fn test_missing_alt() {
+        assert_eq!(
+            vec![
+                (LintRule::MissingAlt, Id::from(0)),
+                (LintRule::MissingAlt, Id::from(0)),
+            ],
+            check(vec![par(
+                0,
+                vec![image(None), image(Some(" ")), image(Some("A cat"))]
+            )])
+        );
+    }
+
+    #[test]
+    fn test_repeated_link() {
+        assert_eq!(
+            vec![(LintRule::RepeatedLink, Id::from(0))],
+            check(vec![
+                par(0, vec![link("a"), Inline::Space, link("a")]),
+                par(1, vec![link("a"), Inline::Text(" and ".into()), link("a")]),
+                par(2, vec![link("a"), link("b")]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_all_rules() {
+        let expected: Vec<_> = LintRule::ALL
+            .iter()
+            .zip(&[0, 1, 3, 4, 5, 6])
+            .map(|(rule, id)| (*rule, Id::from(*id)))
+            .collect();
+        assert_eq!(expected, check(bad_doc()));
+    }
+
+    #[test]
+    fn test_disabled_rules() {
+        let mut meta = Meta::new();
+        for rule in LintRule::ALL {
+            meta.insert(format!("lint:{}", rule), "false".into());
+        }
+        let config = LintConfig::from_meta(&meta).unwrap();
+        assert_eq!(Vec::<(LintRule, Id)>::new(), lint_with(bad_doc(), &config));
+
+        meta.insert("lint:missing-alt".into(), "true".into());
+        meta.insert("lint:long-paragraph".into(), "20".into());
+        let config = LintConfig::from_meta(&meta).unwrap();
+        assert_eq!(
+            vec![(LintRule::MissingAlt, Id::from(5))],
+            lint_with(bad_doc(), &config)
+        );
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let config = |key: &str, value: &str| {
+            let mut meta = Meta::new();
+            meta.insert(key.into(), value.into());
+            LintConfig::from_meta(&meta)
+        };
+        assert_eq!(
+            Err(InvalidLintConfig::UnknownRule("typos".into())),
+            config("lint:typos", "false")
+        );
+        assert_eq!(
+            Err(InvalidLintConfig::Value {
+                rule: LintRule::MissingAlt,
+                value: "3".into(),
+            }),
+            config("lint:missing-alt", "3")
+        );
+        assert_eq!(
+            "Invalid value \"many\" for lint:long-footnote",
+            config("lint:long-footnote", "many")
+                .unwrap_err()
+                .to_string()
+        );
+        // Other keys aren't lint settings.
+        assert_eq!(Ok(LintConfig::default()), config("linter", "false"));
+    }
+
+    #[test]
+    fn test_warning_display() {
+        let warnings = lint(
+            &Doc::from_content(Blocks::from(vec![par(7, vec![image(None)])])),
+            &LintConfig::default(),
+        );
+        assert_eq!(
+            "Image cat.png has no alt text (block 7; lint:missing-alt)",
+            warnings[0].to_string()
+        );
+    }
+}
@@ -0,0 +1,467 @@
+//! Serialized documents, and migrating them from older versions of the
+//! document model.
+//!
+//! A serialized document is a JSON object with the version of the model it
+//! was serialized with and the document itself, like `{"version": 3, "doc":
+//! {...}}`; see `to_json`. Whenever the model's serialized form changes,
+//! `DOC_FORMAT_VERSION` goes up and a migration from the previous version is
+//! added to `MIGRATIONS`, so documents serialized by older versions of
+//! textecca (e.g. cached, or written by other tools) still load with
+//! `migrate`.
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use super::Doc;
+use crate::diagnostic::ErrorCode;
+
+/// The version of the document model's serialized form; see `to_json`.
+///
+/// 1. The first version.
+/// 2. Added `Doc::collections`.
+/// 3. Added `Inline::Raw`.
+/// 4. `Color`s have components or names.
+/// 5. Added `BlockInner::Raw`.
+/// 6. Added `Figure::number`.
+pub const DOC_FORMAT_VERSION: u64 = 6;
+
+/// A migration of a serialized `Doc` (without the surrounding version) from
+/// one format version to the next.
+type Migration = fn(Value) -> Result<Value, MigrateError>;
+
+/// `MIGRATIONS[i]` migrates documents from version `i + 1` to `i + 2`.
+const MIGRATIONS: &[Migration] = &[v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6];
+
+fn v1_to_v2(mut doc: Value) -> Result<Value, MigrateError> {
+    doc.as_object_mut()
+        .ok_or_else(|| MigrateError::NotADoc("the document isn't an object".into()))?
+        .insert("collections".into(), Value::Object(Map::new()));
+    Ok(doc)
+}
+
+/// Older documents have no raw content, so they're unchanged.
+fn v2_to_v3(doc: Value) -> Result<Value, MigrateError> {
+    Ok(doc)
+}
+
+/// Colors used to be empty, so colored text carries no color; it becomes
+/// text tagged with no metadata, which serializers write as it is.
+fn v3_to_v4(mut doc: Value) -> Result<Value, MigrateError> {
+    fn uncolor(value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                let colored = map
+                    .get("Styled")
+                    .and_then(|styled| styled.get("style"))
+                    .and_then(|style| style.get("Color"))
+                    .is_some();
+                if colored {
+                    let mut styled = map.remove("Styled").unwrap();
+                    let content = styled
+                        .as_object_mut()
+                        .and_then(|styled| styled.remove("content"))
+                        .unwrap_or_else(|| Value::Array(Vec::new()));
+                    map.insert(
+                        "Tagged".into(),
+                        serde_json::json!({ "content": content, "meta": {} }),
+                    );
+                }
+                map.values_mut().for_each(uncolor);
+            }
+            Value::Array(values) => values.iter_mut().for_each(uncolor),
+            _ => {}
+        }
+    }
+    uncolor(&mut doc);
+    Ok(doc)
+}
+
+/// Older documents have no raw blocks, so they're unchanged.
+fn v4_to_v5(doc: Value) -> Result<Value, MigrateError> {
+    Ok(doc)
+}
+
+/// Older documents' figures have no recorded number, which loads as `None`,
+/// so they're unchanged.
+fn v5_to_v6(doc: Value) -> Result<Value, MigrateError> {
+    Ok(doc)
+}
+
+/// Serialize a document with the current `DOC_FORMAT_VERSION`, for loading
+/// later with `migrate`.
+pub fn to_json(doc: &Doc) -> Value {
+    let mut ret = Map::new();
+    ret.insert("version".into(), DOC_FORMAT_VERSION.into());
+    ret.insert(
+        "doc".into(),
+        serde_json::to_value(doc).expect("Documents always serialize"),
+    );
+    Value::Object(ret)
+}
+
+/// Load a document serialized with `to_json` by this or any earlier version
+/// of textecca, migrating it to the current document model.
+pub fn migrate(value: Value) -> Result<Doc, MigrateError> {
+    let mut value = match value {
+        Value::Object(value) => value,
+        _ => return Err(MigrateError::NotADoc("it isn't an object".into())),
+    };
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| MigrateError::NotADoc("it has no version".into()))?;
+    let mut doc = value
+        .remove("doc")
+        .ok_or_else(|| MigrateError::NotADoc("it has no document".into()))?;
+    if version == 0 || version > DOC_FORMAT_VERSION {
+        return Err(MigrateError::Version(version));
+    }
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        doc = migration(doc)?;
+    }
+    Ok(serde_json::from_value(doc)?)
+}
+
+/// An error loading a serialized document; see `migrate`.
+#[derive(Error, Debug)]
+pub enum MigrateError {
+    /// The value isn't a serialized document at all.
+    #[error("Not a serialized document: {0}")]
+    NotADoc(String),
+
+    /// The document is from a newer (or unknown) version of the model.
+    #[error(
+        "The document has format version {0}, but this version of textecca \
+         only reads versions 1 to {}",
+        DOC_FORMAT_VERSION
+    )]
+    Version(u64),
+
+    /// The document doesn't match the model, even after migrating it.
+    #[error("Invalid serialized document: {0}")]
+    Invalid(#[from] serde_json::Error),
+}
+
+impl MigrateError {
+    /// The stable code for this error; see `diagnostic::ErrorCode`.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::NotADoc(_) | Self::Invalid(_) => ErrorCode::InvalidSerializedDoc,
+            Self::Version(_) => ErrorCode::DocFormatVersion,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::doc::*;
+    use crate::ser::fnv1a;
+
+    fn text(s: &str) -> Inlines {
+        vec![Inline::Text(s.into())]
+    }
+
+    fn par(id: usize, s: &str) -> Block {
+        Block::new(Id::from(id), BlockInner::Par(text(s)))
+    }
+
+    fn meta(pairs: &[(&str, &str)]) -> Meta {
+        pairs
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect()
+    }
+
+    /// A document with every kind of block and inline, so that changing any
+    /// of them changes its serialization.
+    fn fixture() -> Doc {
+        let inlines = vec![
+            Inline::Text("Text".into()),
+            Inline::Styled {
+                style: Style::Size(Length::Absolute(AbsLength::Pt(12.0))),
+                content: text("styled"),
+            },
+            Inline::Styled {
+                style: Style::Color(Color::Rgb(0x66, 0x33, 0x99)),
+                content: text("colored"),
+            },
+            Inline::Quote(Quote {
+                kind: QuoteKind::Other(Box::new(text("<")), Box::new(text(">"))),
+                content: text("quoted"),
+            }),
+            Inline::Code(InlineCode {
+                language: Some("rust".into()),
+                content: "let x = 1;".into(),
+                attrs: meta(&[("class", "x")]),
+            }),
+            Inline::Space,
+            Inline::LineBreak,
+            Inline::Link(Link {
+                content: Some(text("a link")),
+                label: None,
+                target: LinkTarget::Label(RefId::new("fig:a").unwrap()),
+                attrs: Meta::new(),
+            }),
+            Inline::Footnote(Footnote {
+                content: par(1, "A note.").into(),
+            }),
+            Inline::Math(InlineMath {
+                tex: "x^2".into(),
+                alt: Some("x squared".into()),
+            }),
+            Inline::Image(Image {
+                src: "a.png".into(),
+                alt: None,
+                attrs: Meta::new(),
+            }),
+            Inline::Raw(RawInline {
+                format: "html".into(),
+                content: "<kbd>Ctrl</kbd>".into(),
+            }),
+            Inline::Tagged(TaggedInlines {
+                content: text("tagged"),
+                meta: meta(&[("lang", "de"), ("dir", "ltr")]),
+            }),
+        ];
+        let blocks = vec![
+            BlockInner::Plain(text("Plain")),
+            BlockInner::Par(inlines),
+            BlockInner::Code(Code {
+                line_numbers: Some(LineNumbers { start: 3 }),
+                lines: vec![text("fn main() {}")],
+                highlight_lines: vec![0..1, 2..3],
+                ..Default::default()
+            }),
+            BlockInner::Quote(par(2, "Quoted.").into()),
+            BlockInner::List(List {
+                kind: ListKind::Ordered,
+                items: vec![ListItem {
+                    content: par(3, "An item.").into(),
+                }],
+            }),
+            BlockInner::TermList(vec![TermListItem {
+                term: text("Term"),
+                content: par(4, "Definition.").into(),
+            }]),
+            BlockInner::Heading(Heading {
+                level: 1,
+                text: text("Heading"),
+                attrs: meta(&[("id", "heading")]),
+            }),
+            BlockInner::Rule,
+            BlockInner::Table(Table {
+                columns: vec![TableColumn {
+                    alignment: Alignment::Center,
+                    width: Some(Length::Relative(RelLength::Percent(50.0))),
+                }],
+                cells: vec![vec![TableCell {
+                    content: par(5, "Cell.").into(),
+                    ..Default::default()
+                }]],
+            }),
+            BlockInner::Figure(Figure {
+                kind: FigureKind::Other("plate".into()),
+                caption: text("A caption."),
+                content: par(6, "Figure.").into(),
+                label: Some(RefId::new("fig:a").unwrap()),
+                number: None,
+            }),
+            BlockInner::Defn(Defn {
+                name: text("Term"),
+                summary: par(7, "Summary.").into(),
+                content: Blocks::default(),
+            }),
+            BlockInner::Math(Math {
+                tex: "\\sum i".into(),
+                alt: None,
+            }),
+            BlockInner::Raw(RawBlock {
+                format: "latex".into(),
+                content: "\\newpage".into(),
+            }),
+            BlockInner::Tagged(TaggedBlocks {
+                content: par(8, "Tagged.").into(),
+                meta: meta(&[("todo", "")]),
+            }),
+        ];
+        // Adding a kind of block or inline fails to compile here; add it to
+        // the fixture too.
+        for block in &blocks {
+            match block {
+                BlockInner::Plain(_)
+                | BlockInner::Par(_)
+                | BlockInner::Code(_)
+                | BlockInner::Quote(_)
+                | BlockInner::List(_)
+                | BlockInner::TermList(_)
+                | BlockInner::Heading(_)
+                | BlockInner::Rule
+                | BlockInner::Table(_)
+                | BlockInner::Figure(_)
+                | BlockInner::Defn(_)
+                | BlockInner::Math(_)
+                | BlockInner::Raw(_)
+                | BlockInner::Tagged(_) => {}
+            }
+        }
+        if let BlockInner::Par(inlines) = &blocks[1] {
+            for inline in inlines {
+                match inline {
+                    Inline::Text(_)
+                    | Inline::Styled { .. }
+                    | Inline::Quote(_)
+                    | Inline::Code(_)
+                    | Inline::Space
+                    | Inline::LineBreak
+                    | Inline::Link(_)
+                    | Inline::Footnote(_)
+                    | Inline::Math(_)
+                    | Inline::Image(_)
+                    | Inline::Raw(_)
+                    | Inline::Tagged(_) => {}
+                }
+            }
+        }
+
+        let mut doc = Doc::from_content(
+            blocks
+                .into_iter()
+                .enumerate()
+                .map(|(id, inner)| Block::new(Id::from(id + 10), inner))
+                .collect::<Vec<_>>()
+                .into(),
+        );
+        doc.meta = meta(&[("title", "A document"), ("draft", "true")]);
+        doc.collections
+            .insert("abstract".into(), vec![par(9, "Summary.").into()]);
+        doc
+    }
+
+    /// `value` with its objects' keys sorted, so it serializes the same way
+    /// every time.
+    fn canonical(value: Value) -> Value {
+        match value {
+            Value::Object(map) => {
+                let mut entries: Vec<_> = map.into_iter().collect();
+                entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+                Value::Object(
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| (key, canonical(value)))
+                        .collect(),
+                )
+            }
+            Value::Array(values) => Value::Array(values.into_iter().map(canonical).collect()),
+            value => value,
+        }
+    }
+
+    /// The format version and the hash of `fixture`'s canonical
+    /// serialization.
+    const SCHEMA: (u64, u64) = (6, 7_263_033_391_701_184_526);
+
+    #[test]
+    fn test_schema_versioned() {
+        let json = serde_json::to_string(&canonical(to_json(&fixture()))).unwrap();
+        assert_eq!(
+            SCHEMA,
+            (DOC_FORMAT_VERSION, fnv1a(json.as_bytes())),
+            "The document model's serialized form changed. Increment \
+             DOC_FORMAT_VERSION, add a migration from the previous version, \
+             and update SCHEMA. Serialized fixture: {}",
+            json
+        );
+        assert_eq!(DOC_FORMAT_VERSION as usize - 1, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let doc = fixture();
+        assert_eq!(doc, migrate(to_json(&doc)).unwrap());
+    }
+
+    #[test]
+    fn test_migrate_v1() {
+        let v1 = serde_json::from_str(include_str!("../tests/fixtures/doc-v1.json")).unwrap();
+        let mut expected = Doc::from_content(
+            vec![
+                Block::new(
+                    Id::from(0),
+                    BlockInner::Heading(Heading {
+                        level: 1,
+                        text: text("Introduction"),
+                        attrs: Meta::new(),
+                    }),
+                ),
+                par(1, "Some text."),
+            ]
+            .into(),
+        );
+        expected.meta = meta(&[("title", "A document")]);
+        assert_eq!(expected, migrate(v1).unwrap());
+    }
+
+    #[test]
+    fn test_migrate_v3_colors() {
+        let mut v3 = serde_json::json!({
+            "version": 3,
+            "doc": to_json(&Doc::from_content(par(0, "").into()))["doc"].clone(),
+        });
+        v3["doc"]["content"][0]["inner"]["Par"] = serde_json::json!([
+            { "Styled": { "style": { "Color": {} }, "content": [
+                { "Styled": { "style": "Emph", "content": [{ "Text": "red" }] } },
+            ] } },
+        ]);
+        let expected = Doc::from_content(
+            Block::new(
+                Id::from(0),
+                BlockInner::Par(vec![Inline::Tagged(TaggedInlines {
+                    content: vec![Inline::Styled {
+                        style: Style::Emph,
+                        content: text("red"),
+                    }],
+                    meta: Meta::new(),
+                })]),
+            )
+            .into(),
+        );
+        assert_eq!(expected, migrate(v3).unwrap());
+    }
+
+    #[test]
+    fn test_migrate_errors() {
+        let doc = serde_json::to_value(fixture()).unwrap();
+        for (value, code) in [
+            (Value::Null, ErrorCode::InvalidSerializedDoc),
+            (
+                serde_json::json!({ "doc": doc.clone() }),
+                ErrorCode::InvalidSerializedDoc,
+            ),
+            (
+                serde_json::json!({ "version": 0, "doc": doc.clone() }),
+                ErrorCode::DocFormatVersion,
+            ),
+            (
+                serde_json::json!({ "version": DOC_FORMAT_VERSION + 1, "doc": doc }),
+                ErrorCode::DocFormatVersion,
+            ),
+            (
+                serde_json::json!({ "version": 1, "doc": [] }),
+                ErrorCode::InvalidSerializedDoc,
+            ),
+            (
+                serde_json::json!({ "version": 3, "doc": { "meta": {} } }),
+                ErrorCode::InvalidSerializedDoc,
+            ),
+        ] {
+            assert_eq!(code, migrate(value).unwrap_err().code());
+        }
+        assert_eq!(
+            "The document has format version 7, but this version of textecca only reads \
+             versions 1 to 6",
+            MigrateError::Version(7).to_string()
+        );
+    }
+}
@@ -5,16 +5,36 @@
 //! `ser` module) render `Block`s into a particular output format.
 mod blocks;
 mod builder;
+mod color;
+mod footnotes;
 mod inlines;
 mod iter;
 mod length;
+mod lint;
+#[cfg(feature = "cli-support")]
+mod migrate;
+mod numbering;
 mod ref_id;
+mod speech;
 mod structure;
+mod tex;
+pub mod text;
+mod toc;
 
 pub use blocks::*;
 pub use builder::*;
+pub use color::*;
+pub use footnotes::*;
 pub use inlines::*;
 pub use iter::*;
 pub use length::*;
+pub use lint::*;
+#[cfg(feature = "cli-support")]
+pub use migrate::*;
+pub use numbering::*;
 pub use ref_id::*;
+pub use speech::*;
 pub use structure::*;
+pub use tex::*;
+pub use text::*;
+pub use toc::*;
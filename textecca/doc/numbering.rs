@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{walk_mut, Block, BlockInner, Doc, Inline, Link, LinkTarget, RefId, VisitMut};
+
+/// Number the captioned figures in a document.
+///
+/// Each `FigureKind` is counted separately, in document order, and the label
+/// (e.g. "Figure 3:") is prepended to each captioned figure's caption. Links to
+/// a numbered figure's label with no content of their own are given the
+/// figure's label as their text (e.g. "Figure 3").
+///
+/// Numbering is skipped if the document metadata sets `number_figures` to
+/// `"false"`. The word used for each kind of figure may be localized by setting
+/// `{kind}_name` in the metadata, e.g. `figure_name = "Abbildung"`.
+pub fn number_figures(doc: &mut Doc) {
+    if doc.meta.get("number_figures").map(String::as_str) == Some("false") {
+        return;
+    }
+
+    let mut numberer = FigureNumberer {
+        meta: &doc.meta,
+        counters: Default::default(),
+        labels: Default::default(),
+    };
+    walk_mut(&mut doc.content, &mut numberer);
+
+    let mut resolver = RefResolver {
+        labels: numberer.labels,
+    };
+    walk_mut(&mut doc.content, &mut resolver);
+}
+
+struct FigureNumberer<'d> {
+    meta: &'d HashMap<String, String>,
+    /// Figure kind name to the number of figures of that kind seen so far.
+    counters: HashMap<String, usize>,
+    /// Figure label to the figure's numbered name, e.g. "Figure 3".
+    labels: HashMap<RefId, String>,
+}
+
+impl VisitMut for FigureNumberer<'_> {
+    fn visit_block(&mut self, block: &mut Block) {
+        if let BlockInner::Figure(figure) = &mut block.inner {
+            if figure.caption.is_empty() {
+                return;
+            }
+            let kind = figure.kind.name();
+            let counter = self.counters.entry(kind.to_owned()).or_insert(0);
+            *counter += 1;
+            let prefix = self
+                .meta
+                .get(&format!("{}_name", kind))
+                .map(String::as_str)
+                .unwrap_or_else(|| figure.kind.label_prefix());
+            let name = format!("{} {}", prefix, counter);
+            figure.caption.splice(
+                0..0,
+                vec![Inline::Text(format!("{}:", name)), Inline::Space],
+            );
+            if let Some(label) = &figure.label {
+                self.labels.insert(label.clone(), name.clone());
+            }
+            figure.number = Some(name);
+        }
+    }
+}
+
+struct RefResolver {
+    labels: HashMap<RefId, String>,
+}
+
+impl VisitMut for RefResolver {
+    fn visit_inline(&mut self, inline: &mut Inline) {
+        if let Inline::Link(Link {
+            content: content @ None,
+            target: LinkTarget::Label(label),
+            ..
+        }) = inline
+        {
+            if let Some(name) = self.labels.get(label) {
+                *content = Some(vec![Inline::Text(name.clone())]);
+            }
+        }
+    }
+}
+
+/// The labels which links in the document refer to, but which no figure
+/// defines, in the order they're first referred to.
+pub fn undefined_labels(doc: &mut Doc) -> Vec<RefId> {
+    let mut collector = LabelCollector::default();
+    walk_mut(&mut doc.content, &mut collector);
+    let LabelCollector { defined, refs } = collector;
+    let mut seen = HashSet::new();
+    refs.into_iter()
+        .filter(|label| !defined.contains(label) && seen.insert(label.clone()))
+        .collect()
+}
+
+#[derive(Default)]
+struct LabelCollector {
+    defined: HashSet<RefId>,
+    refs: Vec<RefId>,
+}
+
+impl VisitMut for LabelCollector {
+    fn visit_block(&mut self, block: &mut Block) {
+        if let BlockInner::Figure(figure) = &block.inner {
+            self.defined.extend(figure.label.clone());
+        }
+    }
+
+    fn visit_inline(&mut self, inline: &mut Inline) {
+        if let Inline::Link(Link {
+            target: LinkTarget::Label(label),
+            ..
+        }) = inline
+        {
+            self.refs.push(label.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::doc::{Figure, FigureKind, Id, Inlines};
+
+    fn text(s: &str) -> Inlines {
+        vec![Inline::Text(s.into())]
+    }
+
+    fn figure(id: usize, kind: FigureKind, label: Option<&str>) -> Block {
+        Block {
+            id: Id::from(id),
+            inner: BlockInner::Figure(Figure {
+                kind,
+                caption: text("A caption."),
+                content: Default::default(),
+                label: label.map(|label| RefId::new(label).unwrap()),
+                number: None,
+            }),
+        }
+    }
+
+    fn reference(id: usize, label: &str) -> Block {
+        Block {
+            id: Id::from(id),
+            inner: BlockInner::Par(vec![Inline::Link(Link {
+                content: None,
+                label: None,
+                target: LinkTarget::Label(RefId::new(label).unwrap()),
+                attrs: Default::default(),
+            })]),
+        }
+    }
+
+    fn caption(block: &Block) -> &Inlines {
+        match &block.inner {
+            BlockInner::Figure(figure) => &figure.caption,
+            _ => panic!("Expected a figure, got {:?}", block),
+        }
+    }
+
+    fn numbered(s: &str) -> Inlines {
+        vec![
+            Inline::Text(s.into()),
+            Inline::Space,
+            Inline::Text("A caption.".into()),
+        ]
+    }
+
+    fn test_doc() -> Doc {
+        Doc::from_content(
+            vec![
+                figure(0, FigureKind::Figure, None),
+                figure(1, FigureKind::Table, None),
+                figure(2, FigureKind::Figure, Some("fig:second")),
+                reference(3, "fig:second"),
+            ]
+            .into(),
+        )
+    }
+
+    #[test]
+    fn test_number_figures() {
+        let mut doc = test_doc();
+        number_figures(&mut doc);
+        assert_eq!(&numbered("Figure 1:"), caption(&doc.content[0]));
+        assert_eq!(&numbered("Table 1:"), caption(&doc.content[1]));
+        assert_eq!(&numbered("Figure 2:"), caption(&doc.content[2]));
+        match &doc.content[2].inner {
+            BlockInner::Figure(figure) => {
+                assert_eq!(Some("Figure 2"), figure.number.as_deref());
+                assert_eq!(&text("A caption.")[..], figure.unnumbered_caption());
+            }
+            other => panic!("Expected a figure, got {:?}", other),
+        }
+        assert_eq!(
+            BlockInner::Par(vec![Inline::Link(Link {
+                content: Some(text("Figure 2")),
+                label: None,
+                target: LinkTarget::Label(RefId::new("fig:second").unwrap()),
+                attrs: Default::default(),
+            })]),
+            doc.content[3].inner
+        );
+    }
+
+    #[test]
+    fn test_number_figures_meta() {
+        let mut doc = test_doc();
+        doc.meta.insert("figure_name".into(), "Abbildung".into());
+        number_figures(&mut doc);
+        assert_eq!(&numbered("Abbildung 2:"), caption(&doc.content[2]));
+
+        let mut doc = test_doc();
+        doc.meta.insert("number_figures".into(), "false".into());
+        number_figures(&mut doc);
+        assert_eq!(&text("A caption."), caption(&doc.content[2]));
+    }
+
+    #[test]
+    fn test_undefined_labels() {
+        let mut doc = test_doc();
+        doc.content.push(reference(4, "fig:missing"));
+        doc.content.push(reference(5, "fig:missing"));
+        assert_eq!(
+            vec![RefId::new("fig:missing").unwrap()],
+            undefined_labels(&mut doc)
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Identifiers for blocks and for the things users refer to.
+//!
+//! An `Id` is assigned to each `Block` by the `DocBuilder`; it's internal to a
+//! single build of a document and changes whenever content is added before
+//! the block. A `RefId` is a user-visible label, like a figure's label or a
+//! heading's anchor, which links refer to and which serializers write as
+//! (e.g.) HTML ids.
+use std::borrow::Borrow;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+#[cfg(feature = "cli-support")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{slugify, Inline};
+use crate::diagnostic::ErrorCode;
+
+/// A `Block` identifier, unique within a `Doc` and monotonically increasing.
+///
+/// Ids are formatted as decimal numbers, and parse from the same format:
+///
+/// ```
+/// use textecca::doc::Id;
+///
+/// let id = Id::from(12);
+/// assert_eq!("12", id.to_string());
+/// assert_eq!(Ok(id), "12".parse());
+/// ```
+///
+/// Ids are ordered as they're assigned, so a block's id is greater than the
+/// ids of the blocks before it in the same build of a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
+pub struct Id(usize);
+
+impl From<usize> for Id {
+    fn from(id: usize) -> Self {
+        Self(id)
+    }
+}
+
+impl From<Id> for usize {
+    fn from(id: Id) -> Self {
+        id.0
+    }
+}
+
+impl Iterator for Id {
+    type Item = Id;
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(Id(self.0 + 1))
+    }
+}
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Id {
+    type Err = ParseIdError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // `usize::from_str` also accepts a leading `+`.
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse().map(Id).map_err(|_| ParseIdError(s.to_owned()))
+        } else {
+            Err(ParseIdError(s.to_owned()))
+        }
+    }
+}
+
+/// A string which isn't a formatted `Id`.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("Invalid block id {0:?}; expected a number")]
+pub struct ParseIdError(pub String);
+
+/// A user-visible label for something in a document, e.g. `fig:results` for a
+/// figure or `Introduction` for a heading, which links refer to with
+/// `LinkTarget::Label`.
+///
+/// Labels are non-empty, and don't contain whitespace or control characters;
+/// otherwise, any characters (including punctuation and non-ASCII letters)
+/// are allowed, and serializers escape them as needed. Every slug (see
+/// `slugify`) is a valid label, so headings' anchors are labels too.
+///
+/// ```
+/// use textecca::doc::{InvalidRefId, RefId};
+///
+/// let label = RefId::new("fig:results").unwrap();
+/// assert_eq!("fig:results", label.as_str());
+/// assert_eq!(Err(InvalidRefId::Empty), RefId::new(""));
+/// assert!("fig results".parse::<RefId>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "cli-support", serde(try_from = "String", into = "String"))]
+pub struct RefId(String);
+
+impl RefId {
+    /// Validate a label.
+    pub fn new(id: impl Into<String>) -> Result<Self, InvalidRefId> {
+        let id = id.into();
+        if id.is_empty() {
+            return Err(InvalidRefId::Empty);
+        }
+        match id.chars().find(|&c| c.is_whitespace() || c.is_control()) {
+            Some(c) => Err(InvalidRefId::Character { id, c }),
+            None => Ok(Self(id)),
+        }
+    }
+
+    /// The label made by slugifying the given inlines; see `slugify`.
+    pub fn slug(inlines: &[Inline]) -> Self {
+        Self(slugify(inlines))
+    }
+
+    /// The label as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The label as a `String`.
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl fmt::Display for RefId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for RefId {
+    type Err = InvalidRefId;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<String> for RefId {
+    type Error = InvalidRefId;
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        Self::new(id)
+    }
+}
+
+impl Deref for RefId {
+    type Target = str;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<str> for RefId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for RefId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<RefId> for String {
+    fn from(id: RefId) -> Self {
+        id.0
+    }
+}
+
+/// A string which isn't a valid `RefId`.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum InvalidRefId {
+    /// The label was empty.
+    #[error("Labels can't be empty")]
+    Empty,
+
+    /// The label contained whitespace or a control character.
+    #[error("Labels can't contain whitespace or control characters, but {id:?} contains {c:?}")]
+    Character {
+        /// The invalid label.
+        id: String,
+        /// The first invalid character in the label.
+        c: char,
+    },
+}
+
+impl InvalidRefId {
+    /// The stable code for this error; see `diagnostic::ErrorCode`.
+    pub fn code(&self) -> ErrorCode {
+        ErrorCode::InvalidLabel
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_id() {
+        assert_eq!("0", Id::default().to_string());
+        assert_eq!(Ok(Id::from(1234)), "1234".parse());
+        for s in &["", "+1", "-1", " 1", "1.0", "b1", "99999999999999999999999"] {
+            assert_eq!(Err(ParseIdError((*s).to_owned())), s.parse::<Id>());
+        }
+        assert!(Id::from(2) < Id::from(10));
+    }
+
+    #[test]
+    fn test_ref_id() {
+        for id in &["fig:results", "Größe", "a--b", "Why?", "C#"] {
+            assert_eq!(*id, RefId::new(*id).unwrap().as_str());
+        }
+        assert_eq!(Err(InvalidRefId::Empty), RefId::new(""));
+        assert_eq!(
+            Err(InvalidRefId::Character {
+                id: "fig results".into(),
+                c: ' ',
+            }),
+            RefId::new("fig results")
+        );
+        assert_eq!(
+            "Labels can't contain whitespace or control characters, \
+             but \"a\\tb\" contains '\\t'",
+            RefId::new("a\tb").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "A-heading",
+            RefId::slug(&[Inline::Text(" A  heading ".into())]).as_str()
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_id_round_trip(n in any::<usize>()) {
+            let id = Id::from(n);
+            prop_assert_eq!(Ok(id), id.to_string().parse());
+        }
+
+        #[test]
+        fn test_ref_id_round_trip(s in "\\PC*") {
+            if let Ok(id) = RefId::new(s.clone()) {
+                prop_assert_eq!(Ok(id.clone()), id.to_string().parse());
+                prop_assert_eq!(&s, id.as_str());
+            }
+        }
+
+        #[test]
+        fn test_slug_is_ref_id(s in any::<String>()) {
+            let slug = RefId::slug(&[Inline::Text(s)]);
+            prop_assert_eq!(Ok(slug.clone()), RefId::new(slug.as_str()));
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use super::tex::TexReader;
+
+/// Read simple TeX math aloud in English, e.g. `x^2` as "x squared", for use
+/// as alt text for a `Math` or `InlineMath`.
+///
+/// Only letters, numbers, basic operators, sub- and superscripts, `\frac`,
+/// `\sqrt`, and a handful of symbols (e.g. Greek letters) are understood;
+/// `None` is returned for anything else, rather than guessing.
+pub fn tex_to_speech(tex: &str) -> Option<String> {
+    let words = TexSpeaker::new(tex).speak().ok()?;
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+/// The TeX couldn't be read aloud.
+struct Unsupported;
+
+struct TexSpeaker<'t> {
+    tex: TexReader<'t>,
+}
+
+impl<'t> TexSpeaker<'t> {
+    fn new(tex: &'t str) -> Self {
+        Self {
+            tex: TexReader::new(tex),
+        }
+    }
+
+    /// Read all the remaining input.
+    fn speak(&mut self) -> Result<Vec<String>, Unsupported> {
+        let mut words = Vec::new();
+        while let Some(atom) = self.atom()? {
+            words.extend(atom);
+            self.scripts(&mut words)?;
+        }
+        Ok(words)
+    }
+
+    /// Read any sub- and superscripts following an atom.
+    fn scripts(&mut self, words: &mut Vec<String>) -> Result<(), Unsupported> {
+        loop {
+            self.tex.skip_space();
+            if self.tex.eat('^') {
+                let exponent = self.atom()?.ok_or(Unsupported)?;
+                match exponent.join(" ").as_str() {
+                    "2" => words.push("squared".into()),
+                    "3" => words.push("cubed".into()),
+                    _ => {
+                        words.push("to the power of".into());
+                        words.extend(exponent);
+                    }
+                }
+            } else if self.tex.eat('_') {
+                words.push("sub".into());
+                words.extend(self.atom()?.ok_or(Unsupported)?);
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Read the next atom; a number, variable, operator, command, or group.
+    fn atom(&mut self) -> Result<Option<Vec<String>>, Unsupported> {
+        self.tex.skip_space();
+        let c = match self.tex.next() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        if c.is_ascii_digit() {
+            let mut number = c.to_string();
+            while let Some(c) = self.tex.peek().filter(|&c| c.is_ascii_digit() || c == '.') {
+                self.tex.next();
+                number.push(c);
+            }
+            return Ok(Some(vec![number]));
+        }
+        let word = match c {
+            '{' => return self.group_rest().map(Some),
+            '\\' => return self.command().map(Some),
+            c if c.is_alphabetic() => c.to_string(),
+            '+' => "plus".into(),
+            '-' => "minus".into(),
+            '=' => "equals".into(),
+            '<' => "is less than".into(),
+            '>' => "is greater than".into(),
+            '*' => "times".into(),
+            '/' => "over".into(),
+            '(' => "open parenthesis".into(),
+            ')' => "close parenthesis".into(),
+            ',' => ",".into(),
+            '!' => "factorial".into(),
+            '\'' => "prime".into(),
+            _ => return Err(Unsupported),
+        };
+        Ok(Some(vec![word]))
+    }
+
+    /// Read a command, after its backslash.
+    fn command(&mut self) -> Result<Vec<String>, Unsupported> {
+        match self.tex.command_name().ok_or(Unsupported)? {
+            "frac" => {
+                let mut words = self.group()?;
+                words.push("over".into());
+                words.extend(self.group()?);
+                Ok(words)
+            }
+            "sqrt" => {
+                let mut words = vec!["the square root of".to_owned()];
+                words.extend(self.group()?);
+                Ok(words)
+            }
+            name => symbol_name(name).map(|word| vec![word]).ok_or(Unsupported),
+        }
+    }
+
+    /// Read a braced group, or else an atom.
+    fn group(&mut self) -> Result<Vec<String>, Unsupported> {
+        self.tex.skip_space();
+        if self.tex.eat('{') {
+            self.group_rest()
+        } else {
+            self.atom()?.ok_or(Unsupported)
+        }
+    }
+
+    /// Read the rest of a braced group, after its opening brace.
+    fn group_rest(&mut self) -> Result<Vec<String>, Unsupported> {
+        let inner = self.tex.group_rest().ok_or(Unsupported)?;
+        TexSpeaker::new(inner).speak()
+    }
+}
+
+/// The spoken name of a TeX symbol command, e.g. `alpha` or `leq`.
+fn symbol_name(name: &str) -> Option<String> {
+    const GREEK: [&str; 24] = [
+        "alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta", "iota", "kappa",
+        "lambda", "mu", "nu", "xi", "omicron", "pi", "rho", "sigma", "tau", "upsilon", "phi",
+        "chi", "psi", "omega",
+    ];
+    if GREEK.contains(&name) {
+        return Some(name.to_owned());
+    }
+    let lower = name.to_lowercase();
+    if GREEK.contains(&lower.as_str()) && name != lower {
+        return Some(format!("capital {}", lower));
+    }
+    Some(
+        match name {
+            "cdot" | "times" => "times",
+            "div" => "divided by",
+            "pm" => "plus or minus",
+            "leq" | "le" => "is less than or equal to",
+            "geq" | "ge" => "is greater than or equal to",
+            "neq" | "ne" => "is not equal to",
+            "approx" => "is approximately",
+            "infty" => "infinity",
+            _ => return None,
+        }
+        .to_owned(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_tex_to_speech() {
+        assert_eq!(Some("x squared".into()), tex_to_speech("x^2"));
+        assert_eq!(
+            Some("a sub i plus b cubed".into()),
+            tex_to_speech("a_i + b^{3}")
+        );
+        assert_eq!(
+            Some("e to the power of i pi equals minus 1".into()),
+            tex_to_speech(r"e^{i\pi} = -1")
+        );
+        assert_eq!(
+            Some(
+                "a plus b over 2 is less than or equal to the square root of capital delta".into()
+            ),
+            tex_to_speech(r"\frac{a + b}{2} \leq \sqrt{\Delta}")
+        );
+    }
+
+    #[test]
+    fn test_tex_to_speech_unsupported() {
+        assert_eq!(None, tex_to_speech(r"\begin{matrix} 1 \end{matrix}"));
+        assert_eq!(None, tex_to_speech(r"\int_0^1 x"));
+        assert_eq!(None, tex_to_speech("{x"));
+        assert_eq!(None, tex_to_speech("x^"));
+        assert_eq!(None, tex_to_speech(""));
+    }
+}
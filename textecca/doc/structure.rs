@@ -1,27 +1,141 @@
 use std::collections::HashMap;
+use std::iter;
+use std::mem;
 use std::ops::{Deref, DerefMut};
 use std::vec;
 
+#[cfg(feature = "cli-support")]
+use serde::{Deserialize, Serialize};
+
 use super::blocks::*;
 use super::inlines::*;
+use super::{nodes, DocNode, Id};
 
 /// Some metadata to be associated with a group of blocks or inlines; metadata is
 /// currently unstructured and its representation will almost certainly change in
 /// the future.
 pub type Meta = HashMap<String, String>;
 
+/// The `Meta` key restricting tagged content to a single output format, named
+/// by `Serializer::format_name`.
+pub const ONLY_FORMAT_KEY: &str = "only";
+
+/// Should content tagged with the given metadata be included in the given
+/// output format?
+pub fn meta_includes_format(meta: &Meta, format: &str) -> bool {
+    meta.get(ONLY_FORMAT_KEY)
+        .map(|only| only == format)
+        .unwrap_or(true)
+}
+
+/// Renderer hints for the given output format, sorted by key. Hints are `Meta`
+/// entries with keys prefixed by the format name and a colon, e.g. `html:class`;
+/// the prefix is removed from the returned keys.
+///
+/// Hints let commands say how their content should look in a particular
+/// format, while other formats write the content as usual.
+pub fn meta_hints<'m>(meta: &'m Meta, format: &str) -> Vec<(&'m str, &'m str)> {
+    let mut ret: Vec<_> = meta
+        .iter()
+        .filter_map(|(key, value)| {
+            let hint = key.strip_prefix(format)?.strip_prefix(':')?;
+            Some((hint, value.as_str()))
+        })
+        .collect();
+    ret.sort_unstable();
+    ret
+}
+
+/// The `Meta` (or `DocMeta`) key giving the language of tagged content (or the
+/// whole document) as a BCP 47 tag, e.g. `de` or `en-GB`. Nested tags
+/// override the languages of the content surrounding them.
+pub const LANG_KEY: &str = "lang";
+
+/// The `Meta` (or `DocMeta`) key giving the writing direction of tagged
+/// content (or the whole document): `rtl` for right-to-left scripts like
+/// Arabic and Hebrew, or `ltr`.
+pub const DIR_KEY: &str = "dir";
+
+/// The `Meta` key giving a block's id explicitly, e.g. `\sec[id=intro]{...}`.
+/// A heading's id is otherwise generated from its text (see
+/// `Heading::anchor`), and changes when the heading is reworded.
+pub const ID_KEY: &str = "id";
+
+/// The `DocMeta` key enabling draft mode; see `is_draft`.
+pub const DRAFT_KEY: &str = "draft";
+
+/// The `Meta` key restricting tagged content to draft builds.
+pub const DRAFT_ONLY_KEY: &str = "draftonly";
+
+/// The `Meta` key marking tagged content as a todo note. Todo notes are also
+/// restricted to draft builds, and are styled distinctly by serializers.
+pub const TODO_KEY: &str = "todo";
+
+/// The `Meta` key marking tagged content as a placeholder for an error, added
+/// where evaluation failed in a partially-built document; see
+/// `DocBuilder::push_error`. Errors are styled distinctly by serializers.
+pub const ERROR_KEY: &str = "error";
+
+/// The `Doc::collections` channel holding the document's abstract, e.g. from
+/// `\abstract`.
+pub const ABSTRACT_CHANNEL: &str = "abstract";
+
+/// The `Doc::collections` channel holding a copy of each todo note's content
+/// (see `TODO_KEY`), e.g. for a list of what's left to do.
+pub const TODO_CHANNEL: &str = "todo";
+
+/// Is the given metadata value truthy? Empty values and `false`, `no`, `off`,
+/// and `0` (in any case) are falsy; everything else is truthy.
+pub fn is_truthy(value: &str) -> bool {
+    let value = value.trim();
+    !(value.is_empty()
+        || ["false", "no", "off", "0"]
+            .iter()
+            .any(|falsy| value.eq_ignore_ascii_case(falsy)))
+}
+
+/// Is a document with the given metadata a draft, i.e. is `DRAFT_KEY` truthy?
+pub fn is_draft(meta: &DocMeta) -> bool {
+    meta.get(DRAFT_KEY).is_some_and(|draft| is_truthy(draft))
+}
+
+/// Should content tagged with the given metadata be included in a draft (or
+/// non-draft) build?
+pub fn meta_includes_draft(meta: &Meta, draft: bool) -> bool {
+    draft || !(meta.contains_key(DRAFT_ONLY_KEY) || meta.contains_key(TODO_KEY))
+}
+
+/// Should content tagged with the given metadata be written in the given
+/// output format, in a draft if `draft`? See `meta_includes_format` and
+/// `meta_includes_draft`.
+pub fn meta_includes(meta: &Meta, format: &str, draft: bool) -> bool {
+    meta_includes_format(meta, format) && meta_includes_draft(meta, draft)
+}
+
 /// An entire document.
 #[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
 pub struct Doc {
     /// Document metadata.
     pub meta: DocMeta,
     /// Document content.
     pub content: Blocks,
+    /// Content outside of the document's flow, like its abstract, by channel
+    /// name; see `DocBuilder::collect`.
+    pub collections: Collections,
 }
 
 /// Document metadata.
 pub type DocMeta = HashMap<String, String>;
 
+/// Content collected from throughout a document, by channel name. Each
+/// channel's entries are in the order they were collected.
+///
+/// Channels are named by convention, e.g. `ABSTRACT_CHANNEL`; serializers
+/// write the channels they know and ignore the others, so commands can
+/// collect content for other tools without any serializer changing.
+pub type Collections = HashMap<String, Vec<Blocks>>;
+
 impl Doc {
     /// Create a document from the given `Blocks`.
     pub fn from_content(content: Blocks) -> Self {
@@ -31,17 +145,29 @@ impl Doc {
         }
     }
 
-    /// Does this document contain any math blocks or inlines?
+    /// Does this document contain any math blocks or inlines, in its content
+    /// or its collections?
     pub fn has_math(&self) -> bool {
-        // TODO: An actual tree-traversal.
-        true
+        let collected = self.collections.values().flatten();
+        iter::once(&self.content)
+            .chain(collected)
+            .flat_map(|blocks| nodes(blocks))
+            .any(|node| {
+                matches!(
+                    node,
+                    DocNode::Block(Block {
+                        inner: BlockInner::Math(_),
+                        ..
+                    }) | DocNode::Inline(Inline::Math(_))
+                )
+            })
     }
 
     /// Recursively visit the blocks in this document to resolve all references.
     ///
     /// Returns a map of String -> Ref generated by the visitor function, or a
     /// Vec<String> of unresolved references.
-    pub fn visit_references<F, Ref>(&self, visit: F) -> Result<HashMap<String, Ref>, Vec<String>>
+    pub fn visit_references<F, Ref>(&self, _visit: F) -> Result<HashMap<String, Ref>, Vec<String>>
     where
         F: Fn(BlockInner) -> Ref,
     {
@@ -52,7 +178,7 @@ impl Doc {
     ///
     /// Note that because this function handles traversal, the visitor function
     /// shouldn't attempt to traverse the tree of blocks it visits.
-    pub fn visit_inorder_iter<'s, F, R>(&'s self, visit: F) -> impl Iterator<Item = R> + 's
+    pub fn visit_inorder_iter<'s, F, R>(&'s self, _visit: F) -> impl Iterator<Item = R> + 's
     where
         F: FnMut(&BlockInner) -> R,
         R: 's,
@@ -65,6 +191,8 @@ impl Doc {
 
 /// A sequence of `Block`s.
 #[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "cli-support", serde(transparent))]
 pub struct Blocks(pub Vec<Block>);
 
 impl Deref for Blocks {
@@ -83,14 +211,88 @@ impl DerefMut for Blocks {
 impl IntoIterator for Blocks {
     type Item = Block;
     type IntoIter = std::vec::IntoIter<Block>;
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+    fn into_iter(mut self) -> Self::IntoIter {
+        mem::take(&mut self.0).into_iter()
+    }
+}
+
+impl From<Blocks> for Vec<Block> {
+    fn from(mut blocks: Blocks) -> Self {
+        mem::take(&mut blocks.0)
     }
 }
 
-impl Into<Vec<Block>> for Blocks {
-    fn into(self) -> Vec<Block> {
-        self.0
+/// Blocks are dropped without recursion, so dropping very deeply nested
+/// blocks or inlines (e.g. a long chain of quotes, or of `\emph`s) doesn't
+/// overflow the stack.
+impl Drop for Blocks {
+    fn drop(&mut self) {
+        let mut blocks = mem::take(&mut self.0);
+        let mut inlines: Vec<Inline> = Vec::new();
+        loop {
+            if let Some(mut inline) = inlines.pop() {
+                match &mut inline {
+                    Inline::Styled { content, .. }
+                    | Inline::Quote(Quote { content, .. })
+                    | Inline::Link(Link {
+                        content: Some(content),
+                        ..
+                    })
+                    | Inline::Tagged(TaggedInlines { content, .. }) => inlines.append(content),
+                    Inline::Footnote(Footnote { content }) => blocks.append(&mut content.0),
+                    Inline::Text(_)
+                    | Inline::Code(_)
+                    | Inline::Space
+                    | Inline::LineBreak
+                    | Inline::Link(_)
+                    | Inline::Math(_)
+                    | Inline::Image(_)
+                    | Inline::Raw(_) => {}
+                }
+                continue;
+            }
+            let mut block = match blocks.pop() {
+                Some(block) => block,
+                None => break,
+            };
+            let mut take = |content: &mut Blocks| blocks.append(&mut content.0);
+            match &mut block.inner {
+                BlockInner::Plain(text)
+                | BlockInner::Par(text)
+                | BlockInner::Heading(Heading { text, .. }) => inlines.append(text),
+                BlockInner::Code(code) => {
+                    code.lines.iter_mut().for_each(|line| inlines.append(line))
+                }
+                BlockInner::Quote(content) | BlockInner::Tagged(TaggedBlocks { content, .. }) => {
+                    take(content)
+                }
+                BlockInner::Figure(Figure {
+                    caption, content, ..
+                }) => {
+                    inlines.append(caption);
+                    take(content);
+                }
+                BlockInner::List(list) => list
+                    .items
+                    .iter_mut()
+                    .for_each(|item| take(&mut item.content)),
+                BlockInner::TermList(items) => items.iter_mut().for_each(|item| {
+                    inlines.append(&mut item.term);
+                    take(&mut item.content);
+                }),
+                BlockInner::Table(table) => table
+                    .cells
+                    .iter_mut()
+                    .flatten()
+                    .for_each(|cell| take(&mut cell.content)),
+                BlockInner::Defn(defn) => {
+                    inlines.append(&mut defn.name);
+                    take(&mut defn.summary);
+                    take(&mut defn.content);
+                }
+                BlockInner::Rule | BlockInner::Math(_) | BlockInner::Raw(_) => {}
+            }
+        }
     }
 }
 
@@ -106,27 +308,15 @@ impl From<Block> for Blocks {
     }
 }
 
-/// A `Block` identifier, unique within a `Doc` and monotonically increasing.
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
-pub struct Id(usize);
-
-impl From<usize> for Id {
-    fn from(id: usize) -> Self {
-        Self(id)
-    }
-}
-
-impl Iterator for Id {
-    type Item = Id;
-    fn next(&mut self) -> Option<Self::Item> {
-        Some(Id(self.0 + 1))
-    }
-}
-
 /// A block of content within a document.
 ///
 /// The actual content lives in the `inner` field.
+///
+/// Blocks may gain fields in future versions, so other crates create them
+/// with `Block::new`.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub struct Block {
     /// A document-unique `Id`.
     pub id: Id,
@@ -134,6 +324,13 @@ pub struct Block {
     pub inner: BlockInner,
 }
 
+impl Block {
+    /// Create a block with the given `Id` and content.
+    pub fn new(id: Id, inner: BlockInner) -> Self {
+        Self { id, inner }
+    }
+}
+
 impl Deref for Block {
     type Target = BlockInner;
     fn deref(&self) -> &Self::Target {
@@ -148,7 +345,12 @@ impl DerefMut for Block {
 }
 
 /// A block of content within a document, typically separated by vertical space.
+///
+/// New kinds of blocks may be added in future versions; see `ser::Serializer`
+/// for how serializers handle them.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum BlockInner {
     /// Text not in a paragraph; this is treated as `Inlines`, but in a block context.
     Plain(Inlines),
@@ -185,13 +387,48 @@ pub enum BlockInner {
 
     /// Display math
     Math(Math),
+
+    /// Content in a particular output format, written verbatim by that
+    /// format's serializer.
+    Raw(RawBlock),
+
+    /// Blocks tagged with some metadata.
+    Tagged(TaggedBlocks),
+}
+
+impl BlockInner {
+    /// A short description of this kind of block, for error messages, e.g.
+    /// `"code block"`.
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            BlockInner::Plain(_) => "plain text block",
+            BlockInner::Par(_) => "paragraph",
+            BlockInner::Code(_) => "code block",
+            BlockInner::Quote(_) => "block quote",
+            BlockInner::List(_) => "list",
+            BlockInner::TermList(_) => "term list",
+            BlockInner::Heading(_) => "heading",
+            BlockInner::Rule => "rule",
+            BlockInner::Table(_) => "table",
+            BlockInner::Figure(_) => "figure",
+            BlockInner::Defn(_) => "definition",
+            BlockInner::Math(_) => "math block",
+            BlockInner::Raw(_) => "raw block",
+            BlockInner::Tagged(_) => "tagged block",
+        }
+    }
 }
 
 /// A sequence of `Inline`s.
 pub type Inlines = Vec<Inline>;
 
 /// A span of inline content in a document.
+///
+/// New kinds of inlines may be added in future versions; see
+/// `ser::Serializer` for how serializers handle them.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli-support", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum Inline {
     /// Plain text.
     Text(String),
@@ -215,6 +452,9 @@ pub enum Inline {
     /// TODO: How to handle inter-sentence spacing?
     Space,
 
+    /// A hard line break within a paragraph.
+    LineBreak,
+
     /// A link, either intra-document or external.
     Link(Link),
 
@@ -225,4 +465,34 @@ pub enum Inline {
 
     /// Mathematics.
     Math(InlineMath),
+
+    /// An image.
+    Image(Image),
+
+    /// Content in a particular output format.
+    Raw(RawInline),
+
+    /// Inlines tagged with some metadata.
+    Tagged(TaggedInlines),
+}
+
+impl Inline {
+    /// A short description of this kind of inline, for error messages, e.g.
+    /// `"inline code"`.
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            Inline::Text(_) => "text",
+            Inline::Styled { .. } => "styled text",
+            Inline::Quote(_) => "quotation",
+            Inline::Code(_) => "inline code",
+            Inline::Space => "space",
+            Inline::LineBreak => "line break",
+            Inline::Link(_) => "link",
+            Inline::Footnote(_) => "footnote",
+            Inline::Math(_) => "inline math",
+            Inline::Image(_) => "image",
+            Inline::Raw(_) => "raw content",
+            Inline::Tagged(_) => "tagged inlines",
+        }
+    }
 }
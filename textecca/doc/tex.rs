@@ -0,0 +1,365 @@
+//! Reading TeX math for formats which can't render it, as plain text (see
+//! `tex_to_text`) or read aloud (see `tex_to_speech`).
+
+/// Write TeX math as readable plain text, e.g. `\frac{a}{b}` as `a/b` and
+/// `\sqrt{x^2}` as `√x²`, for formats which can't render math.
+///
+/// This is best-effort: Greek letters and common symbols become their Unicode
+/// characters, fractions and roots are written inline, and sub- and
+/// superscripts use Unicode's sub- and superscript characters where they all
+/// exist. Anything else, like environments or unknown commands, gives back
+/// the original TeX.
+pub fn tex_to_text(tex: &str) -> String {
+    let mut transcriber = TexTranscriber {
+        tex: TexReader::new(tex),
+    };
+    match transcriber.read_group() {
+        Some(text) if transcriber.tex.is_empty() => text.trim().to_owned(),
+        _ => tex.to_owned(),
+    }
+}
+
+/// A cursor over TeX, shared by the readers for `tex_to_text` and
+/// `tex_to_speech`.
+pub(super) struct TexReader<'t> {
+    rest: &'t str,
+}
+
+impl<'t> TexReader<'t> {
+    pub(super) fn new(tex: &'t str) -> Self {
+        Self { rest: tex }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    pub(super) fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+
+    pub(super) fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.take(c.len_utf8());
+        Some(c)
+    }
+
+    /// Read `c` if it's next.
+    pub(super) fn eat(&mut self, c: char) -> bool {
+        if self.rest.starts_with(c) {
+            self.take(c.len_utf8());
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(super) fn skip_space(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    /// Read the next `len` bytes.
+    fn take(&mut self, len: usize) -> &'t str {
+        let (ret, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        ret
+    }
+
+    /// Read a command's name, after its backslash: a run of ASCII letters, or
+    /// else a single character, like the `,` of `\,`.
+    pub(super) fn command_name(&mut self) -> Option<&'t str> {
+        let len = match self.rest.find(|c: char| !c.is_ascii_alphabetic()) {
+            Some(0) => self.peek()?.len_utf8(),
+            Some(len) => len,
+            None if self.rest.is_empty() => return None,
+            None => self.rest.len(),
+        };
+        Some(self.take(len))
+    }
+
+    /// Read the rest of a braced group, after its opening brace, giving its
+    /// content; the closing brace is read but not included. Escaped braces,
+    /// like `\{`, don't open or close groups.
+    pub(super) fn group_rest(&mut self) -> Option<&'t str> {
+        let mut depth = 1;
+        let mut escaped = false;
+        let (end, _) = self.rest.char_indices().find(|&(_, c)| {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        })?;
+        let ret = self.take(end);
+        self.take(1);
+        Some(ret)
+    }
+}
+
+/// Converts TeX to text for `tex_to_text`; methods return `None` for TeX
+/// they don't understand.
+struct TexTranscriber<'t> {
+    tex: TexReader<'t>,
+}
+
+impl TexTranscriber<'_> {
+    /// Read until the end of input or an unmatched `}`, which isn't consumed.
+    fn read_group(&mut self) -> Option<String> {
+        let mut ret = String::new();
+        while let Some(c) = self.tex.peek() {
+            match c {
+                '}' => break,
+                '{' => ret.push_str(&self.read_arg()?),
+                '\\' => {
+                    self.tex.next();
+                    ret.push_str(&self.read_command()?);
+                }
+                '^' | '_' => {
+                    self.tex.next();
+                    let arg = self.read_arg()?;
+                    ret.push_str(&script(&arg, c == '^'));
+                }
+                '\'' => {
+                    self.tex.next();
+                    ret.push('′');
+                }
+                '~' => {
+                    self.tex.next();
+                    ret.push(' ');
+                }
+                '$' | '&' | '#' | '%' => return None,
+                c if c.is_whitespace() => {
+                    self.tex.skip_space();
+                    if !ret.ends_with(' ') {
+                        ret.push(' ');
+                    }
+                }
+                c => {
+                    self.tex.next();
+                    ret.push(c);
+                }
+            }
+        }
+        Some(ret)
+    }
+
+    /// Read a command's argument or a script: a group in braces, a command,
+    /// or a single character.
+    fn read_arg(&mut self) -> Option<String> {
+        self.tex.skip_space();
+        match self.tex.next()? {
+            '{' => {
+                let ret = self.read_group()?;
+                match self.tex.next()? {
+                    '}' => Some(ret.trim().to_owned()),
+                    _ => None,
+                }
+            }
+            '\\' => self.read_command(),
+            '}' | '^' | '_' => None,
+            c => Some(c.to_string()),
+        }
+    }
+
+    /// Read a command, after its backslash.
+    fn read_command(&mut self) -> Option<String> {
+        let name = self.tex.command_name()?;
+        Some(match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let num = self.read_arg()?;
+                let denom = self.read_arg()?;
+                format!("{}/{}", tex_atom(&num), tex_atom(&denom))
+            }
+            "sqrt" if self.tex.peek() != Some('[') => format!("√{}", tex_atom(&self.read_arg()?)),
+            "text" | "textrm" | "mathrm" | "mathit" | "mathbf" | "mathsf" | "mathtt"
+            | "operatorname" => self.read_arg()?,
+            "left" | "right" => {
+                // The delimiter is written as usual, unless it's omitted with `.`.
+                if self.tex.peek() == Some('.') {
+                    self.tex.next();
+                }
+                String::new()
+            }
+            "," | ":" | ";" | " " | "quad" | "qquad" => " ".to_owned(),
+            "!" => String::new(),
+            "{" | "}" | "$" | "&" | "#" | "%" | "_" => name.to_owned(),
+            "sin" | "cos" | "tan" | "log" | "ln" | "exp" | "lim" | "max" | "min" | "det" => {
+                name.to_owned()
+            }
+            _ => tex_symbol(name)?.to_owned(),
+        })
+    }
+}
+
+/// The character for a TeX symbol command, e.g. `α` for `alpha`.
+fn tex_symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "α",
+        "beta" => "β",
+        "gamma" => "γ",
+        "delta" => "δ",
+        "epsilon" | "varepsilon" => "ε",
+        "zeta" => "ζ",
+        "eta" => "η",
+        "theta" => "θ",
+        "iota" => "ι",
+        "kappa" => "κ",
+        "lambda" => "λ",
+        "mu" => "μ",
+        "nu" => "ν",
+        "xi" => "ξ",
+        "pi" => "π",
+        "rho" => "ρ",
+        "sigma" => "σ",
+        "tau" => "τ",
+        "upsilon" => "υ",
+        "phi" | "varphi" => "φ",
+        "chi" => "χ",
+        "psi" => "ψ",
+        "omega" => "ω",
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Xi" => "Ξ",
+        "Pi" => "Π",
+        "Sigma" => "Σ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        "infty" => "∞",
+        "pm" => "±",
+        "mp" => "∓",
+        "times" => "×",
+        "cdot" => "⋅",
+        "div" => "÷",
+        "le" | "leq" => "≤",
+        "ge" | "geq" => "≥",
+        "ne" | "neq" => "≠",
+        "approx" => "≈",
+        "equiv" => "≡",
+        "to" | "rightarrow" => "→",
+        "leftarrow" => "←",
+        "Rightarrow" | "implies" => "⇒",
+        "iff" => "⇔",
+        "in" => "∈",
+        "notin" => "∉",
+        "subset" => "⊂",
+        "subseteq" => "⊆",
+        "cup" => "∪",
+        "cap" => "∩",
+        "emptyset" => "∅",
+        "forall" => "∀",
+        "exists" => "∃",
+        "neg" => "¬",
+        "land" | "wedge" => "∧",
+        "lor" | "vee" => "∨",
+        "sum" => "∑",
+        "prod" => "∏",
+        "int" => "∫",
+        "partial" => "∂",
+        "nabla" => "∇",
+        "ldots" | "dots" => "…",
+        "cdots" => "⋯",
+        "prime" => "′",
+        "circ" => "∘",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        "lbrace" => "{",
+        "rbrace" => "}",
+        _ => return None,
+    })
+}
+
+/// Parenthesize converted TeX unless it's a single number, name, or
+/// character, e.g. for the numerator of a fraction.
+fn tex_atom(text: &str) -> String {
+    if text.chars().count() == 1 || text.chars().all(char::is_alphanumeric) {
+        text.to_owned()
+    } else {
+        format!("({})", text)
+    }
+}
+
+/// Write converted TeX as a sub- or superscript, with Unicode sub- or
+/// superscript characters if they all exist, or else after `^` or `_`,
+/// parenthesized if it's longer than a character.
+fn script(text: &str, superscript: bool) -> String {
+    if text.chars().all(|c| c == '′') {
+        return text.to_owned();
+    }
+    let (from, to, mark) = if superscript {
+        ("0123456789+-=()ni", "⁰¹²³⁴⁵⁶⁷⁸⁹⁺⁻⁼⁽⁾ⁿⁱ", '^')
+    } else {
+        ("0123456789+-=()aeoxijn", "₀₁₂₃₄₅₆₇₈₉₊₋₌₍₎ₐₑₒₓᵢⱼₙ", '_')
+    };
+    let mapped: Option<String> = text
+        .chars()
+        .map(|c| {
+            from.chars()
+                .position(|f| f == c)
+                .and_then(|i| to.chars().nth(i))
+        })
+        .collect();
+    match mapped {
+        Some(mapped) if !mapped.is_empty() => mapped,
+        _ if text.chars().count() == 1 => format!("{}{}", mark, text),
+        _ => format!("{}({})", mark, text),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_tex_to_text() {
+        let cases = [
+            (r"\frac{a}{b}", "a/b"),
+            (r"\frac{x+1}{2}", "(x+1)/2"),
+            (r"x^2 + y^{10}", "x² + y¹⁰"),
+            (r"a_1, a_{n+1}", "a₁, aₙ₊₁"),
+            (r"e^{i\pi} = -1", "e^(iπ) = -1"),
+            (r"e^{x y}", "e^(x y)"),
+            (r"\alpha \to \infty", "α → ∞"),
+            (r"\sqrt{x}", "√x"),
+            (r"\sqrt{x^2 + 1}", "√(x² + 1)"),
+            (r"\sin \theta \le 1", "sin θ ≤ 1"),
+            (r"f'(x)", "f′(x)"),
+            (r"\left( \frac12 \right)", "( 1/2 )"),
+            (r"\text{if } x \ne 0", "if x ≠ 0"),
+            (r"\{ x \}", "{ x }"),
+        ];
+        for (tex, text) in &cases {
+            assert_eq!(*text, tex_to_text(tex), "{:?}", tex);
+        }
+    }
+
+    #[test]
+    fn test_tex_to_text_fallback() {
+        for tex in &[
+            r"\begin{matrix} a & b \end{matrix}",
+            r"\sqrt[3]{x}",
+            r"\frac{a}",
+            r"\mathcal{L}",
+            r"{x",
+            "x}",
+            "x^",
+        ] {
+            assert_eq!(*tex, tex_to_text(tex));
+        }
+    }
+
+    #[test]
+    fn test_group_rest() {
+        let mut reader = TexReader::new(r"a {b} \} c} d");
+        assert_eq!(Some(r"a {b} \} c"), reader.group_rest());
+        assert_eq!(Some(' '), reader.next());
+        assert_eq!(None, TexReader::new("{a}").group_rest());
+    }
+}
@@ -0,0 +1,287 @@
+//! Flattening inlines to text, e.g. for attribute values and ids.
+//!
+//! These are used by textecca's serializers, and are stable so that tools
+//! embedding textecca (e.g. static site generators building URLs for
+//! sections) give the same results.
+use thiserror::Error;
+
+use super::{tex_to_text, Image, Inline, InlineCode, InlineMath, Quote, TaggedInlines};
+use crate::diagnostic::ErrorCode;
+
+/// Flatten inlines to plain text, e.g. for use as an attribute value.
+///
+/// Styles and tags are dropped, quotes are written with US English quotation
+/// marks, and math and images are replaced with their alt text. Math without
+/// alt text is written with `tex_to_text`. Footnotes and images without
+/// alt text have no plain text, and give an error.
+///
+/// ```
+/// use textecca::doc::{text::inlines_to_plain_text, Inline, Style};
+///
+/// let inlines = vec![
+///     Inline::Text("A".into()),
+///     Inline::Space,
+///     Inline::Styled {
+///         style: Style::Emph,
+///         content: vec![Inline::Text("fine".into())],
+///     },
+///     Inline::Space,
+///     Inline::Text("day".into()),
+/// ];
+/// assert_eq!(Ok("A fine day".to_owned()), inlines_to_plain_text(&inlines));
+/// ```
+pub fn inlines_to_plain_text(inlines: &[Inline]) -> Result<String, NotPlainText> {
+    let mut ret = String::new();
+    push_plain_text(&mut ret, inlines)?;
+    Ok(ret)
+}
+
+fn push_plain_text(ret: &mut String, inlines: &[Inline]) -> Result<(), NotPlainText> {
+    for inline in inlines {
+        match inline {
+            Inline::Text(content) | Inline::Code(InlineCode { content, .. }) => {
+                ret.push_str(content);
+            }
+            Inline::Styled { content, .. } | Inline::Tagged(TaggedInlines { content, .. }) => {
+                push_plain_text(ret, content)?;
+            }
+            Inline::Quote(Quote { content, kind }) => {
+                let (l, r) = kind.to_inlines();
+                push_plain_text(ret, &l)?;
+                push_plain_text(ret, content)?;
+                push_plain_text(ret, &r)?;
+            }
+            Inline::Space | Inline::LineBreak => ret.push(' '),
+            Inline::Link(link) => push_plain_text(ret, &link.text())?,
+            Inline::Math(InlineMath { alt: Some(alt), .. }) => ret.push_str(alt),
+            Inline::Math(InlineMath { tex, .. }) => ret.push_str(&tex_to_text(tex)),
+            Inline::Image(Image { alt: Some(alt), .. }) => ret.push_str(alt),
+            Inline::Image(_) => return Err(NotPlainText("image without alt text")),
+            Inline::Footnote(_) => return Err(NotPlainText("footnote")),
+            Inline::Raw(_) => return Err(NotPlainText("raw content")),
+        }
+    }
+    Ok(())
+}
+
+/// Inline content with no plain text representation; see
+/// `inlines_to_plain_text`.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("Expected plain text, but got a {0}")]
+pub struct NotPlainText(pub &'static str);
+
+impl NotPlainText {
+    /// The stable code for this error; see `diagnostic::ErrorCode`.
+    pub fn code(&self) -> ErrorCode {
+        ErrorCode::NotPlainText
+    }
+}
+
+/// The slug given to inlines with no text; see `slugify`.
+pub const EMPTY_SLUG: &str = "section";
+
+/// Make a slug from inlines, for use as an id or in a URL; e.g. headings' ids
+/// are slugs of their text (see `Heading::anchor`).
+///
+/// - Text is kept as it is, including its case and punctuation, so that slugs
+///   stay readable in any language.
+/// - Each run of whitespace and control characters (including spaces and line
+///   breaks between inlines) becomes a single `-`, and runs at the start or
+///   end are dropped; slugs never contain whitespace.
+/// - Styles, tags, and footnotes are dropped, quotes are written with US
+///   English quotation marks, math is written as its TeX, and images as
+///   their alt text.
+/// - Slugs are never empty; inlines with no text give `EMPTY_SLUG`.
+///
+/// Slugifying a slug (as text) gives the same slug.
+///
+/// ```
+/// use textecca::doc::{text::slugify, Inline, InlineCode};
+///
+/// let inlines = vec![
+///     Inline::Text("Using".into()),
+///     Inline::Space,
+///     Inline::Code(InlineCode {
+///         content: "cargo  run".into(),
+///         ..Default::default()
+///     }),
+///     Inline::Text("!".into()),
+/// ];
+/// assert_eq!("Using-cargo-run!", slugify(&inlines));
+/// assert_eq!("section", slugify(&[Inline::Space]));
+/// ```
+pub fn slugify(inlines: &[Inline]) -> String {
+    let mut slug = Slugify::default();
+    slug.inlines(inlines);
+    if slug.text.is_empty() {
+        EMPTY_SLUG.to_owned()
+    } else {
+        slug.text
+    }
+}
+
+#[derive(Default)]
+struct Slugify {
+    text: String,
+    /// Whether a separator is written before the next character.
+    separate: bool,
+}
+
+impl Slugify {
+    fn separator(&mut self) {
+        self.separate = !self.text.is_empty();
+    }
+
+    fn str(&mut self, text: &str) {
+        self.text.reserve(text.len());
+        for c in text.chars() {
+            if c.is_whitespace() || c.is_control() {
+                self.separator();
+            } else {
+                if self.separate {
+                    self.text.push('-');
+                    self.separate = false;
+                }
+                self.text.push(c);
+            }
+        }
+    }
+
+    fn inline(&mut self, inline: &Inline) {
+        match inline {
+            Inline::Text(content) | Inline::Code(InlineCode { content, .. }) => {
+                self.str(content);
+            }
+            Inline::Styled { content, .. } | Inline::Tagged(TaggedInlines { content, .. }) => {
+                self.inlines(content);
+            }
+            Inline::Quote(Quote { content, kind }) => {
+                // TODO: Support locale-dependent quotes
+                let (l, r) = kind.to_inlines();
+                self.inlines(&l);
+                self.inlines(content);
+                self.inlines(&r);
+            }
+            Inline::Space | Inline::LineBreak => self.separator(),
+            Inline::Link(link) => self.inlines(&link.text()),
+            Inline::Footnote(_) | Inline::Raw(_) => {}
+            Inline::Math(InlineMath { tex, .. }) => {
+                // (big shrug)
+                self.str(tex);
+            }
+            Inline::Image(image) => {
+                if let Some(alt) = &image.alt {
+                    self.str(alt);
+                }
+            }
+        }
+    }
+
+    fn inlines(&mut self, inlines: &[Inline]) {
+        for inline in inlines {
+            self.inline(inline);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::doc::{Footnote, QuoteKind, Style};
+
+    fn text(s: &str) -> Inline {
+        Inline::Text(s.into())
+    }
+
+    #[test]
+    fn test_inlines_to_plain_text() {
+        assert_eq!(
+            Ok("A very “fine” day".to_owned()),
+            inlines_to_plain_text(&[
+                text("A"),
+                Inline::Space,
+                Inline::Styled {
+                    style: Style::Emph,
+                    content: vec![text("very")],
+                },
+                Inline::Space,
+                Inline::Quote(Quote {
+                    kind: QuoteKind::Primary,
+                    content: vec![text("fine")],
+                }),
+                Inline::Space,
+                text("day"),
+            ])
+        );
+        assert_eq!(
+            Ok("x squared".to_owned()),
+            inlines_to_plain_text(&[Inline::Math(InlineMath {
+                tex: "x^2".into(),
+                alt: Some("x squared".into()),
+            })])
+        );
+        assert_eq!(
+            Ok("x²".to_owned()),
+            inlines_to_plain_text(&[Inline::Math(InlineMath {
+                tex: "x^2".into(),
+                alt: None,
+            })])
+        );
+        assert_eq!(
+            Err(NotPlainText("footnote")),
+            inlines_to_plain_text(&[
+                text("Note"),
+                Inline::Footnote(Footnote {
+                    content: Default::default(),
+                }),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(
+            "A-very-“fine”-day",
+            slugify(&[
+                text(" A"),
+                Inline::Space,
+                Inline::Styled {
+                    style: Style::Emph,
+                    content: vec![text("very")],
+                },
+                Inline::LineBreak,
+                Inline::Quote(Quote {
+                    kind: QuoteKind::Primary,
+                    content: vec![text("fine")],
+                }),
+                Inline::Space,
+                text("\tday\n"),
+                Inline::Footnote(Footnote {
+                    content: Default::default(),
+                }),
+            ])
+        );
+        assert_eq!("Größe-x^2", slugify(&[text("Größe  x^2")]));
+        assert_eq!("a--b", slugify(&[text("a--b")]));
+        assert_eq!(EMPTY_SLUG, slugify(&[]));
+        assert_eq!(EMPTY_SLUG, slugify(&[text(" \u{2028}\0")]));
+    }
+
+    proptest! {
+        #[test]
+        fn test_slugify_idempotent(s in any::<String>()) {
+            let slug = slugify(&[text(&s)]);
+            prop_assert_eq!(&slug, &slugify(&[text(&slug)]));
+        }
+
+        #[test]
+        fn test_slugify_no_whitespace(s in any::<String>()) {
+            let slug = slugify(&[text(&s)]);
+            prop_assert!(!slug.is_empty());
+            prop_assert!(!slug.chars().any(|c| c.is_whitespace() || c.is_control()));
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use super::{BlockInner, Doc, Heading, Id, RefId};
+
+/// An entry in a document's table of contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TocEntry<'d> {
+    /// The heading's block `Id`.
+    pub id: Id,
+    /// The heading itself.
+    pub heading: &'d Heading,
+}
+
+impl TocEntry<'_> {
+    /// The heading's id, for linking to it; see `Heading::anchor`.
+    pub fn anchor(&self) -> RefId {
+        self.heading.anchor()
+    }
+}
+
+/// Collect the headings in a document which belong in its table of contents,
+/// in document order.
+///
+/// Headings with the attribute `toc = "false"` are skipped.
+pub fn toc(doc: &Doc) -> Vec<TocEntry<'_>> {
+    doc.content
+        .iter()
+        .filter_map(|block| match &block.inner {
+            BlockInner::Heading(heading) if heading.in_toc() => Some(TocEntry {
+                id: block.id,
+                heading,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Map the ids of the headings in a document (see `Heading::anchor`) to their
+/// blocks' `Id`s, e.g. to generate redirects for ids which changed between
+/// two versions of a document. If two headings share an id, the first is
+/// kept.
+pub fn slug_map(doc: &Doc) -> HashMap<RefId, Id> {
+    let mut ret = HashMap::new();
+    for block in doc.content.iter() {
+        if let BlockInner::Heading(heading) = &block.inner {
+            ret.entry(heading.anchor()).or_insert(block.id);
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::doc::{Block, Inline, ID_KEY};
+
+    fn heading(id: usize, text: &str, toc: Option<&str>) -> Block {
+        Block {
+            id: id.into(),
+            inner: BlockInner::Heading(Heading {
+                level: 1,
+                text: vec![Inline::Text(text.into())],
+                attrs: toc
+                    .map(|toc| vec![("toc".to_owned(), toc.to_owned())])
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_toc_skips_toc_false() {
+        let doc = Doc::from_content(
+            vec![
+                heading(0, "Intro", None),
+                heading(1, "Colophon", Some("false")),
+                heading(2, "Outro", Some("true")),
+            ]
+            .into(),
+        );
+        let ids: Vec<_> = toc(&doc).into_iter().map(|entry| entry.id).collect();
+        assert_eq!(vec![Id::from(0), Id::from(2)], ids);
+    }
+
+    #[test]
+    fn test_explicit_ids() {
+        let mut revised = heading(1, "Introduction, revised", None);
+        if let BlockInner::Heading(heading) = &mut revised.inner {
+            heading.attrs.insert(ID_KEY.to_owned(), "intro".to_owned());
+        }
+        let doc = Doc::from_content(vec![heading(0, "Preface", None), revised].into());
+        let anchors: Vec<_> = toc(&doc)
+            .iter()
+            .map(|entry| entry.anchor().into_string())
+            .collect();
+        assert_eq!(vec!["Preface", "intro"], anchors);
+        assert_eq!(
+            vec![
+                (RefId::new("Preface").unwrap(), Id::from(0)),
+                (RefId::new("intro").unwrap(), Id::from(1))
+            ]
+            .into_iter()
+            .collect::<HashMap<_, _>>(),
+            slug_map(&doc)
+        );
+    }
+}
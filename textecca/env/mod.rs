@@ -1,7 +1,9 @@
 //! Evaluation environment, binding names to commands.
 
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use derivative::Derivative;
 
@@ -10,12 +12,49 @@ use crate::cmd::{
 };
 use crate::parse::{self, Parser};
 
+/// The last generation given to an environment; see `Environment::generation`.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// A generation no environment has had yet.
+fn next_generation() -> u64 {
+    GENERATION.fetch_add(1, Ordering::Relaxed) + 1
+}
+
 /// An evaluation environment, mapping command names to bindings and inheriting
 /// from a parent environment.
 #[derive(Default, Debug, Clone)]
 pub struct Environment {
     parent: Option<Rc<Environment>>,
-    cmds: HashMap<String, CommandInfoMemo>,
+    /// Shared with snapshots, and copied when a binding is added.
+    cmds: Rc<HashMap<String, Rc<CommandInfoMemo>>>,
+    /// Changed whenever a binding is added, and never less than the
+    /// parent's; see `generation`.
+    generation: u64,
+    /// Names resolved to bindings inherited from the parent; see `lookup`.
+    resolved: RefCell<Resolved>,
+}
+
+/// The inherited bindings an `Environment` has looked up, so looking them up
+/// again doesn't walk the parent chain.
+#[derive(Default, Debug, Clone)]
+struct Resolved {
+    /// The parent the names were resolved in. A parent's bindings can't
+    /// change while it's shared, so the resolved bindings are correct until
+    /// the environment's parent is replaced, e.g. by `restore`. Held weakly
+    /// so a replaced parent can be dropped, while its address can't be
+    /// reused by another environment.
+    parent: Weak<Environment>,
+    /// The binding each name resolved to.
+    bindings: HashMap<String, Rc<CommandInfoMemo>>,
+}
+
+/// The bindings of an `Environment` at some point, for restoring later; see
+/// `Environment::snapshot`.
+#[derive(Debug, Clone)]
+pub struct EnvSnapshot {
+    parent: Option<Rc<Environment>>,
+    cmds: Rc<HashMap<String, Rc<CommandInfoMemo>>>,
+    generation: u64,
 }
 
 impl Environment {
@@ -27,32 +66,112 @@ impl Environment {
     /// Creates a new environment inheriting from this one.
     pub fn new_inheriting(self: Rc<Self>) -> Rc<Self> {
         Rc::new(Self {
+            generation: self.generation,
             parent: Some(self),
             ..Default::default()
         })
     }
 
     /// Get the memozied information for the command with the given name.
-    pub fn cmd_info(&self, name: &str) -> Result<&CommandInfoMemo, CommandError<'static>> {
-        self.cmds
-            .get(name)
-            .or_else(|| {
-                self.parent
-                    .as_ref()
-                    .map(|env| env.cmd_info(name).ok())
-                    .flatten()
-            })
+    ///
+    /// The binding's `CommandInfoMemo::generation` tells apart the commands
+    /// a name is bound to over time, e.g. for caching things computed from
+    /// the binding.
+    pub fn cmd_info(&self, name: &str) -> Result<Rc<CommandInfoMemo>, CommandError<'static>> {
+        self.lookup(name)
             .ok_or_else(|| CommandError::Name(name.to_owned()))
     }
 
+    /// Get the memoized information for the command with the given name, or
+    /// `None` if it isn't bound.
+    ///
+    /// Inherited bindings are remembered until this environment's parent is
+    /// replaced, so looking up a name again is about as fast in a deeply
+    /// nested environment as in its root. Looking up a name only allocates
+    /// the first time it's found in a parent.
+    pub fn lookup(&self, name: &str) -> Option<Rc<CommandInfoMemo>> {
+        if let Some(info) = self.cmds.get(name) {
+            return Some(Rc::clone(info));
+        }
+        let parent = self.parent.as_ref()?;
+        let mut resolved = self.resolved.borrow_mut();
+        if resolved.parent.as_ptr() != Rc::as_ptr(parent) {
+            resolved.parent = Rc::downgrade(parent);
+            resolved.bindings.clear();
+        }
+        if let Some(info) = resolved.bindings.get(name) {
+            return Some(Rc::clone(info));
+        }
+        let info = parent.lookup(name)?;
+        resolved.bindings.insert(name.to_owned(), Rc::clone(&info));
+        Some(info)
+    }
+
+    /// The names of the commands bound in this environment, not including
+    /// those inherited from its parent, in no particular order.
+    pub fn bound_names(&self) -> impl Iterator<Item = &str> {
+        self.cmds.keys().map(String::as_str)
+    }
+
+    /// The names of the commands in scope, including those inherited from its
+    /// parent, in sorted order.
+    pub fn names_in_scope(&self) -> impl Iterator<Item = &str> {
+        let mut names = BTreeSet::new();
+        let mut env = Some(self);
+        while let Some(current) = env {
+            names.extend(current.bound_names());
+            env = current.parent.as_deref();
+        }
+        names.into_iter()
+    }
+
+    /// Identifies this environment's bindings, including those inherited from
+    /// its parent.
+    ///
+    /// The generation changes whenever a binding is added (replacing any
+    /// binding with the same name) or the bindings are restored, and never
+    /// goes back to a value it had for different bindings, so anything
+    /// computed from an environment's bindings can be cached by generation.
+    pub fn generation(&self) -> u64 {
+        // A parent can't change while it's shared with its children, so its
+        // generation is copied by `new_inheriting`, and only ever exceeded
+        // by `add_binding`.
+        self.generation
+    }
+
     /// Add a binding from the given type.
     pub fn add_binding<C: CommandInfo>(&mut self) {
-        let info = CommandInfoMemo::new::<C>();
-        self.cmds.insert(info.name.clone(), info);
+        self.add_binding_name::<C>(C::name());
     }
 
     /// Add a binding from the given type, but override the binding's name.
     pub fn add_binding_name<C: CommandInfo>(&mut self, name: String) {
-        self.cmds.insert(name, CommandInfoMemo::new::<C>());
+        self.generation = next_generation();
+        let info = CommandInfoMemo {
+            generation: self.generation,
+            ..CommandInfoMemo::new::<C>()
+        };
+        Rc::make_mut(&mut self.cmds).insert(name, Rc::new(info));
+    }
+
+    /// Save this environment's bindings, e.g. before evaluating something
+    /// speculatively; see `restore`.
+    ///
+    /// Taking a snapshot is cheap: the bindings are shared until one of them
+    /// changes.
+    pub fn snapshot(&self) -> EnvSnapshot {
+        EnvSnapshot {
+            parent: self.parent.clone(),
+            cmds: Rc::clone(&self.cmds),
+            generation: self.generation,
+        }
+    }
+
+    /// Restore the bindings saved by `snapshot`, undoing any bindings added
+    /// since.
+    pub fn restore(&mut self, snapshot: EnvSnapshot) {
+        self.parent = snapshot.parent;
+        self.cmds = snapshot.cmds;
+        self.generation = snapshot.generation;
     }
 }
@@ -0,0 +1,99 @@
+//! Filling paragraphs, i.e. joining words into lines which fit in a width,
+//! for `parse::format` and `ser::PlainTextSerializer`.
+
+/// A piece of a paragraph, for wrapping it with `fill`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Atom {
+    /// Text which isn't broken across lines; adjacent words are joined.
+    Word(String),
+    /// A space, where a line can be broken.
+    Space,
+    /// A line break.
+    Break,
+    /// A paragraph break, written as a blank line except at the start.
+    Par,
+}
+
+/// Join atoms, breaking lines at spaces so they fit in `width` where possible.
+/// Spaces at the start and end of lines are dropped.
+pub(crate) fn fill(atoms: &[Atom], width: Option<usize>) -> String {
+    let mut ret = String::new();
+    let mut col = 0;
+    let mut space = false;
+    for (i, atom) in atoms.iter().enumerate() {
+        match atom {
+            Atom::Space => space = true,
+            Atom::Break => {
+                ret.push('\n');
+                col = 0;
+                space = false;
+            }
+            Atom::Par => {
+                if !ret.is_empty() {
+                    ret.push_str("\n\n");
+                }
+                col = 0;
+                space = false;
+            }
+            Atom::Word(word) => {
+                if space && col > 0 {
+                    if matches!(width, Some(width) if col + 1 + word_width(&atoms[i..]) > width) {
+                        ret.push('\n');
+                        col = 0;
+                    } else {
+                        ret.push(' ');
+                        col += 1;
+                    }
+                }
+                space = false;
+                ret.push_str(word);
+                col = match word.rfind('\n') {
+                    Some(nl) => word[nl + 1..].chars().count(),
+                    None => col + word.chars().count(),
+                };
+            }
+        }
+    }
+    ret
+}
+
+/// The width of the first line of the word starting at `atoms[0]`.
+fn word_width(atoms: &[Atom]) -> usize {
+    let mut ret = 0;
+    for atom in atoms {
+        match atom {
+            Atom::Word(word) => match word.find('\n') {
+                Some(nl) => return ret + word[..nl].chars().count(),
+                None => ret += word.chars().count(),
+            },
+            Atom::Space | Atom::Break | Atom::Par => break,
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_fill_pars() {
+        let word = |word: &str| Atom::Word(word.into());
+        let atoms = vec![
+            Atom::Par,
+            word("a"),
+            Atom::Space,
+            word("b"),
+            word("c"),
+            Atom::Space,
+            Atom::Par,
+            word("d\ne"),
+            Atom::Space,
+            word("f"),
+        ];
+        assert_eq!("a\nbc\n\nd\ne f", fill(&atoms, Some(3)));
+        assert_eq!("a bc\n\nd\ne f", fill(&atoms, None));
+    }
+}
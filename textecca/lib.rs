@@ -3,9 +3,52 @@
 #![warn(missing_docs)]
 
 //! Textecca is a markup language framework.
+//!
+//! This crate has no commands of its own; the standard ones (`\sec`,
+//! `\emph`, and so on) are in the `textecca_stdlib` crate, and are bound in
+//! an `env::Environment` with `textecca_stdlib::import`.
+//!
+//! # Threads
+//!
+//! Evaluation is single-threaded: a `cmd::World` shares its environment with
+//! `Rc`. Its inputs and outputs aren't, though; `parse::Source`, parsed
+//! `parse::Tokens`, and `doc::Doc` are `Send` and `Sync`, so a document can be
+//! split (e.g. into chapters) and serialized on several threads, each with its
+//! own serializer. Serializer options may hold `Rc`s, so serializers aren't
+//! `Send`; create them on the thread which uses them.
+//!
+//! # Features
+//!
+//! The parser, the document model, and the plain-text, Markdown, and LaTeX
+//! serializers (`ser::PlainTextSerializer`, `ser::MarkdownSerializer`, and
+//! `ser::LatexSerializer`) are always built. Everything else is behind a
+//! feature, all of which except `epub` are enabled by default; embedders
+//! which only need the document model (e.g. in a WASM bundle) can build with
+//! `default-features = false`.
+//!
+//! | Feature       | Enables                                                                                                | Dependencies              |
+//! |---------------|--------------------------------------------------------------------------------------------------------|---------------------------|
+//! | `html`        | `ser::HtmlSerializer` and the `"html"` format; `ser::AssetEmbedder`                                    | `friendly_html`, `base64` |
+//! | `math`        | `ser::KatexRenderer`, the default math renderer                                                        | `katex`; implies `html`   |
+//! | `epub`        | `ser::EpubSerializer` and the `"epub"` format                                                          | `zip`; implies `html`     |
+//! | `cli-support` | serde for `diagnostic::Diagnostic` and `doc::Doc`; `doc::migrate`; the `"json"` and `"pandoc"` formats | `serde`, `serde_json`     |
+//!
+//! Without `math`, the `HtmlSerializer` writes math with
+//! `ser::PlainMathRenderer`.
 
 pub mod cmd;
+pub mod diagnostic;
 pub mod doc;
 pub mod env;
+mod fill;
 pub mod parse;
 pub mod ser;
+
+/// Compile-time checks of the guarantees in "Threads", above.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<parse::Source>();
+    assert_send_sync::<parse::Span<'static>>();
+    assert_send_sync::<parse::Tokens<'static>>();
+    assert_send_sync::<doc::Doc>();
+};
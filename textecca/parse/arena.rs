@@ -1,10 +1,16 @@
 use std::error::Error;
+use std::fmt::{self, Display};
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, PoisonError};
 
 use derivative::Derivative;
 use typed_arena::Arena;
 
-use super::{Parser, Span, Tokens};
+use super::{Parser, Span, Tokens, DEFAULT_MAX_BRACE_DEPTH};
+
+/// A UTF-8 byte order mark.
+const BOM: char = '\u{feff}';
 
 /// Source code tied to an arena allocator of strings.
 ///
@@ -16,17 +22,33 @@ use super::{Parser, Span, Tokens};
 /// with the allocator.
 ///
 /// No, a `Cow<'i, str>` doesn't work here, unfortunately.
+///
+/// A `Source` is `Sync`, so one document's tokens can be shared between
+/// threads; allocating takes a lock, which is uncontended outside of parallel
+/// phases.
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct Source {
     src: String,
+    /// The source's path, relative to the project root.
+    path: Option<PathBuf>,
+    /// How deeply braces may be nested in command arguments.
+    max_brace_depth: usize,
+    #[derivative(Debug = "ignore")]
+    arena: Mutex<Arena<String>>,
+    /// The byte offset of the start of each line, built the first time a
+    /// position is looked up.
     #[derivative(Debug = "ignore")]
-    arena: Arena<String>,
+    line_starts: OnceLock<Vec<usize>>,
 }
 
 impl Clone for Source {
     fn clone(&self) -> Self {
-        Source::new(self.src.clone())
+        Source {
+            path: self.path.clone(),
+            max_brace_depth: self.max_brace_depth,
+            ..Source::new(self.src.clone())
+        }
     }
 }
 
@@ -42,9 +64,9 @@ impl From<String> for Source {
     }
 }
 
-impl<'i> Into<Span<'i>> for &'i Source {
-    fn into(self) -> Span<'i> {
-        Span::new(&self.src)
+impl<'i> From<&'i Source> for Span<'i> {
+    fn from(src: &'i Source) -> Self {
+        Span::new(&src.src)
     }
 }
 
@@ -57,6 +79,9 @@ impl Deref for Source {
 
 impl Source {
     /// Create a new source-arena.
+    ///
+    /// A leading byte order mark is removed, so offsets into the source are
+    /// relative to the text after it.
     pub fn new(src: String) -> Self {
         let cap = src.len() / 16;
         Self::with_capacity(src, cap)
@@ -64,38 +89,156 @@ impl Source {
 
     /// Create a new source-arena with the given capacity for new tokens.
     pub fn with_capacity(src: String, n: usize) -> Self {
+        let src = match src.strip_prefix(BOM) {
+            Some(rest) => rest.to_owned(),
+            None => src,
+        };
         Self {
             src,
-            arena: Arena::with_capacity(n),
+            path: None,
+            max_brace_depth: DEFAULT_MAX_BRACE_DEPTH,
+            arena: Mutex::new(Arena::with_capacity(n)),
+            line_starts: OnceLock::new(),
+        }
+    }
+
+    /// Set the path of the file this source was read from, relative to the
+    /// project root; paths in the source are resolved relative to it. See
+    /// `cmd::ResourceResolver`.
+    pub fn with_path(self, path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+            ..self
         }
     }
 
+    /// Set how deeply braces may be nested in command arguments; by default,
+    /// `DEFAULT_MAX_BRACE_DEPTH`. Parsing more deeply nested braces is an
+    /// error.
+    pub fn with_max_brace_depth(self, max_brace_depth: usize) -> Self {
+        Self {
+            max_brace_depth,
+            ..self
+        }
+    }
+
+    /// How deeply braces may be nested in command arguments.
+    pub fn max_brace_depth(&self) -> usize {
+        self.max_brace_depth
+    }
+
+    /// The path of the file this source was read from, if known.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
     /// Allocate a string and return a mutable reference to it.
     ///
     /// This is useful for creating new tokens with the same lifespan as the input.
     #[allow(clippy::mut_from_ref)]
     pub fn alloc(&self, val: String) -> &mut str {
-        self.arena.alloc(val)
+        let arena = self.arena.lock().unwrap_or_else(PoisonError::into_inner);
+        let val: *mut String = arena.alloc(val);
+        // The arena never moves its values, and only drops them when it's
+        // dropped itself, so they live as long as `self` even once the lock is
+        // released. Each value is only handed out once.
+        unsafe { (*val).as_mut_str() }
     }
 
     /// Allocate a span with the given text, using an existing span for the
     /// location.
     pub fn alloc_span<'i>(&'i self, val: String, loc: Span<'i>) -> Span<'i> {
-        let fragment = self.arena.alloc(val);
+        let fragment = self.alloc(val);
         unsafe {
             Span::new_from_raw_offset(loc.location_offset(), loc.location_line(), fragment, ())
         }
     }
 
+    /// The 1-indexed line and column of the start of the given span, which
+    /// must have been created from this source.
+    ///
+    /// Columns are counted in characters.
+    pub fn position(&self, span: &Span) -> SourcePosition {
+        self.offset_position(span.location_offset())
+    }
+
+    /// The byte offset of the start of each line in the source; the first is
+    /// always 0.
+    fn line_starts(&self) -> &[usize] {
+        self.line_starts
+            .get_or_init(|| std::iter::once(0).chain(line_ends(&self.src)).collect())
+    }
+
+    /// The 1-indexed line and column of the given byte offset in the source.
+    ///
+    /// Lines end with `\n`, `\r\n`, or a lone `\r`; see `line_ends`.
+    pub fn offset_position(&self, offset: usize) -> SourcePosition {
+        let offset = offset.min(self.src.len());
+        let starts = self.line_starts();
+        // `starts[0]` is 0, so at least one line starts at or before `offset`.
+        let line = starts.partition_point(|&start| start <= offset);
+        let line_start = starts[line - 1];
+        SourcePosition {
+            line: line as u32,
+            column: self.src[line_start..offset].chars().count() + 1,
+        }
+    }
+
+    /// The byte offset of the given 1-indexed line and column in the source;
+    /// the inverse of `offset_position`. Positions past the end of a line or
+    /// of the source give the offset of its end.
+    pub fn position_offset(&self, position: SourcePosition) -> usize {
+        let starts = self.line_starts();
+        let index = (position.line as usize).saturating_sub(1);
+        let line_start = match starts.get(index) {
+            Some(&start) => start,
+            None => return self.src.len(),
+        };
+        // The line's break, if any, isn't part of it.
+        let line_end = starts
+            .get(index + 1)
+            .map_or(self.src.len(), |next| next - 1);
+        let line = &self.src[line_start..line_end];
+        line_start
+            + line
+                .char_indices()
+                .nth(position.column.saturating_sub(1))
+                .map_or(line.len(), |(i, _)| i)
+    }
+
     /// Create a span-generation function. This helps avoid passing the arena itself around.
     pub fn alloc_spans<'i>(&'i self, val: String) -> impl Fn(Span<'i>) -> Span<'i> + 'i {
-        let fragment: &'i str = self.arena.alloc(val);
+        let fragment: &'i str = self.alloc(val);
         move |loc| unsafe {
             Span::new_from_raw_offset(loc.location_offset(), loc.location_line(), fragment, ())
         }
     }
 }
 
+/// The byte offsets just after each line break in `src`, in order. A line
+/// break is a `\n`, or a `\r` not followed by one, so `\r\n` is a single line
+/// break and old Mac OS line endings are counted too.
+fn line_ends(src: &str) -> impl Iterator<Item = usize> + '_ {
+    src.match_indices(&['\n', '\r'][..])
+        .filter(move |&(i, m)| m == "\n" || !src[i + 1..].starts_with('\n'))
+        .map(|(i, _)| i + 1)
+}
+
+/// A 1-indexed line and column in a `Source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SourcePosition {
+    /// The line number.
+    pub line: u32,
+    /// The column number, in characters.
+    pub column: usize,
+}
+
+impl Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 /// A `Parser` bundled with a `Source`-arena.
 pub struct ParserArena<'i> {
     arena: &'i Source,
@@ -5,17 +5,17 @@ use nom::{
     character::complete::{anychar, char as take_char, none_of, one_of},
     combinator::{all_consuming, complete, cut, map, not, opt, recognize, rest_len, value, verify},
     error::{context, make_error, ErrorKind, ParseError, VerboseError},
-    multi::{many0, many1, many1_count, separated_nonempty_list},
+    multi::{many0, many1, many1_count, separated_list, separated_nonempty_list},
     sequence::{delimited, pair, preceded, terminated, tuple},
     IResult, Slice,
 };
 
 use super::parse_util::{
-    is_letter, is_mark, is_number, is_punctuation, is_symbol, many_at_least, take_ident,
-    take_inline_space1, take_letter1, take_not_inline_space1, take_number1, take_punctuation1,
-    take_symbol1,
+    is_inline_space, is_letter, is_mark, is_number, is_punctuation, is_symbol, many_at_least,
+    take_ident, take_inline_space1, take_letter1, take_not_inline_space1, take_number1,
+    take_punctuation1, take_symbol1,
 };
-use super::{Source, Span};
+use super::{Source, Span, Tokens};
 
 /// A parsed command, consisting of a name and arguments.
 #[derive(Clone, Debug, PartialEq)]
@@ -45,64 +45,198 @@ pub struct Argument<'i> {
     pub name: Option<Span<'i>>,
     /// The argument's value.
     pub value: Span<'i>,
+    /// The argument's value, already parsed with its command's parser; see
+    /// `World::validate`.
+    pub tokens: Option<Tokens<'i>>,
 }
 
 impl<'i> Argument<'i> {
     /// Create a new `Argument`.
     pub fn new(name: Option<Span<'i>>, value: Span<'i>) -> Self {
-        Argument { name, value }
+        Argument {
+            name,
+            value,
+            tokens: None,
+        }
     }
 
     /// Create a new `Argument` with no explicit name.
     pub fn from_value(value: Span<'i>) -> Self {
-        Argument { name: None, value }
+        Self::new(None, value)
     }
 }
 
+/// The default limit on how deeply braces may be nested in a command
+/// argument; see `Source::with_max_brace_depth`.
+pub const DEFAULT_MAX_BRACE_DEPTH: usize = 256;
+
 /// Parse a string with balanced braces.
-fn balanced_braces<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span, Span, E> {
-    context(
-        "balanced braces",
-        recognize(many0(alt((
-            recognize(none_of("{}\\")),
-            // Escaped braces
-            recognize(preceded(tag("\\"), one_of("{}"))),
-            // Other escapes are passed through literally.
-            recognize(pair(tag("\\"), anychar)),
-            brace_group,
-        )))),
-    )(i)
+fn balanced_braces<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span<'a>, Span<'a>, E> {
+    balanced_braces_within(DEFAULT_MAX_BRACE_DEPTH)(i)
+}
+
+/// Parse a string with balanced braces, nested at most `max_depth` deep.
+///
+/// Escaped braces (`\{` and `\}`) don't count, and other escapes are passed
+/// through literally. This counts depth rather than recursing, so deeply
+/// nested input can't overflow the stack.
+fn balanced_braces_within<'a, E: ParseError<Span<'a>>>(
+    max_depth: usize,
+) -> impl Fn(Span<'a>) -> IResult<Span<'a>, Span<'a>, E> {
+    move |i: Span<'a>| {
+        let text = *i.fragment();
+        let mut chars = text.char_indices();
+        let mut depth = 0;
+        let mut end = text.len();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                // The guard skips the escaped character, if there is one.
+                '\\' if chars.next().is_none() => {
+                    end = offset;
+                    break;
+                }
+                '{' if depth == max_depth => {
+                    let rest = i.slice(offset..);
+                    return Err(nom::Err::Failure(E::add_context(
+                        rest,
+                        "braces nested too deeply",
+                        E::from_error_kind(rest, ErrorKind::TooLarge),
+                    )));
+                }
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    end = offset;
+                    break;
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        if depth > 0 {
+            let rest = i.slice(end..);
+            return Err(nom::Err::Failure(E::add_context(
+                rest,
+                "balanced braces",
+                E::from_char(rest, '}'),
+            )));
+        }
+        Ok((i.slice(end..), i.slice(..end)))
+    }
 }
 
 /// Recognize a group of braces.
-pub fn brace_group<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span, Span, E> {
-    delimited(take_char('{'), balanced_braces, cut(take_char('}')))(i)
+pub fn brace_group<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span<'a>, Span<'a>, E> {
+    brace_group_within(DEFAULT_MAX_BRACE_DEPTH)(i)
+}
+
+/// Recognize a group of braces, nested at most `max_depth` deep (including
+/// the group's own braces).
+fn brace_group_within<'a, E: ParseError<Span<'a>>>(
+    max_depth: usize,
+) -> impl Fn(Span<'a>) -> IResult<Span<'a>, Span<'a>, E> {
+    delimited(
+        take_char('{'),
+        balanced_braces_within(max_depth.saturating_sub(1)),
+        cut(take_char('}')),
+    )
 }
 
 /// Parse a command keyword-argument name.
-fn command_kwarg_name<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span, Span, E> {
+fn command_kwarg_name<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span<'a>, Span<'a>, E> {
     recognize(many0(none_of("\\{}$=")))(i)
 }
 
 fn command_kwarg_value<'a, E: ParseError<Span<'a>>>(
     i: Span<'a>,
-) -> IResult<Span, (Option<char>, Span), E> {
+) -> IResult<Span<'a>, (Option<char>, Span<'a>), E> {
     pair(opt(take_char('=')), balanced_braces)(i)
 }
 
 /// Parse a command argument.
 fn command_arg<'a, E: ParseError<Span<'a>>>(
-    _arena: &'a Source,
+    arena: &'a Source,
     i: Span<'a>,
 ) -> IResult<Span<'a>, Argument<'a>, E> {
     preceded(
         opt(take_inline_space1),
-        map(brace_group, |value| Argument { name: None, value }),
+        map(
+            brace_group_within(arena.max_brace_depth()),
+            Argument::from_value,
+        ),
+    )(i)
+}
+
+/// Parse a bracketed list of keyword arguments, e.g. `[lang=rust, linenos]`.
+///
+/// A key given without a value (`linenos`) gets an empty value. Values may be
+/// bare (running up to the next `,` or `]`, with trailing space trimmed),
+/// double-quoted, or a balanced brace group.
+fn bracket_args<'a, E: ParseError<Span<'a>>>(
+    arena: &'a Source,
+    i: Span<'a>,
+) -> IResult<Span<'a>, Vec<Argument<'a>>, E> {
+    context(
+        "bracket arguments",
+        delimited(
+            take_char('['),
+            separated_list(
+                take_char(','),
+                delimited(
+                    opt(take_inline_space1),
+                    |i| bracket_arg(arena, i),
+                    opt(take_inline_space1),
+                ),
+            ),
+            take_char(']'),
+        ),
     )(i)
 }
 
+/// Parse a single `key` or `key=value` pair within `bracket_args`.
+fn bracket_arg<'a, E: ParseError<Span<'a>>>(
+    arena: &'a Source,
+    i: Span<'a>,
+) -> IResult<Span<'a>, Argument<'a>, E> {
+    let (i, name) = take_ident(i)?;
+    let (i, value) = opt(preceded(
+        tuple((
+            opt(take_inline_space1),
+            take_char('='),
+            opt(take_inline_space1),
+        )),
+        |i| bracket_arg_value(arena, i),
+    ))(i)?;
+    let value = value.unwrap_or_else(|| name.slice(name.fragment().len()..));
+    Ok((i, Argument::new(Some(name), value)))
+}
+
+/// Parse the value of a `key=value` pair within `bracket_args`.
+fn bracket_arg_value<'a, E: ParseError<Span<'a>>>(
+    arena: &'a Source,
+    i: Span<'a>,
+) -> IResult<Span<'a>, Span<'a>, E> {
+    alt((
+        delimited(
+            take_char('"'),
+            recognize(many0(none_of("\"\r\n"))),
+            take_char('"'),
+        ),
+        brace_group_within(arena.max_brace_depth()),
+        bare_bracket_arg_value,
+    ))(i)
+}
+
+/// A bare bracket-argument value, with trailing inline space trimmed.
+fn bare_bracket_arg_value<'a, E: ParseError<Span<'a>>>(
+    i: Span<'a>,
+) -> IResult<Span<'a>, Span<'a>, E> {
+    let (_, value) = recognize(many1(none_of(",]\\{}\r\n")))(i)?;
+    let len = value.fragment().trim_end_matches(is_inline_space).len();
+    Ok((i.slice(len..), i.slice(..len)))
+}
+
 /// Parse a command name.
-fn command_name<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span, Span, E> {
+fn command_name<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span<'a>, Span<'a>, E> {
     preceded(tag("\\"), take_ident)(i)
 }
 
@@ -110,19 +244,24 @@ fn command_name<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span, Span,
 pub fn parse_command<'a, E: ParseError<Span<'a>>>(
     arena: &'a Source,
     mandatory_args: usize,
-) -> impl Fn(Span<'a>) -> IResult<Span, Command, E> {
+) -> impl Fn(Span<'a>) -> IResult<Span<'a>, Command<'a>, E> {
     move |i| {
         context(
             "command",
             map(
-                pair(
+                tuple((
                     command_name,
+                    opt(complete(|i| bracket_args(arena, i))),
                     cut(many_at_least(
                         mandatory_args,
                         complete(|i| command_arg(arena, i)),
                     )),
-                ),
-                |(name, args)| Command { name, args },
+                )),
+                |(name, kwargs, args)| {
+                    let mut kwargs = kwargs.unwrap_or_default();
+                    kwargs.extend(args);
+                    Command::new(name, kwargs)
+                },
             ),
         )(i)
     }
@@ -177,6 +316,39 @@ mod test {
             .assert("}");
     }
 
+    #[test]
+    fn test_brace_depth() {
+        let nested = |depth| format!("{}{}", "{".repeat(depth), "}".repeat(depth));
+        fn arg(source: &Source) -> Result<Argument<'_>, nom::Err<VerboseError<Span<'_>>>> {
+            all_consuming(|i| command_arg(source, i))(source.into()).map(|(_, arg)| arg)
+        }
+
+        // Deep nesting is an error rather than a stack overflow.
+        let source = Source::new(nested(10_000));
+        let err = assert_err!(arg(&source));
+        match err {
+            nom::Err::Failure(err) => {
+                assert_eq!(
+                    (
+                        DEFAULT_MAX_BRACE_DEPTH,
+                        VerboseErrorKind::Context("braces nested too deeply")
+                    ),
+                    (err.errors[0].0.location_offset(), err.errors[1].1.clone())
+                );
+            }
+            err => panic!("Expected a failure, got {:?}", err),
+        }
+
+        // ...unless the limit is raised.
+        let source = Source::new(nested(10_000)).with_max_brace_depth(10_000);
+        assert_eq!(9_999 * 2, assert_ok!(arg(&source)).value.fragment().len());
+
+        let source = Source::new(nested(3)).with_max_brace_depth(3);
+        assert_ok!(arg(&source));
+        let source = Source::new(nested(4)).with_max_brace_depth(3);
+        assert_err!(arg(&source));
+    }
+
     #[test]
     fn test_command_name() {
         AssertParse::new(command_name)
@@ -201,12 +373,10 @@ mod test {
             .build()
             .assert(" {y}{z}");
 
+        // Keyword arguments go in brackets; within braces, `=` is just text.
         assert()
             .ok(Box::new(|input, arg| {
-                assert_eq!(
-                    Argument::new(Some(input.offset(1, "name ")), input.offset(7, " val")),
-                    arg
-                )
+                assert_eq!(Argument::from_value(input.offset(1, "name = val")), arg)
             }))
             .all_consuming(true)
             .build()
@@ -259,4 +429,46 @@ mod test {
             .build()
             .assert("\\section{Whatever}");
     }
+
+    #[test]
+    fn test_command_bracket_args() {
+        let source = Source::new("".into());
+
+        AssertParse::new(parse_command(&source, 0))
+            .ok(Box::new(|i, cmd| {
+                assert_eq!(
+                    Command {
+                        name: i.offset(1, "code"),
+                        args: vec![
+                            Argument::new(Some(i.offset(6, "lang")), i.offset(11, "rust")),
+                            Argument::new(Some(i.offset(17, "linenos")), i.offset(24, "")),
+                            Argument::new(Some(i.offset(26, "title")), i.offset(33, "a, b")),
+                            Argument::from_value(i.offset(41, "x")),
+                        ],
+                    },
+                    cmd
+                )
+            }))
+            .all_consuming(true)
+            .build()
+            .assert("\\code[lang=rust, linenos, title=\"a, b\" ]{x}");
+
+        // Bare values run up to the next comma and have trailing space trimmed.
+        AssertParse::new(parse_command(&source, 0))
+            .ok(Box::new(|i, cmd| {
+                assert_eq!(
+                    Command {
+                        name: i.offset(1, "math"),
+                        args: vec![
+                            Argument::new(Some(i.offset(6, "alt")), i.offset(10, "x squared")),
+                            Argument::from_value(i.offset(22, "x^2")),
+                        ],
+                    },
+                    cmd
+                )
+            }))
+            .all_consuming(true)
+            .build()
+            .assert("\\math[alt=x squared ]{x^2}");
+    }
 }
@@ -1,4 +1,5 @@
-use std::error::Error;
+use std::error;
+use std::ops::Range;
 
 use nom::{
     branch::alt,
@@ -6,40 +7,171 @@ use nom::{
     bytes::streaming::{take_while, take_while1},
     character::complete::{anychar, char as take_char, none_of, one_of},
     combinator::*,
-    error::{make_error, ErrorKind, ParseError, VerboseError},
+    error::{make_error, ErrorKind, ParseError, VerboseError, VerboseErrorKind},
     multi::*,
     sequence::*,
     IResult, Slice,
 };
+use thiserror::Error;
 
 use super::parse_util::*;
-use super::{parse_command, Command, Parser, Source, Span, Token, Tokens};
+use super::{parse_command, Command, Parser, Source, SourcePosition, Span, Token, Tokens};
+use crate::diagnostic::{self, DiagnosticSpan, ErrorCode, RelatedSpan, Severity};
 
-/// The default textecca parser.
+/// The default textecca parser. Errors are `SyntaxError`s.
 pub fn default_parser<'i>(
     arena: &'i Source,
     input: Span<'i>,
-) -> Result<Tokens<'i>, Box<dyn Error + 'i>> {
+) -> Result<Tokens<'i>, Box<dyn error::Error + 'i>> {
+    parse_tokens(arena, input).map_err(|err| SyntaxError::new(arena, err).into())
+}
+
+/// Parse a whole source file with `default_parser`.
+pub fn parse_source(src: &Source) -> Result<Tokens<'_>, SyntaxError> {
+    parse_tokens(src, src.into()).map_err(|err| SyntaxError::new(src, err))
+}
+
+fn parse_tokens<'i>(
+    arena: &'i Source,
+    input: Span<'i>,
+) -> Result<Tokens<'i>, nom::Err<VerboseError<Span<'i>>>> {
     all_consuming(many0(alt((
         map(parse_command(arena, 0), Token::from),
-        map(recognize(many1(none_of("\\\r\n"))), Token::from),
-        newlines(arena.alloc_spans("par".into())),
+        map(
+            recognize(many1(verify(none_of("\\"), |c| {
+                !is_line_break(*c) && !is_control_space(*c)
+            }))),
+            Token::from,
+        ),
+        newlines(
+            arena.alloc_spans("par".into()),
+            arena.alloc_spans(" ".into()),
+        ),
+        control_spaces(arena.alloc_spans(" ".into())),
     ))))(input)
     .map(|(_remaining, tokens)| tokens)
-    .map_err(|e: nom::Err<VerboseError<_>>| e.into())
 }
 
-fn newlines<'i, E: ParseError<Span<'i>> + 'i>(
+/// Source which doesn't parse, e.g. because a brace isn't closed.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{message}")]
+pub struct SyntaxError {
+    /// The byte range of the problem.
+    pub span: Range<usize>,
+    /// The position of the start of `span`.
+    pub position: SourcePosition,
+    /// What's wrong, e.g. `expected '}'`, and what it's in, e.g. `in the
+    /// command at 1:7`.
+    pub message: String,
+    /// The byte ranges of what the problem is in, innermost first, with
+    /// what they are, e.g. `command`.
+    pub context: Vec<(Range<usize>, &'static str)>,
+}
+
+impl SyntaxError {
+    fn new<'i>(src: &Source, err: nom::Err<VerboseError<Span<'i>>>) -> Self {
+        let errors = match err {
+            nom::Err::Error(err) | nom::Err::Failure(err) => err.errors,
+            nom::Err::Incomplete(_) => Vec::new(),
+        };
+        let (span, mut message) = match errors.first() {
+            Some((span, kind)) => {
+                let start = span.location_offset();
+                let next = span.fragment().chars().next();
+                let end = start + next.map_or(0, char::len_utf8);
+                let message = match (kind, next) {
+                    (VerboseErrorKind::Char(c), _) => format!("expected {:?}", c),
+                    (VerboseErrorKind::Context(context), _) => (*context).to_owned(),
+                    (VerboseErrorKind::Nom(_), Some(c)) => format!("unexpected {:?}", c),
+                    (VerboseErrorKind::Nom(_), None) => "unexpected end of input".to_owned(),
+                };
+                (start..end, message)
+            }
+            None => (src.len()..src.len(), "unexpected end of input".to_owned()),
+        };
+        let mut context = Vec::new();
+        for (i, (ctx_span, kind)) in errors.iter().enumerate().skip(1) {
+            if let VerboseErrorKind::Context(what) = kind {
+                let start = ctx_span.location_offset();
+                if start == span.start {
+                    // E.g. "braces nested too deeply" says more than the
+                    // error it's attached to.
+                    if i == 1 && matches!(errors[0].1, VerboseErrorKind::Nom(_)) {
+                        message = (*what).to_owned();
+                    }
+                    continue;
+                }
+                message.push_str(&format!(
+                    " in the {} at {}",
+                    what,
+                    src.offset_position(start)
+                ));
+                context.push((start..start + ctx_span.fragment().len(), *what));
+            }
+        }
+        Self {
+            position: src.offset_position(span.start),
+            span,
+            message,
+            context,
+        }
+    }
+
+    /// The stable code for this error; see `diagnostic::ErrorCode`.
+    pub fn code(&self) -> ErrorCode {
+        ErrorCode::Syntax
+    }
+
+    /// This error as a `diagnostic::Diagnostic`, in the given source.
+    pub fn diagnostic(&self, src: &Source) -> diagnostic::Diagnostic {
+        let mut ret =
+            diagnostic::Diagnostic::new(Severity::Error, self.code(), self.message.clone())
+                .in_file(src)
+                .with_span(Some(DiagnosticSpan::new(src, self.span.clone())));
+        ret.related = self
+            .context
+            .iter()
+            .map(|(span, what)| RelatedSpan {
+                span: DiagnosticSpan::new(src, span.clone()),
+                message: format!("In this {}", what),
+            })
+            .collect();
+        ret
+    }
+}
+
+/// Form feeds and vertical tabs are read as a single space.
+fn control_spaces<'i, E: ParseError<Span<'i>> + 'i>(
     alloc_span: impl Fn(Span<'i>) -> Span<'i> + 'i,
 ) -> impl Fn(Span<'i>) -> IResult<Span, Token, E> + 'i {
-    map(many1(newline), move |nls| {
-        let nl = *nls.last().unwrap();
-        if nls.len() == 1 {
-            // A single newline is nothing special.
-            nl.into()
+    map(
+        nom::bytes::complete::take_while1(is_control_space),
+        move |sp| alloc_span(sp).into(),
+    )
+}
+
+/// A single newline is read as a space, and multiple newlines as a `\par`
+/// command; hard line breaks must be written explicitly (e.g. `\br`).
+///
+/// Newlines may be written in any of the styles `newline` accepts, mixed
+/// freely. A paragraph separator (U+2029) is a `\par` by itself, along with
+/// any newlines next to it.
+fn newlines<'i, E: ParseError<Span<'i>> + 'i>(
+    alloc_par: impl Fn(Span<'i>) -> Span<'i> + 'i,
+    alloc_space: impl Fn(Span<'i>) -> Span<'i> + 'i,
+) -> impl Fn(Span<'i>) -> IResult<Span, Token, E> + 'i {
+    let line_break = alt((
+        map(newline, |nl| (nl, false)),
+        map(paragraph_separator, |sep| (sep, true)),
+    ));
+    map(many1(line_break), move |nls| {
+        let (nl, _) = *nls.last().unwrap();
+        if nls.len() == 1 && !nls[0].1 {
+            // A single newline is just a space.
+            alloc_space(nl).into()
         } else {
-            // Multiple newlines is a paragraph.
-            Token::from(Command::from_name(alloc_span(nl)))
+            // Multiple newlines, or a paragraph separator, is a paragraph.
+            Token::from(Command::from_name(alloc_par(nl)))
         }
     })
 }
@@ -58,7 +190,7 @@ mod test {
         assert_eq!(
             vec![
                 Token::from(input.offset(0, "xxx")),
-                input.offset(3, "\n").into(),
+                input.arena.alloc_span(" ".into(), input.slice(3..4)).into(),
                 Command::new(
                     input.offset(5, "cmd"),
                     vec![Argument::from_value(input.offset(9, "foo"))]
@@ -79,4 +211,173 @@ mod test {
             default_parser(&input.arena, input.span).unwrap()
         );
     }
+
+    #[test]
+    fn parse_wrapped_lines() {
+        let input = Input::new("A sentence\nwrapped across\r\nthree lines.");
+        assert_eq!(
+            vec![
+                Token::from(input.offset(0, "A sentence")),
+                input
+                    .arena
+                    .alloc_span(" ".into(), input.slice(10..11))
+                    .into(),
+                input.offset(11, "wrapped across").into(),
+                input
+                    .arena
+                    .alloc_span(" ".into(), input.slice(25..27))
+                    .into(),
+                input.offset(27, "three lines.").into(),
+            ],
+            default_parser(&input.arena, input.span).unwrap()
+        );
+    }
+
+    /// The text of each token, or the names of commands.
+    fn fragments(src: &str) -> Vec<String> {
+        let input = Input::new(src);
+        default_parser(&input.arena, input.span)
+            .unwrap()
+            .iter()
+            .map(|tok| match tok {
+                Token::Text(span) => span.fragment().to_string(),
+                Token::Command(cmd) => format!("\\{}", cmd.name.fragment()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_line_endings() {
+        for nl in &["\n", "\r\n", "\r", "\u{2028}"] {
+            assert_eq!(
+                vec!["a", " ", "b"],
+                fragments(&format!("a{}b", nl)),
+                "{:?}",
+                nl
+            );
+            assert_eq!(
+                vec!["a", "\\par", "b"],
+                fragments(&format!("a{}{}b", nl, nl)),
+                "{:?}",
+                nl
+            );
+        }
+        for par in &[
+            "\u{2029}",
+            "\n\u{2029}\n",
+            "\r\n\n",
+            "\r\r\n",
+            "\n\r",
+            "\u{2028}\n",
+        ] {
+            assert_eq!(
+                vec!["a", "\\par", "b"],
+                fragments(&format!("a{}b", par)),
+                "{:?}",
+                par
+            );
+        }
+        assert_eq!(
+            vec!["One", " ", "line.", "\\par", "Two", " ", "lines.", "\\par", "Three."],
+            fragments("One\rline.\r\n\r\nTwo\u{2028}lines.\u{2029}Three.")
+        );
+    }
+
+    /// How long this takes is measured by the `long_blank_run` benchmark.
+    #[test]
+    fn parse_many_blank_lines() {
+        let text = format!("first.{}second.", "\n".repeat(100_000));
+        let input = Input::new(&text);
+        let toks = default_parser(&input.arena, input.span).unwrap();
+        assert_eq!(
+            vec![
+                Token::from(input.offset(0, "first.")),
+                Command::from_name(
+                    input
+                        .arena
+                        .alloc_span("par".into(), input.slice(100_005..100_005))
+                )
+                .into(),
+                input.offset(100_006, "second.").into()
+            ],
+            toks
+        );
+    }
+
+    #[test]
+    fn parse_bom() {
+        // Offsets are relative to the source after the byte order mark.
+        let src = Source::new("\u{feff}\\cmd x".into());
+        let input = Input::new("\\cmd x");
+        assert_eq!(
+            vec![
+                Token::from(Command::from_name(input.offset(1, "cmd"))),
+                input.offset(4, " x").into(),
+            ],
+            default_parser(&src, (&src).into()).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_control_chars() {
+        let input = Input::new("one\x0c\x0ctwo");
+        assert_eq!(
+            vec![
+                Token::from(input.offset(0, "one")),
+                input.arena.alloc_span(" ".into(), input.slice(3..5)).into(),
+                input.offset(5, "two").into(),
+            ],
+            default_parser(&input.arena, input.span).unwrap()
+        );
+
+        // Joiners are kept as-is within words.
+        let input = Input::new("fam\u{200d}ily");
+        assert_eq!(
+            vec![Token::from(input.offset(0, "fam\u{200d}ily"))],
+            default_parser(&input.arena, input.span).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_code_args() {
+        let input = Input::new("\\code[lang=rust]{Vec<Box<dyn Fn()>>}");
+        assert_eq!(
+            vec![Token::from(Command::new(
+                input.offset(1, "code"),
+                vec![
+                    Argument::new(Some(input.offset(6, "lang")), input.offset(11, "rust")),
+                    Argument::from_value(input.offset(17, "Vec<Box<dyn Fn()>>")),
+                ]
+            ))],
+            default_parser(&input.arena, input.span).unwrap()
+        );
+
+        // Escaped braces don't end the argument.
+        let input = Input::new("\\code{a\\}b} c");
+        assert_eq!(
+            vec![
+                Token::from(Command::new(
+                    input.offset(1, "code"),
+                    vec![Argument::from_value(input.offset(6, "a\\}b"))]
+                )),
+                input.offset(11, " c").into(),
+            ],
+            default_parser(&input.arena, input.span).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let src = Source::new("Hello \\emph{x\n".into());
+        let err = parse_source(&src).unwrap_err();
+        assert_eq!(14..14, err.span);
+        assert_eq!(SourcePosition { line: 2, column: 1 }, err.position);
+        assert_eq!("expected '}' in the command at 1:7", err.to_string());
+        assert_eq!(vec![(6..14, "command")], err.context);
+
+        let src = Source::new("a \\".into());
+        let err = parse_source(&src).unwrap_err();
+        assert_eq!(2..3, err.span);
+        assert_eq!("unexpected '\\\\'", err.to_string());
+    }
 }
@@ -0,0 +1,260 @@
+use nom::{error::VerboseError, Slice};
+
+use super::{parse_command, parse_source, Argument, Command, Source, Span, SyntaxError};
+use crate::env::Environment;
+use crate::fill::{fill, Atom};
+
+/// Options for `format`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatStyle {
+    /// The width prose is wrapped to, in characters. Lines may be longer if
+    /// they contain long words or verbatim arguments.
+    pub width: usize,
+    /// Commands whose arguments are kept exactly as written, e.g. code and
+    /// math; see `for_env`.
+    pub verbatim_commands: Vec<String>,
+}
+
+impl FormatStyle {
+    /// The default style for source evaluated in `env`. The arguments to
+    /// commands with their own parser, like the standard library's `\code`
+    /// and `\math`, aren't prose, so they're kept verbatim.
+    pub fn for_env(env: &Environment) -> Self {
+        Self {
+            verbatim_commands: env
+                .names_in_scope()
+                .filter(|name| env.lookup(name).is_some_and(|info| !info.inherits_parser))
+                .map(str::to_owned)
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for FormatStyle {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            verbatim_commands: Vec::new(),
+        }
+    }
+}
+
+/// Format textecca source without evaluating it.
+///
+/// Whitespace between a command and its arguments is removed, keyword
+/// arguments are written as `[key=value, flag]`, runs of blank lines are
+/// collapsed to a single blank line, and prose is rewrapped to
+/// `style.width`. Arguments to `style.verbatim_commands` are left untouched.
+///
+/// Formatting is idempotent, and never changes how the source is evaluated
+/// (besides dropping whitespace at the start of a paragraph).
+pub fn format(src: &Source, style: &FormatStyle) -> Result<String, SyntaxError> {
+    // Refuse to format source that doesn't parse.
+    parse_source(src)?;
+    let formatter = Formatter { arena: src, style };
+    let mut atoms = Vec::new();
+    formatter.atoms(src.into(), &mut atoms);
+    let mut ret = fill(&atoms, Some(style.width));
+    if !ret.is_empty() {
+        ret.push('\n');
+    }
+    Ok(ret)
+}
+
+struct Formatter<'i, 's> {
+    arena: &'i Source,
+    style: &'s FormatStyle,
+}
+
+impl<'i, 's> Formatter<'i, 's> {
+    /// Split `input` into atoms, formatting any commands within it.
+    fn atoms(&self, input: Span<'i>, atoms: &mut Vec<Atom>) {
+        let mut rest = input;
+        let mut text = String::new();
+        while let Some(c) = rest.fragment().chars().next() {
+            if c.is_whitespace() {
+                let len = rest
+                    .fragment()
+                    .find(|c: char| !c.is_whitespace())
+                    .unwrap_or_else(|| rest.fragment().len());
+                let newlines = rest.fragment()[..len].matches('\n').count();
+                flush_text(&mut text, atoms);
+                atoms.push(if newlines > 1 { Atom::Par } else { Atom::Space });
+                rest = rest.slice(len..);
+            } else if c == '\\' {
+                match parse_command::<VerboseError<Span<'i>>>(self.arena, 0)(rest) {
+                    Ok((after, cmd)) => {
+                        text.push('\\');
+                        text.push_str(cmd.name.fragment());
+                        self.command_args(&cmd, &mut text, atoms);
+                        rest = after;
+                    }
+                    Err(_) => {
+                        // An escape, e.g. `\{`.
+                        let len = rest.fragment()[1..]
+                            .chars()
+                            .next()
+                            .filter(|c| !c.is_whitespace())
+                            .map_or(1, |c| 1 + c.len_utf8());
+                        text.push_str(&rest.fragment()[..len]);
+                        rest = rest.slice(len..);
+                    }
+                }
+            } else {
+                text.push(c);
+                rest = rest.slice(c.len_utf8()..);
+            }
+        }
+        flush_text(&mut text, atoms);
+    }
+
+    /// Write a command's arguments, after its name.
+    fn command_args(&self, cmd: &Command<'i>, text: &mut String, atoms: &mut Vec<Atom>) {
+        let verbatim = self
+            .style
+            .verbatim_commands
+            .iter()
+            .any(|name| name == cmd.name.fragment());
+        let kwargs: Vec<String> = cmd
+            .args
+            .iter()
+            .filter(|arg| arg.name.is_some())
+            .map(format_kwarg)
+            .collect();
+        if !kwargs.is_empty() {
+            text.push('[');
+            text.push_str(&kwargs.join(", "));
+            text.push(']');
+        }
+        for arg in cmd.args.iter().filter(|arg| arg.name.is_none()) {
+            text.push('{');
+            if verbatim {
+                text.push_str(arg.value.fragment());
+            } else {
+                flush_text(text, atoms);
+                self.atoms(arg.value, atoms);
+            }
+            text.push('}');
+        }
+    }
+}
+
+fn flush_text(text: &mut String, atoms: &mut Vec<Atom>) {
+    if !text.is_empty() {
+        atoms.push(Atom::Word(std::mem::take(text)));
+    }
+}
+
+/// Write a keyword argument as `key=value`, or `key` if it was given with no
+/// value.
+fn format_kwarg(arg: &Argument) -> String {
+    let name = arg.name.as_ref().expect("Only called on keyword arguments");
+    let value = arg.value.fragment();
+    let no_equals = value.is_empty()
+        && arg.value.location_offset() == name.location_offset() + name.fragment().len();
+    if no_equals {
+        return name.fragment().to_string();
+    }
+    let bare = !value.is_empty()
+        && value.trim() == *value
+        && !value.contains(|c| ",]\\{}\"\r\n".contains(c));
+    if bare {
+        format!("{}={}", name.fragment(), value)
+    } else if !value.contains(|c| "\"\r\n".contains(c)) {
+        format!("{}=\"{}\"", name.fragment(), value)
+    } else {
+        format!("{}={{{}}}", name.fragment(), value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn fmt(src: &str, width: usize) -> String {
+        let src = Source::new(src.into());
+        let style = FormatStyle {
+            width,
+            verbatim_commands: vec!["code".into(), "codeblock".into(), "equation".into()],
+        };
+        format(&src, &style).unwrap()
+    }
+
+    #[test]
+    fn test_format() {
+        let src = indoc!(
+            "
+            \\sec {Title}
+
+
+
+              Some   \\emph {emphasized} text
+            in a \\link[to = https://example.com,title=\"A, B\"] {paragraph}.
+
+
+            More.
+            "
+        );
+        assert_eq!(
+            indoc!(
+                "
+                \\sec{Title}
+
+                Some
+                \\emph{emphasized}
+                text in a
+                \\link[to=https://example.com, title=\"A, B\"]{paragraph}.
+
+                More.
+                "
+            )
+            .trim_start(),
+            fmt(src, 20)
+        );
+    }
+
+    #[test]
+    fn test_format_idempotent() {
+        let src = indoc!(
+            "
+            A  long paragraph\\footnote{With a footnote, which has
+
+            two paragraphs.} which goes on and on \\code[lang = rust]{x}.
+            \\equation{
+               a  = b
+            }
+            "
+        );
+        for width in &[10, 20, 40, 80] {
+            let once = fmt(src, *width);
+            assert_eq!(once, fmt(&once, *width));
+        }
+    }
+
+    #[test]
+    fn test_format_verbatim() {
+        let src = indoc!(
+            "
+            Code: \\code{a   {b}   \\c}
+
+            \\codeblock[lang=rust, linenos]{
+            fn main() {
+
+                  x();
+            }
+            }
+            "
+        );
+        assert_eq!(src.trim_start(), fmt(src, 80));
+    }
+
+    #[test]
+    fn test_format_syntax_error() {
+        let src = Source::new("\\x{".into());
+        assert!(format(&src, &Default::default()).is_err());
+    }
+}
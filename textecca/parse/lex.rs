@@ -0,0 +1,292 @@
+//! Splitting source into `RawToken`s, a parser-independent first pass which
+//! custom `Parser`s can build on.
+//!
+//! # Guarantees
+//!
+//! For any input, the tokens returned by `lex` (or `Lexer`):
+//!
+//! - Are non-empty, non-overlapping, and in source order; each token starts
+//!   where the previous one ends.
+//! - Cover the whole input, so concatenating their fragments gives the input
+//!   back exactly. Nothing is dropped or normalized, including trailing
+//!   whitespace and `\r\n` line endings.
+//! - Merge line endings maximally: a run of two or more line endings,
+//!   separated only by inline space, is always one `BlankLines` token, never
+//!   several `Newline` and `BlankLines` tokens. Inline space before the first
+//!   or after the last line ending isn't part of the run.
+//! - Never have two adjacent `Word`s or two adjacent `Space`s.
+//!
+//! Spans carry correct offsets and line numbers relative to the input.
+//!
+//! # Example
+//!
+//! A tiny `Parser` which reads text and paragraph breaks, but no commands:
+//!
+//! ```
+//! use std::error::Error;
+//!
+//! use textecca::parse::{lex, Command, Parser, RawTokenKind, Source, Span, Token, Tokens};
+//!
+//! fn prose<'i>(arena: &'i Source, input: Span<'i>) -> Result<Tokens<'i>, Box<dyn Error + 'i>> {
+//!     Ok(lex(input)
+//!         .into_iter()
+//!         .map(|tok| match tok.kind {
+//!             RawTokenKind::BlankLines => {
+//!                 Command::from_name(arena.alloc_span("par".into(), tok.span)).into()
+//!             }
+//!             RawTokenKind::Newline => arena.alloc_span(" ".into(), tok.span).into(),
+//!             _ => tok.span.into(),
+//!         })
+//!         .collect())
+//! }
+//!
+//! let parser: Parser = prose;
+//! let src = Source::new("One\ntwo.\n\nThree.".into());
+//! let toks = parser(&src, (&src).into()).unwrap();
+//! let text: Vec<_> = toks
+//!     .iter()
+//!     .map(|tok| match tok {
+//!         Token::Text(span) => *span.fragment(),
+//!         Token::Command(cmd) => *cmd.name.fragment(),
+//!     })
+//!     .collect();
+//! assert_eq!(vec!["One", " ", "two", ".", "par", "Three", "."], text);
+//! ```
+use nom::Slice;
+
+use super::parse_util::is_inline_space;
+use super::Span;
+
+/// What sort of text a `RawToken` holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawTokenKind {
+    /// A run of characters which aren't any of the other kinds.
+    Word,
+    /// A single ASCII punctuation character, like `\`, `{`, or `.`.
+    Punct,
+    /// A run of inline space: spaces, tabs, form feeds, vertical tabs, and
+    /// other Unicode space separators.
+    Space,
+    /// A single line ending, `\n` or `\r\n`.
+    Newline,
+    /// Two or more line endings, and any inline space between them.
+    BlankLines,
+}
+
+/// A lexed region of input; see the module documentation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawToken<'i> {
+    /// What sort of text this is.
+    pub kind: RawTokenKind,
+    /// The text.
+    pub span: Span<'i>,
+}
+
+/// An iterator over the `RawToken`s in some input; see `lex`.
+#[derive(Debug, Clone)]
+pub struct Lexer<'i> {
+    rest: Span<'i>,
+}
+
+impl<'i> Lexer<'i> {
+    /// Create a lexer for the given input.
+    pub fn new(input: Span<'i>) -> Self {
+        Self { rest: input }
+    }
+}
+
+impl<'i> Iterator for Lexer<'i> {
+    type Item = RawToken<'i>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (kind, len) = next_token(self.rest.fragment())?;
+        let span = self.rest.slice(..len);
+        self.rest = self.rest.slice(len..);
+        Some(RawToken { kind, span })
+    }
+}
+
+/// Split the input into `RawToken`s; see the module documentation for what's
+/// guaranteed about them.
+pub fn lex(input: Span) -> Vec<RawToken> {
+    Lexer::new(input).collect()
+}
+
+/// Lex the input a line at a time, without lexing lines before they're needed.
+///
+/// Each line's tokens end with its `Newline` or `BlankLines` token, except at
+/// the end of input; a `BlankLines` token ends the line it's on, so blank lines
+/// don't get lines of their own. Flattened, the lines are exactly the tokens
+/// `lex` returns.
+pub fn lex_lines(input: Span) -> impl Iterator<Item = Vec<RawToken>> {
+    let mut lexer = Lexer::new(input).peekable();
+    std::iter::from_fn(move || {
+        lexer.peek()?;
+        let mut line = Vec::new();
+        for tok in &mut lexer {
+            let end = matches!(tok.kind, RawTokenKind::Newline | RawTokenKind::BlankLines);
+            line.push(tok);
+            if end {
+                break;
+            }
+        }
+        Some(line)
+    })
+}
+
+/// The length in bytes of a line ending at the start of `s`, if any.
+fn line_ending_len(s: &str) -> Option<usize> {
+    if s.starts_with('\n') {
+        Some(1)
+    } else if s.starts_with("\r\n") {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// The kind and length in bytes of the token at the start of `s`.
+fn next_token(s: &str) -> Option<(RawTokenKind, usize)> {
+    let c = s.chars().next()?;
+    if let Some(first) = line_ending_len(s) {
+        let mut len = first;
+        let mut count = 1;
+        loop {
+            let rest = &s[len..];
+            let space = rest.len() - rest.trim_start_matches(is_inline_space).len();
+            match line_ending_len(&rest[space..]) {
+                Some(ending) => {
+                    len += space + ending;
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+        let kind = if count == 1 {
+            RawTokenKind::Newline
+        } else {
+            RawTokenKind::BlankLines
+        };
+        Some((kind, len))
+    } else if is_inline_space(c) {
+        Some((
+            RawTokenKind::Space,
+            s.len() - s.trim_start_matches(is_inline_space).len(),
+        ))
+    } else if c.is_ascii_punctuation() {
+        Some((RawTokenKind::Punct, 1))
+    } else {
+        let len = s
+            .char_indices()
+            .find(|&(i, c)| {
+                is_inline_space(c) || c.is_ascii_punctuation() || line_ending_len(&s[i..]).is_some()
+            })
+            .map_or(s.len(), |(i, _)| i);
+        Some((RawTokenKind::Word, len))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+
+    use super::*;
+    use RawTokenKind::*;
+
+    fn kinds(input: &str) -> Vec<(RawTokenKind, &str)> {
+        lex(Span::new(input))
+            .into_iter()
+            .map(|tok| (tok.kind, *tok.span.fragment()))
+            .collect()
+    }
+
+    #[test]
+    fn test_lex() {
+        assert_eq!(
+            vec![
+                (Punct, "\\"),
+                (Word, "emph"),
+                (Punct, "{"),
+                (Word, "hi"),
+                (Punct, "}"),
+                (Space, " \t"),
+                (Word, "there"),
+                (Newline, "\r\n"),
+                (Word, "x"),
+            ],
+            kinds("\\emph{hi} \tthere\r\nx")
+        );
+        assert_eq!(
+            vec![
+                (Word, "a"),
+                (Space, " "),
+                (BlankLines, "\n \n\r\n\n"),
+                (Space, "  "),
+                (Word, "b"),
+                (Space, " "),
+                (Newline, "\n"),
+            ],
+            kinds("a \n \n\r\n\n  b \n")
+        );
+        assert_eq!(vec![(Word, "a\rb")], kinds("a\rb"));
+        assert_eq!(Vec::<(RawTokenKind, &str)>::new(), kinds(""));
+    }
+
+    #[test]
+    fn test_lex_positions() {
+        let toks = lex(Span::new("a\n\nb c"));
+        let positions: Vec<_> = toks
+            .iter()
+            .map(|tok| (tok.span.location_offset(), tok.span.location_line()))
+            .collect();
+        assert_eq!(vec![(0, 1), (1, 1), (3, 3), (4, 3), (5, 3)], positions);
+    }
+
+    #[test]
+    fn test_lex_lines() {
+        let lines: Vec<Vec<_>> = lex_lines(Span::new("a b\n\n\\c\nd"))
+            .map(|line| line.iter().map(|tok| *tok.span.fragment()).collect())
+            .collect();
+        assert_eq!(
+            vec![
+                vec!["a", " ", "b", "\n\n"],
+                vec!["\\", "c", "\n"],
+                vec!["d"]
+            ],
+            lines
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_lex_invariants(input in "(\\PC|[ \t\r\n\\\\{}])*") {
+            let toks = lex(Span::new(&input));
+            let mut offset = 0;
+            for tok in &toks {
+                prop_assert!(!tok.span.fragment().is_empty());
+                prop_assert_eq!(offset, tok.span.location_offset());
+                offset += tok.span.fragment().len();
+            }
+            let joined: String = toks.iter().map(|tok| *tok.span.fragment()).collect();
+            prop_assert_eq!(&input, &joined);
+            for pair in toks.windows(2) {
+                let (a, b) = (pair[0].kind, pair[1].kind);
+                prop_assert!(a != b || a == Punct, "Adjacent {:?} tokens", a);
+                let ending = |kind| matches!(kind, Newline | BlankLines);
+                prop_assert!(!(ending(a) && ending(b)), "Unmerged line endings");
+            }
+            for (i, tok) in toks.iter().enumerate() {
+                if tok.kind == Space && i > 0 && i + 1 < toks.len() {
+                    let ending = |kind| matches!(kind, Newline | BlankLines);
+                    prop_assert!(
+                        !(ending(toks[i - 1].kind) && ending(toks[i + 1].kind)),
+                        "Unmerged blank line"
+                    );
+                }
+            }
+            let flattened: Vec<_> = lex_lines(Span::new(&input)).flatten().collect();
+            prop_assert_eq!(toks, flattened);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use super::parse_util::{is_bidi_control, is_stray_control};
+use super::Source;
+use crate::cmd::{Warning, WarningKind};
+
+/// Check a source for characters which are likely mistakes or attempts to hide
+/// content, such as bidirectional formatting characters or stray control
+/// characters.
+pub fn lint_source(src: &Source) -> Vec<Warning> {
+    src.char_indices()
+        .filter_map(|(offset, c)| {
+            let (kind, what) = if is_bidi_control(c) {
+                (
+                    WarningKind::BidiControl,
+                    "Bidirectional formatting character",
+                )
+            } else if is_stray_control(c) {
+                (WarningKind::ControlCharacter, "Control character")
+            } else {
+                return None;
+            };
+            Some(
+                Warning::new(kind, format!("{} U+{:04X} in source", what, c as u32))
+                    .at(Some(src.offset_position(offset))),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::parse::SourcePosition;
+
+    #[test]
+    fn test_lint_source() {
+        let src = Source::new("\u{feff}ok\u{200d}\x0c\nab\u{202e}cd\x07".into());
+        assert_eq!(
+            vec![
+                Warning::new(
+                    WarningKind::BidiControl,
+                    "Bidirectional formatting character U+202E in source".into()
+                )
+                .at(Some(SourcePosition { line: 2, column: 3 })),
+                Warning::new(
+                    WarningKind::ControlCharacter,
+                    "Control character U+0007 in source".into()
+                )
+                .at(Some(SourcePosition { line: 2, column: 6 })),
+            ],
+            lint_source(&src)
+        );
+    }
+}
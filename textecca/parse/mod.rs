@@ -7,6 +7,9 @@ use nom_locate::LocatedSpan;
 mod arena;
 mod cmd;
 mod default_parser;
+mod format;
+mod lex;
+mod lint;
 pub(crate) mod parse_util;
 mod ucd_tables;
 
@@ -17,6 +20,9 @@ mod test_util;
 pub use arena::*;
 pub use cmd::*;
 pub use default_parser::*;
+pub use format::*;
+pub use lex::*;
+pub use lint::*;
 
 /// A region of input.
 ///
@@ -49,9 +55,9 @@ impl<'i> From<Command<'i>> for Token<'i> {
     }
 }
 
-/// A function transforming a stream of `RawToken`s into a sequence of `Token`s;
-/// that is, parsers decide what delimits a command and how to parse command
-/// arguments. In the future, parsers will also decide how to parse sub-blocks.
+/// A function transforming a stream of `RawToken`s (see `lex`) into a sequence
+/// of `Token`s; that is, parsers decide what delimits a command and how to
+/// parse command arguments. In the future, parsers will also decide how to parse sub-blocks.
 ///
 /// This makes textecca's markup language highly flexible, so care must be taken
 /// to make parsers that aren't confusing and don't behave unexpectedly.
@@ -36,13 +36,27 @@ where
 }
 
 /// Succeeds if there's no remaining input, errors otherwise.
-pub fn eof<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span, (), E> {
+pub fn eof<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span<'a>, (), E> {
     not(take_bytes(1usize))(i)
 }
 
-/// Parses a newline, i.e. `\r\n` or `\n`.
-pub fn newline<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Span, E> {
-    line_ending(i)
+/// Parses a newline: `\r\n`, `\n`, a lone `\r`, or a U+2028 LINE SEPARATOR.
+pub fn newline<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span<'i>, Span<'i>, E> {
+    alt((line_ending, tag("\r"), tag("\u{2028}")))(i)
+}
+
+/// Parses a U+2029 PARAGRAPH SEPARATOR, which separates paragraphs by itself,
+/// like a blank line.
+pub fn paragraph_separator<'i, E: ParseError<Span<'i>>>(
+    i: Span<'i>,
+) -> IResult<Span<'i>, Span<'i>, E> {
+    tag("\u{2029}")(i)
+}
+
+/// True if `c` is part of a newline (see `newline`) or a paragraph separator
+/// (see `paragraph_separator`); text never contains these characters.
+pub fn is_line_break(c: char) -> bool {
+    matches!(c, '\n' | '\r' | '\u{2028}' | '\u{2029}')
 }
 
 /// True if `c` is of [category] `N`.
@@ -53,7 +67,7 @@ pub fn is_number(c: char) -> bool {
 }
 
 /// Takes a string of at least 1 consecutive `N` category codepoints.
-pub fn take_number1<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Span, E> {
+pub fn take_number1<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span<'i>, Span<'i>, E> {
     take_while1(is_number)(i)
 }
 
@@ -65,7 +79,9 @@ pub fn is_punctuation(c: char) -> bool {
 }
 
 /// Takes a string of at least 1 consecutive `P` category codepoints.
-pub fn take_punctuation1<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Span, E> {
+pub fn take_punctuation1<'i, E: ParseError<Span<'i>>>(
+    i: Span<'i>,
+) -> IResult<Span<'i>, Span<'i>, E> {
     take_while1(is_punctuation)(i)
 }
 
@@ -77,7 +93,7 @@ pub fn is_symbol(c: char) -> bool {
 }
 
 /// Takes a string of at least 1 consecutive `S` category codepoints.
-pub fn take_symbol1<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Span, E> {
+pub fn take_symbol1<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span<'i>, Span<'i>, E> {
     take_while1(is_symbol)(i)
 }
 
@@ -89,7 +105,7 @@ pub fn is_letter(c: char) -> bool {
 }
 
 /// Takes a string of at least 1 consecutive `S` category codepoints.
-pub fn take_letter1<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Span, E> {
+pub fn take_letter1<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span<'i>, Span<'i>, E> {
     take_while1(is_letter)(i)
 }
 
@@ -101,29 +117,57 @@ pub fn is_mark(c: char) -> bool {
 }
 
 /// Takes a string of at least 1 consecutive `S` category codepoints.
-pub fn take_mark1<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Span, E> {
+pub fn take_mark1<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span<'i>, Span<'i>, E> {
     take_while1(is_mark)(i)
 }
 
-/// True if `c` is of [category] `Zs` or a tab (`'\t'`).
+/// True if `c` is of [category] `Zs`, a tab (`'\t'`), or a control space (see
+/// `is_control_space`).
 ///
 /// [category]: https://unicode.org/reports/tr44/#General_Category_Values
 pub fn is_inline_space(c: char) -> bool {
-    c == ' ' || c == '\t' || general_category::SPACE_SEPARATOR.contains_char(c)
+    c == ' '
+        || c == '\t'
+        || is_control_space(c)
+        || general_category::SPACE_SEPARATOR.contains_char(c)
+}
+
+/// True if `c` is a form feed or vertical tab, which are treated as inline
+/// space.
+pub fn is_control_space(c: char) -> bool {
+    c == '\x0b' || c == '\x0c'
+}
+
+/// True if `c` is a [bidirectional formatting character][bidi], which can make
+/// text display in a different order than it's parsed.
+///
+/// [bidi]: https://unicode.org/reports/tr9/#Explicit_Directional_Formatting_Characters
+pub fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{061c}' | '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+/// True if `c` is a control character with no meaning in textecca source; that
+/// is, a C0 or C1 control other than a tab, line ending, or control space.
+pub fn is_stray_control(c: char) -> bool {
+    c.is_control() && !(c == '\t' || c == '\n' || c == '\r' || is_control_space(c))
 }
 
 /// Takes a string of at least 1 consecutive `Zs` category codepoints.
-pub fn take_inline_space1<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Span, E> {
+pub fn take_inline_space1<'i, E: ParseError<Span<'i>>>(
+    i: Span<'i>,
+) -> IResult<Span<'i>, Span<'i>, E> {
     take_while1(is_inline_space)(i)
 }
 
 /// Takes a string of at least 1 consecutive non-`Zs` category codepoints.
-pub fn take_not_inline_space1<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Span, E> {
+pub fn take_not_inline_space1<'i, E: ParseError<Span<'i>>>(
+    i: Span<'i>,
+) -> IResult<Span<'i>, Span<'i>, E> {
     take_while1(|c| !is_inline_space(c))(i)
 }
 
 /// Succeeds if the next character is not whitespace.
-pub fn peek_printing_char<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, (), E> {
+pub fn peek_printing_char<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span<'i>, (), E> {
     not(verify(anychar, |c| is_inline_space(*c)))(i)
 }
 
@@ -143,7 +187,7 @@ pub fn is_xid_continue(c: char) -> bool {
 
 /// Takes a string of 1 `XID_Start` codepoint followed by any number of
 /// `XID_Continue` codepoints.
-pub fn take_xid<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Span, E> {
+pub fn take_xid<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span<'i>, Span<'i>, E> {
     recognize(pair(
         verify(anychar, |c| is_xid_start(*c)),
         take_while(is_xid_continue),
@@ -158,6 +202,9 @@ pub fn is_ident_start(c: char) -> bool {
 /// True if `c` is a valid second or later character of an identifier.
 pub fn is_ident_continue(c: char) -> bool {
     is_xid_continue(c)
+        // Joiners are needed to spell some words; see UAX #31, section 2.3.
+        || c == '\u{200c}'
+        || c == '\u{200d}'
         || (is_symbol(c) && !"=|$".contains(c))
         || "-".contains(c)
         || (general_category::OTHER_PUNCTUATION.contains_char(c) && !"\"',\\%".contains(c))
@@ -165,7 +212,7 @@ pub fn is_ident_continue(c: char) -> bool {
 
 /// Takes a string of 1 codepoint matching `is_ident_start` followed by any
 /// number of codepoints matching `is_ident_continue`.
-pub fn take_ident<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Span, E> {
+pub fn take_ident<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span<'i>, Span<'i>, E> {
     recognize(pair(
         verify(anychar, |c| is_ident_start(*c)),
         take_while(is_ident_continue),
@@ -173,7 +220,7 @@ pub fn take_ident<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Spa
 }
 
 /// Returns the slice up to the next Unicode word boundary.
-pub fn next_word_bound<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Span, E> {
+pub fn next_word_bound<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span<'i>, Span<'i>, E> {
     match i.fragment().split_word_bounds().next() {
         Some(chunk) => Ok((i.slice(chunk.len()..), i.slice(..chunk.len()))),
         // TODO: Should this be `Incomplete` instead?
@@ -182,7 +229,7 @@ pub fn next_word_bound<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span
 }
 
 /// Returns the slice up to the next EGC boundary.
-pub fn next_egc_bound<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span, Span, E> {
+pub fn next_egc_bound<'i, E: ParseError<Span<'i>>>(i: Span<'i>) -> IResult<Span<'i>, Span<'i>, E> {
     match i.fragment().grapheme_indices(/* extended = */ true).next() {
         Some((_, chunk)) => Ok((i.slice(chunk.len()..), i.slice(..chunk.len()))),
         // TODO: Should this be `Incomplete` instead?
@@ -204,6 +251,30 @@ mod test {
         assert!(!is_inline_space('X'));
     }
 
+    #[test]
+    fn test_control_chars() {
+        assert!(is_inline_space('\x0c')); // form feed
+        assert!(is_inline_space('\x0b')); // vertical tab
+        assert!(!is_stray_control('\x0c'));
+        assert!(!is_stray_control('\t'));
+        assert!(!is_stray_control('\n'));
+        assert!(is_stray_control('\x00'));
+        assert!(is_stray_control('\x7f'));
+        assert!(is_stray_control('\u{85}'));
+        assert!(!is_inline_space('\u{200b}'));
+        assert!(is_bidi_control('\u{202e}'));
+        assert!(is_bidi_control('\u{2066}'));
+        assert!(!is_bidi_control('a'));
+    }
+
+    #[test]
+    fn test_ident_joiner() {
+        let input = "\u{0915}\u{094d}\u{200d}\u{0937} x";
+        let (rest, ident) = take_ident::<()>(Span::new(input)).unwrap();
+        assert_eq!(" x", *rest.fragment());
+        assert_eq!("\u{0915}\u{094d}\u{200d}\u{0937}", *ident.fragment());
+    }
+
     #[test]
     fn test_symbol() {
         assert!(is_symbol('$'));
@@ -30,22 +30,22 @@ impl<'i> Input<'i> {
     }
 
     /// This fragment as a Span, i.e. with offset 0.
-    pub fn as_span(&self) -> Span {
+    pub fn as_span(&self) -> Span<'i> {
         self.span
     }
 
     /// The Span at this fragment's end.
-    pub fn eof(&self) -> Span {
+    pub fn eof(&self) -> Span<'i> {
         self.span.slice(self.span.input_len()..)
     }
 
     /// Get a span from a given offset and length.
-    pub fn offset_len(&self, offset: usize, length: usize) -> Span {
+    pub fn offset_len(&self, offset: usize, length: usize) -> Span<'i> {
         self.span.slice(offset..offset + length)
     }
 
     /// Get a span from a given offset and substring.
-    pub fn offset(&self, offset: usize, fragment: &'static str) -> Span {
+    pub fn offset(&self, offset: usize, fragment: &'static str) -> Span<'i> {
         let bad_offset = offset > self.span.fragment().len()
             || offset + fragment.len() > self.span.fragment().len();
         let ret = if bad_offset {
@@ -93,7 +93,7 @@ impl<'i> Input<'i> {
         ret.unwrap()
     }
 
-    pub fn slice<R>(&self, range: R) -> Span
+    pub fn slice<R>(&self, range: R) -> Span<'i>
     where
         Span<'i>: Slice<R>,
     {
@@ -101,13 +101,13 @@ impl<'i> Input<'i> {
     }
 }
 
-impl<'i> Into<&'i str> for Input<'i> {
-    fn into(self) -> &'i str {
-        self.span.fragment()
+impl<'i> From<Input<'i>> for &'i str {
+    fn from(input: Input<'i>) -> Self {
+        input.span.fragment()
     }
 }
 
-fn assert_parse_err(e: impl fmt::Debug) {
+fn assert_parse_err(e: (Span<'_>, ErrorKind)) {
     panic!("Unexpected Err(nom::Err::Failure({:#?})).", e);
 }
 
@@ -115,6 +115,9 @@ fn assert_parse_incomplete(needed: nom::Needed) {
     panic!("Unexpected Err(nom::Err::Incomplete({:#?})).", needed);
 }
 
+/// A check run on a parser's output.
+type Check<'i, T> = Box<dyn Fn(&Input<'i>, T)>;
+
 #[derive(TypedBuilder)]
 pub struct AssertParse<'i, Parser, O> {
     parser: Parser,
@@ -123,22 +126,23 @@ pub struct AssertParse<'i, Parser, O> {
     all_consuming: bool,
 
     #[builder(default=Box::new(|_i, _output| ()))]
-    ok: Box<dyn Fn(&Input, O) -> ()>,
+    ok: Check<'i, O>,
 
-    #[builder(default=Box::new(|err| assert_parse_err(err)))]
-    err: Box<dyn Fn((Span<'i>, ErrorKind)) -> ()>,
+    #[builder(default=Box::new(assert_parse_err))]
+    err: Box<dyn Fn((Span<'i>, ErrorKind))>,
 
-    #[builder(default=Box::new(|needed| assert_parse_incomplete(needed)))]
-    incomplete: Box<dyn Fn(nom::Needed) -> ()>,
+    #[builder(default=Box::new(assert_parse_incomplete))]
+    incomplete: Box<dyn Fn(nom::Needed)>,
 
     #[builder(default=Box::new(|_i, _rest| ()))]
-    rest: Box<dyn Fn(&Input, Span<'i>) -> ()>,
+    rest: Check<'i, Span<'i>>,
 }
 
 impl<'i, Parser, O> AssertParse<'i, Parser, O>
 where
     Parser: Fn(Span<'i>) -> IResult<Span<'i>, O, (Span<'i>, ErrorKind)>,
 {
+    #[allow(clippy::new_ret_no_self, clippy::type_complexity)]
     pub fn new(
         parser: Parser,
     ) -> AssertParseBuilder<'i, ((Parser,), (), (), (), (), ()), Parser, O> {
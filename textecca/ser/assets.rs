@@ -0,0 +1,426 @@
+//! Copying the local files a document refers to alongside its output, or
+//! embedding them in it.
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use derivative::Derivative;
+use thiserror::Error;
+
+use crate::cmd::{ResourceError, ResourceResolver};
+use crate::doc::is_url;
+use crate::ser::fnv1a;
+
+/// Collects the local files a serializer refers to, like images and
+/// stylesheets, so they can be copied alongside the output; see
+/// `HtmlOptions::assets`.
+///
+/// Each file is named after a hash of its content (which is the same in every
+/// build), so files with the same content are only copied once, and
+/// references to a file are rewritten to `{prefix}/{name}`. If two files with
+/// different content have the same hash, the second is named `{hash}-1`, and
+/// so on.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct AssetCollector {
+    #[derivative(Debug = "ignore")]
+    resolver: Rc<dyn ResourceResolver>,
+    prefix: String,
+    /// Asset names to their paths, relative to the project root.
+    assets: BTreeMap<String, PathBuf>,
+    /// Paths, as they were referred to, to the URLs of their copies.
+    urls: HashMap<String, String>,
+}
+
+impl AssetCollector {
+    /// Create a collector which reads files with the given resolver, and
+    /// refers to copied files with the given prefix, e.g. `"assets"`.
+    pub fn new(resolver: Rc<dyn ResourceResolver>, prefix: impl Into<String>) -> Self {
+        Self {
+            resolver,
+            prefix: prefix.into(),
+            assets: Default::default(),
+            urls: Default::default(),
+        }
+    }
+
+    /// Record a reference to a file, given relative to the project root, and
+    /// return the URL to refer to its copy with.
+    pub fn add(&mut self, path: &str) -> Result<String, ResourceError> {
+        if let Some(url) = self.urls.get(path) {
+            return Ok(url.clone());
+        }
+        let content = self.resolver.read(Path::new(path))?;
+        let hash = fnv1a(&content);
+        let ext = match Path::new(path).extension() {
+            Some(ext) => format!(".{}", ext.to_string_lossy()),
+            None => String::new(),
+        };
+        let mut collisions = 0;
+        let name = loop {
+            let name = match collisions {
+                0 => format!("{:016x}{}", hash, ext),
+                n => format!("{:016x}-{}{}", hash, n, ext),
+            };
+            match self.assets.get(&name) {
+                Some(other) if self.resolver.read(other)? != content => collisions += 1,
+                _ => break name,
+            }
+        };
+        let url = match self.prefix.trim_end_matches('/') {
+            "" => name.clone(),
+            prefix => format!("{}/{}", prefix, name),
+        };
+        self.assets.insert(name, path.into());
+        self.urls.insert(path.to_owned(), url.clone());
+        Ok(url)
+    }
+
+    /// The names of the collected files' copies, and the paths of the
+    /// originals relative to the project root, sorted by name.
+    pub fn assets(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.assets
+            .iter()
+            .map(|(name, path)| (name.as_str(), path.as_path()))
+    }
+
+    /// Copy the collected files into `dir`, creating it if it doesn't exist.
+    pub fn copy_to(&self, dir: &Path) -> Result<(), ResourceError> {
+        let io_error = |path: &Path| {
+            let path = path.to_owned();
+            move |error| ResourceError::Io { path, error }
+        };
+        fs::create_dir_all(dir).map_err(io_error(dir))?;
+        for (name, path) in self.assets() {
+            let dest = dir.join(name);
+            fs::write(&dest, self.resolver.read(path)?).map_err(io_error(&dest))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "html")]
+/// Limits on the size of the files embedded with `HtmlOptions::embed_assets`;
+/// files over the limits are referred to by path instead, with a warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmbedLimits {
+    /// The largest file embedded, in bytes; by default, 1 MiB.
+    pub max_asset_size: usize,
+    /// The most bytes embedded in one document, in total; by default, 8 MiB.
+    pub max_total_size: usize,
+}
+
+#[cfg(feature = "html")]
+impl Default for EmbedLimits {
+    fn default() -> Self {
+        Self {
+            max_asset_size: 1 << 20,
+            max_total_size: 8 << 20,
+        }
+    }
+}
+
+#[cfg(feature = "html")]
+/// Reads the local files a serializer refers to, like images and
+/// stylesheets, so they can be embedded in the output; see
+/// `HtmlOptions::embed_assets`.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct AssetEmbedder {
+    #[derivative(Debug = "ignore")]
+    resolver: Rc<dyn ResourceResolver>,
+    limits: EmbedLimits,
+    /// The number of bytes embedded so far.
+    total_size: usize,
+}
+
+#[cfg(feature = "html")]
+impl AssetEmbedder {
+    /// Create an embedder which reads files with the given resolver.
+    pub fn new(resolver: Rc<dyn ResourceResolver>, limits: EmbedLimits) -> Self {
+        Self {
+            resolver,
+            limits,
+            total_size: 0,
+        }
+    }
+
+    /// The number of bytes embedded so far.
+    pub fn total_size(&self) -> usize {
+        self.total_size
+    }
+
+    /// Read a file to embed, given relative to the project root, counting it
+    /// towards the limits.
+    fn read(&mut self, path: &Path) -> Result<Vec<u8>, EmbedError> {
+        let content = self.resolver.read(path)?;
+        if content.len() > self.limits.max_asset_size {
+            return Err(EmbedError::TooLarge {
+                size: content.len(),
+                limit: self.limits.max_asset_size,
+            });
+        }
+        if self.total_size + content.len() > self.limits.max_total_size {
+            return Err(EmbedError::TotalTooLarge {
+                limit: self.limits.max_total_size,
+            });
+        }
+        self.total_size += content.len();
+        Ok(content)
+    }
+
+    /// A `data:` URI with the content of a file, given relative to the
+    /// project root. The media type is guessed from the file's extension; see
+    /// `media_type`.
+    pub fn data_uri(&mut self, path: &str) -> Result<String, EmbedError> {
+        let path = Path::new(path);
+        let content = self.read(path)?;
+        Ok(format!(
+            "data:{};base64,{}",
+            media_type(path),
+            base64::encode(&content)
+        ))
+    }
+
+    /// The content of a stylesheet, given relative to the project root, with
+    /// the local files it refers to with `url()` (e.g. fonts and images)
+    /// embedded as `data:` URIs.
+    ///
+    /// References which can't be embedded are left as they are, and returned
+    /// along with the stylesheet, with their errors.
+    pub fn stylesheet(
+        &mut self,
+        path: &str,
+    ) -> Result<(String, Vec<(String, EmbedError)>), EmbedError> {
+        let css = String::from_utf8(self.read(Path::new(path))?)
+            .map_err(|_| ResourceError::Utf8(path.into()))?;
+        let mut ret = String::with_capacity(css.len());
+        let mut errors = Vec::new();
+        let mut rest = &css[..];
+        while let Some((start, url, end)) = find_css_url(rest) {
+            ret.push_str(&rest[..start]);
+            let embedded = if is_url(url) || url.starts_with('#') {
+                None
+            } else {
+                let res = self
+                    .resolver
+                    .resolve(Path::new(path), url)
+                    .map_err(EmbedError::from)
+                    .and_then(|resolved| self.data_uri(&resolved.to_string_lossy()));
+                match res {
+                    Ok(uri) => Some(uri),
+                    Err(err) => {
+                        errors.push((url.to_owned(), err));
+                        None
+                    }
+                }
+            };
+            match embedded {
+                Some(uri) => ret.push_str(&format!("url(\"{}\")", uri)),
+                None => ret.push_str(&rest[start..end]),
+            }
+            rest = &rest[end..];
+        }
+        ret.push_str(rest);
+        Ok((ret, errors))
+    }
+}
+
+#[cfg(feature = "html")]
+/// Find the first `url()` in some CSS, returning the offsets of its start
+/// and end and the URL it contains, without quotes.
+fn find_css_url(css: &str) -> Option<(usize, &str, usize)> {
+    let mut from = 0;
+    loop {
+        let start = from + css[from..].to_ascii_lowercase().find("url(")?;
+        let inner = start + "url(".len();
+        let arg = css[inner..].trim_start();
+        let arg_start = css.len() - arg.len();
+        let (url, after) = match arg.chars().next() {
+            Some(quote @ '"') | Some(quote @ '\'') => match arg[1..].find(quote) {
+                Some(len) => (&arg[1..1 + len], arg_start + len + 2),
+                None => return None,
+            },
+            _ => match arg.find(')') {
+                Some(len) => (arg[..len].trim_end(), arg_start + len),
+                None => return None,
+            },
+        };
+        match css[after..].trim_start().strip_prefix(')') {
+            Some(rest) => return Some((start, url, css.len() - rest.len())),
+            None => from = inner,
+        }
+    }
+}
+
+/// The media type of a file, guessed from its extension, e.g. `image/png`;
+/// `application/octet-stream` if it's not known.
+pub fn media_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "css" => "text/css",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(feature = "html")]
+/// An error embedding a file; see `AssetEmbedder`.
+#[derive(Debug, Error)]
+pub enum EmbedError {
+    /// The file couldn't be found or read.
+    #[error(transparent)]
+    Resource(#[from] ResourceError),
+
+    /// The file is larger than `EmbedLimits::max_asset_size`.
+    #[error("It's {size} bytes, over the limit of {limit} bytes per file")]
+    TooLarge {
+        /// The file's size, in bytes.
+        size: usize,
+        /// The limit.
+        limit: usize,
+    },
+
+    /// Embedding the file would go over `EmbedLimits::max_total_size`.
+    #[error("The document's embedded files would be over the limit of {limit} bytes")]
+    TotalTooLarge {
+        /// The limit.
+        limit: usize,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::cmd::MemoryResolver;
+    use crate::ser::test_util::TempDir;
+
+    fn collector() -> AssetCollector {
+        let mut resolver = MemoryResolver::new();
+        resolver.insert("a.png", "image");
+        resolver.insert("img/b.png", "image");
+        resolver.insert("style.css", "p {}");
+        AssetCollector::new(Rc::new(resolver), "assets/")
+    }
+
+    #[test]
+    fn test_add() {
+        let mut assets = collector();
+        let a = assets.add("a.png").unwrap();
+        assert!(a.starts_with("assets/"));
+        assert!(a.ends_with(".png"));
+        assert_eq!(a, assets.add("img/b.png").unwrap());
+        let css = assets.add("style.css").unwrap();
+        assert_ne!(a, css);
+        assert_eq!(2, assets.assets().count());
+        assert!(matches!(
+            assets.add("missing.png"),
+            Err(ResourceError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_add_collision() {
+        let mut resolver = MemoryResolver::new();
+        resolver.insert("a.png", "image");
+        resolver.insert("other.png", "another image");
+        let mut assets = AssetCollector::new(Rc::new(resolver), "");
+        // Names are the same in every build.
+        let name = format!("{:016x}.png", fnv1a(b"image"));
+        assert_eq!("2ab612888528489a.png", name);
+        // As if `other.png` had the same hash as `a.png`.
+        assets.assets.insert(name.clone(), "other.png".into());
+        assert_eq!(
+            format!("{:016x}-1.png", fnv1a(b"image")),
+            assets.add("a.png").unwrap()
+        );
+        assert_eq!(2, assets.assets().count());
+    }
+
+    #[test]
+    #[cfg(feature = "html")]
+    fn test_data_uri() {
+        let mut resolver = MemoryResolver::new();
+        resolver.insert("a.png", "image");
+        resolver.insert("big.png", vec![0; 11]);
+        let limits = EmbedLimits {
+            max_asset_size: 10,
+            max_total_size: 12,
+        };
+        let mut embedder = AssetEmbedder::new(Rc::new(resolver), limits);
+        assert_eq!(
+            "data:image/png;base64,aW1hZ2U=",
+            embedder.data_uri("a.png").unwrap()
+        );
+        assert!(matches!(
+            embedder.data_uri("big.png"),
+            Err(EmbedError::TooLarge {
+                size: 11,
+                limit: 10
+            })
+        ));
+        assert_eq!(5, embedder.total_size());
+        embedder.data_uri("a.png").unwrap();
+        assert!(matches!(
+            embedder.data_uri("a.png"),
+            Err(EmbedError::TotalTooLarge { limit: 12 })
+        ));
+        assert!(matches!(
+            embedder.data_uri("missing.png"),
+            Err(EmbedError::Resource(ResourceError::NotFound(_)))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "html")]
+    fn test_embed_stylesheet() {
+        let mut resolver = MemoryResolver::new();
+        resolver.insert(
+            "css/style.css",
+            "@font-face { src: url(fonts/a.woff2) format('woff2'), URL( \"b.ttf\" ); }\n\
+             p { background: url('https://example.com/c.png'); }",
+        );
+        resolver.insert("css/fonts/a.woff2", "font");
+        let mut embedder = AssetEmbedder::new(Rc::new(resolver), Default::default());
+        let (css, errors) = embedder.stylesheet("css/style.css").unwrap();
+        assert_eq!(
+            "@font-face { src: url(\"data:font/woff2;base64,Zm9udA==\") format('woff2'), \
+             URL( \"b.ttf\" ); }\n\
+             p { background: url('https://example.com/c.png'); }",
+            css
+        );
+        assert_eq!(
+            vec!["b.ttf"],
+            errors
+                .iter()
+                .map(|(url, _)| url.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_copy_to() {
+        let dir = TempDir::new("assets");
+        let mut assets = collector();
+        let url = assets.add("style.css").unwrap();
+        assets.copy_to(&dir).unwrap();
+        let copied = fs::read_to_string(dir.join(url.trim_start_matches("assets/"))).unwrap();
+        assert_eq!("p {}", copied);
+    }
+}
@@ -0,0 +1,392 @@
+//! Utilities shared between serializers.
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::doc::{self, Block, BlockInner, Collections, DocNode, Heading};
+
+/// Percent-encode a string for use as a URL fragment (the part after the `#`),
+/// e.g. to link to an element by its `id`.
+///
+/// Characters in the WHATWG [fragment percent-encode set][set] (controls,
+/// space, `"`, `<`, `>`, `` ` ``, and all non-ASCII characters) are encoded, as
+/// is `%` itself so that decoding the result always gives back `fragment`.
+///
+/// [set]: https://url.spec.whatwg.org/#fragment-percent-encode-set
+pub fn percent_encode_fragment(fragment: &str) -> String {
+    let mut ret = String::with_capacity(fragment.len());
+    for c in fragment.chars() {
+        if c.is_ascii_control() || !c.is_ascii() || matches!(c, ' ' | '"' | '<' | '>' | '`' | '%') {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                write!(ret, "%{:02X}", byte).unwrap();
+            }
+        } else {
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+/// Escape `text` by replacing each character for which `escape` returns a
+/// replacement; other characters are copied unchanged.
+pub fn escape_with<'a>(text: &str, escape: impl Fn(char) -> Option<&'a str>) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match escape(c) {
+            Some(replacement) => ret.push_str(replacement),
+            None => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// Escape text for LaTeX, so that it's typeset literally.
+///
+/// The special characters `# $ % & _ { }` are escaped with backslashes, `~`,
+/// `^`, and `\` are replaced with the corresponding text commands, and the
+/// `--` and `---` dash ligatures are broken up with `{}`. `<`, `>`, and `|`
+/// are also replaced with text commands, since the default OT1 font encoding
+/// typesets them as `¡`, `¿`, and `—`.
+pub fn latex_escape(text: &str) -> String {
+    let escaped = escape_with(text, |c| {
+        Some(match c {
+            '#' => r"\#",
+            '$' => r"\$",
+            '%' => r"\%",
+            '&' => r"\&",
+            '_' => r"\_",
+            '{' => r"\{",
+            '}' => r"\}",
+            '~' => r"\textasciitilde{}",
+            '^' => r"\textasciicircum{}",
+            '\\' => r"\textbackslash{}",
+            '<' => r"\textless{}",
+            '>' => r"\textgreater{}",
+            '|' => r"\textbar{}",
+            _ => return None,
+        })
+    });
+    let mut ret = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars().peekable();
+    while let Some(c) = chars.next() {
+        ret.push(c);
+        if c == '-' && chars.peek() == Some(&'-') {
+            ret.push_str("{}");
+        }
+    }
+    ret
+}
+
+/// Escape text for Markdown (CommonMark), so that it's rendered literally.
+///
+/// Only punctuation which could have a meaning where it appears is
+/// backslash-escaped, so the output stays readable:
+///
+/// - `` \ ` * _ [ ] < $ `` everywhere, including `$`, which delimits math in
+///   Markdown with math extensions;
+/// - `- + # > = ~` at the start of a line, where they could start a list item,
+///   heading, block quote, or code fence;
+/// - `.` and `)` after digits at the start of a line, where they could start
+///   an ordered list item;
+/// - `!` before `[`, where it would start an image;
+/// - `&` where it would start an entity reference, like `&amp;`.
+///
+/// `line_start` says whether `text` is written at the start of a line; the
+/// text after each newline in it is too.
+pub fn markdown_escape(text: &str, line_start: bool) -> String {
+    let mut ret = String::with_capacity(text.len());
+    let mut line_start = line_start;
+    // Whether we're in the run of digits at the start of a line.
+    let mut leading_digits = false;
+    for (i, c) in text.char_indices() {
+        let rest = &text[i + c.len_utf8()..];
+        let escape = match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '$' => true,
+            '-' | '+' | '#' | '>' | '=' | '~' => line_start,
+            '.' | ')' => leading_digits,
+            '!' => rest.starts_with('['),
+            '&' => starts_entity(rest),
+            _ => false,
+        };
+        if escape {
+            ret.push('\\');
+        }
+        ret.push(c);
+        leading_digits = c.is_ascii_digit() && (line_start || leading_digits);
+        line_start = c == '\n';
+    }
+    ret
+}
+
+/// Does `text`, just after an `&`, look like the rest of an entity or numeric
+/// character reference, like `amp;` or `#x26;`?
+fn starts_entity(text: &str) -> bool {
+    let name = text.strip_prefix('#').unwrap_or(text);
+    let len = name
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(name.len());
+    len > 0 && name[len..].starts_with(';')
+}
+
+/// Escape text for roff (e.g. man pages), so that it's typeset literally.
+///
+/// Backslashes are written as `\e` and hyphens as `\-`, and lines starting
+/// with a control character (`.` or `'`) are prefixed with the zero-width
+/// `\&` so they aren't read as requests.
+pub fn roff_escape(text: &str) -> String {
+    let escaped = escape_with(text, |c| match c {
+        '\\' => Some(r"\e"),
+        '-' => Some(r"\-"),
+        _ => None,
+    });
+    let mut ret = String::with_capacity(escaped.len());
+    for (i, line) in escaped.split('\n').enumerate() {
+        if i > 0 {
+            ret.push('\n');
+        }
+        if line.starts_with('.') || line.starts_with('\'') {
+            ret.push_str(r"\&");
+        }
+        ret.push_str(line);
+    }
+    ret
+}
+
+/// The id to write for a heading: its explicit id (see `doc::ID_KEY`) as it
+/// is, or else a slug of its text (see `Heading::anchor`) with `-1`, `-2`,
+/// etc. appended if another heading already has that id. Headings with no
+/// text get `section`, `section-1`, and so on.
+///
+/// `ids` counts the headings which have wanted each id so far. Explicit ids
+/// reserved with `reserve_heading_ids` are skipped even by headings before
+/// the ones which have them, so only two explicit ids can collide.
+pub(crate) fn heading_id(heading: &Heading, ids: &mut HashMap<String, usize>) -> String {
+    let slug = heading.anchor().into_string();
+    if heading.attrs.contains_key(doc::ID_KEY) {
+        ids.entry(slug.clone()).or_insert(1);
+        return slug;
+    }
+    let count = ids.entry(slug.clone()).or_insert(0);
+    if *count == 0 {
+        *count += 1;
+        return slug;
+    }
+    loop {
+        let count = ids.get_mut(&slug).unwrap();
+        let id = format!("{}-{}", slug, count);
+        *count += 1;
+        if !ids.contains_key(&id) {
+            ids.insert(id.clone(), 1);
+            return id;
+        }
+    }
+}
+
+/// The entries of a document's abstract; see `doc::ABSTRACT_CHANNEL`.
+pub(crate) fn abstract_blocks(collections: &Collections) -> impl Iterator<Item = &[Block]> {
+    collections
+        .get(doc::ABSTRACT_CHANNEL)
+        .into_iter()
+        .flatten()
+        .map(|blocks| &blocks[..])
+}
+
+/// Reserve the explicit ids of the headings in `blocks` for them; see
+/// `heading_id`.
+pub(crate) fn reserve_heading_ids<'d>(
+    blocks: impl IntoIterator<Item = &'d [Block]>,
+    ids: &mut HashMap<String, usize>,
+) {
+    for node in blocks.into_iter().flat_map(doc::nodes) {
+        if let DocNode::Block(Block {
+            inner: BlockInner::Heading(heading),
+            ..
+        }) = node
+        {
+            if heading.attrs.contains_key(doc::ID_KEY) {
+                ids.entry(heading.anchor().into_string()).or_insert(1);
+            }
+        }
+    }
+}
+
+/// A 64-bit FNV-1a hash, which unlike `std`'s hashers is stable across Rust
+/// versions, e.g. for naming files after their content.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    fnv1a_extend(0xcbf2_9ce4_8422_2325, bytes)
+}
+
+/// Continue an FNV-1a hash (see `fnv1a`) with more bytes.
+pub(crate) fn fnv1a_extend(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Typeset the output of `latex_escape` (which only uses a handful of
+    /// commands) back into plain text, panicking on unescaped special
+    /// characters.
+    fn render_latex(latex: &str) -> String {
+        let mut ret = String::new();
+        let mut rest = latex;
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '\\' => {
+                    let commands = [
+                        ("textasciitilde{}", '~'),
+                        ("textasciicircum{}", '^'),
+                        ("textbackslash{}", '\\'),
+                        ("textless{}", '<'),
+                        ("textgreater{}", '>'),
+                        ("textbar{}", '|'),
+                    ];
+                    match commands.iter().find(|(cmd, _)| rest.starts_with(cmd)) {
+                        Some((cmd, c)) => {
+                            ret.push(*c);
+                            rest = &rest[cmd.len()..];
+                        }
+                        None => {
+                            let c = rest.chars().next().expect("Trailing backslash");
+                            assert!("#$%&_{}".contains(c), "Unknown escape \\{}", c);
+                            ret.push(c);
+                            rest = &rest[c.len_utf8()..];
+                        }
+                    }
+                }
+                '{' => {
+                    assert!(rest.starts_with('}'), "Unescaped brace in {:?}", latex);
+                    assert!(ret.ends_with('-'), "Unexpected group in {:?}", latex);
+                    rest = &rest[1..];
+                }
+                '#' | '$' | '%' | '&' | '_' | '}' | '~' | '^' | '<' | '>' | '|' => {
+                    panic!("Unescaped {:?} in {:?}", c, latex)
+                }
+                '-' if rest.starts_with('-') => panic!("Dash ligature in {:?}", latex),
+                c => ret.push(c),
+            }
+        }
+        ret
+    }
+
+    /// Read backslash escapes in the output of `markdown_escape`, panicking on
+    /// Markdown punctuation which should always be escaped.
+    fn render_markdown(markdown: &str) -> String {
+        let mut ret = String::new();
+        let mut chars = markdown.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => ret.push(chars.next().expect("Trailing backslash")),
+                c if "`*_[]<$".contains(c) => {
+                    panic!("Unescaped {:?} in {:?}", c, markdown)
+                }
+                c => ret.push(c),
+            }
+        }
+        ret
+    }
+
+    /// Read the escapes in the output of `roff_escape`, panicking on requests
+    /// and unknown escapes.
+    fn render_roff(roff: &str) -> String {
+        let mut ret = String::new();
+        for (i, line) in roff.split('\n').enumerate() {
+            if i > 0 {
+                ret.push('\n');
+            }
+            assert!(
+                !line.starts_with('.') && !line.starts_with('\''),
+                "Request in {:?}",
+                roff
+            );
+            let mut chars = line.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => match chars.next() {
+                        Some('e') => ret.push('\\'),
+                        Some('-') => ret.push('-'),
+                        Some('&') => {}
+                        c => panic!("Unknown escape {:?} in {:?}", c, roff),
+                    },
+                    '-' => panic!("Unescaped hyphen in {:?}", roff),
+                    c => ret.push(c),
+                }
+            }
+        }
+        ret
+    }
+
+    proptest! {
+        #[test]
+        fn test_latex_escape_round_trips(text in any::<String>()) {
+            prop_assert_eq!(&text, &render_latex(&latex_escape(&text)));
+        }
+
+        #[test]
+        fn test_markdown_escape_round_trips(text in any::<String>()) {
+            prop_assert_eq!(&text, &render_markdown(&markdown_escape(&text, true)));
+        }
+
+        #[test]
+        fn test_roff_escape_round_trips(text in any::<String>()) {
+            prop_assert_eq!(&text, &render_roff(&roff_escape(&text)));
+        }
+    }
+
+    #[test]
+    fn test_latex_escape() {
+        assert_eq!(r"50\% of \$10", latex_escape("50% of $10"));
+        assert_eq!(r"a\_b \{c\} \#1 \& 2", latex_escape("a_b {c} #1 & 2"));
+        assert_eq!(
+            r"\textasciitilde{}\textasciicircum{}\textbackslash{}",
+            latex_escape(r"~^\")
+        );
+        assert_eq!("1-{}-2 -{}-{}-", latex_escape("1--2 ---"));
+        assert_eq!(
+            r"a \textless{}b\textgreater{} \textbar{} c",
+            latex_escape("a <b> | c")
+        );
+    }
+
+    #[test]
+    fn test_markdown_escape() {
+        assert_eq!(r"\*not emph\*", markdown_escape("*not emph*", false));
+        assert_eq!(r"12\. \[x\](y)", markdown_escape("12. [x](y)", true));
+        assert_eq!("12. x", markdown_escape("12. x", false));
+        assert_eq!("café, ok.", markdown_escape("café, ok.", true));
+        assert_eq!(
+            r"\$5-\$10 (or more)!",
+            markdown_escape("$5-$10 (or more)!", true)
+        );
+        assert_eq!(r"\- a - b", markdown_escape("- a - b", true));
+        assert_eq!("- a", markdown_escape("- a", false));
+        assert_eq!("a\n\\# b\n1\\)", markdown_escape("a\n# b\n1)", false));
+        assert_eq!(r"\!\[x\] !x", markdown_escape("![x] !x", false));
+        assert_eq!(
+            r"\&amp; \&#38; & &x",
+            markdown_escape("&amp; &#38; & &x", false)
+        );
+    }
+
+    #[test]
+    fn test_roff_escape() {
+        assert_eq!(r"C:\eWindows", roff_escape(r"C:\Windows"));
+        assert_eq!("\\&.TH x\n\\&'s\nx\\-y", roff_escape(".TH x\n's\nx-y"));
+    }
+
+    #[test]
+    fn test_percent_encode_fragment() {
+        assert_eq!("intro-1", percent_encode_fragment("intro-1"));
+        assert_eq!("%22%3E%3Cscript%3E", percent_encode_fragment("\"><script>"));
+        assert_eq!("50%25%20off", percent_encode_fragment("50% off"));
+        assert_eq!("caf%C3%A9", percent_encode_fragment("café"));
+        assert_eq!("a'b#c", percent_encode_fragment("a'b#c"));
+    }
+}
@@ -0,0 +1,846 @@
+//! Serialization to EPUB 3 ebooks.
+use std::fmt::Write as _;
+use std::io::{Cursor, Write};
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use friendly_html as fh;
+use zip::result::ZipError;
+use zip::write::{FileOptions, ZipWriter};
+use zip::CompressionMethod;
+
+use super::split::{self, Page, SplitTocEntry};
+use super::{HtmlOptions, HtmlSerializer, MathConfig};
+use crate::cmd::{Warning, WarningKind};
+use crate::doc::{self, Doc};
+use crate::ser::{
+    escape_with, fnv1a, fnv1a_extend, InitSerializer, InitSerializerWithOptions, Serializer,
+    SerializerError,
+};
+
+/// The directory in the container holding the package document, navigation
+/// document, and chapters.
+const PACKAGE_DIR: &str = "OEBPS";
+const PACKAGE_FILE: &str = "content.opf";
+const NAV_FILE: &str = "nav.xhtml";
+
+/// Elements without end tags, which are self-closed in XHTML.
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Options for an `EpubSerializer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpubOptions {
+    /// Options for writing the chapters. Math is always written as its TeX
+    /// (with `MathConfig::None`), because readers can't be relied on to run
+    /// scripts, and no table of contents is written, because the navigation
+    /// document takes its place.
+    pub html: HtmlOptions,
+    /// A new chapter starts at each heading (outside of any other block) with
+    /// a level of at most this; see `write_split`.
+    pub chapter_level: i32,
+}
+
+impl Default for EpubOptions {
+    fn default() -> Self {
+        Self {
+            html: Default::default(),
+            chapter_level: 1,
+        }
+    }
+}
+
+/// Serializer to [EPUB 3](https://www.w3.org/TR/epub-33/) ebooks, written as
+/// a single zip container.
+///
+/// The document is split into chapters like `write_split` splits it into
+/// files, and each chapter is written as XHTML by an `HtmlSerializer`, so
+/// content tagged for the `"html"` format is included. The navigation
+/// document lists the headings in the table of contents (see
+/// `Heading::in_toc`).
+///
+/// The package metadata is taken from the `DocMeta` keys `title` (or else the
+/// first chapter's heading), `author`, `date`, `identifier` (or else a hash of
+/// the content), `modified` (an [ISO 8601] UTC timestamp like
+/// `2020-06-01T12:00:00Z`, or else the time in [`SOURCE_DATE_EPOCH`] if it's
+/// set, or else the current time), and `doc::LANG_KEY`.
+///
+/// Images and stylesheets aren't included in the container; use absolute
+/// URLs (which are declared as remote resources), or
+/// `HtmlOptions::embed_assets` for images. Local stylesheets are skipped with
+/// a warning.
+///
+/// [ISO 8601]: https://www.w3.org/TR/NOTE-datetime
+/// [`SOURCE_DATE_EPOCH`]: https://reproducible-builds.org/specs/source-date-epoch/
+pub struct EpubSerializer<W: Write> {
+    writer: W,
+    options: EpubOptions,
+    warnings: Vec<Warning>,
+}
+
+/// A chapter, written as XHTML.
+struct Chapter {
+    file: String,
+    title: String,
+    xhtml: String,
+    /// Whether the chapter refers to remote resources, e.g. images with
+    /// absolute URLs.
+    remote_resources: bool,
+}
+
+impl<W: Write> InitSerializer<W> for EpubSerializer<W> {
+    fn new(writer: W) -> Result<Box<Self>, SerializerError> {
+        Ok(Self::with_options(writer, Default::default()))
+    }
+}
+
+impl<W: Write> InitSerializerWithOptions<W> for EpubSerializer<W> {
+    type Options = EpubOptions;
+
+    fn new_with_options(writer: W, options: EpubOptions) -> Result<Box<Self>, SerializerError> {
+        Ok(Self::with_options(writer, options))
+    }
+}
+
+impl<W: Write> Serializer for EpubSerializer<W> {
+    fn format_name(&self) -> &'static str {
+        "epub"
+    }
+
+    fn write_doc(&mut self, doc: &Doc) -> Result<(), SerializerError> {
+        let lang = doc
+            .meta
+            .get(doc::LANG_KEY)
+            .map(String::as_str)
+            .unwrap_or("und");
+        let mut pages = split::split_pages(doc.content.clone(), self.options.chapter_level);
+        for page in pages.iter_mut() {
+            page.file = format!("{}.xhtml", page.file.trim_end_matches(".html"));
+        }
+        split::link_pages(&mut pages);
+        let title = match doc.meta.get("title") {
+            Some(title) => title.clone(),
+            None => pages
+                .get(1)
+                .map(|page| page.title.clone())
+                .unwrap_or_else(|| "Untitled".to_owned()),
+        };
+        pages[0].title = title.clone();
+        let toc = nav_toc(&pages, doc);
+        let stylesheets = self.remote_stylesheets();
+
+        let mut chapters = Vec::new();
+        let mut collections = doc.collections.clone();
+        let mut ids = split::SplitIds::new(&pages, &doc.collections);
+        for page in pages.iter_mut() {
+            let chapter = Doc {
+                meta: doc.meta.clone(),
+                content: mem::take(&mut page.content),
+                // E.g. the abstract belongs in the first chapter.
+                collections: mem::take(&mut collections),
+            };
+            let body = self.write_chapter(&chapter, &mut ids)?;
+            // The content before the first chapter is often empty.
+            if body.trim().is_empty() {
+                continue;
+            }
+            chapters.push(Chapter {
+                file: page.file.clone(),
+                title: page.title.clone(),
+                xhtml: xhtml_page(&page.title, doc, &stylesheets, &body),
+                remote_resources: !stylesheets.is_empty() || has_remote_resources(&body)?,
+            });
+        }
+        // The spine must list at least one chapter.
+        if chapters.is_empty() {
+            chapters.push(Chapter {
+                file: pages[0].file.clone(),
+                title: title.clone(),
+                xhtml: xhtml_page(&title, doc, &stylesheets, ""),
+                remote_resources: !stylesheets.is_empty(),
+            });
+        }
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        // The mimetype must come first, uncompressed, so the container can be
+        // identified by its first bytes.
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("mimetype", stored).map_err(zip_error)?;
+        zip.write_all(b"application/epub+zip")?;
+        let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file("META-INF/container.xml", deflated)
+            .map_err(zip_error)?;
+        write!(
+            zip,
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                "\n",
+                r#"<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">"#,
+                "\n<rootfiles>\n",
+                r#"<rootfile full-path="{}/{}" media-type="application/oebps-package+xml"/>"#,
+                "\n</rootfiles>\n</container>\n"
+            ),
+            PACKAGE_DIR, PACKAGE_FILE
+        )?;
+        zip.start_file(format!("{}/{}", PACKAGE_DIR, PACKAGE_FILE), deflated)
+            .map_err(zip_error)?;
+        let modified = match doc.meta.get("modified") {
+            Some(modified) => modified.clone(),
+            None => utc_timestamp(default_modified(
+                std::env::var("SOURCE_DATE_EPOCH").ok().as_deref(),
+            )),
+        };
+        zip.write_all(
+            package_document(doc, &title, lang, &modified, &stylesheets, &chapters).as_bytes(),
+        )?;
+        zip.start_file(format!("{}/{}", PACKAGE_DIR, NAV_FILE), deflated)
+            .map_err(zip_error)?;
+        zip.write_all(nav_document(doc, &title, &stylesheets, &toc, &chapters).as_bytes())?;
+        for chapter in &chapters {
+            zip.start_file(format!("{}/{}", PACKAGE_DIR, chapter.file), deflated)
+                .map_err(zip_error)?;
+            zip.write_all(chapter.xhtml.as_bytes())?;
+        }
+        let zip = zip.finish().map_err(zip_error)?;
+        self.writer.write_all(zip.get_ref())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
+}
+
+impl<W: Write> EpubSerializer<W> {
+    /// Create a new serializer with the given options.
+    pub fn with_options(writer: W, options: EpubOptions) -> Box<Self> {
+        Box::new(Self {
+            writer,
+            options,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// The stylesheets to link to (see `HtmlOptions::stylesheets`), which must
+    /// be remote; local stylesheets are skipped with a warning.
+    fn remote_stylesheets(&mut self) -> Vec<String> {
+        let (remote, local): (Vec<_>, Vec<_>) = self
+            .options
+            .html
+            .stylesheets
+            .iter()
+            .cloned()
+            .partition(|stylesheet| is_remote(stylesheet));
+        for stylesheet in local {
+            self.warnings.push(Warning::new(
+                WarningKind::MissingAsset,
+                format!(
+                    "Local stylesheet {:?} isn't included in EPUB output",
+                    stylesheet
+                ),
+            ));
+        }
+        remote
+    }
+
+    /// Write a chapter's body as XHTML, continuing the footnote numbers and
+    /// heading ids in `ids`.
+    fn write_chapter(
+        &mut self,
+        chapter: &Doc,
+        ids: &mut split::SplitIds,
+    ) -> Result<String, SerializerError> {
+        let options = HtmlOptions {
+            math: MathConfig::None,
+            toc: false,
+            ..self.options.html.clone()
+        };
+        let mut html = Vec::new();
+        let mut ser = HtmlSerializer::fragment(&mut html, options);
+        mem::take(ids).restore(&mut ser);
+        let result = ser.write_doc(chapter);
+        *ids = split::SplitIds::take(&mut ser);
+        self.warnings.extend(ser.take_warnings());
+        result?;
+        drop(ser);
+        to_xhtml(&String::from_utf8_lossy(&html))
+    }
+}
+
+fn zip_error(err: ZipError) -> SerializerError {
+    match err {
+        ZipError::Io(err) => err.into(),
+        err => SerializerError::Other(Box::new(err)),
+    }
+}
+
+/// Escape text for XML, in text or in a quoted attribute value.
+fn xml_escape(text: &str) -> String {
+    escape_with(text, |c| match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        _ => None,
+    })
+}
+
+/// Is `href` a remote resource, which the package document must declare,
+/// rather than a path or a `data:` URL?
+fn is_remote(href: &str) -> bool {
+    doc::is_url(href) && !href.starts_with("data:")
+}
+
+/// Does some (X)HTML refer to remote resources, e.g. images with absolute
+/// URLs? Links to other pages don't count.
+fn has_remote_resources(html: &str) -> Result<bool, SerializerError> {
+    for token in fh::tokenize(html)? {
+        if let fh::HtmlToken::StartTag { name, attrs } = token {
+            let attr = match name.as_str() {
+                "link" => "href",
+                "img" | "audio" | "video" | "source" | "track" | "embed" | "iframe" | "script" => {
+                    "src"
+                }
+                _ => continue,
+            };
+            if attrs
+                .iter()
+                .any(|(name, value)| name == attr && is_remote(value))
+            {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Rewrite HTML written by an `HtmlSerializer` as XHTML, i.e. as well-formed
+/// XML.
+fn to_xhtml(html: &str) -> Result<String, SerializerError> {
+    let mut ret = String::with_capacity(html.len());
+    for token in fh::tokenize(html)? {
+        match token {
+            fh::HtmlToken::StartTag { name, attrs } => {
+                ret.push('<');
+                ret.push_str(&name);
+                for (attr, value) in attrs {
+                    write!(ret, r#" {}="{}""#, attr, xml_escape(&value)).unwrap();
+                }
+                if VOID_ELEMENTS.contains(&name.as_str()) {
+                    ret.push_str("/>");
+                } else {
+                    ret.push('>');
+                }
+            }
+            fh::HtmlToken::EndTag(name) => {
+                if !VOID_ELEMENTS.contains(&name.as_str()) {
+                    write!(ret, "</{}>", name).unwrap();
+                }
+            }
+            fh::HtmlToken::Text(text) => ret.push_str(&xml_escape(&text)),
+            // XML doesn't allow a doctype here, or `--` in comments.
+            fh::HtmlToken::Doctype(_) | fh::HtmlToken::Comment(_) => {}
+        }
+    }
+    Ok(ret)
+}
+
+/// The start of an XHTML content document, up to and including `<body>`.
+fn xhtml_header(title: &str, doc: &Doc, stylesheets: &[String]) -> String {
+    let mut ret = concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n<!DOCTYPE html>\n",
+        r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops""#,
+    )
+    .to_owned();
+    if let Some(lang) = doc.meta.get(doc::LANG_KEY) {
+        let lang = xml_escape(lang);
+        write!(ret, r#" lang="{}" xml:lang="{}""#, lang, lang).unwrap();
+    }
+    if let Some(dir) = doc.meta.get(doc::DIR_KEY) {
+        write!(ret, r#" dir="{}""#, xml_escape(dir)).unwrap();
+    }
+    write!(
+        ret,
+        ">\n<head>\n<meta charset=\"UTF-8\"/>\n<title>{}</title>\n",
+        xml_escape(title)
+    )
+    .unwrap();
+    for stylesheet in stylesheets {
+        writeln!(
+            ret,
+            r#"<link rel="stylesheet" href="{}"/>"#,
+            xml_escape(stylesheet)
+        )
+        .unwrap();
+    }
+    ret.push_str("</head>\n<body>\n");
+    ret
+}
+
+/// A chapter's XHTML content document.
+fn xhtml_page(title: &str, doc: &Doc, stylesheets: &[String], body: &str) -> String {
+    let mut ret = xhtml_header(title, doc, stylesheets);
+    ret.push_str(body);
+    ret.push_str("</body>\n</html>\n");
+    ret
+}
+
+/// The headings listed in the navigation document, linking to the chapter
+/// files.
+fn nav_toc(pages: &[Page], doc: &Doc) -> Vec<SplitTocEntry> {
+    // Chapters are written as fragments, without a `<main>` landmark.
+    let mut toc = split::page_toc(pages, &doc.meta, &doc.collections, &[]);
+    for entry in toc.iter_mut() {
+        // Links to headings on the first page are only fragments.
+        if entry.link.href.starts_with('#') {
+            entry.link.href.insert_str(0, &pages[0].file);
+        }
+    }
+    toc
+}
+
+/// The navigation document, listing the headings in `toc` (or each chapter,
+/// if there are none) as nested lists.
+fn nav_document(
+    doc: &Doc,
+    title: &str,
+    stylesheets: &[String],
+    toc: &[SplitTocEntry],
+    chapters: &[Chapter],
+) -> String {
+    let mut ret = xhtml_header(title, doc, stylesheets);
+    ret.push_str("<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n");
+    let entries: Vec<(i32, &str, &str)> = if toc.is_empty() {
+        chapters
+            .iter()
+            .map(|chapter| (1, chapter.file.as_str(), chapter.title.as_str()))
+            .collect()
+    } else {
+        toc.iter()
+            .map(|entry| {
+                (
+                    entry.level,
+                    entry.link.href.as_str(),
+                    entry.link.text.as_str(),
+                )
+            })
+            .collect()
+    };
+    // The level of each open list's last item, outermost first.
+    let mut levels: Vec<i32> = Vec::new();
+    for (level, href, text) in entries {
+        match levels.last() {
+            None => levels.push(level),
+            Some(&last) if level > last => {
+                ret.push_str("\n<ol>\n");
+                levels.push(level);
+            }
+            Some(_) => {
+                while levels.len() > 1 && levels[levels.len() - 2] >= level {
+                    levels.pop();
+                    ret.push_str("</li>\n</ol>\n");
+                }
+                ret.push_str("</li>\n");
+                *levels.last_mut().unwrap() = level;
+            }
+        }
+        write!(
+            ret,
+            r#"<li><a href="{}">{}</a>"#,
+            xml_escape(href),
+            xml_escape(text)
+        )
+        .unwrap();
+    }
+    for _ in levels {
+        ret.push_str("</li>\n</ol>\n");
+    }
+    ret.push_str("</nav>\n</body>\n</html>\n");
+    ret
+}
+
+/// The package document, giving the book's metadata, its files, and the order
+/// to read the chapters in.
+fn package_document(
+    doc: &Doc,
+    title: &str,
+    lang: &str,
+    modified: &str,
+    stylesheets: &[String],
+    chapters: &[Chapter],
+) -> String {
+    let identifier = match doc.meta.get("identifier") {
+        Some(identifier) => identifier.clone(),
+        None => {
+            let hash = chapters
+                .iter()
+                .fold(fnv1a(title.as_bytes()), |hash, chapter| {
+                    fnv1a_extend(hash, chapter.xhtml.as_bytes())
+                });
+            format!("urn:textecca:{:016x}", hash)
+        }
+    };
+    let mut ret = concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n",
+        r#"<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id">"#,
+        "\n",
+        r#"<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">"#,
+        "\n",
+    )
+    .to_owned();
+    write!(
+        ret,
+        "<dc:identifier id=\"book-id\">{}</dc:identifier>\n<dc:title>{}</dc:title>\n<dc:language>{}</dc:language>\n",
+        xml_escape(&identifier),
+        xml_escape(title),
+        xml_escape(lang)
+    )
+    .unwrap();
+    if let Some(author) = doc.meta.get("author") {
+        writeln!(ret, "<dc:creator>{}</dc:creator>", xml_escape(author)).unwrap();
+    }
+    if let Some(date) = doc.meta.get("date") {
+        writeln!(ret, "<dc:date>{}</dc:date>", xml_escape(date)).unwrap();
+    }
+    write!(
+        ret,
+        "<meta property=\"dcterms:modified\">{}</meta>\n</metadata>\n<manifest>\n",
+        xml_escape(modified)
+    )
+    .unwrap();
+    writeln!(
+        ret,
+        r#"<item id="nav" href="{}" media-type="application/xhtml+xml" properties="nav{}"/>"#,
+        NAV_FILE,
+        if stylesheets.is_empty() {
+            ""
+        } else {
+            " remote-resources"
+        }
+    )
+    .unwrap();
+    for (i, chapter) in chapters.iter().enumerate() {
+        writeln!(
+            ret,
+            r#"<item id="chapter-{}" href="{}" media-type="application/xhtml+xml"{}/>"#,
+            i + 1,
+            xml_escape(&chapter.file),
+            if chapter.remote_resources {
+                r#" properties="remote-resources""#
+            } else {
+                ""
+            }
+        )
+        .unwrap();
+    }
+    ret.push_str("</manifest>\n<spine>\n");
+    for i in 0..chapters.len() {
+        writeln!(ret, r#"<itemref idref="chapter-{}"/>"#, i + 1).unwrap();
+    }
+    ret.push_str("</spine>\n</package>\n");
+    ret
+}
+
+/// The book's modification time if the document doesn't give one: the time
+/// in `source_date_epoch` (the `SOURCE_DATE_EPOCH` environment variable, in
+/// seconds since the Unix epoch) if it's valid, so builds are reproducible, or
+/// else the current time.
+fn default_modified(source_date_epoch: Option<&str>) -> SystemTime {
+    match source_date_epoch.and_then(|secs| secs.trim().parse().ok()) {
+        Some(secs) => UNIX_EPOCH + Duration::from_secs(secs),
+        None => SystemTime::now(),
+    }
+}
+
+/// Format a time as an ISO 8601 UTC timestamp, e.g. `2020-06-01T12:00:00Z`.
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    // Howard Hinnant's `civil_from_days`; see
+    // https://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let secs = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use pretty_assertions::assert_eq;
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::doc::{BlockInner, Image, Inline, InlineMath};
+    use crate::ser::test_util::{assert_balanced, block, heading, text};
+
+    /// The files in an EPUB container, in order, with their contents.
+    fn unzip(epub: Vec<u8>) -> Vec<(String, CompressionMethod, String)> {
+        let mut archive = ZipArchive::new(Cursor::new(epub)).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut file = archive.by_index(i).unwrap();
+                let mut content = String::new();
+                file.read_to_string(&mut content).unwrap();
+                (file.name().to_owned(), file.compression(), content)
+            })
+            .collect()
+    }
+
+    /// Check that the elements in some XHTML are balanced.
+    fn assert_xhtml_balanced(xhtml: &str) {
+        // The XML declaration isn't HTML.
+        let (_, xhtml) = xhtml.split_at(xhtml.find("?>").unwrap() + 2);
+        assert_balanced(xhtml);
+    }
+
+    #[test]
+    fn test_epub() {
+        let mut doc = Doc::from_content(
+            vec![
+                heading(0, 1, "Beginning"),
+                block(
+                    1,
+                    BlockInner::Par(vec![
+                        Inline::Text("Math:".into()),
+                        Inline::Space,
+                        Inline::Math(InlineMath {
+                            tex: "a < b".into(),
+                            alt: None,
+                        }),
+                        Inline::LineBreak,
+                    ]),
+                ),
+                heading(2, 2, "Details"),
+                heading(3, 1, "End"),
+                block(4, BlockInner::Rule),
+            ]
+            .into(),
+        );
+        doc.meta.insert("title".into(), "A <fine> book".into());
+        doc.meta.insert("author".into(), "Someone".into());
+        doc.meta.insert(doc::LANG_KEY.into(), "en".into());
+        doc.meta
+            .insert("modified".into(), "2020-06-01T12:00:00Z".into());
+        let mut out = Vec::new();
+        let mut ser = EpubSerializer::new(&mut out).unwrap();
+        ser.write_doc(&doc).unwrap();
+        assert_eq!(Vec::<Warning>::new(), ser.take_warnings());
+
+        let files = unzip(out);
+        let names: Vec<_> = files.iter().map(|(name, ..)| name.as_str()).collect();
+        assert_eq!(
+            vec![
+                "mimetype",
+                "META-INF/container.xml",
+                "OEBPS/content.opf",
+                "OEBPS/nav.xhtml",
+                "OEBPS/beginning.xhtml",
+                "OEBPS/end.xhtml",
+            ],
+            names
+        );
+        assert_eq!(
+            (CompressionMethod::Stored, "application/epub+zip"),
+            (files[0].1, files[0].2.as_str())
+        );
+        assert!(files[1].2.contains(r#"full-path="OEBPS/content.opf""#));
+
+        let package = &files[2].2;
+        assert!(package.contains(r#"unique-identifier="book-id""#));
+        assert!(package.contains("<dc:identifier id=\"book-id\">urn:textecca:"));
+        assert!(package.contains("<dc:title>A &lt;fine&gt; book</dc:title>"));
+        assert!(package.contains("<dc:language>en</dc:language>"));
+        assert!(package.contains("<dc:creator>Someone</dc:creator>"));
+        assert!(package.contains("<meta property=\"dcterms:modified\">2020-06-01T12:00:00Z</meta>"));
+        assert!(package
+            .contains(r#"href="nav.xhtml" media-type="application/xhtml+xml" properties="nav""#));
+        assert!(package.contains(r#"<item id="chapter-2" href="end.xhtml""#));
+        assert!(package.contains(
+            "<spine>\n<itemref idref=\"chapter-1\"/>\n<itemref idref=\"chapter-2\"/>\n</spine>"
+        ));
+
+        let nav = &files[3].2;
+        assert_xhtml_balanced(nav);
+        assert!(nav.contains(concat!(
+            "<ol>\n<li><a href=\"beginning.xhtml\">Beginning</a>\n",
+            "<ol>\n<li><a href=\"beginning.xhtml#Details\">Details</a></li>\n</ol>\n",
+            "</li>\n<li><a href=\"end.xhtml\">End</a></li>\n</ol>\n</nav>"
+        )));
+
+        for (_, _, chapter) in &files[4..] {
+            assert!(chapter.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+            assert!(chapter.contains(r#"xmlns="http://www.w3.org/1999/xhtml""#));
+            assert!(chapter.contains(r#"lang="en" xml:lang="en""#));
+            assert_xhtml_balanced(chapter);
+        }
+        let beginning = &files[4].2;
+        assert!(beginning.contains("<title>Beginning</title>"));
+        // Math is written as its TeX.
+        assert!(beginning.contains("<code class=\"tex\">a &lt; b</code><br/>"));
+        assert!(files[5].2.contains("<hr/>"));
+    }
+
+    #[test]
+    fn test_epub_preface() {
+        let doc = Doc::from_content(vec![block(0, BlockInner::Par(text("Hi.")))].into());
+        let mut out = Vec::new();
+        EpubSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&doc)
+            .unwrap();
+        let files = unzip(out);
+        assert_eq!("OEBPS/index.xhtml", files[4].0);
+        // The identifier is a hash of the content, which is the same in every
+        // build.
+        assert!(files[2].2.contains(
+            "<dc:identifier id=\"book-id\">urn:textecca:9fd127851a8cc71c</dc:identifier>"
+        ));
+        assert!(files[2].2.contains("<dc:title>Untitled</dc:title>"));
+        assert!(files[2].2.contains("<dc:language>und</dc:language>"));
+        // Without any headings, the chapters are listed instead.
+        assert!(files[3]
+            .2
+            .contains("<ol>\n<li><a href=\"index.xhtml\">Untitled</a></li>\n</ol>"));
+    }
+
+    #[test]
+    fn test_epub_empty() {
+        let mut out = Vec::new();
+        EpubSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&Doc::from_content(Default::default()))
+            .unwrap();
+        let files = unzip(out);
+        assert_eq!("OEBPS/index.xhtml", files[4].0);
+        assert_xhtml_balanced(&files[4].2);
+        assert!(files[2]
+            .2
+            .contains("<spine>\n<itemref idref=\"chapter-1\"/>\n</spine>"));
+    }
+
+    #[test]
+    fn test_epub_remote_resources() {
+        let image = |id, src: &str| {
+            block(
+                id,
+                BlockInner::Par(vec![Inline::Image(Image {
+                    src: src.into(),
+                    alt: Some("An image".into()),
+                    attrs: Default::default(),
+                })]),
+            )
+        };
+        let doc = Doc::from_content(
+            vec![
+                heading(0, 1, "Remote"),
+                image(1, "https://example.com/a.png"),
+                heading(2, 1, "Local"),
+                image(3, "b.png"),
+            ]
+            .into(),
+        );
+        let write = |stylesheets: Vec<String>| {
+            let mut out = Vec::new();
+            let options = EpubOptions {
+                html: HtmlOptions {
+                    stylesheets,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let mut ser = EpubSerializer::new_with_options(&mut out, options).unwrap();
+            ser.write_doc(&doc).unwrap();
+            let warnings = ser.take_warnings();
+            (unzip(out), warnings)
+        };
+
+        let (files, warnings) = write(vec![]);
+        assert_eq!(Vec::<Warning>::new(), warnings);
+        let package = &files[2].2;
+        assert!(package.contains(concat!(
+            r#"<item id="chapter-1" href="remote.xhtml" media-type="application/xhtml+xml" properties="remote-resources"/>"#,
+            "\n",
+            r#"<item id="chapter-2" href="local.xhtml" media-type="application/xhtml+xml"/>"#,
+        )));
+        assert!(package.contains(r#"properties="nav"/>"#));
+
+        // Remote stylesheets are linked from every file; local ones are
+        // skipped.
+        let (files, warnings) = write(vec![
+            "https://example.com/style.css".into(),
+            "style.css".into(),
+        ]);
+        assert_eq!(
+            vec![WarningKind::MissingAsset],
+            warnings
+                .iter()
+                .map(|warning| warning.kind)
+                .collect::<Vec<_>>()
+        );
+        let package = &files[2].2;
+        assert!(package.contains(r#"properties="nav remote-resources"/>"#));
+        assert!(package.contains(r#"href="local.xhtml" media-type="application/xhtml+xml" properties="remote-resources"/>"#));
+        for (_, _, xhtml) in &files[3..] {
+            assert!(xhtml.contains(
+                "<link rel=\"stylesheet\" href=\"https://example.com/style.css\"/>\n</head>"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_default_modified() {
+        assert_eq!(
+            UNIX_EPOCH + Duration::from_secs(1_591_014_896),
+            default_modified(Some("1591014896"))
+        );
+        let before = SystemTime::now();
+        assert!(default_modified(Some("yesterday")) >= before);
+        assert!(default_modified(None) >= before);
+    }
+
+    #[test]
+    fn test_to_xhtml() {
+        assert_eq!(
+            r#"<p class="a&quot;b">x &amp; y<br/><img src="a.png" alt=""/></p>"#,
+            to_xhtml(r#"<!DOCTYPE html><!-- a comment --><p class='a"b'>x &amp; y<br><img src="a.png" alt></p>"#)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_utc_timestamp() {
+        let at = |secs| utc_timestamp(UNIX_EPOCH + std::time::Duration::from_secs(secs));
+        assert_eq!("1970-01-01T00:00:00Z", at(0));
+        assert_eq!("2000-02-29T00:00:00Z", at(951_782_400));
+        assert_eq!("2020-06-01T12:34:56Z", at(1_591_014_896));
+    }
+}
@@ -1,17 +1,146 @@
-use super::super::SerializerError;
+//! Rendering TeX math to HTML.
+use thiserror::Error;
 
+use super::super::escape_with;
+
+/// How an `HtmlSerializer` renders math, and what it links to in documents
+/// containing math for the math to display properly; see `HtmlOptions::math`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MathConfig {
+    /// Math is rendered with `KatexRenderer`, and KaTeX's stylesheet is
+    /// linked to: `css_href` if it's given (a URL, or a path relative to the
+    /// project root, which is treated like `HtmlOptions::stylesheets`), or
+    /// else the copy on the jsDelivr CDN.
+    ///
+    /// Without the `math` feature, math is written with `PlainMathRenderer`
+    /// and nothing is linked to.
+    Katex {
+        /// KaTeX's stylesheet.
+        css_href: Option<String>,
+    },
+    /// Math is written with `MathJaxRenderer` for MathJax to typeset in the
+    /// browser, loading its script from `script_src`.
+    MathJax {
+        /// MathJax's script, e.g.
+        /// `https://cdn.jsdelivr.net/npm/mathjax@3/es5/tex-chtml.js`.
+        script_src: String,
+    },
+    /// Math is written with `PlainMathRenderer`, and nothing is linked to.
+    None,
+}
+
+impl Default for MathConfig {
+    /// `Katex` with its stylesheet on the CDN, or `None` without the `math`
+    /// feature.
+    fn default() -> Self {
+        if cfg!(feature = "math") {
+            Self::Katex { css_href: None }
+        } else {
+            Self::None
+        }
+    }
+}
+
+impl MathConfig {
+    /// The renderer for this configuration.
+    pub fn renderer(&self) -> Box<dyn MathRenderer> {
+        match self {
+            #[cfg(feature = "math")]
+            Self::Katex { .. } => Box::new(KatexRenderer),
+            Self::MathJax { .. } => Box::new(MathJaxRenderer),
+            _ => Box::new(PlainMathRenderer),
+        }
+    }
+}
+
+/// Whether math is rendered inline with text or displayed on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MathMode {
+    /// Inline with text, e.g. `\math`.
     Inline,
+    /// Displayed on its own, e.g. `\equation`.
     Display,
 }
 
-pub fn render_tex(tex: &str, mode: MathMode) -> Result<String, SerializerError> {
-    let opts = katex::OptsBuilder::default()
-        .display_mode(match mode {
-            MathMode::Inline => false,
-            MathMode::Display => true,
-        })
-        .build()
-        .unwrap();
-    katex::render_with_opts(&tex, opts).map_err(|e| SerializerError::Other(Box::new(e)))
+/// Renders TeX math to HTML for an `HtmlSerializer`; see
+/// `HtmlSerializer::set_math_renderer`. The default is given by
+/// `HtmlOptions::math`, but other engines, like a rendering service, can be
+/// plugged in.
+///
+/// Identical formulas are only rendered once per document.
+pub trait MathRenderer {
+    /// Render the given TeX to HTML. Failures are reported according to
+    /// `HtmlOptions::strictness`, and the TeX is written as code instead.
+    fn render(&self, tex: &str, mode: MathMode) -> Result<String, MathError>;
+}
+
+/// An error rendering math.
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct MathError(pub String);
+
+/// Renders math with [KaTeX](https://katex.org/).
+///
+/// The output needs KaTeX's stylesheet, which the `HtmlSerializer` links to
+/// in documents containing math.
+#[cfg(feature = "math")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KatexRenderer;
+
+#[cfg(feature = "math")]
+impl MathRenderer for KatexRenderer {
+    fn render(&self, tex: &str, mode: MathMode) -> Result<String, MathError> {
+        let opts = katex::OptsBuilder::default()
+            .display_mode(mode == MathMode::Display)
+            .build()
+            .unwrap();
+        katex::render_with_opts(tex, opts).map_err(|err| MathError(err.to_string()))
+    }
+}
+
+/// Writes math as its TeX in a `<code class="tex">` element, e.g. for
+/// environments without a JavaScript engine, or to render math client-side.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlainMathRenderer;
+
+impl MathRenderer for PlainMathRenderer {
+    fn render(&self, tex: &str, _mode: MathMode) -> Result<String, MathError> {
+        Ok(format!(r#"<code class="tex">{}</code>"#, escape_tex(tex)))
+    }
+}
+
+/// Writes math as its TeX, delimited with `\(...\)` or `\[...\]`, for
+/// [MathJax](https://www.mathjax.org/) to typeset in the browser; see
+/// `MathConfig::MathJax`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MathJaxRenderer;
+
+impl MathRenderer for MathJaxRenderer {
+    fn render(&self, tex: &str, mode: MathMode) -> Result<String, MathError> {
+        let (open, close) = match mode {
+            MathMode::Inline => ("\\(", "\\)"),
+            MathMode::Display => ("\\[", "\\]"),
+        };
+        Ok(format!("{}{}{}", open, escape_tex(tex), close))
+    }
+}
+
+/// Escape TeX for writing in HTML text.
+fn escape_tex(tex: &str) -> String {
+    escape_with(tex, |c| match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        _ => None,
+    })
+}
+
+/// Fails to render any math, e.g. to test how failures are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FailingMathRenderer;
+
+impl MathRenderer for FailingMathRenderer {
+    fn render(&self, tex: &str, _mode: MathMode) -> Result<String, MathError> {
+        Err(MathError(format!("Can't render {:?}", tex)))
+    }
 }
@@ -1,283 +1,1695 @@
+use std::collections::HashMap;
 use std::io::{self, Write};
 use std::iter;
 use std::mem;
+use std::path::{Component, Path};
+use std::rc::Rc;
 use std::{borrow::Cow, vec};
 
 use thiserror::Error;
-use typed_html::html;
 
 use friendly_html as fh;
 
-use super::{InitSerializer, Serializer, SerializerError};
+use super::{
+    abstract_blocks, escape_with, heading_id, percent_encode_fragment, reserve_heading_ids,
+    AssetEmbedder, InitSerializer, InitSerializerWithOptions, Serializer, SerializerError,
+};
+use crate::cmd::{Warning, WarningKind};
+use crate::diagnostic::ErrorCode;
 use crate::doc::{
-    self, Block, BlockInner, Blocks, Doc, Footnote, Heading, Inline, Inlines, List, ListKind,
+    self, Block, BlockInner, Code, Collections, Defn, Doc, DocNode, Figure, Footnote, Heading, Id,
+    Image, Inline, Inlines, Link, LinkTarget, List, ListKind, Meta, RefId, Table, TaggedBlocks,
+    TaggedInlines, TermListItem,
 };
 
+#[cfg(feature = "epub")]
+mod epub;
 mod math;
-mod slugify;
+mod options;
+mod record;
+mod split;
 
-use math::*;
-pub use slugify::*;
+#[cfg(feature = "epub")]
+pub use epub::*;
+pub use math::*;
+pub use options::*;
+pub use split::*;
+// Re-exported from its old location; see `doc::text`.
+pub use crate::doc::text::slugify;
 
 /// Serializer to HTML5.
 pub struct HtmlSerializer<W: Write> {
-    ser: fh::HtmlSerializer<W>,
+    ser: record::Output<W>,
+    options: HtmlOptions,
+    /// Footnotes referenced but not yet listed.
     footnotes: Vec<MarkedFootnote>,
+    /// The number of footnotes referenced so far, for unique ids.
+    footnote_count: usize,
+    /// The number of the last footnote referenced; see
+    /// `HtmlOptions::footnote_numbering`.
+    footnote_number: usize,
+    /// Whether `write_doc` writes a fragment rather than a whole page; see
+    /// `fragment`.
+    fragment: bool,
+    /// Whether the document being written is a draft; see `doc::is_draft`.
+    draft: bool,
+    /// The languages of the content being written, innermost last; see
+    /// `doc::LANG_KEY`.
+    langs: Vec<String>,
+    /// Warnings emitted so far; see `Serializer::take_warnings`.
+    warnings: Vec<Warning>,
+    /// The slug of the last heading written, for reporting where problems
+    /// are.
+    section: Option<String>,
+    /// Renders math; see `set_math_renderer`.
+    math_renderer: Box<dyn MathRenderer>,
+    /// Rendered math, so repeated formulas are only rendered once.
+    math_cache: HashMap<(String, MathMode), String>,
+    /// The text of the headings written so far, by id, whether the id is
+    /// explicit (see `doc::ID_KEY`) or generated.
+    heading_ids: HashMap<String, String>,
+    /// The number of headings which have wanted each id so far; see
+    /// `heading_id`.
+    heading_slugs: HashMap<String, usize>,
+    /// The ids of the document's headings not yet written, by their blocks'
+    /// ids; see `heading_ids`.
+    pending_heading_ids: HashMap<Id, String>,
+    /// The ids written on elements other than headings, like the `<main>`
+    /// landmark (see `reserved_ids`) and definitions (see `defn_id`), which
+    /// headings can't have, with what they're used for.
+    reserved_ids: HashMap<String, String>,
+    /// Reads the files embedded in the output; see
+    /// `HtmlOptions::embed_assets`.
+    embedder: Option<AssetEmbedder>,
+    /// The number of blocks and inlines being written; see
+    /// `HtmlOptions::max_depth`.
+    depth: usize,
+    /// The table of contents and navigation links, when writing one of the
+    /// files of a split document; see `write_split`.
+    split: Option<split::SplitPage>,
 }
 
+/// KaTeX's stylesheet on the jsDelivr CDN, for `MathConfig::Katex`, and its
+/// subresource integrity hash.
+const KATEX_CSS_HREF: &str = "https://cdn.jsdelivr.net/npm/katex@0.11.1/dist/katex.min.css";
+const KATEX_CSS_INTEGRITY: &str =
+    "sha384-zB1R0rpPzHqg7Kpt0Aljp8JPLqbXI3bhnPWROx27a9N0Ll6ZP/+DiW/UqRcLbRjq";
+
+/// The built-in renderer hint giving the class of tagged content's wrapper; see
+/// `HintHandler`.
+const CLASS_HINT: &str = "class";
+
 struct MarkedFootnote {
+    /// The footnote's displayed number.
+    num: usize,
     id: String,
     return_id: String,
-    content: Blocks,
+    /// The footnote's content, written where the footnote is referenced.
+    content: record::Recording,
 }
 
 impl<W: Write> InitSerializer<W> for HtmlSerializer<W> {
     fn new(writer: W) -> Result<Box<Self>, SerializerError> {
-        Ok(Box::new(Self {
-            ser: fh::HtmlSerializer::with_doctype(writer)?,
-            footnotes: Default::default(),
-        }))
+        Self::with_options(writer, Default::default())
+    }
+}
+
+impl<W: Write> InitSerializerWithOptions<W> for HtmlSerializer<W> {
+    type Options = HtmlOptions;
+
+    fn new_with_options(writer: W, options: HtmlOptions) -> Result<Box<Self>, SerializerError> {
+        Self::with_options(writer, options)
     }
 }
 
 impl<W: Write> Serializer for HtmlSerializer<W> {
-    fn write_doc(&mut self, doc: Doc) -> Result<(), SerializerError> {
-        self.write_header(&doc)?;
-        self.write_blocks(doc.content)?;
+    fn format_name(&self) -> &'static str {
+        "html"
+    }
+
+    fn write_doc(&mut self, doc: &Doc) -> Result<(), SerializerError> {
+        let result = self.write_doc_inner(doc);
+        // The block ids are only meaningful for `doc`.
+        self.pending_heading_ids.clear();
+        if result.is_err() {
+            // Close whatever's still open so the partial output is well-formed;
+            // the original error is more useful than any error from this.
+            let _ = self.ser.abort();
+        }
+        result
+    }
+
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
+}
+
+impl<W: Write> HtmlSerializer<W> {
+    /// Create a new serializer with the given options.
+    pub fn with_options(writer: W, options: HtmlOptions) -> Result<Box<Self>, SerializerError> {
+        let ser = fh::HtmlSerializer::with_doctype_options(writer, fh_options(&options))?;
+        Ok(Self::from_ser(ser, options, false))
+    }
+
+    /// Create a new serializer with the given options for writing snippets of
+    /// HTML with `write_blocks` and `write_inlines`, e.g. to include in
+    /// another page. Unlike `with_options`, no doctype is written, and
+    /// `write_doc` only writes the document's abstract, content, and
+    /// footnotes, without the `<html>`, `<head>`, `<body>`, and `<main>`
+    /// elements around them.
+    pub fn fragment(writer: W, options: HtmlOptions) -> Box<Self> {
+        let ser = fh::HtmlSerializer::with_options(writer, fh_options(&options));
+        Self::from_ser(ser, options, true)
+    }
+
+    fn from_ser(ser: fh::HtmlSerializer<W>, options: HtmlOptions, fragment: bool) -> Box<Self> {
+        let embedder = match &options.resolver {
+            Some(resolver) if options.embed_assets => Some(AssetEmbedder::new(
+                Rc::clone(resolver),
+                options.embed_limits,
+            )),
+            _ => None,
+        };
+        let math_renderer = options.math.renderer();
+        Box::new(Self {
+            ser: record::Output::new(ser),
+            options,
+            fragment,
+            footnotes: Default::default(),
+            footnote_count: 0,
+            footnote_number: 0,
+            draft: false,
+            langs: Default::default(),
+            warnings: Default::default(),
+            section: None,
+            math_renderer,
+            math_cache: Default::default(),
+            heading_ids: Default::default(),
+            heading_slugs: Default::default(),
+            pending_heading_ids: Default::default(),
+            reserved_ids: Default::default(),
+            embedder,
+            depth: 0,
+            split: None,
+        })
+    }
+
+    /// Set how math is rendered; by default, with the renderer for
+    /// `HtmlOptions::math`. The stylesheet or script linked to in the header
+    /// still follows `HtmlOptions::math`.
+    pub fn set_math_renderer(&mut self, renderer: Box<dyn MathRenderer>) {
+        self.math_renderer = renderer;
+    }
+
+    /// Report a recoverable problem according to `HtmlOptions::strictness`.
+    fn report(&mut self, kind: WarningKind, message: String) -> Result<(), SerializerError> {
+        match self.options.strictness.handle(Warning::new(kind, message)) {
+            Ok(Some(warning)) => self.warnings.push(warning),
+            Ok(None) => {}
+            Err(warning) => return Err(SerializerError::Strict(warning)),
+        }
+        Ok(())
+    }
+
+    /// Report content which can't be written as HTML, and is skipped; see
+    /// `ser::unsupported`.
+    fn unsupported(&mut self, what: &str) -> Result<(), SerializerError> {
+        let warning = super::unsupported(self.options.strictness, "HTML", what)?;
+        self.warnings.extend(warning);
+        Ok(())
+    }
+
+    fn write_doc_inner(&mut self, doc: &Doc) -> Result<(), SerializerError> {
+        self.draft = doc::is_draft(&doc.meta);
+        self.langs = doc.meta.get(doc::LANG_KEY).cloned().into_iter().collect();
+        if !self.fragment {
+            for (id, used_for) in reserved_ids(&self.options) {
+                self.heading_slugs.entry(id.to_owned()).or_insert(1);
+                self.reserved_ids.insert(id.to_owned(), used_for.to_owned());
+            }
+            self.write_header(doc)?;
+        }
+        for (id, defn) in defns(&doc.collections, &doc.content) {
+            self.reserve_defn_id(id, defn);
+        }
+        // The ids are decided up front so the table of contents, which comes
+        // first, links to the same ones.
+        let ids = heading_ids(
+            &doc.collections,
+            &doc.content,
+            self.draft,
+            &mut self.heading_slugs,
+        );
+        self.write_abstract(doc)?;
+        if self.options.toc && self.split.is_none() {
+            self.write_toc(&ids)?;
+        }
+        self.pending_heading_ids = ids
+            .into_iter()
+            .map(|(block_id, _, id)| (block_id, id))
+            .collect();
+        for block in doc.content.iter() {
+            let section_level = self.options.footnote_section_level;
+            if matches!(&block.inner, BlockInner::Heading(heading) if heading.level <= section_level)
+            {
+                if self.options.footnote_placement == FootnotePlacement::EndOfSection {
+                    self.finish_footnotes()?;
+                }
+                if self.options.footnote_numbering == FootnoteNumbering::PerSection {
+                    self.footnote_number = 0;
+                }
+            }
+            self.write_block(block)?;
+            if self.options.footnote_placement == FootnotePlacement::EndOfBlock {
+                self.finish_footnotes()?;
+            }
+        }
+        self.write_split_toc()?;
         self.finish_footnotes()?;
         self.finish()?;
         Ok(())
     }
-}
 
-impl<W: Write> HtmlSerializer<W> {
+    /// Link to a stylesheet, or embed it; see `HtmlOptions::stylesheets`.
+    fn write_stylesheet(&mut self, stylesheet: String) -> Result<(), SerializerError> {
+        let href = if doc::is_url(&stylesheet) {
+            stylesheet
+        } else if let Some(css) = self.embed_stylesheet(&stylesheet)? {
+            self.ser.elem("style")?;
+            // Text in a `<style>` isn't escaped, so it mustn't end the
+            // element early; in CSS, `\/` is just `/`.
+            self.ser.write_text(css.replace("</", "<\\/"))?;
+            self.ser.end_elem()?;
+            self.ser.write_text("\n")?;
+            return Ok(());
+        } else {
+            self.linked_url(&stylesheet, "stylesheet")?
+        };
+        self.ser
+            .elem_attrs("link", &[("rel", "stylesheet"), ("href", &href)])?;
+        self.ser.write_text("\n")?;
+        Ok(())
+    }
+
+    /// Link to the stylesheet or script math needs; see `HtmlOptions::math`.
+    fn write_math_header(&mut self) -> Result<(), SerializerError> {
+        match self.options.math.clone() {
+            MathConfig::Katex {
+                css_href: Some(href),
+            } if cfg!(feature = "math") => {
+                self.write_stylesheet(href)?;
+            }
+            MathConfig::Katex { css_href: None } if cfg!(feature = "math") => {
+                if self.embedder.is_some() {
+                    self.report(
+                        WarningKind::MissingAsset,
+                        "Couldn't embed KaTeX's stylesheet and fonts; the output links to them"
+                            .to_owned(),
+                    )?;
+                }
+                self.ser.elem_attrs(
+                    "link",
+                    &[
+                        ("rel", "stylesheet"),
+                        ("href", KATEX_CSS_HREF),
+                        ("integrity", KATEX_CSS_INTEGRITY),
+                        ("crossorigin", "anonymous"),
+                    ],
+                )?;
+                self.ser.write_text("\n")?;
+            }
+            MathConfig::MathJax { script_src } => {
+                if self.embedder.is_some() {
+                    self.report(
+                        WarningKind::MissingAsset,
+                        "Couldn't embed MathJax's script; the output links to it".to_owned(),
+                    )?;
+                }
+                self.ser.elem_attrs(
+                    "script",
+                    &[
+                        ("id", "MathJax-script"),
+                        ("async", ""),
+                        ("src", &script_src),
+                    ],
+                )?;
+                self.ser.end_elem()?;
+                self.ser.write_text("\n")?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
     fn write_header(&mut self, doc: &Doc) -> Result<(), SerializerError> {
-        self.ser.elem("html")?;
+        let attrs: Vec<_> = [doc::LANG_KEY, doc::DIR_KEY]
+            .iter()
+            .filter_map(|key| doc.meta.get(*key).map(|value| (*key, value)))
+            .collect();
+        self.ser.elem_attrs("html", &attrs)?;
         self.ser.write_text("\n")?;
         self.ser.elem("head")?;
         self.ser.write_text("\n")?;
+        for stylesheet in self.options.stylesheets.clone() {
+            self.write_stylesheet(stylesheet)?;
+        }
         if doc.has_math() {
-            // TODO: Don't hardcode KaTeX tag.
-            self.ser.elem_attrs(
-                "link",
-                &[
-                    ("rel", "stylesheet"),
-                    (
-                        "href",
-                        "https://cdn.jsdelivr.net/npm/katex@0.11.1/dist/katex.min.css",
-                    ),
-                    (
-                        "integrity",
-                        "sha384-zB1R0rpPzHqg7Kpt0Aljp8JPLqbXI3bhnPWROx27a9N0Ll6ZP/+DiW/UqRcLbRjq",
-                    ),
-                    ("crossorigin", "anonymous"),
-                ],
-            )?;
-            self.ser.write_text("\n")?;
+            self.write_math_header()?;
         }
         self.ser.end_elem()?;
         self.ser.write_text("\n")?;
         self.ser.elem("body")?;
         self.ser.write_text("\n")?;
+        if self.options.a11y_landmarks {
+            if let Some(text) = self.options.skip_link.clone() {
+                self.ser.elem_attrs(
+                    "a",
+                    &[("class", "skip-link"), ("href", &fragment_href(MAIN_ID))],
+                )?;
+                self.ser.write_text(text)?;
+                self.ser.end_elem()?;
+                self.ser.write_text("\n")?;
+            }
+            self.ser.elem_attrs("main", &[("id", MAIN_ID)])?;
+            self.ser.write_text("\n")?;
+        }
+        Ok(())
+    }
+
+    /// The `class` attribute of an element of the given kind (see
+    /// `HtmlOptions::classes`), with the `others` classes after its own, or no
+    /// attributes if it has no classes.
+    fn class_attrs(&self, kind: ElementKind, others: &[&str]) -> Vec<(&'static str, String)> {
+        let classes: Vec<&str> = iter::once(self.options.classes.get(kind))
+            .chain(others.iter().copied())
+            .filter(|class| !class.is_empty())
+            .collect();
+        if classes.is_empty() {
+            Vec::new()
+        } else {
+            vec![("class", classes.join(" "))]
+        }
+    }
+
+    /// Write the document's abstract, if it has one; see
+    /// `doc::ABSTRACT_CHANNEL`. Other channels of `Doc::collections` aren't
+    /// written.
+    fn write_abstract(&mut self, doc: &Doc) -> Result<(), SerializerError> {
+        let entries = match doc.collections.get(doc::ABSTRACT_CHANNEL) {
+            Some(entries) if !entries.is_empty() => entries,
+            _ => return Ok(()),
+        };
+        let attrs = self.class_attrs(ElementKind::Abstract, &[]);
+        self.ser.elem_attrs("section", &attrs)?;
+        for blocks in entries {
+            self.write_blocks(blocks)?;
+        }
+        self.ser.end_elem()?;
         Ok(())
     }
 
     fn finish(&mut self) -> Result<(), SerializerError> {
+        if self.fragment {
+            self.ser.flush()?;
+            return Ok(());
+        }
         self.ser.write_text("\n")?;
+        if self.options.a11y_landmarks {
+            self.ser.end_elem()?; // </main>
+            self.ser.write_text("\n")?;
+        }
+        self.write_split_nav()?;
         self.ser.end_elem()?; // </body>
         self.ser.write_text("\n")?;
         self.ser.end_elem()?; // </html>
         self.ser.write_text("\n")?;
+        // Otherwise, a buffered writer's errors would be lost when it's
+        // dropped.
+        self.ser.flush()?;
+        Ok(())
+    }
+
+    /// Write the document's table of contents, listing the headings with the
+    /// given ids (see `heading_ids`); see `HtmlOptions::toc`.
+    fn write_toc(&mut self, ids: &[(Id, &Heading, String)]) -> Result<(), SerializerError> {
+        let toc = ids
+            .iter()
+            .filter(|(_, heading, _)| heading.in_toc())
+            .map(|(_, heading, id)| split::SplitTocEntry {
+                level: heading.level,
+                link: split::NavLink {
+                    href: fragment_href(id),
+                    text: split::heading_text(heading),
+                },
+            })
+            .collect();
+        self.write_toc_entries(toc)
+    }
+
+    /// Write the table of contents of a split document's index page; see
+    /// `write_split`.
+    fn write_split_toc(&mut self) -> Result<(), SerializerError> {
+        let toc = match &mut self.split {
+            Some(split) => mem::take(&mut split.toc),
+            None => return Ok(()),
+        };
+        self.write_toc_entries(toc)
+    }
+
+    /// Write a table of contents as nested lists, in a `<nav class="toc">`.
+    /// A list is nested in the entry before it when its level is deeper, even
+    /// if levels are skipped, e.g. a level 3 heading after a level 1 heading.
+    fn write_toc_entries(&mut self, toc: Vec<split::SplitTocEntry>) -> Result<(), SerializerError> {
+        if toc.is_empty() {
+            return Ok(());
+        }
+        let mut attrs = self.class_attrs(ElementKind::Toc, &[]);
+        attrs.push(("aria-label", "Contents".to_owned()));
+        self.ser.elem_attrs("nav", &attrs)?;
+        // The levels of the lists which are open, innermost last.
+        let mut levels: Vec<i32> = Vec::new();
+        for entry in toc {
+            while matches!(levels.last(), Some(&level) if level > entry.level) {
+                self.ser.end_elem()?; // </li>
+                self.ser.end_elem()?; // </ol>
+                levels.pop();
+            }
+            if levels.last() == Some(&entry.level) {
+                self.ser.end_elem()?; // </li>
+            } else {
+                self.ser.elem("ol")?;
+                levels.push(entry.level);
+            }
+            self.ser.elem("li")?;
+            self.ser.elem_attrs("a", &[("href", &entry.link.href)])?;
+            self.ser.write_text(&entry.link.text)?;
+            self.ser.end_elem()?;
+        }
+        for _ in levels {
+            self.ser.end_elem()?; // </li>
+            self.ser.end_elem()?; // </ol>
+        }
+        self.ser.end_elem()?; // </nav>
+        Ok(())
+    }
+
+    /// Write the links to the previous and next files of a split document;
+    /// see `write_split`.
+    fn write_split_nav(&mut self) -> Result<(), SerializerError> {
+        let split = match self.split.take() {
+            Some(split) if split.prev.is_some() || split.next.is_some() => split,
+            _ => return Ok(()),
+        };
+        self.ser
+            .elem_attrs("nav", &[("class", "pages"), ("aria-label", "Pages")])?;
+        let links = [
+            (split.prev, "prev"),
+            (split.contents, "contents"),
+            (split.next, "next"),
+        ];
+        for (link, class) in links.iter() {
+            if let Some(link) = link {
+                self.ser.write_text("\n")?;
+                let mut attrs = vec![("class", *class), ("href", &link.href)];
+                if *class != "contents" {
+                    attrs.push(("rel", class));
+                }
+                self.ser.elem_attrs("a", &attrs)?;
+                self.ser.write_text(&link.text)?;
+                self.ser.end_elem()?;
+            }
+        }
+        self.ser.write_text("\n")?;
+        self.ser.end_elem()?; // </nav>
+        self.ser.write_text("\n")?;
         Ok(())
     }
 
+    /// Write styled text. Small caps have no element of their own, so they're
+    /// written as a `<span>` with a `small-caps` class (for stylesheets) and
+    /// an inline `font-variant` (for everyone else); struck-out text is an
+    /// `<s>`, because a `<del>` would claim it was removed in an edit. Sizes
+    /// and colors are inline styles, too.
+    ///
+    /// Fonts and font features can't be written yet; they're reported as
+    /// unsupported and written as a `<span>` with a `font` or
+    /// `font-features` class, so they can at least be styled.
     fn write_styled(
         &mut self,
         style: &doc::Style,
         content: &Inlines,
     ) -> Result<(), SerializerError> {
-        match style {
-            doc::Style::Emph => {
-                self.ser.elem("em")?;
-                self.write_inlines(content)?;
-                self.ser.end_elem()?;
+        let css;
+        let (tag_name, attrs): (_, &[(&str, &str)]) = match style {
+            doc::Style::Emph => ("em", &[]),
+            doc::Style::Strong => ("strong", &[]),
+            doc::Style::Superscript => ("sup", &[]),
+            doc::Style::Subscript => ("sub", &[]),
+            doc::Style::SmallCaps => (
+                "span",
+                &[
+                    ("class", "small-caps"),
+                    ("style", "font-variant: small-caps"),
+                ],
+            ),
+            doc::Style::Strikeout => ("s", &[]),
+            doc::Style::Underline => ("u", &[]),
+            doc::Style::Size(size) => {
+                css = format!("font-size: {}", size);
+                ("span", &[("style", &css)])
             }
-            doc::Style::Strong => {
-                self.ser.elem("strong")?;
-                self.write_inlines(content)?;
-                self.ser.end_elem()?;
+            doc::Style::Color(color) if color.is_valid() => {
+                css = format!("color: {}", color);
+                ("span", &[("style", &css)])
             }
-            _ => todo!("Unimplemented style {:?}", style),
-        }
+            doc::Style::Font(_) => {
+                self.unsupported("fonts")?;
+                ("span", &[("class", "font")])
+            }
+            doc::Style::FontFeatures(_) => {
+                self.unsupported("font features")?;
+                ("span", &[("class", "font-features")])
+            }
+            _ => {
+                self.unsupported(&format!("the style {:?}", style))?;
+                return self.write_inlines(content);
+            }
+        };
+        self.ser.elem_attrs(tag_name, attrs)?;
+        self.write_inlines(content)?;
+        self.ser.end_elem()?;
         Ok(())
     }
 
-    fn write_inlines(&mut self, inlines: &[Inline]) -> Result<(), SerializerError> {
+    /// Write inlines as HTML, e.g. to a serializer created with `fragment`.
+    ///
+    /// Like `write_blocks`, the footnotes referred to aren't listed until
+    /// `finish_footnotes` is called.
+    pub fn write_inlines(&mut self, inlines: &[Inline]) -> Result<(), SerializerError> {
         for inline in inlines {
-            self.write_inline(Cow::Borrowed(inline))?;
+            self.write_inline(inline)?;
         }
         Ok(())
     }
 
-    fn write_inline(&mut self, inline: Cow<Inline>) -> Result<(), SerializerError> {
-        match inline.as_ref() {
+    fn write_inline(&mut self, inline: &Inline) -> Result<(), SerializerError> {
+        self.nested(|this| this.write_inline_inner(inline))
+    }
+
+    /// Call `f` one level deeper in the document, or give an error if that's
+    /// deeper than `HtmlOptions::max_depth`. Nesting is tracked so that
+    /// deeply nested documents give an error rather than overflowing the
+    /// stack.
+    fn nested(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<(), SerializerError>,
+    ) -> Result<(), SerializerError> {
+        if self.depth >= self.options.max_depth {
+            return Err(SerializerError::TooDeep {
+                depth: self.options.max_depth,
+            });
+        }
+        self.depth += 1;
+        let ret = f(self);
+        self.depth -= 1;
+        ret
+    }
+
+    fn write_inline_inner(&mut self, inline: &Inline) -> Result<(), SerializerError> {
+        match inline {
             Inline::Text(content) => {
                 self.ser.write_text(content)?;
             }
-            Inline::Styled { style, content } => self.write_styled(&style, &content)?,
+            Inline::Styled { style, content } => self.write_styled(style, content)?,
             Inline::Quote(quote) => {
-                let (l, r) = quote.kind.to_inlines();
+                let lang = self.langs.last().map(String::as_str);
+                let (l, r) = quote.kind.to_inlines_in(lang);
                 self.write_inlines(&l)?;
                 self.write_inlines(&quote.content)?;
                 self.write_inlines(&r)?;
             }
             Inline::Code(code) => {
+                let mut attrs = html_attrs(&code.attrs, &[]);
                 if let Some(lang) = &code.language {
-                    self.ser.elem_attrs("code", &[("class", &lang)])?;
-                } else {
-                    self.ser.elem("code")?;
+                    add_class(&mut attrs, lang);
                 }
+                add_class(
+                    &mut attrs,
+                    self.options.classes.get(ElementKind::InlineCode),
+                );
+                self.ser.elem_attrs("code", &attrs)?;
                 self.ser.write_text(&code.content)?;
                 self.ser.end_elem()?;
             }
             Inline::Space => {
                 self.ser.write_text(" ")?;
             }
-            Inline::Link(_) => {}
-            Inline::Footnote(_) => match inline.into_owned() {
-                Inline::Footnote(footnote) => self.write_footnote(footnote)?,
-                _ => unreachable!(),
-            },
-            Inline::Math(math) => {
-                self.ser
-                    .write_html(&render_tex(&math.tex, MathMode::Inline)?)?;
+            Inline::LineBreak => {
+                self.ser.elem("br")?;
             }
+            Inline::Link(link) => self.write_link(link)?,
+            Inline::Footnote(footnote) => self.write_footnote(footnote)?,
+            Inline::Math(math) => self.write_math(&math.tex, &math.alt, MathMode::Inline)?,
+            Inline::Image(image) => self.write_image(image)?,
+            Inline::Raw(raw) if raw.format == self.format_name() => {
+                self.ser.write_html(&raw.content)?;
+            }
+            Inline::Raw(_) => {}
+            Inline::Tagged(tagged) => self.write_tagged_inlines(tagged)?,
         }
         Ok(())
     }
 
-    fn write_list(&mut self, list: List) -> Result<(), SerializerError> {
-        let list_tag = match list.kind {
-            ListKind::Unordered => "ul",
-            ListKind::Ordered => "ol",
-        };
-        self.ser.elem(list_tag)?;
-        for item in list.items {
-            self.ser.elem("li")?;
-            self.write_blocks(item.content)?;
+    /// Start the element wrapping tagged content, if it needs one, and enter
+    /// its language. Returns whether an element was started.
+    fn begin_tagged(&mut self, tag_name: &str, meta: &Meta) -> Result<bool, SerializerError> {
+        let mut classes = Vec::new();
+        if meta.contains_key(doc::TODO_KEY) {
+            classes.push("todo");
+        }
+        if meta.contains_key(doc::ERROR_KEY) {
+            classes.push("error");
+        }
+        for (hint, value) in doc::meta_hints(meta, "html") {
+            if hint == CLASS_HINT {
+                classes.push(value);
+            }
+        }
+        let class = classes.join(" ");
+        let mut attrs = Vec::new();
+        if !class.is_empty() {
+            attrs.push(("class", class.as_str()));
+        }
+        if let Some(lang) = meta.get(doc::LANG_KEY) {
+            attrs.push(("lang", lang.as_str()));
+            self.langs.push(lang.clone());
+        }
+        if let Some(dir) = meta.get(doc::DIR_KEY) {
+            attrs.push(("dir", dir.as_str()));
+        }
+        if attrs.is_empty() {
+            Ok(false)
+        } else {
+            self.ser.elem_attrs(tag_name, &attrs)?;
+            Ok(true)
+        }
+    }
+
+    /// Finish tagged content started with `begin_tagged`.
+    fn end_tagged(&mut self, meta: &Meta, wrapped: bool) -> Result<(), SerializerError> {
+        if meta.contains_key(doc::LANG_KEY) {
+            self.langs.pop();
+        }
+        if wrapped {
             self.ser.end_elem()?;
         }
-        self.ser.end_elem()?;
         Ok(())
     }
 
-    fn write_blocks(&mut self, blocks: Blocks) -> Result<(), SerializerError> {
-        for block in blocks {
-            self.write_block(block)?;
+    fn write_tagged_inlines(&mut self, tagged: &TaggedInlines) -> Result<(), SerializerError> {
+        if !doc::meta_includes(&tagged.meta, "html", self.draft) {
+            return Ok(());
         }
-        Ok(())
+        let wrapped = self.begin_tagged("span", &tagged.meta)?;
+        self.write_inlines(&tagged.content)?;
+        self.end_tagged(&tagged.meta, wrapped)
     }
 
-    fn write_block(&mut self, block: Block) -> Result<(), SerializerError> {
-        match block.inner {
-            BlockInner::Plain(inlines) => {
-                self.write_inlines(&inlines)?;
+    fn write_tagged_blocks(&mut self, tagged: &TaggedBlocks) -> Result<(), SerializerError> {
+        if !doc::meta_includes(&tagged.meta, "html", self.draft) {
+            return Ok(());
+        }
+        if let Some(handler) = self.options.hint_handler.clone() {
+            for (hint, value) in doc::meta_hints(&tagged.meta, "html") {
+                if hint == CLASS_HINT {
+                    continue;
+                }
+                if let Handled::Replace(html) = handler(hint, value, tagged) {
+                    self.ser.write_html(&html)?;
+                    return Ok(());
+                }
             }
-            BlockInner::Par(inlines) => {
-                self.ser.write_text("\n")?;
-                self.ser.elem("p")?;
-                self.write_inlines(&inlines)?;
-                self.ser.end_elem()?;
+        }
+        let wrapped = self.begin_tagged("div", &tagged.meta)?;
+        self.write_blocks(&tagged.content)?;
+        self.end_tagged(&tagged.meta, wrapped)
+    }
+
+    /// Write a link. Links to labels are written whether or not the label is
+    /// defined; see `doc::undefined_labels`.
+    fn write_link(&mut self, link: &Link) -> Result<(), SerializerError> {
+        let href = match &link.target {
+            LinkTarget::Label(label) => fragment_href(label),
+            LinkTarget::URL(url) => url.clone(),
+        };
+        let mut attrs = vec![("href".to_owned(), href)];
+        // Without content, the label is already the link's text.
+        if let (Some(label), Some(_)) = (&link.label, &link.content) {
+            attrs.push(("aria-label".to_owned(), label.clone()));
+        }
+        attrs.extend(html_attrs(&link.attrs, &LINK_ATTRS));
+        self.ser.elem_attrs("a", &attrs)?;
+        self.write_inlines(&link.text())?;
+        self.ser.end_elem()?;
+        Ok(())
+    }
+
+    /// The URL to refer to a local file with, given its path relative to the
+    /// project root; a `data:` URI if `HtmlOptions::embed_assets` is set, and
+    /// otherwise its `linked_url`.
+    ///
+    /// Files which can't be embedded are reported, and linked to.
+    fn local_url(&mut self, path: &str, what: &str) -> Result<String, SerializerError> {
+        if let Some(embedder) = &mut self.embedder {
+            match embedder.data_uri(path) {
+                Ok(uri) => return Ok(uri),
+                Err(err) => self.report_asset("embed", &format!("{} {:?}", what, path), &err)?,
             }
-            BlockInner::Code(_) => todo!(),
-            BlockInner::Quote(quote) => {
-                self.ser.elem("blockquote")?;
-                self.write_blocks(quote)?;
-                self.ser.end_elem()?;
+        }
+        self.linked_url(path, what)
+    }
+
+    /// The URL to link to a local file with, given its path relative to the
+    /// project root; the URL of its copy if `HtmlOptions::assets` is given,
+    /// and otherwise its path relative to `HtmlOptions::output_dir`.
+    ///
+    /// Files which can't be copied are reported, and referred to by their
+    /// path.
+    fn linked_url(&mut self, path: &str, what: &str) -> Result<String, SerializerError> {
+        if let Some(assets) = self.options.assets.clone() {
+            let res = assets.borrow_mut().add(path);
+            match res {
+                Ok(url) => return Ok(url),
+                Err(err) => self.report_asset("copy", &format!("{} {:?}", what, path), &err)?,
             }
-            BlockInner::List(list) => self.write_list(list)?,
-            BlockInner::Heading(heading) => {
-                if !(1..6).contains(&heading.level) {
-                    return Err(HtmlError::from(heading).into());
+        }
+        Ok(match &self.options.output_dir {
+            Some(dir) => relative_url(dir, Path::new(path)),
+            None => path.to_owned(),
+        })
+    }
+
+    /// The content of a local stylesheet to embed, given its path relative to
+    /// the project root, if `HtmlOptions::embed_assets` is set; see
+    /// `AssetEmbedder::stylesheet`.
+    ///
+    /// Stylesheets which can't be embedded are reported, and `None` is
+    /// returned, as are the files they refer to which can't be embedded.
+    fn embed_stylesheet(&mut self, path: &str) -> Result<Option<String>, SerializerError> {
+        let res = match &mut self.embedder {
+            Some(embedder) => embedder.stylesheet(path),
+            None => return Ok(None),
+        };
+        match res {
+            Ok((css, errors)) => {
+                for (url, err) in errors {
+                    let what = format!("{:?} from stylesheet {:?}", url, path);
+                    self.report_asset("embed", &what, &err)?;
                 }
-                let tag_name = format!("h{}", heading.level);
-                let slug = slugify(&heading.text);
-                self.ser.elem_attrs(&tag_name, &[("id", &slug)])?;
+                Ok(Some(css))
+            }
+            Err(err) => {
+                self.report_asset("embed", &format!("stylesheet {:?}", path), &err)?;
+                Ok(None)
+            }
+        }
+    }
 
-                self.ser
-                    .elem_attrs("a", &[("href", format!("#{}", &slug))])?;
-                self.ser.end_elem()?;
+    /// Report a local file which couldn't be copied or embedded (the `verb`),
+    /// along with the section it's in.
+    fn report_asset(
+        &mut self,
+        verb: &str,
+        what: &str,
+        err: &dyn std::error::Error,
+    ) -> Result<(), SerializerError> {
+        let location = match &self.section {
+            Some(slug) => format!(" in section #{}", slug),
+            None => String::new(),
+        };
+        self.report(
+            WarningKind::MissingAsset,
+            format!("Couldn't {} {}{}: {}", verb, what, location, err),
+        )
+    }
 
-                self.write_inlines(&heading.text)?;
+    /// Write an image; see `local_url`.
+    fn write_image(&mut self, image: &Image) -> Result<(), SerializerError> {
+        let src = if image.is_url() {
+            image.src.clone()
+        } else {
+            self.local_url(&image.src, "image")?
+        };
+        let mut attrs = vec![("src".to_owned(), src)];
+        if let Some(alt) = &image.alt {
+            attrs.push(("alt".to_owned(), alt.clone()));
+        }
+        attrs.extend(html_attrs(&image.attrs, &IMG_ATTRS));
+        self.ser.elem_attrs("img", &attrs)?;
+        Ok(())
+    }
 
-                self.ser.end_elem()?;
-            }
-            BlockInner::Rule => {
-                self.ser.elem("hr")?;
+    /// Write a code block as `<pre><code>`, with a `language-*` class unless
+    /// its language is `plain`. Lines are separated by newlines and written
+    /// as they are, escaped but never reflowed. With line numbers or
+    /// highlighted lines, each line is wrapped in a `<span class="line">`
+    /// with its number, for stylesheets to show.
+    fn write_code(&mut self, code: &Code) -> Result<(), SerializerError> {
+        let mut attrs = html_attrs(&code.attrs, &[]);
+        if code.language != "plain" {
+            add_class(&mut attrs, &format!("language-{}", code.language));
+        }
+        let pre_attrs = self.class_attrs(ElementKind::Code, &[]);
+        self.ser.elem_attrs("pre", &pre_attrs)?;
+        self.ser.elem_attrs("code", &attrs)?;
+        // Lines are only wrapped if there's something to style them with.
+        let wrap_lines = code.line_numbers.is_some() || !code.highlight_lines.is_empty();
+        let start = code.first_line_number();
+        for (i, line) in code.lines.iter().enumerate() {
+            if i > 0 {
+                self.ser.write_text("\n")?;
             }
-            BlockInner::Math(math) => {
-                self.ser
-                    .write_html(&render_tex(&math.tex, MathMode::Display)?)?;
+            if wrap_lines {
+                let number = start + i as i32;
+                let class = if code.is_highlighted(number) {
+                    "line highlight"
+                } else {
+                    "line"
+                };
+                self.ser.elem_attrs(
+                    "span",
+                    &[
+                        ("class", class.to_owned()),
+                        ("data-line", number.to_string()),
+                    ],
+                )?;
+                self.write_inlines(line)?;
+                self.ser.end_elem()?;
+            } else {
+                self.write_inlines(line)?;
             }
-            BlockInner::Table(_) => todo!(),
-            BlockInner::Figure(_) => todo!(),
-            BlockInner::Defn(_) => todo!(),
-            BlockInner::TermList(_) => todo!(),
         }
+        self.ser.end_elem()?; // </code>
+        self.ser.end_elem()?; // </pre>
         Ok(())
     }
 
-    fn write_footnote(&mut self, footnote: Footnote) -> Result<(), SerializerError> {
-        let num = self.footnotes.len() + 1;
-        let id = format!("fn-{}", num);
-        let return_id = format!("fn-link-{}", num);
-        self.ser.elem("sup")?;
-        self.ser
-            .elem_attrs("a", &[("href", &format!("#{}", &id)), ("id", &return_id)])?;
-        self.ser.write_text(format!("[{}]", num))?;
-        self.ser.end_elem()?; // </a>
-        self.ser.end_elem()?; // </sup>
-        self.footnotes.push(MarkedFootnote {
-            id,
-            return_id,
-            content: footnote.content,
-        });
+    fn write_list(&mut self, list: &List) -> Result<(), SerializerError> {
+        let list_tag = match list.kind {
+            ListKind::Unordered => "ul",
+            ListKind::Ordered => "ol",
+        };
+        let attrs = self.class_attrs(ElementKind::List, &[]);
+        self.ser.elem_attrs(list_tag, &attrs)?;
+        for item in &list.items {
+            self.ser.elem("li")?;
+            self.write_blocks(&item.content)?;
+            self.ser.end_elem()?;
+        }
+        self.ser.end_elem()?;
         Ok(())
     }
 
-    fn finish_footnote(&mut self, footnote: MarkedFootnote) -> Result<(), SerializerError> {
-        // TODO: Write self-link.
-        self.write_blocks(footnote.content)?;
-        self.ser.write_text(" ")?;
-        self.ser
-            .elem_attrs("a", &[("href", format!("#{}", footnote.return_id))])?;
-        self.ser.write_text("↩")?;
+    /// Write a term list as a `<dl>`. Every item gets its own `<dt>` and
+    /// `<dd>`, even if it's empty or has the same term as the item before it.
+    fn write_term_list(&mut self, items: &[TermListItem]) -> Result<(), SerializerError> {
+        let attrs = self.class_attrs(ElementKind::TermList, &[]);
+        self.ser.elem_attrs("dl", &attrs)?;
+        for item in items {
+            self.ser.elem("dt")?;
+            self.write_inlines(&item.term)?;
+            self.ser.end_elem()?;
+            self.ser.elem("dd")?;
+            self.write_blocks(&item.content)?;
+            self.ser.end_elem()?;
+        }
         self.ser.end_elem()?;
         Ok(())
     }
 
-    fn finish_footnotes(&mut self) -> Result<(), SerializerError> {
-        if self.footnotes.is_empty() {
-            return Ok(());
+    /// Write a table. Column widths are given as styles on a `<colgroup>`, and
+    /// alignments as `align-*` classes on each cell; a cell's own alignment
+    /// overrides its column's.
+    fn write_table(&mut self, table: &Table) -> Result<(), SerializerError> {
+        let Table { columns, cells } = table;
+        let attrs = self.class_attrs(ElementKind::Table, &[]);
+        self.ser.elem_attrs("table", &attrs)?;
+        if !columns.is_empty() {
+            self.ser.elem("colgroup")?;
+            for column in columns {
+                match &column.width {
+                    Some(width) => self
+                        .ser
+                        .elem_attrs("col", &[("style", format!("width: {}", width))])?,
+                    None => self.ser.elem("col")?,
+                }
+            }
+            self.ser.end_elem()?;
+        }
+        self.ser.elem("tbody")?;
+        // How many more rows each column is covered by a cell from a row
+        // above, so cells are matched with the right columns.
+        let mut spanned: Vec<u32> = Vec::new();
+        for row in cells {
+            self.ser.elem("tr")?;
+            let mut next: Vec<u32> = spanned.iter().map(|rows| rows.saturating_sub(1)).collect();
+            let mut col = 0;
+            for cell in row {
+                while matches!(spanned.get(col), Some(&rows) if rows > 0) {
+                    col += 1;
+                }
+                let alignment = cell
+                    .alignment
+                    .or_else(|| columns.get(col).map(|column| column.alignment));
+                let span = cell.col_span.max(1) as usize;
+                if cell.row_span > 1 {
+                    if next.len() < col + span {
+                        next.resize(col + span, 0);
+                    }
+                    for rows in &mut next[col..col + span] {
+                        *rows = cell.row_span - 1;
+                    }
+                }
+                col += span;
+                let mut attrs = Vec::new();
+                if let Some(alignment) = alignment {
+                    attrs.push(("class", format!("align-{}", alignment.name())));
+                }
+                if cell.row_span > 1 {
+                    attrs.push(("rowspan", cell.row_span.to_string()));
+                }
+                if cell.col_span > 1 {
+                    attrs.push(("colspan", cell.col_span.to_string()));
+                }
+                self.ser.elem_attrs("td", &attrs)?;
+                self.write_blocks(&cell.content)?;
+                self.ser.end_elem()?;
+            }
+            self.ser.end_elem()?;
+            spanned = next;
         }
+        self.ser.end_elem()?; // </tbody>
+        self.ser.end_elem()?; // </table>
+        Ok(())
+    }
 
-        self.ser.elem_attrs("ol", &[("class", "footnotes")])?;
-        for footnote in mem::take(&mut self.footnotes) {
-            self.ser.elem_attrs("li", &[("id", &footnote.id)])?;
-            self.finish_footnote(footnote)?;
+    /// Write a figure, with its kind's name (e.g. `listing`) as its class and
+    /// its label as its id. The caption is omitted if it's empty.
+    fn write_figure(&mut self, figure: &Figure) -> Result<(), SerializerError> {
+        let mut attrs = Vec::new();
+        if let Some(label) = &figure.label {
+            attrs.push(("id", label.to_string()));
+        }
+        attrs.extend(self.class_attrs(ElementKind::Figure, &[figure.kind.name()]));
+        self.ser.elem_attrs("figure", &attrs)?;
+        self.write_blocks(&figure.content)?;
+        if !figure.caption.is_empty() {
+            self.ser.elem("figcaption")?;
+            self.write_inlines(&figure.caption)?;
             self.ser.end_elem()?;
         }
         self.ser.end_elem()?;
         Ok(())
     }
-}
 
-/// An error when serializing HTML.
-#[derive(Debug, Error)]
-pub enum HtmlError {
+    /// Write a definition as a `<section class="defn">`, with an id made from
+    /// its block's id (see `defn_id`). The defined name is written in a
+    /// `<dfn>` in the section's `<header>`, followed by the summary and, if
+    /// there is any, the rest of the content.
+    fn write_defn(&mut self, id: Id, defn: &Defn) -> Result<(), SerializerError> {
+        let defn_id = defn_id(id);
+        // Headings written by `write_doc` already avoid the id, but those
+        // written by `write_blocks` may not.
+        if let Some(heading) = self.heading_ids.get(&defn_id) {
+            return Err(HtmlError::ReservedId {
+                id: RefId::new(defn_id.as_str()).expect("definition ids are valid"),
+                heading: heading.clone(),
+                used_for: defn_description(defn),
+            }
+            .into());
+        }
+        self.reserve_defn_id(id, defn);
+        let mut attrs = vec![("id", defn_id)];
+        attrs.extend(self.class_attrs(ElementKind::Defn, &[]));
+        self.ser.elem_attrs("section", &attrs)?;
+        self.ser.elem("header")?;
+        self.ser.elem("dfn")?;
+        self.write_inlines(&defn.name)?;
+        self.ser.end_elem()?; // </dfn>
+        self.ser.end_elem()?; // </header>
+        self.ser.elem_attrs("div", &[("class", "defn-summary")])?;
+        self.write_blocks(&defn.summary)?;
+        self.ser.end_elem()?;
+        if !defn.content.is_empty() {
+            self.ser.elem_attrs("div", &[("class", "defn-content")])?;
+            self.write_blocks(&defn.content)?;
+            self.ser.end_elem()?;
+        }
+        self.ser.end_elem()?; // </section>
+        Ok(())
+    }
+
+    /// Keep headings from taking a definition's id; see `reserved_ids`.
+    fn reserve_defn_id(&mut self, id: Id, defn: &Defn) {
+        let id = defn_id(id);
+        self.heading_slugs.entry(id.clone()).or_insert(1);
+        self.reserved_ids
+            .entry(id)
+            .or_insert_with(|| defn_description(defn));
+    }
+
+    /// Write blocks as HTML, e.g. to a serializer created with `fragment`.
+    ///
+    /// Only the blocks are written, without the document structure around them
+    /// (e.g. `<head>` and `<body>`) which `Serializer::write_doc` writes.
+    /// Footnotes referred to in the blocks are numbered as they're written,
+    /// but the caller is responsible for listing them by calling
+    /// `finish_footnotes` afterwards; otherwise, their references link to
+    /// nothing.
+    ///
+    /// After an error, the output may have unclosed elements.
+    pub fn write_blocks(&mut self, blocks: &[Block]) -> Result<(), SerializerError> {
+        for block in blocks {
+            self.write_block(block)?;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: &Block) -> Result<(), SerializerError> {
+        self.nested(|this| this.write_block_inner(block))
+    }
+
+    fn write_block_inner(&mut self, block: &Block) -> Result<(), SerializerError> {
+        match &block.inner {
+            BlockInner::Plain(inlines) => {
+                self.write_inlines(inlines)?;
+            }
+            BlockInner::Par(inlines) => {
+                self.ser.write_text("\n")?;
+                let attrs = self.class_attrs(ElementKind::Paragraph, &[]);
+                self.ser.elem_attrs("p", &attrs)?;
+                self.write_inlines(inlines)?;
+                self.ser.end_elem()?;
+            }
+            BlockInner::Code(code) => self.write_code(code)?,
+            BlockInner::Quote(quote) => {
+                let attrs = self.class_attrs(ElementKind::Blockquote, &[]);
+                self.ser.elem_attrs("blockquote", &attrs)?;
+                self.write_blocks(quote)?;
+                self.ser.end_elem()?;
+            }
+            BlockInner::List(list) => self.write_list(list)?,
+            BlockInner::Heading(heading) => self.write_heading(block.id, heading)?,
+            BlockInner::Rule => {
+                let attrs = self.class_attrs(ElementKind::Rule, &[]);
+                self.ser.elem_attrs("hr", &attrs)?;
+            }
+            BlockInner::Math(math) => self.write_math(&math.tex, &math.alt, MathMode::Display)?,
+            BlockInner::Table(table) => self.write_table(table)?,
+            BlockInner::Figure(figure) => self.write_figure(figure)?,
+            BlockInner::Defn(defn) => self.write_defn(block.id, defn)?,
+            BlockInner::TermList(items) => self.write_term_list(items)?,
+            BlockInner::Raw(raw) if raw.format == self.format_name() => {
+                self.ser.write_html(&raw.content)?;
+            }
+            BlockInner::Raw(_) => {}
+            BlockInner::Tagged(tagged) => self.write_tagged_blocks(tagged)?,
+        }
+        Ok(())
+    }
+
+    /// Write rendered math. If there's alt text, it's given as the label of a
+    /// wrapping element, and the rendered math is hidden from screen readers.
+    ///
+    /// Math which fails to render is reported, and written as a `<code>`
+    /// element with the class `math-error`.
+    fn write_math(
+        &mut self,
+        tex: &str,
+        alt: &Option<String>,
+        mode: MathMode,
+    ) -> Result<(), SerializerError> {
+        let tag_name = match mode {
+            MathMode::Inline => "span",
+            MathMode::Display => "div",
+        };
+        let html = match self.render_math_cached(tex, mode) {
+            Ok(html) => html,
+            Err(err) => {
+                self.report(
+                    WarningKind::Math,
+                    format!("Failed to render math {:?}: {}", tex, err),
+                )?;
+                self.ser.elem_attrs("code", &[("class", "math-error")])?;
+                self.ser.write_text(tex)?;
+                self.ser.end_elem()?;
+                return Ok(());
+            }
+        };
+        let alt = match alt {
+            Some(alt) => alt,
+            None => {
+                self.ser.write_html(&html)?;
+                return Ok(());
+            }
+        };
+        self.ser.elem_attrs(
+            tag_name,
+            &[("class", "math"), ("role", "img"), ("aria-label", alt)],
+        )?;
+        self.ser.elem_attrs(tag_name, &[("aria-hidden", "true")])?;
+        self.ser.write_html(&html)?;
+        self.ser.end_elem()?;
+        self.ser.end_elem()?;
+        Ok(())
+    }
+
+    /// Render math, or reuse the rendering of an identical formula. Failures
+    /// aren't cached.
+    fn render_math_cached(&mut self, tex: &str, mode: MathMode) -> Result<String, MathError> {
+        let key = (tex.to_owned(), mode);
+        if let Some(html) = self.math_cache.get(&key) {
+            return Ok(html.clone());
+        }
+        let html = self.math_renderer.render(tex, mode)?;
+        self.math_cache.insert(key, html.clone());
+        Ok(html)
+    }
+
+    /// The element for a heading of the given level; see `write_heading`.
+    fn heading_tag(&self, level: i32) -> String {
+        let html_level = level
+            .max(1)
+            .saturating_add(self.options.heading_offset)
+            .clamp(1, 6);
+        format!("h{}", html_level)
+    }
+
+    /// Write a heading. Levels 1 to 6 are `<h1>` to `<h6>`; the levels above
+    /// sections (a document's title, parts, and chapters, at -3 to -1, and 0)
+    /// are `<h1>`s, with a class naming the level. The HTML level is then
+    /// shifted by `HtmlOptions::heading_offset`; the class isn't.
+    fn write_heading(&mut self, block_id: Id, heading: &Heading) -> Result<(), SerializerError> {
+        if !(-3..=6).contains(&heading.level) {
+            return Err(HtmlError::from(heading.clone()).into());
+        }
+        let tag_name = self.heading_tag(heading.level);
+        let class = match heading.level {
+            -3 => Some("title"),
+            -2 => Some("part"),
+            -1 => Some("chapter"),
+            _ => None,
+        };
+        let slug = heading.anchor();
+        let id = match self.pending_heading_ids.remove(&block_id) {
+            Some(id) => id,
+            // E.g. a heading written with `write_blocks`.
+            None => heading_id(heading, &mut self.heading_slugs),
+        };
+        // Generated ids skip the explicit ids reserved up front, so only an
+        // explicit id can repeat another (or, for a heading written with
+        // `write_blocks`, an earlier generated one).
+        let text =
+            doc::inlines_to_plain_text(&heading.text).unwrap_or_else(|_| slugify(&heading.text));
+        match heading.attrs.get(doc::ID_KEY) {
+            Some(explicit) if explicit != slug.as_str() => self.report(
+                WarningKind::InvalidId,
+                format!(
+                    "Heading {:?} has the invalid id {:?}, which is written as {:?}",
+                    text,
+                    explicit,
+                    slug.as_str()
+                ),
+            )?,
+            _ => {}
+        }
+        if let Some(used_for) = self.reserved_ids.get(&id) {
+            return Err(HtmlError::ReservedId {
+                id: slug,
+                heading: text,
+                used_for: used_for.clone(),
+            }
+            .into());
+        }
+        if let Some(first) = self.heading_ids.insert(id.clone(), text.clone()) {
+            return Err(HtmlError::DuplicateId {
+                id: slug,
+                first,
+                second: text,
+            }
+            .into());
+        }
+        self.section = Some(id.clone());
+        let mut attrs = vec![("id".to_owned(), id.clone())];
+        let mut extra = heading.attrs.clone();
+        extra.remove(doc::ID_KEY);
+        attrs.extend(html_attrs(&extra, &[]));
+        if let Some(class) = class {
+            add_class(&mut attrs, class);
+        }
+        add_class(&mut attrs, self.options.classes.get(ElementKind::Heading));
+        self.ser.elem_attrs(&tag_name, &attrs)?;
+
+        match self.options.heading_anchors {
+            HeadingAnchors::None => {
+                self.write_inlines(&heading.text)?;
+            }
+            HeadingAnchors::WrapHeading => {
+                self.ser.elem_attrs("a", &[("href", fragment_href(&id))])?;
+                self.write_inlines(&heading.text)?;
+                self.ser.end_elem()?;
+            }
+            HeadingAnchors::TrailingMarker => {
+                self.write_inlines(&heading.text)?;
+                let label = self.options.anchor_label.clone();
+                self.write_anchor_marker(&id, label)?;
+            }
+        }
+
+        self.ser.end_elem()?;
+        Ok(())
+    }
+
+    /// Write the marker linking to the element with the given id, for
+    /// `HeadingAnchors::TrailingMarker`, labeled for screen readers with
+    /// `label`. Without a label, the link is hidden from screen readers, so
+    /// it's also kept out of the tab order.
+    fn write_anchor_marker(
+        &mut self,
+        id: &str,
+        label: Option<String>,
+    ) -> Result<(), SerializerError> {
+        let mut attrs = vec![
+            ("href", fragment_href(id)),
+            ("class", self.options.anchor_class.clone()),
+        ];
+        match label {
+            Some(label) => attrs.push(("aria-label", label)),
+            None => {
+                attrs.push(("aria-hidden", "true".to_owned()));
+                attrs.push(("tabindex", "-1".to_owned()));
+            }
+        }
+        self.ser.elem_attrs("a", &attrs)?;
+        self.ser.write_text(&self.options.anchor_marker)?;
+        self.ser.end_elem()?;
+        Ok(())
+    }
+
+    fn write_footnote(&mut self, footnote: &Footnote) -> Result<(), SerializerError> {
+        self.footnote_count += 1;
+        self.footnote_number += 1;
+        let num = self.footnote_number;
+        let id = format!("fn-{}", self.footnote_count);
+        let return_id = format!("fn-link-{}", self.footnote_count);
+        self.ser.elem("sup")?;
+        self.ser.elem_attrs(
+            "a",
+            &[
+                ("href", fragment_href(&id)),
+                ("id", return_id.clone()),
+                ("role", "doc-noteref".to_owned()),
+                ("aria-label", format!("Footnote {}", num)),
+            ],
+        )?;
+        self.ser
+            .write_text(self.options.footnote_markers.marker(num))?;
+        self.ser.end_elem()?; // </a>
+        self.ser.end_elem()?; // </sup>
+
+        // Footnotes are listed later (see `finish_footnotes`), possibly in
+        // another call after `write_blocks` has returned, so their content is
+        // written now and recorded to be replayed then. The footnote's place
+        // in the list is taken first so footnotes it refers to come after it.
+        let index = self.footnotes.len();
+        self.footnotes.push(MarkedFootnote {
+            num,
+            id,
+            return_id,
+            content: Default::default(),
+        });
+        self.ser.start_recording();
+        let result = self.write_blocks(&footnote.content);
+        let content = self.ser.finish_recording();
+        result?;
+        self.footnotes[index].content = content;
+        Ok(())
+    }
+
+    /// Write a footnote's content, followed by a link back to where it's
+    /// referenced and, with `HeadingAnchors::TrailingMarker`, a link to the
+    /// footnote itself like those after headings.
+    fn finish_footnote(&mut self, footnote: MarkedFootnote) -> Result<(), SerializerError> {
+        self.ser.replay(footnote.content)?;
+        self.ser.write_text(" ")?;
+        self.ser.elem_attrs(
+            "a",
+            &[
+                ("href", fragment_href(&footnote.return_id)),
+                ("role", "doc-backlink".to_owned()),
+                ("aria-label", format!("Back to reference {}", footnote.num)),
+            ],
+        )?;
+        self.ser.write_text("↩")?;
+        self.ser.end_elem()?;
+        if self.options.heading_anchors == HeadingAnchors::TrailingMarker {
+            // Footnotes get their own label, so they aren't announced as
+            // sections; they're still hidden without an `anchor_label`.
+            let num = footnote.num;
+            let label = self
+                .options
+                .anchor_label
+                .as_ref()
+                .map(|_| format!("Link to footnote {}", num));
+            self.write_anchor_marker(&footnote.id, label)?;
+        }
+        Ok(())
+    }
+
+    /// List the footnotes referred to since they were last listed, as an
+    /// `<ol class="footnotes">`, and forget them; does nothing if there
+    /// aren't any. `Serializer::write_doc` calls this according to
+    /// `HtmlOptions::footnote_placement`; after `write_blocks` and
+    /// `write_inlines`, it's up to the caller.
+    pub fn finish_footnotes(&mut self) -> Result<(), SerializerError> {
+        if self.footnotes.is_empty() {
+            return Ok(());
+        }
+
+        let footnotes = mem::take(&mut self.footnotes);
+        let label = self
+            .options
+            .footnote_heading
+            .clone()
+            .unwrap_or_else(|| "Footnotes".to_owned());
+        if self.options.a11y_landmarks {
+            self.ser.elem_attrs(
+                "section",
+                &[("role", "doc-endnotes"), ("aria-label", &label)],
+            )?;
+        }
+        if let Some(heading) = &self.options.footnote_heading {
+            let attrs = self.class_attrs(ElementKind::FootnoteHeading, &[]);
+            // The footnotes are a subsection of the section they end.
+            let tag_name = self.heading_tag(self.options.footnote_section_level.saturating_add(1));
+            self.ser.elem_attrs(&tag_name, &attrs)?;
+            self.ser.write_text(heading)?;
+            self.ser.end_elem()?;
+        }
+        let mut ol_attrs = self.class_attrs(ElementKind::FootnoteList, &[]);
+        if footnotes[0].num != 1 {
+            ol_attrs.push(("start", footnotes[0].num.to_string()));
+        }
+        self.ser.elem_attrs("ol", &ol_attrs)?;
+        let numeric = matches!(self.options.footnote_markers, FootnoteMarkers::Numeric);
+        let mut next = footnotes[0].num;
+        for footnote in footnotes {
+            let mut attrs = vec![("id", footnote.id.clone())];
+            // Numbers restart mid-list when sections restart numbering but
+            // footnotes aren't listed per section.
+            if footnote.num != next {
+                attrs.push(("value", footnote.num.to_string()));
+            }
+            if !numeric {
+                let marker = self.options.footnote_markers.marker(footnote.num);
+                attrs.push((
+                    "style",
+                    format!("list-style-type: \"{} \"", css_string_escape(&marker)),
+                ));
+            }
+            self.ser.elem_attrs("li", &attrs)?;
+            next = footnote.num + 1;
+            self.finish_footnote(footnote)?;
+            self.ser.end_elem()?;
+        }
+        self.ser.end_elem()?; // </ol>
+        if self.options.a11y_landmarks {
+            self.ser.end_elem()?; // </section>
+        }
+        Ok(())
+    }
+}
+
+/// Write blocks as an HTML snippet, followed by a list of the footnotes they
+/// refer to, if any; see `HtmlSerializer::write_blocks`. Warnings are
+/// discarded; to keep them, use an `HtmlSerializer` created with
+/// `HtmlSerializer::fragment`.
+pub fn blocks_to_string(
+    blocks: &[Block],
+    options: &HtmlOptions,
+) -> Result<String, SerializerError> {
+    let mut out = Vec::new();
+    let mut ser = HtmlSerializer::fragment(&mut out, options.clone());
+    ser.write_blocks(blocks)?;
+    ser.finish_footnotes()?;
+    drop(ser);
+    // The serializer only writes `str`s.
+    Ok(String::from_utf8(out).expect("HTML output is UTF-8"))
+}
+
+/// The `friendly_html` options for the given `HtmlOptions`.
+fn fh_options(options: &HtmlOptions) -> fh::Options {
+    fh::Options {
+        scripting_enabled: options.scripting_enabled,
+        indent: options.indent,
+        ..Default::default()
+    }
+}
+
+/// The ids a document's headings are written with (see `heading_id`), with
+/// their blocks' ids, in document order, starting with those in its
+/// abstract; the headings in content which isn't written, like draft-only
+/// content outside of drafts, are skipped. Headings in other blocks are
+/// included.
+pub(super) fn heading_ids<'d>(
+    collections: &'d Collections,
+    content: &'d [Block],
+    draft: bool,
+    ids: &mut HashMap<String, usize>,
+) -> Vec<(Id, &'d Heading, String)> {
+    // Definitions' ids are written on their sections, so headings skip them,
+    // even headings before the definitions.
+    for (id, _) in defns(collections, content) {
+        ids.entry(defn_id(id)).or_insert(1);
+    }
+    reserve_heading_ids(abstract_blocks(collections).chain(iter::once(content)), ids);
+    let mut stack: Vec<_> = collections
+        .get(doc::ABSTRACT_CHANNEL)
+        .into_iter()
+        .flatten()
+        .flat_map(|blocks| blocks.iter())
+        .chain(content)
+        .map(DocNode::Block)
+        .collect();
+    stack.reverse();
+    let mut children = Vec::new();
+    let mut ret = Vec::new();
+    while let Some(node) = stack.pop() {
+        match node {
+            DocNode::Block(Block {
+                id,
+                inner: BlockInner::Heading(heading),
+            }) => ret.push((*id, heading, heading_id(heading, ids))),
+            DocNode::Block(Block {
+                inner: BlockInner::Tagged(TaggedBlocks { meta, .. }),
+                ..
+            })
+            | DocNode::Inline(Inline::Tagged(TaggedInlines { meta, .. }))
+                if !doc::meta_includes(meta, "html", draft) =>
+            {
+                continue;
+            }
+            _ => {}
+        }
+        node.children(&mut children);
+        stack.extend(children.drain(..).rev());
+    }
+    ret
+}
+
+/// The definitions in a document's abstract and `content`, including those
+/// in other blocks, with their blocks' ids.
+fn defns<'d>(
+    collections: &'d Collections,
+    content: &'d [Block],
+) -> impl Iterator<Item = (Id, &'d Defn)> {
+    abstract_blocks(collections)
+        .chain(iter::once(content))
+        .flat_map(doc::nodes)
+        .filter_map(|node| match node {
+            DocNode::Block(Block {
+                id,
+                inner: BlockInner::Defn(defn),
+            }) => Some((*id, defn)),
+            _ => None,
+        })
+}
+
+/// The `id` of the section a definition is written in, made from its block's
+/// id.
+fn defn_id(id: Id) -> String {
+    format!("defn-{}", id)
+}
+
+/// What a definition's id is used for, in errors.
+fn defn_description(defn: &Defn) -> String {
+    let name = doc::inlines_to_plain_text(&defn.name).unwrap_or_else(|_| slugify(&defn.name));
+    format!("the definition of {:?}", name)
+}
+
+/// The `id` of the `<main>` landmark; see `HtmlOptions::a11y_landmarks`.
+const MAIN_ID: &str = "main";
+
+/// The ids a document written with `options` has on elements other than
+/// headings, with what they're used for. Generated heading ids skip them (see
+/// `heading_id`), and a heading with one of them as its explicit id is an
+/// error.
+pub(super) fn reserved_ids(options: &HtmlOptions) -> Vec<(&'static str, &'static str)> {
+    if options.a11y_landmarks {
+        vec![(MAIN_ID, "the <main> landmark")]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Attributes which are written as-is on any element; other attributes are
+/// written as `data-*` attributes.
+const GLOBAL_ATTRS: [&str; 4] = ["class", "title", "lang", "dir"];
+
+/// Attributes which are written as-is on links.
+const LINK_ATTRS: [&str; 5] = ["rel", "target", "hreflang", "download", "type"];
+
+/// Attributes which are written as-is on images.
+const IMG_ATTRS: [&str; 3] = ["width", "height", "loading"];
+
+/// The relative URL of `path` from the directory `dir`, where both are
+/// relative to the same directory.
+fn relative_url(dir: &Path, path: &Path) -> String {
+    let dir: Vec<Component> = dir
+        .components()
+        .filter(|c| c != &Component::CurDir)
+        .collect();
+    let path: Vec<Component> = path
+        .components()
+        .filter(|c| c != &Component::CurDir)
+        .collect();
+    let common = dir.iter().zip(&path).take_while(|(a, b)| a == b).count();
+    iter::repeat_n(Cow::Borrowed(".."), dir.len() - common)
+        .chain(
+            path[common..]
+                .iter()
+                .map(|c| c.as_os_str().to_string_lossy()),
+        )
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Translate a block or inline's attributes into HTML attributes.
+///
+/// Attributes in `GLOBAL_ATTRS` or `recognized` are passed through, and others
+/// are prefixed with `data-`, with any characters which aren't allowed in
+/// attribute names replaced by `-`. The result is sorted for stable output.
+fn html_attrs(meta: &Meta, recognized: &[&str]) -> Vec<(String, String)> {
+    let mut ret: Vec<_> = meta
+        .iter()
+        .map(|(name, value)| {
+            let name =
+                if GLOBAL_ATTRS.contains(&name.as_str()) || recognized.contains(&name.as_str()) {
+                    name.clone()
+                } else {
+                    format!("data-{}", sanitize_attr_name(name))
+                };
+            (name, value.clone())
+        })
+        .collect();
+    ret.sort();
+    ret
+}
+
+/// Replace characters which aren't allowed in an attribute name with `-`.
+fn sanitize_attr_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            let mut buf = [0; 4];
+            if fh::is_valid_attr_name(c.encode_utf8(&mut buf)) {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Escape text for a double-quoted CSS string.
+fn css_string_escape(text: &str) -> String {
+    escape_with(text, |c| match c {
+        '"' => Some("\\\""),
+        '\\' => Some("\\\\"),
+        '\n' => Some("\\a "),
+        _ => None,
+    })
+}
+
+/// An `href` linking to the element with the given `id` in the same document.
+fn fragment_href(id: &str) -> String {
+    format!("#{}", percent_encode_fragment(id))
+}
+
+/// Add a class to a list of attributes, merging it with an existing `class`
+/// attribute if one is present. Empty classes aren't added.
+fn add_class(attrs: &mut Vec<(String, String)>, class: &str) {
+    if class.is_empty() {
+        return;
+    }
+    match attrs.iter_mut().find(|(name, _)| name == "class") {
+        Some((_, classes)) => {
+            classes.insert(0, ' ');
+            classes.insert_str(0, class);
+        }
+        None => attrs.push(("class".to_owned(), class.to_owned())),
+    }
+}
+
+/// An error when serializing HTML.
+#[derive(Debug, Error)]
+pub enum HtmlError {
     /// A bad document heading, in particular an unsupported level.
     #[error("Bad heading: {0:?}")]
     BadHeading(Heading),
+
+    /// A heading was given an explicit id (see `doc::ID_KEY`) which another
+    /// heading already has, explicitly or not.
+    #[error("Headings {first:?} and {second:?} both have the id {:?}", id.as_str())]
+    DuplicateId {
+        /// The id.
+        id: RefId,
+        /// The text of the first heading with the id.
+        first: String,
+        /// The text of the second heading with the id.
+        second: String,
+    },
+
+    /// A heading was given an explicit id which is written on another
+    /// element; see `reserved_ids`.
+    #[error("Heading {heading:?} has the id {:?}, which is used for {used_for}", id.as_str())]
+    ReservedId {
+        /// The id.
+        id: RefId,
+        /// The text of the heading.
+        heading: String,
+        /// What else the id is used for.
+        used_for: String,
+    },
+}
+
+impl HtmlError {
+    /// The stable code for this error; see `diagnostic::ErrorCode`.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::BadHeading(_) => ErrorCode::BadHeading,
+            Self::DuplicateId { .. } | Self::ReservedId { .. } => ErrorCode::DuplicateId,
+        }
+    }
 }
 
 impl From<Heading> for HtmlError {
@@ -286,9 +1698,9 @@ impl From<Heading> for HtmlError {
     }
 }
 
-impl Into<SerializerError> for HtmlError {
-    fn into(self) -> SerializerError {
-        SerializerError::Other(Box::new(self))
+impl From<HtmlError> for SerializerError {
+    fn from(err: HtmlError) -> Self {
+        SerializerError::Other(Box::new(err))
     }
 }
 
@@ -300,3 +1712,2416 @@ impl From<fh::SerializeError> for SerializerError {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::cmd::Strictness;
+    use crate::doc::{Blocks, Id, InlineCode, LineNumbers, RawBlock, RawInline, ONLY_FORMAT_KEY};
+    use crate::ser::test_util::{assert_balanced, block, heading, heading_with};
+    use crate::ser::EmbedLimits;
+    use fh::test_util::FailAfter;
+
+    fn to_html(content: Vec<BlockInner>) -> String {
+        to_html_with(Default::default(), content)
+    }
+
+    fn to_html_with(options: HtmlOptions, content: Vec<BlockInner>) -> String {
+        let doc = Doc::from_content(
+            content
+                .into_iter()
+                .enumerate()
+                .map(|(id, inner)| Block {
+                    id: Id::from(id),
+                    inner,
+                })
+                .collect::<Vec<_>>()
+                .into(),
+        );
+        let mut out = Vec::new();
+        HtmlSerializer::with_options(&mut out, options)
+            .unwrap()
+            .write_doc(&doc)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn attrs(pairs: &[(&str, &str)]) -> Meta {
+        pairs
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_heading_attrs() {
+        let html = to_html(vec![
+            heading_with(0, 1, "Intro", &[("toc", "false"), ("class", "big")]).inner,
+        ]);
+        assert!(html.contains(r#"<h1 id="Intro" class="big" data-toc="false">"#));
+    }
+
+    #[test]
+    fn test_heading_ids() {
+        let html = to_html(vec![
+            heading_with(0, 1, "Introduction, revised", &[("id", "intro")]).inner,
+        ]);
+        assert!(html.contains(r#"<h1 id="intro">"#));
+        assert!(!html.contains("data-id"));
+
+        let mut out = Vec::new();
+        let err = HtmlSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&Doc::from_content(
+                vec![
+                    heading_with(0, 1, "Intro", &[("id", "intro")]),
+                    heading_with(1, 1, "Introduction", &[("id", "intro")]),
+                ]
+                .into(),
+            ))
+            .unwrap_err();
+        assert_eq!(
+            r#"Headings "Intro" and "Introduction" both have the id "intro""#,
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_heading_ids_generated() {
+        let intro = || heading(0, 1, "Intro").inner;
+        let explicit = |text: &str, id: &str| heading_with(0, 1, text, &[("id", id)]).inner;
+        // Generated ids skip explicit ids, even those of later headings.
+        let html = to_html(vec![intro(), explicit("Introduction", "Intro")]);
+        assert!(html.contains(r#"<h1 id="Intro-1">Intro<"#));
+        assert!(html.contains(r#"<h1 id="Intro">Introduction<"#));
+        let html = to_html(vec![intro(), intro(), explicit("B", "Intro-1")]);
+        assert!(html.contains(r#"<h1 id="Intro">Intro<"#));
+        assert!(html.contains(r#"<h1 id="Intro-2">Intro<"#));
+        assert!(html.contains(r#"<h1 id="Intro-1">B<"#));
+        // A later slug avoids an earlier explicit id.
+        let html = to_html(vec![explicit("Introduction", "Intro"), intro()]);
+        assert!(html.contains(r#"<h1 id="Intro-1">"#));
+    }
+
+    #[test]
+    fn test_heading_ids_reserved() {
+        let main = || heading(0, 1, "main").inner;
+        let html = to_html(vec![main()]);
+        assert!(html.contains(r#"<main id="main">"#));
+        assert!(html.contains(r#"<h1 id="main-1">"#));
+
+        let err = HtmlSerializer::new(Vec::new())
+            .unwrap()
+            .write_doc(&Doc::from_content(
+                vec![heading_with(0, 1, "main", &[("id", "main")])].into(),
+            ))
+            .unwrap_err();
+        assert_eq!(
+            r#"Heading "main" has the id "main", which is used for the <main> landmark"#,
+            err.to_string()
+        );
+
+        // Without the landmark, the id is free.
+        let html = to_html_with(
+            HtmlOptions {
+                a11y_landmarks: false,
+                ..Default::default()
+            },
+            vec![main()],
+        );
+        assert!(html.contains(r#"<h1 id="main">"#));
+    }
+
+    #[test]
+    fn test_heading_ids_invalid() {
+        let blocks = vec![heading_with(0, 1, "Intro", &[("id", "my intro")])];
+        let write = |strictness| {
+            let mut out = Vec::new();
+            let mut ser = HtmlSerializer::fragment(
+                &mut out,
+                HtmlOptions {
+                    strictness,
+                    ..Default::default()
+                },
+            );
+            let result = ser.write_blocks(&blocks);
+            let warnings = ser.take_warnings();
+            drop(ser);
+            result.map(|()| (String::from_utf8(out).unwrap(), warnings))
+        };
+
+        let (html, warnings) = write(Strictness::Warn).unwrap();
+        assert!(html.contains(r#"<h1 id="my-intro">"#), "{}", html);
+        let message =
+            r#"Heading "Intro" has the invalid id "my intro", which is written as "my-intro""#;
+        assert_eq!(
+            vec![Warning::new(WarningKind::InvalidId, message.into())],
+            warnings
+        );
+        match write(Strictness::Strict) {
+            Err(SerializerError::Strict(warning)) => assert_eq!(message, warning.message),
+            result => panic!("Expected a strictness error, got {:?}", result),
+        }
+    }
+
+    fn heading_html(heading_anchors: HeadingAnchors) -> String {
+        to_html_with(
+            HtmlOptions {
+                heading_anchors,
+                ..Default::default()
+            },
+            vec![heading(0, 2, "Intro").inner],
+        )
+    }
+
+    #[test]
+    fn test_heading_offset() {
+        let html = |level: i32, heading_offset: i32| {
+            to_html_with(
+                HtmlOptions {
+                    heading_offset,
+                    heading_anchors: HeadingAnchors::None,
+                    ..Default::default()
+                },
+                vec![heading(0, level, "Intro").inner],
+            )
+        };
+        assert!(html(1, 1).contains(r#"<h2 id="Intro">Intro</h2>"#));
+        assert!(html(6, 1).contains(r#"<h6 id="Intro">Intro</h6>"#));
+        assert!(html(3, 10).contains(r#"<h6 id="Intro">Intro</h6>"#));
+        assert!(html(2, -1).contains(r#"<h1 id="Intro">Intro</h1>"#));
+        assert!(html(1, -1).contains(r#"<h1 id="Intro">Intro</h1>"#));
+        assert!(html(0, 1).contains(r#"<h2 id="Intro">Intro</h2>"#));
+        assert!(html(-1, 2).contains(r#"<h3 id="Intro" class="chapter">Intro</h3>"#));
+        assert!(html(-3, 1).contains(r#"<h2 id="Intro" class="title">Intro</h2>"#));
+    }
+
+    #[test]
+    fn test_heading_dedup() {
+        let h2 = |text: &str| heading(0, 2, text).inner;
+        let html = to_html(vec![
+            h2("Intro"),
+            h2("Intro"),
+            h2("Intro-2"),
+            h2("Intro"),
+            h2(""),
+            h2(" "),
+            heading_with(0, 2, "Intro", &[("id", "Intro-4")]).inner,
+            h2("Intro"),
+        ]);
+        let ids: Vec<_> = html
+            .split("<h2 id=\"")
+            .skip(1)
+            .map(|rest| &rest[..rest.find('"').unwrap()])
+            .collect();
+        assert_eq!(
+            vec![
+                "Intro",
+                "Intro-1",
+                "Intro-2",
+                "Intro-3",
+                "section",
+                "section-1",
+                "Intro-4",
+                "Intro-5",
+            ],
+            ids
+        );
+        for id in ids {
+            assert!(html.contains(&format!(r##"<a href="#{}" class="anchor""##, id)));
+        }
+    }
+
+    #[test]
+    fn test_toc() {
+        let content = || {
+            vec![
+                heading(0, 1, "A").inner,
+                heading(0, 2, "B").inner,
+                heading(0, 3, "C").inner,
+                heading_with(0, 2, "Hidden", &[("toc", "false")]).inner,
+                heading(0, 1, "A").inner,
+                heading(0, 3, "D").inner,
+            ]
+        };
+        let html = to_html_with(
+            HtmlOptions {
+                toc: true,
+                ..Default::default()
+            },
+            content(),
+        );
+        assert!(html.contains(concat!(
+            r#"<main id="main">"#,
+            "\n",
+            r#"<nav class="toc" aria-label="Contents">"#,
+            r##"<ol><li><a href="#A">A</a>"##,
+            r##"<ol><li><a href="#B">B</a>"##,
+            r##"<ol><li><a href="#C">C</a></li></ol>"##,
+            "</li></ol></li>",
+            r##"<li><a href="#A-1">A</a>"##,
+            r##"<ol><li><a href="#D">D</a></li></ol>"##,
+            "</li></ol></nav>",
+        )));
+        assert!(html.contains(r#"<h1 id="A-1">"#));
+
+        assert!(!to_html(content()).contains("<nav"));
+    }
+
+    #[test]
+    fn test_toc_nested_headings() {
+        let html = to_html_with(
+            HtmlOptions {
+                toc: true,
+                heading_anchors: HeadingAnchors::None,
+                ..Default::default()
+            },
+            vec![
+                BlockInner::Quote(vec![heading(10, 1, "A")].into()),
+                heading(10, 1, "A").inner,
+            ],
+        );
+        // Nested headings are listed, and the links match the ids written.
+        assert!(html.contains(concat!(
+            r#"<nav class="toc" aria-label="Contents">"#,
+            r##"<ol><li><a href="#A">A</a></li>"##,
+            r##"<li><a href="#A-1">A</a></li></ol></nav>"##,
+        )));
+        assert!(html.contains(r#"<blockquote><h1 id="A">A</h1></blockquote>"#));
+        assert!(html.contains(r#"<h1 id="A-1">A</h1>"#));
+    }
+
+    #[test]
+    fn test_heading_anchors() {
+        assert!(heading_html(HeadingAnchors::None).contains(r#"<h2 id="Intro">Intro</h2>"#));
+        assert!(heading_html(HeadingAnchors::WrapHeading)
+            .contains(r##"<h2 id="Intro"><a href="#Intro">Intro</a></h2>"##));
+        assert!(
+            heading_html(HeadingAnchors::TrailingMarker).contains(concat!(
+                r#"<h2 id="Intro">Intro"#,
+                r##"<a href="#Intro" class="anchor" aria-label="Link to this section">¶</a></h2>"##
+            ))
+        );
+
+        // Anchors have content, and link to their headings.
+        for anchors in &[HeadingAnchors::WrapHeading, HeadingAnchors::TrailingMarker] {
+            let tokens = fh::tokenize(&heading_html(*anchors)).unwrap();
+            let attr = |tok: &fh::HtmlToken, key: &str| match tok {
+                fh::HtmlToken::StartTag { attrs, .. } => {
+                    attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone())
+                }
+                _ => None,
+            };
+            let heading = tokens
+                .iter()
+                .position(|tok| matches!(tok, fh::HtmlToken::StartTag { name, .. } if name == "h2"))
+                .unwrap();
+            let anchor = heading
+                + tokens[heading..]
+                    .iter()
+                    .position(
+                        |tok| matches!(tok, fh::HtmlToken::StartTag { name, .. } if name == "a"),
+                    )
+                    .unwrap();
+            assert_eq!(
+                Some(format!("#{}", attr(&tokens[heading], "id").unwrap())),
+                attr(&tokens[anchor], "href"),
+                "{:?}",
+                anchors
+            );
+            assert!(
+                matches!(&tokens[anchor + 1], fh::HtmlToken::Text(text) if !text.trim().is_empty()),
+                "{:?}",
+                anchors
+            );
+        }
+
+        let html = to_html_with(
+            HtmlOptions {
+                anchor_marker: "#".into(),
+                anchor_class: "self-link".into(),
+                anchor_label: None,
+                ..Default::default()
+            },
+            vec![heading(0, 1, "Intro").inner],
+        );
+        assert!(html.contains(
+            r##"<a href="#Intro" class="self-link" aria-hidden="true" tabindex="-1">#</a>"##
+        ));
+    }
+
+    #[test]
+    fn test_table() {
+        use crate::doc::{Alignment, Length, RelLength, TableCell, TableColumn};
+
+        let column = |alignment, width| TableColumn { alignment, width };
+        let cell = |text: &str| TableCell {
+            content: vec![Block {
+                id: Id::from(0),
+                inner: BlockInner::Plain(vec![Inline::Text(text.into())]),
+            }]
+            .into(),
+            ..Default::default()
+        };
+        let html = to_html(vec![BlockInner::Table(Table {
+            columns: vec![
+                column(
+                    Alignment::Left,
+                    Some(Length::Relative(RelLength::Percent(30.0))),
+                ),
+                column(Alignment::Center, None),
+                column(Alignment::Right, None),
+            ],
+            cells: vec![vec![
+                cell("a"),
+                TableCell {
+                    alignment: Some(Alignment::Right),
+                    ..cell("b")
+                },
+                cell("c"),
+            ]],
+        })]);
+        assert!(html.contains(concat!(
+            "<table><colgroup>",
+            r#"<col style="width: 30%"><col><col>"#,
+            "</colgroup><tbody><tr>",
+            r#"<td class="align-left">a</td>"#,
+            r#"<td class="align-right">b</td>"#,
+            r#"<td class="align-right">c</td>"#,
+            "</tr></tbody></table>",
+        )));
+    }
+
+    #[test]
+    fn test_table_2x2() {
+        use crate::doc::TableCell;
+
+        let cell = |text: &str| TableCell {
+            content: vec![Block {
+                id: Id::from(0),
+                inner: BlockInner::Plain(vec![Inline::Text(text.into())]),
+            }]
+            .into(),
+            ..Default::default()
+        };
+        let html = to_html(vec![BlockInner::Table(Table {
+            columns: vec![],
+            cells: vec![vec![cell("a"), cell("b")], vec![cell("c"), cell("d")]],
+        })]);
+        assert!(html.contains(concat!(
+            "<table><tbody>",
+            "<tr><td>a</td><td>b</td></tr>",
+            "<tr><td>c</td><td>d</td></tr>",
+            "</tbody></table>",
+        )));
+    }
+
+    #[test]
+    fn test_table_spans() {
+        use crate::doc::{Alignment, TableCell, TableColumn};
+
+        let column = |alignment| TableColumn {
+            alignment,
+            width: None,
+        };
+        let cell = |text: &str| TableCell {
+            content: vec![Block {
+                id: Id::from(0),
+                inner: BlockInner::Plain(vec![Inline::Text(text.into())]),
+            }]
+            .into(),
+            ..Default::default()
+        };
+        let html = to_html(vec![BlockInner::Table(Table {
+            columns: vec![
+                column(Alignment::Left),
+                column(Alignment::Center),
+                column(Alignment::Right),
+            ],
+            cells: vec![
+                vec![
+                    TableCell {
+                        row_span: 2,
+                        ..cell("a")
+                    },
+                    TableCell {
+                        col_span: 2,
+                        ..cell("b")
+                    },
+                ],
+                // The first column is covered by "a", so these cells are in
+                // the second and third columns.
+                vec![cell("c"), cell("d")],
+            ],
+        })]);
+        assert!(html.contains(concat!(
+            "<tbody>",
+            r#"<tr><td class="align-left" rowspan="2">a</td>"#,
+            r#"<td class="align-center" colspan="2">b</td></tr>"#,
+            r#"<tr><td class="align-center">c</td><td class="align-right">d</td></tr>"#,
+            "</tbody>",
+        )));
+    }
+
+    #[test]
+    fn test_figure() {
+        use crate::doc::{FigureKind, ListItem};
+
+        let figure = |kind, caption: &str, label: Option<&str>| {
+            BlockInner::Figure(Figure {
+                kind,
+                caption: if caption.is_empty() {
+                    vec![]
+                } else {
+                    vec![Inline::Text(caption.into())]
+                },
+                content: vec![Block {
+                    id: Id::from(0),
+                    inner: BlockInner::Par(vec![Inline::Text("Content.".into())]),
+                }]
+                .into(),
+                label: label.map(|label| label.parse().unwrap()),
+                number: None,
+            })
+        };
+        let html = to_html(vec![
+            figure(FigureKind::Listing, "A listing.", Some("lst:1")),
+            figure(FigureKind::Other("chart".into()), "", None),
+            BlockInner::List(List {
+                kind: ListKind::Unordered,
+                items: vec![ListItem {
+                    content: vec![Block {
+                        id: Id::from(1),
+                        inner: figure(FigureKind::Figure, "Nested.", None),
+                    }]
+                    .into(),
+                }],
+            }),
+        ]);
+        assert!(html.contains(concat!(
+            r#"<figure id="lst:1" class="listing">"#,
+            "\n<p>Content.</p><figcaption>A listing.</figcaption></figure>",
+            r#"<figure class="chart">"#,
+            "\n<p>Content.</p></figure>",
+            r#"<ul><li><figure class="figure">"#,
+            "\n<p>Content.</p><figcaption>Nested.</figcaption></figure></li></ul>",
+        )));
+        assert_balanced(&html);
+    }
+
+    fn defn(name: &str, summary: Vec<Block>, content: Vec<Block>) -> BlockInner {
+        BlockInner::Defn(Defn {
+            name: vec![Inline::Text(name.into())],
+            summary: summary.into(),
+            content: content.into(),
+        })
+    }
+
+    #[test]
+    fn test_defn() {
+        let par = |id: usize, text: &str| Block {
+            id: Id::from(id),
+            inner: BlockInner::Plain(vec![Inline::Text(text.into())]),
+        };
+        let html = to_html(vec![defn(
+            "Group",
+            vec![par(1, "A set with an operation.")],
+            vec![Block {
+                id: Id::from(2),
+                inner: defn(
+                    "Abelian group",
+                    vec![par(3, "A commutative group.")],
+                    vec![],
+                ),
+            }],
+        )]);
+        assert!(html.contains(concat!(
+            r#"<section id="defn-0" class="defn"><header><dfn>Group</dfn></header>"#,
+            r#"<div class="defn-summary">A set with an operation.</div>"#,
+            r#"<div class="defn-content">"#,
+            r#"<section id="defn-2" class="defn"><header><dfn>Abelian group</dfn></header>"#,
+            r#"<div class="defn-summary">A commutative group.</div></section>"#,
+            "</div></section>",
+        )));
+    }
+
+    #[test]
+    fn test_defn_ids() {
+        let explicit = || heading_with(0, 1, "Defn", &[("id", "defn-1")]);
+        let group = || block(1, defn("Group", vec![], vec![]));
+
+        // A heading's generated id skips a later definition's.
+        let html = to_html(vec![heading(0, 1, "defn 1").inner, group().inner]);
+        assert!(html.contains(r#"<h1 id="defn-1-1">"#), "{}", html);
+        assert!(html.contains(r#"<section id="defn-1" class="defn">"#));
+
+        let message =
+            r#"Heading "Defn" has the id "defn-1", which is used for the definition of "Group""#;
+        let err = HtmlSerializer::new(Vec::new())
+            .unwrap()
+            .write_doc(&Doc::from_content(vec![explicit(), group()].into()))
+            .unwrap_err();
+        assert_eq!(message, err.to_string());
+
+        // Without `write_doc`, the heading's id isn't known to be taken until
+        // the definition is written.
+        let err = HtmlSerializer::fragment(Vec::new(), Default::default())
+            .write_blocks(&[explicit(), group()])
+            .unwrap_err();
+        assert_eq!(message, err.to_string());
+    }
+
+    #[test]
+    fn test_defn_math() {
+        let doc = Doc::from_content(
+            Block {
+                id: Id::from(0),
+                inner: defn(
+                    "Square",
+                    vec![Block {
+                        id: Id::from(1),
+                        inner: BlockInner::Plain(vec![Inline::Math(doc::InlineMath {
+                            tex: "x^2".into(),
+                            alt: None,
+                        })]),
+                    }],
+                    vec![],
+                ),
+            }
+            .into(),
+        );
+        let mut out = Vec::new();
+        let mut ser = HtmlSerializer::new(&mut out).unwrap();
+        ser.set_math_renderer(Box::new(PlainMathRenderer));
+        ser.write_doc(&doc).unwrap();
+        drop(ser);
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains(concat!(
+            r#"<div class="defn-summary"><code class="tex">x^2</code></div>"#,
+            "</section>"
+        )));
+    }
+
+    #[test]
+    fn test_term_list() {
+        use std::convert::TryInto;
+
+        use crate::doc::{DocBuilder, DocBuilderPush};
+
+        let term = |term: &str| TermListItem {
+            term: vec![Inline::Text(term.into())],
+            content: Default::default(),
+        };
+        let mut builder = DocBuilder::new();
+        builder
+            .push(BlockInner::TermList(vec![term("A"), term("A"), term("B")]))
+            .unwrap();
+        // Text after a term list is added to its last item.
+        builder.push(Inline::Text("Bee.".into())).unwrap();
+        let doc: Doc = builder.try_into().unwrap();
+        let mut out = Vec::new();
+        HtmlSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&doc)
+            .unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains(concat!(
+            "<dl><dt>A</dt><dd></dd><dt>A</dt><dd></dd>",
+            "<dt>B</dt><dd>\n<p>Bee.</p></dd></dl>",
+        )));
+        assert!(fh::tokenize(&html).is_ok());
+    }
+
+    #[test]
+    fn test_table_empty() {
+        let html = to_html(vec![BlockInner::Table(Table {
+            columns: vec![],
+            cells: vec![],
+        })]);
+        assert!(html.contains("<table><tbody></tbody></table>"));
+    }
+
+    #[test]
+    fn test_dir() {
+        let mut doc = Doc::from_content(
+            vec![
+                Block {
+                    id: Id::from(0),
+                    inner: BlockInner::Par(vec![Inline::Text("Hello.".into())]),
+                },
+                Block {
+                    id: Id::from(1),
+                    inner: BlockInner::Tagged(TaggedBlocks {
+                        content: Block {
+                            id: Id::from(2),
+                            inner: BlockInner::Par(vec![Inline::Text("שלום.".into())]),
+                        }
+                        .into(),
+                        meta: attrs(&[(doc::LANG_KEY, "he"), (doc::DIR_KEY, "rtl")]),
+                    }),
+                },
+            ]
+            .into(),
+        );
+        doc.meta.insert(doc::DIR_KEY.into(), "ltr".into());
+        let mut out = Vec::new();
+        HtmlSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&doc)
+            .unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains(r#"<html dir="ltr">"#));
+        assert!(html.contains("<p>Hello.</p>"));
+        assert!(html.contains(r#"<div lang="he" dir="rtl">"#));
+        assert!(html.contains("<p>שלום.</p></div>"));
+    }
+
+    #[test]
+    fn test_hints() {
+        let tagged = |meta: &[(&str, &str)]| {
+            BlockInner::Tagged(TaggedBlocks {
+                content: Block {
+                    id: Id::from(0),
+                    inner: BlockInner::Plain(vec![Inline::Text("Content.".into())]),
+                }
+                .into(),
+                meta: attrs(meta),
+            })
+        };
+        let options = HtmlOptions {
+            hint_handler: Some(Rc::new(|hint: &str, value: &str, _: &TaggedBlocks| {
+                if hint == "embed" && value == "widget" {
+                    Handled::Replace(r#"<div class="widget"></div>"#.into())
+                } else {
+                    Handled::No
+                }
+            })),
+            ..Default::default()
+        };
+
+        let html = to_html_with(
+            options.clone(),
+            vec![tagged(&[("html:embed", "widget"), ("html:class", "x")])],
+        );
+        assert!(html.contains(r#"<div class="widget"></div>"#));
+        assert!(!html.contains("Content."));
+
+        // Unhandled hints and hints for other formats fall back to the content.
+        let html = to_html_with(
+            options,
+            vec![tagged(&[
+                ("html:embed", "other"),
+                ("latex:embed", "widget"),
+                ("html:class", "wide"),
+            ])],
+        );
+        assert!(!html.contains("widget"));
+        assert!(html.contains(r#"<div class="wide">Content.</div>"#));
+    }
+
+    #[test]
+    fn test_collections() {
+        let par = |text: &str| {
+            Blocks::from(vec![Block::new(
+                Id::from(0),
+                BlockInner::Par(vec![Inline::Text(text.into())]),
+            )])
+        };
+        let mut doc = Doc::from_content(par("Content."));
+        doc.collections.insert(
+            doc::ABSTRACT_CHANNEL.into(),
+            vec![par("A summary."), par("More summary.")],
+        );
+        doc.collections
+            .insert("custom".into(), vec![par("Only for other tools.")]);
+        let mut out = Vec::new();
+        HtmlSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&doc)
+            .unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains(
+            "<section class=\"abstract\">\n<p>A summary.</p>\n<p>More summary.</p></section>\n<p>Content.</p>"
+        ), "{}", html);
+        assert!(!html.contains("Only for other tools."));
+    }
+
+    #[test]
+    fn test_scripting_enabled() {
+        let to_html = |scripting_enabled| {
+            let options = HtmlOptions {
+                hint_handler: Some(Rc::new(|_: &str, _: &str, _: &TaggedBlocks| {
+                    Handled::Replace("<noscript>&lt;b&gt;</noscript>".into())
+                })),
+                scripting_enabled,
+                ..Default::default()
+            };
+            to_html_with(
+                options,
+                vec![BlockInner::Tagged(TaggedBlocks {
+                    content: Default::default(),
+                    meta: attrs(&[("html:embed", "x")]),
+                })],
+            )
+        };
+        assert!(to_html(true).contains("<noscript><b></noscript>"));
+        assert!(to_html(false).contains("<noscript>&lt;b&gt;</noscript>"));
+    }
+
+    #[test]
+    fn test_abort_on_error() {
+        let doc = Doc::from_content(
+            vec![
+                Block {
+                    id: Id::from(0),
+                    inner: BlockInner::Par(vec![Inline::Text("Before.".into())]),
+                },
+                heading(1, 9, ""),
+            ]
+            .into(),
+        );
+        let mut out = Vec::new();
+        assert!(HtmlSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&doc)
+            .is_err());
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.ends_with(&format!(
+            "<p>Before.</p><!--{}--></main></body></html>",
+            fh::TRUNCATION_COMMENT
+        )));
+        assert!(fh::tokenize(&html).is_ok());
+    }
+
+    #[test]
+    fn test_write_errors() {
+        let doc = Doc::from_content(
+            vec![
+                Block::new(
+                    Id::from(0),
+                    BlockInner::Par(vec![
+                        Inline::Text("Some text.".into()),
+                        Inline::Footnote(Footnote {
+                            content: Block::new(
+                                Id::from(4),
+                                BlockInner::Par(vec![Inline::Text("A note.".into())]),
+                            )
+                            .into(),
+                        }),
+                    ]),
+                ),
+                Block::new(Id::from(1), BlockInner::Rule),
+                Block::new(
+                    Id::from(2),
+                    BlockInner::Quote(
+                        Block::new(
+                            Id::from(3),
+                            BlockInner::Par(vec![Inline::Text("Quoted.".into())]),
+                        )
+                        .into(),
+                    ),
+                ),
+            ]
+            .into(),
+        );
+        let mut html = Vec::new();
+        HtmlSerializer::new(&mut html)
+            .unwrap()
+            .write_doc(&doc)
+            .unwrap();
+
+        // The writer's error is given wherever it fails, and nothing is
+        // written after it.
+        for limit in (0..html.len()).step_by(7) {
+            let mut out = FailAfter::new(limit);
+            let result = HtmlSerializer::new(&mut out).and_then(|mut ser| ser.write_doc(&doc));
+            match result {
+                Err(SerializerError::Io(_)) => {}
+                res => panic!("Expected an IO error, got {:?}", res),
+            }
+            assert_eq!(&html[..limit], &out.out[..]);
+        }
+    }
+
+    #[test]
+    fn test_assets() {
+        let mut resolver = crate::cmd::MemoryResolver::new();
+        resolver.insert("img/a.png", "image");
+        resolver.insert("style.css", "p {}");
+        let assets = Rc::new(std::cell::RefCell::new(super::super::AssetCollector::new(
+            Rc::new(resolver),
+            "assets",
+        )));
+        let options = HtmlOptions {
+            stylesheets: vec!["style.css".into(), "https://example.com/a.css".into()],
+            assets: Some(assets.clone()),
+            ..Default::default()
+        };
+        let image = |src: &str| {
+            Inline::Image(Image {
+                src: src.into(),
+                alt: None,
+                attrs: Default::default(),
+            })
+        };
+        let doc = Doc::from_content(
+            vec![
+                heading(0, 1, "Intro"),
+                Block {
+                    id: Id::from(1),
+                    inner: BlockInner::Par(vec![image("img/a.png"), image("missing.png")]),
+                },
+            ]
+            .into(),
+        );
+        let mut out = Vec::new();
+        let mut ser = HtmlSerializer::with_options(&mut out, options).unwrap();
+        ser.write_doc(&doc).unwrap();
+        let warnings = ser.take_warnings();
+        drop(ser);
+        let html = String::from_utf8(out).unwrap();
+
+        let urls: Vec<_> = assets
+            .borrow()
+            .assets()
+            .map(|(name, _)| name.to_owned())
+            .collect();
+        assert_eq!(2, urls.len());
+        for url in &urls {
+            assert!(html.contains(&format!("\"assets/{}\"", url)));
+        }
+        assert!(html.contains(r#"href="https://example.com/a.css""#));
+        assert!(html.contains(r#"src="missing.png""#));
+        assert_eq!(1, warnings.len());
+        assert_eq!(WarningKind::MissingAsset, warnings[0].kind);
+        assert!(warnings[0].message.contains("missing.png"));
+        assert!(warnings[0].message.contains("in section #Intro"));
+    }
+
+    #[test]
+    fn test_embed_assets() {
+        let mut resolver = crate::cmd::MemoryResolver::new();
+        resolver.insert(
+            "img/pixel.png",
+            &include_bytes!("../../tests/fixtures/pixel.png")[..],
+        );
+        resolver.insert(
+            "style.css",
+            "p::after { content: '</style>'; background: url(img/pixel.png); }",
+        );
+        resolver.insert("big.png", vec![0; 1000]);
+        let options = HtmlOptions {
+            stylesheets: vec!["style.css".into()],
+            embed_assets: true,
+            resolver: Some(Rc::new(resolver)),
+            embed_limits: EmbedLimits {
+                max_asset_size: 500,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let image = |src: &str| {
+            Inline::Image(Image {
+                src: src.into(),
+                alt: None,
+                attrs: Default::default(),
+            })
+        };
+        let doc = Doc::from_content(
+            Block::new(
+                Id::from(0),
+                BlockInner::Par(vec![
+                    image("img/pixel.png"),
+                    image("missing.png"),
+                    image("big.png"),
+                ]),
+            )
+            .into(),
+        );
+        let mut out = Vec::new();
+        let mut ser = HtmlSerializer::with_options(&mut out, options).unwrap();
+        ser.write_doc(&doc).unwrap();
+        let warnings = ser.take_warnings();
+        drop(ser);
+        let html = String::from_utf8(out).unwrap();
+
+        let pixel = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAAD\
+                     UlEQVR4nGP4z8DwHwAFAAH/iZk9HQAAAABJRU5ErkJggg==";
+        assert!(html.contains(&format!(
+            "<style>p::after {{ content: '<\\/style>'; background: url(\"{}\"); }}</style>",
+            pixel
+        )));
+        assert!(html.contains(&format!(r#"<img src="{}">"#, pixel)));
+        assert!(html.contains(r#"<img src="missing.png">"#));
+        assert!(html.contains(r#"<img src="big.png">"#));
+        assert_eq!(
+            vec![
+                r#"Couldn't embed image "missing.png": missing.png not found"#,
+                r#"Couldn't embed image "big.png": It's 1000 bytes, over the limit of 500 bytes per file"#,
+            ],
+            warnings
+                .iter()
+                .map(|warning| warning.message.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert!(warnings
+            .iter()
+            .all(|warning| warning.kind == WarningKind::MissingAsset));
+    }
+
+    #[test]
+    #[cfg(feature = "math")]
+    fn test_embed_math_assets() {
+        let mut resolver = crate::cmd::MemoryResolver::new();
+        resolver.insert("katex.css", ".katex { font: 1em KaTeX_Main; }");
+        let math = Doc::from_content(
+            Block::new(
+                Id::from(0),
+                BlockInner::Math(doc::Math {
+                    tex: "x".into(),
+                    alt: None,
+                }),
+            )
+            .into(),
+        );
+        let render = |math_config| {
+            let options = HtmlOptions {
+                embed_assets: true,
+                resolver: Some(Rc::new(resolver.clone())),
+                math: math_config,
+                ..Default::default()
+            };
+            let mut out = Vec::new();
+            let mut ser = HtmlSerializer::with_options(&mut out, options).unwrap();
+            ser.write_doc(&math).unwrap();
+            let warnings = ser.take_warnings();
+            drop(ser);
+            (String::from_utf8(out).unwrap(), warnings)
+        };
+
+        // A local copy of KaTeX's stylesheet is embedded like any other.
+        let (html, warnings) = render(MathConfig::Katex {
+            css_href: Some("katex.css".into()),
+        });
+        assert!(html.contains("<style>.katex { font: 1em KaTeX_Main; }</style>"));
+        assert!(warnings.is_empty());
+
+        // The copy on the CDN can't be.
+        let (html, warnings) = render(MathConfig::default());
+        assert!(html.contains(KATEX_CSS_HREF));
+        assert_eq!(
+            vec!["Couldn't embed KaTeX's stylesheet and fonts; the output links to them"],
+            warnings
+                .iter()
+                .map(|warning| warning.message.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "math")]
+    fn test_math_alt() {
+        let html = to_html(vec![
+            BlockInner::Par(vec![Inline::Math(doc::InlineMath {
+                tex: "x^2".into(),
+                alt: Some("x squared".into()),
+            })]),
+            BlockInner::Math(doc::Math {
+                tex: "y".into(),
+                alt: None,
+            }),
+        ]);
+        assert!(html.contains(concat!(
+            r#"<span class="math" role="img" aria-label="x squared">"#,
+            r#"<span aria-hidden="true"><span class="katex">"#
+        )));
+        assert!(!html.contains("<div"));
+        assert!(html.contains(r#"<span class="katex-display">"#));
+    }
+
+    #[test]
+    fn test_math_cache() {
+        struct Counting(Rc<RefCell<Vec<(String, MathMode)>>>);
+        impl MathRenderer for Counting {
+            fn render(&self, tex: &str, mode: MathMode) -> Result<String, MathError> {
+                self.0.borrow_mut().push((tex.to_owned(), mode));
+                Ok(format!("<b>{}</b>", tex))
+            }
+        }
+
+        let math = |tex: &str| {
+            Inline::Math(doc::InlineMath {
+                tex: tex.into(),
+                alt: None,
+            })
+        };
+        let doc = Doc::from_content(
+            vec![
+                Block {
+                    id: Id::from(0),
+                    inner: BlockInner::Par(vec![math("x"), math("y"), math("x"), math("x")]),
+                },
+                Block {
+                    id: Id::from(1),
+                    inner: BlockInner::Math(doc::Math {
+                        tex: "x".into(),
+                        alt: None,
+                    }),
+                },
+            ]
+            .into(),
+        );
+        let rendered = Rc::new(RefCell::new(Vec::new()));
+        let mut out = Vec::new();
+        let mut ser = HtmlSerializer::new(&mut out).unwrap();
+        ser.set_math_renderer(Box::new(Counting(Rc::clone(&rendered))));
+        ser.write_doc(&doc).unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert_eq!(4, html.matches("<b>x</b>").count());
+        assert_eq!(
+            vec![
+                ("x".to_owned(), MathMode::Inline),
+                ("y".to_owned(), MathMode::Inline),
+                ("x".to_owned(), MathMode::Display),
+            ],
+            rendered.take()
+        );
+    }
+
+    fn math_html(
+        renderer: Box<dyn MathRenderer>,
+        strictness: Strictness,
+    ) -> Result<(String, Vec<Warning>), SerializerError> {
+        let doc = Doc::from_content(
+            Block {
+                id: Id::from(0),
+                inner: BlockInner::Par(vec![Inline::Math(doc::InlineMath {
+                    tex: "x < y".into(),
+                    alt: None,
+                })]),
+            }
+            .into(),
+        );
+        let mut out = Vec::new();
+        let mut ser = HtmlSerializer::with_options(
+            &mut out,
+            HtmlOptions {
+                strictness,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        ser.set_math_renderer(renderer);
+        ser.write_doc(&doc)?;
+        let warnings = ser.take_warnings();
+        Ok((String::from_utf8(out).unwrap(), warnings))
+    }
+
+    #[test]
+    fn test_math_renderers() {
+        #[cfg(feature = "math")]
+        {
+            let (html, warnings) = math_html(Box::new(KatexRenderer), Strictness::Warn).unwrap();
+            assert!(html.contains(r#"<span class="katex">"#));
+            assert_eq!(Vec::<Warning>::new(), warnings);
+        }
+
+        let (html, warnings) = math_html(Box::new(PlainMathRenderer), Strictness::Warn).unwrap();
+        assert!(html.contains(r#"<p><code class="tex">x &lt; y</code></p>"#));
+        assert_eq!(Vec::<Warning>::new(), warnings);
+
+        let (html, warnings) = math_html(Box::new(FailingMathRenderer), Strictness::Warn).unwrap();
+        assert!(html.contains(r#"<p><code class="math-error">x &lt; y</code></p>"#));
+        assert_eq!(
+            vec![Warning::new(
+                WarningKind::Math,
+                r#"Failed to render math "x < y": Can't render "x < y""#.to_owned()
+            )],
+            warnings
+        );
+
+        let (html, warnings) =
+            math_html(Box::new(FailingMathRenderer), Strictness::Lenient).unwrap();
+        assert!(html.contains(r#"<code class="math-error">"#));
+        assert_eq!(Vec::<Warning>::new(), warnings);
+
+        assert!(matches!(
+            math_html(Box::new(FailingMathRenderer), Strictness::Strict),
+            Err(SerializerError::Strict(warning)) if warning.kind == WarningKind::Math
+        ));
+    }
+
+    #[test]
+    fn test_math_config() {
+        let html = |math| {
+            to_html_with(
+                HtmlOptions {
+                    math,
+                    ..Default::default()
+                },
+                vec![
+                    BlockInner::Plain(vec![Inline::Math(doc::InlineMath {
+                        tex: "x < y".into(),
+                        alt: None,
+                    })]),
+                    BlockInner::Math(doc::Math {
+                        tex: "x".into(),
+                        alt: None,
+                    }),
+                ],
+            )
+        };
+
+        let plain = html(MathConfig::None);
+        assert!(plain.contains(r#"<code class="tex">x &lt; y</code>"#));
+        for external in &["<link", "<script", "http", "katex"] {
+            assert!(!plain.contains(external), "{}", plain);
+        }
+
+        let mathjax = html(MathConfig::MathJax {
+            script_src: "https://example.com/tex-chtml.js".into(),
+        });
+        assert!(mathjax.contains(concat!(
+            r#"<script id="MathJax-script" async="" "#,
+            r#"src="https://example.com/tex-chtml.js"></script>"#
+        )));
+        assert!(mathjax.contains(r"\(x &lt; y\)"));
+        assert!(mathjax.contains(r"\[x\]"));
+        assert!(!mathjax.contains("katex"));
+
+        #[cfg(feature = "math")]
+        {
+            let katex = html(MathConfig::Katex {
+                css_href: Some("https://example.com/katex.css".into()),
+            });
+            assert!(
+                katex.contains(r#"<link rel="stylesheet" href="https://example.com/katex.css">"#)
+            );
+            assert!(!katex.contains("cdn.jsdelivr.net"));
+            assert!(katex.contains(r#"<span class="katex">"#));
+        }
+    }
+
+    #[test]
+    fn test_math_header_only_with_math() {
+        let configs = vec![
+            MathConfig::default(),
+            MathConfig::Katex {
+                css_href: Some("https://example.com/katex.css".into()),
+            },
+            MathConfig::MathJax {
+                script_src: "https://example.com/tex-chtml.js".into(),
+            },
+        ];
+        for math in configs {
+            let html = to_html_with(
+                HtmlOptions {
+                    math,
+                    ..Default::default()
+                },
+                vec![BlockInner::Par(vec![Inline::Text("No math.".into())])],
+            );
+            for external in &["<link", "<script", "katex", "MathJax"] {
+                assert!(!html.contains(external), "{}", html);
+            }
+        }
+    }
+
+    #[test]
+    fn test_nested_langs() {
+        let quote = |text: &str| {
+            Inline::Quote(doc::Quote {
+                kind: doc::QuoteKind::Primary,
+                content: vec![Inline::Text(text.into())],
+            })
+        };
+        let lang = |lang: &str, content: Inlines| {
+            Inline::Tagged(TaggedInlines {
+                content,
+                meta: attrs(&[(doc::LANG_KEY, lang)]),
+            })
+        };
+        let mut doc = Doc::from_content(
+            Block {
+                id: Id::from(0),
+                inner: BlockInner::Par(vec![
+                    quote("a"),
+                    lang(
+                        "de",
+                        vec![quote("b"), lang("fr", vec![quote("c")]), quote("d")],
+                    ),
+                    quote("e"),
+                ]),
+            }
+            .into(),
+        );
+        doc.meta.insert(doc::LANG_KEY.into(), "en".into());
+        let mut out = Vec::new();
+        HtmlSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&doc)
+            .unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains(r#"<html lang="en">"#));
+        assert!(html.contains(concat!(
+            "<p>“a”",
+            r#"<span lang="de">„b“"#,
+            r#"<span lang="fr">«&nbsp;c&nbsp;»</span>"#,
+            "„d“</span>",
+            "“e”</p>",
+        )));
+    }
+
+    #[test]
+    fn test_inline_code_attrs() {
+        let html = to_html(vec![BlockInner::Plain(vec![Inline::Code(InlineCode {
+            language: Some("rust".into()),
+            content: "x".into(),
+            attrs: attrs(&[("class", "snippet"), ("linenos", "")]),
+        })])]);
+        assert!(html.contains(r#"<code class="rust snippet" data-linenos="">x</code>"#));
+    }
+
+    #[test]
+    fn test_link_attrs() {
+        let html = to_html(vec![BlockInner::Plain(vec![Inline::Link(Link {
+            content: Some(vec![Inline::Text("here".into())]),
+            label: None,
+            target: LinkTarget::URL("https://example.com".into()),
+            attrs: attrs(&[("rel", "nofollow"), ("foo", "bar")]),
+        })])]);
+        assert!(html
+            .contains(r#"<a href="https://example.com" data-foo="bar" rel="nofollow">here</a>"#));
+    }
+
+    #[test]
+    fn test_class_map() {
+        let content = || {
+            vec![
+                heading(0, 1, "Title").inner,
+                BlockInner::Par(vec![
+                    Inline::Text("a".into()),
+                    Inline::Footnote(Footnote {
+                        content: Block {
+                            id: Id::from(10),
+                            inner: BlockInner::Plain(vec![Inline::Text("A".into())]),
+                        }
+                        .into(),
+                    }),
+                ]),
+            ]
+        };
+        let body = |classes: ClassMap| {
+            let html = to_html_with(
+                HtmlOptions {
+                    heading_anchors: HeadingAnchors::None,
+                    a11y_landmarks: false,
+                    classes,
+                    ..Default::default()
+                },
+                content(),
+            );
+            html.split("<body>").nth(1).unwrap().replace('\n', "")
+        };
+
+        assert_eq!(
+            concat!(
+                r#"<h1 id="Title" class="title-heading underlined">Title</h1>"#,
+                r#"<p class="prose">a<sup>"#,
+                r##"<a href="#fn-1" id="fn-link-1" role="doc-noteref" aria-label="Footnote 1">"##,
+                r#"[1]</a></sup></p><ol class="notes"><li id="fn-1">A "#,
+                r##"<a href="#fn-link-1" role="doc-backlink" aria-label="Back to reference 1">"##,
+                r#"↩</a></li></ol></body></html>"#,
+            ),
+            body(
+                ClassMap::default()
+                    .with(ElementKind::Paragraph, "prose")
+                    .with(ElementKind::Heading, "title-heading underlined")
+                    .with(ElementKind::FootnoteList, "notes")
+            )
+        );
+
+        // Empty classes aren't written.
+        let html = body(ClassMap::default().with(ElementKind::FootnoteList, ""));
+        assert!(html.contains(r#"<h1 id="Title">"#));
+        assert!(html.contains("<p>a"));
+        assert!(html.contains("<ol><li"));
+        assert!(!html.contains("class"));
+    }
+
+    #[test]
+    fn test_init_with_options() {
+        fn render<S: InitSerializerWithOptions<Vec<u8>> + Serializer>(
+            options: S::Options,
+            doc: &Doc,
+        ) -> Result<(), SerializerError> {
+            S::new_with_options(Vec::new(), options)?.write_doc(doc)
+        }
+
+        let doc = Doc::from_content(
+            Block {
+                id: Id::from(0),
+                inner: BlockInner::Par(vec![Inline::Text("a".into())]),
+            }
+            .into(),
+        );
+        assert!(render::<HtmlSerializer<_>>(Default::default(), &doc).is_ok());
+        assert!(render::<crate::ser::PlainTextSerializer<_>>(Default::default(), &doc).is_ok());
+        let options = HtmlOptions {
+            max_depth: 0,
+            ..Default::default()
+        };
+        assert!(matches!(
+            render::<HtmlSerializer<_>>(options, &doc),
+            Err(SerializerError::TooDeep { depth: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_raw() {
+        let blocks = [
+            Block::new(
+                Id::from(0),
+                BlockInner::Par(vec![Inline::Raw(RawInline {
+                    format: "html".into(),
+                    content: "<kbd>&amp;</kbd>".into(),
+                })]),
+            ),
+            Block::new(
+                Id::from(1),
+                BlockInner::Raw(RawBlock {
+                    format: "html".into(),
+                    content: "<div class=\"raw\"><b>x</b> &lt; y</div>".into(),
+                }),
+            ),
+            Block::new(
+                Id::from(2),
+                BlockInner::Raw(RawBlock {
+                    format: "latex".into(),
+                    content: "\\newpage".into(),
+                }),
+            ),
+        ];
+        // Raw content for other formats isn't a problem, even when strict.
+        let options = HtmlOptions {
+            strictness: Strictness::Strict,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let mut ser = HtmlSerializer::fragment(&mut out, options);
+        ser.write_blocks(&blocks).unwrap();
+        assert_eq!(Vec::<Warning>::new(), ser.take_warnings());
+        drop(ser);
+        assert_eq!(
+            "\n<p><kbd>&amp;</kbd></p><div class=\"raw\"><b>x</b> &lt; y</div>",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_heading_levels() {
+        let write = |level| {
+            let doc = Doc::from_content(heading(0, level, "Title").into());
+            let options = HtmlOptions {
+                heading_anchors: HeadingAnchors::None,
+                ..Default::default()
+            };
+            let mut out = Vec::new();
+            HtmlSerializer::with_options(&mut out, options)
+                .unwrap()
+                .write_doc(&doc)
+                .map(|()| String::from_utf8(out).unwrap())
+        };
+        let expected = [
+            (-3, r#"<h1 id="Title" class="title">Title</h1>"#),
+            (-2, r#"<h1 id="Title" class="part">Title</h1>"#),
+            (-1, r#"<h1 id="Title" class="chapter">Title</h1>"#),
+            (0, r#"<h1 id="Title">Title</h1>"#),
+            (1, r#"<h1 id="Title">Title</h1>"#),
+            (2, r#"<h2 id="Title">Title</h2>"#),
+            (3, r#"<h3 id="Title">Title</h3>"#),
+            (4, r#"<h4 id="Title">Title</h4>"#),
+            (5, r#"<h5 id="Title">Title</h5>"#),
+            (6, r#"<h6 id="Title">Title</h6>"#),
+        ];
+        for (level, html) in expected.iter() {
+            assert!(write(*level).unwrap().contains(html), "Level {}", level);
+        }
+        let err = write(7).unwrap_err();
+        assert_eq!(ErrorCode::BadHeading, err.code());
+    }
+
+    #[test]
+    fn test_styles() {
+        let styled = |style, content| Inline::Styled { style, content };
+        let text = |text: &str| vec![Inline::Text(text.into())];
+        for (style, expected) in &[
+            (doc::Style::Emph, "<em>x</em>"),
+            (doc::Style::Strong, "<strong>x</strong>"),
+            (doc::Style::Superscript, "<sup>x</sup>"),
+            (doc::Style::Subscript, "<sub>x</sub>"),
+            (
+                doc::Style::SmallCaps,
+                r#"<span class="small-caps" style="font-variant: small-caps">x</span>"#,
+            ),
+            (doc::Style::Strikeout, "<s>x</s>"),
+            (doc::Style::Underline, "<u>x</u>"),
+        ] {
+            let html = to_html(vec![BlockInner::Plain(vec![styled(
+                style.clone(),
+                text("x"),
+            )])]);
+            assert!(html.contains(expected), "{} not in {}", expected, html);
+        }
+
+        let html = to_html(vec![BlockInner::Plain(vec![styled(
+            doc::Style::Emph,
+            vec![
+                Inline::Text("a ".into()),
+                styled(
+                    doc::Style::Strikeout,
+                    vec![
+                        styled(doc::Style::Strong, text("b")),
+                        Inline::Text(" c".into()),
+                    ],
+                ),
+            ],
+        )])]);
+        assert!(html.contains("<em>a <s><strong>b</strong> c</s></em>"));
+    }
+
+    #[test]
+    fn test_style_attrs() {
+        use crate::doc::{Color, Length, RelLength};
+
+        let html = |style| {
+            to_html(vec![BlockInner::Plain(vec![Inline::Styled {
+                style,
+                content: vec![Inline::Text("x".into())],
+            }])])
+        };
+        assert!(html(doc::Style::Size(Length::Relative(RelLength::Em(1.5))))
+            .contains(r#"<span style="font-size: 1.5em">x</span>"#));
+        assert!(html(doc::Style::Color(Color::Rgb(0x66, 0x33, 0x99)))
+            .contains(r#"<span style="color: #663399">x</span>"#));
+        assert!(html(doc::Style::Color(Color::Named("teal".into())))
+            .contains(r#"<span style="color: teal">x</span>"#));
+        assert!(html(doc::Style::Font(doc::Font {})).contains(r#"<span class="font">x</span>"#));
+
+        // Invalid names could otherwise close the attribute, or add
+        // declarations of their own.
+        for name in &[r#"red" onclick="alert(1)"#, "red; display: none", "</span>"] {
+            let html = html(doc::Style::Color(Color::Named((*name).to_owned())));
+            assert!(html.contains("<main id=\"main\">\nx\n"), "{}", html);
+            assert!(!html.contains("<span"), "{}", html);
+        }
+    }
+
+    #[test]
+    fn test_link() {
+        let link = |content: Option<Vec<Inline>>, label: Option<&str>, target| {
+            Inline::Link(Link {
+                content,
+                label: label.map(str::to_owned),
+                target,
+                attrs: Default::default(),
+            })
+        };
+        let html = to_html(vec![BlockInner::Plain(vec![
+            link(
+                Some(vec![
+                    Inline::Text("the ".into()),
+                    Inline::Styled {
+                        style: doc::Style::Emph,
+                        content: vec![Inline::Text("docs".into())],
+                    },
+                ]),
+                Some("Documentation"),
+                LinkTarget::URL("https://example.com/?a=1&b=2".into()),
+            ),
+            link(
+                None,
+                Some("Results"),
+                LinkTarget::Label("nowhere".parse().unwrap()),
+            ),
+            link(None, None, LinkTarget::URL("https://example.com".into())),
+        ])]);
+        assert!(html.contains(concat!(
+            r#"<a href="https://example.com/?a=1&amp;b=2" aria-label="Documentation">"#,
+            "the <em>docs</em></a>",
+            r##"<a href="#nowhere">Results</a>"##,
+            r#"<a href="https://example.com">https://example.com</a>"#,
+        )));
+    }
+
+    #[test]
+    fn test_code() {
+        let text = |s: &str| vec![Inline::Text(s.into())];
+        let html = to_html(vec![
+            BlockInner::Code(Code {
+                language: "plain".into(),
+                lines: vec![
+                    text(r#"if a < b && c == "d" {"#),
+                    text("    e('f');"),
+                    text("}"),
+                ],
+                ..Default::default()
+            }),
+            BlockInner::Code(Code {
+                language: "sh".into(),
+                line_numbers: Some(LineNumbers { start: 1 }),
+                lines: vec![text("echo 1"), text("echo 2")],
+                ..Default::default()
+            }),
+        ]);
+        assert!(html.contains(concat!(
+            "<pre><code>if a &lt; b &amp;&amp; c == \"d\" {\n",
+            "    e('f');\n",
+            "}</code></pre>",
+        )));
+        assert!(html.contains(concat!(
+            r#"<pre><code class="language-sh">"#,
+            r#"<span class="line" data-line="1">echo 1</span>"#,
+            "\n",
+            r#"<span class="line" data-line="2">echo 2</span>"#,
+            "</code></pre>",
+        )));
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_code_highlight_lines() {
+        let html = to_html(vec![BlockInner::Code(Code {
+            language: "rust".into(),
+            line_numbers: Some(LineNumbers { start: 10 }),
+            lines: vec![
+                vec![Inline::Text("a".into())],
+                vec![],
+                vec![Inline::Text("c".into())],
+            ],
+            highlight_lines: vec![11..13],
+            ..Default::default()
+        })]);
+        assert!(html.contains(concat!(
+            r#"<pre><code class="language-rust">"#,
+            r#"<span class="line" data-line="10">a</span>"#,
+            "\n",
+            r#"<span class="line highlight" data-line="11"></span>"#,
+            "\n",
+            r#"<span class="line highlight" data-line="12">c</span>"#,
+            "</code></pre>",
+        )));
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_code_highlight_lines_numbered() {
+        // Highlighted lines are numbered from the first line's number, like
+        // the numbers shown.
+        let html = to_html(vec![BlockInner::Code(Code {
+            line_numbers: Some(LineNumbers { start: 10 }),
+            lines: vec![
+                vec![Inline::Text("a".into())],
+                vec![Inline::Text("b".into())],
+                vec![Inline::Text("c".into())],
+            ],
+            highlight_lines: vec![12..13],
+            ..Default::default()
+        })]);
+        assert!(html.contains(concat!(
+            r#"<span class="line" data-line="10">a</span>"#,
+            "\n",
+            r#"<span class="line" data-line="11">b</span>"#,
+            "\n",
+            r#"<span class="line highlight" data-line="12">c</span>"#,
+        )));
+    }
+
+    #[test]
+    fn test_only_format() {
+        let only = |format: &str, text: &str| {
+            Inline::Tagged(TaggedInlines {
+                content: vec![Inline::Text(text.into())],
+                meta: attrs(&[(ONLY_FORMAT_KEY, format)]),
+            })
+        };
+        let html = to_html(vec![
+            BlockInner::Par(vec![only("html", "interactive"), only("latex", "static")]),
+            BlockInner::Tagged(TaggedBlocks {
+                content: Block {
+                    id: Id::from(10),
+                    inner: BlockInner::Par(vec![Inline::Text("fallback".into())]),
+                }
+                .into(),
+                meta: attrs(&[(ONLY_FORMAT_KEY, "epub")]),
+            }),
+        ]);
+        assert!(html.contains("<p>interactive</p>"));
+        assert!(!html.contains("static"));
+        assert!(!html.contains("fallback"));
+
+        // Each format writes its own content, and no other format's.
+        let formats = ["html", "latex", "markdown", "text"];
+        let doc = Doc::from_content(
+            vec![Block {
+                id: Id::from(0),
+                inner: BlockInner::Par(
+                    formats
+                        .iter()
+                        .map(|format| only(format, &format!(" only{}", format)))
+                        .collect(),
+                ),
+            }]
+            .into(),
+        );
+        let write = |mut ser: Box<dyn Serializer + '_>| ser.write_doc(&doc).unwrap();
+        let mut outputs = vec![Vec::new(); formats.len()];
+        write(HtmlSerializer::new(&mut outputs[0]).unwrap());
+        write(crate::ser::LatexSerializer::new(&mut outputs[1]).unwrap());
+        write(crate::ser::MarkdownSerializer::new(&mut outputs[2]).unwrap());
+        write(crate::ser::PlainTextSerializer::new(&mut outputs[3]).unwrap());
+        for (format, out) in formats.iter().zip(outputs) {
+            let out = String::from_utf8(out).unwrap();
+            for other in formats.iter() {
+                assert_eq!(
+                    other == format,
+                    out.contains(&format!("only{}", other)),
+                    "{} in {}: {:?}",
+                    other,
+                    format,
+                    out
+                );
+            }
+        }
+    }
+
+    /// The start tags in `html`, which must parse without errors.
+    fn start_tags(html: &str) -> Vec<(String, Vec<(String, String)>)> {
+        fh::tokenize(html)
+            .unwrap()
+            .into_iter()
+            .filter_map(|tok| match tok {
+                fh::HtmlToken::StartTag { name, attrs } => Some((name, attrs)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn attr_pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_adversarial_attrs() {
+        let evil = r#""><script>alert(1)</script>"#;
+        let evil_href = "#%22%3E%3Cscript%3Ealert(1)%3C/script%3E";
+        let html = to_html(vec![
+            heading_with(0, 1, evil, &[(evil, evil)]).inner,
+            BlockInner::Plain(vec![
+                Inline::Link(Link {
+                    content: Some(vec![Inline::Text("url".into())]),
+                    label: None,
+                    target: LinkTarget::URL(evil.into()),
+                    attrs: attrs(&[("title", evil)]),
+                }),
+                Inline::Link(Link {
+                    content: Some(vec![Inline::Text("label".into())]),
+                    label: None,
+                    target: LinkTarget::Label(RefId::new(evil).unwrap()),
+                    attrs: Default::default(),
+                }),
+            ]),
+        ]);
+        let tags = start_tags(&html);
+        let names: Vec<_> = tags.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            vec!["html", "head", "body", "main", "h1", "a", "a", "a"],
+            names
+        );
+        assert_eq!(
+            attr_pairs(&[("id", evil), ("data----script-alert(1)--script-", evil)]),
+            tags[4].1
+        );
+        assert_eq!(
+            attr_pairs(&[
+                ("href", evil_href),
+                ("class", "anchor"),
+                ("aria-label", "Link to this section")
+            ]),
+            tags[5].1
+        );
+        assert_eq!(attr_pairs(&[("href", evil), ("title", evil)]), tags[6].1);
+        assert_eq!(attr_pairs(&[("href", evil_href)]), tags[7].1);
+        assert!(fh::tokenize(&html)
+            .unwrap()
+            .contains(&fh::HtmlToken::Text(evil.into())));
+    }
+
+    fn footnote_html(
+        footnote_placement: FootnotePlacement,
+        footnote_numbering: FootnoteNumbering,
+    ) -> String {
+        let par = |text: &str, note: &str| {
+            BlockInner::Par(vec![
+                Inline::Text(text.into()),
+                Inline::Footnote(Footnote {
+                    content: Block {
+                        id: Id::from(10),
+                        inner: BlockInner::Plain(vec![Inline::Text(note.into())]),
+                    }
+                    .into(),
+                }),
+            ])
+        };
+        let html = to_html_with(
+            HtmlOptions {
+                footnote_placement,
+                footnote_numbering,
+                heading_anchors: HeadingAnchors::None,
+                a11y_landmarks: false,
+                ..Default::default()
+            },
+            vec![
+                heading(0, 1, "One").inner,
+                par("a", "A"),
+                heading(0, 1, "Two").inner,
+                par("b", "B"),
+                par("c", "C"),
+            ],
+        );
+        let body = html.split("<body>").nth(1).unwrap();
+        body.replace('\n', "")
+    }
+
+    #[test]
+    fn test_blocks_to_string() {
+        let plain = |text: &str| -> Blocks {
+            Block::new(
+                Id::from(0),
+                BlockInner::Plain(vec![Inline::Text(text.into())]),
+            )
+            .into()
+        };
+        let list = Block::new(
+            Id::from(0),
+            BlockInner::List(List {
+                kind: ListKind::Unordered,
+                items: vec![
+                    doc::ListItem {
+                        content: plain("a"),
+                    },
+                    doc::ListItem {
+                        content: plain("b"),
+                    },
+                ],
+            }),
+        );
+        assert_eq!(
+            "<ul><li>a</li><li>b</li></ul>",
+            blocks_to_string(&[list], &Default::default()).unwrap()
+        );
+
+        let par = [Block::new(
+            Id::from(0),
+            BlockInner::Par(vec![
+                Inline::Text("Text".into()),
+                Inline::Footnote(Footnote {
+                    content: plain("A note."),
+                }),
+            ]),
+        )];
+        let options = HtmlOptions {
+            a11y_landmarks: false,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let mut ser = HtmlSerializer::fragment(&mut out, options.clone());
+        ser.write_blocks(&par).unwrap();
+        ser.write_inlines(&[Inline::Text(" and more.".into())])
+            .unwrap();
+        drop(ser);
+        // Footnotes are only listed when asked.
+        assert_eq!(
+            "\n<p>Text<sup><a href=\"#fn-1\" id=\"fn-link-1\" role=\"doc-noteref\" aria-label=\"Footnote 1\">[1]</a></sup></p> and more.",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(
+            "\n<p>Text<sup><a href=\"#fn-1\" id=\"fn-link-1\" role=\"doc-noteref\" aria-label=\"Footnote 1\">[1]</a></sup></p>\
+             <ol class=\"footnotes\"><li id=\"fn-1\">A note. \
+             <a href=\"#fn-link-1\" role=\"doc-backlink\" aria-label=\"Back to reference 1\">↩</a>\
+             <a href=\"#fn-1\" class=\"anchor\" aria-label=\"Link to footnote 1\">¶</a></li></ol>",
+            blocks_to_string(&par, &options).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fragment_doc() {
+        let note = |text: &str| {
+            Inline::Footnote(Footnote {
+                content: Block::new(
+                    Id::from(0),
+                    BlockInner::Plain(vec![Inline::Text(text.into())]),
+                )
+                .into(),
+            })
+        };
+        let doc = Doc::from_content(
+            vec![
+                Block::new(
+                    Id::from(1),
+                    BlockInner::Par(vec![Inline::Text("One".into()), note("a")]),
+                ),
+                Block::new(
+                    Id::from(2),
+                    BlockInner::Par(vec![Inline::Text("Two".into()), note("b")]),
+                ),
+            ]
+            .into(),
+        );
+        let mut out = Vec::new();
+        let mut ser = HtmlSerializer::fragment(
+            &mut out,
+            HtmlOptions {
+                stylesheets: vec!["https://example.com/style.css".into()],
+                ..Default::default()
+            },
+        );
+        ser.write_doc(&doc).unwrap();
+        drop(ser);
+        let html = String::from_utf8(out).unwrap();
+        for scaffolding in &["<!DOCTYPE", "<html", "<head", "<link", "<body", "<main"] {
+            assert!(!html.contains(scaffolding), "{}", html);
+        }
+        assert!(html.starts_with("\n<p>One<sup>"), "{}", html);
+        assert!(html.ends_with("</ol></section>"), "{}", html);
+        assert!(html.contains(r#"<li id="fn-1">a"#));
+        assert!(html.contains(r#"<li id="fn-2">b"#));
+        assert_balanced(&html);
+    }
+
+    #[test]
+    fn test_landmarks() {
+        let content = || {
+            vec![BlockInner::Par(vec![
+                Inline::Text("a".into()),
+                Inline::Footnote(Footnote {
+                    content: Block {
+                        id: Id::from(10),
+                        inner: BlockInner::Plain(vec![Inline::Text("A".into())]),
+                    }
+                    .into(),
+                }),
+            ])]
+        };
+        let html = to_html_with(
+            HtmlOptions {
+                skip_link: Some("Skip to content".into()),
+                ..Default::default()
+            },
+            content(),
+        );
+        let body = html.split("<body>").nth(1).unwrap().replace('\n', "");
+        assert_eq!(
+            concat!(
+                r##"<a class="skip-link" href="#main">Skip to content</a>"##,
+                r##"<main id="main"><p>a<sup><a href="#fn-1" id="fn-link-1" role="doc-noteref" "##,
+                r##"aria-label="Footnote 1">[1]</a></sup></p>"##,
+                r##"<section role="doc-endnotes" aria-label="Footnotes"><ol class="footnotes">"##,
+                r##"<li id="fn-1">A <a href="#fn-link-1" role="doc-backlink" "##,
+                r##"aria-label="Back to reference 1">↩</a>"##,
+                r##"<a href="#fn-1" class="anchor" aria-label="Link to footnote 1">¶</a></li></ol></section>"##,
+                r##"</main></body></html>"##,
+            ),
+            body
+        );
+
+        let html = to_html_with(
+            HtmlOptions {
+                a11y_landmarks: false,
+                skip_link: Some("Skip to content".into()),
+                ..Default::default()
+            },
+            content(),
+        );
+        assert!(!html.contains("<main"));
+        assert!(!html.contains("<section"));
+        assert!(!html.contains("skip-link"));
+    }
+
+    #[test]
+    fn test_footnote_placement() {
+        let note = |id: usize, num: usize| {
+            format!(
+                concat!(
+                    r##"<sup><a href="#fn-{0}" id="fn-link-{0}" role="doc-noteref" "##,
+                    r##"aria-label="Footnote {1}">[{1}]</a></sup>"##,
+                ),
+                id, num
+            )
+        };
+        let item = |id: usize, num: usize, text: &str| {
+            format!(
+                concat!(
+                    r##"<li id="fn-{0}">{2} <a href="#fn-link-{0}" role="doc-backlink" "##,
+                    r##"aria-label="Back to reference {1}">↩</a></li>"##,
+                ),
+                id, num, text
+            )
+        };
+
+        assert_eq!(
+            format!(
+                concat!(
+                    r#"<h1 id="One">One</h1><p>a{}</p>"#,
+                    r#"<h1 id="Two">Two</h1><p>b{}</p><p>c{}</p>"#,
+                    r#"<ol class="footnotes">{}{}{}</ol></body></html>"#
+                ),
+                note(1, 1),
+                note(2, 2),
+                note(3, 3),
+                item(1, 1, "A"),
+                item(2, 2, "B"),
+                item(3, 3, "C"),
+            ),
+            footnote_html(
+                FootnotePlacement::EndOfDocument,
+                FootnoteNumbering::Continuous
+            )
+        );
+
+        assert_eq!(
+            format!(
+                concat!(
+                    r#"<h1 id="One">One</h1><p>a{}</p><ol class="footnotes">{}</ol>"#,
+                    r#"<h1 id="Two">Two</h1><p>b{}</p><p>c{}</p>"#,
+                    r#"<ol class="footnotes" start="2">{}{}</ol></body></html>"#
+                ),
+                note(1, 1),
+                item(1, 1, "A"),
+                note(2, 2),
+                note(3, 3),
+                item(2, 2, "B"),
+                item(3, 3, "C"),
+            ),
+            footnote_html(
+                FootnotePlacement::EndOfSection,
+                FootnoteNumbering::Continuous
+            )
+        );
+
+        assert_eq!(
+            format!(
+                concat!(
+                    r#"<h1 id="One">One</h1><p>a{}</p><ol class="footnotes">{}</ol>"#,
+                    r#"<h1 id="Two">Two</h1><p>b{}</p><p>c{}</p>"#,
+                    r#"<ol class="footnotes">{}{}</ol></body></html>"#
+                ),
+                note(1, 1),
+                item(1, 1, "A"),
+                note(2, 1),
+                note(3, 2),
+                item(2, 1, "B"),
+                item(3, 2, "C"),
+            ),
+            footnote_html(
+                FootnotePlacement::EndOfSection,
+                FootnoteNumbering::PerSection
+            )
+        );
+
+        let html = footnote_html(
+            FootnotePlacement::EndOfDocument,
+            FootnoteNumbering::PerSection,
+        );
+        assert!(html.contains(&format!(
+            r#"<ol class="footnotes">{}<li id="fn-2" value="1">"#,
+            item(1, 1, "A")
+        )));
+
+        let html = footnote_html(FootnotePlacement::EndOfBlock, FootnoteNumbering::Continuous);
+        assert!(html.contains(&format!(
+            r#"<p>c{}</p><ol class="footnotes" start="3">{}</ol>"#,
+            note(3, 3),
+            item(3, 3, "C")
+        )));
+    }
+
+    #[test]
+    fn test_footnote_section_level() {
+        let par = |note: &str| {
+            BlockInner::Par(vec![Inline::Footnote(Footnote {
+                content: Block {
+                    id: Id::from(10),
+                    inner: BlockInner::Plain(vec![Inline::Text(note.into())]),
+                }
+                .into(),
+            })])
+        };
+        // The footnote references' labels, and the `<ol>`s with their
+        // items' ids.
+        let lists = |footnote_section_level: i32| {
+            let html = to_html_with(
+                HtmlOptions {
+                    footnote_placement: FootnotePlacement::EndOfSection,
+                    footnote_numbering: FootnoteNumbering::PerSection,
+                    footnote_section_level,
+                    ..Default::default()
+                },
+                vec![
+                    heading(0, 1, "One").inner,
+                    par("A"),
+                    heading(0, 2, "Sub").inner,
+                    par("B"),
+                    heading(0, 1, "Two").inner,
+                    par("C"),
+                ],
+            );
+            let mut labels = Vec::new();
+            let mut lists: Vec<Vec<String>> = Vec::new();
+            for (name, attrs) in start_tags(&html) {
+                let attr = |key: &str| attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+                match name.as_str() {
+                    "a" if attr("role").as_deref() == Some("doc-noteref") => {
+                        labels.push(attr("aria-label").unwrap())
+                    }
+                    "ol" => lists.push(Vec::new()),
+                    "li" => lists.last_mut().unwrap().push(attr("id").unwrap()),
+                    _ => {}
+                }
+            }
+            (labels, lists)
+        };
+
+        let (labels, ids) = lists(1);
+        assert_eq!(vec!["Footnote 1", "Footnote 2", "Footnote 1"], labels);
+        assert_eq!(vec![vec!["fn-1", "fn-2"], vec!["fn-3"]], ids);
+
+        // Footnote ids stay unique when their numbers restart.
+        let (labels, ids) = lists(2);
+        assert_eq!(vec!["Footnote 1", "Footnote 1", "Footnote 1"], labels);
+        assert_eq!(vec![vec!["fn-1"], vec!["fn-2"], vec!["fn-3"]], ids);
+    }
+
+    /// A chain of `depth` quotes, around a paragraph.
+    fn quote_chain(depth: usize) -> Block {
+        let mut block = Block {
+            id: Id::from(0),
+            inner: BlockInner::Par(vec![Inline::Text("Deep.".into())]),
+        };
+        for i in 1..=depth {
+            block = Block {
+                id: Id::from(i),
+                inner: BlockInner::Quote(block.into()),
+            };
+        }
+        block
+    }
+
+    #[test]
+    fn test_too_deep() {
+        let mut out = Vec::new();
+        let err = HtmlSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&Doc::from_content(quote_chain(100_000).into()))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SerializerError::TooDeep {
+                depth: DEFAULT_MAX_DEPTH
+            }
+        ));
+        let html = String::from_utf8(out).unwrap();
+        assert!(fh::tokenize(&html).is_ok());
+
+        let html = to_html(vec![quote_chain(100).inner]);
+        assert_eq!(100, html.matches("<blockquote>").count());
+    }
+
+    #[test]
+    fn test_too_deep_inlines() {
+        let mut inline = Inline::Text("Deep.".into());
+        for _ in 0..100_000 {
+            inline = Inline::Styled {
+                style: doc::Style::Emph,
+                content: vec![inline],
+            };
+        }
+        let doc = Doc::from_content(
+            Block {
+                id: Id::from(0),
+                inner: BlockInner::Par(vec![inline]),
+            }
+            .into(),
+        );
+        let mut out = Vec::new();
+        let err = HtmlSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&doc)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SerializerError::TooDeep {
+                depth: DEFAULT_MAX_DEPTH
+            }
+        ));
+        // Dropping the document doesn't overflow the stack either.
+        drop(doc);
+    }
+
+    #[test]
+    fn test_adversarial_footnote() {
+        let evil = "</li></ol><script>";
+        let html = to_html(vec![BlockInner::Plain(vec![Inline::Footnote(Footnote {
+            content: Block {
+                id: Id::from(10),
+                inner: BlockInner::Plain(vec![Inline::Text(evil.into())]),
+            }
+            .into(),
+        })])]);
+        let tags = start_tags(&html);
+        let names: Vec<_> = tags.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            vec!["html", "head", "body", "main", "sup", "a", "section", "ol", "li", "a", "a"],
+            names
+        );
+        assert!(fh::tokenize(&html)
+            .unwrap()
+            .contains(&fh::HtmlToken::Text(format!("{} ", evil))));
+    }
+
+    fn footnote(content: Vec<BlockInner>) -> Inline {
+        Inline::Footnote(Footnote {
+            content: content
+                .into_iter()
+                .map(|inner| Block {
+                    id: Id::from(10),
+                    inner,
+                })
+                .collect::<Vec<_>>()
+                .into(),
+        })
+    }
+
+    #[test]
+    fn test_footnote_content() {
+        let text = |text: &str| vec![Inline::Text(text.into())];
+        let html = to_html_with(
+            HtmlOptions {
+                heading_anchors: HeadingAnchors::None,
+                a11y_landmarks: false,
+                indent: Some(2),
+                ..Default::default()
+            },
+            vec![
+                BlockInner::Par(vec![
+                    Inline::Text("a".into()),
+                    footnote(vec![
+                        BlockInner::Par(text("A")),
+                        BlockInner::Quote(
+                            vec![Block {
+                                id: Id::from(11),
+                                inner: BlockInner::Par(text("quoted")),
+                            }]
+                            .into(),
+                        ),
+                    ]),
+                ]),
+                BlockInner::Par(vec![
+                    Inline::Text("b".into()),
+                    footnote(vec![BlockInner::Plain(text("B"))]),
+                ]),
+            ],
+        );
+        assert_eq!(
+            concat!(
+                "<!DOCTYPE html>\n<html>\n  <head>\n  </head>\n  <body>\n\n",
+                r##"    <p>a<sup><a href="#fn-1" id="fn-link-1" role="doc-noteref" "##,
+                r##"aria-label="Footnote 1">[1]</a></sup></p>"##,
+                "\n",
+                r##"    <p>b<sup><a href="#fn-2" id="fn-link-2" role="doc-noteref" "##,
+                r##"aria-label="Footnote 2">[2]</a></sup></p>"##,
+                "\n",
+                "    <ol class=\"footnotes\">\n",
+                "      <li id=\"fn-1\">\n",
+                "        <p>A</p>\n",
+                "        <blockquote>\n",
+                "          <p>quoted</p>\n",
+                r##"        </blockquote> <a href="#fn-link-1" role="doc-backlink" "##,
+                r##"aria-label="Back to reference 1">↩</a></li>"##,
+                "\n",
+                r##"      <li id="fn-2">B <a href="#fn-link-2" role="doc-backlink" "##,
+                r##"aria-label="Back to reference 2">↩</a></li>"##,
+                "\n",
+                "    </ol>\n  </body>\n</html>\n",
+            ),
+            html
+        );
+    }
+
+    #[test]
+    fn test_nested_footnotes() {
+        let text = |text: &str| vec![Inline::Text(text.into())];
+        let html = to_html_with(
+            HtmlOptions {
+                heading_anchors: HeadingAnchors::None,
+                a11y_landmarks: false,
+                ..Default::default()
+            },
+            vec![BlockInner::Par(vec![
+                Inline::Text("a".into()),
+                footnote(vec![BlockInner::Plain(vec![
+                    Inline::Text("b".into()),
+                    footnote(vec![BlockInner::Plain(text("c"))]),
+                ])]),
+                footnote(vec![BlockInner::Plain(text("d"))]),
+            ])],
+        );
+        let ids: Vec<_> = start_tags(&html)
+            .into_iter()
+            .filter(|(name, _)| name == "li")
+            .map(|(_, attrs)| attrs)
+            .collect();
+        // Footnotes in footnotes are listed after them, numbered in the order
+        // they're referenced.
+        assert_eq!(
+            vec![
+                attr_pairs(&[("id", "fn-1")]),
+                attr_pairs(&[("id", "fn-2")]),
+                attr_pairs(&[("id", "fn-3")]),
+            ],
+            ids
+        );
+        assert!(html.contains(concat!(
+            r##"<li id="fn-1">b<sup><a href="#fn-2" id="fn-link-2" "##,
+            r##"role="doc-noteref" aria-label="Footnote 2">[2]</a></sup> "##,
+        )));
+        assert!(html.contains(r#"<li id="fn-2">c "#));
+        assert!(html.contains(r#"<li id="fn-3">d "#));
+    }
+
+    #[test]
+    fn test_footnote_markers() {
+        let notes = || {
+            vec![BlockInner::Par(
+                (0..3)
+                    .map(|i| {
+                        Inline::Footnote(Footnote {
+                            content: Block {
+                                id: Id::from(10 + i),
+                                inner: BlockInner::Plain(vec![Inline::Text(i.to_string())]),
+                            }
+                            .into(),
+                        })
+                    })
+                    .collect(),
+            )]
+        };
+        let markers = |footnote_markers: FootnoteMarkers| {
+            let html = to_html_with(
+                HtmlOptions {
+                    footnote_markers,
+                    ..Default::default()
+                },
+                notes(),
+            );
+            let tags = start_tags(&html);
+            let tokens = fh::tokenize(&html).unwrap();
+            let mut texts = Vec::new();
+            let mut list_styles = Vec::new();
+            for (i, tok) in tokens.iter().enumerate() {
+                match tok {
+                    fh::HtmlToken::StartTag { name, attrs }
+                        if name == "a"
+                            && attrs.contains(&("role".to_owned(), "doc-noteref".to_owned())) =>
+                    {
+                        if let Some(fh::HtmlToken::Text(text)) = tokens.get(i + 1) {
+                            texts.push(text.clone());
+                        }
+                    }
+                    fh::HtmlToken::StartTag { name, attrs } if name == "li" => {
+                        list_styles.extend(
+                            attrs
+                                .iter()
+                                .filter(|(k, _)| k == "style")
+                                .map(|(_, v)| v.clone()),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+
+            // Every link within the page goes somewhere.
+            let ids: Vec<_> = tags
+                .iter()
+                .flat_map(|(_, attrs)| attrs.iter().filter(|(k, _)| k == "id"))
+                .map(|(_, v)| v.as_str())
+                .collect();
+            for (_, attrs) in &tags {
+                for (_, href) in attrs.iter().filter(|(k, _)| k == "href") {
+                    if let Some(target) = href.strip_prefix('#') {
+                        assert!(ids.contains(&target), "No element with id {:?}", target);
+                    }
+                }
+            }
+            (texts, list_styles)
+        };
+
+        assert_eq!(
+            (vec!["[1]".to_owned(), "[2]".into(), "[3]".into()], vec![]),
+            markers(FootnoteMarkers::Numeric)
+        );
+        assert_eq!(
+            (
+                vec!["*".to_owned(), "†".into(), "‡".into()],
+                vec![
+                    "list-style-type: \"* \"".to_owned(),
+                    "list-style-type: \"† \"".into(),
+                    "list-style-type: \"‡ \"".into(),
+                ]
+            ),
+            markers(FootnoteMarkers::Symbols)
+        );
+        assert_eq!(
+            (
+                vec!["a".to_owned(), "b".into(), "\"".into()],
+                vec![
+                    "list-style-type: \"a \"".to_owned(),
+                    "list-style-type: \"b \"".into(),
+                    "list-style-type: \"\\\" \"".into(),
+                ]
+            ),
+            markers(FootnoteMarkers::Custom(Rc::new(|num| {
+                ["a", "b", "\""][num - 1].to_owned()
+            })))
+        );
+
+        let html = to_html_with(
+            HtmlOptions {
+                footnote_heading: Some("Notes".into()),
+                ..Default::default()
+            },
+            notes(),
+        );
+        assert!(html.contains(r#"<section role="doc-endnotes" aria-label="Notes">"#));
+        assert!(html.contains(r#"<h2 class="footnotes-heading">Notes</h2>"#));
+
+        let html = to_html_with(
+            HtmlOptions {
+                footnote_heading: Some("Notes".into()),
+                footnote_section_level: 2,
+                heading_offset: 1,
+                ..Default::default()
+            },
+            notes(),
+        );
+        assert!(html.contains(r#"<h4 class="footnotes-heading">Notes</h4>"#));
+    }
+}
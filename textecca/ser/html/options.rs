@@ -0,0 +1,484 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use super::super::{AssetCollector, EmbedLimits};
+use super::MathConfig;
+use crate::cmd::{ResourceResolver, Strictness};
+use crate::doc::TaggedBlocks;
+
+/// Options for an `HtmlSerializer`.
+#[derive(Clone)]
+pub struct HtmlOptions {
+    /// How headings link to themselves.
+    pub heading_anchors: HeadingAnchors,
+    /// Added to the HTML levels of headings before they're written, e.g. 1 to
+    /// write level 1 headings (and titles, parts, and chapters, which are
+    /// `<h1>`s) as `<h2>`s when the output is embedded in a page under an
+    /// `<h1>`. Shifted levels are clamped to 1 to 6, the levels HTML has,
+    /// rather than being rejected. Headings' ids and classes, and the sections
+    /// footnotes are grouped by, are unaffected.
+    pub heading_offset: i32,
+    /// Whether a table of contents (see `doc::toc`) is written before the
+    /// document's content, after its abstract; off by default. Split
+    /// documents (see `write_split`) always have one on their index page
+    /// instead.
+    pub toc: bool,
+    /// The text of the marker written after headings with
+    /// `HeadingAnchors::TrailingMarker`, e.g. `¶` or `#`.
+    pub anchor_marker: String,
+    /// The class of the marker written after headings with
+    /// `HeadingAnchors::TrailingMarker`.
+    pub anchor_class: String,
+    /// The label for screen readers of the marker written after headings
+    /// with `HeadingAnchors::TrailingMarker`; by default, `Link to this
+    /// section`. Footnotes' markers are labeled `Link to footnote 1` and so
+    /// on. With `None`, both are hidden from screen readers and left out of
+    /// the tab order.
+    pub anchor_label: Option<String>,
+    /// Handles renderer hints on tagged blocks; see `HintHandler`.
+    pub hint_handler: Option<HintHandler>,
+    /// How math which fails to render and content which can't be written as
+    /// HTML are handled.
+    pub strictness: Strictness,
+    /// The directory the HTML is written to, relative to the project root (see
+    /// `cmd::ResourceResolver`). Image paths are rewritten to be relative to
+    /// it; if it's not given, they're written relative to the project root.
+    pub output_dir: Option<PathBuf>,
+    /// Stylesheets to link to; URLs, or paths relative to the project root.
+    pub stylesheets: Vec<String>,
+    /// How math is rendered, and the stylesheet or script linked to in
+    /// documents containing math; by default, with KaTeX.
+    pub math: MathConfig,
+    /// If given, local images and stylesheets are added to the collector, and
+    /// referred to by the URLs of their copies rather than their paths.
+    pub assets: Option<Rc<RefCell<AssetCollector>>>,
+    /// Whether local images and stylesheets are embedded in the output, so
+    /// it's a single self-contained file: images as `data:` URIs, and
+    /// stylesheets in `<style>` elements, along with the local files they
+    /// refer to (e.g. fonts). Files are read with `resolver`; without one,
+    /// nothing is embedded. Files which can't be embedded, e.g. because
+    /// they're over `embed_limits`, are reported, and referred to as if this
+    /// was off. A local copy of KaTeX's stylesheet (see `MathConfig::Katex`)
+    /// is embedded with its fonts, but the copy on the CDN and MathJax's script
+    /// are always linked to.
+    pub embed_assets: bool,
+    /// Reads the files embedded with `embed_assets`.
+    pub resolver: Option<Rc<dyn ResourceResolver>>,
+    /// Limits on the size of the files embedded with `embed_assets`.
+    pub embed_limits: EmbedLimits,
+    /// Where footnotes are listed.
+    pub footnote_placement: FootnotePlacement,
+    /// Whether footnote numbers restart in each section.
+    pub footnote_numbering: FootnoteNumbering,
+    /// How footnotes are marked, where they're referenced and in their list.
+    pub footnote_markers: FootnoteMarkers,
+    /// If given, a heading with this text is written before each list of
+    /// footnotes, which is also labeled with it; otherwise, the list is
+    /// labeled `Footnotes` for screen readers. The heading is one level below
+    /// `footnote_section_level`, shifted by `heading_offset`.
+    pub footnote_heading: Option<String>,
+    /// The highest heading level which starts a section for
+    /// `FootnotePlacement::EndOfSection` and `FootnoteNumbering::PerSection`;
+    /// by default, 1. Only headings outside of any other block start sections.
+    pub footnote_section_level: i32,
+    /// Whether the document's content is wrapped in a `<main>` landmark and
+    /// footnote lists in a `<section role="doc-endnotes">`, so screen reader
+    /// users can navigate by them; on by default.
+    pub a11y_landmarks: bool,
+    /// If given with `a11y_landmarks`, a link with this text to the `<main>`
+    /// landmark is written first in the `<body>`, e.g. `Skip to content`, so
+    /// keyboard users can skip to the content.
+    pub skip_link: Option<String>,
+    /// The deepest nesting of blocks and inlines written, e.g. quotes in
+    /// quotes; deeper documents give a `SerializerError::TooDeep`. By
+    /// default, `DEFAULT_MAX_DEPTH`.
+    pub max_depth: usize,
+    /// Whether the output is for browsers with scripting enabled, which
+    /// changes how text in `<noscript>` elements (e.g. in the HTML given by a
+    /// `HintHandler`) is escaped; see `friendly_html::Options`. On by default.
+    pub scripting_enabled: bool,
+    /// If given, block-level elements are indented by this many spaces per
+    /// level of nesting, so the output is easier to read and diff; see
+    /// `friendly_html::Options::indent`. By default, `None`.
+    pub indent: Option<usize>,
+    /// The classes of the elements written for each kind of content, e.g. so
+    /// the output fits an existing stylesheet; see `ClassMap`.
+    pub classes: ClassMap,
+}
+
+/// The default `HtmlOptions::max_depth`.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+impl Default for HtmlOptions {
+    fn default() -> Self {
+        Self {
+            heading_anchors: Default::default(),
+            heading_offset: 0,
+            toc: false,
+            anchor_marker: "¶".to_owned(),
+            anchor_class: "anchor".to_owned(),
+            anchor_label: Some("Link to this section".to_owned()),
+            hint_handler: None,
+            strictness: Default::default(),
+            output_dir: None,
+            stylesheets: Vec::new(),
+            math: Default::default(),
+            assets: None,
+            embed_assets: false,
+            resolver: None,
+            embed_limits: Default::default(),
+            footnote_placement: Default::default(),
+            footnote_numbering: Default::default(),
+            footnote_markers: Default::default(),
+            footnote_heading: None,
+            footnote_section_level: 1,
+            a11y_landmarks: true,
+            skip_link: None,
+            max_depth: DEFAULT_MAX_DEPTH,
+            scripting_enabled: true,
+            indent: None,
+            classes: Default::default(),
+        }
+    }
+}
+
+impl fmt::Debug for HtmlOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HtmlOptions")
+            .field("heading_anchors", &self.heading_anchors)
+            .field("heading_offset", &self.heading_offset)
+            .field("toc", &self.toc)
+            .field("anchor_marker", &self.anchor_marker)
+            .field("anchor_class", &self.anchor_class)
+            .field("anchor_label", &self.anchor_label)
+            .field(
+                "hint_handler",
+                &self.hint_handler.as_ref().map(|_| "<handler>"),
+            )
+            .field("strictness", &self.strictness)
+            .field("output_dir", &self.output_dir)
+            .field("stylesheets", &self.stylesheets)
+            .field("math", &self.math)
+            .field("assets", &self.assets)
+            .field("embed_assets", &self.embed_assets)
+            .field("resolver", &self.resolver.as_ref().map(|_| "<resolver>"))
+            .field("embed_limits", &self.embed_limits)
+            .field("footnote_placement", &self.footnote_placement)
+            .field("footnote_numbering", &self.footnote_numbering)
+            .field("footnote_markers", &self.footnote_markers)
+            .field("footnote_heading", &self.footnote_heading)
+            .field("footnote_section_level", &self.footnote_section_level)
+            .field("a11y_landmarks", &self.a11y_landmarks)
+            .field("skip_link", &self.skip_link)
+            .field("max_depth", &self.max_depth)
+            .field("scripting_enabled", &self.scripting_enabled)
+            .field("indent", &self.indent)
+            .field("classes", &self.classes)
+            .finish()
+    }
+}
+
+impl PartialEq for HtmlOptions {
+    fn eq(&self, other: &Self) -> bool {
+        self.heading_anchors == other.heading_anchors
+            && self.heading_offset == other.heading_offset
+            && self.toc == other.toc
+            && self.anchor_marker == other.anchor_marker
+            && self.anchor_class == other.anchor_class
+            && self.anchor_label == other.anchor_label
+            && match (&self.hint_handler, &other.hint_handler) {
+                (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+            && self.strictness == other.strictness
+            && self.output_dir == other.output_dir
+            && self.stylesheets == other.stylesheets
+            && self.math == other.math
+            && match (&self.assets, &other.assets) {
+                (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+            && self.embed_assets == other.embed_assets
+            && match (&self.resolver, &other.resolver) {
+                (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+            && self.embed_limits == other.embed_limits
+            && self.footnote_placement == other.footnote_placement
+            && self.footnote_numbering == other.footnote_numbering
+            && self.footnote_markers == other.footnote_markers
+            && self.footnote_heading == other.footnote_heading
+            && self.footnote_section_level == other.footnote_section_level
+            && self.a11y_landmarks == other.a11y_landmarks
+            && self.skip_link == other.skip_link
+            && self.max_depth == other.max_depth
+            && self.scripting_enabled == other.scripting_enabled
+            && self.indent == other.indent
+            && self.classes == other.classes
+    }
+}
+
+/// A function handling the renderer hints (see `doc::meta_hints`) on tagged
+/// blocks. It's called with each `html:` hint's key (without the prefix) and
+/// value, along with the tagged blocks, until a hint is handled.
+///
+/// The `class` hint is built in, and gives the class of the element wrapping
+/// the tagged content.
+///
+/// ```
+/// use std::rc::Rc;
+///
+/// use textecca::doc::{Block, BlockInner, Doc, Id, Inline, Meta, TaggedBlocks};
+/// use textecca::ser::{Handled, HtmlOptions, HtmlSerializer, Serializer as _};
+///
+/// // A `\youtube{id}` command might push a link to the video, hinting that
+/// // HTML output should embed the video instead.
+/// let mut meta = Meta::new();
+/// meta.insert("html:embed".into(), "youtube".into());
+/// meta.insert("youtube".into(), "dQw4w9WgXcQ".into());
+/// let doc = Doc::from_content(
+///     Block::new(
+///         Id::from(0),
+///         BlockInner::Tagged(TaggedBlocks {
+///             content: Block::new(
+///                 Id::from(1),
+///                 BlockInner::Plain(vec![Inline::Text("A video.".into())]),
+///             )
+///             .into(),
+///             meta,
+///         }),
+///     )
+///     .into(),
+/// );
+///
+/// let options = HtmlOptions {
+///     hint_handler: Some(Rc::new(|key: &str, value: &str, tagged: &TaggedBlocks| {
+///         match (key, value, tagged.meta.get("youtube")) {
+///             ("embed", "youtube", Some(id)) if id.chars().all(|c| c.is_ascii_alphanumeric()) => {
+///                 Handled::Replace(format!(
+///                     r#"<iframe src="https://www.youtube.com/embed/{}"></iframe>"#,
+///                     id
+///                 ))
+///             }
+///             _ => Handled::No,
+///         }
+///     })),
+///     ..Default::default()
+/// };
+/// let mut out = Vec::new();
+/// HtmlSerializer::with_options(&mut out, options)
+///     .unwrap()
+///     .write_doc(&doc)
+///     .unwrap();
+/// let html = String::from_utf8(out).unwrap();
+/// assert!(html.contains(r#"<iframe src="https://www.youtube.com/embed/dQw4w9WgXcQ"></iframe>"#));
+/// assert!(!html.contains("A video."));
+/// ```
+pub type HintHandler = Rc<dyn Fn(&str, &str, &TaggedBlocks) -> Handled>;
+
+/// The result of a `HintHandler`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Handled {
+    /// The hint was handled; this HTML is written instead of the tagged
+    /// content.
+    Replace(String),
+    /// The hint wasn't handled.
+    No,
+}
+
+/// How headings link to themselves, so readers can copy a link to a section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeadingAnchors {
+    /// Headings aren't links.
+    None,
+    /// The heading's text is a link to the heading.
+    WrapHeading,
+    /// A marker linking to the heading is written after the heading's text,
+    /// labeled for screen readers with `HtmlOptions::anchor_label`.
+    #[default]
+    TrailingMarker,
+}
+
+/// Where footnotes are listed, as an `<ol class="footnotes">`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FootnotePlacement {
+    /// All footnotes are listed at the end of the document.
+    #[default]
+    EndOfDocument,
+    /// Footnotes are listed before each heading which starts a section (see
+    /// `HtmlOptions::footnote_section_level`), and at the end of the document.
+    EndOfSection,
+    /// Footnotes are listed after each block outside of any other block, e.g.
+    /// after each paragraph.
+    EndOfBlock,
+}
+
+/// How footnotes are numbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FootnoteNumbering {
+    /// Footnotes are numbered from 1 through the whole document.
+    #[default]
+    Continuous,
+    /// Footnote numbers restart from 1 in each section (see
+    /// `HtmlOptions::footnote_section_level`).
+    PerSection,
+}
+
+/// How footnotes are marked; see `HtmlOptions::footnote_markers`.
+#[derive(Clone, Default)]
+pub enum FootnoteMarkers {
+    /// Footnotes are marked with their numbers, like `[1]`, and listed in
+    /// a numbered list.
+    #[default]
+    Numeric,
+    /// Footnotes are marked with the traditional symbols `*`, `†`, `‡`, `§`,
+    /// `‖`, and `¶`, which are doubled, tripled, etc. after the first six
+    /// footnotes; see `footnote_symbol`.
+    Symbols,
+    /// Footnotes are marked with the text given by a function of their
+    /// numbers, which start at 1.
+    Custom(Rc<dyn Fn(usize) -> String>),
+}
+
+impl FootnoteMarkers {
+    /// The marker for the footnote with the given number.
+    pub fn marker(&self, num: usize) -> String {
+        match self {
+            Self::Numeric => format!("[{}]", num),
+            Self::Symbols => footnote_symbol(num),
+            Self::Custom(marker) => marker(num),
+        }
+    }
+}
+
+impl fmt::Debug for FootnoteMarkers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Numeric => write!(f, "Numeric"),
+            Self::Symbols => write!(f, "Symbols"),
+            Self::Custom(_) => write!(f, "Custom(<function>)"),
+        }
+    }
+}
+
+impl PartialEq for FootnoteMarkers {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Numeric, Self::Numeric) | (Self::Symbols, Self::Symbols) => true,
+            (Self::Custom(a), Self::Custom(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// The traditional symbol for the footnote with the given number, which
+/// starts at 1: `*`, `†`, `‡`, `§`, `‖`, `¶`, then `**`, `††`, and so on.
+///
+/// ```
+/// use textecca::ser::footnote_symbol;
+///
+/// assert_eq!("*", footnote_symbol(1));
+/// assert_eq!("¶", footnote_symbol(6));
+/// assert_eq!("††", footnote_symbol(8));
+/// ```
+pub fn footnote_symbol(num: usize) -> String {
+    const SYMBOLS: [&str; 6] = ["*", "†", "‡", "§", "‖", "¶"];
+    let i = num.saturating_sub(1);
+    SYMBOLS[i % SYMBOLS.len()].repeat(i / SYMBOLS.len() + 1)
+}
+
+/// The kinds of elements whose classes are given by a `ClassMap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ElementKind {
+    /// A paragraph's `<p>`.
+    Paragraph,
+    /// A heading's `<h1>` to `<h6>`.
+    Heading,
+    /// A quote's `<blockquote>`.
+    Blockquote,
+    /// A list's `<ul>` or `<ol>`.
+    List,
+    /// A term list's `<dl>`.
+    TermList,
+    /// A table's `<table>`.
+    Table,
+    /// A code block's `<pre>`.
+    Code,
+    /// Inline code's `<code>`.
+    InlineCode,
+    /// A figure's `<figure>`, along with its kind's name.
+    Figure,
+    /// A horizontal rule's `<hr>`.
+    Rule,
+    /// A definition's `<section>`.
+    Defn,
+    /// The `<section>` of the document's abstract.
+    Abstract,
+    /// The `<nav>` of a table of contents.
+    Toc,
+    /// The `<ol>` listing footnotes.
+    FootnoteList,
+    /// The heading before a list of footnotes; see
+    /// `HtmlOptions::footnote_heading`.
+    FootnoteHeading,
+}
+
+/// The classes of the elements written for each kind of content, as
+/// space-separated class names. Elements with no classes (including those
+/// mapped to an empty string) have no `class` attribute.
+///
+/// By default, the abstract, tables of contents, definitions, and footnote
+/// lists and their headings have classes named after them (`abstract`,
+/// `toc`, `defn`, `footnotes`, and `footnotes-heading`), and other elements
+/// have none. Classes given in a block's attributes are added to these.
+///
+/// ```
+/// # use textecca::ser::{ClassMap, ElementKind};
+/// let classes = ClassMap::default()
+///     .with(ElementKind::Paragraph, "prose")
+///     .with(ElementKind::FootnoteList, "");
+/// assert_eq!("prose", classes.get(ElementKind::Paragraph));
+/// assert_eq!("", classes.get(ElementKind::FootnoteList));
+/// assert_eq!("toc", classes.get(ElementKind::Toc));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassMap {
+    classes: HashMap<ElementKind, String>,
+}
+
+impl ClassMap {
+    /// A map giving no element any classes.
+    pub fn empty() -> Self {
+        Self {
+            classes: HashMap::new(),
+        }
+    }
+
+    /// Set the classes of an element kind.
+    pub fn with(mut self, kind: ElementKind, classes: impl Into<String>) -> Self {
+        self.classes.insert(kind, classes.into());
+        self
+    }
+
+    /// The classes of an element kind, or an empty string if it has none.
+    pub fn get(&self, kind: ElementKind) -> &str {
+        self.classes.get(&kind).map(String::as_str).unwrap_or("")
+    }
+}
+
+impl Default for ClassMap {
+    fn default() -> Self {
+        Self::empty()
+            .with(ElementKind::Abstract, "abstract")
+            .with(ElementKind::Toc, "toc")
+            .with(ElementKind::Defn, "defn")
+            .with(ElementKind::FootnoteList, "footnotes")
+            .with(ElementKind::FootnoteHeading, "footnotes-heading")
+    }
+}
@@ -0,0 +1,155 @@
+//! Recording output to write it later, somewhere else; see `Output`.
+
+use std::io::Write;
+
+use friendly_html as fh;
+
+/// A call to one of `fh::HtmlSerializer`'s writing methods.
+#[derive(Debug, Clone, PartialEq)]
+enum Op {
+    /// `elem_attrs`; `elem` is recorded with no attributes.
+    Elem(String, Vec<(String, String)>),
+    EndElem,
+    Text(String),
+    Html(String),
+}
+
+/// Output recorded by `Output::start_recording` and `Output::finish_recording`,
+/// to be written with `Output::replay`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct Recording(Vec<Op>);
+
+/// An `fh::HtmlSerializer` whose output can be recorded rather than written,
+/// e.g. so a footnote's content can be written where the footnote is
+/// referenced, while its blocks are at hand, but listed later.
+///
+/// Calls are recorded rather than their output so they're indented where
+/// they're replayed, exactly as if they were made there.
+pub(super) struct Output<W: Write> {
+    ser: fh::HtmlSerializer<W>,
+    /// The recordings in progress, innermost last; only the innermost is
+    /// added to.
+    recordings: Vec<Recording>,
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(ser: fh::HtmlSerializer<W>) -> Self {
+        Self {
+            ser,
+            recordings: Vec::new(),
+        }
+    }
+
+    fn record(&mut self) -> Option<&mut Vec<Op>> {
+        self.recordings.last_mut().map(|recording| &mut recording.0)
+    }
+
+    /// Record output until the matching `finish_recording` rather than
+    /// writing it.
+    pub fn start_recording(&mut self) {
+        self.recordings.push(Recording::default());
+    }
+
+    /// Stop the innermost recording started with `start_recording` and return
+    /// it.
+    pub fn finish_recording(&mut self) -> Recording {
+        self.recordings
+            .pop()
+            .expect("finish_recording called without start_recording")
+    }
+
+    /// Write a recording as if its calls were made now, or add them to the
+    /// recording in progress if there is one.
+    pub fn replay(&mut self, recording: Recording) -> Result<(), fh::SerializeError> {
+        if let Some(ops) = self.record() {
+            ops.extend(recording.0);
+            return Ok(());
+        }
+        for op in recording.0 {
+            match op {
+                Op::Elem(name, attrs) => self.ser.elem_attrs(name, &attrs)?,
+                Op::EndElem => self.ser.end_elem()?,
+                Op::Text(text) => self.ser.write_text(text)?,
+                Op::Html(html) => self.ser.write_html(&html)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// See `fh::HtmlSerializer::elem`.
+    pub fn elem(&mut self, name: impl AsRef<str>) -> Result<(), fh::SerializeError> {
+        let no_attrs: &[(&str, &str)] = &[];
+        self.elem_attrs(name, no_attrs)
+    }
+
+    /// See `fh::HtmlSerializer::elem_attrs`. Attribute names are checked when
+    /// they're recorded, so the error is reported where it happens.
+    pub fn elem_attrs(
+        &mut self,
+        name: impl AsRef<str>,
+        attrs: &[(impl AsRef<str>, impl AsRef<str>)],
+    ) -> Result<(), fh::SerializeError> {
+        match self.record() {
+            Some(ops) => {
+                let attrs = attrs
+                    .iter()
+                    .map(|(name, value)| {
+                        let name = name.as_ref();
+                        if fh::is_valid_attr_name(name) {
+                            Ok((name.to_owned(), value.as_ref().to_owned()))
+                        } else {
+                            Err(fh::SerializeError::InvalidAttrName(name.to_owned()))
+                        }
+                    })
+                    .collect::<Result<_, _>>()?;
+                ops.push(Op::Elem(name.as_ref().to_owned(), attrs));
+                Ok(())
+            }
+            None => self.ser.elem_attrs(name, attrs),
+        }
+    }
+
+    /// See `fh::HtmlSerializer::end_elem`.
+    pub fn end_elem(&mut self) -> Result<(), fh::SerializeError> {
+        match self.record() {
+            Some(ops) => {
+                ops.push(Op::EndElem);
+                Ok(())
+            }
+            None => self.ser.end_elem(),
+        }
+    }
+
+    /// See `fh::HtmlSerializer::write_text`.
+    pub fn write_text(&mut self, text: impl AsRef<str>) -> Result<(), fh::SerializeError> {
+        match self.record() {
+            Some(ops) => {
+                ops.push(Op::Text(text.as_ref().to_owned()));
+                Ok(())
+            }
+            None => self.ser.write_text(text),
+        }
+    }
+
+    /// See `fh::HtmlSerializer::write_html`.
+    pub fn write_html(&mut self, html: &str) -> Result<(), fh::SerializeError> {
+        match self.record() {
+            Some(ops) => {
+                ops.push(Op::Html(html.to_owned()));
+                Ok(())
+            }
+            None => self.ser.write_html(html),
+        }
+    }
+
+    /// See `fh::HtmlSerializer::flush`.
+    pub fn flush(&mut self) -> Result<(), fh::SerializeError> {
+        self.ser.flush()
+    }
+
+    /// See `fh::HtmlSerializer::abort`. Recordings in progress are dropped.
+    pub fn abort(&mut self) -> Result<(), fh::SerializeError> {
+        self.recordings.clear();
+        self.ser.abort()
+    }
+}
@@ -0,0 +1,551 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use super::{heading_ids, reserved_ids, HtmlOptions, HtmlSerializer};
+use crate::cmd::Warning;
+use crate::doc::{
+    self, walk_mut, Block, BlockInner, Blocks, Collections, Doc, Heading, Inline, Link, LinkTarget,
+    Meta, RefId, VisitMut,
+};
+use crate::ser::{
+    abstract_blocks, percent_encode_fragment, reserve_heading_ids, Serializer, SerializerError,
+};
+
+/// The file name of the index page written by `write_split`.
+pub const SPLIT_INDEX: &str = "index.html";
+
+/// Write a document as multiple HTML files in `out_dir`, starting a new file
+/// at each heading (outside of any other block) with a level of at most
+/// `level`; e.g. with a level of 1, each chapter is written to its own file.
+///
+/// Files are named after their first heading's id (see `Heading::anchor`).
+/// The content before the first of these headings is written to
+/// `SPLIT_INDEX`, followed by a table of contents linking to each file.
+/// Links to labels and headings in other files are rewritten to point to the
+/// right file, and each file links to the previous and next ones. Footnotes
+/// are written in the file they're referenced in, but numbered as if the
+/// document were a single file, and heading ids are unique across all of the
+/// files, so a duplicate explicit id is an error as it is in a single file.
+///
+/// Returns the warnings emitted while writing the files.
+pub fn write_split(
+    doc: Doc,
+    level: i32,
+    out_dir: &Path,
+    options: HtmlOptions,
+) -> Result<Vec<Warning>, SerializerError> {
+    let Doc {
+        meta,
+        content,
+        mut collections,
+    } = doc;
+    let mut pages = split_pages(content, level);
+    link_pages(&mut pages);
+    let reserved: Vec<_> = reserved_ids(&options)
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+    let toc = page_toc(&pages, &meta, &collections, &reserved);
+
+    let mut warnings = Vec::new();
+    let mut ids = SplitIds::new(&pages, &collections);
+    for i in 0..pages.len() {
+        let nav = |i: usize| {
+            pages.get(i).map(|page: &Page| NavLink {
+                href: page.file.clone(),
+                text: page.title.clone(),
+            })
+        };
+        let split = SplitPage {
+            toc: if i == 0 { toc.clone() } else { Vec::new() },
+            prev: i.checked_sub(1).and_then(nav),
+            contents: if i <= 1 { None } else { nav(0) },
+            next: nav(i + 1),
+        };
+        let content = std::mem::take(&mut pages[i].content);
+        let file = BufWriter::new(File::create(out_dir.join(&pages[i].file))?);
+        let mut ser = HtmlSerializer::with_options(file, options.clone())?;
+        ser.split = Some(split);
+        ids.restore(&mut ser);
+        let result = ser.write_doc(&Doc {
+            meta: meta.clone(),
+            content,
+            // E.g. the abstract belongs on the first page.
+            collections: std::mem::take(&mut collections),
+        });
+        ids = SplitIds::take(&mut ser);
+        warnings.extend(ser.take_warnings());
+        result?;
+    }
+    Ok(warnings)
+}
+
+/// The state an `HtmlSerializer` carries from one of the files written by
+/// `write_split` (or one chapter of an EPUB) to the next, so footnote numbers
+/// and heading ids continue across files.
+#[derive(Default)]
+pub(super) struct SplitIds {
+    footnote_count: usize,
+    footnote_number: usize,
+    heading_ids: HashMap<String, String>,
+    heading_slugs: HashMap<String, usize>,
+}
+
+impl SplitIds {
+    /// The state for writing the first of `pages`, with the explicit heading
+    /// ids on every page reserved, so ids generated for headings on earlier
+    /// pages skip them.
+    pub(super) fn new(pages: &[Page], collections: &Collections) -> Self {
+        let mut ids = Self::default();
+        reserve_page_ids(pages, collections, &mut ids.heading_slugs);
+        ids
+    }
+
+    pub(super) fn take<W: std::io::Write>(ser: &mut HtmlSerializer<W>) -> Self {
+        Self {
+            footnote_count: ser.footnote_count,
+            footnote_number: ser.footnote_number,
+            heading_ids: std::mem::take(&mut ser.heading_ids),
+            heading_slugs: std::mem::take(&mut ser.heading_slugs),
+        }
+    }
+
+    pub(super) fn restore<W: std::io::Write>(self, ser: &mut HtmlSerializer<W>) {
+        ser.footnote_count = self.footnote_count;
+        ser.footnote_number = self.footnote_number;
+        ser.heading_ids = self.heading_ids;
+        ser.heading_slugs = self.heading_slugs;
+    }
+}
+
+/// One of the files written by `write_split`, or a chapter written by an
+/// `EpubSerializer`.
+pub(super) struct Page {
+    /// The file's name, relative to the output directory.
+    pub(super) file: String,
+    /// The plain text of the file's first heading, or "Contents" for the
+    /// index page.
+    pub(super) title: String,
+    pub(super) content: Blocks,
+}
+
+/// Extra content for one of the files written by `write_split`, written by
+/// the `HtmlSerializer`.
+#[derive(Debug, Clone, Default)]
+pub(super) struct SplitPage {
+    /// The table of contents, written after the content; empty except on the
+    /// index page.
+    pub(super) toc: Vec<SplitTocEntry>,
+    /// The previous file.
+    pub(super) prev: Option<NavLink>,
+    /// The index page, unless it's this page or the previous one.
+    pub(super) contents: Option<NavLink>,
+    /// The next file.
+    pub(super) next: Option<NavLink>,
+}
+
+/// A heading listed in a `SplitPage`'s table of contents.
+#[derive(Debug, Clone)]
+pub(super) struct SplitTocEntry {
+    pub(super) level: i32,
+    pub(super) link: NavLink,
+}
+
+/// A link to another file written by `write_split`.
+#[derive(Debug, Clone)]
+pub(super) struct NavLink {
+    pub(super) href: String,
+    pub(super) text: String,
+}
+
+/// Split a document's content into the index page and a page starting at
+/// each heading with a level of at most `level`.
+pub(super) fn split_pages(content: Blocks, level: i32) -> Vec<Page> {
+    let mut pages = vec![Page {
+        file: SPLIT_INDEX.to_owned(),
+        title: "Contents".to_owned(),
+        content: Default::default(),
+    }];
+    let mut files: HashSet<String> = pages.iter().map(|page| page.file.clone()).collect();
+    for block in content {
+        if let BlockInner::Heading(heading) = &block.inner {
+            if heading.level <= level {
+                let file = unique_file_name(&heading.anchor(), &mut files);
+                pages.push(Page {
+                    file,
+                    title: heading_text(heading),
+                    content: Default::default(),
+                });
+            }
+        }
+        pages.last_mut().unwrap().content.push(block);
+    }
+    pages
+}
+
+/// A file name for a page starting with a heading with the given id, which
+/// isn't already in `files`.
+fn unique_file_name(anchor: &str, files: &mut HashSet<String>) -> String {
+    let mut stem = String::new();
+    for c in anchor.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            stem.push(c.to_ascii_lowercase());
+        } else if !stem.is_empty() && !stem.ends_with('-') {
+            stem.push('-');
+        }
+    }
+    let stem = match stem.trim_end_matches('-') {
+        "" => "section",
+        stem => stem,
+    };
+    let mut file = format!("{}.html", stem);
+    let mut n = 1;
+    while !files.insert(file.clone()) {
+        n += 1;
+        file = format!("{}-{}.html", stem, n);
+    }
+    file
+}
+
+/// The plain text of a heading, for linking to it.
+pub(super) fn heading_text(heading: &Heading) -> String {
+    doc::inlines_to_plain_text(&heading.text).unwrap_or_else(|_| heading.anchor().into_string())
+}
+
+/// Rewrite links to labels and headings in other pages to point to those
+/// pages.
+pub(super) fn link_pages(pages: &mut [Page]) {
+    let mut targets = HashMap::new();
+    for (i, page) in pages.iter_mut().enumerate() {
+        let mut collector = TargetCollector {
+            page: i,
+            targets: &mut targets,
+        };
+        walk_mut(&mut page.content, &mut collector);
+    }
+    let files: Vec<_> = pages.iter().map(|page| page.file.clone()).collect();
+    for (i, page) in pages.iter_mut().enumerate() {
+        let mut linker = PageLinker {
+            page: i,
+            targets: &targets,
+            files: &files,
+        };
+        walk_mut(&mut page.content, &mut linker);
+    }
+}
+
+/// Collects the page each heading id and figure label is on.
+struct TargetCollector<'t> {
+    page: usize,
+    targets: &'t mut HashMap<RefId, usize>,
+}
+
+impl VisitMut for TargetCollector<'_> {
+    fn visit_block(&mut self, block: &mut Block) {
+        let target = match &block.inner {
+            BlockInner::Heading(heading) => heading.anchor(),
+            BlockInner::Figure(figure) => match &figure.label {
+                Some(label) => label.clone(),
+                None => return,
+            },
+            _ => return,
+        };
+        self.targets.entry(target).or_insert(self.page);
+    }
+}
+
+/// Rewrites links to targets on other pages; see `link_pages`.
+struct PageLinker<'t> {
+    page: usize,
+    targets: &'t HashMap<RefId, usize>,
+    files: &'t [String],
+}
+
+impl VisitMut for PageLinker<'_> {
+    fn visit_inline(&mut self, inline: &mut Inline) {
+        if let Inline::Link(link) = inline {
+            if let LinkTarget::Label(label) = &link.target {
+                match self.targets.get(label) {
+                    Some(&page) if page != self.page => {
+                        let url =
+                            format!("{}#{}", self.files[page], percent_encode_fragment(label));
+                        // Keep the text the link would have had.
+                        link.content = Some(link.text().into_owned());
+                        link.target = LinkTarget::URL(url);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Reserve the explicit ids of the headings in `collections` and on every
+/// page; see `reserve_heading_ids`.
+fn reserve_page_ids(pages: &[Page], collections: &Collections, ids: &mut HashMap<String, usize>) {
+    let pages = pages.iter().map(|page| &page.content[..]);
+    reserve_heading_ids(abstract_blocks(collections).chain(pages), ids);
+}
+
+/// The table of contents for the index page: the headings on each page which
+/// belong in it (see `Heading::in_toc`), including those in other blocks.
+/// The first page's headings come after those in `collections`, which are
+/// written there. No heading is given one of the `reserved` ids (see
+/// `reserved_ids`).
+pub(super) fn page_toc(
+    pages: &[Page],
+    meta: &Meta,
+    collections: &Collections,
+    reserved: &[&str],
+) -> Vec<SplitTocEntry> {
+    let draft = doc::is_draft(meta);
+    let no_collections = Collections::new();
+    let mut slugs: HashMap<_, _> = reserved.iter().map(|id| ((*id).to_owned(), 1)).collect();
+    reserve_page_ids(pages, collections, &mut slugs);
+    let mut ret = Vec::new();
+    for (i, page) in pages.iter().enumerate() {
+        let collections = if i == 0 { collections } else { &no_collections };
+        // The same ids the page's headings are written with.
+        let ids = heading_ids(collections, &page.content, draft, &mut slugs);
+        for (_, heading, id) in ids {
+            if !heading.in_toc() {
+                continue;
+            }
+            let fragment = format!("#{}", percent_encode_fragment(&id));
+            let href = if i == 0 {
+                fragment
+            } else if matches!(
+                page.content.first(),
+                Some(Block { inner: BlockInner::Heading(first), .. }) if std::ptr::eq(first, heading)
+            ) {
+                page.file.clone()
+            } else {
+                format!("{}{}", page.file, fragment)
+            };
+            ret.push(SplitTocEntry {
+                level: heading.level,
+                link: NavLink {
+                    href,
+                    text: heading_text(heading),
+                },
+            });
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use std::fs;
+
+    use super::*;
+    use crate::doc::{Footnote, Id};
+    use crate::ser::test_util::{block, heading, text, TempDir};
+
+    fn link_to(label: &str) -> Inline {
+        Inline::Link(Link {
+            content: None,
+            label: None,
+            target: LinkTarget::Label(RefId::new(label).unwrap()),
+            attrs: Default::default(),
+        })
+    }
+
+    /// The values of the given attribute on the elements with the given tag
+    /// name, in order.
+    fn attr_values(html: &str, tag_name: &str, attr: &str) -> Vec<String> {
+        html.split(&format!("<{} ", tag_name))
+            .skip(1)
+            .filter_map(|tag| {
+                let tag = &tag[..tag.find('>')?];
+                let start = tag.find(&format!("{}=\"", attr))? + attr.len() + 2;
+                let len = tag[start..].find('"')?;
+                Some(tag[start..start + len].to_owned())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_write_split() {
+        let doc = Doc::from_content(
+            vec![
+                block(0, BlockInner::Par(text("Preface."))),
+                heading(1, 1, "Beginning"),
+                block(
+                    2,
+                    BlockInner::Par(vec![
+                        Inline::Text("See".into()),
+                        Inline::Space,
+                        link_to("End"),
+                        Inline::Footnote(Footnote {
+                            content: block(3, BlockInner::Par(text("A note."))).into(),
+                        }),
+                    ]),
+                ),
+                heading(4, 1, "Middle"),
+                heading(5, 2, "Details"),
+                block(6, BlockInner::Par(vec![link_to("Details")])),
+                heading(7, 1, "End"),
+                block(
+                    8,
+                    BlockInner::Par(vec![
+                        link_to("Beginning"),
+                        Inline::Footnote(Footnote {
+                            content: block(9, BlockInner::Par(text("Another note."))).into(),
+                        }),
+                    ]),
+                ),
+            ]
+            .into(),
+        );
+        let dir = TempDir::new("split");
+        let options = HtmlOptions {
+            a11y_landmarks: false,
+            ..Default::default()
+        };
+        let warnings = write_split(doc, 1, &dir, options).unwrap();
+        assert_eq!(Vec::<Warning>::new(), warnings);
+
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            vec!["beginning.html", "end.html", "index.html", "middle.html"],
+            files
+        );
+        let read = |file: &str| fs::read_to_string(dir.join(file)).unwrap();
+
+        let index = read("index.html");
+        assert!(index.contains("<p>Preface.</p>"));
+        assert_eq!(
+            vec![
+                "beginning.html",
+                "middle.html",
+                "middle.html#Details",
+                "end.html",
+                "beginning.html",
+            ],
+            attr_values(&index, "a", "href")
+        );
+
+        // Links across files point to the other file, and footnotes stay in
+        // the file they're referenced in.
+        let beginning = read("beginning.html");
+        assert_eq!(
+            vec![
+                "#Beginning",
+                "end.html#End",
+                "#fn-1",
+                "#fn-link-1",
+                "#fn-1",
+                "index.html",
+                "middle.html",
+            ],
+            attr_values(&beginning, "a", "href")
+        );
+        assert!(beginning.contains(">End</a>"));
+        assert!(beginning.contains("<li id=\"fn-1\">\n<p>A note.</p>"));
+        assert_eq!(vec!["prev", "next"], attr_values(&beginning, "a", "rel"));
+
+        let middle = read("middle.html");
+        assert!(middle.contains("<a href=\"#Details\">"));
+        assert!(!middle.contains("fn-1"));
+        let end = read("end.html");
+        assert!(end.contains("<a href=\"beginning.html#Beginning\">Beginning</a>"));
+        // Footnotes are numbered through the whole document.
+        assert!(end.contains("aria-label=\"Footnote 2\">[2]</a>"));
+        assert!(end.contains("<ol class=\"footnotes\" start=\"2\">"));
+        assert!(end.contains("<li id=\"fn-2\">\n<p>Another note.</p>"));
+        assert_eq!(vec!["prev"], attr_values(&end, "a", "rel"));
+        assert!(end.contains("<a class=\"contents\" href=\"index.html\">Contents</a>"));
+    }
+
+    #[test]
+    fn test_write_split_duplicate_id() {
+        let with_id = |id: usize, s: &str| {
+            let mut ret = heading(id, 1, s);
+            if let BlockInner::Heading(heading) = &mut ret.inner {
+                heading
+                    .attrs
+                    .insert(doc::ID_KEY.to_owned(), "intro".to_owned());
+            }
+            ret
+        };
+        let doc = Doc::from_content(vec![with_id(0, "Intro"), with_id(1, "Introduction")].into());
+        let dir = TempDir::new("split-dup");
+        let err = write_split(doc, 1, &dir, Default::default()).unwrap_err();
+        assert_eq!(
+            r#"Headings "Intro" and "Introduction" both have the id "intro""#,
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_unique_file_name() {
+        let mut files: HashSet<_> = vec![SPLIT_INDEX.to_owned()].into_iter().collect();
+        assert_eq!("intro.html", unique_file_name("Intro", &mut files));
+        assert_eq!("intro-2.html", unique_file_name("intro", &mut files));
+        assert_eq!("index-2.html", unique_file_name("Index", &mut files));
+        assert_eq!(
+            "what-s-new.html",
+            unique_file_name("What’s-new?", &mut files)
+        );
+        assert_eq!("section.html", unique_file_name("¿…?", &mut files));
+    }
+
+    #[test]
+    fn test_page_toc_nested_headings() {
+        let pages = split_pages(
+            vec![
+                heading(0, 1, "Part"),
+                block(1, BlockInner::Quote(vec![heading(2, 2, "Notes")].into())),
+                heading(3, 2, "Notes"),
+            ]
+            .into(),
+            1,
+        );
+        let hrefs: Vec<_> = page_toc(&pages, &Meta::new(), &Collections::new(), &[])
+            .into_iter()
+            .map(|entry| entry.link.href)
+            .collect();
+        assert_eq!(
+            vec!["part.html", "part.html#Notes", "part.html#Notes-1"],
+            hrefs
+        );
+    }
+
+    #[test]
+    fn test_page_toc_repeated_headings() {
+        let pages = split_pages(
+            vec![
+                heading(0, 1, "Part"),
+                heading(1, 2, "Notes"),
+                heading(2, 1, "Part"),
+                heading(3, 2, "Notes"),
+                heading(4, 2, "Notes"),
+            ]
+            .into(),
+            1,
+        );
+        let hrefs: Vec<_> = page_toc(&pages, &Meta::new(), &Collections::new(), &[])
+            .into_iter()
+            .map(|entry| entry.link.href)
+            .collect();
+        assert_eq!(
+            vec![
+                "part.html",
+                "part.html#Notes",
+                "part-2.html",
+                "part-2.html#Notes-1",
+                "part-2.html#Notes-2",
+            ],
+            hrefs
+        );
+    }
+}
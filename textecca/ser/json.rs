@@ -0,0 +1,100 @@
+//! Serialization to JSON, for tools which inspect or transform documents.
+use std::io::{self, Write};
+
+use super::{InitSerializer, Serializer, SerializerError};
+use crate::doc::{self, Doc};
+
+/// Serializer to JSON, writing the document model itself, versioned with
+/// `doc::to_json`; other tools (or later builds) can load it back with
+/// `doc::migrate`.
+///
+/// Enums are written as objects tagged with the variant's name, like
+/// `{"Par": [...]}` for a `BlockInner::Par`, and changes to the serialized
+/// form are tracked by `doc::DOC_FORMAT_VERSION`.
+pub struct JsonSerializer<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> InitSerializer<W> for JsonSerializer<W> {
+    fn new(writer: W) -> Result<Box<Self>, SerializerError> {
+        Ok(Box::new(Self { writer }))
+    }
+}
+
+impl<W: Write> Serializer for JsonSerializer<W> {
+    fn format_name(&self) -> &'static str {
+        "json"
+    }
+
+    fn write_doc(&mut self, doc: &Doc) -> Result<(), SerializerError> {
+        serde_json::to_writer(&mut self.writer, &doc::to_json(doc)).map_err(io::Error::from)?;
+        writeln!(self.writer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::doc::{Block, BlockInner, Heading, Id, Inline, Style};
+
+    fn to_json(doc: &Doc) -> String {
+        let mut out = Vec::new();
+        JsonSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(doc)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn doc() -> Doc {
+        Doc::from_content(
+            vec![
+                Block::new(
+                    Id::from(0),
+                    BlockInner::Heading(Heading {
+                        level: 1,
+                        text: vec![Inline::Text("Intro".into())],
+                        attrs: Default::default(),
+                    }),
+                ),
+                Block::new(
+                    Id::from(1),
+                    BlockInner::Par(vec![Inline::Styled {
+                        style: Style::Emph,
+                        content: vec![Inline::Text("Hi".into())],
+                    }]),
+                ),
+            ]
+            .into(),
+        )
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let doc = doc();
+        let json = to_json(&doc);
+        assert!(json.ends_with("}\n"));
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(doc, doc::migrate(value).unwrap());
+    }
+
+    #[test]
+    fn test_json_tagged() {
+        let value: Value = serde_json::from_str(&to_json(&doc())).unwrap();
+        assert_eq!(json!(doc::DOC_FORMAT_VERSION), value["version"]);
+        assert_eq!(
+            json!({
+                "id": 1,
+                "inner": {
+                    "Par": [{ "Styled": { "style": "Emph", "content": [{ "Text": "Hi" }] } }]
+                }
+            }),
+            value["doc"]["content"][1]
+        );
+    }
+}
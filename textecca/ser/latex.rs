@@ -0,0 +1,869 @@
+//! Serialization to LaTeX.
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::iter;
+use std::mem;
+
+use super::{
+    heading_id, latex_escape, reserve_heading_ids, unsupported, InitSerializer, Serializer,
+    SerializerError,
+};
+use crate::cmd::{Strictness, Warning};
+use crate::doc::{
+    self, Alignment, Block, BlockInner, Code, Doc, Figure, FigureKind, Image, Inline, LinkTarget,
+    ListKind, Meta, Style, Table,
+};
+
+/// Serializer to a standalone LaTeX document, for typesetting with (e.g.)
+/// `pdflatex`.
+///
+/// The document uses the `article` class, with `hyperref` for links,
+/// `graphicx` for images, `ulem` for struck-out text, and `amsmath` when the
+/// document has math. Text is escaped with `latex_escape`, so it's always
+/// typeset literally.
+pub struct LatexSerializer<W: Write> {
+    writer: W,
+    strictness: Strictness,
+    /// Whether the document being written is a draft; see `doc::is_draft`.
+    draft: bool,
+    /// The labels written for headings so far; see `heading_id`.
+    heading_ids: HashMap<String, usize>,
+    /// Warnings emitted so far; see `Serializer::take_warnings`.
+    warnings: Vec<Warning>,
+}
+
+impl<W: Write> InitSerializer<W> for LatexSerializer<W> {
+    fn new(writer: W) -> Result<Box<Self>, SerializerError> {
+        Ok(Self::with_strictness(writer, Default::default()))
+    }
+}
+
+impl<W: Write> Serializer for LatexSerializer<W> {
+    fn format_name(&self) -> &'static str {
+        "latex"
+    }
+
+    fn write_doc(&mut self, doc: &Doc) -> Result<(), SerializerError> {
+        self.draft = doc::is_draft(&doc.meta);
+        self.heading_ids.clear();
+        reserve_heading_ids(iter::once(&doc.content[..]), &mut self.heading_ids);
+        let content = self.blocks_latex(&doc.content)?;
+        writeln!(self.writer, r"\documentclass{{article}}")?;
+        writeln!(self.writer, r"\usepackage{{graphicx}}")?;
+        writeln!(self.writer, r"\usepackage[normalem]{{ulem}}")?;
+        if doc.has_math() {
+            writeln!(self.writer, r"\usepackage{{amsmath}}")?;
+        }
+        // hyperref redefines other packages' commands, so it's loaded last.
+        writeln!(self.writer, r"\usepackage{{hyperref}}")?;
+        writeln!(self.writer, r"\begin{{document}}")?;
+        if !content.is_empty() {
+            writeln!(self.writer, "{}", content)?;
+        }
+        writeln!(self.writer, r"\end{{document}}")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
+}
+
+impl<W: Write> LatexSerializer<W> {
+    /// Create a new serializer, handling recoverable problems (like remote
+    /// images, which LaTeX can't include) according to `strictness`.
+    pub fn with_strictness(writer: W, strictness: Strictness) -> Box<Self> {
+        Box::new(Self {
+            writer,
+            strictness,
+            draft: false,
+            heading_ids: Default::default(),
+            warnings: Default::default(),
+        })
+    }
+
+    /// Report content which can't be written as LaTeX; see
+    /// `ser::unsupported`.
+    fn unsupported(&mut self, what: &str) -> Result<(), SerializerError> {
+        let warning = unsupported(self.strictness, "LaTeX", what)?;
+        self.warnings.extend(warning);
+        Ok(())
+    }
+
+    /// Write blocks separated by blank lines, i.e. as separate paragraphs.
+    fn blocks_latex(&mut self, blocks: &[Block]) -> Result<String, SerializerError> {
+        let mut ret = Vec::new();
+        for block in blocks {
+            let latex = self.block_latex(&block.inner)?;
+            if !latex.is_empty() {
+                ret.push(latex);
+            }
+        }
+        Ok(ret.join("\n\n"))
+    }
+
+    fn block_latex(&mut self, block: &BlockInner) -> Result<String, SerializerError> {
+        Ok(match block {
+            BlockInner::Plain(inlines) | BlockInner::Par(inlines) => self.inlines_latex(inlines)?,
+            BlockInner::Heading(heading) => {
+                // Levels outside of sections' range are clamped to it.
+                let cmd = match heading.level {
+                    i32::MIN..=1 => "section",
+                    2 => "subsection",
+                    3 => "subsubsection",
+                    4 => "paragraph",
+                    _ => "subparagraph",
+                };
+                // Unnumbered headings aren't listed in the table of contents.
+                let star = if heading.in_toc() { "" } else { "*" };
+                let text = self.inlines_latex(&heading.text)?;
+                let id = heading_id(heading, &mut self.heading_ids);
+                format!(
+                    r"\{}{}{{{}}}\label{{{}}}",
+                    cmd,
+                    star,
+                    text,
+                    latex_label(&id)
+                )
+            }
+            // LaTeX's list environments and `tabular` are errors when empty.
+            BlockInner::List(list) if list.items.is_empty() => String::new(),
+            BlockInner::TermList(items) if items.is_empty() => String::new(),
+            BlockInner::Table(table) if table.cells.iter().all(|row| row.is_empty()) => {
+                String::new()
+            }
+            BlockInner::List(list) => {
+                let env = match list.kind {
+                    ListKind::Unordered => "itemize",
+                    ListKind::Ordered => "enumerate",
+                };
+                let mut items = Vec::new();
+                for item in &list.items {
+                    items.push(self.blocks_latex(&item.content)?);
+                }
+                environment(env, &items_latex(items.iter().map(|item| ("", item))))
+            }
+            BlockInner::TermList(items) => {
+                let mut latex = Vec::new();
+                for item in items {
+                    // Braces keep a `]` in the term from ending it.
+                    let term = format!("[{{{}}}]", self.inlines_latex(&item.term)?);
+                    latex.push((term, self.blocks_latex(&item.content)?));
+                }
+                environment(
+                    "description",
+                    &items_latex(latex.iter().map(|(term, content)| (term.as_str(), content))),
+                )
+            }
+            BlockInner::Quote(blocks) => environment("quote", &self.blocks_latex(blocks)?),
+            BlockInner::Rule => r"\noindent\rule{\linewidth}{0.4pt}".to_owned(),
+            BlockInner::Code(code) => self.code_latex(code)?,
+            BlockInner::Math(math) => format!("\\[\n{}\n\\]", math.tex),
+            BlockInner::Raw(raw) if raw.format == self.format_name() => raw.content.clone(),
+            BlockInner::Raw(_) => String::new(),
+            BlockInner::Table(table) => self.table_latex(table)?,
+            BlockInner::Figure(figure) => self.figure_latex(figure)?,
+            BlockInner::Defn(defn) => {
+                let name = self.inlines_latex(&defn.name)?;
+                let summary = self.blocks_latex(&defn.summary)?;
+                let content = self.blocks_latex(&defn.content)?;
+                let mut ret = format!(r"\paragraph{{{}}}", name);
+                for part in [summary, content].iter().filter(|part| !part.is_empty()) {
+                    ret.push_str("\n\n");
+                    ret.push_str(part);
+                }
+                ret
+            }
+            BlockInner::Tagged(tagged) => {
+                if doc::meta_includes(&tagged.meta, self.format_name(), self.draft) {
+                    self.blocks_latex(&tagged.content)?
+                } else {
+                    String::new()
+                }
+            }
+        })
+    }
+
+    fn code_latex(&mut self, code: &Code) -> Result<String, SerializerError> {
+        let mut lines = Vec::new();
+        for line in &code.lines {
+            match doc::inlines_to_plain_text(line) {
+                Ok(text) => lines.push(text),
+                Err(err) => {
+                    self.unsupported(&format!("{} in a code block", err.0))?;
+                    lines.push(String::new());
+                }
+            }
+        }
+        let content = lines.join("\n");
+        // Nothing in a verbatim environment is special except its end.
+        if content.contains(r"\end{verbatim}") {
+            self.unsupported(r"code blocks containing `\end{verbatim}`")?;
+            return Ok(String::new());
+        }
+        Ok(format!(
+            "\\begin{{verbatim}}\n{}\n\\end{{verbatim}}",
+            content
+        ))
+    }
+
+    fn table_latex(&mut self, table: &Table) -> Result<String, SerializerError> {
+        let spec: String = if table.columns.is_empty() {
+            let width = table
+                .cells
+                .iter()
+                .map(|row| row.iter().map(|cell| cell.col_span.max(1)).sum::<u32>())
+                .max()
+                .unwrap_or(0);
+            "l".repeat(width as usize)
+        } else {
+            table
+                .columns
+                .iter()
+                .map(|column| column_spec(column.alignment))
+                .collect()
+        };
+        let mut rows = Vec::new();
+        for row in &table.cells {
+            let mut cells = Vec::new();
+            for cell in row {
+                if cell.row_span > 1 {
+                    self.unsupported("table cells spanning multiple rows")?;
+                }
+                // A cell's content is a single line of the table.
+                let content = self.blocks_latex(&cell.content)?.replace("\n\n", " ");
+                cells.push(if cell.col_span > 1 || cell.alignment.is_some() {
+                    format!(
+                        r"\multicolumn{{{}}}{{{}}}{{{}}}",
+                        cell.col_span.max(1),
+                        column_spec(cell.alignment.unwrap_or(Alignment::Left)),
+                        content
+                    )
+                } else {
+                    content
+                });
+            }
+            rows.push(cells.join(" & "));
+        }
+        Ok(format!(
+            "\\begin{{tabular}}{{{}}}\n{}\n\\end{{tabular}}",
+            spec,
+            rows.join(" \\\\\n")
+        ))
+    }
+
+    fn figure_latex(&mut self, figure: &Figure) -> Result<String, SerializerError> {
+        let env = match figure.kind {
+            FigureKind::Table => "table",
+            _ => "figure",
+        };
+        let mut ret = self.blocks_latex(&figure.content)?;
+        if !figure.unnumbered_caption().is_empty() {
+            // LaTeX numbers the figure itself.
+            let caption = self.inlines_latex(figure.unnumbered_caption())?;
+            write!(ret, "\n\\caption{{{}}}", caption).unwrap();
+        }
+        if let Some(label) = &figure.label {
+            write!(ret, "\\label{{{}}}", latex_label(label.as_str())).unwrap();
+        }
+        Ok(environment(env, ret.trim_start()))
+    }
+
+    fn inlines_latex(&mut self, inlines: &[Inline]) -> Result<String, SerializerError> {
+        let mut ret = String::new();
+        self.push_inlines(&mut ret, inlines)?;
+        Ok(ret)
+    }
+
+    fn push_inlines(
+        &mut self,
+        ret: &mut String,
+        inlines: &[Inline],
+    ) -> Result<(), SerializerError> {
+        for inline in inlines {
+            match inline {
+                Inline::Text(text) => ret.push_str(&latex_escape(text)),
+                Inline::Styled { style, content } => {
+                    let cmd = match style {
+                        Style::Emph => Some("emph"),
+                        Style::Strong => Some("textbf"),
+                        Style::Superscript => Some("textsuperscript"),
+                        Style::Subscript => Some("textsubscript"),
+                        Style::SmallCaps => Some("textsc"),
+                        Style::Strikeout => Some("sout"),
+                        Style::Underline => Some("uline"),
+                        _ => {
+                            self.unsupported(&format!("the style {:?}", style))?;
+                            None
+                        }
+                    };
+                    let latex = self.inlines_latex(content)?;
+                    match cmd {
+                        Some(cmd) => write!(ret, r"\{}{{{}}}", cmd, latex).unwrap(),
+                        None => ret.push_str(&latex),
+                    }
+                }
+                Inline::Quote(quote) => {
+                    let (l, r) = quote.kind.to_inlines();
+                    self.push_inlines(ret, &l)?;
+                    self.push_inlines(ret, &quote.content)?;
+                    self.push_inlines(ret, &r)?;
+                }
+                Inline::Code(code) => {
+                    write!(ret, r"\texttt{{{}}}", latex_escape(&code.content)).unwrap()
+                }
+                Inline::Space => ret.push(' '),
+                Inline::LineBreak => ret.push_str("\\\\\n"),
+                Inline::Link(link) => {
+                    let text = self.inlines_latex(&link.text())?;
+                    match &link.target {
+                        LinkTarget::URL(url) => {
+                            write!(ret, r"\href{{{}}}{{{}}}", href_url(url), text).unwrap()
+                        }
+                        LinkTarget::Label(label) => write!(
+                            ret,
+                            r"\hyperref[{}]{{{}}}",
+                            latex_label(label.as_str()),
+                            text
+                        )
+                        .unwrap(),
+                    }
+                }
+                Inline::Footnote(footnote) => {
+                    let content = self.blocks_latex(&footnote.content)?;
+                    write!(ret, r"\footnote{{{}}}", content).unwrap();
+                }
+                Inline::Math(math) => write!(ret, "${}$", math.tex).unwrap(),
+                Inline::Image(image) => self.push_image(ret, image)?,
+                Inline::Raw(raw) if raw.format == self.format_name() => ret.push_str(&raw.content),
+                Inline::Raw(_) => {}
+                Inline::Tagged(tagged) => {
+                    if doc::meta_includes(&tagged.meta, self.format_name(), self.draft) {
+                        self.push_inlines(ret, &tagged.content)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write an image, or its alt text if it can't be included.
+    fn push_image(&mut self, ret: &mut String, image: &Image) -> Result<(), SerializerError> {
+        let Image { src, alt, .. } = image;
+        if image.is_url() {
+            self.unsupported("remote images")?;
+        } else if src.contains(|c| "{}\\%#".contains(c)) {
+            self.unsupported(&format!("the image path {:?}", src))?;
+        } else {
+            write!(ret, r"\includegraphics{{{}}}", src).unwrap();
+            return Ok(());
+        }
+        ret.push_str(&latex_escape(alt.as_deref().unwrap_or_default()));
+        Ok(())
+    }
+}
+
+/// Wrap `content` in the LaTeX environment `env`.
+fn environment(env: &str, content: &str) -> String {
+    format!("\\begin{{{0}}}\n{1}\n\\end{{{0}}}", env, content)
+}
+
+/// The `\item`s of a list, given each item's optional argument (e.g.
+/// `[term]`, or empty) and content.
+fn items_latex<'a>(items: impl Iterator<Item = (&'a str, &'a String)>) -> String {
+    items
+        .map(|(arg, content)| {
+            if content.is_empty() {
+                format!(r"\item{}", arg)
+            } else {
+                format!(r"\item{} {}", arg, content)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The `tabular` column specifier for an alignment. LaTeX has no justified
+/// columns without a fixed width, so they're left-aligned.
+fn column_spec(alignment: Alignment) -> &'static str {
+    match alignment {
+        Alignment::Left | Alignment::Justify => "l",
+        Alignment::Right => "r",
+        Alignment::Center => "c",
+    }
+}
+
+/// A `\label` name for a `RefId`. Labels may contain any punctuation, so
+/// everything but ASCII letters, digits, and `-:./` is written as `_` and its
+/// UTF-8 bytes in hex, keeping distinct labels distinct.
+fn latex_label(label: &str) -> String {
+    let mut ret = String::with_capacity(label.len());
+    for c in label.chars() {
+        if c.is_ascii_alphanumeric() || "-:./".contains(c) {
+            ret.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                write!(ret, "_{:02X}", byte).unwrap();
+            }
+        }
+    }
+    ret
+}
+
+/// A URL for `\href`, which reads `\#` and `\%` as `#` and `%`. Braces and
+/// backslashes can't be escaped there, so they're percent-encoded.
+fn href_url(url: &str) -> String {
+    let mut ret = String::with_capacity(url.len());
+    for c in url.chars() {
+        match c {
+            '{' => ret.push_str(r"\%7B"),
+            '}' => ret.push_str(r"\%7D"),
+            '\\' => ret.push_str(r"\%5C"),
+            '#' | '%' => {
+                ret.push('\\');
+                ret.push(c);
+            }
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::cmd::WarningKind;
+    use crate::doc::{
+        Defn, Font, Footnote, Heading, Id, InlineCode, InlineMath, Link, List, ListItem, Math,
+        Quote, QuoteKind, RawBlock, RawInline, RefId, TableCell, TableColumn, TaggedBlocks,
+        TaggedInlines, TermListItem, ID_KEY, ONLY_FORMAT_KEY,
+    };
+    use crate::ser::test_util::*;
+
+    fn plain(s: &str) -> Block {
+        block(0, BlockInner::Plain(text(s)))
+    }
+
+    fn only(format: &str) -> Meta {
+        vec![(ONLY_FORMAT_KEY.to_owned(), format.to_owned())]
+            .into_iter()
+            .collect()
+    }
+
+    fn to_latex(blocks: Vec<Block>) -> (String, Vec<Warning>) {
+        let mut out = Vec::new();
+        let mut ser = LatexSerializer::new(&mut out).unwrap();
+        ser.write_doc(&Doc::from_content(blocks.into())).unwrap();
+        let warnings = ser.take_warnings();
+        drop(ser);
+        (String::from_utf8(out).unwrap(), warnings)
+    }
+
+    /// The LaTeX between `\begin{document}` and `\end{document}`.
+    fn body(latex: &str) -> &str {
+        let start = latex.find("\\begin{document}\n").unwrap() + "\\begin{document}\n".len();
+        let end = latex.rfind("\\end{document}").unwrap();
+        &latex[start..end]
+    }
+
+    #[test]
+    fn test_latex_preamble() {
+        let (latex, warnings) = to_latex(vec![par(0, text("Hi."))]);
+        assert_eq!(Vec::<Warning>::new(), warnings);
+        assert_eq!(
+            "\\documentclass{article}\n\
+             \\usepackage{graphicx}\n\
+             \\usepackage[normalem]{ulem}\n\
+             \\usepackage{hyperref}\n\
+             \\begin{document}\n\
+             Hi.\n\
+             \\end{document}\n",
+            latex
+        );
+    }
+
+    #[test]
+    fn test_latex_amsmath() {
+        let (latex, _) = to_latex(vec![par(
+            0,
+            vec![Inline::Math(InlineMath {
+                tex: "x".into(),
+                alt: None,
+            })],
+        )]);
+        assert!(latex.contains("\\usepackage{amsmath}\n"));
+    }
+
+    #[test]
+    fn test_latex_empty() {
+        let (latex, warnings) = to_latex(vec![
+            block(
+                0,
+                BlockInner::List(List {
+                    kind: ListKind::Unordered,
+                    items: vec![],
+                }),
+            ),
+            block(0, BlockInner::TermList(vec![])),
+            block(
+                0,
+                BlockInner::Table(Table {
+                    columns: vec![],
+                    cells: vec![vec![]],
+                }),
+            ),
+            plain("After."),
+        ]);
+        assert_eq!(Vec::<Warning>::new(), warnings);
+        assert_eq!("After.\n", body(&latex));
+    }
+
+    #[test]
+    fn test_latex_heading_dedup() {
+        let intro = || heading(0, 1, "Intro");
+        let (latex, _) = to_latex(vec![intro(), intro()]);
+        assert_eq!(
+            "\\section{Intro}\\label{Intro}\n\n\\section{Intro}\\label{Intro-1}\n",
+            body(&latex)
+        );
+
+        // Generated labels skip explicit ones, even those of later headings.
+        let explicit = heading_with(0, 1, "B", &[(ID_KEY, "Intro-1")]);
+        let (latex, _) = to_latex(vec![intro(), intro(), explicit]);
+        assert!(body(&latex).contains("\\section{Intro}\\label{Intro-2}"));
+        assert!(body(&latex).contains("\\section{B}\\label{Intro-1}"));
+    }
+
+    #[test]
+    fn test_latex_numbered_figure() {
+        // LaTeX numbers figures itself, so the caption isn't numbered twice.
+        let mut doc = Doc::from_content(
+            vec![block(
+                0,
+                BlockInner::Figure(Figure {
+                    kind: FigureKind::Figure,
+                    caption: text("A figure."),
+                    content: vec![plain("Figured.")].into(),
+                    label: None,
+                    number: None,
+                }),
+            )]
+            .into(),
+        );
+        crate::doc::number_figures(&mut doc);
+        let mut out = Vec::new();
+        LatexSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&doc)
+            .unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("\n\\caption{A figure.}\n"));
+    }
+
+    #[test]
+    fn test_latex_blocks() {
+        let (latex, warnings) = to_latex(vec![
+            heading_with(0, 1, "Intro & more", &[(ID_KEY, "intro")]),
+            heading(0, 2, "50% off"),
+            heading_with(0, 9, "Aside", &[("toc", "false")]),
+            par(0, text("A paragraph.")),
+            block(
+                0,
+                BlockInner::List(List {
+                    kind: ListKind::Unordered,
+                    items: vec![
+                        ListItem {
+                            content: vec![
+                                plain("One"),
+                                block(
+                                    0,
+                                    BlockInner::List(List {
+                                        kind: ListKind::Ordered,
+                                        items: vec![ListItem {
+                                            content: vec![plain("A")].into(),
+                                        }],
+                                    }),
+                                ),
+                            ]
+                            .into(),
+                        },
+                        ListItem {
+                            content: vec![plain("Two")].into(),
+                        },
+                    ],
+                }),
+            ),
+            block(
+                0,
+                BlockInner::TermList(vec![TermListItem {
+                    term: text("x[1]"),
+                    content: vec![plain("The first x.")].into(),
+                }]),
+            ),
+            block(0, BlockInner::Quote(vec![par(0, text("Quoted."))].into())),
+            block(0, BlockInner::Rule),
+            block(
+                0,
+                BlockInner::Code(Code {
+                    language: "rust".into(),
+                    lines: vec![text("let x = \"{}\";"), text("x")],
+                    ..Default::default()
+                }),
+            ),
+            block(
+                0,
+                BlockInner::Math(Math {
+                    tex: r"\int_0^1 x".into(),
+                    alt: None,
+                }),
+            ),
+            block(
+                0,
+                BlockInner::Table(Table {
+                    columns: vec![
+                        TableColumn {
+                            alignment: Alignment::Left,
+                            width: None,
+                        },
+                        TableColumn {
+                            alignment: Alignment::Right,
+                            width: None,
+                        },
+                    ],
+                    cells: vec![
+                        vec![
+                            TableCell {
+                                content: vec![plain("a")].into(),
+                                ..Default::default()
+                            },
+                            TableCell {
+                                content: vec![plain("b")].into(),
+                                ..Default::default()
+                            },
+                        ],
+                        vec![TableCell {
+                            col_span: 2,
+                            alignment: Some(Alignment::Center),
+                            content: vec![plain("wide")].into(),
+                            ..Default::default()
+                        }],
+                    ],
+                }),
+            ),
+            block(
+                0,
+                BlockInner::Figure(Figure {
+                    kind: FigureKind::Figure,
+                    caption: text("A figure."),
+                    content: vec![plain("Figured.")].into(),
+                    label: Some(RefId::new("fig:1").unwrap()),
+                    number: None,
+                }),
+            ),
+            block(
+                0,
+                BlockInner::Defn(Defn {
+                    name: text("Term"),
+                    summary: vec![par(0, text("Summary."))].into(),
+                    content: vec![par(0, text("Details."))].into(),
+                }),
+            ),
+            block(
+                0,
+                BlockInner::Tagged(TaggedBlocks {
+                    content: vec![par(0, text("LaTeX only."))].into(),
+                    meta: only("latex"),
+                }),
+            ),
+            block(
+                0,
+                BlockInner::Tagged(TaggedBlocks {
+                    content: vec![par(0, text("HTML only."))].into(),
+                    meta: only("html"),
+                }),
+            ),
+        ]);
+        assert_eq!(Vec::<Warning>::new(), warnings);
+        assert_eq!(
+            concat!(
+                "\\section{Intro \\& more}\\label{intro}\n\n",
+                "\\subsection{50\\% off}\\label{50_25-off}\n\n",
+                "\\subparagraph*{Aside}\\label{Aside}\n\n",
+                "A paragraph.\n\n",
+                "\\begin{itemize}\n",
+                "\\item One\n\n\\begin{enumerate}\n\\item A\n\\end{enumerate}\n",
+                "\\item Two\n",
+                "\\end{itemize}\n\n",
+                "\\begin{description}\n\\item[{x[1]}] The first x.\n\\end{description}\n\n",
+                "\\begin{quote}\nQuoted.\n\\end{quote}\n\n",
+                "\\noindent\\rule{\\linewidth}{0.4pt}\n\n",
+                "\\begin{verbatim}\nlet x = \"{}\";\nx\n\\end{verbatim}\n\n",
+                "\\[\n\\int_0^1 x\n\\]\n\n",
+                "\\begin{tabular}{lr}\na & b \\\\\n\\multicolumn{2}{c}{wide}\n\\end{tabular}\n\n",
+                "\\begin{figure}\nFigured.\n\\caption{A figure.}\\label{fig:1}\n\\end{figure}\n\n",
+                "\\paragraph{Term}\n\nSummary.\n\nDetails.\n\n",
+                "LaTeX only.\n",
+            ),
+            body(&latex)
+        );
+    }
+
+    #[test]
+    fn test_latex_inlines() {
+        let styled = |style, s: &str| Inline::Styled {
+            style,
+            content: text(s),
+        };
+        let (latex, warnings) = to_latex(vec![par(
+            0,
+            vec![
+                styled(Style::Emph, "emph"),
+                styled(Style::Strong, "strong"),
+                styled(Style::Superscript, "sup"),
+                styled(Style::Subscript, "sub"),
+                styled(Style::SmallCaps, "sc"),
+                styled(Style::Strikeout, "struck"),
+                styled(Style::Underline, "under"),
+                Inline::Space,
+                Inline::Quote(Quote {
+                    kind: QuoteKind::Primary,
+                    content: text("quoted"),
+                }),
+                Inline::Code(InlineCode {
+                    content: r"a_b\c".into(),
+                    ..Default::default()
+                }),
+                Inline::LineBreak,
+                Inline::Link(Link {
+                    content: Some(text("a link")),
+                    label: None,
+                    target: LinkTarget::URL("https://example.com/100%#top".into()),
+                    attrs: Default::default(),
+                }),
+                Inline::Link(Link {
+                    content: Some(text("back")),
+                    label: None,
+                    target: LinkTarget::Label("fig:1".parse().unwrap()),
+                    attrs: Default::default(),
+                }),
+                Inline::Footnote(Footnote {
+                    content: vec![par(0, text("A note.")), par(0, text("More."))].into(),
+                }),
+                Inline::Math(InlineMath {
+                    tex: "x^2".into(),
+                    alt: None,
+                }),
+                Inline::Image(Image {
+                    src: "plot.pdf".into(),
+                    alt: None,
+                    attrs: Default::default(),
+                }),
+                Inline::Raw(RawInline {
+                    format: "latex".into(),
+                    content: r"\LaTeX{}".into(),
+                }),
+                Inline::Tagged(TaggedInlines {
+                    content: text("tagged"),
+                    meta: only("latex"),
+                }),
+            ],
+        )]);
+        assert_eq!(Vec::<Warning>::new(), warnings);
+        assert_eq!(
+            concat!(
+                "\\emph{emph}\\textbf{strong}\\textsuperscript{sup}\\textsubscript{sub}",
+                "\\textsc{sc}\\sout{struck}\\uline{under} “quoted”",
+                "\\texttt{a\\_b\\textbackslash{}c}\\\\\n",
+                "\\href{https://example.com/100\\%\\#top}{a link}",
+                "\\hyperref[fig:1]{back}",
+                "\\footnote{A note.\n\nMore.}",
+                "$x^2$\\includegraphics{plot.pdf}\\LaTeX{}tagged\n",
+            ),
+            body(&latex)
+        );
+    }
+
+    #[test]
+    fn test_latex_raw() {
+        let raw = |format: &str, content: &str| {
+            block(
+                0,
+                BlockInner::Raw(RawBlock {
+                    format: format.into(),
+                    content: content.into(),
+                }),
+            )
+        };
+        let doc = Doc::from_content(
+            vec![raw("latex", r"\newpage % 100% raw"), raw("html", "<hr>")].into(),
+        );
+        let mut out = Vec::new();
+        let mut ser = LatexSerializer::with_strictness(&mut out, Strictness::Strict);
+        ser.write_doc(&doc).unwrap();
+        assert_eq!(Vec::<Warning>::new(), ser.take_warnings());
+        drop(ser);
+        let latex = String::from_utf8(out).unwrap();
+        assert!(body(&latex).starts_with("\\newpage % 100% raw\n"));
+        assert!(!latex.contains("<hr>"));
+    }
+
+    #[test]
+    fn test_latex_unsupported() {
+        let (latex, warnings) = to_latex(vec![par(
+            0,
+            vec![
+                Inline::Styled {
+                    style: Style::Font(Font {}),
+                    content: text("font"),
+                },
+                Inline::Image(Image {
+                    src: "https://example.com/a.png".into(),
+                    alt: Some("50%".into()),
+                    attrs: Default::default(),
+                }),
+                Inline::Raw(RawInline {
+                    format: "html".into(),
+                    content: "<br>".into(),
+                }),
+            ],
+        )]);
+        assert_eq!("font50\\%\n", body(&latex));
+        assert_eq!(
+            vec![
+                Warning::new(
+                    WarningKind::Unsupported,
+                    format!(
+                        "LaTeX output doesn't support the style {:?}; skipping",
+                        Style::Font(Font {})
+                    )
+                ),
+                Warning::new(
+                    WarningKind::Unsupported,
+                    "LaTeX output doesn't support remote images; skipping".into()
+                ),
+            ],
+            warnings
+        );
+    }
+
+    #[test]
+    fn test_latex_label() {
+        assert_eq!("fig:results", latex_label("fig:results"));
+        assert_eq!("a_5Fb_7B_E2_80_94", latex_label("a_b{—"));
+        assert_ne!(latex_label("a_b"), latex_label("a_5Fb"));
+    }
+
+    #[test]
+    fn test_href_url() {
+        assert_eq!(
+            r"https://example.com/\%7Ba\%7D\%5C?q=10\%\#x",
+            href_url(r"https://example.com/{a}\?q=10%#x")
+        );
+    }
+}
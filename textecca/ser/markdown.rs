@@ -0,0 +1,648 @@
+//! Serialization to Markdown (CommonMark).
+use std::io::Write;
+use std::mem;
+
+use super::text::hanging;
+use super::{
+    markdown_escape, percent_encode_fragment, unsupported, InitSerializer, Serializer,
+    SerializerError,
+};
+use crate::cmd::{Strictness, Warning};
+use crate::doc::{
+    self, Block, BlockInner, Code, Doc, Image, Inline, LinkTarget, List, ListKind, Meta, Style,
+};
+
+/// Serializer to [CommonMark](https://commonmark.org/).
+///
+/// Footnotes are written as references (`[^1]`) with their definitions after
+/// the document, and math between dollar signs (`$x^2$`, or `$$` for display
+/// math), as most Markdown renderers with footnote and math extensions
+/// expect. Text is escaped with `markdown_escape`, so it's always rendered
+/// literally.
+///
+/// Markdown has no figures, tables, definitions, or term lists, so those are
+/// reported as unsupported and skipped.
+pub struct MarkdownSerializer<W: Write> {
+    writer: W,
+    strictness: Strictness,
+    /// Whether the document being written is a draft; see `doc::is_draft`.
+    draft: bool,
+    /// The Markdown of the footnotes referenced so far, in order.
+    footnotes: Vec<String>,
+    /// Warnings emitted so far; see `Serializer::take_warnings`.
+    warnings: Vec<Warning>,
+}
+
+impl<W: Write> InitSerializer<W> for MarkdownSerializer<W> {
+    fn new(writer: W) -> Result<Box<Self>, SerializerError> {
+        Ok(Self::with_strictness(writer, Default::default()))
+    }
+}
+
+impl<W: Write> Serializer for MarkdownSerializer<W> {
+    fn format_name(&self) -> &'static str {
+        "markdown"
+    }
+
+    fn write_doc(&mut self, doc: &Doc) -> Result<(), SerializerError> {
+        self.draft = doc::is_draft(&doc.meta);
+        self.footnotes.clear();
+        let mut sections = vec![self.blocks_md(&doc.content)?];
+        let footnotes: Vec<_> = mem::take(&mut self.footnotes)
+            .iter()
+            .enumerate()
+            .map(|(i, md)| {
+                // Later lines of a definition are indented by exactly 4
+                // spaces; any more would make them a code block.
+                let body = hanging("    ", md);
+                let body = body.strip_prefix("    ").unwrap_or(&body);
+                format!("[^{}]: {}", i + 1, body)
+            })
+            .collect();
+        sections.push(footnotes.join("\n\n"));
+        sections.retain(|section| !section.is_empty());
+        if !sections.is_empty() {
+            writeln!(self.writer, "{}", sections.join("\n\n"))?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
+}
+
+impl<W: Write> MarkdownSerializer<W> {
+    /// Create a new serializer, handling recoverable problems (like tables,
+    /// which Markdown doesn't have) according to `strictness`.
+    pub fn with_strictness(writer: W, strictness: Strictness) -> Box<Self> {
+        Box::new(Self {
+            writer,
+            strictness,
+            draft: false,
+            footnotes: Default::default(),
+            warnings: Default::default(),
+        })
+    }
+
+    /// Report content which can't be written as Markdown, and is skipped; see
+    /// `ser::unsupported`.
+    fn unsupported(&mut self, what: &str) -> Result<(), SerializerError> {
+        let warning = unsupported(self.strictness, "Markdown", what)?;
+        self.warnings.extend(warning);
+        Ok(())
+    }
+
+    /// Write blocks separated by blank lines.
+    fn blocks_md(&mut self, blocks: &[Block]) -> Result<String, SerializerError> {
+        let mut ret = String::new();
+        let mut prev: Option<&BlockInner> = None;
+        for block in blocks {
+            let md = self.block_md(&block.inner)?;
+            if md.is_empty() {
+                continue;
+            }
+            ret.push_str(match (prev, &block.inner) {
+                (None, _) => "",
+                // A list directly after plain text (e.g. nested in a list
+                // item) stays tight, without paragraphs in its items.
+                (Some(BlockInner::Plain(_)), BlockInner::List(_)) => "\n",
+                // Otherwise, adjacent lists would be joined into one.
+                (Some(BlockInner::List(_)), BlockInner::List(_)) => "\n\n<!-- -->\n\n",
+                _ => "\n\n",
+            });
+            ret.push_str(&md);
+            prev = Some(&block.inner);
+        }
+        Ok(ret)
+    }
+
+    fn block_md(&mut self, block: &BlockInner) -> Result<String, SerializerError> {
+        Ok(match block {
+            BlockInner::Plain(inlines) | BlockInner::Par(inlines) => self.inlines_md(inlines)?,
+            BlockInner::Heading(heading) => {
+                // ATX headings only have 6 levels.
+                let level = heading.level.clamp(1, 6) as usize;
+                let text = self.inlines_md(&heading.text)?;
+                // Headings are a single line, so line breaks become spaces.
+                format!("{} {}", "#".repeat(level), text.replace("\\\n", " "))
+            }
+            BlockInner::List(list) => self.list_md(list)?,
+            BlockInner::Quote(blocks) => {
+                let md = self.blocks_md(blocks)?;
+                md.lines()
+                    .map(|line| {
+                        if line.is_empty() {
+                            ">".to_owned()
+                        } else {
+                            format!("> {}", line)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            BlockInner::Rule => "* * *".to_owned(),
+            BlockInner::Code(code) => self.code_md(code)?,
+            BlockInner::Math(math) => format!("$$\n{}\n$$", math.tex),
+            BlockInner::Raw(raw) if raw.format == self.format_name() => raw.content.clone(),
+            BlockInner::Raw(_) => String::new(),
+            BlockInner::Tagged(tagged) => {
+                if doc::meta_includes(&tagged.meta, self.format_name(), self.draft) {
+                    self.blocks_md(&tagged.content)?
+                } else {
+                    String::new()
+                }
+            }
+            BlockInner::Table(_) => self.unsupported("tables").map(|_| String::new())?,
+            BlockInner::Figure(_) => self.unsupported("figures").map(|_| String::new())?,
+            BlockInner::Defn(_) => self.unsupported("definitions").map(|_| String::new())?,
+            BlockInner::TermList(_) => self.unsupported("term lists").map(|_| String::new())?,
+        })
+    }
+
+    fn list_md(&mut self, list: &List) -> Result<String, SerializerError> {
+        let mut items = Vec::new();
+        for (i, item) in list.items.iter().enumerate() {
+            let marker = match list.kind {
+                ListKind::Unordered => "- ".to_owned(),
+                ListKind::Ordered => format!("{}. ", i + 1),
+            };
+            // Continuation lines line up with the item's text, so nested
+            // blocks stay in the item.
+            items.push(hanging(&marker, &self.blocks_md(&item.content)?));
+        }
+        Ok(items.join("\n"))
+    }
+
+    fn code_md(&mut self, code: &Code) -> Result<String, SerializerError> {
+        let mut lines = Vec::new();
+        for line in &code.lines {
+            match doc::inlines_to_plain_text(line) {
+                Ok(text) => lines.push(text),
+                Err(err) => {
+                    self.unsupported(&format!("{} in a code block", err.0))?;
+                    lines.push(String::new());
+                }
+            }
+        }
+        let content = lines.join("\n");
+        // The fence has to be longer than any run of backticks in the code.
+        let fence = "`".repeat(longest_run(&content, '`').max(2) + 1);
+        // `plain` means no highlighting, so it isn't a language to name.
+        let language = if code.language == "plain" {
+            ""
+        } else {
+            &code.language
+        };
+        Ok(format!("{}{}\n{}\n{}", fence, language, content, fence))
+    }
+
+    fn inlines_md(&mut self, inlines: &[Inline]) -> Result<String, SerializerError> {
+        let mut ret = String::new();
+        self.push_inlines(&mut ret, inlines)?;
+        Ok(ret)
+    }
+
+    fn push_inlines(
+        &mut self,
+        ret: &mut String,
+        inlines: &[Inline],
+    ) -> Result<(), SerializerError> {
+        for inline in inlines {
+            match inline {
+                Inline::Text(text) => {
+                    let line_start = ret.is_empty() || ret.ends_with('\n');
+                    ret.push_str(&markdown_escape(text, line_start));
+                }
+                Inline::Styled { style, content } => {
+                    let delim = match style {
+                        Style::Emph => "*",
+                        Style::Strong => "**",
+                        _ => {
+                            self.unsupported(&format!("the style {:?}", style))?;
+                            ""
+                        }
+                    };
+                    let md = self.inlines_md(content)?;
+                    // Delimiters next to whitespace don't start or end
+                    // emphasis, so the whitespace goes outside them.
+                    let trimmed = md.trim();
+                    if trimmed.is_empty() {
+                        ret.push_str(&md);
+                    } else {
+                        let start = md.len() - md.trim_start().len();
+                        ret.push_str(&md[..start]);
+                        ret.push_str(delim);
+                        ret.push_str(trimmed);
+                        ret.push_str(delim);
+                        ret.push_str(&md[start + trimmed.len()..]);
+                    }
+                }
+                Inline::Quote(quote) => {
+                    let (l, r) = quote.kind.to_inlines();
+                    self.push_inlines(ret, &l)?;
+                    self.push_inlines(ret, &quote.content)?;
+                    self.push_inlines(ret, &r)?;
+                }
+                Inline::Code(code) => ret.push_str(&code_span(&code.content)),
+                Inline::Space => ret.push(' '),
+                Inline::LineBreak => ret.push_str("\\\n"),
+                Inline::Link(link) => {
+                    let text = self.inlines_md(&link.text())?;
+                    let dest = match &link.target {
+                        LinkTarget::URL(url) => link_destination(url),
+                        LinkTarget::Label(label) => {
+                            format!("#{}", percent_encode_fragment(label.as_str()))
+                        }
+                    };
+                    escape_image_bang(ret);
+                    ret.push_str(&format!("[{}]({})", text, dest));
+                }
+                Inline::Footnote(footnote) => {
+                    // Reserve the footnote's number first, so footnotes within
+                    // it are numbered after it.
+                    self.footnotes.push(String::new());
+                    let num = self.footnotes.len();
+                    self.footnotes[num - 1] = self.blocks_md(&footnote.content)?;
+                    escape_image_bang(ret);
+                    ret.push_str(&format!("[^{}]", num));
+                }
+                Inline::Math(math) => ret.push_str(&format!("${}$", math.tex)),
+                Inline::Image(Image { src, alt, .. }) => ret.push_str(&format!(
+                    "![{}]({})",
+                    markdown_escape(alt.as_deref().unwrap_or_default(), false),
+                    link_destination(src)
+                )),
+                Inline::Raw(raw) if raw.format == self.format_name() => ret.push_str(&raw.content),
+                Inline::Raw(_) => {}
+                Inline::Tagged(tagged) => {
+                    if doc::meta_includes(&tagged.meta, self.format_name(), self.draft) {
+                        self.push_inlines(ret, &tagged.content)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escape a `!` at the end of `ret`, which would make a link or footnote
+/// written after it into an image. `markdown_escape` only escapes a `!` when
+/// it can see the `[` after it.
+fn escape_image_bang(ret: &mut String) {
+    if ret.ends_with('!') {
+        ret.insert(ret.len() - 1, '\\');
+    }
+}
+
+/// The length of the longest run of `c` in `text`.
+fn longest_run(text: &str, c: char) -> usize {
+    text.split(|other| other != c)
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0)
+}
+
+/// A code span containing `code` literally, delimited by more backticks than
+/// it contains in a row.
+fn code_span(code: &str) -> String {
+    let ticks = "`".repeat(longest_run(code, '`') + 1);
+    // One space on each side is stripped, so backticks at the edges of the
+    // code aren't read as part of the delimiters.
+    let pad = if code.starts_with('`') || code.ends_with('`') {
+        " "
+    } else {
+        ""
+    };
+    format!("{0}{1}{2}{1}{0}", ticks, pad, code)
+}
+
+/// A link destination for `url`, in angle brackets if it has characters
+/// which would otherwise end it.
+fn link_destination(url: &str) -> String {
+    if url.is_empty() || url.contains(|c: char| c.is_whitespace() || "()<>".contains(c)) {
+        let escaped = url.replace('<', "\\<").replace('>', "\\>");
+        format!("<{}>", escaped)
+    } else {
+        url.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+    use pulldown_cmark::{html, Event, Options, Parser};
+
+    use super::*;
+    use crate::cmd::WarningKind;
+    use crate::doc::{
+        Footnote, Heading, Id, InlineCode, InlineMath, Link, ListItem, RawBlock, Table,
+        TaggedInlines, ONLY_FORMAT_KEY,
+    };
+    use crate::ser::test_util::*;
+
+    fn list(kind: ListKind, items: Vec<Vec<Block>>) -> Block {
+        block(
+            0,
+            BlockInner::List(List {
+                kind,
+                items: items
+                    .into_iter()
+                    .map(|content| ListItem {
+                        content: content.into(),
+                    })
+                    .collect(),
+            }),
+        )
+    }
+
+    fn to_markdown(blocks: Vec<Block>) -> (String, Vec<Warning>) {
+        let mut out = Vec::new();
+        let mut ser = MarkdownSerializer::new(&mut out).unwrap();
+        ser.write_doc(&Doc::from_content(blocks.into())).unwrap();
+        let warnings = ser.take_warnings();
+        drop(ser);
+        (String::from_utf8(out).unwrap(), warnings)
+    }
+
+    /// Render Markdown to HTML as a CommonMark renderer with footnotes does.
+    fn commonmark(md: &str) -> String {
+        let mut ret = String::new();
+        html::push_html(&mut ret, Parser::new_ext(md, Options::ENABLE_FOOTNOTES));
+        ret
+    }
+
+    #[test]
+    fn test_markdown() {
+        let (md, warnings) = to_markdown(vec![
+            heading(0, 1, "A short article"),
+            par(
+                0,
+                vec![
+                    Inline::Text("Some".into()),
+                    Inline::Space,
+                    Inline::Styled {
+                        style: Style::Emph,
+                        content: text("emphasis"),
+                    },
+                    Inline::Text(",".into()),
+                    Inline::Styled {
+                        style: Style::Strong,
+                        content: vec![Inline::Space, Inline::Text("strong".into()), Inline::Space],
+                    },
+                    Inline::Text("text, and ".into()),
+                    Inline::Code(InlineCode {
+                        content: "x = 1".into(),
+                        ..Default::default()
+                    }),
+                    Inline::Text(".".into()),
+                    Inline::Footnote(Footnote {
+                        content: vec![par(0, text("A note.")), par(0, text("And more."))].into(),
+                    }),
+                ],
+            ),
+            par(
+                0,
+                vec![
+                    Inline::Math(InlineMath {
+                        tex: "x^2".into(),
+                        alt: None,
+                    }),
+                    Inline::Space,
+                    Inline::Link(Link {
+                        content: Some(text("a link")),
+                        label: None,
+                        target: LinkTarget::URL("https://example.com/a b".into()),
+                        attrs: Default::default(),
+                    }),
+                    Inline::LineBreak,
+                    Inline::Link(Link {
+                        content: Some(text("Intro")),
+                        label: None,
+                        target: LinkTarget::Label("intro".parse().unwrap()),
+                        attrs: Default::default(),
+                    }),
+                ],
+            ),
+            block(
+                0,
+                BlockInner::Quote(vec![par(0, text("Quoted.")), par(0, text("Twice."))].into()),
+            ),
+            block(0, BlockInner::Rule),
+        ]);
+        assert_eq!(Vec::<Warning>::new(), warnings);
+        assert_eq!(
+            "# A short article\n\
+             \n\
+             Some *emphasis*, **strong** text, and `x = 1`.[^1]\n\
+             \n\
+             $x^2$ [a link](<https://example.com/a b>)\\\n\
+             [Intro](#intro)\n\
+             \n\
+             > Quoted.\n\
+             >\n\
+             > Twice.\n\
+             \n\
+             * * *\n\
+             \n\
+             [^1]: A note.\n\
+             \n    And more.\n",
+            md
+        );
+        assert_eq!(
+            concat!(
+                "<h1>A short article</h1>\n",
+                "<p>Some <em>emphasis</em>, <strong>strong</strong> text, and <code>x = 1</code>.",
+                r##"<sup class="footnote-reference"><a href="#1">1</a></sup></p>"##,
+                "\n",
+                r#"<p>$x^2$ <a href="https://example.com/a%20b">a link</a><br />"#,
+                "\n",
+                r##"<a href="#intro">Intro</a></p>"##,
+                "\n<blockquote>\n<p>Quoted.</p>\n<p>Twice.</p>\n</blockquote>\n<hr />\n",
+                r#"<div class="footnote-definition" id="1">"#,
+                r#"<sup class="footnote-definition-label">1</sup>"#,
+                "\n<p>A note.</p>\n<p>And more.</p>\n</div>\n",
+            ),
+            commonmark(&md)
+        );
+    }
+
+    #[test]
+    fn test_markdown_image_bang() {
+        // A link after a `!` in separate text isn't an image.
+        let (md, _) = to_markdown(vec![par(
+            0,
+            vec![
+                Inline::Text("Wow!".into()),
+                Inline::Link(Link {
+                    content: Some(text("a link")),
+                    label: None,
+                    target: LinkTarget::URL("https://example.com".into()),
+                    attrs: Default::default(),
+                }),
+            ],
+        )]);
+        assert_eq!("Wow\\![a link](https://example.com)\n", md);
+    }
+
+    #[test]
+    fn test_markdown_lists() {
+        let plain = |s: &str| block(0, BlockInner::Plain(text(s)));
+        let (md, _) = to_markdown(vec![
+            list(
+                ListKind::Unordered,
+                vec![
+                    vec![
+                        plain("One"),
+                        list(ListKind::Ordered, vec![vec![plain("A")], vec![plain("B")]]),
+                    ],
+                    vec![plain("Two")],
+                ],
+            ),
+            list(
+                ListKind::Unordered,
+                vec![vec![par(0, text("Another")), par(0, text("list"))]],
+            ),
+        ]);
+        assert_eq!(
+            "- One\n  1. A\n  2. B\n- Two\n\n<!-- -->\n\n- Another\n\n  list\n",
+            md
+        );
+        assert_eq!(
+            concat!(
+                "<ul>\n<li>One\n<ol>\n<li>A</li>\n<li>B</li>\n</ol>\n</li>\n<li>Two</li>\n</ul>\n",
+                "<!-- -->\n",
+                "<ul>\n<li>\n<p>Another</p>\n<p>list</p>\n</li>\n</ul>\n",
+            ),
+            commonmark(&md)
+        );
+    }
+
+    #[test]
+    fn test_markdown_code() {
+        let (md, _) = to_markdown(vec![
+            block(
+                0,
+                BlockInner::Code(Code {
+                    language: "md".into(),
+                    lines: vec![text("```"), vec![], text("*a*")],
+                    ..Default::default()
+                }),
+            ),
+            par(
+                0,
+                vec![Inline::Code(InlineCode {
+                    content: "`a``".into(),
+                    ..Default::default()
+                })],
+            ),
+        ]);
+        assert_eq!("````md\n```\n\n*a*\n````\n\n``` `a`` ```\n", md);
+        assert_eq!(
+            "<pre><code class=\"language-md\">```\n\n*a*\n</code></pre>\n<p><code>`a``</code></p>\n",
+            commonmark(&md)
+        );
+
+        let (md, _) = to_markdown(vec![block(
+            0,
+            BlockInner::Code(Code {
+                lines: vec![text("x")],
+                ..Default::default()
+            }),
+        )]);
+        assert_eq!("```\nx\n```\n", md);
+    }
+
+    #[test]
+    fn test_markdown_raw() {
+        let raw = |format: &str, content: &str| {
+            block(
+                0,
+                BlockInner::Raw(RawBlock {
+                    format: format.into(),
+                    content: content.into(),
+                }),
+            )
+        };
+        let (md, warnings) = to_markdown(vec![
+            raw("markdown", "| a | *b* |\n|---|---|"),
+            raw("latex", "\\newpage"),
+        ]);
+        assert!(md.starts_with("| a | *b* |\n|---|---|\n"));
+        assert!(!md.contains("newpage"));
+        assert_eq!(Vec::<Warning>::new(), warnings);
+    }
+
+    #[test]
+    fn test_markdown_unsupported() {
+        let (md, warnings) = to_markdown(vec![
+            block(
+                0,
+                BlockInner::Table(Table {
+                    columns: vec![],
+                    cells: vec![],
+                }),
+            ),
+            par(
+                0,
+                vec![
+                    Inline::Styled {
+                        style: Style::Strikeout,
+                        content: text("gone"),
+                    },
+                    Inline::Tagged(TaggedInlines {
+                        content: text("HTML only"),
+                        meta: vec![(ONLY_FORMAT_KEY.to_owned(), "html".to_owned())]
+                            .into_iter()
+                            .collect(),
+                    }),
+                ],
+            ),
+        ]);
+        assert_eq!("gone\n", md);
+        assert_eq!(
+            vec![
+                Warning::new(
+                    WarningKind::Unsupported,
+                    "Markdown output doesn't support tables; skipping".into()
+                ),
+                Warning::new(
+                    WarningKind::Unsupported,
+                    "Markdown output doesn't support the style Strikeout; skipping".into()
+                ),
+            ],
+            warnings
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_markdown_escape(s in "[!-~]([ -~]*[!-~])?") {
+            let (md, _) = to_markdown(vec![par(0, text(&s))]);
+            let mut rendered = String::new();
+            for event in Parser::new(&md) {
+                if let Event::Text(text) = event {
+                    rendered.push_str(&text);
+                }
+            }
+            prop_assert_eq!(s, rendered);
+        }
+
+        #[test]
+        fn test_code_span(s in "[!-~]([ -~]*[!-~])?") {
+            let (md, _) = to_markdown(vec![par(0, vec![Inline::Code(InlineCode {
+                content: s.clone(),
+                ..Default::default()
+            })])]);
+            let codes: Vec<_> = Parser::new(&md)
+                .filter_map(|event| match event {
+                    Event::Code(code) => Some(code.into_string()),
+                    _ => None,
+                })
+                .collect();
+            prop_assert_eq!(vec![s], codes);
+        }
+    }
+}
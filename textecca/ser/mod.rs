@@ -4,13 +4,37 @@ use std::io::{self, Write};
 
 use thiserror::Error;
 
+use crate::cmd::{Strictness, Warning, WarningKind};
+use crate::diagnostic::ErrorCode;
 use crate::doc::BlockInner;
 use crate::doc::Doc;
 
+mod assets;
 mod helpers;
+#[cfg(feature = "html")]
 mod html;
+#[cfg(feature = "cli-support")]
+mod json;
+mod latex;
+mod markdown;
+#[cfg(feature = "cli-support")]
+mod pandoc;
+mod registry;
+#[cfg(test)]
+mod test_util;
+mod text;
+pub use assets::*;
 pub use helpers::*;
+#[cfg(feature = "html")]
 pub use html::*;
+#[cfg(feature = "cli-support")]
+pub use json::*;
+pub use latex::*;
+pub use markdown::*;
+#[cfg(feature = "cli-support")]
+pub use pandoc::*;
+pub use registry::*;
+pub use text::*;
 
 /// An error while serializing a document.
 #[derive(Error, Debug)]
@@ -19,25 +43,118 @@ pub enum SerializerError {
     #[error("{0}")]
     Io(#[from] io::Error),
 
+    /// No serializer is registered for the format; see `Registry`.
+    #[error("Unknown output format {0:?}")]
+    UnknownFormat(String),
+
+    /// A recoverable problem was found with `Strictness::Strict`; see
+    /// `cmd::Strictness`.
+    #[error("{0}")]
+    Strict(Warning),
+
+    /// The document is nested more deeply than the serializer allows, e.g.
+    /// with `HtmlOptions::max_depth`.
+    #[error("Document is nested more than {depth} levels deep")]
+    TooDeep {
+        /// The deepest nesting allowed.
+        depth: usize,
+    },
+
     /// Some other arbitrary error.
     #[error("{0}")]
     Other(#[from] Box<dyn error::Error>),
 }
 
+impl SerializerError {
+    /// The stable code for this error; see `diagnostic::ErrorCode`. Errors from
+    /// outside textecca have the code `ErrorCode::Output`.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Io(_) => ErrorCode::Io,
+            Self::UnknownFormat(_) => ErrorCode::UnknownFormat,
+            Self::Strict(warning) => warning.kind.code(),
+            Self::TooDeep { .. } => ErrorCode::TooDeep,
+            #[cfg(feature = "html")]
+            Self::Other(err) => match err.downcast_ref::<HtmlError>() {
+                Some(err) => err.code(),
+                None => ErrorCode::Output,
+            },
+            #[cfg(not(feature = "html"))]
+            Self::Other(_) => ErrorCode::Output,
+        }
+    }
+}
+
 impl<E: error::Error + 'static> From<Box<E>> for SerializerError {
     fn from(e: Box<E>) -> Self {
         Self::Other(e)
     }
 }
 
+/// Report content which a serializer can't write in the given format, e.g.
+/// `"HTML"`, and which it skips, according to `strictness`. Returns the
+/// warning to report, if any; see `Serializer::take_warnings`.
+///
+/// This is also how serializers handle kinds of content they don't know
+/// about; see "Compatibility" in the `Serializer` docs.
+pub fn unsupported(
+    strictness: Strictness,
+    format: &str,
+    what: &str,
+) -> Result<Option<Warning>, SerializerError> {
+    let warning = Warning::new(
+        WarningKind::Unsupported,
+        format!("{} output doesn't support {}; skipping", format, what),
+    );
+    strictness.handle(warning).map_err(SerializerError::Strict)
+}
+
 /// Trait to initialize a `Serializer`.
 pub trait InitSerializer<W: Write> {
     /// Create a new `Serializer` from the given basename.
     fn new(writer: W) -> Result<Box<Self>, SerializerError>;
 }
 
+/// Trait to initialize a `Serializer` with options for its format, e.g.
+/// `HtmlOptions`. `InitSerializer::new` creates it with the default options.
+///
+/// To create serializers by format name, with options for every format, use a
+/// `Registry` instead.
+pub trait InitSerializerWithOptions<W: Write>: InitSerializer<W> {
+    /// The serializer's options.
+    type Options: Default;
+
+    /// Create a new `Serializer` with the given options.
+    fn new_with_options(writer: W, options: Self::Options) -> Result<Box<Self>, SerializerError>;
+}
+
 /// A document serializer for a particular format.
+///
+/// # Compatibility
+///
+/// `doc::Block`, `doc::BlockInner`, `doc::Inline`, and `doc::Style` are
+/// `#[non_exhaustive]`, so new kinds of content can be added without breaking
+/// serializers in other crates. Serializers matching on them need a catch-all
+/// arm, which should report the content with `unsupported` (e.g. using
+/// `BlockInner::kind_name`) and skip it, or write its children if it has
+/// any. A document using a newer kind of content then gives a warning (or an
+/// error with `Strictness::Strict`) rather than failing to compile or being
+/// silently mangled.
 pub trait Serializer {
-    /// Serialize the given document.
-    fn write_doc(&mut self, doc: Doc) -> Result<(), SerializerError>;
+    /// The name of this serializer's output format, e.g. `"html"`.
+    ///
+    /// Content tagged for a particular format (see `doc::ONLY_FORMAT_KEY`) is
+    /// only written by serializers with that format name.
+    fn format_name(&self) -> &'static str;
+
+    /// Serialize the given document. The document is borrowed, so the same
+    /// document can be written by several serializers, or kept for later
+    /// passes, without being cloned.
+    fn write_doc(&mut self, doc: &Doc) -> Result<(), SerializerError>;
+
+    /// Remove and return the warnings emitted while writing documents so far;
+    /// see `cmd::Strictness`.
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        Vec::new()
+    }
 }
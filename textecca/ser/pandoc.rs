@@ -0,0 +1,753 @@
+//! Serialization to pandoc's JSON AST, for converting documents to other
+//! formats with [pandoc](https://pandoc.org/).
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::iter;
+
+use serde_json::{json, Map, Value};
+
+use super::{
+    abstract_blocks, heading_id, percent_encode_fragment, reserve_heading_ids, InitSerializer,
+    Serializer, SerializerError,
+};
+use crate::doc::{
+    self, Alignment, Block, BlockInner, Code, Doc, DocNode, Inline, Length, Meta, QuoteKind,
+    RelLength, Style, Table,
+};
+
+/// The version of pandoc's AST written by a `PandocSerializer`, as given by
+/// the [`pandoc-types`](https://hackage.haskell.org/package/pandoc-types)
+/// package.
+pub const PANDOC_API_VERSION: [u32; 3] = [1, 23, 1];
+
+/// Serializer to pandoc's JSON AST, which `pandoc -f json` reads, e.g. to
+/// convert a document to DOCX or EPUB.
+///
+/// Most content has an equivalent in pandoc's AST; headings are given the same
+/// ids as in HTML output. Content without an equivalent is written as a `Div`
+/// or `Span` with a class naming it rather than skipped, e.g. `defn` for a
+/// definition or `color` for colored text, so pandoc filters can handle it.
+///
+/// The document's metadata is written as `MetaString`s, and its abstract (see
+/// `doc::ABSTRACT_CHANNEL`) as the `abstract` metadata field.
+pub struct PandocSerializer<W: Write> {
+    writer: W,
+    /// Whether the document being written is a draft; see `doc::is_draft`.
+    draft: bool,
+    /// The ids written for headings so far; see `heading_id`.
+    heading_ids: HashMap<String, usize>,
+    /// Added to heading levels, so the document's highest heading is at
+    /// pandoc's level 1; see `level_shift`.
+    level_shift: i32,
+}
+
+impl<W: Write> InitSerializer<W> for PandocSerializer<W> {
+    fn new(writer: W) -> Result<Box<Self>, SerializerError> {
+        Ok(Box::new(Self {
+            writer,
+            draft: false,
+            heading_ids: HashMap::new(),
+            level_shift: 0,
+        }))
+    }
+}
+
+impl<W: Write> Serializer for PandocSerializer<W> {
+    fn format_name(&self) -> &'static str {
+        "pandoc"
+    }
+
+    fn write_doc(&mut self, doc: &Doc) -> Result<(), SerializerError> {
+        self.draft = doc::is_draft(&doc.meta);
+        self.heading_ids.clear();
+        let blocks = abstract_blocks(&doc.collections).chain(iter::once(&doc.content[..]));
+        reserve_heading_ids(blocks, &mut self.heading_ids);
+        self.level_shift = level_shift(doc);
+        let mut meta = Map::new();
+        for (key, value) in &doc.meta {
+            meta.insert(key.clone(), node("MetaString", json!(value)));
+        }
+        if let Some(entries) = doc.collections.get(doc::ABSTRACT_CHANNEL) {
+            let mut blocks = Vec::new();
+            for entry in entries {
+                blocks.extend(self.blocks(entry));
+            }
+            if !blocks.is_empty() {
+                meta.insert("abstract".to_owned(), node("MetaBlocks", json!(blocks)));
+            }
+        }
+        let pandoc = json!({
+            "pandoc-api-version": PANDOC_API_VERSION,
+            "meta": meta,
+            "blocks": self.blocks(&doc.content),
+        });
+        serde_json::to_writer(&mut self.writer, &pandoc).map_err(io::Error::from)?;
+        writeln!(self.writer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> PandocSerializer<W> {
+    fn blocks(&mut self, blocks: &[Block]) -> Vec<Value> {
+        let mut ret = Vec::new();
+        for block in blocks {
+            if let Some(block) = self.block(&block.inner) {
+                ret.push(block);
+            }
+        }
+        ret
+    }
+
+    fn block(&mut self, block: &BlockInner) -> Option<Value> {
+        Some(match block {
+            BlockInner::Plain(inlines) => node("Plain", json!(self.inlines(inlines))),
+            BlockInner::Par(inlines) => node("Para", json!(self.inlines(inlines))),
+            BlockInner::Code(code) => self.code(code),
+            BlockInner::Quote(blocks) => node("BlockQuote", json!(self.blocks(blocks))),
+            BlockInner::List(list) => {
+                let items: Vec<_> = list
+                    .items
+                    .iter()
+                    .map(|item| self.blocks(&item.content))
+                    .collect();
+                match list.kind {
+                    doc::ListKind::Unordered => node("BulletList", json!(items)),
+                    doc::ListKind::Ordered => node(
+                        "OrderedList",
+                        json!([[1, tag("DefaultStyle"), tag("DefaultDelim")], items]),
+                    ),
+                }
+            }
+            BlockInner::TermList(items) => {
+                let items: Vec<_> = items
+                    .iter()
+                    .map(|item| json!([self.inlines(&item.term), [self.blocks(&item.content)]]))
+                    .collect();
+                node("DefinitionList", json!(items))
+            }
+            BlockInner::Heading(heading) => {
+                let id = heading_id(heading, &mut self.heading_ids);
+                // Pandoc's `unlisted` class leaves a heading out of the table
+                // of contents.
+                let classes = if heading.in_toc() {
+                    vec![]
+                } else {
+                    vec!["unlisted"]
+                };
+                let attr = attr(&id, &classes, &heading.attrs, &[doc::ID_KEY, "toc"]);
+                node(
+                    "Header",
+                    // Pandoc's headings only have 6 levels.
+                    json!([
+                        (heading.level + self.level_shift).clamp(1, 6),
+                        attr,
+                        self.inlines(&heading.text)
+                    ]),
+                )
+            }
+            BlockInner::Rule => tag("HorizontalRule"),
+            BlockInner::Table(table) => self.table(table),
+            BlockInner::Figure(figure) => {
+                let id = figure.label.as_ref().map(|label| label.as_str());
+                // Pandoc's writers number figures themselves.
+                let caption = figure.unnumbered_caption();
+                let caption = if caption.is_empty() {
+                    vec![]
+                } else {
+                    vec![node("Plain", json!(self.inlines(caption)))]
+                };
+                node(
+                    "Figure",
+                    json!([
+                        attr(id.unwrap_or(""), &[figure.kind.name()], &Meta::new(), &[]),
+                        [null, caption],
+                        self.blocks(&figure.content),
+                    ]),
+                )
+            }
+            BlockInner::Defn(defn) => {
+                let mut blocks = vec![
+                    div(
+                        "defn-name",
+                        vec![node("Plain", json!(self.inlines(&defn.name)))],
+                    ),
+                    div("defn-summary", self.blocks(&defn.summary)),
+                ];
+                blocks.extend(self.blocks(&defn.content));
+                div("defn", blocks)
+            }
+            // Pandoc has no display math blocks, only display math inlines.
+            BlockInner::Math(math) => node(
+                "Para",
+                json!([node("Math", json!([tag("DisplayMath"), math.tex]))]),
+            ),
+            BlockInner::Raw(raw) => node("RawBlock", json!([raw.format, raw.content])),
+            BlockInner::Tagged(tagged) => {
+                if !doc::meta_includes(&tagged.meta, self.format_name(), self.draft) {
+                    return None;
+                }
+                let attr = attr("", &[], &tagged.meta, &[doc::ONLY_FORMAT_KEY]);
+                node("Div", json!([attr, self.blocks(&tagged.content)]))
+            }
+        })
+    }
+
+    fn code(&mut self, code: &Code) -> Value {
+        let mut classes = Vec::new();
+        if code.language != "plain" {
+            classes.push(code.language.as_str());
+        }
+        let lines: Result<Vec<_>, _> = code
+            .lines
+            .iter()
+            .map(|line| doc::inlines_to_plain_text(line))
+            .collect();
+        match lines {
+            Ok(lines) => {
+                let mut attr = attr("", &classes, &code.attrs, &[]);
+                if let Some(line_numbers) = &code.line_numbers {
+                    // Pandoc's convention for numbered code blocks.
+                    attr[1].as_array_mut().unwrap().push(json!("numberLines"));
+                    attr[2]
+                        .as_array_mut()
+                        .unwrap()
+                        .push(json!(["startFrom", line_numbers.start.to_string()]));
+                }
+                node("CodeBlock", json!([attr, lines.join("\n")]))
+            }
+            // Code blocks in pandoc are only text, so code with markup in it
+            // is written as lines of formatted text instead.
+            Err(_) => {
+                classes.insert(0, "code");
+                let lines: Vec<_> = code.lines.iter().map(|line| self.inlines(line)).collect();
+                node(
+                    "Div",
+                    json!([
+                        attr("", &classes, &code.attrs, &[]),
+                        [node("LineBlock", json!(lines))],
+                    ]),
+                )
+            }
+        }
+    }
+
+    /// A table, with all of its rows in a single table body.
+    fn table(&mut self, table: &Table) -> Value {
+        let columns: Vec<_> = table
+            .columns
+            .iter()
+            .map(|column| {
+                let width = match column.width {
+                    Some(Length::Relative(RelLength::Percent(percent))) => {
+                        node("ColWidth", json!(percent / 100.0))
+                    }
+                    _ => tag("ColWidthDefault"),
+                };
+                json!([alignment(Some(column.alignment)), width])
+            })
+            .collect();
+        let mut rows = Vec::new();
+        for row in &table.cells {
+            let mut cells = Vec::new();
+            for cell in row {
+                cells.push(json!([
+                    empty_attr(),
+                    alignment(cell.alignment),
+                    cell.row_span,
+                    cell.col_span,
+                    self.blocks(&cell.content),
+                ]));
+            }
+            rows.push(json!([empty_attr(), cells]));
+        }
+        node(
+            "Table",
+            json!([
+                empty_attr(),
+                [null, []],
+                columns,
+                [empty_attr(), []],
+                [[empty_attr(), 0, [], rows]],
+                [empty_attr(), []],
+            ]),
+        )
+    }
+
+    fn inlines(&mut self, inlines: &[Inline]) -> Vec<Value> {
+        let mut ret = Vec::new();
+        for inline in inlines {
+            self.push_inline(&mut ret, inline);
+        }
+        ret
+    }
+
+    fn push_inline(&mut self, ret: &mut Vec<Value>, inline: &Inline) {
+        let value = match inline {
+            Inline::Text(text) => return push_text(ret, text),
+            Inline::Styled { style, content } => {
+                let content = self.inlines(content);
+                match style {
+                    Style::Emph => node("Emph", json!(content)),
+                    Style::Strong => node("Strong", json!(content)),
+                    Style::Superscript => node("Superscript", json!(content)),
+                    Style::Subscript => node("Subscript", json!(content)),
+                    Style::SmallCaps => node("SmallCaps", json!(content)),
+                    Style::Strikeout => node("Strikeout", json!(content)),
+                    Style::Underline => node("Underline", json!(content)),
+                    Style::Size(size) => {
+                        let style = format!("font-size: {}", size);
+                        span("size", &[("style", &style)], content)
+                    }
+                    Style::Color(color) if color.is_valid() => {
+                        let style = format!("color: {}", color);
+                        span("color", &[("style", &style)], content)
+                    }
+                    Style::Color(_) => span("color", &[], content),
+                    Style::Font(_) => span("font", &[], content),
+                    Style::FontFeatures(_) => span("font-features", &[], content),
+                }
+            }
+            Inline::Quote(quote) => {
+                let content = self.inlines(&quote.content);
+                match &quote.kind {
+                    QuoteKind::Primary => node("Quoted", json!([tag("DoubleQuote"), content])),
+                    QuoteKind::Secondary => node("Quoted", json!([tag("SingleQuote"), content])),
+                    QuoteKind::Other(open, close) => {
+                        let mut inlines = self.inlines(open);
+                        inlines.extend(content);
+                        inlines.extend(self.inlines(close));
+                        span("quote", &[], inlines)
+                    }
+                }
+            }
+            Inline::Code(code) => {
+                let classes = match code.language.as_deref() {
+                    None | Some("plain") => vec![],
+                    Some(language) => vec![language],
+                };
+                let attr = attr("", &classes, &code.attrs, &[]);
+                node("Code", json!([attr, code.content]))
+            }
+            Inline::Space => tag("Space"),
+            Inline::LineBreak => tag("LineBreak"),
+            Inline::Link(link) => {
+                let url = match &link.target {
+                    doc::LinkTarget::Label(label) => {
+                        format!("#{}", percent_encode_fragment(label.as_str()))
+                    }
+                    doc::LinkTarget::URL(url) => url.clone(),
+                };
+                let title = link.label.as_deref().unwrap_or_default();
+                node(
+                    "Link",
+                    json!([
+                        attr("", &[], &link.attrs, &[]),
+                        self.inlines(&link.text()),
+                        [url, title],
+                    ]),
+                )
+            }
+            Inline::Footnote(footnote) => node("Note", json!(self.blocks(&footnote.content))),
+            Inline::Math(math) => node("Math", json!([tag("InlineMath"), math.tex])),
+            Inline::Image(image) => {
+                let mut alt = Vec::new();
+                push_text(&mut alt, image.alt.as_deref().unwrap_or_default());
+                node(
+                    "Image",
+                    json!([attr("", &[], &image.attrs, &[]), alt, [image.src, ""]]),
+                )
+            }
+            Inline::Raw(raw) => node("RawInline", json!([raw.format, raw.content])),
+            Inline::Tagged(tagged) => {
+                if !doc::meta_includes(&tagged.meta, self.format_name(), self.draft) {
+                    return;
+                }
+                let attr = attr("", &[], &tagged.meta, &[doc::ONLY_FORMAT_KEY]);
+                node("Span", json!([attr, self.inlines(&tagged.content)]))
+            }
+        };
+        ret.push(value);
+    }
+}
+
+/// An element of pandoc's AST, with the given constructor and arguments.
+fn node(t: &str, c: Value) -> Value {
+    json!({ "t": t, "c": c })
+}
+
+/// An element of pandoc's AST with a constructor taking no arguments, e.g.
+/// `Space`.
+fn tag(t: &str) -> Value {
+    json!({ "t": t })
+}
+
+/// An element's attributes: its id, classes, and the key-value pairs from
+/// `meta` other than those in `skip`, sorted by key.
+fn attr(id: &str, classes: &[&str], meta: &Meta, skip: &[&str]) -> Value {
+    let mut pairs: Vec<_> = meta
+        .iter()
+        .filter(|(key, _)| !skip.contains(&key.as_str()))
+        .collect();
+    pairs.sort();
+    json!([id, classes, pairs])
+}
+
+fn empty_attr() -> Value {
+    attr("", &[], &Meta::new(), &[])
+}
+
+/// A `Div` with the given class, for blocks pandoc has no equivalent of.
+fn div(class: &str, blocks: Vec<Value>) -> Value {
+    node(
+        "Div",
+        json!([attr("", &[class], &Meta::new(), &[]), blocks]),
+    )
+}
+
+/// A `Span` with the given class and attributes, for inlines pandoc has no
+/// equivalent of.
+fn span(class: &str, pairs: &[(&str, &str)], inlines: Vec<Value>) -> Value {
+    node("Span", json!([["", [class], pairs], inlines]))
+}
+
+fn alignment(alignment: Option<Alignment>) -> Value {
+    tag(match alignment {
+        Some(Alignment::Left) => "AlignLeft",
+        Some(Alignment::Right) => "AlignRight",
+        Some(Alignment::Center) => "AlignCenter",
+        Some(Alignment::Justify) | None => "AlignDefault",
+    })
+}
+
+/// Write text as pandoc does, as words (`Str`) separated by `Space`s.
+fn push_text(ret: &mut Vec<Value>, text: &str) {
+    for (i, word) in text.split([' ', '\t', '\n']).enumerate() {
+        let space = tag("Space");
+        if i > 0 && ret.last() != Some(&space) {
+            ret.push(space);
+        }
+        if !word.is_empty() {
+            ret.push(node("Str", json!(word)));
+        }
+    }
+}
+
+/// The amount to add to the levels of `doc`'s headings so its highest
+/// heading, which may be a title, part, or chapter at a level below 1, is at
+/// pandoc's level 1, and the levels below it stay distinct.
+fn level_shift(doc: &Doc) -> i32 {
+    let abstract_blocks = doc
+        .collections
+        .get(doc::ABSTRACT_CHANNEL)
+        .into_iter()
+        .flatten();
+    iter::once(&doc.content)
+        .chain(abstract_blocks)
+        .flat_map(|blocks| doc::nodes(blocks))
+        .filter_map(|node| match node {
+            DocNode::Block(Block {
+                inner: BlockInner::Heading(heading),
+                ..
+            }) => Some(heading.level),
+            _ => None,
+        })
+        .min()
+        .map_or(0, |level| 1 - level)
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::doc::{
+        Color, Defn, Figure, FigureKind, Footnote, Heading, Id, InlineMath, Link, LinkTarget, Math,
+        RefId, TableCell, TableColumn,
+    };
+    use crate::ser::test_util::{block, heading, text};
+
+    fn to_pandoc(doc: &Doc) -> Value {
+        let mut out = Vec::new();
+        PandocSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(doc)
+            .unwrap();
+        serde_json::from_slice(&out).unwrap()
+    }
+
+    /// Check that every element of pandoc's AST in `value` is an object with a
+    /// constructor name `t` and, optionally, its arguments `c`.
+    fn assert_well_formed(value: &Value) {
+        match value {
+            Value::Object(object) if object.contains_key("t") => {
+                assert!(object["t"].is_string(), "{}", value);
+                assert!(
+                    object.keys().all(|key| key == "t" || key == "c"),
+                    "{}",
+                    value
+                );
+                if let Some(c) = object.get("c") {
+                    assert_well_formed(c);
+                }
+            }
+            Value::Object(object) => panic!("Not an AST element: {:?}", object),
+            Value::Array(values) => values.iter().for_each(assert_well_formed),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_pandoc_document() {
+        let mut doc = Doc::from_content(
+            vec![
+                heading(0, 1, "Intro"),
+                block(
+                    1,
+                    BlockInner::Par(vec![
+                        Inline::Text("Hi there".into()),
+                        Inline::Space,
+                        Inline::Styled {
+                            style: Style::Emph,
+                            content: text("you"),
+                        },
+                        Inline::Math(InlineMath {
+                            tex: "x^2".into(),
+                            alt: None,
+                        }),
+                        Inline::Footnote(Footnote {
+                            content: block(2, BlockInner::Par(text("Note."))).into(),
+                        }),
+                        Inline::Link(Link {
+                            content: None,
+                            label: None,
+                            target: LinkTarget::Label(RefId::new("Intro").unwrap()),
+                            attrs: Default::default(),
+                        }),
+                    ]),
+                ),
+                block(
+                    3,
+                    BlockInner::Math(Math {
+                        tex: "e = mc^2".into(),
+                        alt: None,
+                    }),
+                ),
+                heading(4, 1, "Intro"),
+            ]
+            .into(),
+        );
+        doc.meta.insert("title".into(), "A document".into());
+        let value = to_pandoc(&doc);
+        assert_eq!(json!([1, 23, 1]), value["pandoc-api-version"]);
+        assert_eq!(
+            json!({ "title": { "t": "MetaString", "c": "A document" } }),
+            value["meta"]
+        );
+        assert_well_formed(&value["blocks"]);
+        let blocks = value["blocks"].as_array().unwrap();
+        assert_eq!(
+            vec![
+                json!({
+                    "t": "Header",
+                    "c": [1, ["Intro", [], []], [{ "t": "Str", "c": "Intro" }]]
+                }),
+                json!({
+                    "t": "Para",
+                    "c": [
+                        { "t": "Str", "c": "Hi" },
+                        { "t": "Space" },
+                        { "t": "Str", "c": "there" },
+                        { "t": "Space" },
+                        { "t": "Emph", "c": [{ "t": "Str", "c": "you" }] },
+                        { "t": "Math", "c": [{ "t": "InlineMath" }, "x^2"] },
+                        {
+                            "t": "Note",
+                            "c": [{ "t": "Para", "c": [{ "t": "Str", "c": "Note." }] }]
+                        },
+                        {
+                            "t": "Link",
+                            "c": [["", [], []], [{ "t": "Str", "c": "Intro" }], ["#Intro", ""]]
+                        },
+                    ]
+                }),
+                json!({
+                    "t": "Para",
+                    "c": [{ "t": "Math", "c": [{ "t": "DisplayMath" }, "e = mc^2"] }]
+                }),
+                // Repeated headings get unique ids, as in HTML.
+                json!({
+                    "t": "Header",
+                    "c": [1, ["Intro-1", [], []], [{ "t": "Str", "c": "Intro" }]]
+                }),
+            ],
+            *blocks
+        );
+    }
+
+    #[test]
+    fn test_pandoc_table() {
+        let cell = |s: &str| TableCell {
+            content: block(0, BlockInner::Plain(text(s))).into(),
+            ..Default::default()
+        };
+        let doc = Doc::from_content(
+            block(
+                0,
+                BlockInner::Table(Table {
+                    columns: vec![TableColumn {
+                        alignment: Alignment::Right,
+                        width: Some(Length::Relative(RelLength::Percent(50.0))),
+                    }],
+                    cells: vec![vec![cell("a")], vec![cell("b")]],
+                }),
+            )
+            .into(),
+        );
+        let value = to_pandoc(&doc);
+        assert_well_formed(&value["blocks"]);
+        let row = |s: &str| {
+            json!([
+                ["", [], []],
+                [[
+                    ["", [], []],
+                    { "t": "AlignDefault" },
+                    1,
+                    1,
+                    [{ "t": "Plain", "c": [{ "t": "Str", "c": s }] }]
+                ]]
+            ])
+        };
+        assert_eq!(
+            json!({
+                "t": "Table",
+                "c": [
+                    ["", [], []],
+                    [null, []],
+                    [[{ "t": "AlignRight" }, { "t": "ColWidth", "c": 0.5 }]],
+                    [["", [], []], []],
+                    [[["", [], []], 0, [], [row("a"), row("b")]]],
+                    [["", [], []], []]
+                ]
+            }),
+            value["blocks"][0]
+        );
+    }
+
+    #[test]
+    fn test_pandoc_degrades() {
+        let doc = Doc::from_content(
+            vec![
+                block(
+                    0,
+                    BlockInner::Defn(Defn {
+                        name: text("Term"),
+                        summary: block(1, BlockInner::Par(text("Meaning."))).into(),
+                        content: Default::default(),
+                    }),
+                ),
+                block(
+                    2,
+                    BlockInner::Plain(vec![Inline::Styled {
+                        style: Style::Color(Color::Named("red".into())),
+                        content: text("Red"),
+                    }]),
+                ),
+            ]
+            .into(),
+        );
+        let value = to_pandoc(&doc);
+        assert_well_formed(&value["blocks"]);
+        assert_eq!(
+            json!([
+                {
+                    "t": "Div",
+                    "c": [["", ["defn"], []], [
+                        {
+                            "t": "Div",
+                            "c": [["", ["defn-name"], []], [
+                                { "t": "Plain", "c": [{ "t": "Str", "c": "Term" }] }
+                            ]]
+                        },
+                        {
+                            "t": "Div",
+                            "c": [["", ["defn-summary"], []], [
+                                { "t": "Para", "c": [{ "t": "Str", "c": "Meaning." }] }
+                            ]]
+                        }
+                    ]]
+                },
+                {
+                    "t": "Plain",
+                    "c": [{
+                        "t": "Span",
+                        "c": [
+                            ["", ["color"], [["style", "color: red"]]],
+                            [{ "t": "Str", "c": "Red" }]
+                        ]
+                    }]
+                }
+            ]),
+            value["blocks"]
+        );
+    }
+
+    #[test]
+    fn test_pandoc_heading_levels() {
+        let levels = |levels: &[i32]| -> Vec<Value> {
+            let doc = Doc::from_content(
+                levels
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &level)| heading(i, level, &format!("Heading {}", i)))
+                    .collect::<Vec<_>>()
+                    .into(),
+            );
+            to_pandoc(&doc)["blocks"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|block| block["c"][0].clone())
+                .collect()
+        };
+        // A title, part, and chapter keep their places above sections.
+        assert_eq!(
+            vec![json!(1), json!(2), json!(3), json!(5), json!(6)],
+            levels(&[-3, -2, -1, 1, 2])
+        );
+        assert_eq!(vec![json!(1), json!(2)], levels(&[-1, 0]));
+        // Sections are at level 1 when they're the highest headings.
+        assert_eq!(vec![json!(1), json!(2), json!(6)], levels(&[1, 2, 9]));
+        // Abstracts count too.
+        let mut doc = Doc::from_content(vec![heading(0, 1, "Section")].into());
+        doc.collections.insert(
+            doc::ABSTRACT_CHANNEL.into(),
+            vec![vec![heading(1, -1, "Summary")].into()],
+        );
+        let value = to_pandoc(&doc);
+        assert_eq!(json!(3), value["blocks"][0]["c"][0]);
+        assert_eq!(json!(1), value["meta"]["abstract"]["c"][0]["c"][0]);
+    }
+
+    #[test]
+    fn test_pandoc_figure_caption() {
+        let mut doc = Doc::from_content(
+            vec![block(
+                0,
+                BlockInner::Figure(Figure {
+                    kind: FigureKind::Figure,
+                    caption: text("Plotted."),
+                    content: Default::default(),
+                    label: None,
+                    number: None,
+                }),
+            )]
+            .into(),
+        );
+        doc::number_figures(&mut doc);
+        assert_eq!(
+            json!([null, [{ "t": "Plain", "c": [{ "t": "Str", "c": "Plotted." }] }]]),
+            to_pandoc(&doc)["blocks"][0]["c"][1]
+        );
+    }
+}
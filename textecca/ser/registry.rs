@@ -0,0 +1,188 @@
+//! Looking up serializers by format name, including serializers defined in
+//! other crates.
+//!
+//! A third-party output format needs only:
+//!
+//! 1. A type implementing `Serializer`; `format_name` names the format for
+//!    `\only` (see `doc::ONLY_FORMAT_KEY`), and `write_doc` writes a whole
+//!    `Doc`. `SerializerError::Other` can wrap any format-specific error.
+//! 2. A `SerializerFactory` creating it from a writer and `SerializerOptions`,
+//!    registered with `Registry::register`.
+//!
+//! `InitSerializer` is optional, and only needed to create the serializer
+//! generically outside of a `Registry`.
+use std::collections::BTreeMap;
+use std::io::Write;
+
+#[cfg(feature = "epub")]
+use super::{EpubOptions, EpubSerializer};
+#[cfg(feature = "html")]
+use super::{HtmlOptions, HtmlSerializer};
+#[cfg(feature = "cli-support")]
+use super::{InitSerializer, JsonSerializer, PandocSerializer};
+use super::{
+    LatexSerializer, MarkdownSerializer, PlainTextSerializer, Serializer, SerializerError,
+    TextOptions,
+};
+use crate::cmd::Strictness;
+
+/// Options passed to every serializer a `Registry` creates. Serializers ignore
+/// options for other formats.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SerializerOptions {
+    /// How recoverable problems are handled, overriding the strictness given
+    /// in each format's options.
+    pub strictness: Strictness,
+    /// Options for the `"html"` format.
+    #[cfg(feature = "html")]
+    pub html: HtmlOptions,
+    /// Options for the `"epub"` format.
+    #[cfg(feature = "epub")]
+    pub epub: EpubOptions,
+    /// Options for the `"text"` format.
+    pub text: TextOptions,
+}
+
+/// A function creating a serializer which writes to the given writer.
+pub type SerializerFactory =
+    fn(Box<dyn Write>, &SerializerOptions) -> Result<Box<dyn Serializer>, SerializerError>;
+
+/// A set of serializers, by format name.
+#[derive(Debug, Clone)]
+pub struct Registry {
+    factories: BTreeMap<String, SerializerFactory>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::with_builtins()
+    }
+}
+
+impl SerializerOptions {
+    /// The options for the `"html"` format, with `strictness`; e.g. for
+    /// `write_split`.
+    #[cfg(feature = "html")]
+    pub fn html_options(&self) -> HtmlOptions {
+        HtmlOptions {
+            strictness: self.strictness,
+            ..self.html.clone()
+        }
+    }
+}
+
+impl Registry {
+    /// Create a registry with no serializers.
+    pub fn new() -> Self {
+        Self {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    /// Create a registry with textecca's built-in serializers, i.e. `"latex"`,
+    /// `"markdown"`, `"text"`, with the `html` feature, `"html"`, with the
+    /// `epub` feature, `"epub"`, and with the `cli-support` feature, `"json"`
+    /// and `"pandoc"`.
+    pub fn with_builtins() -> Self {
+        let mut ret = Self::new();
+        #[cfg(feature = "html")]
+        ret.register("html", |writer, options| {
+            Ok(HtmlSerializer::with_options(
+                writer,
+                options.html_options(),
+            )?)
+        });
+        #[cfg(feature = "epub")]
+        ret.register("epub", |writer, options| {
+            let mut epub = options.epub.clone();
+            epub.html.strictness = options.strictness;
+            Ok(EpubSerializer::with_options(writer, epub))
+        });
+        #[cfg(feature = "cli-support")]
+        ret.register("json", |writer, _options| Ok(JsonSerializer::new(writer)?));
+        #[cfg(feature = "cli-support")]
+        ret.register("pandoc", |writer, _options| {
+            Ok(PandocSerializer::new(writer)?)
+        });
+        ret.register("latex", |writer, options| {
+            Ok(LatexSerializer::with_strictness(writer, options.strictness))
+        });
+        ret.register("markdown", |writer, options| {
+            Ok(MarkdownSerializer::with_strictness(
+                writer,
+                options.strictness,
+            ))
+        });
+        ret.register("text", |writer, options| {
+            let text = TextOptions {
+                strictness: options.strictness,
+                ..options.text.clone()
+            };
+            Ok(PlainTextSerializer::with_options(writer, text))
+        });
+        ret
+    }
+
+    /// Register a serializer under the given format name, replacing any
+    /// serializer already registered with that name.
+    pub fn register(&mut self, name: impl Into<String>, factory: SerializerFactory) {
+        self.factories.insert(name.into(), factory);
+    }
+
+    /// The factory registered with the given format name, if any.
+    pub fn get(&self, name: &str) -> Option<SerializerFactory> {
+        self.factories.get(name).copied()
+    }
+
+    /// The registered format names, in sorted order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    /// Create a serializer for the given format.
+    pub fn create(
+        &self,
+        name: &str,
+        writer: Box<dyn Write>,
+        options: &SerializerOptions,
+    ) -> Result<Box<dyn Serializer>, SerializerError> {
+        let factory = self
+            .get(name)
+            .ok_or_else(|| SerializerError::UnknownFormat(name.to_owned()))?;
+        factory(writer, options)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_builtins() {
+        let registry = Registry::with_builtins();
+        let mut names = vec!["latex", "markdown", "text"];
+        if cfg!(feature = "html") {
+            names.push("html");
+        }
+        if cfg!(feature = "epub") {
+            names.push("epub");
+        }
+        if cfg!(feature = "cli-support") {
+            names.extend(&["json", "pandoc"]);
+        }
+        names.sort_unstable();
+        assert_eq!(names, registry.names().collect::<Vec<_>>());
+        for name in registry.names() {
+            let ser = registry
+                .create(name, Box::new(Vec::new()), &Default::default())
+                .unwrap();
+            assert_eq!(name, ser.format_name());
+        }
+        assert!(matches!(
+            registry.create("pdf", Box::new(Vec::new()), &Default::default()),
+            Err(SerializerError::UnknownFormat(name)) if name == "pdf"
+        ));
+    }
+}
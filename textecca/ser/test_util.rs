@@ -0,0 +1,56 @@
+//! Fixtures for serializers' tests.
+pub(crate) use friendly_html::test_util::TempDir;
+
+use crate::doc::{Block, BlockInner, Heading, Id, Inline};
+
+pub(crate) fn text(s: &str) -> Vec<Inline> {
+    vec![Inline::Text(s.into())]
+}
+
+pub(crate) fn block(id: usize, inner: BlockInner) -> Block {
+    Block {
+        id: Id::from(id),
+        inner,
+    }
+}
+
+pub(crate) fn par(id: usize, inlines: Vec<Inline>) -> Block {
+    block(id, BlockInner::Par(inlines))
+}
+
+pub(crate) fn heading(id: usize, level: i32, s: &str) -> Block {
+    heading_with(id, level, s, &[])
+}
+
+/// A heading with attributes, e.g. `&[(ID_KEY, "intro")]` for an explicit id.
+pub(crate) fn heading_with(id: usize, level: i32, s: &str, attrs: &[(&str, &str)]) -> Block {
+    block(
+        id,
+        BlockInner::Heading(Heading {
+            level,
+            text: text(s),
+            attrs: attrs
+                .iter()
+                .map(|(key, value)| ((*key).to_owned(), (*value).to_owned()))
+                .collect(),
+        }),
+    )
+}
+
+/// Check that every element in some HTML is closed, in order.
+#[cfg(feature = "html")]
+pub(crate) fn assert_balanced(html: &str) {
+    let mut open = Vec::new();
+    for token in friendly_html::tokenize(html).unwrap() {
+        match token {
+            friendly_html::HtmlToken::StartTag { name, .. } if !friendly_html::is_void(&name) => {
+                open.push(name)
+            }
+            friendly_html::HtmlToken::EndTag(name) => {
+                assert_eq!(Some(name), open.pop(), "{}", html)
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(Vec::<String>::new(), open, "{}", html);
+}
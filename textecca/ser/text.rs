@@ -0,0 +1,644 @@
+//! Serialization to plain text, e.g. for search indexes and previews.
+use std::io::Write;
+use std::mem;
+
+use super::{unsupported, InitSerializer, InitSerializerWithOptions, Serializer, SerializerError};
+use crate::cmd::{Strictness, Warning};
+use crate::doc::{self, tex_to_text, Block, BlockInner, Doc, Image, Inline, ListKind, Meta};
+use crate::fill::{fill, Atom};
+
+/// Options for a `PlainTextSerializer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextOptions {
+    /// How content which can't be written as text is handled.
+    pub strictness: Strictness,
+    /// The width to wrap paragraphs to, in characters; by default, 80. With
+    /// `None`, each paragraph is written on a single line.
+    pub width: Option<usize>,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        Self {
+            strictness: Default::default(),
+            width: Some(80),
+        }
+    }
+}
+
+/// Serializer to plain text.
+///
+/// Blocks are separated by blank lines, and styles, tags, and link targets
+/// are dropped. Headings are underlined with `=` (for level 1) or `-`, list
+/// items are marked with bullets or numbers, block quotes with `>`, and
+/// footnotes are numbered and listed after the document. Math is written as
+/// its alt text, or with `doc::tex_to_text`.
+///
+/// Paragraphs are wrapped to `TextOptions::width`, breaking lines at spaces
+/// but never within inline code; code blocks are written as they are.
+pub struct PlainTextSerializer<W: Write> {
+    writer: W,
+    options: TextOptions,
+    /// Whether the document being written is a draft; see `doc::is_draft`.
+    draft: bool,
+    /// How far the text being written will be indented, e.g. by list
+    /// markers, which is taken out of the width it's wrapped to.
+    indent: usize,
+    /// Whether spaces in text are kept as they are, as in code blocks.
+    preformatted: bool,
+    /// The text of the footnotes referenced so far, in order.
+    footnotes: Vec<String>,
+    /// Warnings emitted so far; see `Serializer::take_warnings`.
+    warnings: Vec<Warning>,
+}
+
+impl<W: Write> InitSerializer<W> for PlainTextSerializer<W> {
+    fn new(writer: W) -> Result<Box<Self>, SerializerError> {
+        Ok(Self::with_options(writer, Default::default()))
+    }
+}
+
+impl<W: Write> InitSerializerWithOptions<W> for PlainTextSerializer<W> {
+    type Options = TextOptions;
+
+    fn new_with_options(writer: W, options: TextOptions) -> Result<Box<Self>, SerializerError> {
+        Ok(Self::with_options(writer, options))
+    }
+}
+
+impl<W: Write> Serializer for PlainTextSerializer<W> {
+    fn format_name(&self) -> &'static str {
+        "text"
+    }
+
+    fn write_doc(&mut self, doc: &Doc) -> Result<(), SerializerError> {
+        self.draft = doc::is_draft(&doc.meta);
+        self.footnotes.clear();
+        let mut sections = Vec::new();
+        for blocks in doc
+            .collections
+            .get(doc::ABSTRACT_CHANNEL)
+            .into_iter()
+            .flatten()
+        {
+            sections.push(self.blocks_text(blocks)?);
+        }
+        sections.push(self.blocks_text(&doc.content)?);
+        let footnotes: Vec<_> = mem::take(&mut self.footnotes)
+            .iter()
+            .enumerate()
+            .map(|(i, text)| hanging(&footnote_marker(i + 1), text))
+            .collect();
+        sections.push(footnotes.join("\n"));
+        sections.retain(|section| !section.is_empty());
+        if !sections.is_empty() {
+            writeln!(self.writer, "{}", sections.join("\n\n"))?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
+}
+
+impl<W: Write> PlainTextSerializer<W> {
+    /// Create a new serializer with the given options.
+    pub fn with_options(writer: W, options: TextOptions) -> Box<Self> {
+        Box::new(Self {
+            writer,
+            options,
+            draft: false,
+            indent: 0,
+            preformatted: false,
+            footnotes: Default::default(),
+            warnings: Default::default(),
+        })
+    }
+
+    /// Create a new serializer, handling recoverable problems (like raw
+    /// content for other formats) according to `strictness`.
+    pub fn with_strictness(writer: W, strictness: Strictness) -> Box<Self> {
+        Self::with_options(
+            writer,
+            TextOptions {
+                strictness,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Report content which can't be written as text, and is skipped; see
+    /// `ser::unsupported`.
+    fn unsupported(&mut self, what: &str) -> Result<(), SerializerError> {
+        let warning = unsupported(self.options.strictness, "Text", what)?;
+        self.warnings.extend(warning);
+        Ok(())
+    }
+
+    /// Write blocks which will be indented by `indent` more columns.
+    fn indented_blocks_text(
+        &mut self,
+        indent: usize,
+        blocks: &[Block],
+    ) -> Result<String, SerializerError> {
+        self.indent += indent;
+        let ret = self.blocks_text(blocks);
+        self.indent -= indent;
+        ret
+    }
+
+    fn blocks_text(&mut self, blocks: &[Block]) -> Result<String, SerializerError> {
+        let mut ret = Vec::new();
+        for block in blocks {
+            let text = self.block_text(&block.inner)?;
+            if !text.is_empty() {
+                ret.push(text);
+            }
+        }
+        Ok(ret.join("\n\n"))
+    }
+
+    fn block_text(&mut self, block: &BlockInner) -> Result<String, SerializerError> {
+        Ok(match block {
+            BlockInner::Plain(inlines) | BlockInner::Par(inlines) => self.inlines_text(inlines)?,
+            BlockInner::Code(code) => {
+                self.preformatted = true;
+                let lines: Result<Vec<_>, _> = code
+                    .lines
+                    .iter()
+                    .map(|line| self.inlines_text(line))
+                    .collect();
+                self.preformatted = false;
+                lines?.join("\n")
+            }
+            BlockInner::Quote(blocks) => {
+                let text = self.indented_blocks_text(2, blocks)?;
+                text.lines()
+                    .map(|line| {
+                        if line.is_empty() {
+                            ">".to_owned()
+                        } else {
+                            format!("> {}", line)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            BlockInner::List(list) => {
+                let mut items = Vec::new();
+                for (i, item) in list.items.iter().enumerate() {
+                    let marker = match list.kind {
+                        ListKind::Unordered => "- ".to_owned(),
+                        ListKind::Ordered => format!("{}. ", i + 1),
+                    };
+                    let content = self.indented_blocks_text(marker.len(), &item.content)?;
+                    items.push(hanging(&marker, &content));
+                }
+                items.join("\n")
+            }
+            BlockInner::TermList(items) => {
+                let mut ret = Vec::new();
+                for item in items {
+                    ret.push(self.inlines_text(&item.term)?);
+                    let content = self.indented_blocks_text(4, &item.content)?;
+                    if !content.is_empty() {
+                        ret.push(hanging("    ", &content));
+                    }
+                }
+                ret.join("\n")
+            }
+            BlockInner::Heading(heading) => {
+                let text = self.inlines_text(&heading.text)?;
+                let width = text.lines().map(|line| line.chars().count()).max();
+                let underline = if heading.level <= 1 { "=" } else { "-" };
+                match width {
+                    Some(width) if width > 0 => format!("{}\n{}", text, underline.repeat(width)),
+                    _ => String::new(),
+                }
+            }
+            BlockInner::Rule => "* * *".to_owned(),
+            BlockInner::Table(table) => {
+                let mut rows = Vec::new();
+                for row in &table.cells {
+                    let mut cells = Vec::new();
+                    for cell in row {
+                        let text = self.blocks_text(&cell.content)?;
+                        cells.push(text.split_whitespace().collect::<Vec<_>>().join(" "));
+                    }
+                    rows.push(cells.join("\t"));
+                }
+                rows.join("\n")
+            }
+            BlockInner::Figure(figure) => {
+                let content = self.blocks_text(&figure.content)?;
+                let caption = self.inlines_text(&figure.caption)?;
+                join_nonempty(&[content, caption])
+            }
+            BlockInner::Defn(defn) => {
+                let name = self.inlines_text(&defn.name)?;
+                let summary = self.blocks_text(&defn.summary)?;
+                let content = self.blocks_text(&defn.content)?;
+                join_nonempty(&[name, summary, content])
+            }
+            BlockInner::Math(math) => match &math.alt {
+                Some(alt) => alt.clone(),
+                None => tex_to_text(&math.tex),
+            },
+            BlockInner::Raw(raw) if raw.format == self.format_name() => raw.content.clone(),
+            BlockInner::Raw(_) => String::new(),
+            BlockInner::Tagged(tagged) => {
+                if doc::meta_includes(&tagged.meta, self.format_name(), self.draft) {
+                    self.blocks_text(&tagged.content)?
+                } else {
+                    String::new()
+                }
+            }
+        })
+    }
+
+    /// Write inlines, wrapped to fit in the width left after the indent.
+    fn inlines_text(&mut self, inlines: &[Inline]) -> Result<String, SerializerError> {
+        let mut atoms = Vec::new();
+        self.push_inlines(&mut atoms, inlines)?;
+        let width = if self.preformatted {
+            None
+        } else {
+            self.options
+                .width
+                .map(|width| width.saturating_sub(self.indent))
+        };
+        Ok(fill(&atoms, width))
+    }
+
+    /// Add text, which can be broken at its spaces unless it's preformatted.
+    fn push_text(&self, atoms: &mut Vec<Atom>, text: &str) {
+        if self.preformatted {
+            atoms.push(Atom::Word(text.to_owned()));
+            return;
+        }
+        for (i, word) in text.split(is_breaking_space).enumerate() {
+            if i > 0 {
+                atoms.push(Atom::Space);
+            }
+            if !word.is_empty() {
+                atoms.push(Atom::Word(word.to_owned()));
+            }
+        }
+    }
+
+    fn push_inlines(
+        &mut self,
+        atoms: &mut Vec<Atom>,
+        inlines: &[Inline],
+    ) -> Result<(), SerializerError> {
+        for inline in inlines {
+            match inline {
+                Inline::Text(text) => self.push_text(atoms, text),
+                Inline::Styled { content, .. } => self.push_inlines(atoms, content)?,
+                Inline::Quote(quote) => {
+                    let (l, r) = quote.kind.to_inlines();
+                    self.push_inlines(atoms, &l)?;
+                    self.push_inlines(atoms, &quote.content)?;
+                    self.push_inlines(atoms, &r)?;
+                }
+                // Code is never broken across lines.
+                Inline::Code(code) => atoms.push(Atom::Word(code.content.clone())),
+                Inline::Space => atoms.push(Atom::Space),
+                Inline::LineBreak => atoms.push(Atom::Break),
+                Inline::Link(link) => self.push_inlines(atoms, &link.text())?,
+                Inline::Footnote(footnote) => {
+                    // Reserve the footnote's number first, so footnotes within
+                    // it are numbered after it.
+                    self.footnotes.push(String::new());
+                    let num = self.footnotes.len();
+                    // Footnotes are listed after the document, indented by
+                    // their markers rather than wherever they're referenced.
+                    let indent = mem::replace(&mut self.indent, 0);
+                    let preformatted = mem::replace(&mut self.preformatted, false);
+                    let text =
+                        self.indented_blocks_text(footnote_marker(num).len(), &footnote.content);
+                    self.indent = indent;
+                    self.preformatted = preformatted;
+                    self.footnotes[num - 1] = text?;
+                    atoms.push(Atom::Word(format!("[{}]", num)));
+                }
+                Inline::Math(math) => match &math.alt {
+                    Some(alt) => self.push_text(atoms, alt),
+                    None => self.push_text(atoms, &tex_to_text(&math.tex)),
+                },
+                Inline::Image(Image { alt, .. }) => {
+                    if let Some(alt) = alt {
+                        self.push_text(atoms, alt);
+                    }
+                }
+                Inline::Raw(raw) if raw.format == self.format_name() => {
+                    atoms.push(Atom::Word(raw.content.clone()))
+                }
+                Inline::Raw(_) => {}
+                Inline::Tagged(tagged) => {
+                    if doc::meta_includes(&tagged.meta, self.format_name(), self.draft) {
+                        self.push_inlines(atoms, &tagged.content)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Is `c` whitespace which lines can be broken at, i.e. not a no-break space?
+fn is_breaking_space(c: char) -> bool {
+    c.is_whitespace() && !matches!(c, '\u{a0}' | '\u{2007}' | '\u{202f}')
+}
+
+/// The marker a footnote is listed with, e.g. `[1] `.
+fn footnote_marker(num: usize) -> String {
+    format!("[{}] ", num)
+}
+
+/// Prefix the first line of `text` with `marker`, and indent the rest to line
+/// up with it.
+pub(super) fn hanging(marker: &str, text: &str) -> String {
+    let indent = " ".repeat(marker.chars().count());
+    let mut ret = String::new();
+    for (i, line) in text.lines().enumerate() {
+        if i == 0 {
+            ret.push_str(marker);
+        } else {
+            ret.push('\n');
+            if !line.is_empty() {
+                ret.push_str(&indent);
+            }
+        }
+        ret.push_str(line);
+    }
+    if ret.is_empty() {
+        ret.push_str(marker.trim_end());
+    }
+    ret
+}
+
+fn join_nonempty(parts: &[String]) -> String {
+    parts
+        .iter()
+        .filter(|part| !part.is_empty())
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::cmd::WarningKind;
+    use crate::doc::{
+        Code, Footnote, Heading, Id, InlineCode, InlineMath, List, ListItem, RawBlock, RawInline,
+        Style, TaggedBlocks, DRAFT_KEY, ONLY_FORMAT_KEY,
+    };
+    use crate::ser::test_util::*;
+
+    fn write(
+        doc: &Doc,
+        strictness: Strictness,
+    ) -> (Result<(), SerializerError>, String, Vec<Warning>) {
+        let mut out = Vec::new();
+        let mut ser = PlainTextSerializer::with_strictness(&mut out, strictness);
+        let result = ser.write_doc(doc);
+        let warnings = ser.take_warnings();
+        drop(ser);
+        (result, String::from_utf8(out).unwrap(), warnings)
+    }
+
+    #[test]
+    fn test_text() {
+        let doc = Doc::from_content(
+            vec![
+                heading(0, 1, "Intro"),
+                par(
+                    0,
+                    vec![
+                        Inline::Text("Some".into()),
+                        Inline::Space,
+                        Inline::Styled {
+                            style: Style::Emph,
+                            content: text("styled"),
+                        },
+                        Inline::Space,
+                        Inline::Text("text".into()),
+                        Inline::Footnote(Footnote {
+                            content: vec![par(0, text("A note."))].into(),
+                        }),
+                        Inline::Text(", and ".into()),
+                        Inline::Math(InlineMath {
+                            tex: "x^2".into(),
+                            alt: Some("x squared".into()),
+                        }),
+                        Inline::Text(".".into()),
+                    ],
+                ),
+                block(
+                    0,
+                    BlockInner::List(List {
+                        kind: ListKind::Ordered,
+                        items: vec![
+                            ListItem {
+                                content: vec![par(0, text("One")), par(0, text("More"))].into(),
+                            },
+                            ListItem {
+                                content: vec![par(0, text("Two"))].into(),
+                            },
+                        ],
+                    }),
+                ),
+                block(
+                    0,
+                    BlockInner::Quote(vec![par(0, text("Quoted")), par(0, text("Twice"))].into()),
+                ),
+                block(0, BlockInner::Rule),
+            ]
+            .into(),
+        );
+        let (result, out, warnings) = write(&doc, Strictness::Warn);
+        assert!(result.is_ok());
+        assert_eq!(
+            "Intro\n\
+             =====\n\
+             \n\
+             Some styled text[1], and x squared.\n\
+             \n\
+             1. One\n\
+             \n   More\n\
+             2. Two\n\
+             \n\
+             > Quoted\n\
+             >\n\
+             > Twice\n\
+             \n\
+             * * *\n\
+             \n\
+             [1] A note.\n",
+            out
+        );
+        assert_eq!(Vec::<Warning>::new(), warnings);
+    }
+
+    #[test]
+    fn test_text_tagged() {
+        let tagged = |key: &str, value: &str, content: &str| {
+            block(
+                0,
+                BlockInner::Tagged(TaggedBlocks {
+                    content: vec![par(0, text(content))].into(),
+                    meta: vec![(key.to_owned(), value.to_owned())]
+                        .into_iter()
+                        .collect(),
+                }),
+            )
+        };
+        let mut doc = Doc::from_content(
+            vec![
+                tagged(ONLY_FORMAT_KEY, "text", "Text only."),
+                tagged(ONLY_FORMAT_KEY, "html", "HTML only."),
+                tagged(doc::DRAFT_ONLY_KEY, "", "Drafts only."),
+            ]
+            .into(),
+        );
+        assert_eq!("Text only.\n", write(&doc, Strictness::Warn).1);
+        doc.meta.insert(DRAFT_KEY.into(), "true".into());
+        assert_eq!(
+            "Text only.\n\nDrafts only.\n",
+            write(&doc, Strictness::Warn).1
+        );
+    }
+
+    #[test]
+    fn test_text_raw() {
+        let raw = |format: &str, content: &str| {
+            Inline::Raw(RawInline {
+                format: format.into(),
+                content: content.into(),
+            })
+        };
+        let raw_block = |format: &str, content: &str| {
+            block(
+                0,
+                BlockInner::Raw(RawBlock {
+                    format: format.into(),
+                    content: content.into(),
+                }),
+            )
+        };
+        let doc = Doc::from_content(
+            vec![
+                par(0, vec![raw("text", "plain"), raw("html", "<hr>")]),
+                raw_block("text", "  not   reflowed"),
+                raw_block("html", "<hr>"),
+            ]
+            .into(),
+        );
+        let (result, out, warnings) = write(&doc, Strictness::Strict);
+        assert!(result.is_ok());
+        assert!(out.starts_with("plain\n\n  not   reflowed\n"));
+        assert!(!out.contains("<hr>"));
+        assert_eq!(Vec::<Warning>::new(), warnings);
+    }
+
+    #[test]
+    fn test_text_wrap() {
+        let doc = Doc::from_content(
+            vec![
+                heading(0, 2, "A longer heading here"),
+                par(
+                    0,
+                    vec![
+                        Inline::Text("The quick brown fox jumps\nover the lazy dog, ".into()),
+                        Inline::Code(InlineCode {
+                            content: "let x = 1;".into(),
+                            ..Default::default()
+                        }),
+                        Inline::Text(" it said.".into()),
+                    ],
+                ),
+                block(
+                    0,
+                    BlockInner::List(List {
+                        kind: ListKind::Unordered,
+                        items: vec![ListItem {
+                            content: vec![par(0, text("one two three four five six"))].into(),
+                        }],
+                    }),
+                ),
+                block(
+                    0,
+                    BlockInner::Code(Code {
+                        lines: vec![text("let  spaced =   \"not wrapped at all\";")],
+                        ..Default::default()
+                    }),
+                ),
+                par(
+                    0,
+                    vec![
+                        Inline::Text("Note".into()),
+                        Inline::Footnote(Footnote {
+                            content: vec![par(0, text("a footnote that needs wrapping too"))]
+                                .into(),
+                        }),
+                    ],
+                ),
+            ]
+            .into(),
+        );
+        let mut out = Vec::new();
+        let options = TextOptions {
+            width: Some(20),
+            ..Default::default()
+        };
+        PlainTextSerializer::with_options(&mut out, options)
+            .write_doc(&doc)
+            .unwrap();
+        assert_eq!(
+            "A longer heading\n\
+             here\n\
+             ----------------\n\
+             \n\
+             The quick brown fox\n\
+             jumps over the lazy\n\
+             dog, let x = 1; it\n\
+             said.\n\
+             \n\
+             - one two three four\n  five six\n\
+             \n\
+             let  spaced =   \"not wrapped at all\";\n\
+             \n\
+             Note[1]\n\
+             \n\
+             [1] a footnote that\n    needs wrapping\n    too\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fill() {
+        let atoms = |inlines: Vec<Inline>| {
+            let mut atoms = Vec::new();
+            PlainTextSerializer::new(Vec::new())
+                .unwrap()
+                .push_inlines(&mut atoms, &inlines)
+                .unwrap();
+            atoms
+        };
+        let words = atoms(vec![
+            Inline::Text(" a\u{a0}b  c ".into()),
+            Inline::LineBreak,
+            Inline::Text("d".into()),
+        ]);
+        assert_eq!("a\u{a0}b\nc\nd", fill(&words, Some(3)));
+        assert_eq!("a\u{a0}b c\nd", fill(&words, None));
+        // Words longer than the width get lines of their own.
+        assert_eq!("abc\nde", fill(&atoms(text("abc de")), Some(2)));
+    }
+}
@@ -0,0 +1,43 @@
+//! Writing a document borrows it rather than copying it, so a large document
+//! can be written several times without being cloned each time.
+//!
+//! Measured with a counting allocator; this is the only test in its binary,
+//! so nothing else allocates while it runs.
+use std::io;
+
+use textecca::doc::{Block, BlockInner, Doc, Id, Inline};
+use textecca::ser::Registry;
+
+#[path = "../benches/counting_alloc/mod.rs"]
+mod counting_alloc;
+use counting_alloc::peak_bytes;
+
+#[test]
+fn test_write_doc_borrows() {
+    // 1,000 paragraphs of 4 KB each.
+    let paragraph = "word ".repeat(800);
+    let doc = Doc::from_content(
+        (0..1000)
+            .map(|id| {
+                Block::new(
+                    Id::from(id),
+                    BlockInner::Par(vec![Inline::Text(paragraph.clone())]),
+                )
+            })
+            .collect::<Vec<_>>()
+            .into(),
+    );
+    let size = 1000 * paragraph.len();
+
+    // The HTML serializer streams its output, so it only needs a small
+    // fraction of the document's size; a copy of the document would need
+    // all of it.
+    let registry = Registry::with_builtins();
+    let (result, peak) = peak_bytes(|| {
+        registry
+            .create("html", Box::new(io::sink()), &Default::default())
+            .and_then(|mut ser| ser.write_doc(&doc))
+    });
+    result.unwrap();
+    assert!(peak < size / 10, "{} bytes allocated", peak);
+}
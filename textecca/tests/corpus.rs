@@ -0,0 +1,265 @@
+//! End-to-end tests: each `.tc` document in `tests/corpus` is evaluated with
+//! the standard library, written as HTML, and compared to the `.html` file
+//! next to it. See `tests/corpus/README.md`.
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use friendly_html::HtmlToken;
+use pretty_assertions::assert_eq;
+use pulldown_cmark::{Event, Options, Parser};
+
+use textecca::{
+    cmd::{FsResolver, Thunk, World},
+    doc::{self, Block, BlockInner, Doc, DocBuilder, Inline},
+    env::Environment,
+    parse::{default_parser, Source, Token, Tokens},
+    ser::{HtmlOptions, HtmlSerializer, InitSerializer as _, MarkdownSerializer, Serializer as _},
+};
+
+/// Set this environment variable to write the expected output instead of
+/// checking it.
+const UPDATE_VAR: &str = "TEXTECCA_UPDATE_CORPUS";
+
+fn corpus_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus")
+}
+
+/// The corpus documents, in sorted order. Files in subdirectories aren't
+/// documents of their own; they're included by the documents.
+fn documents() -> Vec<PathBuf> {
+    let mut ret: Vec<_> = fs::read_dir(corpus_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "tc"))
+        .collect();
+    ret.sort();
+    ret
+}
+
+fn new_world(src: &Source) -> World<'_> {
+    let mut env = Environment::new();
+    textecca_stdlib::import(Rc::get_mut(&mut env).unwrap());
+    World::new(env, src).with_resolver(Rc::new(FsResolver::new(corpus_dir())))
+}
+
+/// Read a corpus file, relative to the corpus directory.
+fn read_source(path: &Path) -> Source {
+    Source::new(fs::read_to_string(corpus_dir().join(path)).unwrap()).with_path(path)
+}
+
+/// Run the whole pipeline on a document, as the command-line interface does.
+fn render(path: &Path) -> String {
+    render_with(path, Default::default())
+}
+
+/// Run the whole pipeline on a document, writing it with the given options.
+fn render_with(path: &Path, options: HtmlOptions) -> String {
+    let doc = evaluate(path);
+    let mut out = Vec::new();
+    HtmlSerializer::with_options(&mut out, options)
+        .unwrap()
+        .write_doc(&doc)
+        .unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+/// Evaluate a document and prepare it for writing, as the command-line
+/// interface does.
+fn evaluate(path: &Path) -> Doc {
+    let src = read_source(path);
+    let world = new_world(&src);
+    let mut toks = default_parser(&src, (&src).into()).unwrap();
+    let errors = world.validate(&mut toks);
+    assert!(errors.is_empty(), "{}: {}", path.display(), errors[0]);
+    let mut builder = DocBuilder::new();
+    Thunk::from(toks)
+        .force(&world, &mut builder)
+        .unwrap_or_else(|err| panic!("{}: {}", path.display(), err.render_causes()));
+    let mut doc: Doc = builder.try_into().unwrap();
+    doc::number_figures(&mut doc);
+    doc::hoist_footnotes(&mut doc, doc::FootnotePolicy::Hoist).unwrap();
+    doc
+}
+
+#[test]
+fn test_corpus() {
+    let update = env::var_os(UPDATE_VAR).is_some();
+    let documents = documents();
+    assert!(documents.len() >= 5);
+    for path in documents {
+        let name = Path::new(path.file_name().unwrap());
+        let html = render(name);
+        let expected_path = path.with_extension("html");
+        if update {
+            fs::write(&expected_path, &html).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&expected_path).unwrap_or_else(|_| {
+            panic!(
+                "No expected output for {}; run with {}=1 to write it",
+                name.display(),
+                UPDATE_VAR
+            )
+        });
+        assert_eq!(
+            friendly_html::tokenize(&expected).unwrap(),
+            friendly_html::tokenize(&html).unwrap(),
+            "{} differs from {}; if the change is intended, run with {}=1",
+            name.display(),
+            expected_path.display(),
+            UPDATE_VAR
+        );
+    }
+}
+
+/// Indented output has the same structure as compact output, and matches
+/// `article.pretty.html`.
+#[test]
+fn test_corpus_indented() {
+    let path = Path::new("article.tc");
+    let compact = render(path);
+    let pretty = render_with(
+        path,
+        HtmlOptions {
+            indent: Some(2),
+            ..Default::default()
+        },
+    );
+    // Indenting only adds whitespace around block-level tags.
+    let trimmed = |html: &str| -> Vec<_> {
+        friendly_html::tokenize(html)
+            .unwrap()
+            .into_iter()
+            .filter_map(|token| match token {
+                HtmlToken::Text(text) if text.trim().is_empty() => None,
+                HtmlToken::Text(text) => Some(HtmlToken::Text(text.trim().to_owned())),
+                token => Some(token),
+            })
+            .collect()
+    };
+    assert_eq!(trimmed(&compact), trimmed(&pretty));
+
+    let expected_path = corpus_dir().join("article.pretty.html");
+    if env::var_os(UPDATE_VAR).is_some() {
+        fs::write(&expected_path, &pretty).unwrap();
+        return;
+    }
+    // Compared as text, since the whitespace is the point.
+    assert_eq!(
+        fs::read_to_string(&expected_path).unwrap(),
+        pretty,
+        "{} differs; if the change is intended, run with {}=1",
+        expected_path.display(),
+        UPDATE_VAR
+    );
+}
+
+/// Collect the text of the blocks in `blocks` which Markdown can express,
+/// and of their inlines, in document order.
+fn markdown_texts(blocks: &[Block], texts: &mut Vec<String>) {
+    for block in blocks {
+        match &block.inner {
+            BlockInner::Plain(inlines) | BlockInner::Par(inlines) => inline_texts(inlines, texts),
+            BlockInner::Heading(heading) => inline_texts(&heading.text, texts),
+            BlockInner::Quote(blocks) => markdown_texts(blocks, texts),
+            BlockInner::List(list) => {
+                for item in &list.items {
+                    markdown_texts(&item.content, texts);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn inline_texts(inlines: &[Inline], texts: &mut Vec<String>) {
+    for inline in inlines {
+        match inline {
+            Inline::Text(text) => texts.push(text.clone()),
+            Inline::Styled { content, .. } => inline_texts(content, texts),
+            Inline::Footnote(footnote) => markdown_texts(&footnote.content, texts),
+            _ => {}
+        }
+    }
+}
+
+/// Each corpus document written as Markdown and read back by a CommonMark
+/// renderer with footnotes and math has all of the document's text,
+/// unchanged by Markdown syntax.
+#[test]
+fn test_corpus_markdown() {
+    for path in documents() {
+        let name = Path::new(path.file_name().unwrap());
+        let doc = evaluate(name);
+        let mut out = Vec::new();
+        MarkdownSerializer::new(&mut out)
+            .unwrap()
+            .write_doc(&doc)
+            .unwrap();
+        let md = String::from_utf8(out).unwrap();
+        let rendered: String =
+            Parser::new_ext(&md, Options::ENABLE_FOOTNOTES | Options::ENABLE_MATH)
+                .filter_map(|event| match event {
+                    Event::Text(text) => Some(text.into_string()),
+                    _ => None,
+                })
+                .collect();
+        let mut texts = Vec::new();
+        markdown_texts(&doc.content, &mut texts);
+        assert!(!texts.is_empty());
+        for text in texts {
+            assert!(
+                rendered.contains(&text),
+                "{}: {:?} isn't in the rendered Markdown:\n{}",
+                name.display(),
+                text,
+                md
+            );
+        }
+    }
+}
+
+/// Collect the names of the commands in `tokens`, including in their
+/// arguments (as parsed by `World::validate`).
+fn command_names(tokens: &Tokens, names: &mut BTreeSet<String>) {
+    for tok in tokens {
+        if let Token::Command(cmd) = tok {
+            names.insert(cmd.name.fragment().to_string());
+            for arg in &cmd.args {
+                if let Some(tokens) = &arg.tokens {
+                    command_names(tokens, names);
+                }
+            }
+        }
+    }
+}
+
+/// Every standard library command should be used somewhere in the corpus.
+#[test]
+fn test_corpus_covers_stdlib() {
+    let mut used = BTreeSet::new();
+    let included = fs::read_dir(corpus_dir().join("include"))
+        .unwrap()
+        .map(|entry| Path::new("include").join(entry.unwrap().file_name()));
+    for path in documents()
+        .iter()
+        .map(|path| PathBuf::from(path.file_name().unwrap()))
+        .chain(included)
+    {
+        let src = read_source(&path);
+        let mut toks = default_parser(&src, (&src).into()).unwrap();
+        new_world(&src).validate(&mut toks);
+        command_names(&toks, &mut used);
+    }
+    let mut env = Environment::new();
+    textecca_stdlib::import(Rc::get_mut(&mut env).unwrap());
+    let unused: BTreeSet<_> = env
+        .bound_names()
+        .filter(|name| !used.contains(*name))
+        .collect();
+    assert_eq!(BTreeSet::new(), unused, "Commands missing from the corpus");
+}
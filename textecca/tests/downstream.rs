@@ -0,0 +1,133 @@
+//! A serializer outside of textecca, which has to handle kinds of content
+//! it doesn't know about; see "Compatibility" in the `ser::Serializer` docs.
+use std::mem;
+
+use pretty_assertions::assert_eq;
+
+use textecca::cmd::{Strictness, Warning, WarningKind};
+use textecca::doc::{Block, BlockInner, Doc, Id, Inline, Style};
+use textecca::ser::{self, Serializer, SerializerError};
+
+/// Writes paragraphs as lines of text, with emphasis between underscores.
+/// Written as if the only style was `Style::Emph`.
+struct TextSerializer {
+    out: String,
+    strictness: Strictness,
+    warnings: Vec<Warning>,
+}
+
+impl TextSerializer {
+    fn unsupported(&mut self, what: &str) -> Result<(), SerializerError> {
+        let warning = ser::unsupported(self.strictness, "Text", what)?;
+        self.warnings.extend(warning);
+        Ok(())
+    }
+
+    fn write_inlines(&mut self, inlines: &[Inline]) -> Result<(), SerializerError> {
+        for inline in inlines {
+            match inline {
+                Inline::Text(text) => self.out.push_str(text),
+                Inline::Space => self.out.push(' '),
+                Inline::Styled { style, content } => match style {
+                    Style::Emph => {
+                        self.out.push('_');
+                        self.write_inlines(content)?;
+                        self.out.push('_');
+                    }
+                    // Styles this serializer doesn't know; the content is
+                    // still written.
+                    _ => {
+                        self.unsupported(&format!("the style {:?}", style))?;
+                        self.write_inlines(content)?;
+                    }
+                },
+                _ => self.unsupported(inline.kind_name())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Serializer for TextSerializer {
+    fn format_name(&self) -> &'static str {
+        "text"
+    }
+
+    fn write_doc(&mut self, doc: &Doc) -> Result<(), SerializerError> {
+        for block in doc.content.iter() {
+            match &block.inner {
+                BlockInner::Par(inlines) => {
+                    self.write_inlines(inlines)?;
+                    self.out.push('\n');
+                }
+                inner => self.unsupported(inner.kind_name())?,
+            }
+        }
+        Ok(())
+    }
+
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
+}
+
+fn write(strictness: Strictness) -> (Result<(), SerializerError>, String, Vec<Warning>) {
+    let doc = Doc::from_content(
+        vec![
+            Block::new(
+                Id::from(0),
+                BlockInner::Par(vec![
+                    Inline::Styled {
+                        style: Style::Emph,
+                        content: vec![Inline::Text("Known".into())],
+                    },
+                    Inline::Space,
+                    Inline::Styled {
+                        style: Style::Strikeout,
+                        content: vec![Inline::Text("unknown".into())],
+                    },
+                ]),
+            ),
+            Block::new(Id::from(1), BlockInner::Rule),
+        ]
+        .into(),
+    );
+    let mut ser = TextSerializer {
+        out: String::new(),
+        strictness,
+        warnings: Vec::new(),
+    };
+    let result = ser.write_doc(&doc);
+    let warnings = ser.take_warnings();
+    (result, ser.out, warnings)
+}
+
+#[test]
+fn test_unknown_content() {
+    let (result, out, warnings) = write(Strictness::Warn);
+    assert!(result.is_ok());
+    assert_eq!("_Known_ unknown\n", out);
+    assert_eq!(
+        vec![
+            Warning::new(
+                WarningKind::Unsupported,
+                "Text output doesn't support the style Strikeout; skipping".into()
+            ),
+            Warning::new(
+                WarningKind::Unsupported,
+                "Text output doesn't support rule; skipping".into()
+            ),
+        ],
+        warnings
+    );
+
+    let (_, _, warnings) = write(Strictness::Lenient);
+    assert_eq!(Vec::<Warning>::new(), warnings);
+
+    match write(Strictness::Strict).0 {
+        Err(SerializerError::Strict(warning)) => {
+            assert_eq!(WarningKind::Unsupported, warning.kind);
+        }
+        result => panic!("Expected a strictness error, got {:?}", result),
+    }
+}